@@ -1,6 +1,7 @@
 use core::alloc::Layout;
 use core::ptr::NonNull;
 
+use super::heap_cache::{cached_alloc, cached_dealloc};
 use super::linked_list_allocator::LinkedListAllocator;
 use super::pmem_manager::PmemManager;
 use super::{heap, zm, HeapAllocator, PageAllocator};
@@ -57,7 +58,8 @@ impl CapAllocatorInner {
         }
 
         if self.used_size + self.prealloc_size + bytes > self.max_capacity {
-            return Err(SysErr::OutOfMem);
+            // the capacity of the root allocator is all of physical memory, for any other allocator it is a user set limit
+            return Err(if self.parent.is_some() { SysErr::QuotaExceeded } else { SysErr::OutOfMem });
         }
 
         // If this is the root node (no parent), return OutOfMem, because we can never prealloc, so we are out of memory
@@ -102,6 +104,19 @@ impl CapAllocatorInner {
         self.prealloc_size += bytes;
         self.used_size -= bytes;
     }
+
+    /// Gives `bytes` of preallocated memory back to the parent allocator
+    fn release_prealloc(&mut self, bytes: usize) {
+        assert!(self.prealloc_size >= bytes, "tried to release more bytes than were preallocated");
+
+        let Some(mut parent_inner) = self.get_parent() else {
+            return;
+        };
+
+        parent_inner.dealloc_bytes(bytes);
+        drop(parent_inner);
+        self.prealloc_size -= bytes;
+    }
 }
 
 /// an allocator that makes up the allocator tree that the kernel presents in its api to the userspace
@@ -123,12 +138,73 @@ impl CapAllocator {
         }
     }
 
+    /// Creates an allocator which gets its memory from `parent`, and can allocate at most `limit` bytes
+    /// 
+    /// `limit` is rounded down to a multiple of the page size
+    pub fn new_child(parent: Arc<CapAllocator>, limit: usize) -> Self {
+        Self {
+            inner: IMutex::new(CapAllocatorInner {
+                parent: Some(parent),
+                is_alive: true,
+                max_capacity: align_down(limit, PAGE_SIZE),
+                prealloc_size: 0,
+                used_size: 0,
+            }),
+        }
+    }
+
+    /// Sets the maximum number of bytes which can be allocated from this allocator
+    /// 
+    /// `limit` is rounded down to a multiple of the page size
+    /// 
+    /// # Returns
+    /// 
+    /// [`SysErr::InvlOp`] if this is the root allocator,
+    /// [`SysErr::InvlArgs`] if more than `limit` bytes are already allocated
+    pub fn set_limit(&self, limit: usize) -> KResult<()> {
+        let limit = align_down(limit, PAGE_SIZE);
+        let mut inner = self.inner.lock();
+
+        if inner.parent.is_none() {
+            return Err(SysErr::InvlOp);
+        }
+
+        if limit < inner.used_size {
+            return Err(SysErr::InvlArgs);
+        }
+
+        // preallocated memory also counts against the limit, give back any past the new limit
+        let excess_prealloc = (inner.used_size + inner.prealloc_size).saturating_sub(limit);
+        if excess_prealloc > 0 {
+            inner.release_prealloc(excess_prealloc);
+        }
+
+        inner.max_capacity = limit;
+
+        Ok(())
+    }
+
+    /// Returns the number of bytes in use by this allocator (including memory given to child allocators), and its limit
+    pub fn usage(&self) -> (usize, usize) {
+        let inner = self.inner.lock();
+        (inner.used_size, inner.max_capacity)
+    }
+
     /// Marks the allocator as dead
     pub fn kill_allocator(&self) {
         self.inner.lock().is_alive = false;
     }
 }
 
+impl Drop for CapAllocator {
+    fn drop(&mut self) {
+        // everything allocated from this allocator holds a reference to it, so only preallocated memory is left
+        let inner = self.inner.get_mut();
+        let prealloc_size = inner.prealloc_size;
+        inner.release_prealloc(prealloc_size);
+    }
+}
+
 impl CapObject for CapAllocator {
     const TYPE: CapType = CapType::Allocator;
 }
@@ -174,18 +250,17 @@ impl CapAllocatorWrapper {
         self.with_inner(|inner| inner.dealloc_bytes(size))
     }
 
-    pub fn page_alloc(&mut self, layout: PageLayout) -> Option<Allocation> {
+    pub fn page_alloc(&mut self, layout: PageLayout) -> KResult<Allocation> {
         let alloc_size = PmemManager::get_allocation_size_for_layout(layout);
-        self.alloc_bytes(alloc_size).ok()?;
+        self.alloc_bytes(alloc_size)?;
 
         let allocation = zm().alloc(layout);
 
         if allocation.is_none() {
             self.dealloc_bytes(alloc_size);
-            None
-        } else {
-            allocation
         }
+
+        allocation.ok_or(SysErr::OutOfMem)
     }
 
     pub unsafe fn page_dealloc(&mut self, allocation: Allocation) {
@@ -243,19 +318,18 @@ impl CapAllocatorWrapper {
         }
     }
 
-    pub fn heap_alloc(&mut self, layout: Layout) -> Option<NonNull<[u8]>> {
-        let allocation = heap().alloc(layout)?;
+    pub fn heap_alloc(&mut self, layout: Layout) -> KResult<NonNull<[u8]>> {
+        let allocation = cached_alloc(layout).ok_or(SysErr::OutOfMem)?;
 
         let result = self.alloc_bytes(allocation.len());
 
         if result.is_err() {
             unsafe {
-                heap().dealloc(allocation.as_non_null_ptr(), layout);
+                cached_dealloc(allocation.as_non_null_ptr(), layout);
             }
-            None
-        } else {
-            Some(allocation)
         }
+
+        result.map(|_| allocation)
     }
 
     pub unsafe fn heap_dealloc(&mut self, allocation_start: NonNull<u8>, layout: Layout) {
@@ -263,10 +337,10 @@ impl CapAllocatorWrapper {
             .expect("invalid deallocation");
 
         self.dealloc_bytes(allocation.len());
-        unsafe { heap().dealloc(allocation_start, layout) }
+        unsafe { cached_dealloc(allocation_start, layout) }
     }
 
-    pub unsafe fn heap_realloc(&mut self, allocation: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Option<NonNull<[u8]>> {
+    pub unsafe fn heap_realloc(&mut self, allocation: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> KResult<NonNull<[u8]>> {
         let old_allocation = LinkedListAllocator::get_allocation(allocation, old_layout)
             .expect("invalid reallocation");
         let old_size = old_allocation.len();
@@ -276,14 +350,14 @@ impl CapAllocatorWrapper {
             .len();
 
         if old_size == new_size {
-            return Some(old_allocation);
+            return Ok(old_allocation);
         }
 
         // only realloc if we are growing memory
         // otherwise if allocating from memory allocator fails, but we already shrunk zone,
         // regrowing the bytes could fail
         if new_size > old_size {
-            self.alloc_bytes(new_size - old_size).ok()?;
+            self.alloc_bytes(new_size - old_size)?;
         }
 
         let new_allocation = unsafe {
@@ -294,12 +368,61 @@ impl CapAllocatorWrapper {
             if new_size > old_size {
                 self.dealloc_bytes(new_size - old_size);
             }
-            None
+            Err(SysErr::OutOfMem)
         } else {
             if old_size > new_size {
                 self.dealloc_bytes(old_size - new_size);
             }
-            new_allocation
+            new_allocation.ok_or(SysErr::OutOfMem)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::{root_alloc, PaRef, HeapRef};
+
+    #[test_case]
+    fn child_allocator_limit() {
+        const LIMIT_PAGES: usize = 4;
+
+        let parent = Arc::new(
+            CapAllocator::new_child(root_alloc().clone(), 16 * PAGE_SIZE),
+            HeapRef::heap(),
+        ).unwrap();
+        let child = Arc::new(
+            CapAllocator::new_child(parent.clone(), LIMIT_PAGES * PAGE_SIZE),
+            HeapRef::heap(),
+        ).unwrap();
+
+        let page_layout = PageLayout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap();
+        let mut child_allocator = PaRef::from_arc(child.clone());
+        let mut parent_allocator = PaRef::from_arc(parent.clone());
+
+        let mut pages = [None; LIMIT_PAGES];
+        for page in pages.iter_mut() {
+            *page = Some(child_allocator.try_alloc(page_layout).unwrap());
+        }
+        assert_eq!(child_allocator.try_alloc(page_layout).err(), Some(SysErr::QuotaExceeded));
+        assert_eq!(child.usage(), (LIMIT_PAGES * PAGE_SIZE, LIMIT_PAGES * PAGE_SIZE));
+
+        // the parent can still allocate up to its own limit
+        let parent_page = parent_allocator.try_alloc(page_layout).unwrap();
+
+        // the limit can not be lowered below what is in use
+        assert_eq!(child.set_limit(PAGE_SIZE), Err(SysErr::InvlArgs));
+
+        unsafe {
+            parent_allocator.dealloc(parent_page);
+            for page in pages.iter_mut() {
+                child_allocator.dealloc(page.take().unwrap());
+            }
         }
+
+        // lowering the limit gives preallocated memory back to the parent
+        child.set_limit(PAGE_SIZE).unwrap();
+        assert_eq!(child.usage(), (0, PAGE_SIZE));
+        assert_eq!(parent.usage().0, PAGE_SIZE);
     }
 }
\ No newline at end of file
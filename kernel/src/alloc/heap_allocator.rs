@@ -6,6 +6,7 @@ use crate::prelude::*;
 use crate::container::Arc;
 
 use super::{heap, CapAllocator};
+use super::heap_cache::{cached_alloc, cached_dealloc};
 use super::linked_list_allocator::LinkedListAllocator;
 use super::cap_allocator::CapAllocatorWrapper;
 
@@ -69,9 +70,17 @@ impl HeapRef {
     }
 
     pub fn alloc(&mut self, layout: Layout) -> Option<NonNull<[u8]>> {
+        self.try_alloc(layout).ok()
+    }
+
+    /// Allocates memory, and returns why the allocation failed on failure
+    /// 
+    /// This returns [`SysErr::QuotaExceeded`] if this references a [`CapAllocator`] which is at its limit,
+    /// or [`SysErr::OutOfMem`] otherwise
+    pub fn try_alloc(&mut self, layout: Layout) -> KResult<NonNull<[u8]>> {
         match self.0 {
-            HeapRefInner::MainAllocator(allocator) => allocator.alloc(layout),
-            HeapRefInner::InitAllocator(init_allocator) => unsafe { (*init_allocator).alloc(layout) },
+            HeapRefInner::MainAllocator(_) => cached_alloc(layout).ok_or(SysErr::OutOfMem),
+            HeapRefInner::InitAllocator(init_allocator) => unsafe { (*init_allocator).alloc(layout) }.ok_or(SysErr::OutOfMem),
             HeapRefInner::CapAllocator(ref mut cap_allocator) => cap_allocator.heap_alloc(layout),
         }
     }
@@ -79,7 +88,7 @@ impl HeapRef {
     pub unsafe fn dealloc(&mut self, allocation: NonNull<u8>, layout: Layout) {
         unsafe {
             match self.0 {
-                HeapRefInner::MainAllocator(allocator) => allocator.dealloc(allocation, layout),
+                HeapRefInner::MainAllocator(_) => cached_dealloc(allocation, layout),
                 HeapRefInner::InitAllocator(init_allocator) => (*init_allocator).dealloc(allocation, layout),
                 HeapRefInner::CapAllocator(ref mut cap_allocator) => cap_allocator.heap_dealloc(allocation, layout),
             }
@@ -87,10 +96,19 @@ impl HeapRef {
     }
 
     pub unsafe fn realloc(&mut self, allocation: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Option<NonNull<[u8]>> {
+        unsafe {
+            self.try_realloc(allocation, old_layout, new_layout).ok()
+        }
+    }
+
+    /// Reallocates memory, and returns why the allocation failed on failure, see [`HeapRef::try_alloc`]
+    pub unsafe fn try_realloc(&mut self, allocation: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> KResult<NonNull<[u8]>> {
         unsafe {
             match self.0 {
-                HeapRefInner::MainAllocator(allocator) => allocator.realloc(allocation, old_layout, new_layout),
-                HeapRefInner::InitAllocator(init_allocator) => (*init_allocator).realloc(allocation, old_layout, new_layout),
+                HeapRefInner::MainAllocator(allocator) => allocator.realloc(allocation, old_layout, new_layout)
+                    .ok_or(SysErr::OutOfMem),
+                HeapRefInner::InitAllocator(init_allocator) => (*init_allocator).realloc(allocation, old_layout, new_layout)
+                    .ok_or(SysErr::OutOfMem),
                 HeapRefInner::CapAllocator(ref mut cap_allocator) => cap_allocator.heap_realloc(allocation, old_layout, new_layout),
             }
         }
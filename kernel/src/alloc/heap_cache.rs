@@ -0,0 +1,430 @@
+//! Per cpu caches of small heap allocations
+//!
+//! Every heap allocation normally takes the heap lock, which serializes allocations on all cpus.
+//! Each cpu keeps a magazine of free blocks for every small size class in its cpu local data,
+//! which are refilled from and flushed to the heap in batches, so most small allocations don't touch the heap lock.
+//!
+//! Blocks in a size class are exactly the size the heap would have allocated for the same layout,
+//! so memory allocated from a cache can be freed directly to the heap and the other way around.
+//! The cache is only used while interrupts are disabled, since the current thread can't be moved to another cpu
+//! and no interrupt handler on this cpu can use the cache at the same time.
+
+use core::cell::UnsafeCell;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+
+use crate::arch::x64::is_int_enabled;
+use crate::config::MAX_CPUS;
+use crate::gs_data::Prid;
+use crate::mem::Layout;
+use crate::prelude::*;
+
+use super::HeapAllocator;
+use super::heap;
+use super::linked_list_allocator::CHUNK_SIZE;
+
+/// Number of size classes, size class n holds blocks of `(n + 1) * CHUNK_SIZE` bytes
+const SIZE_CLASS_COUNT: usize = 8;
+
+/// Maximum number of free blocks cached for each size class
+const MAGAZINE_SIZE: usize = 32;
+
+/// Number of blocks moved between a magazine and the heap at once
+const BATCH_SIZE: usize = MAGAZINE_SIZE / 2;
+
+/// Set once every cpu has initialized its cpu local data
+static CACHES_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Cache of every cpu, used to print statistics
+static CPU_CACHES: [AtomicPtr<HeapCache>; MAX_CPUS] = [const { AtomicPtr::new(null_mut()) }; MAX_CPUS];
+
+/// Starts using the per cpu caches, must be called after all cpus have initialized their cpu local data
+pub fn enable_heap_caches() {
+    CACHES_ENABLED.store(true, Ordering::Release);
+}
+
+/// Returns the size class index of `layout`, or None if it is not cached
+fn size_class(layout: Layout) -> Option<usize> {
+    if layout.size() == 0 || layout.size() > SIZE_CLASS_COUNT * CHUNK_SIZE || layout.align() > CHUNK_SIZE {
+        None
+    } else {
+        Some(align_up(layout.size(), CHUNK_SIZE) / CHUNK_SIZE - 1)
+    }
+}
+
+/// Layout used to allocate blocks in the given size class from the heap
+fn size_class_layout(size_class: usize) -> Layout {
+    Layout::from_size_align((size_class + 1) * CHUNK_SIZE, CHUNK_SIZE).unwrap()
+}
+
+/// Returns the cache of the current cpu, or None if it can't be used right now
+fn current_cache() -> Option<&'static HeapCache> {
+    if !CACHES_ENABLED.load(Ordering::Acquire) || is_int_enabled() {
+        None
+    } else {
+        Some(&cpu_local_data().heap_cache)
+    }
+}
+
+/// Allocates from the heap, using the current cpu's cache if possible
+pub fn cached_alloc(layout: Layout) -> Option<NonNull<[u8]>> {
+    match (size_class(layout), current_cache()) {
+        (Some(size_class), Some(cache)) => cache.alloc(size_class),
+        _ => heap().alloc(layout),
+    }
+}
+
+/// Deallocates memory allocated from the heap, using the current cpu's cache if possible
+/// 
+/// # Safety
+/// 
+/// `allocation` must have been allocated from the heap with the given layout
+pub unsafe fn cached_dealloc(allocation: NonNull<u8>, layout: Layout) {
+    match (size_class(layout), current_cache()) {
+        (Some(size_class), Some(cache)) => unsafe { cache.dealloc(size_class, allocation) },
+        _ => unsafe { heap().dealloc(allocation, layout) },
+    }
+}
+
+#[derive(Debug)]
+struct Magazine {
+    blocks: [*mut u8; MAGAZINE_SIZE],
+    count: usize,
+}
+
+impl Magazine {
+    const fn new() -> Self {
+        Magazine {
+            blocks: [null_mut(); MAGAZINE_SIZE],
+            count: 0,
+        }
+    }
+}
+
+/// Statistics about how often a cpu's heap cache was used
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapCacheStats {
+    /// Allocations served from the cache
+    pub hits: u64,
+    /// Allocations which had to refill the cache from the heap
+    pub misses: u64,
+    /// Number of times the heap lock was taken to refill or flush the cache
+    pub heap_locks: u64,
+}
+
+/// Free blocks of small size classes cached by one cpu
+#[derive(Debug)]
+pub struct HeapCache {
+    /// Only accessed by the owning cpu with interrupts disabled
+    magazines: UnsafeCell<[Magazine; SIZE_CLASS_COUNT]>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    heap_locks: AtomicU64,
+}
+
+impl HeapCache {
+    pub const fn new() -> Self {
+        HeapCache {
+            magazines: UnsafeCell::new([const { Magazine::new() }; SIZE_CLASS_COUNT]),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            heap_locks: AtomicU64::new(0),
+        }
+    }
+
+    /// Registers this cache as the cache of the given cpu, so its statistics are printed by [`print_heap_cache_stats`]
+    pub fn register(&'static self, prid: Prid) {
+        CPU_CACHES[prid.into()].store(self as *const _ as *mut _, Ordering::Release);
+    }
+
+    pub fn stats(&self) -> HeapCacheStats {
+        HeapCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            heap_locks: self.heap_locks.load(Ordering::Relaxed),
+        }
+    }
+
+    /// # Safety
+    /// 
+    /// Must only be called by the owning cpu with interrupts disabled
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn magazine(&self, size_class: usize) -> &mut Magazine {
+        unsafe { &mut (*self.magazines.get())[size_class] }
+    }
+
+    fn alloc(&self, size_class: usize) -> Option<NonNull<[u8]>> {
+        // safety: this is only called from current_cache, which checks interrupts are disabled
+        let magazine = unsafe { self.magazine(size_class) };
+
+        if magazine.count == 0 {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            self.heap_locks.fetch_add(1, Ordering::Relaxed);
+
+            magazine.count = heap().alloc_many(size_class_layout(size_class), &mut magazine.blocks[..BATCH_SIZE]);
+            if magazine.count == 0 {
+                return None;
+            }
+        } else {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+
+        magazine.count -= 1;
+        let block = magazine.blocks[magazine.count];
+
+        Some(NonNull::slice_from_raw_parts(
+            NonNull::new(block).unwrap(),
+            size_class_layout(size_class).size(),
+        ))
+    }
+
+    unsafe fn dealloc(&self, size_class: usize, allocation: NonNull<u8>) {
+        // safety: this is only called from current_cache, which checks interrupts are disabled
+        let magazine = unsafe { self.magazine(size_class) };
+
+        if magazine.count == MAGAZINE_SIZE {
+            self.heap_locks.fetch_add(1, Ordering::Relaxed);
+
+            // flush the oldest blocks, recently freed blocks are more likely to still be in the cpu cache
+            unsafe {
+                heap().dealloc_many(&magazine.blocks[..BATCH_SIZE], size_class_layout(size_class));
+            }
+            magazine.blocks.copy_within(BATCH_SIZE.., 0);
+            magazine.count -= BATCH_SIZE;
+        }
+
+        magazine.blocks[magazine.count] = allocation.as_ptr();
+        magazine.count += 1;
+    }
+}
+
+/// Prints the heap cache statistics of every cpu to the debug console
+pub fn print_heap_cache_stats() {
+    for (cpu, cache) in CPU_CACHES.iter().enumerate() {
+        // safety: caches are stored in cpu local data, which is never freed
+        let Some(cache) = (unsafe { cache.load(Ordering::Acquire).as_ref() }) else {
+            continue;
+        };
+
+        let stats = cache.stats();
+        eprintln!(
+            "cpu {} heap cache: {} hits, {} misses, {} heap locks",
+            cpu,
+            stats.hits,
+            stats.misses,
+            stats.heap_locks,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicUsize;
+    use core::time::Duration;
+
+    use super::*;
+    use crate::alloc::root_alloc_ref;
+    use crate::arch::x64::IntDisable;
+    use crate::config::cpu_count;
+    use crate::sched::{defer_work, WorkItem};
+    use crate::time::monotonic_nsec;
+
+    /// Number of blocks sent between cpus by the stress test
+    const STRESS_BLOCK_COUNT: usize = 100_000;
+    /// Number of blocks which can be in flight between the cpus at once
+    const CHANNEL_SIZE: usize = 64;
+    const STEP_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Single producer single consumer channel of blocks, a null slot is empty
+    static CHANNEL: [AtomicPtr<u8>; CHANNEL_SIZE] = [const { AtomicPtr::new(null_mut()) }; CHANNEL_SIZE];
+    /// Number of blocks the consumer has freed
+    static BLOCKS_FREED: AtomicUsize = AtomicUsize::new(0);
+    /// Index of the first block whose contents were wrong when it was recieved, or usize::MAX
+    static CORRUPT_BLOCK: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+    /// Layout of block `index` in the stress test, which cycles through every size class
+    fn stress_layout(index: usize) -> Layout {
+        size_class_layout(index % SIZE_CLASS_COUNT)
+    }
+
+    fn cpu_stats(cpu: usize) -> HeapCacheStats {
+        // safety: caches are stored in cpu local data, which is never freed
+        unsafe { CPU_CACHES[cpu].load(Ordering::Acquire).as_ref() }.unwrap().stats()
+    }
+
+    /// Spins until `slot` is in the wanted state, a slot is never stuck for long unless the other cpu stopped
+    fn wait_for_slot(slot: &AtomicPtr<u8>, full: bool) {
+        let deadline = monotonic_nsec() + STEP_TIMEOUT.as_nanos() as u64;
+        while slot.load(Ordering::Acquire).is_null() == full {
+            assert!(monotonic_nsec() < deadline, "timed out waiting for the other cpu");
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Spins until the current thread is running on `cpu`, the thread's cpu mask must only allow `cpu`
+    fn wait_for_migration(cpu: usize) {
+        let deadline = monotonic_nsec() + STEP_TIMEOUT.as_nanos() as u64;
+        while prid().into() != cpu {
+            assert!(monotonic_nsec() < deadline, "thread was not moved to cpu {}", cpu);
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Recieves every block from the channel, checks it still holds its index, and frees it to this cpu's cache
+    fn consume_blocks(_: usize) {
+        let mut allocator = root_alloc_ref();
+
+        for index in 0..STRESS_BLOCK_COUNT {
+            let slot = &CHANNEL[index % CHANNEL_SIZE];
+            wait_for_slot(slot, true);
+            let block = slot.swap(null_mut(), Ordering::AcqRel);
+
+            // safety: the producer wrote the index to the start of the block before sending it
+            if unsafe { block.cast::<usize>().read() } != index {
+                let _ = CORRUPT_BLOCK.compare_exchange(usize::MAX, index, Ordering::AcqRel, Ordering::Acquire);
+            }
+
+            let _int_disable = IntDisable::new();
+            unsafe {
+                allocator.dealloc(NonNull::new(block).unwrap(), stress_layout(index));
+            }
+            BLOCKS_FREED.fetch_add(1, Ordering::Release);
+        }
+    }
+
+    #[test_case]
+    fn small_allocations_use_cache() {
+        let _int_disable = IntDisable::new();
+        let cache = current_cache().unwrap();
+        let layout = Layout::from_size_align(40, 8).unwrap();
+        let mut allocator = root_alloc_ref();
+
+        let before = cache.stats();
+        for _ in 0..1000 {
+            let allocation = allocator.alloc(layout).unwrap();
+            unsafe {
+                allocator.dealloc(allocation.as_non_null_ptr(), layout);
+            }
+        }
+        let after = cache.stats();
+
+        assert!(after.hits - before.hits >= 999);
+        assert!(after.heap_locks - before.heap_locks <= 1);
+    }
+
+    #[test_case]
+    fn allocation_bursts_are_batched() {
+        const ALLOCATION_COUNT: usize = 256;
+
+        let layout = Layout::from_size_align(128, 16).unwrap();
+        let mut allocator = root_alloc_ref();
+        let mut allocations = [null_mut(); ALLOCATION_COUNT];
+
+        let _int_disable = IntDisable::new();
+        let cache = current_cache().unwrap();
+        let before = cache.stats();
+
+        for allocation in allocations.iter_mut() {
+            *allocation = allocator.alloc(layout).unwrap().as_mut_ptr();
+        }
+        for allocation in allocations.iter() {
+            unsafe {
+                allocator.dealloc(NonNull::new(*allocation).unwrap(), layout);
+            }
+        }
+
+        let after = cache.stats();
+        // each refill or flush moves a whole batch
+        assert!(after.heap_locks - before.heap_locks <= 2 * (ALLOCATION_COUNT / BATCH_SIZE) + 2);
+    }
+
+    #[test_case]
+    fn uncached_memory_can_be_freed_to_cache() {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let mut allocator = root_alloc_ref();
+
+        // interrupts are enabled while running tests, so this bypasses the cache
+        assert!(current_cache().is_none());
+        let allocation = allocator.alloc(layout).unwrap();
+
+        let _int_disable = IntDisable::new();
+        unsafe {
+            allocator.dealloc(allocation.as_non_null_ptr(), layout);
+        }
+
+        let cache = current_cache().unwrap();
+        let before = cache.stats();
+        let reused = allocator.alloc(layout).unwrap();
+        let after = cache.stats();
+
+        assert_eq!(reused.as_mut_ptr(), allocation.as_mut_ptr());
+        assert_eq!(after.hits - before.hits, 1);
+        assert_eq!(after.misses, before.misses);
+        assert_eq!(after.heap_locks, before.heap_locks);
+
+        unsafe {
+            allocator.dealloc(reused.as_non_null_ptr(), layout);
+        }
+    }
+
+    #[test_case]
+    fn blocks_freed_on_other_cpu_stress() {
+        const PRODUCER_CPU: usize = 1;
+
+        if cpu_count() <= PRODUCER_CPU {
+            return;
+        }
+
+        let start_cpu = prid().into();
+        assert_ne!(start_cpu, PRODUCER_CPU);
+
+        BLOCKS_FREED.store(0, Ordering::Release);
+        CORRUPT_BLOCK.store(usize::MAX, Ordering::Release);
+
+        let consumer_before = cpu_stats(start_cpu);
+        let producer_before = cpu_stats(PRODUCER_CPU);
+
+        // the deferred work runs on this cpu once the test thread has moved away from it
+        defer_work(WorkItem::function(consume_blocks, 0)).unwrap();
+
+        let thread = cpu_local_data().current_thread();
+        thread.set_cpu_mask(1 << PRODUCER_CPU);
+        wait_for_migration(PRODUCER_CPU);
+
+        let mut allocator = root_alloc_ref();
+        for index in 0..STRESS_BLOCK_COUNT {
+            let slot = &CHANNEL[index % CHANNEL_SIZE];
+            wait_for_slot(slot, false);
+
+            let block = {
+                let _int_disable = IntDisable::new();
+                allocator.alloc(stress_layout(index)).unwrap().as_mut_ptr()
+            };
+
+            // safety: every size class is at least CHUNK_SIZE bytes and CHUNK_SIZE aligned
+            unsafe { block.cast::<usize>().write(index) };
+            slot.store(block, Ordering::Release);
+        }
+
+        let deadline = monotonic_nsec() + STEP_TIMEOUT.as_nanos() as u64;
+        while BLOCKS_FREED.load(Ordering::Acquire) < STRESS_BLOCK_COUNT {
+            assert!(monotonic_nsec() < deadline, "timed out waiting for the consumer to free every block");
+            core::hint::spin_loop();
+        }
+
+        // the other tests expect to run on the startup core
+        thread.set_cpu_mask(1 << start_cpu);
+        wait_for_migration(start_cpu);
+        thread.set_cpu_mask(u64::MAX);
+
+        assert_eq!(CORRUPT_BLOCK.load(Ordering::Acquire), usize::MAX, "block was reused while it was still in the channel");
+
+        // blocks only move from the producer to the consumer, so the producer keeps refilling and the consumer keeps flushing,
+        // other allocations on either cpu can only move a few blocks the other way
+        let min_batches = (STRESS_BLOCK_COUNT - SIZE_CLASS_COUNT * MAGAZINE_SIZE) / BATCH_SIZE / 2;
+        let producer_after = cpu_stats(PRODUCER_CPU);
+        let consumer_after = cpu_stats(start_cpu);
+        assert!(producer_after.misses - producer_before.misses >= min_batches as u64);
+        assert!(consumer_after.heap_locks - consumer_before.heap_locks >= min_batches as u64);
+    }
+}
\ No newline at end of file
@@ -1,6 +1,7 @@
 use core::cell::Cell;
 use core::cmp::max;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use super::{HeapAllocator, PaRef};
 use crate::container::{LinkedList, ListNode, ListNodeData, CursorMut};
@@ -9,7 +10,8 @@ use crate::prelude::*;
 use crate::sync::IMutex;
 
 const HEAP_ZONE_SIZE: usize = PAGE_SIZE * 8;
-const CHUNK_SIZE: usize = 1 << log2_up_const(size_of::<Node>());
+/// All allocations are a multiple of this size and aligned to at least this size
+pub(super) const CHUNK_SIZE: usize = 1 << log2_up_const(size_of::<Node>());
 // TODO: make not use 1 extra space in some scenarios
 const INITIAL_CHUNK_SIZE: usize = align_up(size_of::<HeapZone>(), CHUNK_SIZE);
 
@@ -315,15 +317,32 @@ impl LinkedListAllocatorInner {
 
 pub struct LinkedListAllocator {
     inner: IMutex<LinkedListAllocatorInner>,
+    /// Number of bytes currently allocated, counted with the size [`get_allocation`](Self::get_allocation) returns
+    /// 
+    /// Updated with relaxed ordering so it can be read without locking the allocator
+    allocated_bytes: AtomicUsize,
 }
 
 impl LinkedListAllocator {
     pub fn new(page_allocator: PaRef) -> Self {
         LinkedListAllocator {
             inner: IMutex::new(LinkedListAllocatorInner::new(page_allocator)),
+            allocated_bytes: AtomicUsize::new(0),
         }
     }
 
+    /// Returns the number of bytes which are currently allocated
+    /// 
+    /// Memory held in the per cpu heap caches counts as allocated.
+    pub fn allocated_bytes(&self) -> usize {
+        self.allocated_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Size that is counted for each allocation with `layout`, this is the same size that is freed on deallocation
+    fn counted_size(layout: Layout) -> usize {
+        align_up(layout.size(), max(CHUNK_SIZE, layout.align()))
+    }
+
     /// Given the pointer and layout, computes the actual allocation slice that was returned
     pub fn get_allocation(allocation_start: NonNull<u8>, layout: Layout) -> Option<NonNull<[u8]>> {
         if align_of(allocation_start.as_ptr() as usize) < CHUNK_SIZE {
@@ -334,16 +353,53 @@ impl LinkedListAllocator {
             Some(NonNull::slice_from_raw_parts(allocation_start, size))
         }
     }
+
+    /// Allocates up to `allocations.len()` allocations with the same layout while only locking the heap once
+    /// 
+    /// Returns the number of allocations made, which is less than requested if the heap ran out of memory
+    pub fn alloc_many(&self, layout: Layout, allocations: &mut [*mut u8]) -> usize {
+        let mut inner = self.inner.lock();
+
+        let mut count = 0;
+        for allocation in allocations.iter_mut() {
+            match inner.alloc(layout) {
+                Some(memory) => *allocation = memory.as_mut_ptr(),
+                None => break,
+            }
+            count += 1;
+        }
+
+        self.allocated_bytes.fetch_add(count * Self::counted_size(layout), Ordering::Relaxed);
+
+        count
+    }
+
+    /// Deallocates all the allocations, which all have the same layout, while only locking the heap once
+    pub unsafe fn dealloc_many(&self, allocations: &[*mut u8], layout: Layout) {
+        let mut inner = self.inner.lock();
+
+        for allocation in allocations {
+            unsafe {
+                inner.dealloc(NonNull::new(*allocation).expect("null allocation passed to dealloc"), layout);
+            }
+        }
+
+        self.allocated_bytes.fetch_sub(allocations.len() * Self::counted_size(layout), Ordering::Relaxed);
+    }
 }
 
 // TODO: add specialized realloc method
 unsafe impl HeapAllocator for LinkedListAllocator {
     fn alloc(&self, layout: Layout) -> Option<NonNull<[u8]>> {
-        self.inner.lock().alloc(layout)
+        let allocation = self.inner.lock().alloc(layout)?;
+        self.allocated_bytes.fetch_add(Self::counted_size(layout), Ordering::Relaxed);
+
+        Some(allocation)
     }
 
     unsafe fn dealloc(&self, allocation: NonNull<u8>, layout: Layout) {
         unsafe { self.inner.lock().dealloc(allocation, layout) }
+        self.allocated_bytes.fetch_sub(Self::counted_size(layout), Ordering::Relaxed);
     }
 }
 
@@ -354,3 +410,34 @@ impl Drop for LinkedListAllocator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::heap;
+
+    #[test_case]
+    fn allocated_bytes_returns_after_batch() {
+        const BATCH_SIZE: usize = 128;
+
+        // sizes which are not a multiple of the chunk size, so rounding has to match between alloc and dealloc
+        let layouts = [
+            Layout::from_size_align(100, 8).unwrap(),
+            Layout::from_size_align(3000, 64).unwrap(),
+        ];
+        let allocated_bytes = heap().allocated_bytes();
+
+        for layout in layouts {
+            let allocations = [(); BATCH_SIZE].map(|_| heap().alloc(layout).expect("allocation failed"));
+            assert!(heap().allocated_bytes() >= allocated_bytes + BATCH_SIZE * layout.size());
+
+            for allocation in allocations {
+                unsafe {
+                    heap().dealloc(allocation.as_non_null_ptr(), layout);
+                }
+            }
+        }
+
+        assert_eq!(heap().allocated_bytes(), allocated_bytes, "heap byte count drifted after freeing every allocation");
+    }
+}
@@ -1,8 +1,10 @@
 use sys::CapType;
 
 use crate::cap::CapObject;
+use crate::container::{Arc, Weak};
 use crate::cap::address_space::{AddressSpace, PhysMemMapping, AddrSpaceMapping, AddressSpaceInner, MappingId};
 use crate::prelude::*;
+use crate::sync::IMutex;
 use crate::vmem_manager::{MapAction, PageMappingOptions};
 
 use super::HeapRef;
@@ -16,38 +18,45 @@ pub struct MmioAllocator {
     /// 
     /// This includes memory storing kernel code and data and all memory used by regular page allocator
     reserved_regions: Vec<APhysRange>,
+    /// Regions which have been exclusively claimed with [`MmioAllocator::request_region`]
+    /// 
+    /// A claimed region is released when the [`PhysMem`] for it is dropped
+    claimed_regions: IMutex<Vec<APhysRange>>,
+}
+
+/// Returns the index `region` should be inserted at to keep `regions` sorted,
+/// or None if `region` overlaps any of the regions
+fn get_region_insert_index(regions: &[APhysRange], region: APhysRange) -> Option<usize> {
+    match regions.binary_search_by_key(&region.addr(), |region| region.addr()) {
+        // this address is already occupied
+        Ok(_) => None,
+        Err(index) => {
+            if (index == 0 || regions[index - 1].end_addr() <= region.addr())
+                && (index == regions.len() || region.end_addr() <= regions[index].addr()) {
+                Some(index)
+            } else {
+                // this region overlaps an already present region
+                None
+            }
+        },
+    }
 }
 
 impl MmioAllocator {
     pub fn new(allocator: HeapRef) -> Self {
         MmioAllocator {
-            reserved_regions: Vec::new(allocator),
-        }
-    }
-
-    fn get_reserved_region_insert_index(&self, region: APhysRange) -> Option<usize> {
-        match self.reserved_regions.binary_search_by_key(&region.addr(), |region| region.addr()) {
-            // this address is already occupied
-            Ok(_) => None,
-            Err(index) => {
-                if (index == 0 || self.reserved_regions[index - 1].end_addr() <= region.addr())
-                    && (index == self.reserved_regions.len() || region.end_addr() <= self.reserved_regions[index].addr()) {
-                    Some(index)
-                } else {
-                    // this region overlaps an already reserved region
-                    None
-                }
-            },
+            reserved_regions: Vec::new(allocator.clone()),
+            claimed_regions: IMutex::new(Vec::new(allocator)),
         }
     }
 
     fn overlaps_reserved_region(&self, region: APhysRange) -> bool {
-        self.get_reserved_region_insert_index(region).is_none()
+        get_region_insert_index(&self.reserved_regions, region).is_none()
     }
 
     /// Marks a new region as reserved
     pub(super) fn add_reserved_region(&mut self, reserved_region: APhysRange) -> KResult<()> {
-        if let Some(index) = self.get_reserved_region_insert_index(reserved_region) {
+        if let Some(index) = get_region_insert_index(&self.reserved_regions, reserved_region) {
             self.reserved_regions.insert(index, reserved_region)
         } else {
             Err(SysErr::InvlMemZone)
@@ -55,11 +64,46 @@ impl MmioAllocator {
     }
 
     /// Tries to allocate the memory region and returns a PhysMem capability for that region
+    /// 
+    /// The region is not claimed, so multiple PhysMem can be allocated for the same region,
+    /// but it cannot overlap a region claimed with [`MmioAllocator::request_region`]
     pub fn alloc(&self, region: APhysRange) -> KResult<PhysMem> {
-        if self.overlaps_reserved_region(region) {
+        if self.overlaps_reserved_region(region)
+            || get_region_insert_index(&self.claimed_regions.lock(), region).is_none() {
             Err(SysErr::InvlMemZone)
         } else {
-            Ok(PhysMem { region })
+            Ok(PhysMem {
+                region,
+                claimed_from: None,
+            })
+        }
+    }
+
+    /// Exclusively claims the memory region and returns a PhysMem capability for that region
+    /// 
+    /// Fails with [`SysErr::InvlMemZone`] if the region overlaps a reserved region or an already claimed region
+    /// 
+    /// The region becomes claimable again once the returned PhysMem is dropped
+    pub fn request_region(this: &Arc<Self>, region: APhysRange) -> KResult<PhysMem> {
+        if this.overlaps_reserved_region(region) {
+            return Err(SysErr::InvlMemZone);
+        }
+
+        let mut claimed_regions = this.claimed_regions.lock();
+        let index = get_region_insert_index(&claimed_regions, region)
+            .ok_or(SysErr::InvlMemZone)?;
+        claimed_regions.insert(index, region)?;
+
+        Ok(PhysMem {
+            region,
+            claimed_from: Some(Arc::downgrade(this)),
+        })
+    }
+
+    fn release_region(&self, region: APhysRange) {
+        let mut claimed_regions = self.claimed_regions.lock();
+        if let Ok(index) = claimed_regions.binary_search_by_key(&region.addr(), |region| region.addr()) {
+            claimed_regions.remove(index);
         }
     }
 }
@@ -68,21 +112,23 @@ impl CapObject for MmioAllocator {
     const TYPE: CapType = CapType::MmioAllocator;
 }
 
-struct MmioAllocatorInner {
-    allocated_zones: Vec<APhysRange>,
-}
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug)]
 pub struct PhysMem {
     region: APhysRange,
+    /// Allocator this region was claimed from, if it was claimed with [`MmioAllocator::request_region`]
+    claimed_from: Option<Weak<MmioAllocator>>,
 }
 
 impl PhysMem {
-    pub fn map(&self, address_space: &AddressSpace, address: VirtAddr, options: PageMappingOptions) -> KResult<Size> {
+    /// Maps this phys mem into the address space
+    /// 
+    /// The cache setting of `options` decides how the mapping is cached,
+    /// for example device registers should be uncached and framebuffers write combining
+    pub fn map(this: &Arc<Self>, address_space: &AddressSpace, address: VirtAddr, options: PageMappingOptions) -> KResult<Size> {
         let mut addr_space_inner = address_space.inner();
 
         let mapping = PhysMemMapping {
-            phys_mem: *self,
+            phys_mem: this.clone(),
             map_range: AVirtRange::new(address, self.region.size()),
             options,
             map_id: MappingId::new(),
@@ -91,7 +137,7 @@ impl PhysMem {
         addr_space_inner.mappings.insert_mapping(AddrSpaceMapping::PhysMem(mapping))?;
 
         let map_result = unsafe {
-            addr_space_inner.addr_space.map_many(self.iter_mapping(address, options))
+            addr_space_inner.addr_space.map_many(this.iter_mapping(address, options))
         };
 
         if let Err(error) = map_result {
@@ -99,7 +145,7 @@ impl PhysMem {
             addr_space_inner.mappings.remove_mapping_from_address(address).unwrap();
             Err(error)
         } else {
-            Ok(Size::from_bytes(self.region.size()))
+            Ok(this.size())
         }
     }
 
@@ -107,15 +153,14 @@ impl PhysMem {
         let mapping = address_space.mappings.remove_mapping_from_address(address)
             .ok_or(SysErr::InvlVirtAddr)?;
 
-        let AddrSpaceMapping::PhysMem(mapping) = mapping else {
+        let AddrSpaceMapping::PhysMem(_) = mapping else {
             panic!("tried to unmap regular memory with physmem unmap");
         };
 
-        for map_action in self.iter_mapping(address, mapping.options) {
-            unsafe {
-                address_space.addr_space.unmap_page(map_action.virt_addr).expect("failed to unmap physmem page");
-            }
-        }
+        let unmapped_pages = unsafe {
+            address_space.addr_space.unmap_range(AVirtRange::new(address, self.region.size()))
+        };
+        assert_eq!(unmapped_pages, self.region.page_size(), "failed to unmap physmem page");
 
         Ok(())
     }
@@ -136,6 +181,58 @@ impl PhysMem {
     }
 }
 
+impl Drop for PhysMem {
+    fn drop(&mut self) {
+        if let Some(mmio_allocator) = self.claimed_from.as_ref().and_then(Weak::upgrade) {
+            mmio_allocator.release_region(self.region);
+        }
+    }
+}
+
 impl CapObject for PhysMem {
     const TYPE: CapType = CapType::PhysMem;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::root_alloc_ref;
+
+    fn test_allocator() -> Arc<MmioAllocator> {
+        let mut mmio_allocator = MmioAllocator::new(root_alloc_ref());
+        mmio_allocator.add_reserved_region(APhysRange::new(PhysAddr::new(0x100000), 4 * PAGE_SIZE)).unwrap();
+
+        Arc::new(mmio_allocator, root_alloc_ref()).unwrap()
+    }
+
+    #[test_case]
+    fn request_region_rejects_reserved_and_claimed() {
+        let mmio_allocator = test_allocator();
+
+        let reserved = APhysRange::new(PhysAddr::new(0x102000), PAGE_SIZE);
+        assert_eq!(MmioAllocator::request_region(&mmio_allocator, reserved).unwrap_err(), SysErr::InvlMemZone);
+
+        let region = APhysRange::new(PhysAddr::new(0x200000), 2 * PAGE_SIZE);
+        let _phys_mem = MmioAllocator::request_region(&mmio_allocator, region).unwrap();
+
+        let overlapping = APhysRange::new(PhysAddr::new(0x201000), 2 * PAGE_SIZE);
+        assert_eq!(MmioAllocator::request_region(&mmio_allocator, region).unwrap_err(), SysErr::InvlMemZone);
+        assert_eq!(MmioAllocator::request_region(&mmio_allocator, overlapping).unwrap_err(), SysErr::InvlMemZone);
+        assert_eq!(mmio_allocator.alloc(overlapping).unwrap_err(), SysErr::InvlMemZone);
+
+        let adjacent = APhysRange::new(PhysAddr::new(0x202000), PAGE_SIZE);
+        assert!(MmioAllocator::request_region(&mmio_allocator, adjacent).is_ok());
+    }
+
+    #[test_case]
+    fn request_region_released_on_drop() {
+        let mmio_allocator = test_allocator();
+        let region = APhysRange::new(PhysAddr::new(0x200000), PAGE_SIZE);
+
+        let phys_mem = MmioAllocator::request_region(&mmio_allocator, region).unwrap();
+        assert!(MmioAllocator::request_region(&mmio_allocator, region).is_err());
+
+        drop(phys_mem);
+        assert!(MmioAllocator::request_region(&mmio_allocator, region).is_ok());
+    }
 }
\ No newline at end of file
@@ -5,6 +5,7 @@
 mod cap_allocator;
 mod fixed_page_allocator;
 mod heap_allocator;
+mod heap_cache;
 mod linked_list_allocator;
 mod mmio_allocator;
 mod page_allocator;
@@ -12,6 +13,7 @@ mod pmem_manager;
 
 pub use cap_allocator::CapAllocator;
 pub use heap_allocator::{HeapRef, HeapAllocator};
+pub use heap_cache::{HeapCache, HeapCacheStats, enable_heap_caches, print_heap_cache_stats};
 use linked_list_allocator::LinkedListAllocator;
 pub use page_allocator::{PaRef, PageAllocator};
 pub use mmio_allocator::{MmioAllocator, PhysMem};
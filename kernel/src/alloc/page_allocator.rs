@@ -64,9 +64,17 @@ impl PaRef {
     }
 
     pub fn alloc(&mut self, layout: PageLayout) -> Option<Allocation> {
+        self.try_alloc(layout).ok()
+    }
+
+    /// Allocates pages, and returns why the allocation failed on failure
+    /// 
+    /// This returns [`SysErr::QuotaExceeded`] if this references a [`CapAllocator`] which is at its limit,
+    /// or [`SysErr::OutOfMem`] otherwise
+    pub fn try_alloc(&mut self, layout: PageLayout) -> KResult<Allocation> {
         match self.0 {
-            PaRefInner::PmemManager(pmem_manager) => pmem_manager.alloc(layout),
-            PaRefInner::InitAllocator(init_allocator) => unsafe { (*init_allocator).alloc(layout) },
+            PaRefInner::PmemManager(pmem_manager) => pmem_manager.alloc(layout).ok_or(SysErr::OutOfMem),
+            PaRefInner::InitAllocator(init_allocator) => unsafe { (*init_allocator).alloc(layout) }.ok_or(SysErr::OutOfMem),
             PaRefInner::CapAllocator(ref mut cap_allocator) => cap_allocator.page_alloc(layout),
         }
     }
@@ -8,6 +8,7 @@ use core::slice;
 use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 
 use pmem_allocator::PmemAllocator;
+use sys::ZoneMemInfo;
 use zone_map::ZoneMap;
 
 use super::fixed_page_allocator::FixedPageAllocator;
@@ -194,6 +195,32 @@ impl PmemManager {
         )
     }
 
+    /// Returns the number of pages managed by all zones
+    pub fn total_pages(&self) -> usize {
+        self.allocers.iter()
+            .map(|allocator| allocator.size() / PAGE_SIZE)
+            .sum()
+    }
+
+    /// Returns the number of pages in all zones which are not allocated
+    /// 
+    /// This is only a snapshot, and may be slightly off while other cpus are allocating
+    pub fn free_pages(&self) -> usize {
+        self.allocers.iter()
+            .map(|allocator| allocator.free_space() / PAGE_SIZE)
+            .sum()
+    }
+
+    /// Returns usage information about each zone, in order of physical address
+    pub fn zone_infos(&self) -> impl Iterator<Item = ZoneMemInfo> + '_ {
+        self.allocers.iter().map(|allocator| ZoneMemInfo {
+            start_address: allocator.addr_range().to_phys().as_usize(),
+            total_pages: allocator.size() / PAGE_SIZE,
+            free_pages: allocator.free_space() / PAGE_SIZE,
+            largest_free_pages: allocator.largest_free_block() / PAGE_SIZE,
+        })
+    }
+
     /// Returns the size that would be allocated for the given page layout
     pub fn get_allocation_size_for_layout(layout: PageLayout) -> usize {
         1 << log2_up(layout.size())
@@ -286,3 +313,116 @@ unsafe impl PageAllocator for PmemManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::zm;
+
+    /// Size in pages of each allocation made in an allocation cycle
+    const ALLOCATION_PAGES: [usize; 9] = [4, 2, 2, 10, 4, 15, 4, 1, 5];
+    /// Order the allocations are freed in, different from the order they are made in so buddies are merged out of order
+    const DEALLOC_ORDER: [usize; 9] = [8, 3, 0, 2, 7, 1, 4, 6, 5];
+
+    fn check_allocations(allocations: &[Allocation]) {
+        for (i, allocation) in allocations.iter().enumerate() {
+            kassert!(
+                allocation.size() >= ALLOCATION_PAGES[i] * PAGE_SIZE,
+                "allocation {:x?} is smaller than {} pages", allocation, ALLOCATION_PAGES[i],
+            );
+            kassert_eq!(allocation.as_usize() % PAGE_SIZE, 0, "allocation {:x?} is not page aligned", allocation);
+
+            for other in allocations[i + 1..].iter() {
+                let overlaps = allocation.as_usize() < other.as_usize() + other.size()
+                    && other.as_usize() < allocation.as_usize() + allocation.size();
+                kassert!(!overlaps, "allocation {:x?} overlaps {:x?}", allocation, other);
+            }
+        }
+    }
+
+    /// Makes every allocation with `alloc` and checks them, then frees them with `dealloc`
+    /// 
+    /// Returns the address of each allocation.
+    fn allocation_cycle(alloc: impl Fn(usize) -> Option<Allocation>, dealloc: impl Fn(Allocation)) -> [usize; 9] {
+        let allocations = ALLOCATION_PAGES.map(|pages| alloc(pages * PAGE_SIZE).expect("allocation failed"));
+        check_allocations(&allocations);
+
+        for index in DEALLOC_ORDER {
+            dealloc(allocations[index]);
+        }
+
+        allocations.map(|allocation| allocation.as_usize())
+    }
+
+    #[test_case]
+    fn allocations_do_not_overlap() {
+        for _ in 0..2 {
+            allocation_cycle(
+                |size| zm().alloc(PageLayout::from_size_align(size, PAGE_SIZE).unwrap()),
+                |allocation| unsafe { zm().dealloc(allocation) },
+            );
+        }
+    }
+
+    #[test_case]
+    fn freed_memory_is_reused() {
+        // the manager spreads allocations between zones, so use one zone to get predictable addresses
+        let allocator = zm().allocers.iter()
+            .max_by_key(|allocator| allocator.free_space())
+            .unwrap();
+
+        let free_space = allocator.free_space();
+        let first_addresses = allocation_cycle(|size| allocator.alloc(size), |allocation| unsafe { allocator.dealloc(allocation) });
+        let second_addresses = allocation_cycle(|size| allocator.alloc(size), |allocation| unsafe { allocator.dealloc(allocation) });
+
+        kassert_eq!(first_addresses, second_addresses, "allocations moved after everything was freed");
+        kassert_eq!(allocator.free_space(), free_space, "not all memory was returned to the zone");
+    }
+
+    #[test_case]
+    fn free_page_count_returns_after_batch() {
+        const BATCH_SIZE: usize = 64;
+        const BATCH_ALLOCATION_PAGES: usize = 4;
+
+        let layout = |pages| PageLayout::from_size_align(pages * PAGE_SIZE, PAGE_SIZE).unwrap();
+        let free_pages = zm().free_pages();
+
+        let allocations = [(); BATCH_SIZE].map(|_| zm().alloc(layout(BATCH_ALLOCATION_PAGES)).expect("allocation failed"));
+        kassert_eq!(zm().free_pages(), free_pages - BATCH_SIZE * BATCH_ALLOCATION_PAGES);
+
+        // shrinking and growing in place must also keep the count right
+        let shrunk = unsafe { zm().realloc_in_place(allocations[0], layout(1)) }.expect("failed to shrink allocation");
+        let grown = unsafe { zm().realloc_in_place(shrunk, layout(BATCH_ALLOCATION_PAGES)) };
+        let first_allocation = grown.unwrap_or(shrunk);
+        kassert_eq!(zm().free_pages(), free_pages - (BATCH_SIZE - 1) * BATCH_ALLOCATION_PAGES - first_allocation.size() / PAGE_SIZE);
+
+        unsafe {
+            zm().dealloc(first_allocation);
+            for allocation in &allocations[1..] {
+                zm().dealloc(*allocation);
+            }
+        }
+
+        kassert_eq!(zm().free_pages(), free_pages, "free page count drifted after freeing every allocation");
+    }
+
+    #[test_case]
+    fn largest_free_block_can_be_allocated() {
+        let allocator = zm().allocers.iter()
+            .max_by_key(|allocator| allocator.free_space())
+            .unwrap();
+
+        let largest = allocator.largest_free_block();
+        kassert!(largest > 0 && largest <= allocator.free_space());
+
+        if largest < allocator.size() {
+            kassert!(allocator.alloc(2 * largest).is_none(), "block bigger than the largest free block was allocated");
+        }
+
+        let allocation = allocator.alloc(largest).expect("failed to allocate the largest free block");
+        kassert_eq!(allocation.size(), largest);
+        unsafe {
+            allocator.dealloc(allocation);
+        }
+    }
+}
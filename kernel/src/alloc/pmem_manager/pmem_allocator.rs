@@ -1,4 +1,4 @@
-use core::cmp::min;
+use core::cmp::{min, max};
 use core::slice;
 use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 
@@ -186,7 +186,9 @@ pub struct PmemAllocator {
     max_size: usize,
     /// minimum size of a level, size of node at maximum depth
     level_size: usize,
-    // amount of free memory available
+    /// Amount of free memory available
+    /// 
+    /// This is updated with relaxed ordering, so it may be slightly out of date while allocations are happening
     free_space: AtomicUsize,
 }
 
@@ -296,7 +298,7 @@ impl PmemAllocator {
                     continue;
                 } else {
                     // allocation succeeded
-                    self.free_space.fetch_sub(node.size(), Ordering::AcqRel);
+                    self.free_space.fetch_sub(node.size(), Ordering::Relaxed);
                     return Some(Allocation::new(node.addr(), node.size()));
                 }
             }
@@ -412,11 +414,14 @@ impl PmemAllocator {
                 current_node.data().store(0, Ordering::Release);
             }
 
+            self.free_space.fetch_sub(new_node.size() - old_node.size(), Ordering::Relaxed);
+
             Some(Allocation::new(new_node.addr(), new_node.size()))
         } else if old_level < new_level {
             // allocation needs to be shrunk
             let new_node = unsafe { self.shrink_node(old_node, new_level) };
-            
+            self.free_space.fetch_add(old_node.size() - new_node.size(), Ordering::Relaxed);
+
             Some(Allocation::new(new_node.addr(), new_node.size()))
         } else {
             // allocation can stay the same size
@@ -458,7 +463,7 @@ impl PmemAllocator {
 
         self.dealloc_node(node, self.get_tree_node(0));
 
-        self.free_space.fetch_add(node.size(), Ordering::AcqRel);
+        self.free_space.fetch_add(node.size(), Ordering::Relaxed);
     }
 
     pub fn addr_range(&self) -> AVirtRange {
@@ -469,8 +474,39 @@ impl PmemAllocator {
         self.addr_range.as_usize()
     }
 
+    /// Returns the total amount of memory this allocator manages
+    pub fn size(&self) -> usize {
+        self.max_size
+    }
+
     pub fn free_space(&self) -> usize {
-        self.free_space.load(Ordering::Acquire)
+        self.free_space.load(Ordering::Relaxed)
+    }
+
+    /// Returns the size of the biggest allocation which could currently succeed, or 0 if nothing can be allocated
+    /// 
+    /// This walks the parts of the tree which are partly allocated, so it is slower than [`free_space`](Self::free_space).
+    pub fn largest_free_block(&self) -> usize {
+        self.largest_free_block_in(self.get_tree_node(0))
+    }
+
+    fn largest_free_block_in(&self, node: TreeNode) -> usize {
+        let flags = TreeStatus::from_bits_retain(node.data().load(Ordering::Acquire));
+
+        if flags.contains(TreeStatus::OCCUPY) {
+            return 0;
+        } else if !flags.intersects(TreeStatus::BUSY) {
+            return node.size();
+        } else if node.level() == self.depth {
+            return 0;
+        }
+
+        // if either child has nothing allocated in it, no smaller block can be bigger
+        if !flags.contains(TreeStatus::OCCUPY_LEFT) || !flags.contains(TreeStatus::OCCUPY_RIGHT) {
+            return node.size() / 2;
+        }
+
+        max(self.largest_free_block_in(node.left()), self.largest_free_block_in(node.right()))
     }
 
     // goes up the tree starting from start, and up to and including end
@@ -10,6 +10,7 @@ struct CpuidRet {
     edx: u32,
 }
 
+/// Executes cpuid for leaf `n`, with subleaf 0 for leaves which have subleaves
 fn cpuid(n: u32) -> CpuidRet {
     let eax: u32;
     let ebx: u32;
@@ -22,7 +23,7 @@ fn cpuid(n: u32) -> CpuidRet {
 			 "pop rbx",
 			 inout("eax") n => eax,
 			 out("edi") ebx,
-			 out("ecx") ecx,
+			 inout("ecx") 0 => ecx,
 			 out("edx") edx,
 			 options(nomem, nostack));
     }
@@ -50,4 +51,26 @@ pub fn core_clock_freq() -> u32 {
 /// Checks for presence of page attribute table, which allows setting all cache control modes with just page table entries
 pub fn has_pat() -> bool {
     get_bits(cpuid(1).edx as usize, 16..17) == 1
+}
+
+/// Checks for presence of the rdrand instruction
+pub fn has_rdrand() -> bool {
+    get_bits(cpuid(1).ecx as usize, 30..31) == 1
+}
+
+/// Checks for presence of the rdseed instruction
+pub fn has_rdseed() -> bool {
+    get_bits(cpuid(7).ebx as usize, 18..19) == 1
+}
+
+/// Checks for presence of the monitor and mwait instructions
+pub fn has_monitor_mwait() -> bool {
+    get_bits(cpuid(1).ecx as usize, 3..4) == 1
+}
+
+/// Checks if the time stamp counter runs at a constant rate regardless of power state and frequency changes
+pub fn has_invariant_tsc() -> bool {
+    // invariant tsc is reported in an extended leaf, which may not exist
+    cpuid(0x80000000).eax >= 0x80000007
+        && get_bits(cpuid(0x80000007).edx as usize, 8..9) == 1
 }
\ No newline at end of file
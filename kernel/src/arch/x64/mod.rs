@@ -1,4 +1,5 @@
 use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
 use core::time::Duration;
 
 use crate::prelude::*;
@@ -173,6 +174,82 @@ pub fn hlt() {
     }
 }
 
+#[inline]
+pub fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack));
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+/// Frequency of the time stamp counter in ticks per second, this is 0 until it is calibrated
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the time stamp counter frequency from the number of ticks that elapsed during `duration`
+pub fn calibrate_tsc(elapsed_ticks: u64, duration: Duration) {
+    let frequency = elapsed_ticks as u128 * 1_000_000_000 / duration.as_nanos();
+    TSC_FREQUENCY.store(frequency as u64, Ordering::Release);
+}
+
+/// Converts a number of time stamp counter ticks to nanoseconds
+/// 
+/// Returns 0 if the time stamp counter has not been calibrated
+pub fn tsc_ticks_to_nsec(ticks: u64) -> u64 {
+    let frequency = TSC_FREQUENCY.load(Ordering::Acquire);
+    if frequency == 0 {
+        return 0;
+    }
+
+    (ticks as u128 * 1_000_000_000 / frequency as u128) as u64
+}
+
+/// Number of times rdrand and rdseed are retried when no random value is ready
+const RANDOM_RETRY_COUNT: usize = 16;
+
+/// Gets a random number from the rdrand instruction, or None if no random number was available
+/// 
+/// The caller must ensure rdrand is supported
+#[inline]
+pub fn rdrand() -> Option<u64> {
+    for _ in 0..RANDOM_RETRY_COUNT {
+        let value: u64;
+        let success: u8;
+        unsafe {
+            asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) success, options(nomem, nostack));
+        }
+
+        if success != 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Gets a random number from the rdseed instruction, or None if no random number was available
+/// 
+/// The caller must ensure rdseed is supported
+#[inline]
+pub fn rdseed() -> Option<u64> {
+    for _ in 0..RANDOM_RETRY_COUNT {
+        let value: u64;
+        let success: u8;
+        unsafe {
+            asm!("rdseed {}", "setc {}", out(reg) value, out(reg_byte) success, options(nomem, nostack));
+        }
+
+        if success != 0 {
+            return Some(value);
+        }
+
+        core::hint::spin_loop();
+    }
+
+    None
+}
+
 // TODO: use bitflags
 pub const RFLAGS_INT: usize = 1 << 9;
 
@@ -220,6 +297,24 @@ pub fn sti_hlt() {
     }
 }
 
+/// Arms address monitoring on the cache line containing `address`, so a following mwait returns when it is written
+#[inline]
+pub fn monitor<T>(address: *const T) {
+    unsafe {
+        asm!("monitor", in("rax") address, in("ecx") 0, in("edx") 0, options(nostack));
+    }
+}
+
+/// Enables interrupts and waits until the monitored address is written or an interrupt occurs
+///
+/// Like [`sti_hlt`], an interrupt which is pending before this is called ends the wait
+#[inline]
+pub fn sti_mwait() {
+    unsafe {
+        asm!("sti\nmwait", in("eax") 0, in("ecx") 0, options(nomem, nostack));
+    }
+}
+
 pub fn is_int_enabled() -> bool {
     get_flags() & RFLAGS_INT != 0
 }
@@ -267,14 +362,14 @@ pub fn outb(port: u16, data: u8) {
 #[inline]
 pub fn outw(port: u16, data: u16) {
     unsafe {
-        asm!("out dx, al", in("dx") port, in("ax") data);
+        asm!("out dx, ax", in("dx") port, in("ax") data);
     }
 }
 
 #[inline]
 pub fn outd(port: u16, data: u32) {
     unsafe {
-        asm!("out dx, al", in("dx") port, in("eax") data);
+        asm!("out dx, eax", in("dx") port, in("eax") data);
     }
 }
 
@@ -411,9 +506,11 @@ extern "C" {
     fn asm_gs_addr() -> usize;
     pub fn asm_switch_thread(new_rsp: usize, new_addr_space: usize);
     pub fn asm_thread_init();
+    pub fn asm_kernel_thread_init();
 
-    pub fn asm_user_copy(dst: *mut u8, src: *const u8, count: usize) -> bool;
-    pub fn asm_user_copy_fail() -> bool;
+    /// Returns the number of bytes copied before a page fault which could not be resolved, or `count` if there was none
+    pub fn asm_user_copy(dst: *mut u8, src: *const u8, count: usize) -> usize;
+    pub fn asm_user_copy_fail() -> usize;
 }
 
 pub fn gs_addr() -> usize {
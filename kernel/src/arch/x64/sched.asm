@@ -2,6 +2,7 @@
 
 global asm_switch_thread
 global asm_thread_init
+global asm_kernel_thread_init
 
 extern post_switch_handler
 
@@ -71,4 +72,11 @@ asm_thread_init:
     pop rax
     mov rsp, rax
 
-    o64 sysret
\ No newline at end of file
+    o64 sysret
+
+asm_kernel_thread_init:
+    ; load the entry point of the kernel thread
+    pop rax
+
+    ; the stack is now aligned as if the entry point was called, with the return address set to 0
+    jmp rax
\ No newline at end of file
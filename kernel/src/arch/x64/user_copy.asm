@@ -5,19 +5,23 @@ global asm_user_copy_fail:
 section .text
 bits 64
 asm_user_copy:
-    ; asm_user_copy(dst, src, count) -> bool
+    ; asm_user_copy(dst, src, count) -> usize
+    ; returns the number of bytes copied, which is count on success
     cld
-    mov ecx, edx
+    ; r8 is not touched by the page fault handler, so the count is still there if the copy fails
+    mov r8, rdx
+    mov rcx, rdx
     rep movsb
 
-    ; indicates success
-    mov rax, 1
+    mov rax, r8
 
     ret
 asm_user_copy_end:
 
 ; page fault handler will go to this function on user copy failure
 asm_user_copy_fail:
-    ; indicates a failure (page fault) while copying to or from userspace
-    xor rax, rax
-    ret
\ No newline at end of file
+    ; a page fault occured while copying to or from userspace
+    ; rcx is the number of bytes rep movsb had left to copy when it faulted
+    mov rax, r8
+    sub rax, rcx
+    ret
@@ -1,17 +1,20 @@
+use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use sys::CapType;
+use sys::{CapType, FaultAccess};
 
 use crate::alloc::{HeapRef, PaRef, PhysMem};
 use crate::consts;
-use crate::event::EventPool;
+use crate::event::{EventPool, EventPoolListenerRef};
 use crate::prelude::*;
 use crate::sync::{IMutex, IMutexGuard};
-use crate::vmem_manager::{VirtAddrSpace, PageMappingOptions};
-use crate::container::{Arc, HashMap};
+use crate::vmem_manager::{VirtAddrSpace, PageMappingOptions, CpuSet};
+use crate::container::{Arc, Weak, HashMap};
 
 use super::memory::MemoryMappingLocation;
 use super::{CapObject, memory::Memory};
+use super::capability_space::CapabilitySpace;
+use crate::sched::FutexQueues;
 
 crate::make_id_type!(MappingId);
 
@@ -23,19 +26,36 @@ impl MappingId {
     }
 }
 
+/// Where page faults which could not be resolved by the kernel are reported
+#[derive(Debug, Clone)]
+pub struct FaultHandler {
+    pub listener: EventPoolListenerRef,
+    /// Capability space where capabilities to faulting threads are inserted
+    pub cspace: Weak<CapabilitySpace>,
+}
+
 #[derive(Debug)]
 pub struct AddressSpace {
     inner: IMutex<AddressSpaceInner>,
+    fault_handler: IMutex<Option<FaultHandler>>,
+    /// Threads waiting in `futex_wait` on addresses in this address space
+    futexes: IMutex<FutexQueues>,
+    /// Cpus which currently have this address space loaded, updated when switching threads
+    active_cpus: Arc<CpuSet>,
     cr3: PhysAddr,
 }
 
 impl AddressSpace {
     pub fn new(page_allocator: PaRef, heap_allocator: HeapRef) -> KResult<Self> {
-        let addr_space = VirtAddrSpace::new(page_allocator)?;
+        let active_cpus = Arc::new(CpuSet::new(), heap_allocator.clone())?;
+        let addr_space = VirtAddrSpace::new(page_allocator, active_cpus.clone())?;
 
         Ok(AddressSpace {
             cr3: addr_space.cr3_addr(),
-            inner: IMutex::new(AddressSpaceInner {
+            active_cpus,
+            fault_handler: IMutex::named("address_space_fault_handler", None),
+            futexes: IMutex::named("address_space_futexes", FutexQueues::new(heap_allocator.clone())),
+            inner: IMutex::named("address_space", AddressSpaceInner {
                 addr_space,
                 mappings: AddrSpaceMappings {
                     mappings: Vec::new(heap_allocator.clone()),
@@ -54,9 +74,15 @@ impl AddressSpace {
         self.cr3
     }
 
+    pub fn active_cpus(&self) -> &CpuSet {
+        &self.active_cpus
+    }
+
     /// Used to get dirrect access to inner address space
-    pub fn inner(&self) -> IMutexGuard<AddressSpaceInner> {
-        self.inner.lock()
+    /// 
+    /// Tlb entries of pages modified while the lock is held are invalidated on other cpus when it is released
+    pub fn inner(&self) -> AddressSpaceGuard {
+        AddressSpaceGuard(self.inner.lock())
     }
 
     pub fn unmap(&self, address: VirtAddr) -> KResult<()> {
@@ -77,10 +103,98 @@ impl AddressSpace {
                 event_pool.unmap()
             },
             AddrSpaceMapping::PhysMem(mapping) => {
-                let phys_mem = mapping.phys_mem;
+                let phys_mem = mapping.phys_mem.clone();
                 phys_mem.unmap(&mut inner, address)
             },
+            AddrSpaceMapping::Guard(_) => {
+                // nothing is mapped in the page tables for guards
+                inner.mappings.remove_mapping_from_address(address);
+                Ok(())
+            },
+        }
+    }
+
+    /// Unmaps everything in `range`
+    /// 
+    /// Memory mappings which are only partially covered by `range` are shrunk, or split in 2 if `range` is in the middle of them.
+    /// Any other kind of mapping can't be split, so it must be entirely inside of `range`, or this fails with `InvlOp`.
+    /// 
+    /// If this fails partway through, mappings before the one which failed are already unmapped.
+    pub fn unmap_range(&self, range: AVirtRange) -> KResult<()> {
+        let can_unmap = |mapping: &AddrSpaceMapping| {
+            matches!(mapping, AddrSpaceMapping::Memory(_)) || range.full_contains_range(&mapping.map_range())
+        };
+
+        // check everything first so the common error cases don't leave the range partially unmapped
+        if !self.inner().mappings.mappings_overlapping(range).all(can_unmap) {
+            return Err(SysErr::InvlOp);
+        }
+
+        loop {
+            let inner = self.inner();
+
+            let Some(mapping) = inner.mappings.mappings_overlapping(range).next() else {
+                return Ok(());
+            };
+
+            if !can_unmap(mapping) {
+                return Err(SysErr::InvlOp);
+            }
+
+            let address = mapping.map_range().addr();
+            match mapping {
+                AddrSpaceMapping::Memory(mapping) => {
+                    let memory = mapping.memory.clone();
+                    // memory lock must be acquired before address space lock
+                    drop(inner);
+                    memory.unmap_memory_range(self, address, range)?;
+                },
+                _ => {
+                    drop(inner);
+                    self.unmap(address)?;
+                },
+            }
+        }
+    }
+
+    /// Reserves `range` as a guard region, nothing can be mapped in a guard region until it is unmapped
+    /// 
+    /// Faults in a guard region are treated as stack overflows
+    pub fn map_guard(&self, range: AVirtRange) -> KResult<()> {
+        let mut inner = self.inner();
+
+        inner.mappings.insert_mapping(AddrSpaceMapping::Guard(GuardMapping {
+            map_range: range,
+            map_id: MappingId::new(),
+        }))
+    }
+
+    /// Returns true if `address` is inside of a guard region
+    pub fn is_guard_address(&self, address: VirtAddr) -> bool {
+        matches!(
+            self.inner().mappings.get_mapping_containing_address(address),
+            Some(AddrSpaceMapping::Guard(_)),
+        )
+    }
+
+    /// Clones the mappings at an address of at least `cursor` into `out`, in address order
+    /// 
+    /// Returns the number of mappings written to `out`
+    pub fn list_mappings(&self, cursor: usize, out: &mut [Option<AddrSpaceMapping>]) -> usize {
+        let inner = self.inner();
+
+        let mut count = 0;
+        for (slot, mapping) in out.iter_mut().zip(inner.mappings.mappings_from(cursor)) {
+            *slot = Some(mapping.clone());
+            count += 1;
         }
+
+        count
+    }
+
+    /// Returns the number of pages used by this address space's page tables
+    pub fn page_table_pages(&self) -> usize {
+        self.inner().addr_space.page_table_pages()
     }
 
     pub fn memory_at_addr(&self, address: VirtAddr) -> KResult<Arc<Memory>> {
@@ -95,12 +209,67 @@ impl AddressSpace {
 
         Ok(mapping.memory.clone())
     }
+
+    /// Handles a page fault caused by `access` at `address` in this address space
+    /// 
+    /// This allocates lazily allocated pages and copies copy on write pages in memory mapped at `address`
+    /// 
+    /// Returns an error if no memory is mapped at `address`, or if the mapping does not allow this access
+    pub fn handle_page_fault(&self, address: VirtAddr, access: FaultAccess) -> KResult<()> {
+        let inner = self.inner();
+
+        let mapping = inner.mappings.get_mapping_containing_address(address)
+            .ok_or(SysErr::InvlVirtAddr)?;
+
+        let AddrSpaceMapping::Memory(mapping) = mapping else {
+            return Err(SysErr::InvlOp);
+        };
+
+        let options = mapping.location.options;
+        let allowed = match access {
+            FaultAccess::Read => options.read,
+            FaultAccess::Write => options.write,
+            FaultAccess::Execute => options.exec,
+        };
+
+        if !allowed {
+            return Err(SysErr::InvlOp);
+        }
+
+        let page_index = mapping.location.offset.pages_rounded()
+            + (address - mapping.location.map_addr) / PAGE_SIZE;
+        let memory = mapping.memory.clone();
+
+        // memory lock must be acquired before address space lock
+        drop(inner);
+        memory.resolve_page_fault(page_index, access == FaultAccess::Write)
+    }
 }
 
 impl CapObject for AddressSpace {
     const TYPE: CapType = CapType::AddressSpace;
 }
 
+impl AddressSpace {
+    pub fn fault_handler(&self) -> Option<FaultHandler> {
+        self.fault_handler.lock().clone()
+    }
+
+    /// Sets the fault handler, or removes it if `fault_handler` is None
+    pub fn set_fault_handler(&self, fault_handler: Option<FaultHandler>) {
+        *self.fault_handler.lock() = fault_handler;
+    }
+
+    /// Threads waiting on futexes in this address space, they are dropped along with the address space
+    /// 
+    /// # Locking
+    /// 
+    /// Userspace memory may be read while this is locked, so page faults must not lock it
+    pub fn futexes(&self) -> &IMutex<FutexQueues> {
+        &self.futexes
+    }
+}
+
 /// Stores details about memory mapped in the address space
 #[derive(Debug, Clone)]
 pub struct MemoryMapping {
@@ -119,18 +288,26 @@ pub struct EventPoolMapping {
 /// Stores details about phys mem mapped in the address space
 #[derive(Debug, Clone)]
 pub struct PhysMemMapping {
-    pub phys_mem: PhysMem,
+    pub phys_mem: Arc<PhysMem>,
     pub map_range: AVirtRange,
     pub options: PageMappingOptions,
     pub map_id: MappingId,
 }
 
+/// Stores details about a guard region reserved in the address space
+#[derive(Debug, Clone)]
+pub struct GuardMapping {
+    pub map_range: AVirtRange,
+    pub map_id: MappingId,
+}
+
 /// Represents where in the address space a capability was mapped
 #[derive(Debug, Clone)]
 pub enum AddrSpaceMapping {
     Memory(MemoryMapping),
     EventPool(EventPoolMapping),
     PhysMem(PhysMemMapping),
+    Guard(GuardMapping),
 }
 
 impl AddrSpaceMapping {
@@ -140,6 +317,7 @@ impl AddrSpaceMapping {
             Self::Memory(memory) => memory.mapping_id,
             Self::EventPool(event_pool) => event_pool.event_pool.id(),
             Self::PhysMem(phys_mem) => phys_mem.map_id,
+            Self::Guard(guard) => guard.map_id,
         }
     }
 
@@ -148,6 +326,7 @@ impl AddrSpaceMapping {
             Self::Memory(memory) => memory.location.map_range(),
             Self::EventPool(event_pool) => event_pool.map_range,
             Self::PhysMem(phys_mem) => phys_mem.map_range,
+            Self::Guard(guard) => guard.map_range,
         }
     }
 
@@ -156,6 +335,37 @@ impl AddrSpaceMapping {
     }
 }
 
+/// Lock guard for [`AddressSpaceInner`] which sends a tlb shootdown for all modified pages when dropped
+/// 
+/// This means one shootdown is sent for a whole map or unmap operation
+#[derive(Debug)]
+pub struct AddressSpaceGuard<'a>(IMutexGuard<'a, AddressSpaceInner>);
+
+impl Deref for AddressSpaceGuard<'_> {
+    type Target = AddressSpaceInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for AddressSpaceGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for AddressSpaceGuard<'_> {
+    fn drop(&mut self) {
+        // the lock is still held here, so no one can reuse the unmapped pages before other cpus stop using them
+        // 
+        // waiting for other cpus with the lock held can't deadlock: a cpu with interrupts enabled takes the shootdown ipi,
+        // and every lock a cpu can spin on with interrupts disabled (IMutex, IrwLock, and the shootdown lock itself)
+        // handles pending shootdowns while spinning, including a cpu waiting for this address space's lock
+        self.0.addr_space.flush_tlb();
+    }
+}
+
 #[derive(Debug)]
 pub struct AddressSpaceInner {
     pub addr_space: VirtAddrSpace,
@@ -245,12 +455,63 @@ impl AddrSpaceMappings {
         )
     }
 
+    /// Gets the mapping which contains `address`, returns None if nothing is mapped at `address`
+    pub fn get_mapping_containing_address(&self, address: VirtAddr) -> Option<&AddrSpaceMapping> {
+        let index = self.mappings.partition_point(|mapping| mapping.map_range().addr() <= address);
+        let mapping = self.mappings.get(index.checked_sub(1)?)?;
+
+        if mapping.map_range().contains(address) {
+            Some(mapping)
+        } else {
+            None
+        }
+    }
+
     pub fn get_mapping_from_address_mut(&mut self, address: VirtAddr) -> Option<&mut AddrSpaceMapping> {
         self.mappings.get_mut(
             self.get_mapping_index(address)?
         )
     }
 
+    /// Returns all mappings which overlap `range`, in address order
+    pub fn mappings_overlapping(&self, range: AVirtRange) -> impl Iterator<Item = &AddrSpaceMapping> + '_ {
+        let start_index = self.mappings.partition_point(|mapping| mapping.map_range().end_addr() <= range.addr());
+
+        self.mappings[start_index..]
+            .iter()
+            .take_while(move |mapping| mapping.map_range().addr() < range.end_addr())
+    }
+
+    /// Returns all mappings at an address of at least `address`, in address order
+    pub fn mappings_from(&self, address: usize) -> &[AddrSpaceMapping] {
+        let start_index = self.mappings.partition_point(|mapping| mapping.map_range().addr().as_usize() < address);
+
+        &self.mappings[start_index..]
+    }
+
+    /// Changes where the memory mapping at `address` is located
+    /// 
+    /// The new location must be inside of the old one, so the mappings stay sorted
+    pub fn set_memory_location(&mut self, address: VirtAddr, location: MemoryMappingLocation) -> KResult<()> {
+        let index = self.get_mapping_index(address).ok_or(SysErr::InvlVirtAddr)?;
+
+        let AddrSpaceMapping::Memory(mapping) = &mut self.mappings[index] else {
+            return Err(SysErr::InvlOp);
+        };
+
+        assert!(
+            mapping.location.map_range().full_contains_range(&location.map_range()),
+            "new mapping location is not inside of old location",
+        );
+
+        if location.map_addr != address {
+            self.map_id_addrs.insert(mapping.mapping_id, location.map_addr)?;
+        }
+        mapping.location = location;
+
+        Ok(())
+    }
+
     fn get_mapping_from_id(&self, memory_id: MappingId) -> Option<&AddrSpaceMapping> {
         let mapping_addr = self.map_id_addrs.get(&memory_id)?;
 
@@ -1,6 +1,7 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use paste::paste;
+use spin::Once;
 use sys::CapType;
 
 use crate::event::{UserspaceBuffer, EventPool};
@@ -12,7 +13,9 @@ use crate::alloc::{CapAllocator, MmioAllocator, PhysMem};
 use crate::sync::IMutex;
 use crate::container::Arc;
 use super::address_space::AddressSpace;
+use super::debug::DebugCap;
 use super::drop_check::{DropCheck, DropCheckReciever};
+use super::io_port::{IoPortAccess, IoPort};
 use super::{CapId, Capability, StrongCapability, CapFlags, CapObject, key::Key, memory::Memory, channel::{Channel, Reply}};
 
 #[derive(Debug)]
@@ -26,7 +29,12 @@ type InnerCapMap<T> = IMutex<HashMap<CapId, CapabilityEntry<T>>>;
 /// A map that holds all the capabilities in a process
 #[derive(Debug)]
 pub struct CapabilitySpace {
+    /// Base id of the next inserted capability
+    /// 
+    /// This only ever increases, so ids of removed capabilities are never given out again
     next_id: AtomicUsize,
+    /// Id of the key registered as this process's identity, which channel senders can attach to their messages
+    identity_key_id: Once<u64>,
     thread_map: InnerCapMap<Thread>,
     thread_group_map: InnerCapMap<ThreadGroup>,
     address_space_map: InnerCapMap<AddressSpace>,
@@ -43,28 +51,36 @@ pub struct CapabilitySpace {
     phys_mem_map: InnerCapMap<PhysMem>,
     int_allocator_map: InnerCapMap<IntAllocator>,
     interrupt_map: InnerCapMap<Interrupt>,
+    debug_cap_map: InnerCapMap<DebugCap>,
+    io_port_access_map: InnerCapMap<IoPortAccess>,
+    io_port_map: InnerCapMap<IoPort>,
 }
 
 impl CapabilitySpace {
     pub fn new(allocator: HeapRef) -> Self {
         CapabilitySpace {
             next_id: AtomicUsize::new(0),
-            thread_map: IMutex::new(HashMap::new(allocator.clone())),
-            thread_group_map: IMutex::new(HashMap::new(allocator.clone())),
-            address_space_map: IMutex::new(HashMap::new(allocator.clone())),
-            capability_space_map: IMutex::new(HashMap::new(allocator.clone())),
-            memory_map: IMutex::new(HashMap::new(allocator.clone())),
-            event_pool_map: IMutex::new(HashMap::new(allocator.clone())),
-            key_map: IMutex::new(HashMap::new(allocator.clone())),
-            channel_map: IMutex::new(HashMap::new(allocator.clone())),
-            reply_map: IMutex::new(HashMap::new(allocator.clone())),
-            allocator_map: IMutex::new(HashMap::new(allocator.clone())),
-            drop_check_map: IMutex::new(HashMap::new(allocator.clone())),
-            drop_check_reciever_map: IMutex::new(HashMap::new(allocator.clone())),
-            mmio_allocator_map: IMutex::new(HashMap::new(allocator.clone())),
-            phys_mem_map: IMutex::new(HashMap::new(allocator.clone())),
-            int_allocator_map: IMutex::new(HashMap::new(allocator.clone())),
-            interrupt_map: IMutex::new(HashMap::new(allocator)),
+            identity_key_id: Once::new(),
+            // the maps share a lock class, they are never held in an order which matters for deadlocks
+            thread_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            thread_group_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            address_space_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            capability_space_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            memory_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            event_pool_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            key_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            channel_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            reply_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            allocator_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            drop_check_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            drop_check_reciever_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            mmio_allocator_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            phys_mem_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            int_allocator_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            interrupt_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            debug_cap_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            io_port_access_map: IMutex::named("capability_space_map", HashMap::new(allocator.clone())),
+            io_port_map: IMutex::named("capability_space_map", HashMap::new(allocator)),
         }
     }
 
@@ -72,6 +88,32 @@ impl CapabilitySpace {
     pub fn current() -> Arc<Self> {
         cpu_local_data().current_thread().capability_space().clone()
     }
+
+    /// Registers `key_id` as the identity of this process
+    ///
+    /// The identity can only be registered once, so a process can't change who it claims to be after sending messages.
+    ///
+    /// # Returns
+    ///
+    /// [`SysErr::InvlOp`] if an identity is already registered
+    pub fn register_identity(&self, key_id: u64) -> KResult<()> {
+        let mut registered = false;
+        self.identity_key_id.call_once(|| {
+            registered = true;
+            key_id
+        });
+
+        if registered {
+            Ok(())
+        } else {
+            Err(SysErr::InvlOp)
+        }
+    }
+
+    /// Returns the id of the identity key registered with [`register_identity`](Self::register_identity)
+    pub fn identity_key_id(&self) -> Option<u64> {
+        self.identity_key_id.get().copied()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -86,7 +128,10 @@ macro_rules! generate_cap_methods {
         paste! {
             impl $map {
                 pub fn [<insert_ $cap_name _inner>](&self, mut capability: Capability<$cap_type>, visible: bool) -> KResult<CapId> {
-                    let next_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                    // fail instead of wrapping around once all base ids are used, so old ids are never reused
+                    let next_id = self.next_id.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |id| {
+                        (id <= CapId::MAX_BASE_ID).then_some(id + 1)
+                    }).or(Err(SysErr::OutOfCapacity))?;
 
                     let cap_id = CapId::new(
                         $cap_type::TYPE,
@@ -112,6 +157,10 @@ macro_rules! generate_cap_methods {
                     self.[<insert_ $cap_name _inner>](capability, false)
                 }
 
+                pub fn [<reserve_ $cap_name>](&self, additional: usize) -> KResult<()> {
+                    self.$cap_map.lock().reserve(additional)
+                }
+
                 pub fn [<make_ $cap_name _visible>](&self, cap_id: CapId) -> KResult<()> {
                     let mut map = self.$cap_map.lock();
 
@@ -121,6 +170,15 @@ macro_rules! generate_cap_methods {
                     Ok(())
                 }
 
+                /// Returns the id of a visible capability which references `object`, if there is one
+                pub fn [<find_ $cap_name _id>](&self, object: &Arc<$cap_type>) -> Option<CapId> {
+                    let object_ptr = Arc::as_ptr(object);
+
+                    self.$cap_map.lock().iter()
+                        .find(|(_, entry)| entry.visible && entry.capability.object_ptr() == object_ptr)
+                        .map(|(cap_id, _)| *cap_id)
+                }
+
                 pub fn [<remove_ $cap_name>](&self, cap_id: CapId) -> KResult<Capability<$cap_type>> {
                     Ok(self.$cap_map.lock().remove(&cap_id)
                         .ok_or(SysErr::InvlId)?
@@ -172,6 +230,8 @@ macro_rules! generate_cap_methods {
                 }
 
                 /// Used by cap_clone syscall
+                /// 
+                /// Making a strong capability from a weak one requires the weak capability to have the upgrade permission
                 // TODO: don't have so many arguments
                 pub fn [<clone_ $cap_name>](
                     dst: &Self,
@@ -203,6 +263,10 @@ macro_rules! generate_cap_methods {
                             }
                         },
                         Capability::Weak(mut capability) => {
+                            if make_strong_cap && !capability.flags().contains(CapFlags::UPGRADE) {
+                                return Err(SysErr::InvlPerm);
+                            }
+
                             capability.id = new_flags_capid;
 
                             if make_strong_cap {
@@ -252,9 +316,97 @@ generate_cap_methods!(CapabilitySpace, MmioAllocator, mmio_allocator_map, mmio_a
 generate_cap_methods!(CapabilitySpace, PhysMem, phys_mem_map, phys_mem);
 generate_cap_methods!(CapabilitySpace, IntAllocator, int_allocator_map, int_allocator);
 generate_cap_methods!(CapabilitySpace, Interrupt, interrupt_map, interrupt);
+generate_cap_methods!(CapabilitySpace, DebugCap, debug_cap_map, debug_cap);
+generate_cap_methods!(CapabilitySpace, IoPortAccess, io_port_access_map, io_port_access);
+generate_cap_methods!(CapabilitySpace, IoPort, io_port_map, io_port);
 
 impl CapabilitySpace {
+    /// Fills `out` with the ids of visible capabilities that have a base id of at least `cursor`, in order of increasing base id
+    /// 
+    /// Each capability map is locked one at a time, so capabilities inserted or removed
+    /// while listing may or may not be included, but ids are never repeated within 1 call
+    /// 
+    /// # Returns
+    /// 
+    /// The number of ids written to `out`, and the cursor to pass in to list the capabilities after the last written id
+    pub fn list_capabilities(&self, cursor: usize, out: &mut [CapId]) -> (usize, usize) {
+        let mut count = 0;
+
+        macro_rules! list_maps {
+            ($($cap_map:ident),*) => {
+                $(
+                    for (cap_id, entry) in self.$cap_map.lock().iter() {
+                        if entry.visible && cap_id.base_id() >= cursor {
+                            insert_sorted_cap_id(out, &mut count, *cap_id);
+                        }
+                    }
+                )*
+            };
+        }
+
+        list_maps!(
+            thread_map,
+            thread_group_map,
+            address_space_map,
+            capability_space_map,
+            memory_map,
+            event_pool_map,
+            key_map,
+            channel_map,
+            reply_map,
+            allocator_map,
+            drop_check_map,
+            drop_check_reciever_map,
+            mmio_allocator_map,
+            phys_mem_map,
+            int_allocator_map,
+            interrupt_map,
+            debug_cap_map,
+            io_port_access_map,
+            io_port_map
+        );
+
+        let next_cursor = if count == 0 {
+            cursor
+        } else {
+            out[count - 1].base_id() + 1
+        };
+
+        (count, next_cursor)
+    }
+
+    /// Reserves space for at least `additional` more capabilities of type `cap_type`
+    /// 
+    /// This should be called before inserting many capabilities at once,
+    /// so the capability map does not have to keep growing while they are inserted
+    pub fn reserve(&self, cap_type: CapType, additional: usize) -> KResult<()> {
+        match cap_type {
+            CapType::Thread => self.reserve_thread(additional),
+            CapType::ThreadGroup => self.reserve_thread_group(additional),
+            CapType::AddressSpace => self.reserve_address_space(additional),
+            CapType::CapabilitySpace => self.reserve_capability_space(additional),
+            CapType::Memory => self.reserve_memory(additional),
+            CapType::EventPool => self.reserve_event_pool(additional),
+            CapType::Channel => self.reserve_channel(additional),
+            CapType::Reply => self.reserve_reply(additional),
+            CapType::Key => self.reserve_key(additional),
+            CapType::Allocator => self.reserve_allocator(additional),
+            CapType::DropCheck => self.reserve_drop_check(additional),
+            CapType::DropCheckReciever => self.reserve_drop_check_reciever(additional),
+            CapType::MmioAllocator => self.reserve_mmio_allocator(additional),
+            CapType::PhysMem => self.reserve_phys_mem(additional),
+            CapType::IntAllocator => self.reserve_int_allocator(additional),
+            CapType::Interrupt => self.reserve_interrupt(additional),
+            CapType::DebugCap => self.reserve_debug_cap(additional),
+            CapType::IoPortAccess => self.reserve_io_port_access(additional),
+            CapType::IoPort => self.reserve_io_port(additional),
+            _ => Err(SysErr::InvlArgs),
+        }
+    }
+
     /// Gets a userspace buffer from the given memory id and size and offset
+    /// 
+    /// Fails with [`SysErr::InvlBuffer`] if the buffer does not fit inside of the memory
     pub fn get_userspace_buffer(
         &self,
         memory_id: usize,
@@ -266,7 +418,10 @@ impl CapabilitySpace {
         let memory = self.get_memory_with_perms(memory_id, required_perms, weak_auto_destroy)?
             .into_inner();
 
-        Ok(UserspaceBuffer::new(memory, buffer_offset, buffer_size))
+        let buffer = UserspaceBuffer::new(memory, buffer_offset, buffer_size);
+        buffer.validate()?;
+
+        Ok(buffer)
     }
 
     pub fn cap_clone(
@@ -312,11 +467,123 @@ impl CapabilitySpace {
             CapType::PhysMem => call_cap_clone!(clone_phys_mem),
             CapType::IntAllocator => call_cap_clone!(clone_int_allocator),
             CapType::Interrupt => call_cap_clone!(clone_interrupt),
+            CapType::DebugCap => call_cap_clone!(clone_debug_cap),
+            CapType::IoPortAccess => call_cap_clone!(clone_io_port_access),
+            CapType::IoPort => call_cap_clone!(clone_io_port),
             _ => todo!(),
         }
     }
 }
 
+/// Inserts `cap_id` into the first `count` ids of `ids`, keeping them sorted by base id
+/// 
+/// If `ids` is full, the id with the largest base id is dropped
+fn insert_sorted_cap_id(ids: &mut [CapId], count: &mut usize, cap_id: CapId) {
+    let index = ids[..*count].partition_point(|id| id.base_id() < cap_id.base_id());
+    if index == ids.len() {
+        return;
+    }
+
+    if *count < ids.len() {
+        *count += 1;
+    }
+
+    ids[index..*count].rotate_right(1);
+    ids[index] = cap_id;
+}
+
 impl CapObject for CapabilitySpace {
     const TYPE: CapType = CapType::CapabilitySpace;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::{root_alloc_page_ref, root_alloc_ref};
+    use crate::cap::memory::PageSource;
+
+    fn new_memory_capability() -> Capability<Memory> {
+        let memory = Memory::new_with_page_source(
+            root_alloc_page_ref(),
+            root_alloc_ref(),
+            1,
+            PageSource::LazyZeroAlloc,
+        ).unwrap();
+
+        Capability::Strong(StrongCapability::new_flags(
+            Arc::new(memory, root_alloc_ref()).unwrap(),
+            CapFlags::all(),
+        ))
+    }
+
+    #[test_case]
+    fn stale_id_does_not_alias_new_capability() {
+        let cspace = CapabilitySpace::new(root_alloc_ref());
+
+        let old_id = cspace.insert_memory(new_memory_capability()).unwrap();
+        cspace.remove_memory(old_id).unwrap();
+
+        let new_id = cspace.insert_memory(new_memory_capability()).unwrap();
+        assert_ne!(old_id, new_id);
+        assert!(new_id.base_id() > old_id.base_id());
+
+        assert_eq!(cspace.get_memory(old_id).err(), Some(SysErr::InvlId));
+        assert_eq!(
+            cspace.get_memory_with_perms(old_id.into(), CapFlags::READ, false).err(),
+            Some(SysErr::InvlId),
+        );
+        assert!(cspace.get_memory_with_perms(new_id.into(), CapFlags::READ, false).is_ok());
+    }
+
+    #[test_case]
+    fn base_ids_do_not_wrap() {
+        let cspace = CapabilitySpace::new(root_alloc_ref());
+        cspace.next_id.store(CapId::MAX_BASE_ID, Ordering::Relaxed);
+
+        let last_id = cspace.insert_memory(new_memory_capability()).unwrap();
+        assert_eq!(last_id.base_id(), CapId::MAX_BASE_ID);
+
+        assert_eq!(cspace.insert_memory(new_memory_capability()).err(), Some(SysErr::OutOfCapacity));
+    }
+
+    #[test_case]
+    fn weak_capability_upgrade() {
+        let cspace = CapabilitySpace::new(root_alloc_ref());
+        let strong_id = cspace.insert_memory(new_memory_capability()).unwrap();
+
+        let clone = |cap_id, perms, weakness| {
+            CapabilitySpace::clone_memory(&cspace, &cspace, cap_id, perms, weakness, false, false)
+        };
+
+        let weak_id = clone(strong_id, CapFlags::all(), CapCloneWeakness::MakeWeak).unwrap();
+        let no_upgrade_id = clone(strong_id, CapFlags::READ | CapFlags::WRITE, CapCloneWeakness::MakeWeak).unwrap();
+        assert!(weak_id.is_weak());
+
+        assert_eq!(clone(no_upgrade_id, CapFlags::all(), CapCloneWeakness::MakeStrong).err(), Some(SysErr::InvlPerm));
+
+        let upgraded_id = clone(weak_id, CapFlags::all(), CapCloneWeakness::MakeStrong).unwrap();
+        assert!(!upgraded_id.is_weak());
+        cspace.remove_memory(upgraded_id).unwrap();
+
+        cspace.remove_memory(strong_id).unwrap();
+        assert_eq!(clone(weak_id, CapFlags::all(), CapCloneWeakness::MakeStrong).err(), Some(SysErr::InvlWeak));
+    }
+
+    #[test_case]
+    fn userspace_buffer_must_fit_in_memory() {
+        let cspace = CapabilitySpace::new(root_alloc_ref());
+        let memory_id: usize = cspace.insert_memory(new_memory_capability()).unwrap().into();
+
+        assert!(cspace.get_userspace_buffer(memory_id, 0, PAGE_SIZE, CapFlags::WRITE, false).is_ok());
+        assert!(cspace.get_userspace_buffer(memory_id, PAGE_SIZE - 8, 8, CapFlags::WRITE, false).is_ok());
+
+        assert_eq!(
+            cspace.get_userspace_buffer(memory_id, PAGE_SIZE - 8, 9, CapFlags::WRITE, false).err(),
+            Some(SysErr::InvlBuffer),
+        );
+        assert_eq!(
+            cspace.get_userspace_buffer(memory_id, 8, usize::MAX, CapFlags::WRITE, false).err(),
+            Some(SysErr::InvlBuffer),
+        );
+    }
 }
\ No newline at end of file
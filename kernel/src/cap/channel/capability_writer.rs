@@ -71,6 +71,14 @@ impl<T: MemoryWriter> MemoryWriter for CapabilityWriter<'_, T> {
                 let cap_id = CapId::try_from(cap_id)
                     .ok_or(SysErr::InvlId)?;
 
+                // capabilities sent together are usually the same type, so reserve space for the rest of the ids in this region
+                // ignore errors, the insert will report out of memory if it actually fails
+                let remaining_region_ids = region.size() / size_of::<usize>();
+                let _ = self.cap_transfer_info.dst_cspace.reserve(
+                    cap_id.cap_type(),
+                    1 + core::cmp::min(copy_count.remaining_cap_count, remaining_region_ids),
+                );
+
                 CapabilitySpace::cap_clone(
                     self.cap_transfer_info.dst_cspace,
                     self.cap_transfer_info.src_cspace,
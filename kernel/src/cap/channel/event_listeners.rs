@@ -27,6 +27,8 @@ pub enum ChannelSenderInner {
     CallEventPool {
         event_pool: Weak<EventPool>,
         event_id: EventId,
+        /// Buffer the reply is copied into if it fits, instead of the event pool
+        response_buffer: Option<WeakUserspaceBuffer>,
     }
 }
 
@@ -35,21 +37,24 @@ pub enum ChannelSenderInner {
 pub struct ChannelSenderRef {
     pub cspace: Weak<CapabilitySpace>,
     pub send_buffer: WeakUserspaceBuffer,
+    /// The sender's identity key id is attached to the message
+    pub send_identity: bool,
     pub inner: ChannelSenderInner,
 }
 
 impl ChannelSenderRef {
-    pub fn current_thread(buffer: &UserspaceBuffer, cspace: &Arc<CapabilitySpace>) -> Self {
+    pub fn current_thread(buffer: &UserspaceBuffer, cspace: &Arc<CapabilitySpace>, send_identity: bool) -> Self {
         ChannelSenderRef {
             cspace: Arc::downgrade(cspace),
             send_buffer: buffer.downgrade(),
+            send_identity,
             inner: ChannelSenderInner::Thread {
                 thread: None,
             },
         }
     }
 
-    pub fn event_pool(listener: EventPoolListenerRef, send_buffer: &UserspaceBuffer, cspace: &Arc<CapabilitySpace>) -> Self {
+    pub fn event_pool(listener: EventPoolListenerRef, send_buffer: &UserspaceBuffer, cspace: &Arc<CapabilitySpace>, send_identity: bool) -> Self {
         let EventPoolListenerRef {
             event_pool,
             event_id,
@@ -58,6 +63,7 @@ impl ChannelSenderRef {
         ChannelSenderRef {
             cspace: Arc::downgrade(cspace),
             send_buffer: send_buffer.downgrade(),
+            send_identity,
             inner: ChannelSenderInner::EventPool {
                 event_pool,
                 event_id,
@@ -90,12 +96,10 @@ impl ChannelSenderRef {
                     recieved_size: write_size,
                 });
 
-                let event = Event {
+                event_pool.write_event(Event {
                     event_data,
                     event_id: *event_id,
-                }.as_raw();
-
-                event_pool.write_event(event.as_bytes())?;
+                })?;
             },
             _ => (),
         }
@@ -103,6 +107,20 @@ impl ChannelSenderRef {
         Ok(())
     }
 
+    /// Returns the thread blocked waiting for this send to finish, if there is one
+    pub fn waiting_thread(&self) -> Option<&ThreadRef> {
+        match &self.inner {
+            ChannelSenderInner::Thread { thread } => thread.as_ref(),
+            ChannelSenderInner::CallThread { thread, .. } => thread.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the send finishes with an event, rather than by waking a blocked thread
+    pub fn is_async(&self) -> bool {
+        matches!(self.inner, ChannelSenderInner::EventPool { .. } | ChannelSenderInner::CallEventPool { .. })
+    }
+
     /// Gets the buffer that holds the data for the event to be sent, or None if the buffer has been dropped
     pub fn send_buffer(&self) -> Option<UserspaceBuffer> {
         self.send_buffer.upgrade()
@@ -113,6 +131,8 @@ impl ChannelSenderRef {
     }
 
     pub fn get_reply(&self, future_ref: Option<ThreadRef>) -> Option<Reply> {
+        let mut response_buffer = None;
+
         let reciever = match &self.inner {
             ChannelSenderInner::CallThread {
                 thread,
@@ -131,16 +151,21 @@ impl ChannelSenderRef {
             ChannelSenderInner::CallEventPool {
                 event_pool,
                 event_id,
-            } => ChannelRecieverRef::EventPool {
-                event_pool: event_pool.clone(),
-                event_id: *event_id,
-                cspace: self.cspace.clone(),
-                auto_reque: false,
+                response_buffer: call_response_buffer,
+            } => {
+                response_buffer = call_response_buffer.clone();
+
+                ChannelRecieverRef::EventPool {
+                    event_pool: event_pool.clone(),
+                    event_id: *event_id,
+                    cspace: self.cspace.clone(),
+                    auto_reque: false,
+                }
             },
             _ => return None,
         };
 
-        let reply = Reply::new(reciever);
+        let reply = Reply::new(reciever, response_buffer);
 
         Some(reply)
     }
@@ -193,6 +218,14 @@ impl ChannelRecieverRef {
         *old_thread = Some(new_thread_ref);
     }
 
+    /// Returns the thread blocked waiting to recieve a message, if there is one
+    pub fn waiting_thread(&self) -> Option<&ThreadRef> {
+        match self {
+            Self::Thread { thread, .. } => thread.as_ref(),
+            Self::EventPool { .. } => None,
+        }
+    }
+
     pub fn is_auto_reque(&self) -> bool {
         match self {
             Self::Thread { .. } => false,
@@ -3,14 +3,15 @@ use core::ops::FromResidual;
 
 use bit_utils::MemOwner;
 use bit_utils::container::{LinkedList, DefaultNode};
-use sys::{CapType, CapId, CapFlags};
+use sys::{CapType, CapId, CapFlags, EventData, ChannelSpace, DEFAULT_CHANNEL_SENDER_LIMIT};
 
 use crate::alloc::HeapRef;
+use crate::config::CHANNEL_SYNC_SENDER_LIMIT;
 use crate::event::{UserspaceBuffer, EventPoolListenerRef};
 use crate::prelude::*;
 use crate::mem::MemOwnerKernelExt;
-use crate::sched::{ThreadRef, WakeReason, thread_map};
-use crate::container::Arc;
+use crate::sched::{ThreadGroup, ThreadRef, WakeReason, thread_map};
+use crate::container::{Arc, Weak};
 use crate::sync::{IMutex, IMutexGuard};
 
 use super::{CapObject, StrongCapability, Capability};
@@ -20,6 +21,8 @@ mod capability_writer;
 pub use capability_writer::{CapabilityWriter, CapabilityTransferInfo};
 mod event_listeners;
 use event_listeners::{ChannelSenderRef, ChannelSenderInner, ChannelRecieverRef};
+mod node_pool;
+use node_pool::NodePool;
 mod reply;
 pub use reply::Reply;
 
@@ -28,6 +31,8 @@ pub use reply::Reply;
 pub struct RecieveResult {
     pub recieve_size: Size,
     pub reply_cap_id: Option<CapId>,
+    /// Id of the sender's identity key, if it asked for its identity to be sent
+    pub sender_key_id: Option<u64>,
 }
 
 /// Returns result of synchronous channel functions to indicate to calling thread, success, failure or if it should block
@@ -57,8 +62,25 @@ pub struct Channel {
 
 impl Channel {
     pub fn new(allocator: HeapRef) -> Self {
+        Self::with_sender_limit(allocator, DEFAULT_CHANNEL_SENDER_LIMIT)
+    }
+
+    /// Creates a channel where at most `async_sender_limit` async senders can be queued waiting for a reciever
+    /// 
+    /// Threads blocked sending on the channel are limited separately by [`CHANNEL_SYNC_SENDER_LIMIT`]
+    pub fn with_sender_limit(allocator: HeapRef, async_sender_limit: usize) -> Self {
         Channel {
-            inner: IMutex::default(),
+            inner: IMutex::named("channel", ChannelInner {
+                sender_queue: Default::default(),
+                reciever_queue: Default::default(),
+                sender_pool: Default::default(),
+                reciever_pool: Default::default(),
+                async_sender_count: 0,
+                async_sender_limit,
+                sync_sender_count: 0,
+                space_listeners: Vec::new(allocator.clone()),
+                sender_groups: Vec::new(allocator.clone()),
+            }),
             allocator,
         }
     }
@@ -88,25 +110,24 @@ impl Channel {
     /// 
     /// Ok(number of bytes written) on success,
     /// Err if there was a nobody waiting to recieve the message
-    pub fn try_send(&self, buffer: &UserspaceBuffer, src_cspace: &Arc<CapabilitySpace>) -> KResult<Size> {
-        let sender = ChannelSenderRef::current_thread(buffer, src_cspace);
+    pub fn try_send(this: &Arc<Self>, buffer: &UserspaceBuffer, src_cspace: &Arc<CapabilitySpace>, send_identity: bool) -> KResult<Size> {
+        let sender = ChannelSenderRef::current_thread(buffer, src_cspace, send_identity);
 
-        let mut inner = self.inner();
+        let mut inner = this.inner();
+        Self::register_current_sender(this, &mut inner)?;
 
         loop {
             let reciever = inner.reciever_queue.pop_front()
                 .ok_or(SysErr::OkUnreach)?;
-            let reciever = unsafe { reciever.as_box(self.allocator.clone()) };
 
-            let Ok(recieve_result) = self.do_send(&sender, &reciever.data, None) else {
+            let send_result = this.do_send(&sender, &reciever.data.listener, None);
+            inner.finish_reciever(reciever, send_result.is_ok(), &mut this.allocator.clone());
+
+            let Ok(recieve_result) = send_result else {
                 // this listener is no longer valid, retry on next listner
                 continue;
             };
 
-            if reciever.data.is_auto_reque() {
-                inner.reciever_queue.push(Box::into_mem_owner(reciever));
-            }
-
             return Ok(recieve_result.recieve_size);
         }
     }
@@ -123,11 +144,14 @@ impl Channel {
         let mut inner = self.inner();
 
         loop {
-            let sender = inner.sender_queue.pop_front()
+            let sender = inner.pop_sender()
                 .ok_or(SysErr::OkUnreach)?;
-            let sender = unsafe { sender.as_box(self.allocator.clone()) };
+            let send_result = self.do_send(&sender.data.listener, &reciever, None);
+            unsafe {
+                inner.sender_pool.free(sender, &mut self.allocator.clone());
+            }
 
-            let Ok(recieve_result) = self.do_send(&sender.data, &reciever, None) else {
+            let Ok(recieve_result) = send_result else {
                 continue;
             };
 
@@ -141,33 +165,30 @@ impl Channel {
     /// 
     /// # Returns
     /// 
-    /// See [`ChannelSyncResult`]
-    pub fn sync_send(&self, buffer: &UserspaceBuffer, src_cspace: &Arc<CapabilitySpace>) -> ChannelSyncResult<Size> {
-        let mut sender = ChannelSenderRef::current_thread(buffer, src_cspace);
+    /// See [`ChannelSyncResult`], the error is [`SysErr::ChannelFull`] if too many threads are already blocked sending
+    pub fn sync_send(this: &Arc<Self>, buffer: &UserspaceBuffer, src_cspace: &Arc<CapabilitySpace>, send_identity: bool) -> ChannelSyncResult<Size> {
+        let mut sender = ChannelSenderRef::current_thread(buffer, src_cspace, send_identity);
         let current_thread = ThreadRef::future_ref(&cpu_local_data().current_thread());
 
-        let mut inner = self.inner();
+        let mut inner = this.inner();
+        Self::register_current_sender(this, &mut inner)?;
 
         loop {
             let Some(reciever) = inner.reciever_queue.pop_front() else {
                 // no recievers present, insert ourselves in the senders list
                 sender.set_thread(current_thread);
-
-                let sender = MemOwner::new(sender.into(), &mut self.allocator.clone())?;
-                inner.sender_queue.push(sender);
+                Self::queue_sender(this, &mut inner, sender)?;
 
                 return ChannelSyncResult::Block;
             };
-            let reciever = unsafe { reciever.as_box(self.allocator.clone()) };
 
-            let Ok(recieve_result) = self.do_send(&sender, &reciever.data, None) else {
+            let send_result = this.do_send(&sender, &reciever.data.listener, None);
+            inner.finish_reciever(reciever, send_result.is_ok(), &mut this.allocator.clone());
+
+            let Ok(recieve_result) = send_result else {
                 continue;
             };
 
-            if reciever.data.is_auto_reque() {
-                inner.reciever_queue.push(Box::into_mem_owner(reciever));
-            }
-
             return ChannelSyncResult::Success(recieve_result.recieve_size);
         }
     }
@@ -179,25 +200,26 @@ impl Channel {
     /// # Returns
     /// 
     /// See [`ChannelSyncResult`]
-    pub fn sync_recv(&self, buffer: &UserspaceBuffer, dst_cspace: &Arc<CapabilitySpace>) -> ChannelSyncResult<RecieveResult> {
+    pub fn sync_recv(this: &Arc<Self>, buffer: &UserspaceBuffer, dst_cspace: &Arc<CapabilitySpace>) -> ChannelSyncResult<RecieveResult> {
         let mut reciever = ChannelRecieverRef::current_thread(buffer, dst_cspace);
         let current_thread = ThreadRef::future_ref(&cpu_local_data().current_thread());
 
-        let mut inner = self.inner();
+        let mut inner = this.inner();
 
         loop {
-            let Some(sender) = inner.sender_queue.pop_front() else {
+            let Some(sender) = inner.pop_sender() else {
                 // no senders present, insert our selves in the recievers list
                 reciever.set_thread(current_thread);
-
-                let reciever = MemOwner::new(reciever.into(), &mut self.allocator.clone())?;
-                inner.reciever_queue.push(reciever);
+                Self::queue_reciever(this, &mut inner, reciever)?;
 
                 return ChannelSyncResult::Block;
             };
-            let sender = unsafe { sender.as_box(self.allocator.clone()) };
+            let send_result = this.do_send(&sender.data.listener, &reciever, None);
+            unsafe {
+                inner.sender_pool.free(sender, &mut this.allocator.clone());
+            }
 
-            let Ok(recieve_result) = self.do_send(&sender.data, &reciever, None) else {
+            let Ok(recieve_result) = send_result else {
                 continue;
             };
 
@@ -205,56 +227,61 @@ impl Channel {
         }
     }
 
-    pub fn async_send(&self, listener: EventPoolListenerRef, send_buffer: &UserspaceBuffer, src_cspace: &Arc<CapabilitySpace>) -> KResult<()> {
-        let sender = ChannelSenderRef::event_pool(listener, send_buffer, src_cspace);
-
-        let mut inner = self.inner();
+    /// Sends a message, or queues it until a reciever is present
+    /// 
+    /// Returns [`SysErr::ChannelFull`] if the async sender queue is full, [`Channel::async_wait_space`]
+    /// can be used to find out when to try again
+    pub fn async_send(
+        this: &Arc<Self>,
+        listener: EventPoolListenerRef,
+        send_buffer: &UserspaceBuffer,
+        src_cspace: &Arc<CapabilitySpace>,
+        send_identity: bool,
+    ) -> KResult<()> {
+        let sender = ChannelSenderRef::event_pool(listener, send_buffer, src_cspace, send_identity);
+
+        let mut inner = this.inner();
+        Self::register_current_sender(this, &mut inner)?;
 
         loop {
             let Some(reciever) = inner.reciever_queue.pop_front() else {
-                let sender = MemOwner::new(sender.into(), &mut self.allocator.clone())?;
-                inner.sender_queue.push(sender);
-
-                return Ok(());
+                return Self::queue_sender(this, &mut inner, sender);
             };
-            let reciever = unsafe { reciever.as_box(self.allocator.clone()) };
 
-            let Ok(_) = self.do_send(&sender, &reciever.data, None) else {
+            let send_result = this.do_send(&sender, &reciever.data.listener, None);
+            inner.finish_reciever(reciever, send_result.is_ok(), &mut this.allocator.clone());
+
+            let Ok(_) = send_result else {
                 continue;
             };
 
-            if reciever.data.is_auto_reque() {
-                inner.reciever_queue.push(Box::into_mem_owner(reciever));
-            }
-
             return Ok(());
         }
     }
 
-    pub fn async_recv(&self, listener: EventPoolListenerRef, auto_reque: bool, dst_cspace: &Arc<CapabilitySpace>) -> KResult<()> {
+    pub fn async_recv(this: &Arc<Self>, listener: EventPoolListenerRef, auto_reque: bool, dst_cspace: &Arc<CapabilitySpace>) -> KResult<()> {
         let reciever = ChannelRecieverRef::event_pool(listener, auto_reque, dst_cspace);
 
-        let mut inner = self.inner();
+        let mut inner = this.inner();
 
         loop {
-            let Some(sender) = inner.sender_queue.pop_front() else {
+            let Some(sender) = inner.pop_sender() else {
                 // no senders present, insert ourselves in reciever queue
-                let reciever = MemOwner::new(reciever.into(), &mut self.allocator.clone())?;
-                inner.reciever_queue.push(reciever);
-
-                return Ok(());
+                return Self::queue_reciever(this, &mut inner, reciever);
             };
-            let sender = unsafe { sender.as_box(self.allocator.clone()) };
+            let send_result = this.do_send(&sender.data.listener, &reciever, None);
+            unsafe {
+                inner.sender_pool.free(sender, &mut this.allocator.clone());
+            }
 
-            let Ok(_) = self.do_send(&sender.data, &reciever, None) else {
+            let Ok(_) = send_result else {
                 continue;
             };
 
             // NOTE: this could report failure when trying to listen for a message,
             // but the message may still have been successfully sent
             if reciever.is_auto_reque() {
-                let reciever = MemOwner::new(reciever.into(), &mut self.allocator.clone())?;
-                inner.reciever_queue.push(reciever);
+                Self::queue_reciever(this, &mut inner, reciever)?;
             }
 
             return Ok(());
@@ -262,10 +289,17 @@ impl Channel {
     }
 
     /// It is always required to block after calling this
-    pub fn sync_call(&self, send_buffer: &UserspaceBuffer, recv_buffer: &UserspaceBuffer, cspace: &Arc<CapabilitySpace>) -> KResult<()> {
+    pub fn sync_call(
+        this: &Arc<Self>,
+        send_buffer: &UserspaceBuffer,
+        recv_buffer: &UserspaceBuffer,
+        cspace: &Arc<CapabilitySpace>,
+        send_identity: bool,
+    ) -> KResult<()> {
         let mut sender = ChannelSenderRef {
             cspace: Arc::downgrade(cspace),
             send_buffer: send_buffer.downgrade(),
+            send_identity,
             inner: ChannelSenderInner::CallThread {
                 thread: None,
                 recv_buffer: recv_buffer.downgrade(),
@@ -273,32 +307,36 @@ impl Channel {
         };
         let current_thread = ThreadRef::future_ref(&cpu_local_data().current_thread());
 
-        let mut inner = self.inner();
+        let mut inner = this.inner();
+        Self::register_current_sender(this, &mut inner)?;
 
         loop {
             let Some(reciever) = inner.reciever_queue.pop_front() else {
                 sender.set_thread(current_thread);
 
-                let sender = MemOwner::new(sender.into(), &mut self.allocator.clone())?;
-                inner.sender_queue.push(sender);
-
-                return Ok(());
+                return Self::queue_sender(this, &mut inner, sender);
             };
-            let reciever = unsafe { reciever.as_box(self.allocator.clone()) };
 
-            let Ok(_) = self.do_send(&sender, &reciever.data, Some(current_thread.clone())) else {
+            let send_result = this.do_send(&sender, &reciever.data.listener, Some(current_thread.clone()));
+            inner.finish_reciever(reciever, send_result.is_ok(), &mut this.allocator.clone());
+
+            let Ok(_) = send_result else {
                 continue;
             };
 
-            if reciever.data.is_auto_reque() {
-                inner.reciever_queue.push(Box::into_mem_owner(reciever));
-            }
-
             return Ok(());
         }
     }
 
-    pub fn async_call(&self, listener: EventPoolListenerRef, send_buffer: &UserspaceBuffer, cspace: &Arc<CapabilitySpace>) -> KResult<()> {
+    /// If `response_buffer` is given, the reply is copied into it instead of the event pool when it fits
+    pub fn async_call(
+        this: &Arc<Self>,
+        listener: EventPoolListenerRef,
+        send_buffer: &UserspaceBuffer,
+        response_buffer: Option<&UserspaceBuffer>,
+        cspace: &Arc<CapabilitySpace>,
+        send_identity: bool,
+    ) -> KResult<()> {
         let EventPoolListenerRef {
             event_pool,
             event_id,
@@ -307,33 +345,174 @@ impl Channel {
         let sender = ChannelSenderRef {
             cspace: Arc::downgrade(cspace),
             send_buffer: send_buffer.downgrade(),
+            send_identity,
             inner: ChannelSenderInner::CallEventPool {
                 event_pool,
                 event_id,
+                response_buffer: response_buffer.map(UserspaceBuffer::downgrade),
             },
         };
 
-        let mut inner = self.inner();
+        let mut inner = this.inner();
+        Self::register_current_sender(this, &mut inner)?;
 
         loop {
             let Some(reciever) = inner.reciever_queue.pop_front() else {
-                let sender = MemOwner::new(sender.into(), &mut self.allocator.clone())?;
-                inner.sender_queue.push(sender);
-
-                return Ok(());
+                return Self::queue_sender(this, &mut inner, sender);
             };
-            let reciever = unsafe { reciever.as_box(self.allocator.clone()) };
 
-            let Ok(_) = self.do_send(&sender, &reciever.data, None) else {
+            let send_result = this.do_send(&sender, &reciever.data.listener, None);
+            inner.finish_reciever(reciever, send_result.is_ok(), &mut this.allocator.clone());
+
+            let Ok(_) = send_result else {
                 continue;
             };
 
-            if reciever.data.is_auto_reque() {
-                inner.reciever_queue.push(Box::into_mem_owner(reciever));
-            }
+            return Ok(());
+        }
+    }
+
+    /// Sends a [`ChannelSpace`] event to `listener` once there is space in the async sender queue
+    /// 
+    /// If the queue is not full the event is sent right away, otherwise it is sent once the queue drains to half its limit.
+    /// 
+    /// # Returns
+    /// 
+    /// [`SysErr::OutOfCapacity`] if as many listeners as the async sender limit are already waiting
+    pub fn async_wait_space(&self, listener: EventPoolListenerRef) -> KResult<()> {
+        let mut inner = self.inner();
 
+        if inner.async_sender_count < inner.async_sender_limit {
+            listener.write_event(inner.space_event())?;
             return Ok(());
         }
+
+        // otherwise senders which keep getting a full queue could grow the channel without limit by waiting
+        if inner.space_listeners.len() >= inner.async_sender_limit {
+            return Err(SysErr::OutOfCapacity);
+        }
+
+        inner.space_listeners.push(listener)
+    }
+
+    /// Queues `sender` on behalf of the current thread's thread group, the channel must be locked as `inner`
+    /// 
+    /// Returns [`SysErr::ChannelFull`] if the queue already holds as many senders of the same kind as it can
+    fn queue_sender(this: &Arc<Self>, inner: &mut ChannelInner, sender: ChannelSenderRef) -> KResult<()> {
+        if inner.is_sender_queue_full(sender.is_async()) {
+            return Err(SysErr::ChannelFull);
+        }
+
+        let owner = register_current_waiter(this)?;
+
+        let sender = inner.sender_pool.alloc(
+            Waiter { listener: sender, owner }.into(),
+            &mut this.allocator.clone(),
+        )?;
+        inner.push_sender(sender);
+
+        Ok(())
+    }
+
+    /// Queues `reciever` on behalf of the current thread's thread group, the channel must be locked as `inner`
+    fn queue_reciever(this: &Arc<Self>, inner: &mut ChannelInner, reciever: ChannelRecieverRef) -> KResult<()> {
+        let owner = register_current_waiter(this)?;
+
+        let reciever = inner.reciever_pool.alloc(
+            Waiter { listener: reciever, owner }.into(),
+            &mut this.allocator.clone(),
+        )?;
+        inner.reciever_queue.push(reciever);
+
+        Ok(())
+    }
+
+    /// Records the current thread's thread group as a sender on this channel, the channel must be locked as `inner`
+    /// 
+    /// Threads blocked recieving are woken with [`WakeReason::PeerGone`] once every sender group has exited
+    fn register_current_sender(this: &Arc<Self>, inner: &mut ChannelInner) -> KResult<()> {
+        let thread_group = register_current_waiter(this)?;
+        let thread_group_ptr = thread_group.as_ptr();
+
+        if inner.sender_groups.iter().any(|sender_group| sender_group.as_ptr() == thread_group_ptr) {
+            return Ok(());
+        }
+
+        match inner.sender_groups.iter_mut().find(|sender_group| sender_group.strong_count() == 0) {
+            Some(dead_group) => *dead_group = thread_group,
+            None => inner.sender_groups.push(thread_group)?,
+        }
+
+        Ok(())
+    }
+
+    /// Removes every sender and reciever queued by threads in `thread_group`, and forgets it as a sender
+    /// 
+    /// This is called when `thread_group` exits, so other threads don't have to find out later
+    /// that its waiters are no longer valid. If it was the last group which sent on this channel,
+    /// threads blocked recieving are woken with [`WakeReason::PeerGone`], since nothing is left to send to them.
+    pub fn remove_waiters_from(&self, thread_group: &ThreadGroup) {
+        let thread_group_ptr = thread_group as *const ThreadGroup;
+
+        let mut inner = self.inner();
+        let inner = &mut *inner;
+        let mut allocator = self.allocator.clone();
+
+        let mut removed_async_senders = 0;
+        let mut removed_sync_senders = 0;
+
+        let mut cursor = inner.sender_queue.cursor_start_mut();
+        while let Some(sender) = cursor.next() {
+            if sender.data.owner.as_ptr() == thread_group_ptr {
+                // panic safety: next was just checked to exist
+                let sender = cursor.remove_next().unwrap();
+                if sender.data.listener.is_async() {
+                    removed_async_senders += 1;
+                } else {
+                    removed_sync_senders += 1;
+                }
+
+                // safety: all sender nodes are allocated from the channel's allocator
+                unsafe {
+                    inner.sender_pool.free(sender, &mut allocator);
+                }
+            } else {
+                cursor.move_next();
+            }
+        }
+        inner.senders_removed(removed_async_senders, removed_sync_senders);
+
+        let mut cursor = inner.reciever_queue.cursor_start_mut();
+        while let Some(reciever) = cursor.next() {
+            if reciever.data.owner.as_ptr() == thread_group_ptr {
+                // panic safety: next was just checked to exist
+                let reciever = cursor.remove_next().unwrap();
+                // safety: all reciever nodes are allocated from the channel's allocator
+                unsafe {
+                    inner.reciever_pool.free(reciever, &mut allocator);
+                }
+            } else {
+                cursor.move_next();
+            }
+        }
+
+        let mut was_sender = false;
+        let mut i = 0;
+        while let Some(sender_group) = inner.sender_groups.get(i) {
+            if sender_group.as_ptr() == thread_group_ptr {
+                inner.sender_groups.remove(i);
+                was_sender = true;
+            } else if sender_group.strong_count() == 0 {
+                // a group which was dropped has already removed itself, this is only a leftover reference
+                inner.sender_groups.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        if was_sender && inner.sender_groups.is_empty() {
+            inner.wake_blocked_recievers(&mut allocator);
+        }
     }
 
     pub fn do_send(&self, sender: &ChannelSenderRef, reciever: &ChannelRecieverRef, current_thread_future_ref: Option<ThreadRef>) -> KResult<RecieveResult> {
@@ -342,6 +521,13 @@ impl Channel {
 
         let send_buffer = sender.send_buffer().ok_or(SysErr::InvlWeak)?;
 
+        // the key id comes from the sender's cspace, so a sender can only ever send its own identity
+        let sender_key_id = if sender.send_identity {
+            sender_cspace.identity_key_id()
+        } else {
+            None
+        };
+
         let reply_id = if let Some(reply) = sender.get_reply(current_thread_future_ref) {
             let reply = StrongCapability::new_flags(
                 Arc::new(
@@ -376,19 +562,26 @@ impl Channel {
                     if let Some(thread) = thread {
                         let thread = thread.get_thread_as_ready().ok_or(SysErr::OkUnreach)?;
 
-                        let write_size = recieve_buffer.copy_channel_message_from_buffer(&send_buffer, cap_transfer_info)?;
-                        thread.set_wake_reason(WakeReason::MsgRecv(RecieveResult {
-                            recieve_size: write_size,
-                            reply_cap_id: reply_id,
-                        }));
-    
-                        make_reply_visible();
+                        let write_result = recieve_buffer.copy_channel_message_from_buffer(&send_buffer, cap_transfer_info);
+                        match write_result {
+                            Ok(write_size) => {
+                                thread.set_wake_reason(WakeReason::MsgRecv(RecieveResult {
+                                    recieve_size: write_size,
+                                    reply_cap_id: reply_id,
+                                    sender_key_id,
+                                }));
+
+                                make_reply_visible();
+                            },
+                            // the thread is no longer waiting, so it has to be woken up even if the copy failed
+                            Err(error) => thread.set_wake_reason(WakeReason::MsgRecvError(error)),
+                        }
     
                         // FIXME: don't have oom here
                         thread_map().insert_ready_thread(Arc::downgrade(&thread))
                             .expect("failed to insert thread into ready list");
     
-                        write_size
+                        write_result?
                     } else {
                         let write_size = recieve_buffer.copy_channel_message_from_buffer(&send_buffer, cap_transfer_info)?;
 
@@ -403,6 +596,7 @@ impl Channel {
                     let write_size = event_pool.write_channel_event(
                         *event_id,
                         reply_id,
+                        sender_key_id,
                         &send_buffer,
                         cap_transfer_info,
                     )?;
@@ -426,12 +620,18 @@ impl Channel {
                 Ok(RecieveResult {
                     recieve_size: write_size,
                     reply_cap_id: reply_id,
+                    sender_key_id,
                 })
             },
             Err(error) => {
                 if let Some(reply_id) = reply_id {
                     // panic safety: this was inserted earlier, it should be present in reciever cspace
-                    reciever_cspace.remove_reply(reply_id).unwrap();
+                    let reply = reciever_cspace.remove_reply(reply_id).unwrap();
+
+                    // the sender never sent its message, so it is not waiting on the reply yet
+                    if let Capability::Strong(reply) = reply {
+                        reply.object().cancel();
+                    }
                 }
 
                 Err(error)
@@ -444,17 +644,31 @@ impl Drop for Channel {
     fn drop(&mut self) {
         let inner = self.inner.get_mut();
 
+        // threads blocked on this channel would otherwise never be woken up
         while let Some(sender) = inner.sender_queue.pop() {
+            if let Some(thread) = sender.data.listener.waiting_thread() {
+                thread.move_to_ready_list(WakeReason::PeerGone);
+            }
+
             unsafe {
                 sender.drop_in_place(&mut self.allocator);
             }
         }
 
         while let Some(reciever) = inner.reciever_queue.pop() {
+            if let Some(thread) = reciever.data.listener.waiting_thread() {
+                thread.move_to_ready_list(WakeReason::PeerGone);
+            }
+
             unsafe {
                 reciever.drop_in_place(&mut self.allocator);
             }
         }
+
+        unsafe {
+            inner.sender_pool.clear(&mut self.allocator);
+            inner.reciever_pool.clear(&mut self.allocator);
+        }
     }
 }
 
@@ -462,8 +676,536 @@ impl CapObject for Channel {
     const TYPE: CapType = CapType::Channel;
 }
 
-#[derive(Debug, Default)]
+/// Records in the current thread's thread group that it has a waiter queued on `channel`
+/// 
+/// This must be called with `channel` locked, see [`ThreadGroup::add_channel_wait`]
+/// 
+/// Returns the thread group which should own the waiter
+fn register_current_waiter(channel: &Arc<Channel>) -> KResult<Weak<ThreadGroup>> {
+    let thread_group = cpu_local_data().current_thread().thread_group()
+        .upgrade()
+        .ok_or(SysErr::InvlOp)?;
+
+    thread_group.add_channel_wait(channel)?;
+
+    Ok(Arc::downgrade(&thread_group))
+}
+
+/// A sender or reciever queued in a channel
+#[derive(Debug)]
+struct Waiter<T> {
+    listener: T,
+    /// Thread group of the thread which queued this waiter, the waiter is removed when this group exits
+    owner: Weak<ThreadGroup>,
+}
+
+#[derive(Debug)]
 struct ChannelInner {
-    sender_queue: LinkedList<DefaultNode<ChannelSenderRef>>,
-    reciever_queue: LinkedList<DefaultNode<ChannelRecieverRef>>,
+    sender_queue: LinkedList<DefaultNode<Waiter<ChannelSenderRef>>>,
+    reciever_queue: LinkedList<DefaultNode<Waiter<ChannelRecieverRef>>>,
+    /// Free nodes for `sender_queue`, only used while the channel is locked
+    sender_pool: NodePool<DefaultNode<Waiter<ChannelSenderRef>>>,
+    /// Free nodes for `reciever_queue`, only used while the channel is locked
+    reciever_pool: NodePool<DefaultNode<Waiter<ChannelRecieverRef>>>,
+    /// Number of senders in `sender_queue` waiting on an event pool
+    async_sender_count: usize,
+    async_sender_limit: usize,
+    /// Number of senders in `sender_queue` which are blocked threads
+    sync_sender_count: usize,
+    /// Listeners sent a [`ChannelSpace`] event once the async senders drain to the low watermark
+    space_listeners: Vec<EventPoolListenerRef>,
+    /// Thread groups which have sent on this channel and not yet exited
+    sender_groups: Vec<Weak<ThreadGroup>>,
+}
+
+impl ChannelInner {
+    fn is_sender_queue_full(&self, is_async: bool) -> bool {
+        if is_async {
+            self.async_sender_count >= self.async_sender_limit
+        } else {
+            self.sync_sender_count >= CHANNEL_SYNC_SENDER_LIMIT
+        }
+    }
+
+    fn push_sender(&mut self, sender: MemOwner<DefaultNode<Waiter<ChannelSenderRef>>>) {
+        if sender.data.listener.is_async() {
+            self.async_sender_count += 1;
+        } else {
+            self.sync_sender_count += 1;
+        }
+
+        self.sender_queue.push(sender);
+    }
+
+    fn pop_sender(&mut self) -> Option<MemOwner<DefaultNode<Waiter<ChannelSenderRef>>>> {
+        let sender = self.sender_queue.pop_front()?;
+
+        if sender.data.listener.is_async() {
+            self.senders_removed(1, 0);
+        } else {
+            self.senders_removed(0, 1);
+        }
+
+        Some(sender)
+    }
+
+    /// Updates the sender counts after senders are taken out of the sender queue,
+    /// and wakes the space listeners once the async senders have drained to the low watermark
+    fn senders_removed(&mut self, async_senders: usize, sync_senders: usize) {
+        self.async_sender_count -= async_senders;
+        self.sync_sender_count -= sync_senders;
+
+        // space listeners are only woken at half the limit, so a sender which was woken doesn't immediately fill the queue again
+        if async_senders > 0 && self.async_sender_count <= self.async_sender_limit / 2 {
+            let event_data = self.space_event();
+
+            while let Some(listener) = self.space_listeners.pop() {
+                // ignore errors, the event pool may be gone, and a full event pool reports the event as dropped
+                let _ = listener.write_event(event_data);
+            }
+        }
+    }
+
+    fn space_event(&self) -> EventData {
+        EventData::ChannelSpace(ChannelSpace {
+            queued_senders: self.async_sender_count,
+        })
+    }
+
+    /// Puts a reciever which was popped from the reciever queue back in the queue if it is auto reque
+    /// and it recieved a message, otherwise returns its node to the pool
+    fn finish_reciever(&mut self, reciever: MemOwner<DefaultNode<Waiter<ChannelRecieverRef>>>, recieved: bool, allocator: &mut HeapRef) {
+        if recieved && reciever.data.listener.is_auto_reque() {
+            self.reciever_queue.push(reciever);
+        } else {
+            // safety: all reciever nodes are allocated from the channel's allocator
+            unsafe {
+                self.reciever_pool.free(reciever, allocator);
+            }
+        }
+    }
+
+    /// Wakes every thread blocked recieving with [`WakeReason::PeerGone`] and removes it from the reciever queue
+    /// 
+    /// Recievers waiting on an event pool are left queued, they are not blocking anything
+    fn wake_blocked_recievers(&mut self, allocator: &mut HeapRef) {
+        let mut cursor = self.reciever_queue.cursor_start_mut();
+        while let Some(reciever) = cursor.next() {
+            if reciever.data.listener.waiting_thread().is_some() {
+                // panic safety: next was just checked to exist
+                let reciever = cursor.remove_next().unwrap();
+                if let Some(thread) = reciever.data.listener.waiting_thread() {
+                    thread.move_to_ready_list(WakeReason::PeerGone);
+                }
+
+                // safety: all reciever nodes are allocated from the channel's allocator
+                unsafe {
+                    self.reciever_pool.free(reciever, allocator);
+                }
+            } else {
+                cursor.move_next();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+    use crate::alloc::{CapAllocator, root_alloc, root_alloc_page_ref, root_alloc_ref};
+    use crate::arch::x64::IntDisable;
+    use crate::cap::key::Key;
+    use crate::cap::memory::{Memory, PageSource};
+    use crate::event::EventPool;
+    use crate::ktest;
+    use crate::sched::{switch_current_thread_to, PostSwitchAction, Thread, ThreadStartMode, ThreadState};
+    use crate::time;
+    use sys::{EventId, ExitReason};
+
+    pub(super) fn new_test_memory(page_count: usize) -> Arc<Memory> {
+        Arc::new(
+            Memory::new_with_page_source(root_alloc_page_ref(), root_alloc_ref(), page_count, PageSource::LazyZeroAlloc).unwrap(),
+            root_alloc_ref(),
+        ).unwrap()
+    }
+
+    fn read_byte(memory: &Memory, offset: usize) -> u8 {
+        let mut inner = memory.inner_write();
+        let page = inner.get_page_for_reading(offset / PAGE_SIZE).unwrap();
+
+        unsafe { *page.allocation().as_ptr::<u8>().add(offset % PAGE_SIZE) }
+    }
+
+    fn new_test_event_pool(max_size: Size) -> Arc<EventPool> {
+        Arc::new(
+            EventPool::new(root_alloc_page_ref(), root_alloc_ref(), max_size).unwrap(),
+            root_alloc_ref(),
+        ).unwrap()
+    }
+
+    /// Creates a reply to an async call made from `event_pool`
+    fn event_pool_reply(event_pool: &Arc<EventPool>, cspace: &Arc<CapabilitySpace>, response_buffer: Option<&UserspaceBuffer>) -> Reply {
+        let listener = ChannelRecieverRef::event_pool(
+            EventPoolListenerRef {
+                event_pool: Arc::downgrade(event_pool),
+                event_id: EventId::from_u64(1),
+            },
+            false,
+            cspace,
+        );
+
+        Reply::new(listener, response_buffer.map(UserspaceBuffer::downgrade))
+    }
+
+    #[test_case]
+    fn recieve_buffer_shrunk_while_waiting() {
+        let cspace = Arc::new(CapabilitySpace::new(root_alloc_ref()), root_alloc_ref()).unwrap();
+        let channel = Channel::new(root_alloc_ref());
+
+        let memory = new_test_memory(2);
+
+        let send_buffer = UserspaceBuffer::new(new_test_memory(1), 0, 64);
+        let recv_buffer = UserspaceBuffer::new(memory.clone(), PAGE_SIZE, 64);
+        recv_buffer.validate().unwrap();
+
+        // the reciever is queued with a buffer over the second page, which is then removed
+        let reciever = ChannelRecieverRef::current_thread(&recv_buffer, &cspace);
+        memory.resize(Size::from_pages(1), PageSource::LazyZeroAlloc).unwrap();
+
+        let sender = ChannelSenderRef::current_thread(&send_buffer, &cspace, false);
+        assert_eq!(channel.do_send(&sender, &reciever, None).err(), Some(SysErr::InvlBuffer));
+        assert_eq!(recv_buffer.validate(), Err(SysErr::InvlBuffer));
+
+        // once the memory grows back the same buffer can be used again
+        memory.resize(Size::from_pages(2), PageSource::LazyZeroAlloc).unwrap();
+        assert!(channel.do_send(&sender, &reciever, None).is_ok());
+    }
+
+    #[test_case]
+    fn exited_thread_group_waiters_are_removed() {
+        let cspace = Arc::new(CapabilitySpace::new(root_alloc_ref()), root_alloc_ref()).unwrap();
+        let channel = Arc::new(Channel::new(root_alloc_ref()), root_alloc_ref()).unwrap();
+
+        let exiting_group = Arc::new(ThreadGroup::new(root_alloc_page_ref(), root_alloc_ref()).unwrap(), root_alloc_ref()).unwrap();
+        let other_group = Arc::new(ThreadGroup::new(root_alloc_page_ref(), root_alloc_ref()).unwrap(), root_alloc_ref()).unwrap();
+
+        let send_buffer = UserspaceBuffer::new(new_test_memory(1), 0, 64);
+        let recv_buffer = UserspaceBuffer::new(new_test_memory(1), 0, 64);
+        let event_pool = new_test_event_pool(Size::from_pages(1));
+
+        {
+            let mut inner = channel.inner();
+
+            // the exiting group also listens with an auto reque event pool reciever
+            exiting_group.add_channel_wait(&channel).unwrap();
+            let reciever = inner.reciever_pool.alloc(
+                Waiter {
+                    listener: ChannelRecieverRef::event_pool(
+                        EventPoolListenerRef {
+                            event_pool: Arc::downgrade(&event_pool),
+                            event_id: EventId::from_u64(1),
+                        },
+                        true,
+                        &cspace,
+                    ),
+                    owner: Arc::downgrade(&exiting_group),
+                }.into(),
+                &mut root_alloc_ref(),
+            ).unwrap();
+            inner.reciever_queue.push(reciever);
+
+            // queue a reciever from each group, as if a thread in each was blocked in sync_recv
+            for owner in [&exiting_group, &other_group] {
+                owner.add_channel_wait(&channel).unwrap();
+
+                let reciever = inner.reciever_pool.alloc(
+                    Waiter {
+                        listener: ChannelRecieverRef::current_thread(&recv_buffer, &cspace),
+                        owner: Arc::downgrade(owner),
+                    }.into(),
+                    &mut root_alloc_ref(),
+                ).unwrap();
+                inner.reciever_queue.push(reciever);
+            }
+        }
+
+        // the auto reque reciever gets the first message, and is put back at the end of the queue
+        assert!(Channel::try_send(&channel, &send_buffer, &cspace, false).is_ok());
+        assert_eq!(event_pool.unprocessed_event_count(), 1);
+        assert_eq!(channel.inner().reciever_queue.len(), 3);
+
+        ThreadGroup::exit(exiting_group.clone(), ExitReason::Killed);
+        assert_eq!(channel.inner().reciever_queue.len(), 1);
+        assert_eq!(exiting_group.add_channel_wait(&channel), Err(SysErr::InvlOp));
+
+        // the message goes to the reciever which is still alive, and the auto reque reciever is not requeued
+        assert!(Channel::try_send(&channel, &send_buffer, &cspace, false).is_ok());
+        assert_eq!(channel.inner().reciever_queue.len(), 0);
+        assert_eq!(event_pool.unprocessed_event_count(), 1);
+        assert_eq!(Channel::try_send(&channel, &send_buffer, &cspace, false), Err(SysErr::OkUnreach));
+    }
+
+    /// Channel and buffer the blocked reciever test thread recieves with
+    static BLOCKED_RECIEVER_ARGS: IMutex<Option<(Arc<Channel>, UserspaceBuffer)>> = IMutex::new(None);
+    /// Why the blocked reciever test thread was woken
+    static BLOCKED_RECIEVER_WAKE_REASON: IMutex<Option<WakeReason>> = IMutex::new(None);
+
+    /// Blocks the current thread the way the channel syscalls do after [`ChannelSyncResult::Block`], and returns why it was woken
+    pub(super) fn block_current_thread(int_disable: IntDisable) -> WakeReason {
+        switch_current_thread_to(
+            ThreadState::Suspended,
+            int_disable,
+            PostSwitchAction::None,
+            false,
+        ).expect("failed to suspend thread while waiting on channel");
+
+        let _int_disable = IntDisable::new();
+        cpu_local_data().current_thread().wake_reason()
+    }
+
+    /// Suspends a test thread which is done until the test destroys it with [`destroy_test_thread`]
+    pub(super) fn suspend_forever() -> ! {
+        loop {
+            block_current_thread(IntDisable::new());
+        }
+    }
+
+    /// Starts a kernel thread in `thread_group` running `entry`, with the address space and capability space of the test thread
+    pub(super) fn spawn_test_thread(thread_group: &Arc<ThreadGroup>, name: &str, entry: extern "C" fn() -> !) -> Arc<Thread> {
+        let current_thread = cpu_local_data().current_thread();
+
+        let thread = ThreadGroup::create_kernel_thread(
+            thread_group,
+            current_thread.address_space().clone(),
+            current_thread.capability_space().clone(),
+            String::from_str(root_alloc_ref(), name).unwrap(),
+            ThreadStartMode::Suspended,
+            entry,
+        ).unwrap();
+        Thread::resume_suspended_thread(&thread).unwrap();
+
+        thread
+    }
+
+    /// Destroys a test thread once it has suspended itself for good
+    pub(super) fn destroy_test_thread(thread: &Arc<Thread>) {
+        while Thread::destroy_suspended_thread(thread).is_err() {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Spins until `condition` is true, panicking with `message` if that takes longer than a second
+    pub(super) fn wait_until(mut condition: impl FnMut() -> bool, message: &str) {
+        const WAIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+        let deadline = time::monotonic_nsec() + WAIT_TIMEOUT.as_nanos() as u64;
+        while !condition() {
+            assert!(time::monotonic_nsec() < deadline, "{}", message);
+            core::hint::spin_loop();
+        }
+    }
+
+    extern "C" fn blocked_reciever_main() -> ! {
+        let int_disable = IntDisable::new();
+        let (channel, buffer) = BLOCKED_RECIEVER_ARGS.lock().take().unwrap();
+        let cspace = CapabilitySpace::current();
+
+        let ChannelSyncResult::Block = Channel::sync_recv(&channel, &buffer, &cspace) else {
+            panic!("reciever did not block on an empty channel");
+        };
+        drop((channel, buffer, cspace));
+
+        let wake_reason = block_current_thread(int_disable);
+        *BLOCKED_RECIEVER_WAKE_REASON.lock() = Some(wake_reason);
+
+        suspend_forever()
+    }
+
+    #[test_case]
+    fn blocked_reciever_is_woken_when_last_sender_exits() {
+        let channel = Arc::new(Channel::new(root_alloc_ref()), root_alloc_ref()).unwrap();
+        let sender_groups = [0, 1].map(|_| {
+            Arc::new(ThreadGroup::new(root_alloc_page_ref(), root_alloc_ref()).unwrap(), root_alloc_ref()).unwrap()
+        });
+        let reciever_group = Arc::new(ThreadGroup::new(root_alloc_page_ref(), root_alloc_ref()).unwrap(), root_alloc_ref()).unwrap();
+
+        // record both groups as senders, as if a thread in each had sent on the channel
+        {
+            let mut inner = channel.inner();
+            for sender_group in sender_groups.iter() {
+                sender_group.add_channel_wait(&channel).unwrap();
+                inner.sender_groups.push(Arc::downgrade(sender_group)).unwrap();
+            }
+        }
+
+        *BLOCKED_RECIEVER_ARGS.lock() = Some((channel.clone(), UserspaceBuffer::new(new_test_memory(1), 0, 64)));
+        let reciever_thread = spawn_test_thread(&reciever_group, "blocked_reciever_test", blocked_reciever_main);
+        wait_until(|| channel.inner().reciever_queue.len() == 1, "reciever never blocked on the channel");
+
+        // the reciever keeps waiting while any sender is left
+        ThreadGroup::exit(sender_groups[0].clone(), ExitReason::Killed);
+        assert_eq!(channel.inner().reciever_queue.len(), 1);
+        assert!(BLOCKED_RECIEVER_WAKE_REASON.lock().is_none());
+
+        ThreadGroup::exit(sender_groups[1].clone(), ExitReason::Killed);
+        wait_until(|| BLOCKED_RECIEVER_WAKE_REASON.lock().is_some(), "reciever was not woken after the last sender exited");
+        assert!(matches!(BLOCKED_RECIEVER_WAKE_REASON.lock().take(), Some(WakeReason::PeerGone)));
+        assert_eq!(channel.inner().reciever_queue.len(), 0);
+        assert!(channel.inner().sender_groups.is_empty());
+
+        destroy_test_thread(&reciever_thread);
+    }
+
+    #[test_case]
+    fn sender_identity_is_attached_when_requested() {
+        let reciever_cspace = Arc::new(CapabilitySpace::new(root_alloc_ref()), root_alloc_ref()).unwrap();
+        let channel = Channel::new(root_alloc_ref());
+
+        let send_buffer = UserspaceBuffer::new(new_test_memory(1), 0, 64);
+        let recv_buffer = UserspaceBuffer::new(new_test_memory(1), 0, 64);
+        let reciever = ChannelRecieverRef::current_thread(&recv_buffer, &reciever_cspace);
+
+        let sender_key_ids = [0, 1].map(|_| {
+            let sender_cspace = Arc::new(CapabilitySpace::new(root_alloc_ref()), root_alloc_ref()).unwrap();
+            let key_id = Key::new().id();
+            sender_cspace.register_identity(key_id).unwrap();
+            assert_eq!(sender_cspace.register_identity(Key::new().id()), Err(SysErr::InvlOp));
+
+            let sender = ChannelSenderRef::current_thread(&send_buffer, &sender_cspace, true);
+            let recieve_result = channel.do_send(&sender, &reciever, None).unwrap();
+            assert_eq!(recieve_result.sender_key_id, Some(key_id));
+
+            // the identity is only sent when asked for
+            let sender = ChannelSenderRef::current_thread(&send_buffer, &sender_cspace, false);
+            assert_eq!(channel.do_send(&sender, &reciever, None).unwrap().sender_key_id, None);
+
+            key_id
+        });
+        assert_ne!(sender_key_ids[0], sender_key_ids[1]);
+
+        // a process with no identity sends none even with the flag
+        let sender = ChannelSenderRef::current_thread(&send_buffer, &reciever_cspace, true);
+        assert_eq!(channel.do_send(&sender, &reciever, None).unwrap().sender_key_id, None);
+    }
+
+    #[test_case]
+    fn reply_is_written_to_response_buffer_if_it_fits() {
+        let cspace = Arc::new(CapabilitySpace::new(root_alloc_ref()), root_alloc_ref()).unwrap();
+        let event_pool = new_test_event_pool(Size::from_pages(4));
+
+        // the message starts with a capability count of 0, followed by the response data
+        let src_memory = new_test_memory(1);
+        src_memory.inner_write().copy_from(8..64, [0xab; 56].as_slice()).unwrap();
+        let src_buffer = UserspaceBuffer::new(src_memory, 0, 64);
+
+        let response_memory = new_test_memory(1);
+        let new_reply = |response_size| {
+            let response_buffer = UserspaceBuffer::new(response_memory.clone(), 0, response_size);
+            event_pool_reply(&event_pool, &cspace, Some(&response_buffer))
+        };
+
+        // a response buffer which is too small is skipped, and the reply goes to the event pool
+        assert!(new_reply(32).reply(&src_buffer, &cspace).is_ok());
+        assert_eq!(read_byte(&response_memory, 8), 0);
+
+        assert_eq!(new_reply(PAGE_SIZE).reply(&src_buffer, &cspace), Ok(Size::from_bytes(64)));
+        assert_eq!(read_byte(&response_memory, 8), 0xab);
+        assert_eq!(read_byte(&response_memory, 63), 0xab);
+        assert_eq!(read_byte(&response_memory, 64), 0);
+    }
+    #[test_case]
+    fn large_reply_benchmark() {
+        const REPLY_SIZE: usize = 256 * 1024;
+        const ROUNDS: u64 = 16;
+
+        let cspace = Arc::new(CapabilitySpace::new(root_alloc_ref()), root_alloc_ref()).unwrap();
+        let src_buffer = UserspaceBuffer::new(new_test_memory(REPLY_SIZE / PAGE_SIZE), 0, REPLY_SIZE);
+        let response_buffer = UserspaceBuffer::new(new_test_memory(REPLY_SIZE / PAGE_SIZE), 0, REPLY_SIZE);
+
+        let mut event_pool_time = 0;
+        let mut response_buffer_time = 0;
+        for _ in 0..ROUNDS {
+            // each reply needs an empty event pool, since nothing reads the events
+            let event_pool = new_test_event_pool(Size::from_bytes(2 * REPLY_SIZE));
+            let reply = event_pool_reply(&event_pool, &cspace, None);
+
+            let start = time::monotonic_nsec();
+            reply.reply(&src_buffer, &cspace).unwrap();
+            event_pool_time += time::monotonic_nsec() - start;
+
+            let event_pool = new_test_event_pool(Size::from_bytes(2 * REPLY_SIZE));
+            let reply = event_pool_reply(&event_pool, &cspace, Some(&response_buffer));
+
+            let start = time::monotonic_nsec();
+            reply.reply(&src_buffer, &cspace).unwrap();
+            response_buffer_time += time::monotonic_nsec() - start;
+        }
+
+        ktest::report_benchmark("256 KiB reply to event pool", event_pool_time, ROUNDS);
+        ktest::report_benchmark("256 KiB reply to response buffer", response_buffer_time, ROUNDS);
+    }
+
+    #[test_case]
+    fn async_sender_queue_is_bounded() {
+        const SENDER_LIMIT: usize = DEFAULT_CHANNEL_SENDER_LIMIT;
+        const FLOOD_SENDS: usize = 16 * SENDER_LIMIT;
+
+        let cspace = Arc::new(CapabilitySpace::new(root_alloc_ref()), root_alloc_ref()).unwrap();
+
+        // an unbounded queue would run into the allocator's limit well before the flood is over
+        let channel_allocator = Arc::new(
+            CapAllocator::new_child(root_alloc().clone(), 16 * PAGE_SIZE),
+            root_alloc_ref(),
+        ).unwrap();
+        let channel = Arc::new(Channel::new(HeapRef::from_arc(channel_allocator.clone())), root_alloc_ref()).unwrap();
+
+        let send_buffer = UserspaceBuffer::new(new_test_memory(1), 0, 64);
+        let recv_buffer = UserspaceBuffer::new(new_test_memory(1), 0, 64);
+        let send_event_pool = new_test_event_pool(Size::from_pages(4));
+        let space_event_pool = new_test_event_pool(Size::from_pages(1));
+
+        let send = || Channel::async_send(
+            &channel,
+            EventPoolListenerRef {
+                event_pool: Arc::downgrade(&send_event_pool),
+                event_id: EventId::from_u64(1),
+            },
+            &send_buffer,
+            &cspace,
+            false,
+        );
+        let wait_space = || channel.async_wait_space(EventPoolListenerRef {
+            event_pool: Arc::downgrade(&space_event_pool),
+            event_id: EventId::from_u64(2),
+        });
+
+        for _ in 0..SENDER_LIMIT {
+            send().unwrap();
+        }
+        let full_usage = channel_allocator.usage().0;
+
+        for _ in 0..FLOOD_SENDS {
+            assert_eq!(send(), Err(SysErr::ChannelFull));
+        }
+        assert_eq!(channel.inner().sender_queue.len(), SENDER_LIMIT);
+        assert_eq!(channel_allocator.usage().0, full_usage);
+
+        // the listener is not woken until the queue drains to half its limit
+        wait_space().unwrap();
+        for _ in 0..(SENDER_LIMIT / 2 - 1) {
+            channel.try_recv(&recv_buffer, &cspace).unwrap();
+        }
+        assert_eq!(space_event_pool.unprocessed_event_count(), 0);
+
+        channel.try_recv(&recv_buffer, &cspace).unwrap();
+        assert_eq!(space_event_pool.unprocessed_event_count(), 1);
+        assert!(channel.inner().space_listeners.is_empty());
+
+        // there is space now, so sending works again and waiting for space returns right away
+        send().unwrap();
+        wait_space().unwrap();
+        assert_eq!(space_event_pool.unprocessed_event_count(), 2);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,253 @@
+use core::alloc::Layout;
+use core::mem::align_of;
+use core::ptr::NonNull;
+
+use crate::alloc::HeapRef;
+use crate::prelude::*;
+use crate::mem::{MemOwner, MemOwnerKernelExt};
+
+/// Maximum number of free nodes a [`NodePool`] will hold on to
+pub const NODE_POOL_SIZE: usize = 32;
+
+struct FreeNode {
+    next: Option<NonNull<FreeNode>>,
+}
+
+/// A small bounded pool of free channel queue nodes
+/// 
+/// Every message which has to wait in a channel queue needs a node, so this
+/// avoids going to the heap for each message in the common case.
+/// The pool is not synchronized, it is meant to live next to the queues it serves
+/// and be protected by the same lock.
+/// 
+/// Memory in the pool is allocated with the layout of `T`, so nodes allocated with
+/// [`MemOwner::new`] can be freed to the pool, and nodes from the pool can be freed with
+/// [`MemOwner::drop_in_place`].
+pub struct NodePool<T> {
+    free_list: Option<NonNull<FreeNode>>,
+    free_count: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T> NodePool<T> {
+    /// Free nodes are stored in the memory of the freed `T`, so it must be able to hold a [`FreeNode`]
+    const CAN_HOLD_FREE_NODE: () = assert!(
+        size_of::<T>() >= size_of::<FreeNode>() && align_of::<T>() >= align_of::<FreeNode>()
+    );
+
+    /// Returns the number of free nodes currently held by the pool
+    pub fn free_count(&self) -> usize {
+        self.free_count
+    }
+
+    /// Moves `data` into a node, using memory from the pool if any is available
+    pub fn alloc(&mut self, data: T, allocator: &mut HeapRef) -> KResult<MemOwner<T>> {
+        let Some(free_node) = self.free_list else {
+            return MemOwner::new(data, allocator);
+        };
+
+        unsafe {
+            self.free_list = free_node.as_ref().next;
+            self.free_count -= 1;
+
+            let ptr = free_node.as_ptr() as *mut T;
+            ptr.write(data);
+            Ok(MemOwner::from_raw(ptr))
+        }
+    }
+
+    /// Drops the data in `node` and returns its memory to the pool,
+    /// or to `allocator` if the pool is full
+    /// 
+    /// # Safety
+    /// 
+    /// `node` must have been allocated from `allocator`, and no other mem owner may point to this memory
+    pub unsafe fn free(&mut self, node: MemOwner<T>, allocator: &mut HeapRef) {
+        if self.free_count == NODE_POOL_SIZE {
+            unsafe {
+                node.drop_in_place(allocator);
+            }
+            return;
+        }
+
+        let () = Self::CAN_HOLD_FREE_NODE;
+
+        let ptr = node.ptr_nonnull();
+        unsafe {
+            ptr::drop_in_place(ptr.as_ptr());
+
+            let free_node = ptr.cast::<FreeNode>();
+            free_node.as_ptr().write(FreeNode {
+                next: self.free_list,
+            });
+            self.free_list = Some(free_node);
+        }
+        self.free_count += 1;
+    }
+
+    /// Returns all memory held by the pool to `allocator`
+    /// 
+    /// # Safety
+    /// 
+    /// All nodes in the pool must have been allocated from `allocator`
+    pub unsafe fn clear(&mut self, allocator: &mut HeapRef) {
+        while let Some(free_node) = self.free_list {
+            unsafe {
+                self.free_list = free_node.as_ref().next;
+                allocator.dealloc(free_node.cast(), Layout::new::<T>());
+            }
+        }
+        self.free_count = 0;
+    }
+}
+
+impl<T> Default for NodePool<T> {
+    fn default() -> Self {
+        NodePool {
+            free_list: None,
+            free_count: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> core::fmt::Debug for NodePool<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("NodePool")
+            .field("free_count", &self.free_count)
+            .finish()
+    }
+}
+
+// safety: the pool only holds uninitialized memory, never a live T
+unsafe impl<T> Send for NodePool<T> {}
+
+#[cfg(test)]
+mod tests {
+    use bit_utils::container::DefaultNode;
+
+    use super::*;
+    use crate::alloc::{root_alloc_page_ref, root_alloc_ref};
+    use crate::arch::x64::IntDisable;
+    use crate::cap::Capability;
+    use crate::cap::capability_space::CapabilitySpace;
+    use crate::cap::channel::{Channel, ChannelSyncResult};
+    use crate::cap::channel::tests::{block_current_thread, destroy_test_thread, new_test_memory, spawn_test_thread, suspend_forever};
+    use crate::container::Arc;
+    use crate::event::UserspaceBuffer;
+    use crate::ktest;
+    use crate::sched::{ThreadGroup, WakeReason};
+    use crate::sync::IMutex;
+    use crate::time;
+
+    type TestNode = DefaultNode<[usize; 6]>;
+
+    /// Number of calls made by [`node_round_trip_benchmark`]
+    const ROUND_TRIPS: u64 = 100_000;
+    /// Size of the calls and replies, which only hold an empty capability list
+    const MESSAGE_SIZE: usize = 64;
+
+    /// Channel the round trip server thread recieves calls on
+    static ROUND_TRIP_CHANNEL: IMutex<Option<Arc<Channel>>> = IMutex::new(None);
+
+    /// Answers [`ROUND_TRIPS`] calls on [`ROUND_TRIP_CHANNEL`] with an empty reply
+    extern "C" fn round_trip_server_main() -> ! {
+        let channel = ROUND_TRIP_CHANNEL.lock().take().unwrap();
+        let cspace = CapabilitySpace::current();
+        let buffer = UserspaceBuffer::new(new_test_memory(1), 0, MESSAGE_SIZE);
+
+        for _ in 0..ROUND_TRIPS {
+            let int_disable = IntDisable::new();
+            let recieve_result = match Channel::sync_recv(&channel, &buffer, &cspace) {
+                ChannelSyncResult::Success(recieve_result) => recieve_result,
+                ChannelSyncResult::Block => match block_current_thread(int_disable) {
+                    WakeReason::MsgRecv(recieve_result) => recieve_result,
+                    wake_reason => panic!("round trip server was woken by {:?}", wake_reason),
+                },
+                ChannelSyncResult::Error(error) => panic!("round trip server failed to recieve: {:?}", error),
+            };
+
+            let reply_id = recieve_result.reply_cap_id.expect("round trip call has no reply");
+            let Capability::Strong(reply) = cspace.remove_reply(reply_id).unwrap() else {
+                panic!("round trip reply is weak");
+            };
+            reply.object().reply(&buffer, &cspace).unwrap();
+        }
+
+        drop((channel, cspace, buffer));
+        suspend_forever()
+    }
+
+    #[test_case]
+    fn freed_nodes_are_reused() {
+        let mut allocator = root_alloc_ref();
+        let mut pool = NodePool::<TestNode>::default();
+
+        let node = pool.alloc([1; 6].into(), &mut allocator).unwrap();
+        let addr = node.ptr() as usize;
+        unsafe {
+            pool.free(node, &mut allocator);
+        }
+        assert_eq!(pool.free_count(), 1);
+
+        let node = pool.alloc([2; 6].into(), &mut allocator).unwrap();
+        assert_eq!(node.ptr() as usize, addr);
+        assert_eq!(node.data, [2; 6]);
+        assert_eq!(pool.free_count(), 0);
+
+        unsafe {
+            pool.free(node, &mut allocator);
+            pool.clear(&mut allocator);
+        }
+        assert_eq!(pool.free_count(), 0);
+    }
+
+    #[test_case]
+    fn pool_size_is_bounded() {
+        let mut allocator = root_alloc_ref();
+        let mut pool = NodePool::<TestNode>::default();
+        let mut nodes = [const { None }; NODE_POOL_SIZE + 8];
+
+        for (i, node) in nodes.iter_mut().enumerate() {
+            *node = Some(pool.alloc([i; 6].into(), &mut allocator).unwrap());
+        }
+        for node in nodes.iter_mut() {
+            unsafe {
+                pool.free(node.take().unwrap(), &mut allocator);
+            }
+        }
+        assert_eq!(pool.free_count(), NODE_POOL_SIZE);
+
+        unsafe {
+            pool.clear(&mut allocator);
+        }
+    }
+
+    /// Each call queues a sender or reciever node on the channel, so this measures the pool where channels use it
+    #[test_case]
+    fn node_round_trip_benchmark() {
+        let channel = Arc::new(Channel::new(root_alloc_ref()), root_alloc_ref()).unwrap();
+        let server_group = Arc::new(ThreadGroup::new(root_alloc_page_ref(), root_alloc_ref()).unwrap(), root_alloc_ref()).unwrap();
+
+        *ROUND_TRIP_CHANNEL.lock() = Some(channel.clone());
+        let server_thread = spawn_test_thread(&server_group, "round_trip_server", round_trip_server_main);
+
+        let cspace = CapabilitySpace::current();
+        let send_buffer = UserspaceBuffer::new(new_test_memory(1), 0, MESSAGE_SIZE);
+        let recv_buffer = UserspaceBuffer::new(new_test_memory(1), 0, MESSAGE_SIZE);
+
+        let start = time::monotonic_nsec();
+        for _ in 0..ROUND_TRIPS {
+            // like the syscall, interrupts stay disabled from queueing the call until this thread is suspended
+            let int_disable = IntDisable::new();
+            Channel::sync_call(&channel, &send_buffer, &recv_buffer, &cspace, false).unwrap();
+
+            let wake_reason = block_current_thread(int_disable);
+            assert!(matches!(wake_reason, WakeReason::MsgRecv(_)), "round trip call was answered with {:?}", wake_reason);
+        }
+        let round_trip_time = time::monotonic_nsec() - start;
+
+        destroy_test_thread(&server_thread);
+        ktest::report_benchmark("channel sync_call round trip", round_trip_time, ROUND_TRIPS);
+    }
+}
\ No newline at end of file
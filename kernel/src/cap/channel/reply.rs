@@ -1,10 +1,11 @@
 use core::sync::atomic::{AtomicBool, Ordering};
 
-use sys::CapType;
+use sys::{CapType, Event, EventData, ReplyWritten};
 
 use crate::prelude::*;
 use crate::cap::{CapObject, capability_space::CapabilitySpace};
-use crate::event::UserspaceBuffer;
+use crate::cap::memory::MemoryCopySrc;
+use crate::event::{UserspaceBuffer, WeakUserspaceBuffer};
 use crate::sched::{thread_map, WakeReason};
 use crate::container::Arc;
 
@@ -14,13 +15,18 @@ use super::event_listeners::ChannelRecieverRef;
 #[derive(Debug)]
 pub struct Reply {
     listener: ChannelRecieverRef,
+    /// Buffer designated by the caller which the reply is copied into if it fits
+    /// 
+    /// Only used when the listener is an event pool
+    response_buffer: Option<WeakUserspaceBuffer>,
     reply_fired: AtomicBool,
 }
 
 impl Reply {
-    pub fn new(listener: ChannelRecieverRef) -> Self {
+    pub fn new(listener: ChannelRecieverRef, response_buffer: Option<WeakUserspaceBuffer>) -> Self {
         Reply {
             listener,
+            response_buffer,
             reply_fired: AtomicBool::new(false),
         }
     }
@@ -36,6 +42,13 @@ impl Reply {
         }
     }
 
+    /// Marks this reply as used without sending anything, so the listener is not woken when it is dropped
+    /// 
+    /// This is used when the call carrying this reply could not be delivered, so the caller is not waiting on it yet
+    pub fn cancel(&self) {
+        self.reply_fired.store(true, Ordering::Relaxed);
+    }
+
     fn reply_inner(&self, src_buffer: &UserspaceBuffer, src_cspace: &CapabilitySpace) -> KResult<Size> {
         match &self.listener {
             ChannelRecieverRef::Thread {
@@ -49,21 +62,25 @@ impl Reply {
                 let thread = thread.as_ref().expect("reply must have a valid listening thread");
                 let thread = thread.get_thread_as_ready().ok_or(SysErr::OkUnreach)?;
 
-                let write_size = dst_buffer.copy_channel_message_from_buffer(src_buffer, CapabilityTransferInfo {
+                let write_result = dst_buffer.copy_channel_message_from_buffer(src_buffer, CapabilityTransferInfo {
                     src_cspace,
                     dst_cspace: &dst_cspace,
-                })?;
+                });
 
-                thread.set_wake_reason(WakeReason::MsgRecv(RecieveResult {
-                    recieve_size: write_size,
-                    reply_cap_id: None,
-                }));
+                thread.set_wake_reason(match write_result {
+                    Ok(write_size) => WakeReason::MsgRecv(RecieveResult {
+                        recieve_size: write_size,
+                        reply_cap_id: None,
+                        sender_key_id: None,
+                    }),
+                    Err(error) => WakeReason::MsgRecvError(error),
+                });
 
                 // FIXME: don't have oom here
                 thread_map().insert_ready_thread(Arc::downgrade(&thread))
                     .expect("failed to insert thread into ready list");
 
-                Ok(write_size)
+                write_result
             },
             ChannelRecieverRef::EventPool {
                 event_pool,
@@ -74,9 +91,31 @@ impl Reply {
                 let dst_cspace = cspace.upgrade().ok_or(SysErr::InvlWeak)?;
                 let event_pool = event_pool.upgrade().ok_or(SysErr::InvlWeak)?;
 
+                // the response buffer could have been shrunk since the call was made, so check it again here
+                if let Some(response_buffer) = self.response_buffer.as_ref().and_then(WeakUserspaceBuffer::upgrade)
+                    && src_buffer.size() <= response_buffer.size()
+                    && response_buffer.validate().is_ok()
+                {
+                    let write_size = response_buffer.copy_channel_message_from_buffer(src_buffer, CapabilityTransferInfo {
+                        src_cspace,
+                        dst_cspace: &dst_cspace,
+                    })?;
+
+                    event_pool.write_event(Event {
+                        event_data: EventData::ReplyWritten(ReplyWritten {
+                            response_size: write_size,
+                        }),
+                        event_id: *event_id,
+                    })?;
+                    event_pool.wake_listener()?;
+
+                    return Ok(write_size);
+                }
+
                 let write_size = event_pool.write_channel_event(
                     *event_id,
                     None,
+                    None,
                     src_buffer,
                     CapabilityTransferInfo {
                         src_cspace,
@@ -92,6 +131,17 @@ impl Reply {
     }
 }
 
+impl Drop for Reply {
+    fn drop(&mut self) {
+        // the caller would otherwise wait forever if the reciever exits or drops the reply without replying
+        if !*self.reply_fired.get_mut() {
+            if let Some(thread) = self.listener.waiting_thread() {
+                thread.move_to_ready_list(WakeReason::PeerGone);
+            }
+        }
+    }
+}
+
 impl CapObject for Reply {
     const TYPE: CapType = CapType::Reply;
 }
\ No newline at end of file
@@ -0,0 +1,11 @@
+use super::{CapObject, CapType};
+
+/// Capability which allows using privileged debugging syscalls, such as reading the kernel log
+/// 
+/// Only 1 is created, and it is given to early-init
+#[derive(Debug)]
+pub struct DebugCap;
+
+impl CapObject for DebugCap {
+    const TYPE: CapType = CapType::DebugCap;
+}
\ No newline at end of file
@@ -0,0 +1,149 @@
+use core::ops::Range;
+
+use crate::alloc::HeapRef;
+use crate::container::Vec;
+use crate::prelude::*;
+use super::{CapObject, CapType};
+
+/// Number of io ports on x86
+pub const IO_PORT_COUNT: u32 = 0x10000;
+
+/// Io ports the kernel drives itself, which userspace is never allowed to access
+const KERNEL_IO_PORTS: [Range<u32>; 6] = [
+    // master pic
+    0x20..0x22,
+    // pit
+    0x40..0x44,
+    // cmos and rtc
+    0x70..0x72,
+    // used by io_wait
+    0x80..0x81,
+    // slave pic
+    0xa0..0xa2,
+    // debugcon
+    0xe9..0xea,
+];
+
+/// Capability which allows creating [`IoPort`] capabilities for any range of ports in a list of allowed ranges
+///
+/// Only 1 is created, and it is given to early-init to pass on to hwaccess-server
+#[derive(Debug)]
+pub struct IoPortAccess {
+    /// Sorted and non overlapping ranges of ports which can be accessed
+    allowed_ranges: Vec<Range<u32>>,
+}
+
+impl IoPortAccess {
+    /// Creates an io port access capability which allows every port except for the ones the kernel uses
+    pub fn new_all_unreserved(allocator: HeapRef) -> KResult<Self> {
+        let mut allowed_ranges = Vec::new(allocator);
+
+        let mut start = 0;
+        for reserved in KERNEL_IO_PORTS.iter() {
+            if start < reserved.start {
+                allowed_ranges.push(start..reserved.start)?;
+            }
+            start = reserved.end;
+        }
+
+        if start < IO_PORT_COUNT {
+            allowed_ranges.push(start..IO_PORT_COUNT)?;
+        }
+
+        Ok(IoPortAccess {
+            allowed_ranges,
+        })
+    }
+
+    /// Creates an io port capability for the `count` ports starting at `base`
+    ///
+    /// # Returns
+    ///
+    /// [`SysErr::InvlArgs`] if `count` is 0, [`SysErr::Overflow`] if the range does not fit in a u32,
+    /// or [`SysErr::InvlPerm`] if any of the ports are not allowed
+    pub fn new_port(&self, base: u16, count: u32) -> KResult<IoPort> {
+        if count == 0 {
+            return Err(SysErr::InvlArgs);
+        }
+
+        let end = (base as u32).checked_add(count).ok_or(SysErr::Overflow)?;
+        let ports = (base as u32)..end;
+        let allowed = self.allowed_ranges.iter()
+            .any(|range| range.start <= ports.start && ports.end <= range.end);
+
+        if allowed {
+            Ok(IoPort {
+                ports,
+            })
+        } else {
+            Err(SysErr::InvlPerm)
+        }
+    }
+}
+
+impl CapObject for IoPortAccess {
+    const TYPE: CapType = CapType::IoPortAccess;
+}
+
+/// Capability which allows reading and writing a range of io ports
+#[derive(Debug)]
+pub struct IoPort {
+    ports: Range<u32>,
+}
+
+impl IoPort {
+    /// Checks that every port in the `width` bytes starting at `port` is in this capability's range
+    ///
+    /// # Returns
+    ///
+    /// [`SysErr::InvlPerm`] if any of the ports are outside of the range
+    pub fn check_access(&self, port: u16, width: u32) -> KResult<()> {
+        let start = port as u32;
+        let end = start + width;
+
+        if self.ports.start <= start && end <= self.ports.end {
+            Ok(())
+        } else {
+            Err(SysErr::InvlPerm)
+        }
+    }
+}
+
+impl CapObject for IoPort {
+    const TYPE: CapType = CapType::IoPort;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::root_alloc_ref;
+
+    #[test_case]
+    fn kernel_io_ports_are_not_allowed() {
+        let io_port_access = IoPortAccess::new_all_unreserved(root_alloc_ref()).unwrap();
+
+        assert_eq!(io_port_access.new_port(0x20, 1).unwrap_err(), SysErr::InvlPerm);
+        assert_eq!(io_port_access.new_port(0x43, 1).unwrap_err(), SysErr::InvlPerm);
+        assert_eq!(io_port_access.new_port(0xe9, 1).unwrap_err(), SysErr::InvlPerm);
+        // a range which runs into a kernel port is not allowed either
+        assert_eq!(io_port_access.new_port(0x1e, 4).unwrap_err(), SysErr::InvlPerm);
+        assert_eq!(io_port_access.new_port(0xfffe, 4).unwrap_err(), SysErr::InvlPerm);
+        assert_eq!(io_port_access.new_port(0x3f8, 0).unwrap_err(), SysErr::InvlArgs);
+
+        assert!(io_port_access.new_port(0x1e, 2).is_ok());
+        assert!(io_port_access.new_port(0x604, 2).is_ok());
+        assert!(io_port_access.new_port(0xfffc, 4).is_ok());
+    }
+
+    #[test_case]
+    fn io_port_access_must_be_in_range() {
+        let io_port_access = IoPortAccess::new_all_unreserved(root_alloc_ref()).unwrap();
+        let io_port = io_port_access.new_port(0x3f8, 8).unwrap();
+
+        assert_eq!(io_port.check_access(0x3f8, 1), Ok(()));
+        assert_eq!(io_port.check_access(0x3fc, 4), Ok(()));
+        assert_eq!(io_port.check_access(0x3f7, 1), Err(SysErr::InvlPerm));
+        assert_eq!(io_port.check_access(0x3fe, 4), Err(SysErr::InvlPerm));
+        assert_eq!(io_port.check_access(0x400, 1), Err(SysErr::InvlPerm));
+    }
+}
@@ -230,7 +230,7 @@ struct PlainMemoryCopySrcInner<'a>(PlainMemoryWriter<'a>);
 
 impl PlainMemoryCopySrcInner<'_> {
     fn current_page(&mut self) -> KResult<&Page> {
-        self.0.memory.get_page_for_reading(self.0.page_index)
+        self.0.memory.get_page_for_reading(self.0.offset / PAGE_SIZE)
     }
 
     fn size(&self) -> usize {
@@ -251,16 +251,24 @@ impl PlainMemoryCopySrcInner<'_> {
 
             let result = writer.write_region(region)?;
             write_size += result.write_size;
-            self.0.offset += result.write_size.bytes();
             if result.end_reached {
                 break;
             }
+
+            // the writer consumes the whole region unless it reaches its end,
+            // but it may write a different number of bytes than it consumed
+            self.0.offset += region_size;
         }
 
         Ok(write_size)
     }
 }
 
+/// Copies out of a range of a memory capability
+/// 
+/// Each source page is handed to the writer as its own region, and [`PlainMemoryWriter`] splits
+/// regions at destination page boundaries, so copying between 2 memory capabilities goes
+/// directly from page to page without any intermediate buffer, regardless of how the offsets are aligned.
 pub struct PlainMemoryCopySrc<'a>(RefCell<PlainMemoryCopySrcInner<'a>>);
 
 impl<'a> From<PlainMemoryWriter<'a>> for PlainMemoryCopySrc<'a> {
@@ -1,4 +1,6 @@
-use core::ops::{RangeBounds, Bound};
+use core::cmp::{min, max};
+use core::ops::{Range, RangeBounds, Bound};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::prelude::*;
 use crate::alloc::{PaRef, HeapRef};
@@ -13,6 +15,18 @@ pub use memory_writer::*;
 mod page;
 pub use page::*;
 
+/// Number of memory objects which currently exist
+static MEMORY_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// Total size in pages of all memory objects which currently exist
+static MEMORY_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of memory objects which exist, and their total size in pages
+/// 
+/// The counters are updated with relaxed ordering, so this is only a snapshot
+pub fn memory_stats() -> (usize, usize) {
+    (MEMORY_COUNT.load(Ordering::Relaxed), MEMORY_PAGES.load(Ordering::Relaxed))
+}
+
 /// A capability that represents memory that can be mapped into a process
 #[derive(Debug)]
 pub struct Memory {
@@ -38,16 +52,60 @@ impl Memory {
 
         pages.extend(page_source.create_pages(page_count, &mut page_allocator)?)?;
 
-        let inner = MemoryInner {
-            pages,
-            size,
-            page_allocator,
-            mappings: HashMap::new(heap_allocator),
-        };
+        let inner = MemoryInner::new(pages, size, page_allocator, heap_allocator);
 
         Ok(Memory {
             id: MappingId::new(),
-            inner: IrwLock::new(inner),
+            inner: IrwLock::named("memory", inner),
+        })
+    }
+
+    /// Creates a copy on write clone of this memory
+    /// 
+    /// All owned pages in this memory are converted to copy on write pages which are shared with the new memory,
+    /// and existing mappings of those pages are remapped as read only. Pages are copied when either memory writes to them.
+    /// 
+    /// # Locking
+    /// 
+    /// acquires the memory inner lock for write
+    /// then acquires the inner lock of each address space this memory is mapped in
+    pub fn new_cow(&self, page_allocator: PaRef, heap_allocator: HeapRef) -> KResult<Self> {
+        let mut inner = self.inner_write();
+
+        let mut pages = Vec::try_with_capacity(heap_allocator.clone(), inner.pages.len())?;
+        // pages converted to copy on write so far, which are converted back if a later page fails
+        let mut converted_pages = Vec::try_with_capacity(heap_allocator.clone(), inner.pages.len())?;
+
+        for page_index in 0..inner.pages.len() {
+            let page_data = match &inner.pages[page_index] {
+                PageData::Owned(_) => match unsafe { inner.convert_to_cow(page_index, heap_allocator.clone()) } {
+                    Ok(page) => {
+                        converted_pages.push(page_index)?;
+                        PageData::Cow(page)
+                    },
+                    Err(error) => {
+                        // this memory holds the only references to the converted pages once the new pages are dropped
+                        drop(pages);
+                        unsafe {
+                            inner.restore_owned_pages(&converted_pages);
+                        }
+
+                        return Err(error);
+                    },
+                },
+                PageData::Cow(page) => PageData::Cow(page.clone()),
+                PageData::LazyAlloc => PageData::LazyAlloc,
+                PageData::LazyZeroAlloc => PageData::LazyZeroAlloc,
+            };
+
+            pages.push(page_data)?;
+        }
+
+        let inner = MemoryInner::new(pages, inner.size, page_allocator, heap_allocator);
+
+        Ok(Memory {
+            id: MappingId::new(),
+            inner: IrwLock::named("memory", inner),
         })
     }
 
@@ -55,7 +113,7 @@ impl Memory {
     /// 
     /// # Returns
     /// 
-    /// returns the size of the mapping
+    /// returns the size of the mapping, or [`SysErr::InvlPerm`] if the mapping is writable and the memory is sealed
     /// 
     /// # Locking
     /// 
@@ -65,6 +123,10 @@ impl Memory {
         let mut inner = this.inner_write();
         let mut addr_space_inner = addr_space.inner();
 
+        if inner.sealed && args.options.write {
+            return Err(SysErr::InvlPerm);
+        }
+
         let location = inner.map_memory_args_to_location(args)
             .ok_or(SysErr::InvlArgs)?;
 
@@ -124,6 +186,21 @@ impl Memory {
         inner.unmap_memory_inner(&mut addr_space_inner, address)
     }
 
+    /// Unmaps the part of the mapping of this memory at `address` which overlaps `range`
+    /// 
+    /// See [`MemoryInner::unmap_memory_range_inner`]
+    /// 
+    /// # Locking
+    /// 
+    /// acquires the memory inner lock for write
+    /// then acquires the addr_space inner lock
+    pub fn unmap_memory_range(&self, address_space: &AddressSpace, address: VirtAddr, range: AVirtRange) -> KResult<()> {
+        let mut inner = self.inner_write();
+        let mut addr_space_inner = address_space.inner();
+
+        inner.unmap_memory_range_inner(&mut addr_space_inner, address, range)
+    }
+
     pub fn update_mapping(&self, address_space: &AddressSpace, address: VirtAddr, args: UpdateMappingAgs) -> KResult<Size> {
         let mut inner = self.inner_write();
         let mut addr_space_inner = address_space.inner();
@@ -134,6 +211,10 @@ impl Memory {
     pub fn resize(&self, new_size: Size, page_source: PageSource) -> KResult<Size> {
         let mut inner = self.inner_write();
 
+        if inner.sealed {
+            return Err(SysErr::InvlPerm);
+        }
+
         if inner.mappings.len() != 0 {
             // cannot resize memory if it is mapped
             return Err(SysErr::InvlOp);
@@ -156,7 +237,7 @@ impl Memory {
     /// 
     /// # Returns
     /// 
-    /// The new size of the memory
+    /// The new size of the memory, or [`SysErr::InvlPerm`] if the memory is sealed
     pub fn resize_in_place(&self, new_size: Size, extend_mapping: bool, page_source: PageSource) -> KResult<Size> {
         if new_size.pages_rounded() == 0 {
             return Err(SysErr::InvlArgs);
@@ -164,6 +245,10 @@ impl Memory {
 
         let mut inner = self.inner_write();
 
+        if inner.sealed {
+            return Err(SysErr::InvlPerm);
+        }
+
         if inner.size == new_size {
             return Ok(inner.size)
         }
@@ -239,6 +324,207 @@ impl Memory {
         }
     }
 
+    /// Copies `size` bytes from `src` starting at `src_offset` into `dst` starting at `dst_offset`
+    /// 
+    /// Lazily allocated and copy on write pages are resolved as needed.
+    /// `dst` and `src` may be the same memory, but then the source and destination ranges must not overlap.
+    /// Fails with [`SysErr::InvlPerm`] if `dst` is sealed.
+    /// 
+    /// # Locking
+    /// 
+    /// acquires the inner lock of `dst` and `src` for write, in order of their address
+    pub fn copy_memory(dst: &Self, dst_offset: usize, src: &Self, src_offset: usize, size: usize) -> KResult<()> {
+        let dst_end = dst_offset.checked_add(size).ok_or(SysErr::Overflow)?;
+        let src_end = src_offset.checked_add(size).ok_or(SysErr::Overflow)?;
+
+        if ptr::eq(dst, src) {
+            if dst_offset < src_end && src_offset < dst_end {
+                return Err(SysErr::InvlArgs);
+            }
+
+            let mut inner = dst.inner_write();
+            if inner.sealed {
+                return Err(SysErr::InvlPerm);
+            }
+            if dst_end > inner.size.bytes() || src_end > inner.size.bytes() {
+                return Err(SysErr::InvlMemZone);
+            }
+
+            inner.copy_pages(dst_offset, src_offset, size)
+        } else {
+            // always lock the memory with the lower address first so 2 copies in opposite directions can't deadlock
+            let (mut dst_inner, mut src_inner) = if (dst as *const Self) < (src as *const Self) {
+                let dst_inner = dst.inner_write();
+                (dst_inner, src.inner_write())
+            } else {
+                let src_inner = src.inner_write();
+                (dst.inner_write(), src_inner)
+            };
+
+            if dst_inner.sealed {
+                return Err(SysErr::InvlPerm);
+            } else if dst_end > dst_inner.size.bytes() || src_end > src_inner.size.bytes() {
+                return Err(SysErr::InvlMemZone);
+            } else if size == 0 {
+                return Ok(());
+            }
+
+            let mut writer = dst_inner.create_memory_writer(dst_offset..dst_end)
+                .ok_or(SysErr::InvlMemZone)?;
+            let copy_src = PlainMemoryCopySrc::from(
+                src_inner.create_memory_writer(src_offset..src_end).ok_or(SysErr::InvlMemZone)?,
+            );
+
+            copy_src.copy_to(&mut writer)?;
+
+            Ok(())
+        }
+    }
+
+    /// Resolves a page fault on the page at `page_index` by allocating it if it is lazily allocated,
+    /// or copying it if it is copy on write and `write` is true
+    /// 
+    /// Pages which are already present are left as is, since another thread may have resolved the fault first
+    /// 
+    /// # Locking
+    /// 
+    /// acquires the memory inner lock for write
+    /// then acquires the inner lock of each address space the page is mapped in
+    pub fn resolve_page_fault(&self, page_index: usize, write: bool) -> KResult<()> {
+        let mut inner = self.inner_write();
+
+        if page_index >= inner.pages.len() {
+            return Err(SysErr::InvlMemZone);
+        }
+
+        if write {
+            inner.get_page_for_writing(page_index)?;
+        } else {
+            inner.get_page_for_reading(page_index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the physical address of the page at `page_index`, so it can be used for dma
+    /// 
+    /// Lazily allocated and copy on write pages are allocated and copied first, so the returned page is owned by this memory.
+    /// The page stays at the same physical address until the memory is resized to not include it,
+    /// or a copy on write clone is made of the memory.
+    /// Sealed memory is refused with [`SysErr::InvlPerm`], since the device could write to the page.
+    /// 
+    /// # Locking
+    /// 
+    /// acquires the memory inner lock for write
+    /// then acquires the inner lock of each address space the page is mapped in
+    pub fn page_phys_addr(&self, page_index: usize) -> KResult<PhysAddr> {
+        let mut inner = self.inner_write();
+
+        if inner.sealed {
+            return Err(SysErr::InvlPerm);
+        }
+
+        if page_index >= inner.pages.len() {
+            return Err(SysErr::InvlMemZone);
+        }
+
+        Ok(inner.get_page_for_writing(page_index)?.phys_addr())
+    }
+
+    /// Permanently makes this memory read only
+    /// 
+    /// Existing writable mappings are remapped read only. Afterwards the memory can't be mapped writable, resized,
+    /// or written to by the kernel, those operations fail with [`SysErr::InvlPerm`].
+    /// Copy on write clones made before sealing are separate memory, and are not sealed.
+    /// Sealing memory which is already sealed does nothing.
+    /// 
+    /// # Locking
+    /// 
+    /// acquires the memory inner lock for write
+    /// then acquires the inner lock of each address space the memory is mapped in
+    pub fn seal(&self) -> KResult<()> {
+        self.inner_write().seal()
+    }
+
+    /// Returns the address where byte `offset` of this memory is mapped in `address_space`, or None if it is not mapped there
+    /// 
+    /// If the memory is mapped more than once in the address space, any of the mappings may be used.
+    /// 
+    /// # Locking
+    /// 
+    /// acquires the memory inner lock for read
+    pub fn mapped_address(&self, address_space: &Arc<AddressSpace>, offset: usize) -> Option<VirtAddr> {
+        let inner = self.inner_read();
+
+        inner.mappings.iter().find_map(|(_, mapping)| {
+            if mapping.addr_space.as_ptr() != Arc::as_ptr(address_space) {
+                return None;
+            }
+
+            let mapping_offset = offset.checked_sub(mapping.location.offset.bytes())?;
+            (mapping_offset < mapping.location.map_size.bytes())
+                .then(|| mapping.location.map_addr + mapping_offset)
+        })
+    }
+
+    /// Finds which pages in `pages` were written through a mapping of this memory since their dirty bits were last reset
+    /// 
+    /// Bit `i` of `dirty` is set if page `pages.start + i` is dirty in any of the mappings, and pages which are not mapped are clean.
+    /// Writes the kernel makes directly to the pages, like channel messages and [`copy_memory`](Self::copy_memory), are not tracked.
+    /// If `reset` is true the dirty bits are cleared, and other cpus are flushed so later writes are seen.
+    /// 
+    /// # Returns
+    /// 
+    /// The number of dirty pages, or [`SysErr::InvlMemZone`] if `pages` goes past the end of the memory
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if `dirty` has less than `pages.len()` bits
+    /// 
+    /// # Locking
+    /// 
+    /// acquires the memory inner lock for read
+    /// then acquires the inner lock of each address space the memory is mapped in, one at a time
+    pub fn query_dirty(&self, pages: Range<usize>, reset: bool, dirty: &mut [u8]) -> KResult<usize> {
+        assert!(dirty.len() * 8 >= pages.len());
+        dirty.fill(0);
+
+        let inner = self.inner_read();
+        if pages.end > inner.size().pages_rounded() {
+            return Err(SysErr::InvlMemZone);
+        }
+
+        for (_, mapping) in inner.mappings.iter() {
+            let Some(address_space) = mapping.addr_space.upgrade() else {
+                continue;
+            };
+
+            let mapping_start = mapping.location.offset.pages_rounded();
+            let mapping_end = mapping_start + mapping.location.map_size.pages_rounded();
+            let query_pages = max(pages.start, mapping_start)..min(pages.end, mapping_end);
+            if query_pages.is_empty() {
+                continue;
+            }
+
+            let mut addr_space_inner = address_space.inner();
+
+            for page_index in query_pages {
+                let address = mapping.location.map_addr + (page_index - mapping_start) * PAGE_SIZE;
+
+                if addr_space_inner.addr_space.take_page_dirty(address, reset) == Some(true) {
+                    let bit = page_index - pages.start;
+                    dirty[bit / 8] |= 1 << (bit % 8);
+                }
+            }
+
+            if reset {
+                addr_space_inner.addr_space.flush_tlb();
+            }
+        }
+
+        Ok(dirty.iter().map(|byte| byte.count_ones() as usize).sum())
+    }
+
     pub fn id(&self) -> MappingId {
         self.id
     }
@@ -268,7 +554,24 @@ pub struct MemoryMappingLocation {
 
 impl MemoryMappingLocation {
     pub fn map_range(&self) -> AVirtRange {
-        AVirtRange::new(self.map_addr, self.offset.bytes())
+        AVirtRange::new(self.map_addr, self.map_size.bytes_aligned())
+    }
+
+    /// Returns the part of this location between `start` and `end`, or None if it is empty
+    fn sub_location(&self, start: VirtAddr, end: VirtAddr) -> Option<MemoryMappingLocation> {
+        let start = max(start, self.map_addr);
+        let end = min(end, self.map_range().end_addr());
+
+        if start >= end {
+            return None;
+        }
+
+        Some(MemoryMappingLocation {
+            map_addr: start,
+            map_size: Size::from_bytes(end - start),
+            offset: self.offset + Size::from_bytes(start - self.map_addr),
+            options: self.options,
+        })
     }
 }
 
@@ -307,14 +610,97 @@ pub struct MemoryInner {
     page_allocator: PaRef,
     /// All places where this memory capability is currently mapped
     mappings: HashMap<MappingId, MemoryMapping>,
+    /// Sealed memory can never be written again, see [`Memory::seal`]
+    sealed: bool,
 }
 
 impl MemoryInner {
+    fn new(pages: Vec<PageData>, size: Size, page_allocator: PaRef, heap_allocator: HeapRef) -> Self {
+        MEMORY_COUNT.fetch_add(1, Ordering::Relaxed);
+        MEMORY_PAGES.fetch_add(size.pages_rounded(), Ordering::Relaxed);
+
+        MemoryInner {
+            pages,
+            size,
+            page_allocator,
+            mappings: HashMap::new(heap_allocator),
+            sealed: false,
+        }
+    }
+
     /// Returns the total size of this memory
     pub fn size(&self) -> Size {
         self.size
     }
 
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    /// Marks this memory as sealed, and remaps all of its writable mappings read only
+    /// 
+    /// If any mapping can't be remapped, the mappings which were already remapped are made writable again,
+    /// and the memory is left unsealed
+    pub fn seal(&mut self) -> KResult<()> {
+        let mut failure = None;
+
+        for (mapping_id, mapping) in self.mappings.iter() {
+            if !mapping.location.options.write {
+                continue;
+            }
+
+            let mut new_location = mapping.location;
+            new_location.options = new_location.options.writable(false);
+
+            if let Err(error) = self.remap(mapping, new_location) {
+                failure = Some((*mapping_id, error));
+                break;
+            }
+        }
+
+        if let Some((failed_id, error)) = failure {
+            // iteration order is the same as above, so this restores every mapping up to and including the failed one
+            for (mapping_id, mapping) in self.mappings.iter() {
+                if mapping.location.options.write {
+                    // pages this fails to map again are unmapped, and are mapped writable again on the next page fault
+                    let _ = self.remap(mapping, mapping.location);
+                }
+
+                if *mapping_id == failed_id {
+                    break;
+                }
+            }
+
+            return Err(error);
+        }
+
+        for (_, mapping) in self.mappings.iter_mut() {
+            mapping.location.options = mapping.location.options.writable(false);
+        }
+        self.sealed = true;
+
+        Ok(())
+    }
+
+    /// Changes the location of `mapping` in its address space to `new_location`, and maps the pages which are present
+    /// 
+    /// `new_location` must have the same range as the current location of the mapping
+    fn remap(&self, mapping: &MemoryMapping, new_location: MemoryMappingLocation) -> KResult<()> {
+        let Some(addr_space) = mapping.addr_space.upgrade() else {
+            return Ok(());
+        };
+        let mut addr_space_inner = addr_space.inner();
+
+        addr_space_inner.mappings.set_memory_location(new_location.map_addr, new_location)?;
+
+        // panic safety: the location is already mapped, so it is in bounds
+        let mapping_iter = self.mapping_iter(new_location).unwrap();
+        // safety: mapping_iter ensures the regions are valid to map
+        unsafe {
+            addr_space_inner.addr_space.map_many(mapping_iter)
+        }
+    }
+
     pub fn get_map_size(&self, map_size: Option<Size>, offset: Size) -> Option<Size> {
         if offset >= self.size {
             return None;
@@ -363,6 +749,10 @@ impl MemoryInner {
             return Err(SysErr::InvlOp);
         };
 
+        if let UpdateValue::Change(options) = args.options && options.write && self.sealed {
+            return Err(SysErr::InvlPerm);
+        }
+
         if let UpdateValue::Change(new_size) = args.size {
             let mut new_location = mapping.location;
 
@@ -435,19 +825,87 @@ impl MemoryInner {
         Ok(())
     }
 
+    /// Unmaps the part of the memory mapping at `address` which overlaps `range`
+    /// 
+    /// If `range` covers the whole mapping it is removed, if it covers the start or end the mapping is shrunk,
+    /// and if it is in the middle the mapping is split in 2, with the second half getting a new mapping id.
+    pub fn unmap_memory_range_inner(&mut self, addr_space: &mut AddressSpaceInner, address: VirtAddr, range: AVirtRange) -> KResult<()> {
+        let mapping = addr_space.mappings.get_mapping_from_address(address)
+            .ok_or(SysErr::InvlVirtAddr)?;
+
+        let AddrSpaceMapping::Memory(mapping) = mapping else {
+            // event pools and other mappings can't be split
+            return Err(SysErr::InvlOp);
+        };
+
+        let memory = mapping.memory.clone();
+        let location = mapping.location;
+        let mapping_id = mapping.mapping_id;
+        let map_range = location.map_range();
+
+        let unmap_location = location.sub_location(range.addr(), range.end_addr())
+            .ok_or(SysErr::InvlArgs)?;
+        let before = location.sub_location(map_range.addr(), range.addr());
+        let after = location.sub_location(range.end_addr(), map_range.end_addr());
+
+        match (before, after) {
+            (None, None) => return self.unmap_memory_inner(addr_space, address),
+            (Some(remaining), None) | (None, Some(remaining)) => {
+                addr_space.mappings.set_memory_location(address, remaining)?;
+                // panic safety: memory and address space mappings are always kept in sync
+                self.mappings.get_mut(&mapping_id).unwrap().location = remaining;
+            },
+            (Some(before), Some(after)) => {
+                // panic safety: memory and address space mappings are always kept in sync
+                let weak_addr_space = self.mappings.get(&mapping_id).unwrap().addr_space.clone();
+
+                let after_id = MappingId::new();
+                self.mappings.insert(after_id, MemoryMapping {
+                    addr_space: weak_addr_space,
+                    location: after,
+                })?;
+
+                // the start address is unchanged, so this can't fail
+                addr_space.mappings.set_memory_location(address, before).unwrap();
+
+                let insert_result = addr_space.mappings.insert_mapping(AddrSpaceMapping::Memory(AddrSpaceMemoryMapping {
+                    memory,
+                    location: after,
+                    mapping_id: after_id,
+                }));
+
+                if let Err(error) = insert_result {
+                    // panic safety: these mappings were checked or inserted above
+                    let Some(AddrSpaceMapping::Memory(mapping)) = addr_space.mappings.get_mapping_from_address_mut(address) else {
+                        unreachable!();
+                    };
+                    mapping.location = location;
+                    self.mappings.remove(&after_id).unwrap();
+
+                    return Err(error);
+                }
+
+                self.mappings.get_mut(&mapping_id).unwrap().location = before;
+            },
+        }
+
+        self.unmap_location(&mut addr_space.addr_space, unmap_location);
+
+        Ok(())
+    }
+
     /// Unmaps the memory at the given location
     /// 
     /// Panics if the memory was not mapped therre
     pub fn unmap_location(&self, addr_space: &mut VirtAddrSpace, location: MemoryMappingLocation) {
         // panic safety: if this region was mapped, the pages should exist
-        for (i, page) in self.get_pages_for_location(location).unwrap().iter().enumerate() {
-            if let PageData::Owned(_) | PageData::Cow(_) = page {
-                let virt_addr = location.map_addr + PAGE_SIZE * i;
-                unsafe {
-                    addr_space.unmap_page(virt_addr).expect("failed to unmap page");
-                }
-            }
-        }
+        let mapped_pages = self.get_pages_for_location(location).unwrap()
+            .iter()
+            .filter(|page| matches!(page, PageData::Owned(_) | PageData::Cow(_)))
+            .count();
+
+        let unmapped_pages = unsafe { addr_space.unmap_range(location.map_range()) };
+        assert_eq!(unmapped_pages, mapped_pages, "failed to unmap page");
     }
 
     /// Resizes the memory to hav `new_page_count` pages
@@ -475,6 +933,8 @@ impl MemoryInner {
             self.pages.truncate(new_page_count);
         }
 
+        MEMORY_PAGES.fetch_add(new_page_count, Ordering::Relaxed);
+        MEMORY_PAGES.fetch_sub(self.size.pages_rounded(), Ordering::Relaxed);
         self.size = new_size;
 
         Ok(())
@@ -579,6 +1039,55 @@ impl MemoryInner {
         result
     }
 
+    /// Converts the owned page at `page_index` to a copy on write page, and returns the now shared page
+    /// 
+    /// On failure the page is left owned by this memory
+    unsafe fn convert_to_cow(&mut self, page_index: usize, heap_allocator: HeapRef) -> KResult<Arc<Page>> {
+        let PageData::Owned(page) = core::mem::replace(&mut self.pages[page_index], PageData::LazyAlloc) else {
+            panic!("expected owned page");
+        };
+
+        let page = match Arc::try_new(page, heap_allocator) {
+            Ok(page) => page,
+            Err((page, error)) => {
+                // nothing has been remapped yet, so the page can just be put back
+                self.pages[page_index] = PageData::Owned(page);
+                return Err(error);
+            },
+        };
+
+        if let Err(error) = unsafe { self.set_page(page_index, PageData::Cow(page.clone())) } {
+            // set_page put back the placeholder and dropped its clone, but some mappings could be read only now
+            self.pages[page_index] = PageData::Cow(page);
+            unsafe {
+                self.restore_owned_pages(&[page_index]);
+            }
+
+            return Err(error);
+        }
+
+        Ok(page)
+    }
+
+    /// Converts pages made copy on write by [`MemoryInner::convert_to_cow`] back to owned pages, and remaps them as writable
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if any of the pages are still shared with another memory
+    unsafe fn restore_owned_pages(&mut self, page_indexes: &[usize]) {
+        for page_index in page_indexes.iter().copied() {
+            let PageData::Cow(page) = core::mem::replace(&mut self.pages[page_index], PageData::LazyAlloc) else {
+                panic!("expected copy on write page");
+            };
+
+            let page = Arc::into_inner(page).expect("restored copy on write page is still shared");
+            self.pages[page_index] = PageData::Owned(page);
+
+            // the page is already mapped everywhere it is remapped, so no page tables are allocated and this can't fail
+            let _ = unsafe { self.remap_all_mappings_for_page_index(page_index) };
+        }
+    }
+
     fn get_page_assuming_owned(&self, page_index: usize) -> &Page {
         match &self.pages[page_index] {
             PageData::Owned(page) => page,
@@ -667,6 +1176,44 @@ impl MemoryInner {
         }
     }
 
+    /// Copies `size` bytes starting at `src_offset` to `dst_offset` within this memory, 1 page chunk at a time
+    /// 
+    /// The ranges must not overlap. Copies between different memory capabilities use [`PlainMemoryCopySrc`] instead,
+    /// which can't be used here since it needs to borrow the source memory separately.
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if either range is out of bounds
+    fn copy_pages(&mut self, dst_offset: usize, src_offset: usize, size: usize) -> KResult<()> {
+        let mut copied = 0;
+
+        while copied < size {
+            let dst_position = dst_offset + copied;
+            let src_position = src_offset + copied;
+
+            let copy_size = min(
+                size - copied,
+                min(PAGE_SIZE - dst_position % PAGE_SIZE, PAGE_SIZE - src_position % PAGE_SIZE),
+            );
+
+            let mut dst_allocation = self.get_page_for_writing(dst_position / PAGE_SIZE)?.allocation();
+            let src_allocation = self.get_page_for_reading(src_position / PAGE_SIZE)?.allocation();
+
+            // safety: both pages are owned by this memory capability, and the caller ensures the ranges don't overlap
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    src_allocation.as_ptr::<u8>().add(src_position % PAGE_SIZE),
+                    dst_allocation.as_mut_ptr::<u8>().add(dst_position % PAGE_SIZE),
+                    copy_size,
+                );
+            }
+
+            copied += copy_size;
+        }
+
+        Ok(())
+    }
+
     /// Zeros this entire memory capability
     /// 
     /// # Safety
@@ -696,6 +1243,13 @@ impl MemoryInner {
     }
 }
 
+impl Drop for MemoryInner {
+    fn drop(&mut self) {
+        MEMORY_COUNT.fetch_sub(1, Ordering::Relaxed);
+        MEMORY_PAGES.fetch_sub(self.size.pages_rounded(), Ordering::Relaxed);
+    }
+}
+
 #[derive(Debug, Clone)]
 struct MemoryMappingIter<'a> {
     pages: &'a [PageData],
@@ -747,4 +1301,529 @@ impl MemoryInner {
             options: map_location.options,
         })
     }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::{root_alloc, root_alloc_page_ref, root_alloc_ref, CapAllocator};
+    use sys::FaultAccess;
+
+    fn new_test_memory(page_count: usize) -> Memory {
+        Memory::new_with_page_source(
+            root_alloc_page_ref(),
+            root_alloc_ref(),
+            page_count,
+            PageSource::LazyZeroAlloc,
+        ).unwrap()
+    }
+
+    fn read_byte(memory: &Memory, offset: usize) -> u8 {
+        let mut inner = memory.inner_write();
+        let page = inner.get_page_for_reading(offset / PAGE_SIZE).unwrap();
+
+        unsafe { *page.allocation().as_ptr::<u8>().add(offset % PAGE_SIZE) }
+    }
+
+    fn pattern_byte(offset: usize) -> u8 {
+        (offset % 251) as u8
+    }
+
+    /// Fills the whole memory with a pattern that doesn't repeat at page boundaries
+    fn fill_with_pattern(memory: &Memory) {
+        let mut inner = memory.inner_write();
+        let mut chunk = [0u8; 256];
+
+        for start in (0..inner.size().bytes()).step_by(chunk.len()) {
+            for (i, byte) in chunk.iter_mut().enumerate() {
+                *byte = pattern_byte(start + i);
+            }
+            inner.copy_from(start..(start + chunk.len()), chunk.as_slice()).unwrap();
+        }
+    }
+
+    fn assert_pattern_copied(dst: &Memory, dst_offset: usize, src_offset: usize, size: usize) {
+        assert_eq!(read_byte(dst, dst_offset - 1), 0);
+        for i in 0..size {
+            assert_eq!(read_byte(dst, dst_offset + i), pattern_byte(src_offset + i));
+        }
+        assert_eq!(read_byte(dst, dst_offset + size), 0);
+    }
+
+    #[test_case]
+    fn copy_memory_across_pages() {
+        let src = new_test_memory(2);
+        let dst = new_test_memory(2);
+
+        let data = [0xab_u8; 100];
+        src.inner_write().copy_from((PAGE_SIZE - 50)..(PAGE_SIZE + 50), data.as_slice()).unwrap();
+
+        Memory::copy_memory(&dst, 10, &src, PAGE_SIZE - 50, 100).unwrap();
+
+        assert_eq!(read_byte(&dst, 9), 0);
+        for i in 10..110 {
+            assert_eq!(read_byte(&dst, i), 0xab);
+        }
+        assert_eq!(read_byte(&dst, 110), 0);
+    }
+
+    #[test_case]
+    fn copy_src_misaligned_across_pages() {
+        let src = new_test_memory(4);
+        let dst = new_test_memory(5);
+        fill_with_pattern(&src);
+
+        let src_offset = 100;
+        let dst_offset = 4000;
+        let size = 3 * PAGE_SIZE + 123;
+
+        {
+            let mut dst_inner = dst.inner_write();
+            let mut src_inner = src.inner_write();
+
+            let mut writer = dst_inner.create_memory_writer(dst_offset..(dst_offset + size)).unwrap();
+            let copy_src = PlainMemoryCopySrc::from(
+                src_inner.create_memory_writer(src_offset..(src_offset + size)).unwrap(),
+            );
+
+            assert_eq!(copy_src.copy_to(&mut writer), Ok(Size::from_bytes(size)));
+        }
+
+        assert_pattern_copied(&dst, dst_offset, src_offset, size);
+    }
+
+    #[test_case]
+    fn copy_memory_misaligned_across_pages() {
+        let src = new_test_memory(5);
+        let dst = new_test_memory(4);
+        fill_with_pattern(&src);
+
+        let src_offset = 4000;
+        let dst_offset = 100;
+        let size = 3 * PAGE_SIZE + 123;
+
+        Memory::copy_memory(&dst, dst_offset, &src, src_offset, size).unwrap();
+
+        assert_pattern_copied(&dst, dst_offset, src_offset, size);
+    }
+
+    #[test_case]
+    fn copy_memory_rejects_overlap() {
+        let memory = new_test_memory(2);
+
+        assert_eq!(Memory::copy_memory(&memory, 0, &memory, 100, 200), Err(SysErr::InvlArgs));
+        assert_eq!(Memory::copy_memory(&memory, 0, &memory, PAGE_SIZE, 200), Ok(()));
+        assert_eq!(Memory::copy_memory(&memory, PAGE_SIZE, &memory, 0, 2 * PAGE_SIZE), Err(SysErr::InvlArgs));
+        assert_eq!(Memory::copy_memory(&memory, PAGE_SIZE, &memory, 0, PAGE_SIZE + 1), Err(SysErr::InvlArgs));
+    }
+
+    #[test_case]
+    fn lazy_zeroed_page_fault_reads_zero() {
+        let memory = Arc::new(new_test_memory(2), root_alloc_ref()).unwrap();
+        let address_space = Arc::new(
+            AddressSpace::new(root_alloc_page_ref(), root_alloc_ref()).unwrap(),
+            root_alloc_ref(),
+        ).unwrap();
+
+        let map_addr = VirtAddr::new(0x100000);
+        Memory::map_memory(memory.clone(), address_space.clone(), MapMemoryArgs {
+            map_addr,
+            map_size: None,
+            offset: Size::zero(),
+            options: PageMappingOptions {
+                read: true,
+                ..Default::default()
+            },
+        }).unwrap();
+
+        assert!(matches!(memory.inner_read().pages[1], PageData::LazyZeroAlloc));
+
+        address_space.handle_page_fault(map_addr + PAGE_SIZE + 8, FaultAccess::Read).unwrap();
+        assert!(matches!(memory.inner_read().pages[1], PageData::Owned(_)));
+        assert!(matches!(memory.inner_read().pages[0], PageData::LazyZeroAlloc));
+
+        for i in PAGE_SIZE..(2 * PAGE_SIZE) {
+            assert_eq!(read_byte(&memory, i), 0);
+        }
+
+        // mapping is read only, so write faults can't be resolved
+        assert_eq!(address_space.handle_page_fault(map_addr, FaultAccess::Write), Err(SysErr::InvlOp));
+        assert_eq!(address_space.handle_page_fault(map_addr + 2 * PAGE_SIZE, FaultAccess::Read), Err(SysErr::InvlVirtAddr));
+    }
+
+    #[test_case]
+    fn unmap_range_splits_mapping() {
+        let memory = Arc::new(new_test_memory(16), root_alloc_ref()).unwrap();
+        let address_space = Arc::new(
+            AddressSpace::new(root_alloc_page_ref(), root_alloc_ref()).unwrap(),
+            root_alloc_ref(),
+        ).unwrap();
+
+        let map_addr = VirtAddr::new(0x100000);
+        Memory::map_memory(memory.clone(), address_space.clone(), MapMemoryArgs {
+            map_addr,
+            map_size: None,
+            offset: Size::zero(),
+            options: PageMappingOptions {
+                read: true,
+                write: true,
+                ..Default::default()
+            },
+        }).unwrap();
+
+        address_space.unmap_range(AVirtRange::new(map_addr + 4 * PAGE_SIZE, 4 * PAGE_SIZE)).unwrap();
+
+        for page in (0..4).chain(8..16) {
+            address_space.handle_page_fault(map_addr + page * PAGE_SIZE, FaultAccess::Write).unwrap();
+            assert!(matches!(memory.inner_read().pages[page], PageData::Owned(_)));
+        }
+        for page in 4..8 {
+            assert_eq!(
+                address_space.handle_page_fault(map_addr + page * PAGE_SIZE, FaultAccess::Read),
+                Err(SysErr::InvlVirtAddr),
+            );
+            assert!(matches!(memory.inner_read().pages[page], PageData::LazyZeroAlloc));
+        }
+
+        // the second half maps the end of the memory
+        let inner = address_space.inner();
+        let Some(AddrSpaceMapping::Memory(mapping)) = inner.mappings.get_mapping_from_address(map_addr + 8 * PAGE_SIZE) else {
+            panic!("second half of split mapping is missing");
+        };
+        assert_eq!(mapping.location.offset, Size::from_pages(8));
+        assert_eq!(mapping.location.map_size, Size::from_pages(8));
+        drop(inner);
+        assert_eq!(memory.inner_read().mappings.len(), 2);
+
+        // shrinking from the start and end keeps the middle mapped
+        address_space.unmap_range(AVirtRange::new(map_addr, 2 * PAGE_SIZE)).unwrap();
+        address_space.unmap_range(AVirtRange::new(map_addr + 14 * PAGE_SIZE, 2 * PAGE_SIZE)).unwrap();
+        assert_eq!(address_space.handle_page_fault(map_addr + PAGE_SIZE, FaultAccess::Read), Err(SysErr::InvlVirtAddr));
+        assert_eq!(address_space.handle_page_fault(map_addr + 15 * PAGE_SIZE, FaultAccess::Read), Err(SysErr::InvlVirtAddr));
+        address_space.handle_page_fault(map_addr + 2 * PAGE_SIZE, FaultAccess::Read).unwrap();
+        address_space.handle_page_fault(map_addr + 13 * PAGE_SIZE, FaultAccess::Read).unwrap();
+
+        address_space.unmap_range(AVirtRange::new(map_addr, 16 * PAGE_SIZE)).unwrap();
+        assert_eq!(memory.inner_read().mappings.len(), 0);
+    }
+
+    #[test_case]
+    fn list_mappings_from_cursor() {
+        let memory = Arc::new(new_test_memory(2), root_alloc_ref()).unwrap();
+        let address_space = Arc::new(
+            AddressSpace::new(root_alloc_page_ref(), root_alloc_ref()).unwrap(),
+            root_alloc_ref(),
+        ).unwrap();
+
+        let map_addr = VirtAddr::new(0x100000);
+        let guard_addr = VirtAddr::new(0x200000);
+        Memory::map_memory(memory.clone(), address_space.clone(), MapMemoryArgs {
+            map_addr,
+            map_size: None,
+            offset: Size::zero(),
+            options: PageMappingOptions {
+                read: true,
+                ..Default::default()
+            },
+        }).unwrap();
+        address_space.map_guard(AVirtRange::new(guard_addr, PAGE_SIZE)).unwrap();
+
+        let mut out = [const { None }; 4];
+        assert_eq!(address_space.list_mappings(0, &mut out), 2);
+
+        let mut out = [const { None }; 1];
+        assert_eq!(address_space.list_mappings(0, &mut out), 1);
+        assert!(matches!(&out[0], Some(AddrSpaceMapping::Memory(mapping)) if mapping.location.map_addr == map_addr));
+
+        assert_eq!(address_space.list_mappings(map_addr.as_usize() + 1, &mut out), 1);
+        assert!(matches!(&out[0], Some(AddrSpaceMapping::Guard(guard)) if guard.map_range.addr() == guard_addr));
+
+        assert_eq!(address_space.list_mappings(guard_addr.as_usize() + 1, &mut out), 0);
+
+        address_space.unmap_range(AVirtRange::new(map_addr, 0x101000)).unwrap();
+    }
+
+    #[test_case]
+    fn cow_memory_copies_on_write() {
+        let memory = Arc::new(new_test_memory(2), root_alloc_ref()).unwrap();
+        memory.inner_write().copy_from(0..64, [0xab_u8; 64].as_slice()).unwrap();
+        memory.inner_write().copy_from(PAGE_SIZE..(PAGE_SIZE + 64), [0xab_u8; 64].as_slice()).unwrap();
+
+        let address_space = Arc::new(
+            AddressSpace::new(root_alloc_page_ref(), root_alloc_ref()).unwrap(),
+            root_alloc_ref(),
+        ).unwrap();
+
+        let map_addr = VirtAddr::new(0x100000);
+        Memory::map_memory(memory.clone(), address_space.clone(), MapMemoryArgs {
+            map_addr,
+            map_size: None,
+            offset: Size::zero(),
+            options: PageMappingOptions {
+                read: true,
+                write: true,
+                ..Default::default()
+            },
+        }).unwrap();
+
+        let cow_memory = memory.new_cow(root_alloc_page_ref(), root_alloc_ref()).unwrap();
+        assert!(matches!(memory.inner_read().pages[0], PageData::Cow(_)));
+        assert!(matches!(cow_memory.inner_read().pages[0], PageData::Cow(_)));
+
+        // write to the first page through the mapping
+        address_space.handle_page_fault(map_addr, FaultAccess::Write).unwrap();
+        assert!(matches!(memory.inner_read().pages[0], PageData::Owned(_)));
+        memory.inner_write().copy_from(0..1, [0xcd_u8].as_slice()).unwrap();
+
+        assert_eq!(read_byte(&memory, 0), 0xcd);
+        assert_eq!(read_byte(&cow_memory, 0), 0xab);
+        assert_eq!(read_byte(&cow_memory, PAGE_SIZE), 0xab);
+
+        // the unwritten page is still shared
+        let phys_addr = memory.inner_write().get_page_for_reading(1).unwrap().phys_addr();
+        let cow_phys_addr = cow_memory.inner_write().get_page_for_reading(1).unwrap().phys_addr();
+        assert_eq!(phys_addr, cow_phys_addr);
+    }
+
+    #[test_case]
+    fn failed_cow_clone_restores_owned_pages() {
+        const PAGE_COUNT: usize = 256;
+
+        let memory = Arc::new(new_test_memory(PAGE_COUNT), root_alloc_ref()).unwrap();
+        fill_with_pattern(&memory);
+
+        let address_space = new_test_address_space();
+        Memory::map_memory(memory.clone(), address_space.clone(), read_write_args(VirtAddr::new(0x100000))).unwrap();
+
+        let mut phys_addrs = Vec::new(root_alloc_ref());
+        for page_index in 0..PAGE_COUNT {
+            phys_addrs.push(memory.inner_write().get_page_for_reading(page_index).unwrap().phys_addr()).unwrap();
+        }
+
+        // cloning identical memory shows how much heap memory a successful clone keeps
+        let measure_memory = new_test_memory(PAGE_COUNT);
+        fill_with_pattern(&measure_memory);
+        let measure_allocator = Arc::new(
+            CapAllocator::new_child(root_alloc().clone(), 256 * PAGE_SIZE),
+            root_alloc_ref(),
+        ).unwrap();
+        let measure_clone = measure_memory.new_cow(root_alloc_page_ref(), HeapRef::from_arc(measure_allocator.clone())).unwrap();
+        let clone_size = measure_allocator.usage().0;
+        drop(measure_clone);
+        drop(measure_memory);
+
+        // the page list fits under this limit, so some pages are converted before the clone runs out of memory
+        let limited_allocator = Arc::new(
+            CapAllocator::new_child(root_alloc().clone(), clone_size - 1),
+            root_alloc_ref(),
+        ).unwrap();
+        let result = memory.new_cow(root_alloc_page_ref(), HeapRef::from_arc(limited_allocator.clone()));
+        assert_eq!(result.err(), Some(SysErr::QuotaExceeded));
+        assert_eq!(limited_allocator.usage().0, 0);
+
+        for (page_index, phys_addr) in phys_addrs.iter().enumerate() {
+            assert!(matches!(memory.inner_read().pages[page_index], PageData::Owned(_)), "page {} is not owned", page_index);
+            assert_eq!(memory.inner_write().get_page_for_reading(page_index).unwrap().phys_addr(), *phys_addr);
+
+            let page_start = page_index * PAGE_SIZE;
+            assert_eq!(read_byte(&memory, page_start), pattern_byte(page_start));
+            assert_eq!(read_byte(&memory, page_start + PAGE_SIZE - 1), pattern_byte(page_start + PAGE_SIZE - 1));
+        }
+    }
+
+    fn new_test_address_space() -> Arc<AddressSpace> {
+        Arc::new(
+            AddressSpace::new(root_alloc_page_ref(), root_alloc_ref()).unwrap(),
+            root_alloc_ref(),
+        ).unwrap()
+    }
+
+    fn read_write_args(map_addr: VirtAddr) -> MapMemoryArgs {
+        MapMemoryArgs {
+            map_addr,
+            map_size: None,
+            offset: Size::zero(),
+            options: PageMappingOptions {
+                read: true,
+                write: true,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test_case]
+    fn seal_removes_write_from_all_mappings() {
+        let memory = Arc::new(new_test_memory(2), root_alloc_ref()).unwrap();
+        let address_space1 = new_test_address_space();
+        let address_space2 = new_test_address_space();
+
+        let map_addr1 = VirtAddr::new(0x100000);
+        let map_addr2 = VirtAddr::new(0x200000);
+        Memory::map_memory(memory.clone(), address_space1.clone(), read_write_args(map_addr1)).unwrap();
+        Memory::map_memory(memory.clone(), address_space2.clone(), read_write_args(map_addr2)).unwrap();
+
+        // the first page is present and writable before sealing
+        address_space1.handle_page_fault(map_addr1, FaultAccess::Write).unwrap();
+
+        memory.seal().unwrap();
+        assert!(memory.inner_read().is_sealed());
+
+        for (address_space, map_addr) in [(&address_space1, map_addr1), (&address_space2, map_addr2)] {
+            let inner = address_space.inner();
+            let Some(AddrSpaceMapping::Memory(mapping)) = inner.mappings.get_mapping_from_address(map_addr) else {
+                panic!("sealed memory mapping is missing");
+            };
+            assert!(mapping.location.options.read);
+            assert!(!mapping.location.options.write);
+            drop(inner);
+
+            // stores fault, and the fault can't be resolved
+            assert_eq!(address_space.handle_page_fault(map_addr, FaultAccess::Write), Err(SysErr::InvlOp));
+            assert_eq!(address_space.handle_page_fault(map_addr + PAGE_SIZE, FaultAccess::Write), Err(SysErr::InvlOp));
+            address_space.handle_page_fault(map_addr + PAGE_SIZE, FaultAccess::Read).unwrap();
+        }
+        assert!(memory.inner_read().mappings.iter().all(|(_, mapping)| !mapping.location.options.write));
+
+        // sealed memory can't be written or resized, and sealing again does nothing
+        assert_eq!(
+            Memory::map_memory(memory.clone(), address_space1.clone(), read_write_args(VirtAddr::new(0x300000))),
+            Err(SysErr::InvlPerm),
+        );
+        assert_eq!(memory.update_mapping(&address_space2, map_addr2, UpdateMappingAgs {
+            options: UpdateValue::Change(read_write_args(map_addr2).options),
+            ..Default::default()
+        }), Err(SysErr::InvlPerm));
+        assert_eq!(memory.resize_in_place(Size::from_pages(4), false, PageSource::LazyZeroAlloc), Err(SysErr::InvlPerm));
+
+        let src = new_test_memory(1);
+        assert_eq!(Memory::copy_memory(&memory, 0, &src, 0, 8), Err(SysErr::InvlPerm));
+        assert_eq!(memory.page_phys_addr(0), Err(SysErr::InvlPerm));
+        memory.seal().unwrap();
+    }
+
+    #[test_case]
+    fn seal_does_not_affect_earlier_cow_clone() {
+        let memory = Arc::new(new_test_memory(1), root_alloc_ref()).unwrap();
+        memory.inner_write().copy_from(0..64, [0xab_u8; 64].as_slice()).unwrap();
+
+        let cow_memory = Arc::new(
+            memory.new_cow(root_alloc_page_ref(), root_alloc_ref()).unwrap(),
+            root_alloc_ref(),
+        ).unwrap();
+        memory.seal().unwrap();
+        assert!(!cow_memory.inner_read().is_sealed());
+
+        let address_space = new_test_address_space();
+        let map_addr = VirtAddr::new(0x100000);
+        Memory::map_memory(cow_memory.clone(), address_space.clone(), read_write_args(map_addr)).unwrap();
+
+        // writing to the clone copies the shared page, and the sealed memory keeps its data
+        address_space.handle_page_fault(map_addr, FaultAccess::Write).unwrap();
+        assert!(matches!(cow_memory.inner_read().pages[0], PageData::Owned(_)));
+
+        let src = new_test_memory(1);
+        Memory::copy_memory(&cow_memory, 0, &src, 0, 1).unwrap();
+        assert_eq!(read_byte(&cow_memory, 0), 0);
+        assert_eq!(read_byte(&memory, 0), 0xab);
+    }
+
+    #[test_case]
+    fn failed_seal_restores_writable_mappings() {
+        let memory = Arc::new(new_test_memory(2), root_alloc_ref()).unwrap();
+        let address_space1 = new_test_address_space();
+
+        // the second address space can't allocate any more page tables once the memory is mapped
+        let limited_allocator = Arc::new(
+            CapAllocator::new_child(root_alloc().clone(), 256 * PAGE_SIZE),
+            root_alloc_ref(),
+        ).unwrap();
+        let address_space2 = Arc::new(
+            AddressSpace::new(PaRef::from_arc(limited_allocator.clone()), root_alloc_ref()).unwrap(),
+            root_alloc_ref(),
+        ).unwrap();
+
+        let map_addr1 = VirtAddr::new(0x100000);
+        let map_addr2 = VirtAddr::new(0x200000);
+        Memory::map_memory(memory.clone(), address_space1.clone(), read_write_args(map_addr1)).unwrap();
+        Memory::map_memory(memory.clone(), address_space2.clone(), read_write_args(map_addr2)).unwrap();
+        limited_allocator.set_limit(limited_allocator.usage().0).unwrap();
+
+        // the first page is now present, so sealing has to map it in the second address space, which fails
+        address_space1.handle_page_fault(map_addr1, FaultAccess::Write).unwrap();
+        assert!(memory.seal().is_err());
+        assert!(!memory.inner_read().is_sealed());
+
+        for (address_space, map_addr) in [(&address_space1, map_addr1), (&address_space2, map_addr2)] {
+            let inner = address_space.inner();
+            let Some(AddrSpaceMapping::Memory(mapping)) = inner.mappings.get_mapping_from_address(map_addr) else {
+                panic!("memory mapping is missing after failed seal");
+            };
+            assert!(mapping.location.options.write);
+        }
+        assert!(memory.inner_read().mappings.iter().all(|(_, mapping)| mapping.location.options.write));
+
+        // the memory can still be written, and sealing works once page tables can be allocated
+        address_space1.handle_page_fault(map_addr1, FaultAccess::Write).unwrap();
+        address_space1.handle_page_fault(map_addr1 + PAGE_SIZE, FaultAccess::Write).unwrap();
+        let src = new_test_memory(1);
+        Memory::copy_memory(&memory, 0, &src, 0, 8).unwrap();
+
+        limited_allocator.set_limit(256 * PAGE_SIZE).unwrap();
+        memory.seal().unwrap();
+        assert!(memory.inner_read().is_sealed());
+        assert_eq!(address_space2.handle_page_fault(map_addr2, FaultAccess::Write), Err(SysErr::InvlOp));
+    }
+
+    #[test_case]
+    fn page_phys_addr_allocates_lazy_page() {
+        let memory = new_test_memory(2);
+
+        assert!(matches!(memory.inner_read().pages[1], PageData::LazyZeroAlloc));
+        let phys_addr = memory.page_phys_addr(1).unwrap();
+        assert!(matches!(memory.inner_read().pages[1], PageData::Owned(_)));
+        assert!(matches!(memory.inner_read().pages[0], PageData::LazyZeroAlloc));
+
+        // the page does not move once it is owned
+        assert_eq!(memory.page_phys_addr(1), Ok(phys_addr));
+        assert_eq!(memory.page_phys_addr(2), Err(SysErr::InvlMemZone));
+    }
+
+    #[test_case]
+    fn query_dirty_finds_written_page() {
+        let memory = Arc::new(new_test_memory(4), root_alloc_ref()).unwrap();
+        // the current address space is used so the cpu sets the dirty bit when writing through the mapping
+        let address_space = AddressSpace::current();
+        let map_addr = VirtAddr::new(0x50000000);
+        Memory::map_memory(memory.clone(), address_space.clone(), read_write_args(map_addr)).unwrap();
+
+        // mapping the pages writable does not make them dirty, the last page is left unmapped
+        for page_index in 0..3 {
+            address_space.handle_page_fault(map_addr + page_index * PAGE_SIZE, FaultAccess::Write).unwrap();
+        }
+
+        let page2 = (map_addr + 2 * PAGE_SIZE).as_usize() as *mut u8;
+        unsafe {
+            ptr::write_volatile(page2, 0xab);
+        }
+
+        let mut dirty = [0u8; 1];
+        assert_eq!(memory.query_dirty(0..4, false, &mut dirty), Ok(1));
+        assert_eq!(dirty[0], 0b0100);
+        // the queried range is relative to the start of the bitmap
+        assert_eq!(memory.query_dirty(1..4, false, &mut dirty), Ok(1));
+        assert_eq!(dirty[0], 0b0010);
+
+        assert_eq!(memory.query_dirty(0..4, true, &mut dirty), Ok(1));
+        assert_eq!(memory.query_dirty(0..4, false, &mut dirty), Ok(0));
+        assert_eq!(dirty[0], 0);
+
+        // writes after a reset are seen again
+        unsafe {
+            ptr::write_volatile(page2, 0xcd);
+        }
+        assert_eq!(memory.query_dirty(2..3, false, &mut dirty), Ok(1));
+
+        assert_eq!(memory.query_dirty(0..5, false, &mut dirty), Err(SysErr::InvlMemZone));
+
+        address_space.unmap(map_addr).unwrap();
+    }
 }
\ No newline at end of file
@@ -17,9 +17,9 @@ impl Page {
     }
 
     pub fn new(mut allocator: PaRef) -> KResult<Self> {
-        let allocation = allocator.alloc(
+        let allocation = allocator.try_alloc(
             PageLayout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap(),
-        ).ok_or(SysErr::OutOfMem)?;
+        )?;
 
         Ok(Page {
             allocation,
@@ -170,15 +170,13 @@ impl PageSource {
         match self {
             Self::Owned => {
                 Ok(NewPageIter::Alloced {
-                    allocation: allocator.alloc(PageLayout::from_size_align(page_count * PAGE_SIZE, PAGE_SIZE).unwrap())
-                        .ok_or(SysErr::OutOfMem)?,
+                    allocation: allocator.try_alloc(PageLayout::from_size_align(page_count * PAGE_SIZE, PAGE_SIZE).unwrap())?,
                     allocator,
                     offset: 0,
                 })
             },
             Self::OwnedZeroed => {
-                let mut allocation = allocator.alloc(PageLayout::from_size_align(page_count * PAGE_SIZE, PAGE_SIZE).unwrap())
-                    .ok_or(SysErr::OutOfMem)?;
+                let mut allocation = allocator.try_alloc(PageLayout::from_size_align(page_count * PAGE_SIZE, PAGE_SIZE).unwrap())?;
                 unsafe {
                     allocation.zero();
                 }
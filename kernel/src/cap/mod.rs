@@ -4,7 +4,9 @@ use crate::prelude::*;
 pub mod address_space;
 pub mod capability_space;
 pub mod channel;
+pub mod debug;
 pub mod drop_check;
+pub mod io_port;
 pub mod key;
 pub mod memory;
 
@@ -148,6 +150,14 @@ impl<T: CapObject> Capability<T> {
     pub fn is_weak(&self) -> bool {
         matches!(self, Self::Weak(_))
     }
+
+    /// Returns a pointer to the object this capability references, used to check which object a capability is for
+    pub fn object_ptr(&self) -> *const T {
+        match self {
+            Self::Strong(cap) => Arc::as_ptr(&cap.object),
+            Self::Weak(cap) => cap.object.as_ptr(),
+        }
+    }
 }
 
 impl<T: CapObject> Clone for Capability<T> {
@@ -9,6 +9,22 @@ pub const TIMER_PERIOD: Duration = Duration::from_millis(2);
 /// How long the scheduler will wait before switching threads
 pub const SCHED_TIME: Duration = Duration::from_millis(10);
 
+/// How many times ready threads can be passed over for higher priority threads before one of them is run anyway
+pub const SCHED_AGING_THRESHOLD: usize = 8;
+
+/// Maximum number of threads which can be blocked sending on 1 channel at once
+/// 
+/// The limit for async senders is chosen when the channel is created
+pub const CHANNEL_SYNC_SENDER_LIMIT: usize = 1024;
+
+/// Size in bytes of the ring buffer which stores recent kernel log messages
+pub const KLOG_SIZE: usize = 256 * 1024;
+
+/// How many work items fit in each cpu's deferred work ring before deferring work has to lock and allocate
+///
+/// This must be a power of 2
+pub const DEFERRED_WORK_RING_SIZE: usize = 64;
+
 static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
 
 pub fn set_cpu_count(cpu_count: usize) {
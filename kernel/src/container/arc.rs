@@ -95,6 +95,28 @@ impl<T> Arc<T> {
         unsafe { Ok(Self::from_ptr(ptr)) }
     }
 
+    /// Like [`Arc::new`], but gives `data` back along with the error if the arc could not be allocated
+    pub fn try_new(data: T, mut allocer: HeapRef) -> Result<Self, (T, SysErr)> {
+        let layout = Layout::new::<ArcInner<T>>();
+
+        let ptr = match allocer.try_alloc(layout) {
+            Ok(allocation) => allocation.as_mut_ptr() as *mut ArcInner<T>,
+            Err(error) => return Err((data, error)),
+        };
+
+        unsafe {
+            ptr.write(ArcInner {
+                strong: AtomicUsize::new(1),
+                weak: AtomicUsize::new(1),
+                allocer,
+                layout: Some(layout),
+                data,
+            });
+
+            Ok(Self::from_ptr(ptr))
+        }
+    }
+
     pub fn into_inner(this: Self) -> Option<T> {
         // Make sure that the ordinary `Drop` implementation isn’t called as well
         let this = core::mem::ManuallyDrop::new(this);
@@ -194,6 +216,11 @@ impl<T: ?Sized> Weak<T> {
         &self.inner().data as *const T as *mut T
     }
 
+    /// Returns the number of strong references to the object, which is 0 once it has been dropped
+    pub fn strong_count(&self) -> usize {
+        self.inner().strong.load(Ordering::Acquire)
+    }
+
     pub fn upgrade(&self) -> Option<Arc<T>> {
         let mut strong_count = self.inner().strong.load(Ordering::Relaxed);
 
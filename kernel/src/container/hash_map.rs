@@ -1,5 +1,5 @@
 use core::hash::{Hash, Hasher};
-use core::iter::FusedIterator;
+use core::iter::{Chain, FusedIterator};
 use core::ops::{Index, IndexMut};
 use core::slice;
 use core::fmt;
@@ -10,150 +10,294 @@ use super::{vec, Vec};
 use crate::alloc::HeapRef;
 use crate::prelude::*;
 
+/// The smallest number of cells a non empty table will have
+const MIN_TABLE_SIZE: usize = 8;
+
+/// Number of cells of the old table which are migrated on each insert or remove while a resize is in progress
+const MIGRATE_BATCH_SIZE: usize = 8;
+
+/// Returns the maximum number of used cells (occupied or deleted) a table with `table_size` cells can have,
+/// which is a load factor of 0.75
+fn max_load(table_size: usize) -> usize {
+    3 * table_size / 4
+}
+
+/// Returns the number of cells a table needs to hold `capacity` entries
+fn table_size_for(capacity: usize) -> usize {
+    if capacity == 0 {
+        0
+    } else {
+        core::cmp::max(MIN_TABLE_SIZE, (4 * capacity).div_ceil(3).next_power_of_two())
+    }
+}
+
 enum HashMapCell<K, V> {
     Empty,
     Occupied(K, V),
     Deleted,
 }
 
-impl<K, V> HashMapCell<K, V> {
-    fn is_free(&self) -> bool {
-        matches!(self, Self::Empty | Self::Deleted)
-    }
-}
-
 /// Basic hashmap implementation which uses siphash 2-4 and linear open addressing
+/// 
+/// When the table has to grow, the new table is allocated straight away, but entries are moved from the old table
+/// a few cells at a time by each following insert or remove, so no single operation pays for the whole resize.
+/// While a resize is in progress lookups check both tables.
+/// 
+/// Iteration order is unspecified, and may change after any insert or remove.
 pub struct HashMap<K: Hash + Eq, V> {
+    /// The table new entries are inserted into
     data: Vec<HashMapCell<K, V>>,
+    /// The table entries are being migrated out of, empty if no resize is in progress
+    old_data: Vec<HashMapCell<K, V>>,
+    /// Index of the next cell in `old_data` to migrate
+    migrate_index: usize,
+    /// Number of entries in both tables
     len: usize,
+    /// Number of entries still in `old_data`
+    old_len: usize,
+    /// Number of cells in `data` which are occupied or deleted
+    used_cells: usize,
 }
 
 impl<K: Hash + Eq, V> HashMap<K, V> {
     pub fn new(allocer: HeapRef) -> Self {
         HashMap {
-            data: Vec::new(allocer),
+            data: Vec::new(allocer.clone()),
+            old_data: Vec::new(allocer),
+            migrate_index: 0,
             len: 0,
+            old_len: 0,
+            used_cells: 0,
         }
     }
 
+    /// Creates a hashmap which can hold `capacity` entries before it has to grow
     pub fn try_with_capacity(allocer: HeapRef, capacity: usize) -> KResult<Self> {
-        let mut out = HashMap {
-            data: Vec::try_with_capacity(allocer, capacity)?,
+        Ok(HashMap {
+            data: Self::new_table(allocer.clone(), table_size_for(capacity))?,
+            old_data: Vec::new(allocer),
+            migrate_index: 0,
             len: 0,
-        };
+            old_len: 0,
+            used_cells: 0,
+        })
+    }
 
-        for _ in 0..capacity {
-            out.data.push(HashMapCell::Empty)?;
+    fn new_table(allocer: HeapRef, table_size: usize) -> KResult<Vec<HashMapCell<K, V>>> {
+        let mut table = Vec::try_with_capacity(allocer, table_size)?;
+
+        for _ in 0..table_size {
+            table.push(HashMapCell::Empty)?;
         }
 
-        Ok(out)
+        Ok(table)
     }
 
     pub fn len(&self) -> usize {
         self.len
     }
 
+    /// Returns the number of entries the map can hold before it has to grow
     pub fn capacity(&self) -> usize {
-        self.data.capacity()
+        max_load(self.data.len())
     }
 
     pub fn clear(&mut self) {
-        self.data.clear()
+        self.data.clear();
+        self.old_data.clear();
+        self.migrate_index = 0;
+        self.len = 0;
+        self.old_len = 0;
+        self.used_cells = 0;
     }
 
     pub fn iter(&self) -> Iter<K, V> {
-        Iter(self.data.iter())
+        Iter(self.data.iter().chain(self.old_data.iter()))
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<K, V> {
+        IterMut(self.data.iter_mut().chain(self.old_data.iter_mut()))
     }
 
     pub fn into_iter(self) -> IntoIter<K, V> {
-        IntoIter(self.data.into_iter())
+        IntoIter(self.data.into_iter().chain(self.old_data.into_iter()))
     }
 
-    /// Rehashes the hash table if it is past a certain capacity thresh hold
-    fn try_rehash(&mut self) -> KResult<()> {
-        // load factor of 0.75
-        if 4 * self.len > 3 * self.data.len() {
-            let new_map = Self::try_with_capacity(self.data.alloc_ref(), 2 * self.data.len())?;
-            let old_map = core::mem::replace(self, new_map);
+    /// Reserves space for at least `additional` more entries
+    /// 
+    /// If the table has to grow, existing entries are migrated incrementally by later inserts and removes,
+    /// the same as when the table grows during an insert.
+    pub fn reserve(&mut self, additional: usize) -> KResult<()> {
+        let required_cells = self.used_cells
+            .checked_add(self.old_len)
+            .and_then(|cells| cells.checked_add(additional))
+            .ok_or(SysErr::OutOfMem)?;
+
+        if required_cells > max_load(self.data.len()) {
+            self.resize(table_size_for(self.len + additional))?;
+        }
+
+        Ok(())
+    }
+
+    fn is_migrating(&self) -> bool {
+        self.old_data.len() != 0
+    }
+
+    /// Starts moving all entries into a new table with `table_size` cells
+    fn resize(&mut self, table_size: usize) -> KResult<()> {
+        // only 1 resize can be in progress at a time
+        self.finish_migration();
+
+        let new_table = Self::new_table(self.data.alloc_ref(), table_size)?;
+        self.old_data = core::mem::replace(&mut self.data, new_table);
+        self.migrate_index = 0;
+        self.old_len = self.len;
+        self.used_cells = 0;
 
-            for (key, value) in old_map.into_iter() {
-                self.insert(key, value)?;
+        Ok(())
+    }
+
+    /// Migrates up to `cell_count` cells from the old table to the new table
+    fn migrate(&mut self, cell_count: usize) {
+        if !self.is_migrating() {
+            return;
+        }
+
+        let end_index = core::cmp::min(self.migrate_index.saturating_add(cell_count), self.old_data.len());
+
+        for i in self.migrate_index..end_index {
+            // migrated cells are marked deleted so lookups in the old table still probe past them
+            if let HashMapCell::Occupied(key, value) = core::mem::replace(&mut self.old_data[i], HashMapCell::Deleted) {
+                self.old_len -= 1;
+                self.insert_new_entry(key, value);
             }
-        } else if self.data.len() == 0 {
-            // make a size 1 map otherwise things break
-            let new_map = Self::try_with_capacity(self.data.alloc_ref(), 1)?;
-            let _ = core::mem::replace(self, new_map);
         }
+        self.migrate_index = end_index;
 
-        Ok(())
+        if self.migrate_index == self.old_data.len() {
+            self.old_data = Vec::new(self.data.alloc_ref());
+            self.migrate_index = 0;
+        }
     }
 
-    fn get_key_start_index(&self, key: &K) -> usize {
+    fn finish_migration(&mut self) {
+        self.migrate(usize::MAX);
+    }
+
+    fn get_key_start_index(key: &K, table_size: usize) -> usize {
         // TODO: use a hash builder with keys for siphash to prevent potential denial of service attacks
         let mut hasher = SipHasher::new();
         key.hash(&mut hasher);
-        (hasher.finish() as usize) % self.data.len()
+        (hasher.finish() as usize) % table_size
     }
 
-    /// Returns the old value if it exists
-    pub fn insert(&mut self, key: K, value: V) -> KResult<Option<V>> {
-        self.try_rehash()?;
-        let mut i = self.get_key_start_index(&key);
+    /// Inserts an entry which is not present in either table into the new table
+    /// 
+    /// The new table must have room for the entry
+    fn insert_new_entry(&mut self, key: K, value: V) {
+        let mut i = Self::get_key_start_index(&key, self.data.len());
         loop {
-            if self.data[i].is_free() {
-                self.data[i] = HashMapCell::Occupied(key, value);
-                self.len += 1;
-                return Ok(None);
+            match self.data[i] {
+                HashMapCell::Empty => {
+                    self.used_cells += 1;
+                    break;
+                },
+                HashMapCell::Deleted => break,
+                HashMapCell::Occupied(..) => i = (i + 1) % self.data.len(),
             }
-            if let HashMapCell::Occupied(ref old_key, _) = self.data[i] && old_key == &key {
-                // this should always match, its just because using a normal let will not allow a fallible pattern
-                if let HashMapCell::Occupied(_, old_value) = core::mem::replace(&mut self.data[i], HashMapCell::Occupied(key, value)) {
-                    return Ok(Some(old_value));
-                } else {
-                    unreachable!();
-                }
+        }
+
+        self.data[i] = HashMapCell::Occupied(key, value);
+    }
+
+    /// Returns the old value if it exists
+    pub fn insert(&mut self, key: K, value: V) -> KResult<Option<V>> {
+        self.migrate(MIGRATE_BATCH_SIZE);
+
+        if let Some(index) = Self::get_index_of_key(&self.data, &key) {
+            if let HashMapCell::Occupied(_, ref mut old_value) = self.data[index] {
+                return Ok(Some(core::mem::replace(old_value, value)));
+            } else {
+                unreachable!();
             }
+        }
+
+        if self.used_cells + self.old_len + 1 > max_load(self.data.len()) {
+            self.resize(table_size_for(2 * (self.len + 1)))?;
+        }
 
-            i = (i + 1) % self.data.len();
+        // the key might still be in the old table, it has to be moved so it is not present in both tables
+        let old_value = self.remove_from_old_table(&key);
+        if old_value.is_none() {
+            self.len += 1;
         }
+
+        self.insert_new_entry(key, value);
+
+        Ok(old_value)
     }
 
-    // gets the index in the data array
-    fn get_index_of_key(&self, key: &K) -> Option<usize> {
+    // gets the index of the key in the given table
+    fn get_index_of_key(table: &[HashMapCell<K, V>], key: &K) -> Option<usize> {
         // prevent divide by 0 in get_key_start_index
-        if self.data.len() == 0 {
+        if table.len() == 0 {
             return None;
         }
 
-        let mut i = self.get_key_start_index(key);
+        let mut i = Self::get_key_start_index(key, table.len());
         let start_i = i;
         loop {
-            if matches!(self.data[i], HashMapCell::Empty) {
+            if matches!(table[i], HashMapCell::Empty) {
                 return None;
             }
-            if let HashMapCell::Occupied(ref current_key, _) = self.data[i] && current_key == key {
+            if let HashMapCell::Occupied(ref current_key, _) = table[i] && current_key == key {
                 return Some(i);
             }
-            i = (i + 1) % self.data.len();
+            i = (i + 1) % table.len();
 
-            // at lower sizes hashmap will be completely full, so this avoids infinite loop
+            // the old table can fill up with deleted cells while migrating, so this avoids infinite loop
             if i == start_i {
                 return None;
             }
         }
     }
 
-    pub fn remove(&mut self, key: &K) -> Option<V> {
-        let i = self.get_index_of_key(key)?;
-        if let HashMapCell::Occupied(_, value) = core::mem::replace(&mut self.data[i], HashMapCell::Deleted) {
+    fn remove_from_old_table(&mut self, key: &K) -> Option<V> {
+        let i = Self::get_index_of_key(&self.old_data, key)?;
+        if let HashMapCell::Occupied(_, value) = core::mem::replace(&mut self.old_data[i], HashMapCell::Deleted) {
+            self.old_len -= 1;
             Some(value)
         } else {
-            None
+            unreachable!();
         }
     }
 
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.migrate(MIGRATE_BATCH_SIZE);
+
+        let value = if let Some(i) = Self::get_index_of_key(&self.data, key) {
+            if let HashMapCell::Occupied(_, value) = core::mem::replace(&mut self.data[i], HashMapCell::Deleted) {
+                value
+            } else {
+                unreachable!();
+            }
+        } else {
+            self.remove_from_old_table(key)?
+        };
+
+        self.len -= 1;
+        Some(value)
+    }
+
     pub fn get(&self, key: &K) -> Option<&V> {
-        if let HashMapCell::Occupied(_, ref value) = self.data[self.get_index_of_key(key)?] {
+        let cell = if let Some(i) = Self::get_index_of_key(&self.data, key) {
+            &self.data[i]
+        } else {
+            &self.old_data[Self::get_index_of_key(&self.old_data, key)?]
+        };
+
+        if let HashMapCell::Occupied(_, value) = cell {
             Some(value)
         } else {
             unreachable!();
@@ -162,8 +306,14 @@ impl<K: Hash + Eq, V> HashMap<K, V> {
 
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
         // do this to make borrow checker happy
-        let index = self.get_index_of_key(key)?;
-        if let HashMapCell::Occupied(_, ref mut value) = self.data[index] {
+        let cell = if let Some(i) = Self::get_index_of_key(&self.data, key) {
+            &mut self.data[i]
+        } else {
+            let i = Self::get_index_of_key(&self.old_data, key)?;
+            &mut self.old_data[i]
+        };
+
+        if let HashMapCell::Occupied(_, value) = cell {
             Some(value)
         } else {
             unreachable!();
@@ -191,7 +341,9 @@ impl<K: Eq + Hash, V> IndexMut<&K> for HashMap<K, V> {
     }
 }
 
-pub struct Iter<'a, K: Hash + Eq, V>(slice::Iter<'a, HashMapCell<K, V>>);
+type CellIter<'a, K, V> = Chain<slice::Iter<'a, HashMapCell<K, V>>, slice::Iter<'a, HashMapCell<K, V>>>;
+
+pub struct Iter<'a, K: Hash + Eq, V>(CellIter<'a, K, V>);
 
 impl<'a, K: Hash + Eq, V> Iterator for Iter<'a, K, V> {
     type Item = (&'a K, &'a V);
@@ -208,7 +360,9 @@ impl<'a, K: Hash + Eq, V> Iterator for Iter<'a, K, V> {
 
 impl<K: Hash + Eq, V> FusedIterator for Iter<'_, K, V> {}
 
-pub struct IterMut<'a, K: Hash + Eq, V>(slice::IterMut<'a, HashMapCell<K, V>>);
+type CellIterMut<'a, K, V> = Chain<slice::IterMut<'a, HashMapCell<K, V>>, slice::IterMut<'a, HashMapCell<K, V>>>;
+
+pub struct IterMut<'a, K: Hash + Eq, V>(CellIterMut<'a, K, V>);
 
 impl<'a, K: Hash + Eq, V> Iterator for IterMut<'a, K, V> {
     type Item = (&'a K, &'a mut V);
@@ -225,7 +379,7 @@ impl<'a, K: Hash + Eq, V> Iterator for IterMut<'a, K, V> {
 
 impl<K: Hash + Eq, V> FusedIterator for IterMut<'_, K, V> {}
 
-pub struct IntoIter<K: Hash + Eq, V>(vec::IntoIter<HashMapCell<K, V>>);
+pub struct IntoIter<K: Hash + Eq, V>(Chain<vec::IntoIter<HashMapCell<K, V>>, vec::IntoIter<HashMapCell<K, V>>>);
 
 impl<K: Hash + Eq, V> Iterator for IntoIter<K, V> {
     type Item = (K, V);
@@ -241,3 +395,85 @@ impl<K: Hash + Eq, V> Iterator for IntoIter<K, V> {
 }
 
 impl<K: Hash + Eq, V> FusedIterator for IntoIter<K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::root_alloc_ref;
+
+    #[test_case]
+    fn lookups_during_migration() {
+        let mut map = HashMap::new(root_alloc_ref());
+
+        // insert entries until a resize starts which will take several operations to finish
+        let mut key_count = 0;
+        while !(map.is_migrating() && map.old_data.len() >= 4 * MIGRATE_BATCH_SIZE) {
+            map.insert(key_count, key_count * 2).unwrap();
+            key_count += 1;
+        }
+        assert!(map.old_len > MIGRATE_BATCH_SIZE);
+
+        for key in 0..key_count {
+            assert_eq!(map.get(&key), Some(&(key * 2)));
+        }
+        assert_eq!(map.get(&key_count), None);
+        assert_eq!(map.iter().count(), key_count);
+
+        // a resize must not be finished by one operation
+        map.insert(key_count, 0).unwrap();
+        assert!(map.is_migrating());
+
+        *map.get_mut(&0).unwrap() = 1;
+        assert_eq!(map[&0], 1);
+        assert_eq!(map.len(), key_count + 1);
+    }
+
+    #[test_case]
+    fn interleaved_inserts_and_removes_during_migration() {
+        const KEY_COUNT: usize = 512;
+
+        let mut map = HashMap::new(root_alloc_ref());
+        let mut expected = [None; KEY_COUNT];
+        let mut migrating_operations = 0;
+        let mut rng_state: usize = 0x2545f491;
+
+        for i in 0..8 * KEY_COUNT {
+            rng_state = rng_state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let key = (rng_state >> 33) % KEY_COUNT;
+
+            if map.is_migrating() {
+                migrating_operations += 1;
+            }
+
+            // insert more often than remove so the map keeps growing
+            if (rng_state >> 20) % 3 == 0 {
+                assert_eq!(map.remove(&key), expected[key].take());
+            } else {
+                assert_eq!(map.insert(key, i).unwrap(), expected[key].replace(i));
+            }
+        }
+
+        assert!(migrating_operations > 0);
+        assert_eq!(map.len(), expected.iter().flatten().count());
+        for (key, value) in expected.iter().enumerate() {
+            assert_eq!(map.get(&key), value.as_ref());
+        }
+        for (key, value) in map.iter() {
+            assert_eq!(expected[*key], Some(*value));
+        }
+    }
+
+    #[test_case]
+    fn reserve_prevents_resize() {
+        let mut map = HashMap::new(root_alloc_ref());
+        map.reserve(100).unwrap();
+        assert!(map.capacity() >= 100);
+
+        let table_size = map.data.len();
+        for key in 0..100 {
+            map.insert(key, key).unwrap();
+        }
+        assert_eq!(map.data.len(), table_size);
+        assert!(!map.is_migrating());
+    }
+}
\ No newline at end of file
@@ -7,7 +7,7 @@ use core::ptr::NonNull;
 use core::slice::{SliceIndex, Iter, IterMut};
 use core::cmp::max;
 
-use aser::ByteBuf;
+use aser::{AserError, ByteBuf};
 
 use crate::alloc::{HeapRef, root_alloc_ref};
 use crate::prelude::*;
@@ -41,8 +41,7 @@ impl<T> RawVec<T> {
         } else {
             let layout = Layout::array::<T>(cap).unwrap();
             let ptr = allocer
-                .alloc(layout)
-                .ok_or(SysErr::OutOfMem)?
+                .try_alloc(layout)?
                 .cast();
 
             Ok(RawVec {
@@ -88,21 +87,15 @@ impl<T> RawVec<T> {
         assert!(new_layout.size() <= isize::MAX as usize, "Allocation too large");
 
         let new_alloc = if self.cap == 0 {
-            self.allocer.alloc(new_layout)
+            self.allocer.try_alloc(new_layout)?
         } else {
             let old_layout = Layout::array::<T>(self.cap).unwrap();
-            unsafe { self.allocer.realloc(self.ptr.cast(), old_layout, new_layout) }
+            unsafe { self.allocer.try_realloc(self.ptr.cast(), old_layout, new_layout)? }
         };
 
-        // If allocation fails, `new_ptr` will be null, in which case we abort.
-        match new_alloc {
-            Some(ptr) => {
-                self.ptr = ptr.as_non_null_ptr().cast();
-                self.cap = new_cap;
-                Ok(())
-            },
-            None => Err(SysErr::OutOfMem),
-        }
+        self.ptr = new_alloc.as_non_null_ptr().cast();
+        self.cap = new_cap;
+        Ok(())
     }
 }
 
@@ -402,14 +395,12 @@ impl<T> Default for Vec<T> {
 }
 
 impl ByteBuf for Vec<u8> {
-    fn push(&mut self, byte: u8) {
-        self.push(byte)
-            .expect("failed to push byte to byte buffer");
+    fn push(&mut self, byte: u8) -> Result<(), AserError> {
+        self.push(byte).or(Err(AserError::OutOfMemory))
     }
 
-    fn extend_from_slice(&mut self, slice: &[u8]) {
-        self.extend_from_slice(slice)
-            .expect("failed to extend bytebuf from slice");
+    fn extend_from_slice(&mut self, slice: &[u8]) -> Result<(), AserError> {
+        self.extend_from_slice(slice).or(Err(AserError::OutOfMemory))
     }
 
     fn as_slice(&mut self) -> &mut [u8] {
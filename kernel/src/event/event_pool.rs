@@ -1,6 +1,6 @@
 use core::cmp::{max, min};
 
-use sys::{CapType, CapId, EventId, MESSAGE_RECIEVED_NUM};
+use sys::{CapType, CapId, Event, EventId, MESSAGE_RECIEVED_HEADER, EVENTS_DROPPED_HEADER, NO_SENDER_KEY_ID, EVENT_COUNT_OFFSET};
 
 use crate::alloc::{PaRef, HeapRef};
 use crate::cap::address_space::{MappingId, AddressSpaceInner, AddrSpaceMapping};
@@ -8,7 +8,7 @@ use crate::cap::memory::{MemoryCopySrc, MemoryWriter};
 use crate::prelude::*;
 use crate::sched::{ThreadRef, WakeReason};
 use crate::sync::IMutex;
-use crate::container::{Arc, Weak};
+use crate::container::{Arc, Weak, HashMap};
 use crate::cap::{CapObject, address_space::{AddressSpace, EventPoolMapping as AddrSpaceEventPoolMapping}, memory::{MemoryWriteRegion, WriteResult, Page}};
 use crate::vmem_manager::{MapAction, PageMappingOptions};
 use crate::cap::channel::{CapabilityTransferInfo, CapabilityWriter};
@@ -19,6 +19,8 @@ pub enum AwaitStatus {
     /// There were events in the event pool and they have now been mapped
     Success {
         event_range: UVirtRange,
+        /// Number of events in `event_range`
+        event_count: usize,
     },
     /// There were no events in the event pool and the thread must block
     Block,
@@ -42,7 +44,8 @@ impl EventPool {
                 waiting_thread: None,
                 mapped_buffer: EventBuffer::new(page_allocator.clone(), heap_allocator.clone(), max_size)?,
                 is_buffer_mapped: true,
-                write_buffer: EventBuffer::new(page_allocator, heap_allocator, max_size)?,
+                write_buffer: EventBuffer::new(page_allocator, heap_allocator.clone(), max_size)?,
+                dropped_events: HashMap::new(heap_allocator),
             }),
             id: MappingId::new(),
             max_size,
@@ -70,10 +73,12 @@ impl EventPool {
             return Err(SysErr::InvlOp);
         }
 
+        inner.write_dropped_events();
+
         if inner.has_unprocessed_events() {
-            let event_range = inner.swap_buffers()?;
+            let (event_range, event_count) = inner.swap_buffers()?;
 
-            Ok(AwaitStatus::Success { event_range })
+            Ok(AwaitStatus::Success { event_range, event_count })
         } else {
             // wait for event to arrive
             let thread_ref = ThreadRef::future_ref(&cpu_local_data().current_thread());
@@ -83,15 +88,29 @@ impl EventPool {
         }
     }
 
-    /// Writes the event id and event data into this event pool, and potentially wakes a waiting thread
-    pub fn write_event<T: MemoryCopySrc + ?Sized>(&self, event_data: &T) -> KResult<Size> {
+    /// Writes the event into this event pool, and potentially wakes a waiting thread
+    /// 
+    /// If the event pool is full, the event is counted as dropped and reported to the consumer once there is space.
+    /// 
+    /// # Returns
+    /// 
+    /// The number of bytes written, which is 0 if the event was coalesced with an earlier event
+    pub fn write_event(&self, event: Event) -> KResult<Size> {
         let mut inner = self.inner.lock();
 
+        // older drops are reported before newer events
+        inner.write_dropped_events();
+
         // safety: the write buffer is not mapped
-        let write_size = unsafe {
-            inner.write_buffer.write_event(event_data)?
+        let write_result = unsafe {
+            inner.write_buffer.write_event(event)
         };
 
+        if write_result == Err(SysErr::EventPoolFull) {
+            inner.count_dropped_event(event.event_id);
+        }
+        let write_size = write_result?;
+
         inner.wake_listener()?;
 
         Ok(write_size)
@@ -104,6 +123,7 @@ impl EventPool {
         &self,
         event_id: EventId,
         reply_cap_id: Option<CapId>,
+        sender_key_id: Option<u64>,
         event_data: &T,
         cap_transfer_info: CapabilityTransferInfo,
     ) -> KResult<Size> {
@@ -111,10 +131,16 @@ impl EventPool {
 
         // safety: the write buffer is not mapped
         unsafe {
-            inner.write_buffer.write_channel_event(event_id, reply_cap_id, event_data, cap_transfer_info)
+            inner.write_buffer.write_channel_event(event_id, reply_cap_id, sender_key_id, event_data, cap_transfer_info)
         }
     }
 
+    /// Returns the number of event records written since userspace last took the events
+    #[cfg(test)]
+    pub fn unprocessed_event_count(&self) -> usize {
+        self.inner.lock().write_buffer.event_count
+    }
+
     /// Wakes a thread if it is waiting on the event pool
     pub fn wake_listener(&self) -> KResult<()> {
         self.inner.lock().wake_listener()
@@ -177,6 +203,8 @@ struct EventPoolInner {
     is_buffer_mapped: bool,
     /// The event buffer where new events will be written, currentyl unmapped
     write_buffer: EventBuffer,
+    /// Number of events dropped for each event id because the event pool was full, which have not been reported yet
+    dropped_events: HashMap<EventId, usize>,
 }
 
 impl EventPoolInner {
@@ -184,11 +212,36 @@ impl EventPoolInner {
         self.write_buffer.current_event_offset > 0
     }
 
+    fn count_dropped_event(&mut self, event_id: EventId) {
+        if let Some(count) = self.dropped_events.get_mut(&event_id) {
+            *count += 1;
+        } else {
+            // if this can't be allocated the drop is not reported, there is nothing else that can be done
+            let _ = self.dropped_events.insert(event_id, 1);
+        }
+    }
+
+    /// Writes a record for each event id with dropped events into the write buffer, until it is full
+    fn write_dropped_events(&mut self) {
+        while let Some((&event_id, &count)) = self.dropped_events.iter().next() {
+            // safety: the write buffer is not mapped
+            let write_result = unsafe {
+                self.write_buffer.write_dropped_events(event_id, count)
+            };
+
+            if write_result.is_err() {
+                return;
+            }
+
+            self.dropped_events.remove(&event_id);
+        }
+    }
+
     /// If a thread is waiting on this event pool, wakes that thread and swaps buffers
     fn wake_listener(&mut self) -> KResult<()> {
         if let Some(thread) = self.waiting_thread.take() {
-            let event_range = self.swap_buffers()?;
-            thread.move_to_ready_list(WakeReason::EventPoolEventRecieved { event_range });
+            let (event_range, event_count) = self.swap_buffers()?;
+            thread.move_to_ready_list(WakeReason::EventPoolEventRecieved { event_range, event_count });
         }
 
         Ok(())
@@ -196,8 +249,8 @@ impl EventPoolInner {
 
     /// Swaps the buffers so unprocessed events can be processed
     /// 
-    /// Returns a virt range representing the new memory range of valid events
-    fn swap_buffers(&mut self) -> KResult<UVirtRange> {
+    /// Returns a virt range representing the new memory range of valid events, and the number of events in that range
+    fn swap_buffers(&mut self) -> KResult<(UVirtRange, usize)> {
         let (addr_space, map_addr) = self.get_mapping_info()
             .ok_or(SysErr::InvlOp)?;
 
@@ -208,6 +261,7 @@ impl EventPoolInner {
 
         // map new memory
         let event_size = Size::from_bytes(self.write_buffer.current_event_offset);
+        let event_count = self.write_buffer.event_count;
         // aligns size up
         let map_page_count = event_size.as_aligned().pages_rounded();
 
@@ -236,7 +290,7 @@ impl EventPoolInner {
 
         core::mem::swap(&mut self.mapped_buffer, &mut self.write_buffer);
 
-        Ok(UVirtRange::new(map_addr, event_size.bytes()))
+        Ok((UVirtRange::new(map_addr, event_size.bytes()), event_count))
     }
 
     /// Unmaps the currently mapped buffer if it is mapped
@@ -247,16 +301,17 @@ impl EventPoolInner {
             .ok_or(SysErr::InvlOp)?.mapped_address;
 
         if self.is_buffer_mapped {
-            for i in 0..self.mapped_buffer.pages.len() {
-                unsafe {
-                    addr_space.addr_space.unmap_page(map_addr + PAGE_SIZE * i)
-                        .expect("tried to unmap event buffer page which was not mapped");
-                }
-            }
+            let page_count = self.mapped_buffer.pages.len();
+            let unmapped_pages = unsafe {
+                addr_space.addr_space.unmap_range(AVirtRange::new(map_addr, page_count * PAGE_SIZE))
+            };
+            assert_eq!(unmapped_pages, page_count, "tried to unmap event buffer page which was not mapped");
 
             self.is_buffer_mapped = false;
         }
         self.mapped_buffer.current_event_offset = 0;
+        self.mapped_buffer.event_count = 0;
+        self.mapped_buffer.coalesced_events.clear();
 
         Ok(())
     }
@@ -284,6 +339,10 @@ struct EventBuffer {
     page_allocator: PaRef,
     /// Offset in memory of the top fo the stack, this is kept 8 byte aligned
     current_event_offset: usize,
+    /// Number of records written below `current_event_offset`
+    event_count: usize,
+    /// Offset of the record of each coalescable event id in this buffer, later events with that id increment its count
+    coalesced_events: HashMap<EventId, usize>,
     /// Maximum size event buffer is allowed to grow to
     max_size: Size,
 }
@@ -291,9 +350,11 @@ struct EventBuffer {
 impl EventBuffer {
     pub fn new(page_allocator: PaRef, heap_allocator: HeapRef, max_size: Size) -> KResult<Self> {
         Ok(EventBuffer {
-            pages: Vec::new(heap_allocator),
+            pages: Vec::new(heap_allocator.clone()),
             page_allocator,
             current_event_offset: 0,
+            event_count: 0,
+            coalesced_events: HashMap::new(heap_allocator),
             max_size,
         })
     }
@@ -324,18 +385,21 @@ impl EventBuffer {
 
     /// Ensures the event buffer has enough capacity to write `write_size` more bytes in the event buffer
     /// 
+    /// Grows the buffer if needed, and returns [`SysErr::EventPoolFull`] if it would have to grow past its max size
+    /// 
     /// # Safety
     /// 
     /// this event buffer must not be mapped
     pub unsafe fn ensure_capacity(&mut self, write_size: usize) -> KResult<()> {
         let required_capacity = align_up(self.current_event_offset + write_size, PAGE_SIZE);
         if required_capacity > self.max_size.bytes() {
-            return Err(SysErr::OutOfCapacity);
+            return Err(SysErr::EventPoolFull);
         }
 
         let current_capacity = self.current_capacity().bytes();
 
-        if write_size > current_capacity {
+        if required_capacity > current_capacity {
+            // grow geometrically so a burst of events doesn't allocate on every write
             let new_size = max(
                 2 * current_capacity,
                 required_capacity,
@@ -369,13 +433,74 @@ impl EventBuffer {
         })
     }
 
-    /// Writes the event into this buffer
+    /// Returns a pointer to the word at `offset` in this buffer, `offset` must be 8 byte aligned and below `current_event_offset`
+    fn word_ptr(&self, offset: usize) -> *mut usize {
+        let page = &self.pages[offset / PAGE_SIZE];
+
+        // safety: an aligned word never crosses a page boundary
+        unsafe {
+            page.allocation().as_mut_ptr::<u8>().add(offset % PAGE_SIZE) as *mut usize
+        }
+    }
+
+    /// Writes the event into this buffer, or increments the count of an earlier record if the event is coalescable
+    /// 
+    /// # Safety
+    /// 
+    /// This event buffer must not be mapped
+    pub unsafe fn write_event(&mut self, event: Event) -> KResult<Size> {
+        let coalescable = event.event_data.is_coalescable();
+
+        if coalescable {
+            if let Some(&record_offset) = self.coalesced_events.get(&event.event_id) {
+                // safety: the record was written into this buffer, and the buffer is not mapped
+                unsafe {
+                    *self.word_ptr(record_offset + EVENT_COUNT_OFFSET) += 1;
+                }
+
+                return Ok(Size::zero());
+            }
+        }
+
+        let record_offset = self.current_event_offset;
+        let event_raw = event.as_raw();
+
+        // safety: caller ensures this buffer is not mapped
+        let write_size = unsafe {
+            self.write_record(event_raw.as_bytes())?
+        };
+
+        if coalescable {
+            // if this can't be allocated later events are just not coalesced
+            let _ = self.coalesced_events.insert(event.event_id, record_offset);
+        }
+
+        Ok(write_size)
+    }
+
+    /// Writes a record saying `count` events with `event_id` were dropped
     /// 
     /// # Safety
     /// 
     /// This event buffer must not be mapped
-    // FIXME: report when memory region is exhausted, and no more data could be written
-    pub unsafe fn write_event<T: MemoryCopySrc + ?Sized>(&mut self, event_data: &T) -> KResult<Size> {
+    pub unsafe fn write_dropped_events(&mut self, event_id: EventId, count: usize) -> KResult<Size> {
+        let mut record = [0u8; 3 * size_of::<usize>()];
+        record[..8].copy_from_slice(&EVENTS_DROPPED_HEADER.to_le_bytes());
+        record[8..16].copy_from_slice(&event_id.as_u64().to_le_bytes());
+        record[16..].copy_from_slice(&count.to_le_bytes());
+
+        // safety: caller ensures this buffer is not mapped
+        unsafe {
+            self.write_record(&record[..])
+        }
+    }
+
+    /// Writes the bytes of a record into this buffer
+    /// 
+    /// # Safety
+    /// 
+    /// This event buffer must not be mapped
+    unsafe fn write_record<T: MemoryCopySrc + ?Sized>(&mut self, event_data: &T) -> KResult<Size> {
         let desired_write_size = align_up(event_data.size(), size_of::<usize>());
 
         // safety: caller ensures this buffer is not mapped
@@ -386,6 +511,7 @@ impl EventBuffer {
         let actual_write_size = event_data.copy_to(&mut writer)?;
 
         self.current_event_offset += align_up(actual_write_size.bytes(), size_of::<usize>());
+        self.event_count += 1;
 
         Ok(actual_write_size)
     }
@@ -395,15 +521,16 @@ impl EventBuffer {
     /// # Safety
     /// 
     /// This event buffer must not be mapped
-    // FIXME: report when memory region is exhausted, and no more data could be written
     pub unsafe fn write_channel_event<T: MemoryCopySrc + ?Sized>(
         &mut self,
         event_id: EventId,
         reply_cap_id: Option<CapId>,
+        sender_key_id: Option<u64>,
         event_data: &T,
         cap_transfer_info: CapabilityTransferInfo,
     ) -> KResult<Size> {
-        let desired_write_size = 4 * size_of::<usize>() // 1 word for tag, 1 for event id, 1 for reply capid, 1 for data size
+        // 1 word for header, 1 for event id, 1 for count, 1 for reply capid, 1 for sender key id, 1 for data size
+        let desired_write_size = 6 * size_of::<usize>()
             + align_up(event_data.size(), size_of::<usize>());
 
         // safety: caller ensures this buffer is not mapped
@@ -419,11 +546,14 @@ impl EventBuffer {
             Ok(())
         };
 
-        write_usize(MESSAGE_RECIEVED_NUM)?;
+        write_usize(MESSAGE_RECIEVED_HEADER)?;
         write_usize(event_id.as_u64() as usize)?;
+        // messages are never coalesced
+        write_usize(1)?;
 
         let cap_id = reply_cap_id.unwrap_or(CapId::null()).into();
         write_usize(cap_id)?;
+        write_usize(sender_key_id.map_or(NO_SENDER_KEY_ID, |key_id| key_id as usize))?;
 
         let (Some(write_size_ptr), ptr_write_size) = inner_writer.push_usize_ptr()? else {
             // panic safety: get writer ensures the writer is big enough
@@ -441,6 +571,7 @@ impl EventBuffer {
         }
 
         self.current_event_offset += align_up(actual_write_size.bytes(), size_of::<usize>());
+        self.event_count += 1;
 
         Ok(actual_write_size)
     }
@@ -499,4 +630,157 @@ impl MemoryWriter for EventBufferWriter<'_> {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use sys::{EventData, EventParser, EventParseResult, InterruptTrigger, PageFault};
+
+    use super::*;
+    use crate::alloc::{root_alloc_page_ref, root_alloc_ref};
+
+    fn write_buffer_capacity(event_pool: &EventPool) -> Size {
+        event_pool.inner.lock().write_buffer.current_capacity()
+    }
+
+    fn write_raw(event_pool: &EventPool, data: &[u8]) -> KResult<Size> {
+        // safety: the write buffer is not mapped
+        unsafe {
+            event_pool.inner.lock().write_buffer.write_record(data)
+        }
+    }
+
+    fn interrupt_trigger(event_id: u64) -> Event {
+        Event {
+            event_data: EventData::InterruptTrigger(InterruptTrigger),
+            event_id: EventId::from_u64(event_id),
+        }
+    }
+
+    fn page_fault(event_id: u64) -> Event {
+        Event {
+            event_data: EventData::PageFault(PageFault {
+                address: 0,
+                access: 0,
+                thread_id: 0,
+            }),
+            event_id: EventId::from_u64(event_id),
+        }
+    }
+
+    /// Calls `f` with a parser over the records in the write buffer
+    fn parse_write_buffer(event_pool: &EventPool, f: impl FnOnce(EventParser<'_>)) {
+        let inner = event_pool.inner.lock();
+        let write_buffer = &inner.write_buffer;
+        assert!(write_buffer.current_event_offset <= PAGE_SIZE);
+
+        // safety: the records were written into the first page, and the buffer is locked so they can't change
+        let records = unsafe {
+            core::slice::from_raw_parts(
+                write_buffer.pages[0].allocation().as_mut_ptr::<u8>(),
+                write_buffer.current_event_offset,
+            )
+        };
+
+        f(EventParser::new(records));
+    }
+
+    #[test_case]
+    fn event_pool_grows_until_max_size() {
+        let event_pool = EventPool::new(root_alloc_page_ref(), root_alloc_ref(), Size::from_pages(4)).unwrap();
+        let event = [0xaa; PAGE_SIZE / 4];
+
+        assert_eq!(write_buffer_capacity(&event_pool), Size::zero());
+
+        for _ in 0..4 {
+            write_raw(&event_pool, &event).unwrap();
+        }
+        assert_eq!(write_buffer_capacity(&event_pool), Size::from_pages(1));
+
+        // the next write no longer fits in the first page
+        write_raw(&event_pool, &event).unwrap();
+        assert_eq!(write_buffer_capacity(&event_pool), Size::from_pages(2));
+
+        for _ in 0..11 {
+            write_raw(&event_pool, &event).unwrap();
+        }
+        assert_eq!(write_buffer_capacity(&event_pool), Size::from_pages(4));
+
+        assert_eq!(write_raw(&event_pool, &event), Err(SysErr::EventPoolFull));
+        // a failed write does not use up any space
+        let inner = event_pool.inner.lock();
+        assert_eq!(inner.write_buffer.current_event_offset, 4 * PAGE_SIZE);
+        assert_eq!(inner.write_buffer.event_count, 16);
+    }
+
+    #[test_case]
+    fn coalescable_events_share_a_record() {
+        let event_pool = EventPool::new(root_alloc_page_ref(), root_alloc_ref(), Size::from_pages(1)).unwrap();
+
+        for _ in 0..3 {
+            event_pool.write_event(interrupt_trigger(1)).unwrap();
+        }
+        event_pool.write_event(interrupt_trigger(2)).unwrap();
+        // page faults are not coalesced
+        event_pool.write_event(page_fault(3)).unwrap();
+        event_pool.write_event(page_fault(3)).unwrap();
+        event_pool.write_event(interrupt_trigger(1)).unwrap();
+
+        assert_eq!(event_pool.inner.lock().write_buffer.event_count, 4);
+
+        parse_write_buffer(&event_pool, |mut parser| {
+            let mut next_count = |event_id| match parser.next() {
+                Some(EventParseResult::Event { event, count }) if event.event_id.as_u64() == event_id => count,
+                other => panic!("unexpected record {:?}", other),
+            };
+
+            assert_eq!(next_count(1), 4);
+            assert_eq!(next_count(2), 1);
+            assert_eq!(next_count(3), 1);
+            assert_eq!(next_count(3), 1);
+            assert!(parser.next().is_none());
+        });
+    }
+
+    #[test_case]
+    fn dropped_events_are_reported_once_there_is_space() {
+        let event_pool = EventPool::new(root_alloc_page_ref(), root_alloc_ref(), Size::from_pages(1)).unwrap();
+
+        let filler = [0xaa; PAGE_SIZE];
+        write_raw(&event_pool, &filler).unwrap();
+
+        assert_eq!(event_pool.write_event(page_fault(1)), Err(SysErr::EventPoolFull));
+        assert_eq!(event_pool.write_event(page_fault(1)), Err(SysErr::EventPoolFull));
+        assert_eq!(event_pool.write_event(interrupt_trigger(2)), Err(SysErr::EventPoolFull));
+
+        {
+            // make space as if the consumer had recieved the filler
+            let mut inner = event_pool.inner.lock();
+            inner.write_buffer.current_event_offset = 0;
+            inner.write_buffer.event_count = 0;
+        }
+
+        event_pool.write_event(page_fault(3)).unwrap();
+
+        parse_write_buffer(&event_pool, |parser| {
+            let mut dropped = [0; 3];
+            let mut saw_event = false;
+
+            for record in parser {
+                match record {
+                    EventParseResult::EventsDropped { event_id, count } => {
+                        assert!(!saw_event, "drops were reported after a newer event");
+                        dropped[event_id.as_u64() as usize] = count;
+                    },
+                    EventParseResult::Event { event, count: 1 } if event.event_id.as_u64() == 3 => saw_event = true,
+                    other => panic!("unexpected record {:?}", other),
+                }
+            }
+
+            assert!(saw_event);
+            assert_eq!(dropped, [0, 2, 1]);
+        });
+
+        assert_eq!(event_pool.inner.lock().dropped_events.len(), 0);
+    }
+}
@@ -1,9 +1,11 @@
+use core::ops::Range;
+
 use sys::{Event, EventId, EventData};
 use bit_utils::Size;
 
 use crate::prelude::*;
 use crate::container::Weak;
-use crate::cap::memory::{Memory, MemoryCopySrc, MemoryWriter, PlainMemoryCopySrc};
+use crate::cap::memory::{Memory, MemoryInner, MemoryCopySrc, MemoryWriter, PlainMemoryCopySrc, PlainMemoryWriter};
 use crate::cap::channel::{CapabilityWriter, CapabilityTransferInfo};
 use crate::container::Arc;
 
@@ -31,6 +33,11 @@ impl WeakUserspaceBuffer {
     }
 }
 
+/// A range of a memory capability which userspace passed to the kernel to read or write messages
+/// 
+/// The memory can be resized after the buffer is created, so the range is checked against the size
+/// of the memory again every time the buffer is copied to or from. All copies hold the memory's inner
+/// lock for their whole duration, so the pages can't be removed while a copy is in progress.
 #[derive(Debug, Clone)]
 pub struct UserspaceBuffer {
     pub memory: Arc<Memory>,
@@ -55,6 +62,38 @@ impl UserspaceBuffer {
         }
     }
 
+    /// Returns the range of bytes in the memory this buffer refers to
+    /// 
+    /// Fails with [`SysErr::InvlBuffer`] if the end of the buffer overflows
+    pub fn range(&self) -> KResult<Range<usize>> {
+        let end = self.offset.checked_add(self.buffer_size).ok_or(SysErr::InvlBuffer)?;
+
+        Ok(self.offset..end)
+    }
+
+    /// Checks that the buffer currently lies entirely inside of its memory
+    pub fn validate(&self) -> KResult<()> {
+        let range = self.range()?;
+
+        if range.end > self.memory.inner_read().size().bytes() {
+            Err(SysErr::InvlBuffer)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Creates a writer over this buffer in the already locked memory
+    /// 
+    /// Fails with [`SysErr::InvlPerm`] if the memory is sealed
+    fn create_writer<'a>(&self, memory_lock: &'a mut MemoryInner) -> KResult<PlainMemoryWriter<'a>> {
+        if memory_lock.is_sealed() {
+            return Err(SysErr::InvlPerm);
+        }
+
+        memory_lock.create_memory_writer(self.range()?)
+            .ok_or(SysErr::InvlBuffer)
+    }
+
     /// Writes into the userspace buffer
     /// 
     /// # Returns
@@ -62,8 +101,9 @@ impl UserspaceBuffer {
     /// Number of bytes written
     pub fn copy_from<T: MemoryCopySrc + ?Sized>(&self, src: &T) -> KResult<Size> {
         let mut memory_lock = self.memory.inner_write();
+        let mut output_writer = self.create_writer(&mut memory_lock)?;
 
-        memory_lock.copy_from(self.offset..(self.offset + self.buffer_size), src)
+        src.copy_to(&mut output_writer)
     }
 
     /// Like [`copy_from_buffer`], but also copies capabilties based on the data in the src buffer
@@ -73,9 +113,7 @@ impl UserspaceBuffer {
         cap_transfer_info: CapabilityTransferInfo,
     ) -> KResult<Size> {
         let mut memory_lock = self.memory.inner_write();
-        let output_writer = memory_lock.create_memory_writer(
-            self.offset..(self.offset + self.buffer_size),
-        ).ok_or(SysErr::InvlMemZone)?;
+        let output_writer = self.create_writer(&mut memory_lock)?;
 
         let mut capability_writer = CapabilityWriter::new(
             cap_transfer_info,
@@ -94,7 +132,7 @@ impl MemoryCopySrc for UserspaceBuffer {
     fn copy_to(&self, writer: &mut impl MemoryWriter) -> KResult<Size> {
         let mut memory_lock = self.memory.inner_write();
 
-        let Some(memory_writer) = memory_lock.create_memory_writer(self.offset..(self.offset + self.buffer_size)) else {
+        let Ok(memory_writer) = self.create_writer(&mut memory_lock) else {
             // buffer no longer maps to valid region, so no bytes can be written
             // currently not really considered an error
             return Ok(Size::zero());
@@ -115,11 +153,9 @@ impl EventPoolListenerRef {
     pub fn write_event(&self, event_data: EventData) -> KResult<Size> {
         let event_pool = self.event_pool.upgrade().ok_or(SysErr::InvlWeak)?;
 
-        let event = Event {
+        event_pool.write_event(Event {
             event_data,
             event_id: self.event_id,
-        }.as_raw();
-
-        event_pool.write_event(event.as_bytes())
+        })
     }
 }
\ No newline at end of file
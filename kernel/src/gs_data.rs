@@ -1,15 +1,18 @@
-use core::sync::atomic::{AtomicUsize, Ordering, AtomicU64};
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering, AtomicU64, AtomicPtr};
 
 use spin::Once;
 
-use crate::alloc::root_alloc_ref;
+use crate::alloc::{root_alloc_ref, HeapCache};
 use crate::arch::x64::{gs_addr, wrmsr, GSBASEK_MSR, GSBASE_MSR};
 use crate::container::{Arc, Box};
 use crate::gdt::{Gdt, Tss};
 use crate::int::apic::LocalApic;
 use crate::int::idt::Idt;
 use crate::sync::{IMutex, IMutexGuard};
-use crate::sched::{SchedState, PostSwitchData, Thread};
+#[cfg(feature = "lock-tracking")]
+use crate::sync::lock_tracking::HeldLocks;
+use crate::sched::{SchedState, PostSwitchData, Thread, StraceSettings, DeferredWorkQueue};
 
 crate::make_id_type!(Prid);
 
@@ -27,6 +30,11 @@ pub struct GsData {
     /// 
     /// This is switched when switching to a different thread
     pub syscall_rsp: AtomicUsize,
+    /// Strace settings of the current thread, this is null before the scheduler is initialized
+    /// 
+    /// This is a raw pointer so the syscall entry path can check if strace is enabled without taking the `sched_state` lock,
+    /// it is valid as long as the current thread is alive, which is for the duration of any syscall
+    current_strace: AtomicPtr<StraceSettings>,
     /// Id of the current processor
     pub prid: Prid,
     /// Interrupt descriptor table for current cpu
@@ -42,10 +50,21 @@ pub struct GsData {
 
     /// The last time a thread switch occured
     pub last_thread_switch_nsec: AtomicU64,
+    /// Time stamp counter value at the last thread switch, used to account the cpu time of each thread
+    pub last_thread_switch_tsc: AtomicU64,
     /// Stores the current process and thread
     pub sched_state: Once<IMutex<SchedState>>,
     /// Stores the post switch action to be completed after switching threads
     pub post_switch_data: IMutex<Option<PostSwitchData>>,
+    /// Work deferred by interrupt handlers on this cpu, run by this cpu's deferred work worker
+    pub deferred_work: DeferredWorkQueue,
+
+    /// Cache of small heap allocations for the current cpu
+    pub heap_cache: HeapCache,
+
+    /// Tracked locks held by the current cpu
+    #[cfg(feature = "lock-tracking")]
+    pub held_locks: HeldLocks,
 }
 
 impl GsData {
@@ -69,6 +88,18 @@ impl GsData {
         self.sched_state.get().expect("sched state not initialized").lock()
     }
 
+    /// Sets the strace settings returned by [`current_strace`](Self::current_strace), this is called when switching threads
+    pub fn set_current_strace(&self, strace: &Arc<StraceSettings>) {
+        self.current_strace.store(Arc::as_ptr(strace) as *mut _, Ordering::Relaxed);
+    }
+
+    /// Returns the strace settings of the current thread
+    #[inline(always)]
+    pub fn current_strace(&self) -> Option<&StraceSettings> {
+        // safety: this points to the strace settings of the current thread, which can't be dropped while it is running
+        unsafe { self.current_strace.load(Ordering::Relaxed).as_ref() }
+    }
+
     /// Returns the current thread
     /// 
     /// # Locking
@@ -84,20 +115,30 @@ pub fn init(prid: Prid) {
     let gs_data = GsData {
         self_addr: AtomicUsize::new(0),
         syscall_rsp: AtomicUsize::new(0),
+        current_strace: AtomicPtr::new(ptr::null_mut()),
         prid,
         idt: Idt::new(),
         gdt: IMutex::new(Gdt::new()),
         tss: IMutex::new(Tss::new()),
         local_apic: Once::new(),
         last_thread_switch_nsec: AtomicU64::new(0),
+        last_thread_switch_tsc: AtomicU64::new(0),
         sched_state: Once::new(),
         post_switch_data: IMutex::new(None),
+        deferred_work: DeferredWorkQueue::new(),
+        heap_cache: HeapCache::new(),
+        #[cfg(feature = "lock-tracking")]
+        held_locks: HeldLocks::new(),
     };
 
     let gs_data = Box::new(gs_data, root_alloc_ref()).expect("Failed to allocate gs data struct");
     gs_data.set_self_addr();
 
     let (ptr, _) = Box::into_raw(gs_data);
+
+    // safety: cpu local data is never freed
+    let gs_data: &'static GsData = unsafe { ptr.as_ref().unwrap() };
+    gs_data.heap_cache.register(prid);
     
     wrmsr(GSBASE_MSR, ptr as u64);
     wrmsr(GSBASEK_MSR, ptr as u64);
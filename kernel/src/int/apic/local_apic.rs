@@ -145,9 +145,14 @@ pub enum IpiDest {
 	OtherLogical(u8),
 }
 
+/// Gets the apic id of the processor with the given processor id
+pub fn prid_to_apic_id(prid: Prid) -> u8 {
+	*LOCAL_APIC_ID_MAP.get().unwrap().get(&prid).unwrap()
+}
+
 impl IpiDest {
 	pub fn to_prid(prid: Prid) -> Self {
-		Self::OtherPhysical(*LOCAL_APIC_ID_MAP.get().unwrap().get(&prid).unwrap())
+		Self::OtherPhysical(prid_to_apic_id(prid))
 	}
 }
 
@@ -408,6 +413,8 @@ impl LocalApic {
 			return nanosec_per_tick;
 		}
 		self.write_reg_32(Self::TIMER_INIT_COUNT, u32::MAX);
+		// the time stamp counter is calibrated over the same interval, it is used for cpu time accounting
+		let start_tsc = rdtsc();
 
 		sti();
 
@@ -419,6 +426,7 @@ impl LocalApic {
 		while !CALIBRATE_FIRED.load(Ordering::Acquire) {
 			core::hint::spin_loop();
 		}
+		calibrate_tsc(rdtsc() - start_tsc, TIMER_CALIBRATE_TIME);
 
 		cli();
 
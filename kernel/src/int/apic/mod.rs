@@ -21,7 +21,7 @@ mod apic_modes;
 mod io_apic;
 mod local_apic;
 
-pub use local_apic::{LocalApic, Ipi, IpiDest};
+pub use local_apic::{LocalApic, Ipi, IpiDest, prid_to_apic_id};
 
 // physical address of the local apic
 static LOCAL_APIC_ADDR: AtomicUsize = AtomicUsize::new(0);
@@ -1,8 +1,15 @@
+use sys::{CapFlags, EventData, ExitReason, FaultAccess, FaultKind, FaultRecord, PageFault};
+
 use crate::arch::x64::asm_user_copy_fail;
+use crate::cap::{Capability, WeakCapability};
 use crate::consts::ASM_USER_COPY_CODE_REGION;
+use crate::container::Arc;
+use crate::event::EventPoolListenerRef;
 use crate::prelude::*;
-use crate::sched;
-use crate::arch::x64::{cli, hlt, get_cr2};
+use crate::sched::{self, ThreadState, PostSwitchAction, WorkItem};
+use crate::syscall::{UserCopyError, copy_from_userspace};
+use crate::arch::x64::{cli, hlt, get_cr2, IntDisable};
+use crate::vmem_manager::tlb;
 
 use userspace_interrupt::{InterruptId, interrupt_manager};
 
@@ -62,6 +69,8 @@ pub const IRQ_APIC_TIMER: u8 = 40;
 // TODO: remove this interrupt type
 pub const IPI_PROCESS_EXIT: u8 = 41;
 pub const IPI_PANIC: u8 = 42;
+pub const IPI_TLB_SHOOTDOWN: u8 = 44;
+pub const IPI_RESCHEDULE: u8 = 45;
 
 // The irq src for the pit
 pub const PIT_IRQ_SRC: u8 = 0;
@@ -113,21 +122,135 @@ fn gp_exception(registers: &Registers) {
     panic!("general protection exception\nregisters:\n{:x?}", registers);
 }
 
+/// Gets the type of access that caused a page fault from the page fault error code
+fn page_fault_access(error_code: u64) -> FaultAccess {
+    if error_code & PAGE_FAULT_EXECUTE != 0 {
+        FaultAccess::Execute
+    } else if error_code & PAGE_FAULT_WRITE != 0 {
+        FaultAccess::Write
+    } else {
+        FaultAccess::Read
+    }
+}
+
+/// Attempts to resolve a page fault on a lazily allocated or copy on write page in the current address space
+fn resolve_user_page_fault(error_code: u64) -> bool {
+    // reserved bit faults mean a page table is corrupted, that is not something that can be resolved
+    if error_code & PAGE_FAULT_RESERVED != 0 {
+        return false;
+    }
+
+    let Some(address) = VirtAddr::try_new(get_cr2()) else {
+        return false;
+    };
+
+    let address_space = cpu_local_data().current_thread().address_space().clone();
+    address_space.handle_page_fault(address, page_fault_access(error_code)).is_ok()
+}
+
+/// Returns true if the page fault occured inside of a guard region of the current address space
+fn is_guard_page_fault() -> bool {
+    let Some(address) = VirtAddr::try_new(get_cr2()) else {
+        return false;
+    };
+
+    cpu_local_data().current_thread().address_space().is_guard_address(address)
+}
+
+/// Kills the current thread after a page fault it can't recover from
+/// 
+/// The registers and stack of the thread are recorded in its thread group, so the parent can see where it faulted.
+fn exit_faulting_thread(fault: FaultKind, registers: &Registers, error_code: u64) -> ! {
+    let current_thread = cpu_local_data().current_thread();
+    current_thread.set_exit_reason(ExitReason::Fault(fault));
+
+    if let Some(thread_group) = current_thread.thread_group().upgrade() {
+        let record = FaultRecord {
+            rax: registers.rax,
+            rbx: registers.rbx,
+            rcx: registers.rcx,
+            rdx: registers.rdx,
+            rbp: registers.rbp,
+            rsp: registers.rsp,
+            rdi: registers.rdi,
+            rsi: registers.rsi,
+            r8: registers.r8,
+            r9: registers.r9,
+            r10: registers.r10,
+            r11: registers.r11,
+            r12: registers.r12,
+            r13: registers.r13,
+            r14: registers.r14,
+            r15: registers.r15,
+            rflags: registers.rflags,
+            rip: registers.rip,
+            fault_address: get_cr2(),
+            error_code: error_code as usize,
+            kind: fault as usize,
+            stack_size: 0,
+        };
+
+        // the stack ends at the first page which can't be read, which also stops the copy at a guard page
+        thread_group.record_fault(record, |stack_buffer| {
+            match copy_from_userspace(stack_buffer, registers.rsp as *const u8) {
+                Ok(()) => stack_buffer.len(),
+                Err(UserCopyError::PartialCopy { copied }) => copied,
+                Err(UserCopyError::Rejected(_)) => 0,
+            }
+        });
+    }
+    drop(current_thread);
+
+    sched::switch_current_thread_to(
+        ThreadState::Dead,
+        IntDisable::new(),
+        PostSwitchAction::None,
+        false,
+    ).expect("could not find idle thread to switch to");
+
+    unreachable!("dead thread was switched back to");
+}
+
+/// Creates the event sent to the current address space's fault handler for a page fault that could not be resolved
+/// 
+/// This inserts a capability to the current thread into the fault handler's capability space
+/// 
+/// Returns None if there is no fault handler
+fn create_page_fault_event(error_code: u64) -> Option<(EventPoolListenerRef, EventData)> {
+    let current_thread = cpu_local_data().current_thread();
+    let fault_handler = current_thread.address_space().fault_handler()?;
+    let cspace = fault_handler.cspace.upgrade()?;
+
+    let thread_capability = WeakCapability::new_flags(
+        Arc::downgrade(&current_thread),
+        CapFlags::READ | CapFlags::PROD | CapFlags::WRITE,
+    );
+    let thread_id = cspace.insert_thread(Capability::Weak(thread_capability)).ok()?;
+
+    let event_data = EventData::PageFault(PageFault {
+        address: get_cr2(),
+        access: page_fault_access(error_code) as usize,
+        thread_id: thread_id.into(),
+    });
+
+    Some((fault_handler.listener, event_data))
+}
+
 fn page_fault(registers: &mut Registers, error_code: u64) {
     if error_code & PAGE_FAULT_USER == 0 {
         // page fault occured in kernel mode
         let rip = VirtAddr::new(registers.rip);
         if ASM_USER_COPY_CODE_REGION.contains(rip) {
-            // page fault occured while copying code
-            registers.rip = asm_user_copy_fail as usize;
+            // page fault occured while copying code, it may just be a lazily allocated page
+            if !resolve_user_page_fault(error_code) {
+                registers.rip = asm_user_copy_fail as usize;
+            }
             return;
         } else {
-            let action = if error_code & PAGE_FAULT_EXECUTE != 0 {
-                "instruction fetch"
-            } else if error_code & PAGE_FAULT_WRITE != 0 {
-                "write"
-            } else {
-                "read"
+            let action = match page_fault_access(error_code) {
+                FaultAccess::Execute => "instruction fetch",
+                FaultAccess::Write => "write",
+                FaultAccess::Read => "read",
             };
 
             panic!(
@@ -147,13 +270,44 @@ registers:
     }
 
     // page fault occured in userspace
-    let current_thread = cpu_local_data().current_thread();
-    let _address_space = current_thread.address_space();
+    if resolve_user_page_fault(error_code) {
+        return;
+    }
+
+    // guard regions can never be mapped, so the fault handler is not consulted
+    if is_guard_page_fault() {
+        exit_faulting_thread(FaultKind::StackOverflow, registers, error_code);
+    }
 
-    // TODO: check if this is copy on write or lazy allocated page and load them in to address space as writable
-    // TODO: emit page fault event if this is access to invalid address
+    if let Some((listener, event_data)) = create_page_fault_event(error_code) {
+        // the event is written after this thread is suspended, so the fault handler can't try to resume it too early
+        sched::switch_current_thread_to(
+            ThreadState::Suspended,
+            IntDisable::new(),
+            PostSwitchAction::WriteEvent {
+                listener,
+                event_data,
+            },
+            false,
+        ).expect("could not find idle thread to switch to");
 
-    panic!("user page fault: {:x}", get_cr2());
+        // thread was resumed by the fault handler, so retry the faulting access
+        return;
+    }
+
+    exit_faulting_thread(FaultKind::PageFault, registers, error_code);
+}
+
+/// Emits the interrupt event for user interrupt `int_num` on the current cpu, run as deferred work
+fn notify_user_interrupt(int_num: usize) {
+    // the deferred work worker is pinned to the cpu the interrupt occured on
+    let interrupt_id = InterruptId {
+        cpu: prid(),
+        interrupt_num: int_num as u8,
+    };
+
+    // FIXME: figure out what to do if this fails
+    let _ = interrupt_manager().notify_interrupt(interrupt_id);
 }
 
 /// This function runs if a nother cpu panics, just halt the currnet cpu
@@ -180,17 +334,20 @@ extern "C" fn rust_int_handler(int_num: u8, registers: &mut Registers, error_cod
         },
         IPI_PROCESS_EXIT => sched::exit_handler(),
         IPI_PANIC => ipi_panic(),
+        IPI_TLB_SHOOTDOWN => {
+            tlb::handle_shootdown();
+            cpu_local_data().local_apic().eoi();
+        },
+        // this only has to end the hlt of an idle cpu, the idle loop then checks for ready threads
+        IPI_RESCHEDULE => cpu_local_data().local_apic().eoi(),
         _ if int_num >= USER_INTERRUPT_START => {
-            let interrupt_id = InterruptId {
-                cpu: prid(),
-                interrupt_num: int_num,
-            };
-
+            // writing the interrupt event wakes listeners and allocates, so it is done by the deferred work worker
             // FIXME: figure out what to do if this fails
-            let _ = interrupt_manager().notify_interrupt(interrupt_id);
+            let _ = sched::defer_work(WorkItem::function(notify_user_interrupt, int_num as usize));
 
-            // TODO: figure out if sending eoi is necessary for msi interrupts
-            // (because for now this is what all these user interrupts are intended for)
+            // user interrupts are edge triggered msi interrupts, so the eoi can be sent right away,
+            // userspace masks the interrupt in software if it needs time to handle it
+            cpu_local_data().local_apic().eoi();
         },
         _ => (),
     }
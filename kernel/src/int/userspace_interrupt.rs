@@ -12,7 +12,34 @@ use crate::container::Arc;
 use crate::sync::IMutex;
 use super::{USER_INTERRUPT_COUNT, USER_INTERRUPT_START};
 
-type InterruptEventEmmiter = IMutex<BroadcastEventEmitter>;
+type SharedInterruptState = IMutex<InterruptState>;
+
+/// Delivery state of a userspace interrupt, shared by the [`Interrupt`] capability and the [`InterruptManager`]
+#[derive(Debug)]
+struct InterruptState {
+    event_emmiter: BroadcastEventEmitter,
+    /// While the interrupt is masked, triggers are recorded in `pending` instead of being delivered
+    masked: bool,
+    /// Set if the interrupt was triggered while it was masked
+    pending: bool,
+    /// If set, the interrupt is masked every time it is delivered
+    auto_mask: bool,
+}
+
+impl InterruptState {
+    fn trigger(&mut self) -> KResult<()> {
+        if self.masked {
+            self.pending = true;
+            return Ok(());
+        }
+
+        if self.auto_mask {
+            self.masked = true;
+        }
+
+        self.event_emmiter.emit_event(EventData::InterruptTrigger(InterruptTrigger))
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct InterruptId {
@@ -26,7 +53,7 @@ pub struct InterruptManager {
     // the cpu the next interrupt will be allocated on
     // this is to try and spread interrupt handling out among cpus
     next_alloc_cpu: usize,
-    interrupts: Vec<[Option<Arc<InterruptEventEmmiter>>; USER_INTERRUPT_COUNT]>,
+    interrupts: Vec<[Option<Arc<SharedInterruptState>>; USER_INTERRUPT_COUNT]>,
 }
 
 impl InterruptManager {
@@ -43,11 +70,11 @@ impl InterruptManager {
         })
     }
 
-    fn get_int_entry(&self, interrupt_id: InterruptId) -> &Option<Arc<InterruptEventEmmiter>> {
+    fn get_int_entry(&self, interrupt_id: InterruptId) -> &Option<Arc<SharedInterruptState>> {
         &self.interrupts[interrupt_id.cpu.into()][(interrupt_id.interrupt_num - USER_INTERRUPT_START) as usize]
     }
 
-    fn get_int_entry_mut(&mut self, interrupt_id: InterruptId) -> &mut Option<Arc<InterruptEventEmmiter>> {
+    fn get_int_entry_mut(&mut self, interrupt_id: InterruptId) -> &mut Option<Arc<SharedInterruptState>> {
         &mut self.interrupts[interrupt_id.cpu.into()][(interrupt_id.interrupt_num - USER_INTERRUPT_START) as usize]
     }
 
@@ -61,7 +88,7 @@ impl InterruptManager {
     /// Triggers an interrupt event to be emmitted for the given interrupt
     pub fn notify_interrupt(&self, interrupt_id: InterruptId) -> KResult<()> {
         if let Some(interrupt) = self.get_int_entry(interrupt_id) {
-            interrupt.lock().emit_event(EventData::InterruptTrigger(InterruptTrigger))
+            interrupt.lock().trigger()
         } else {
             Ok(())
         }
@@ -70,21 +97,21 @@ impl InterruptManager {
     /// Creates a new interrupt emmitter at a given interrupt id
     // TODO: make this function faster, currently it is O(n)
     // where n is the number of possible interrupt ids
-    fn create_interrupt(&mut self, allocator: &HeapRef) -> KResult<(InterruptId, Arc<InterruptEventEmmiter>)> {
-        let first_iter = self.interrupts[self.next_alloc_cpu..].iter().enumerate();
-        let second_iter = self.interrupts[..self.next_alloc_cpu].iter().enumerate();
+    fn create_interrupt(&mut self, allocator: &HeapRef, auto_mask: bool) -> KResult<(InterruptId, Arc<SharedInterruptState>)> {
+        let first_iter = self.interrupts.iter().enumerate().skip(self.next_alloc_cpu);
+        let second_iter = self.interrupts.iter().enumerate().take(self.next_alloc_cpu);
 
         let mut interrupt_id = InterruptId {
             cpu: Prid::from(self.next_alloc_cpu),
             // TODO: don't always use interrupt 0
-            interrupt_num: 0,
+            interrupt_num: USER_INTERRUPT_START,
         };
 
         'outer: for (cpu_num, cpu_ints) in first_iter.chain(second_iter) {
             for (int_num, interrupt) in cpu_ints.iter().enumerate() {
                 if interrupt.is_none() {
                     interrupt_id.cpu = Prid::from(cpu_num);
-                    interrupt_id.interrupt_num = int_num as u8;
+                    interrupt_id.interrupt_num = int_num as u8 + USER_INTERRUPT_START;
                     break 'outer;
                 }
             }
@@ -93,16 +120,21 @@ impl InterruptManager {
         self.inc_next_alloc_cpu();
 
         match self.get_int_entry_mut(interrupt_id) {
-            Some(interrupt_emmiter) => Ok((interrupt_id, interrupt_emmiter.clone())),
+            Some(interrupt_state) => Ok((interrupt_id, interrupt_state.clone())),
             entry @ None => {
-                let new_emmiter = Arc::new(
-                    IMutex::new(BroadcastEventEmitter::new(allocator.clone())),
+                let new_state = Arc::new(
+                    IMutex::new(InterruptState {
+                        event_emmiter: BroadcastEventEmitter::new(allocator.clone()),
+                        masked: false,
+                        pending: false,
+                        auto_mask,
+                    }),
                     allocator.clone(),
                 )?;
 
-                *entry = Some(new_emmiter.clone());
+                *entry = Some(new_state.clone());
 
-                Ok((interrupt_id, new_emmiter))
+                Ok((interrupt_id, new_state))
             }
         }
     }
@@ -115,15 +147,16 @@ impl InterruptManager {
 /// A capability which lets userspace handle interrupts
 #[derive(Debug)]
 pub struct Interrupt {
-    event_emmiter: Arc<InterruptEventEmmiter>,
+    state: Arc<SharedInterruptState>,
     interrupt_id: InterruptId,
 }
 
 impl Interrupt {
-    pub fn new(allocator: &HeapRef) -> KResult<Self> {
-        let (interrupt_id, event_emmiter) = interrupt_manager().create_interrupt(allocator)?;
+    /// Creates a new interrupt, if `auto_mask` is set the interrupt is masked each time it is delivered
+    pub fn new(allocator: &HeapRef, auto_mask: bool) -> KResult<Self> {
+        let (interrupt_id, state) = interrupt_manager().create_interrupt(allocator, auto_mask)?;
         Ok(Interrupt {
-            event_emmiter,
+            state,
             interrupt_id,
         })
     }
@@ -133,7 +166,32 @@ impl Interrupt {
     }
 
     pub fn add_interrupt_listener(&self, listener: BroadcastEventListener) -> KResult<()> {
-        self.event_emmiter.lock().add_listener(listener)
+        self.state.lock().event_emmiter.add_listener(listener)
+    }
+
+    /// Masks or unmasks the interrupt
+    /// 
+    /// A masked interrupt is not delivered to listeners, but if it triggers while masked
+    /// a single trigger is delivered once it is unmasked
+    pub fn set_masked(&self, masked: bool) -> KResult<()> {
+        let mut state = self.state.lock();
+
+        state.masked = masked;
+        if !masked && state.pending {
+            state.pending = false;
+            state.trigger()?;
+        }
+
+        Ok(())
+    }
+
+    /// Acknowledges that the last delivered interrupt has been handled
+    /// 
+    /// Every interrupt given to userspace is an edge triggered msi interrupt, and the kernel sends the eoi
+    /// as soon as the interrupt is received, so there is nothing to do here.
+    /// This still exists so drivers don't have to know how their interrupt is triggered.
+    pub fn ack(&self) -> KResult<()> {
+        Ok(())
     }
 }
 
@@ -168,4 +226,57 @@ pub fn init_interrupt_manager(num_cpus: usize) -> KResult<()> {
     let manager = InterruptManager::new(root_alloc_ref(), num_cpus)?;
     INTERRUPT_MANAGER.call_once(|| IMutex::new(manager));
     Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(interrupt: &Interrupt) {
+        interrupt_manager().notify_interrupt(interrupt.interrupt_id()).unwrap();
+    }
+
+    #[test_case]
+    fn masked_interrupt_delivered_on_unmask() {
+        let interrupt = Interrupt::new(&root_alloc_ref(), false).unwrap();
+
+        interrupt.set_masked(true).unwrap();
+        trigger(&interrupt);
+        trigger(&interrupt);
+        assert!(interrupt.state.lock().pending);
+
+        interrupt.set_masked(false).unwrap();
+        assert!(!interrupt.state.lock().pending);
+        assert!(!interrupt.state.lock().masked);
+    }
+
+    #[test_case]
+    fn auto_mask_masks_on_delivery() {
+        let interrupt = Interrupt::new(&root_alloc_ref(), true).unwrap();
+
+        trigger(&interrupt);
+        assert!(interrupt.state.lock().masked);
+        assert!(!interrupt.state.lock().pending);
+
+        // the second trigger arrives while the first is still being handled
+        trigger(&interrupt);
+        assert!(interrupt.state.lock().pending);
+
+        // unmasking delivers the pending trigger, which masks the interrupt again
+        interrupt.set_masked(false).unwrap();
+        assert!(interrupt.state.lock().masked);
+        assert!(!interrupt.state.lock().pending);
+    }
+
+    #[test_case]
+    fn edge_triggered_ack_is_noop() {
+        let interrupt = Interrupt::new(&root_alloc_ref(), true).unwrap();
+
+        trigger(&interrupt);
+        interrupt.ack().unwrap();
+
+        // ack doesn't unmask, only set_masked does
+        assert!(interrupt.state.lock().masked);
+        assert!(!interrupt.state.lock().pending);
+    }
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
 //! Provides utilites for writing debug text to the vga text buffer or the qemu debug port
 
 use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use lazy_static::lazy_static;
 use volatile::Volatile;
@@ -15,6 +16,9 @@ const VGA_BUF_HEIGHT: usize = 25;
 /// Port number of the debug console in qemu
 const DEBUGCON_PORT: u16 = 0xe9;
 
+/// Port of qemu's `isa-debug-exit` device, qemu exits with status `(value << 1) | 1` when it is written to
+const QEMU_EXIT_PORT: u16 = 0xf4;
+
 lazy_static! {
     /// The writer for the vga text buffer, used by print!() and friends
     pub static ref WRITER: IMutex<Writer> = IMutex::new(Writer {
@@ -33,6 +37,39 @@ pub static E_WRITER: IMutex<PortWriter> = IMutex::new(PortWriter::new(DEBUGCON_P
 /// Doesn't lock, so ideal for calling from interrupt handlers, but it is not synchronized
 pub static mut R_WRITER: PortWriter = PortWriter::new(DEBUGCON_PORT);
 
+/// Set once userspace has taken over the console with [`hand_off_console`]
+static CONSOLE_HANDED_OFF: AtomicBool = AtomicBool::new(false);
+
+/// Sequence number of the first kernel log record which was not printed because the console was handed off
+static HANDOFF_KLOG_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Stops the print macros from writing to the vga text buffer and the qemu debug port, so userspace can own the console
+///
+/// Messages are still appended to the kernel log. Handing off the console again does nothing.
+pub fn hand_off_console() {
+    if !CONSOLE_HANDED_OFF.load(Ordering::Acquire) {
+        HANDOFF_KLOG_SEQ.store(crate::klog::next_seq(), Ordering::Release);
+        CONSOLE_HANDED_OFF.store(true, Ordering::Release);
+    }
+}
+
+/// Makes the print macros write to the console again, this is done unconditionally when the kernel panics
+///
+/// # Returns
+///
+/// The sequence number of the first kernel log record which was not printed, or None if the console was not handed off
+pub fn reclaim_console() -> Option<u64> {
+    if CONSOLE_HANDED_OFF.swap(false, Ordering::AcqRel) {
+        Some(HANDOFF_KLOG_SEQ.load(Ordering::Acquire))
+    } else {
+        None
+    }
+}
+
+fn console_handed_off() -> bool {
+    CONSOLE_HANDED_OFF.load(Ordering::Acquire)
+}
+
 /// Represents the vga text buffer
 #[repr(transparent)]
 struct Buffer {
@@ -193,7 +230,11 @@ macro_rules! println {
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    WRITER.lock().write_fmt(args).unwrap();
+    crate::klog::_klog(args);
+
+    if !console_handed_off() {
+        WRITER.lock().write_fmt(args).unwrap();
+    }
 }
 
 /// Writes strings to a port
@@ -241,7 +282,11 @@ macro_rules! eprintln {
 
 #[doc(hidden)]
 pub fn _eprint(args: fmt::Arguments) {
-    E_WRITER.lock().write_fmt(args).unwrap();
+    crate::klog::_klog(args);
+
+    if !console_handed_off() {
+        E_WRITER.lock().write_fmt(args).unwrap();
+    }
 }
 
 /// Prints to the qemu debug port, but does not lock the writer, so it can always write, even from interrupt handlers
@@ -259,7 +304,26 @@ macro_rules! rprintln {
 
 #[doc(hidden)]
 pub fn _rprint(args: fmt::Arguments) {
-    unsafe {
-        R_WRITER.write_fmt(args).unwrap();
+    crate::klog::_try_klog(args);
+
+    if !console_handed_off() {
+        unsafe {
+            R_WRITER.write_fmt(args).unwrap();
+        }
     }
 }
+
+/// Value written to the `isa-debug-exit` device, qemu exits with status 33 for success and 35 for failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// Exits qemu with the given exit code
+///
+/// This returns if the kernel is not running in qemu, or qemu was not started with an `isa-debug-exit` device.
+pub fn exit_qemu(exit_code: QemuExitCode) {
+    outd(QEMU_EXIT_PORT, exit_code as u32);
+}
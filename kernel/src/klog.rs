@@ -0,0 +1,311 @@
+//! Ring buffer which stores recent kernel log messages
+//!
+//! Every message printed with the kernel print macros is appended as one record,
+//! so userspace can read kernel diagnostics with the `debug_read_klog` syscall after boot.
+//! Records are numbered with consecutive sequence numbers, and the oldest records are overwritten when the ring is full.
+
+use core::cmp::min;
+use core::fmt::{self, Write};
+use core::str;
+
+use crate::config::KLOG_SIZE;
+use crate::prelude::*;
+use crate::sync::IMutex;
+
+/// Longest message stored in one record, longer messages are truncated
+pub const KLOG_MAX_MESSAGE_LEN: usize = 512;
+
+/// Each record starts with the length of its message
+const RECORD_HEADER_SIZE: usize = size_of::<u32>();
+
+static KLOG: IMutex<KlogRing<KLOG_SIZE>> = IMutex::new(KlogRing::new());
+
+/// Fixed size buffer that messages are formatted into before being appended to the ring,
+/// so the ring is not locked while formatting
+struct MessageBuf {
+    data: [u8; KLOG_MAX_MESSAGE_LEN],
+    len: usize,
+}
+
+impl MessageBuf {
+    fn new() -> Self {
+        MessageBuf {
+            data: [0; KLOG_MAX_MESSAGE_LEN],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+impl Write for MessageBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // silently truncate, a cut off message is more useful than none
+        let copy_len = min(s.len(), KLOG_MAX_MESSAGE_LEN - self.len);
+        self.data[self.len..(self.len + copy_len)].copy_from_slice(&s.as_bytes()[..copy_len]);
+        self.len += copy_len;
+
+        Ok(())
+    }
+}
+
+struct KlogRing<const N: usize> {
+    data: [u8; N],
+    /// Offset of the oldest record
+    ///
+    /// Offsets are never wrapped, they are only reduced modulo `N` when indexing `data`
+    start: usize,
+    /// Offset the next record will be written at
+    end: usize,
+    /// Sequence number of the record at `start`
+    start_seq: u64,
+    /// Sequence number the next record will have
+    next_seq: u64,
+}
+
+impl<const N: usize> KlogRing<N> {
+    const fn new() -> Self {
+        KlogRing {
+            data: [0; N],
+            start: 0,
+            end: 0,
+            start_seq: 0,
+            next_seq: 0,
+        }
+    }
+
+    fn write_bytes(&mut self, offset: usize, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.data[(offset + i) % N] = *byte;
+        }
+    }
+
+    fn read_bytes(&self, offset: usize, out: &mut [u8]) {
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = self.data[(offset + i) % N];
+        }
+    }
+
+    /// Size of the record at `offset`, including its header
+    fn record_size(&self, offset: usize) -> usize {
+        let mut header = [0; RECORD_HEADER_SIZE];
+        self.read_bytes(offset, &mut header);
+
+        RECORD_HEADER_SIZE + u32::from_le_bytes(header) as usize
+    }
+
+    /// Appends a record containing `message`, overwriting the oldest records if there is not enough space
+    fn push(&mut self, message: &[u8]) {
+        let message = &message[..min(message.len(), N - RECORD_HEADER_SIZE)];
+        let record_size = RECORD_HEADER_SIZE + message.len();
+
+        while self.end + record_size - self.start > N {
+            self.start += self.record_size(self.start);
+            self.start_seq += 1;
+        }
+
+        self.write_bytes(self.end, &(message.len() as u32).to_le_bytes());
+        self.write_bytes(self.end + RECORD_HEADER_SIZE, message);
+        self.end += record_size;
+        self.next_seq += 1;
+    }
+
+    /// Copies the messages of records with a sequence number of at least `since_seq` into `out`
+    ///
+    /// Only whole messages are copied, copying stops at the first record that doesn't fit.
+    /// If records starting at `since_seq` have already been overwritten, copying starts at the oldest record.
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes written and the sequence number of the first record which was not copied,
+    /// or [`SysErr::OutOfCapacity`] if the first record to copy doesn't fit in `out`
+    fn read(&self, since_seq: u64, out: &mut [u8]) -> KResult<(usize, u64)> {
+        if since_seq >= self.next_seq {
+            return Ok((0, self.next_seq));
+        }
+
+        let mut seq = self.start_seq;
+        let mut offset = self.start;
+        while seq < since_seq {
+            offset += self.record_size(offset);
+            seq += 1;
+        }
+
+        let mut written = 0;
+        while offset < self.end {
+            let message_len = self.record_size(offset) - RECORD_HEADER_SIZE;
+            if written + message_len > out.len() {
+                if written == 0 {
+                    return Err(SysErr::OutOfCapacity);
+                }
+
+                break;
+            }
+
+            self.read_bytes(offset + RECORD_HEADER_SIZE, &mut out[written..(written + message_len)]);
+            written += message_len;
+            offset += RECORD_HEADER_SIZE + message_len;
+            seq += 1;
+        }
+
+        Ok((written, seq))
+    }
+
+    /// Calls `f` with the message of each record with a sequence number of at least `since_seq`, oldest first
+    fn for_each_message(&self, since_seq: u64, mut f: impl FnMut(&[u8])) {
+        let mut message = [0; KLOG_MAX_MESSAGE_LEN];
+
+        let mut seq = self.start_seq;
+        let mut offset = self.start;
+        while offset < self.end {
+            let message_len = self.record_size(offset) - RECORD_HEADER_SIZE;
+
+            if seq >= since_seq {
+                // records are never longer than the max message length, but a partial message is better than a panic in the panic handler
+                let message = &mut message[..min(message_len, KLOG_MAX_MESSAGE_LEN)];
+                self.read_bytes(offset + RECORD_HEADER_SIZE, message);
+                f(message);
+            }
+
+            offset += RECORD_HEADER_SIZE + message_len;
+            seq += 1;
+        }
+    }
+}
+
+/// Appends a formatted message to the kernel log
+#[doc(hidden)]
+pub fn _klog(args: fmt::Arguments) {
+    let mut message = MessageBuf::new();
+    let _ = message.write_fmt(args);
+
+    KLOG.lock().push(message.as_bytes());
+}
+
+/// Like [`_klog`], but drops the message if the kernel log is locked, so it can be used from interrupt handlers
+#[doc(hidden)]
+pub fn _try_klog(args: fmt::Arguments) {
+    let mut message = MessageBuf::new();
+    let _ = message.write_fmt(args);
+
+    if let Some(mut klog) = KLOG.try_lock() {
+        klog.push(message.as_bytes());
+    }
+}
+
+/// Copies whole kernel log messages starting at sequence number `since_seq` into `out`
+///
+/// See [`KlogRing::read`] for details
+pub fn read_klog(since_seq: u64, out: &mut [u8]) -> KResult<(usize, u64)> {
+    KLOG.lock().read(since_seq, out)
+}
+
+/// Returns the sequence number the next kernel log record will have
+pub fn next_seq() -> u64 {
+    KLOG.lock().next_seq
+}
+
+/// Writes the messages of records with a sequence number of at least `since_seq` to `writer`
+///
+/// This is used by the panic handler, so it gives up if the kernel log is locked instead of deadlocking.
+///
+/// # Returns
+///
+/// False if the kernel log was locked
+pub fn dump_klog(since_seq: u64, writer: &mut impl Write) -> bool {
+    let Some(klog) = KLOG.try_lock() else {
+        return false;
+    };
+
+    klog.for_each_message(since_seq, |message| {
+        let message = match str::from_utf8(message) {
+            Ok(message) => message,
+            // truncating a message can cut a character in half
+            Err(error) => str::from_utf8(&message[..error.valid_up_to()]).unwrap_or_default(),
+        };
+
+        let _ = writer.write_str(message);
+    });
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all<const N: usize>(ring: &KlogRing<N>, since_seq: u64) -> ([u8; 64], usize, u64) {
+        let mut out = [0; 64];
+        let (written, next_seq) = ring.read(since_seq, &mut out).unwrap();
+        (out, written, next_seq)
+    }
+
+    #[test_case]
+    fn klog_reads_records_after_seq() {
+        let mut ring = KlogRing::<64>::new();
+        ring.push(b"abc");
+        ring.push(b"de");
+        ring.push(b"f");
+
+        let (out, written, next_seq) = read_all(&ring, 0);
+        assert_eq!(&out[..written], b"abcdef");
+        assert_eq!(next_seq, 3);
+
+        let (out, written, next_seq) = read_all(&ring, 1);
+        assert_eq!(&out[..written], b"def");
+        assert_eq!(next_seq, 3);
+
+        let (_, written, next_seq) = read_all(&ring, 3);
+        assert_eq!(written, 0);
+        assert_eq!(next_seq, 3);
+    }
+
+    #[test_case]
+    fn klog_visits_messages_after_seq() {
+        let mut ring = KlogRing::<64>::new();
+        ring.push(b"abc");
+        ring.push(b"de");
+        ring.push(b"f");
+
+        let mut out = [0; 64];
+        let mut len = 0;
+        ring.for_each_message(1, |message| {
+            out[len..(len + message.len())].copy_from_slice(message);
+            len += message.len();
+        });
+
+        assert_eq!(&out[..len], b"def");
+    }
+
+    #[test_case]
+    fn klog_overwrites_oldest_records() {
+        let mut ring = KlogRing::<16>::new();
+        // each record takes 4 header bytes and 3 message bytes
+        ring.push(b"aaa");
+        ring.push(b"bbb");
+        ring.push(b"ccc");
+
+        assert_eq!(ring.start_seq, 1);
+        assert_eq!(ring.next_seq, 3);
+
+        let (out, written, next_seq) = read_all(&ring, 0);
+        assert_eq!(&out[..written], b"bbbccc");
+        assert_eq!(next_seq, 3);
+    }
+
+    #[test_case]
+    fn klog_does_not_split_records() {
+        let mut ring = KlogRing::<64>::new();
+        ring.push(b"abc");
+        ring.push(b"defg");
+
+        let mut out = [0; 5];
+        assert_eq!(ring.read(0, &mut out), Ok((3, 1)));
+        assert_eq!(&out[..3], b"abc");
+
+        let mut out = [0; 2];
+        assert_eq!(ring.read(1, &mut out), Err(SysErr::OutOfCapacity));
+    }
+}
\ No newline at end of file
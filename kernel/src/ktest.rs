@@ -0,0 +1,235 @@
+//! Runner for the kernel's `#[test_case]` tests
+//!
+//! Tests are run one at a time on the startup core, before userspace is started.
+//! Failed [`kassert`] checks are recorded and the test keeps running, a panic fails the test and ends the whole run.
+//! Benchmarks report their results with [`report_benchmark`], which prints them under the running test.
+//! After every test the root allocator's usage is compared to before the test, and any difference is reported as a leak.
+//!
+//! Passing `ktest=<filter>` on the kernel command line only runs tests with `<filter>` in their name.
+//! The run ends with a `KTEST RESULT:` line, and exits qemu through the `isa-debug-exit` device if it is present.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use arrayvec::ArrayString;
+use spin::Once;
+
+use crate::alloc::root_alloc;
+use crate::arch::x64::{cli, hlt};
+use crate::io::{exit_qemu, QemuExitCode};
+use crate::prelude::*;
+use crate::sync::IMutex;
+
+/// Longest test filter which can be passed on the command line, longer filters are truncated
+const TEST_FILTER_MAX_LEN: usize = 128;
+
+static TEST_FILTER: Once<ArrayString<TEST_FILTER_MAX_LEN>> = Once::new();
+
+/// Name of the test currently running, empty if no test is running
+static CURRENT_TEST: IMutex<&'static str> = IMutex::new("");
+/// Number of failed checks in the current test
+static CURRENT_FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+static PASSED: AtomicUsize = AtomicUsize::new(0);
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+static FILTERED: AtomicUsize = AtomicUsize::new(0);
+static LEAKED: AtomicUsize = AtomicUsize::new(0);
+
+/// Set once the result line is printed, so a panic while printing it doesn't print it again
+static FINISHED: AtomicBool = AtomicBool::new(false);
+
+/// A test which can be run by [`run_tests`]
+pub trait KernelTest {
+    fn name(&self) -> &'static str;
+    fn run(&self);
+}
+
+impl<T: Fn()> KernelTest for T {
+    fn name(&self) -> &'static str {
+        let name = core::any::type_name::<T>();
+        name.strip_prefix("kernel::").unwrap_or(name)
+    }
+
+    fn run(&self) {
+        self()
+    }
+}
+
+/// Reads the test filter from the kernel command line
+///
+/// This must be called before the memory holding the boot info could be reused.
+pub fn init(command_line: &str) {
+    let filter = command_line.split_whitespace()
+        .find_map(|arg| arg.strip_prefix("ktest="));
+
+    TEST_FILTER.call_once(|| {
+        let mut test_filter = ArrayString::new();
+        for c in filter.unwrap_or("").chars() {
+            if test_filter.try_push(c).is_err() {
+                break;
+            }
+        }
+        test_filter
+    });
+}
+
+/// Called by [`kassert`] and friends when a check fails
+#[doc(hidden)]
+pub fn record_failure(file: &str, line: u32, message: fmt::Arguments) {
+    CURRENT_FAILURES.fetch_add(1, Ordering::Relaxed);
+    eprintln!("    check failed at {}:{}: {}", file, line, message);
+}
+
+/// Prints the average time per iteration of a benchmark in the current test
+///
+/// Benchmark results are printed under the test they ran in, and don't affect whether it passes.
+#[cfg(test)]
+pub fn report_benchmark(name: &str, total_nsec: u64, iterations: u64) {
+    eprintln!("    bench {}: {} ns/iter ({} iterations)", name, total_nsec / iterations, iterations);
+}
+
+/// Root allocator bytes in use, compared before and after each test to find leaks
+fn allocated_bytes() -> usize {
+    root_alloc().usage().0
+}
+
+pub fn run_tests(tests: &[&dyn KernelTest]) {
+    let filter = TEST_FILTER.get().copied().unwrap_or_default();
+    if filter.is_empty() {
+        eprintln!("Running {} tests", tests.len());
+    } else {
+        eprintln!("Running tests matching '{}'", filter);
+    }
+
+    for test in tests {
+        let name = test.name();
+        if !name.contains(filter.as_str()) {
+            FILTERED.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+
+        eprintln!("test {} ...", name);
+        *CURRENT_TEST.lock() = name;
+        CURRENT_FAILURES.store(0, Ordering::Relaxed);
+        let allocated_before = allocated_bytes();
+
+        test.run();
+
+        let allocated_after = allocated_bytes();
+        *CURRENT_TEST.lock() = "";
+
+        if allocated_after > allocated_before {
+            LEAKED.fetch_add(1, Ordering::Relaxed);
+            eprintln!("    leaked {} bytes", allocated_after - allocated_before);
+        }
+
+        let failures = CURRENT_FAILURES.load(Ordering::Relaxed);
+        if failures == 0 {
+            PASSED.fetch_add(1, Ordering::Relaxed);
+            eprintln!("test {} ... ok", name);
+        } else {
+            FAILED.fetch_add(1, Ordering::Relaxed);
+            eprintln!("test {} ... FAILED ({} failed checks)", name, failures);
+        }
+    }
+
+    finish();
+}
+
+/// Called by the panic handler, the panicking test is counted as failed and the run ends
+pub fn panicked() -> ! {
+    // a panic outside of any test means the kernel could not get to the tests, which is a failure too
+    FAILED.fetch_add(1, Ordering::Relaxed);
+
+    let name = *CURRENT_TEST.lock();
+    if !name.is_empty() {
+        eprintln!("test {} ... FAILED (panicked)", name);
+    }
+
+    finish();
+}
+
+/// Prints the result line and exits qemu
+fn finish() -> ! {
+    let failed = FAILED.load(Ordering::Relaxed);
+
+    if !FINISHED.swap(true, Ordering::Relaxed) {
+        eprintln!(
+            "KTEST RESULT: passed={} failed={} filtered={} leaked={}",
+            PASSED.load(Ordering::Relaxed),
+            failed,
+            FILTERED.load(Ordering::Relaxed),
+            LEAKED.load(Ordering::Relaxed),
+        );
+    }
+
+    let exit_code = if failed == 0 {
+        QemuExitCode::Success
+    } else {
+        QemuExitCode::Failed
+    };
+    exit_qemu(exit_code);
+
+    // not running in qemu, or qemu has no exit device
+    loop {
+        cli();
+        hlt();
+    }
+}
+
+/// Checks that a condition is true, recording a failure in the current test instead of panicking if it is not
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr $(,)?) => {
+        $crate::kassert!($cond, "{}", core::stringify!($cond))
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $crate::ktest::record_failure(core::file!(), core::line!(), core::format_args!($($arg)+));
+        }
+    };
+}
+
+/// Checks that two values are equal, recording a failure in the current test instead of panicking if they are not
+#[macro_export]
+macro_rules! kassert_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => $crate::kassert!(
+                *left == *right,
+                "`{}` == `{}`, left: {:?}, right: {:?}",
+                core::stringify!($left),
+                core::stringify!($right),
+                left,
+                right,
+            ),
+        }
+    };
+    ($left:expr, $right:expr, $($arg:tt)+) => {
+        match (&$left, &$right) {
+            (left, right) => $crate::kassert!(
+                *left == *right,
+                "{}, left: {:?}, right: {:?}",
+                core::format_args!($($arg)+),
+                left,
+                right,
+            ),
+        }
+    };
+}
+
+/// Checks that two values are not equal, recording a failure in the current test instead of panicking if they are
+#[macro_export]
+macro_rules! kassert_ne {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => $crate::kassert!(
+                *left != *right,
+                "`{}` != `{}`, both: {:?}",
+                core::stringify!($left),
+                core::stringify!($right),
+                left,
+            ),
+        }
+    };
+}
\ No newline at end of file
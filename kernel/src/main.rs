@@ -18,7 +18,7 @@
 #![allow(dead_code)]
 #![deny(unsafe_op_in_unsafe_fn)]
 #![feature(custom_test_frameworks)]
-#![test_runner(crate::test_runner)]
+#![test_runner(crate::ktest::run_tests)]
 #![reexport_test_harness_main = "test_main"]
 
 mod acpi;
@@ -32,6 +32,7 @@ mod mem;
 mod sched;
 mod sync;
 mod syscall;
+mod time;
 mod util;
 mod vmem_manager;
 
@@ -40,6 +41,9 @@ mod config;
 mod gdt;
 mod gs_data;
 mod io;
+mod klog;
+#[cfg(test)]
+mod ktest;
 mod mb2;
 mod prelude;
 mod start_userspace;
@@ -57,9 +61,24 @@ use sched::kernel_stack::KernelStack;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // panics must stay visible even after userspace has taken over the console,
+    // so print everything which was only written to the kernel log before the panic message
+    // the header is written directly so it is not added to the kernel log which is being dumped
+    if let Some(since_seq) = io::reclaim_console() {
+        unsafe {
+            io::R_WRITER.write_string("kernel panicked after console handoff, kernel log since handoff:\n");
+            if !klog::dump_klog(since_seq, &mut io::R_WRITER) {
+                io::R_WRITER.write_string("kernel log is locked, could not print it\n");
+            }
+        }
+    }
+
     eprintln!("{}", info);
     println!("{}", info);
 
+    #[cfg(test)]
+    ktest::panicked();
+
     loop {
         cli();
         hlt();
@@ -77,6 +96,12 @@ fn init(boot_info_addr: usize) -> KResult<()> {
 
     let boot_info = unsafe { BootInfo::new(boot_info_addr) };
 
+    // the command line is read before the allocator is initialized, since that could reuse the memory it is in
+    #[cfg(test)]
+    ktest::init(boot_info.command_line);
+    let run_userland_tests = boot_info.command_line.split_whitespace()
+        .any(|arg| arg == "utest");
+
     let mmio_allocator = unsafe {
         alloc::init(&boot_info.memory_map)?
     };
@@ -105,11 +130,23 @@ fn init(boot_info_addr: usize) -> KResult<()> {
         apic::init_local_apic();
     }
 
+    time::init();
+
     int::userspace_interrupt::init_interrupt_manager(ap_apic_ids.len() + 1)?;
 
     apic::smp_init(&ap_apic_ids)?;
 
-    start_userspace::start_early_init_process(boot_info.initrd, mmio_allocator, boot_info.rsdp)
+    // all cpus have initialized their cpu local data now
+    alloc::enable_heap_caches();
+    #[cfg(feature = "lock-tracking")]
+    sync::lock_tracking::enable();
+
+    // tests run without userspace, so nothing else allocates while a test checks for leaks
+    if cfg!(test) {
+        return Ok(());
+    }
+
+    start_userspace::start_early_init_process(boot_info.initrd, mmio_allocator, boot_info.rsdp, run_userland_tests)
 }
 
 /// Rust entry point of the kernel on the startup core
@@ -128,9 +165,7 @@ pub extern "C" fn _start(boot_info_addr: usize) -> ! {
     #[cfg(test)]
     test_main();
 
-    loop {
-        hlt();
-    }
+    sched::idle_loop();
 }
 
 /// Initializes ap cores
@@ -158,6 +193,8 @@ fn ap_init(id: usize, stack_addr: usize) -> KResult<()> {
         apic::init_local_apic();
     }
 
+    time::check_tsc_sync();
+
     apic::ap_init_finished();
 
     Ok(())
@@ -176,119 +213,5 @@ pub extern "C" fn _ap_start(id: usize, stack_top: usize) -> ! {
 
     sti();
 
-    loop {
-        hlt();
-    }
-}
-
-#[cfg(test)]
-fn test_runner(tests: &[&dyn Fn()]) {
-    eprintln!("Running {} tests", tests.len());
-    for test in tests {
-        test();
-    }
-    eprintln!("All tests passed");
-}
-
-#[test_case]
-fn test() {
-    use alloc::{zm, PageAllocator};
-
-    use mem::PageLayout;
-
-    unsafe {
-        let a1 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(4 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a2 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(2 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a3 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(2 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a4 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(10 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a5 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(4 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a6 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(15 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a7 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(4 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a8 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(1 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a9 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(5 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        eprintln!("{:x?}", a1);
-        eprintln!("{:x?}", a2);
-        eprintln!("{:x?}", a3);
-        eprintln!("{:x?}", a4);
-        eprintln!("{:x?}", a5);
-        eprintln!("{:x?}", a6);
-        eprintln!("{:x?}", a7);
-        eprintln!("{:x?}", a8);
-        eprintln!("{:x?}", a9);
-        zm().dealloc(a9);
-        zm().dealloc(a4);
-        zm().dealloc(a1);
-        zm().dealloc(a3);
-        zm().dealloc(a8);
-        zm().dealloc(a2);
-        zm().dealloc(a5);
-        zm().dealloc(a7);
-        zm().dealloc(a6);
-
-        let a1 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(4 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a2 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(2 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a3 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(2 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a4 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(10 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a5 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(4 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a6 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(15 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a7 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(4 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a8 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(1 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        let a9 = zm()
-            .alloc(PageLayout::from_size_align_unchecked(5 * PAGE_SIZE, PAGE_SIZE))
-            .unwrap();
-        eprintln!("{:x?}", a1);
-        eprintln!("{:x?}", a2);
-        eprintln!("{:x?}", a3);
-        eprintln!("{:x?}", a4);
-        eprintln!("{:x?}", a5);
-        eprintln!("{:x?}", a6);
-        eprintln!("{:x?}", a7);
-        eprintln!("{:x?}", a8);
-        eprintln!("{:x?}", a9);
-        zm().dealloc(a9);
-        zm().dealloc(a4);
-        zm().dealloc(a1);
-        zm().dealloc(a3);
-        zm().dealloc(a8);
-        zm().dealloc(a2);
-        zm().dealloc(a5);
-        zm().dealloc(a7);
-        zm().dealloc(a6);
-    }
-
-    eprintln!("tests done");
+    sched::idle_loop();
 }
@@ -11,6 +11,7 @@ use crate::util::{HwaIter, HwaTag};
 
 // multiboot tag type ids
 const END: u32 = 0;
+const COMMAND_LINE: u32 = 1;
 const MODULE: u32 = 3;
 const MEMORY_MAP: u32 = 6;
 const RSDP_OLD: u32 = 14;
@@ -39,6 +40,7 @@ impl TrailerInit for Mb2Start {
 #[derive(Debug, Clone, Copy)]
 enum Mb2Elem<'a> {
     End,
+    CommandLine(&'a [u8]),
     Module(WithTrailer<'a, Mb2Module>),
     MemoryMap(WithTrailer<'a, Mb2MemoryMapHeader>),
     RsdpOld(Rsdp),
@@ -62,6 +64,7 @@ impl HwaTag for TagHeader {
     fn elem(this: WithTrailer<'_, Self>) -> Self::Elem<'_> {
         match this.data.typ {
             END => Mb2Elem::End,
+            COMMAND_LINE => Mb2Elem::CommandLine(this.trailer),
             MODULE => Mb2Elem::Module(Self::data_trailer(&this)),
             MEMORY_MAP => Mb2Elem::MemoryMap(Self::data_trailer(&this)),
             RSDP_OLD => Mb2Elem::RsdpOld(Self::data(&this)),
@@ -206,6 +209,8 @@ impl WithTrailer<'_, Mb2Module> {
 #[derive(Debug, Clone, Copy)]
 pub struct BootInfo<'a> {
     pub memory_map: MemoryMap,
+    /// Arguments given to the kernel by the bootloader, empty if there are none or they are not valid utf-8
+    pub command_line: &'a str,
     pub initrd: &'a [u8],
     pub rsdp: Rsdp,
     pub rsdt: WithTrailer<'a, Rsdt>,
@@ -225,12 +230,19 @@ impl BootInfo<'_> {
         let mut memory_map = MemoryMap::new();
         let mut memory_map_tag = None;
 
+        let mut command_line = "";
+
         let mut rsdp = None;
         let mut rsdt = None;
 
         for data in iter {
             match data {
                 Mb2Elem::End => break,
+                Mb2Elem::CommandLine(data) => {
+                    // the command line is null terminated
+                    let len = data.iter().position(|byte| *byte == 0).unwrap_or(data.len());
+                    command_line = core::str::from_utf8(&data[..len]).unwrap_or("");
+                },
                 Mb2Elem::Module(data_trailer) => {
                     // look for initrd in module
                     if data_trailer.is_initrd() {
@@ -278,6 +290,7 @@ impl BootInfo<'_> {
 
         BootInfo {
             memory_map,
+            command_line,
             initrd: initrd_slice.expect("no initrd"),
             rsdp: rsdp.expect("no rsdp"),
             rsdt: rsdt.expect("no rsdt"),
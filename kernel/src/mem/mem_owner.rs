@@ -18,7 +18,7 @@ impl<T> MemOwnerKernelExt<T> for MemOwner<T> {
     fn new(data: T, allocator: &mut HeapRef) -> KResult<Self> {
         let layout = Layout::new::<T>();
 
-        let mem = allocator.alloc(layout).ok_or(SysErr::OutOfMem)?;
+        let mem = allocator.try_alloc(layout)?;
         let ptr: *mut T = mem.as_mut_ptr() as *mut T;
 
         unsafe {
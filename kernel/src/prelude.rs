@@ -17,3 +17,5 @@ pub use crate::mem::{
 };
 pub use crate::util::*;
 pub use crate::{eprint, eprintln, print, println, rprint, rprintln, format};
+#[cfg(test)]
+pub use crate::{kassert, kassert_eq, kassert_ne};
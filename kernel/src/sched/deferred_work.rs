@@ -0,0 +1,443 @@
+//! Deferred work, which lets interrupt handlers move work out of interrupt context
+//!
+//! [`defer_work`] pushes a [`WorkItem`] onto the current cpu's queue, and a worker thread pinned to that cpu
+//! runs it later with interrupts enabled. Work deferred on the same cpu runs in the order it was deferred.
+//!
+//! Each queue is a fixed size ring which is only pushed to on its own cpu with interrupts disabled,
+//! and only popped from by its worker, so it needs no lock. Work deferred while the ring is full goes to a locked
+//! overflow list, and so does all work after it until the list is empty again, so the order is kept.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use spin::Once;
+use sys::ThreadPriority;
+
+use crate::alloc::{HeapRef, root_alloc_ref};
+use crate::arch::x64::IntDisable;
+use crate::cap::address_space::AddressSpace;
+use crate::cap::capability_space::CapabilitySpace;
+use crate::config::DEFERRED_WORK_RING_SIZE;
+use crate::container::{Arc, Box};
+use crate::prelude::*;
+use crate::sync::IMutex;
+use super::{Thread, ThreadGroup, ThreadState, ThreadStartMode, PostSwitchAction, switch_current_thread_to};
+
+const _: () = assert!(DEFERRED_WORK_RING_SIZE.is_power_of_two(), "deferred work ring size must be a power of 2");
+
+/// A closure moved to the heap, made by [`WorkItem::closure`]
+pub struct BoxedClosure {
+    closure: *mut (),
+    allocator: HeapRef,
+    /// Moves the closure out of its allocation and frees the allocation, then runs the closure if the bool is true
+    consume: unsafe fn(*mut (), HeapRef, bool),
+}
+
+// safety: `WorkItem::closure` requires the closure to be Send
+unsafe impl Send for BoxedClosure {}
+
+impl BoxedClosure {
+    fn run(self) {
+        let this = ManuallyDrop::new(self);
+
+        // safety: the closure has not been consumed yet, and `this` is not used or dropped after this
+        unsafe {
+            (this.consume)(this.closure, ptr::read(&this.allocator), true);
+        }
+    }
+}
+
+impl Drop for BoxedClosure {
+    fn drop(&mut self) {
+        // safety: the closure has not been consumed yet, since `run` does not drop self
+        unsafe {
+            (self.consume)(self.closure, self.allocator.clone(), false);
+        }
+    }
+}
+
+impl fmt::Debug for BoxedClosure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoxedClosure")
+            .field("closure", &self.closure)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Implementation of [`BoxedClosure::consume`] for closures of type `F`
+///
+/// # Safety
+///
+/// `closure` must point to an `F` allocated by `allocator` which has not been consumed yet
+unsafe fn consume_closure<F: FnOnce()>(closure: *mut (), allocator: HeapRef, run: bool) {
+    let boxed = unsafe { Box::from_raw(closure as *mut MaybeUninit<F>, allocator) };
+    let closure = unsafe { boxed.assume_init_read() };
+    // this only frees the memory, the closure was moved out
+    drop(boxed);
+
+    if run {
+        closure();
+    }
+}
+
+/// Work which is run later by the deferred work worker of the cpu it was deferred on
+#[derive(Debug)]
+pub enum WorkItem {
+    /// Calls `function` with `data`, this does not allocate so it can be deferred from anywhere
+    Function {
+        function: fn(usize),
+        data: usize,
+    },
+    /// Runs a closure which was moved to the heap
+    Closure(BoxedClosure),
+}
+
+impl WorkItem {
+    pub fn function(function: fn(usize), data: usize) -> Self {
+        WorkItem::Function {
+            function,
+            data,
+        }
+    }
+
+    /// Moves `closure` to the heap, this fails if it can't be allocated
+    pub fn closure<F: FnOnce() + Send + 'static>(closure: F) -> KResult<Self> {
+        let (closure, allocator) = Box::into_raw(Box::new(closure, root_alloc_ref())?);
+
+        Ok(WorkItem::Closure(BoxedClosure {
+            closure: closure as *mut (),
+            allocator,
+            consume: consume_closure::<F>,
+        }))
+    }
+
+    fn run(self) {
+        match self {
+            WorkItem::Function { function, data } => function(data),
+            WorkItem::Closure(closure) => closure.run(),
+        }
+    }
+}
+
+/// A ring buffer which one producer and one consumer can use at the same time without a lock
+struct WorkRing {
+    slots: [UnsafeCell<MaybeUninit<WorkItem>>; DEFERRED_WORK_RING_SIZE],
+    /// Number of items ever pushed, only written by the producer
+    head: AtomicUsize,
+    /// Number of items ever popped, only written by the consumer
+    tail: AtomicUsize,
+}
+
+// safety: a slot is only accessed by the producer before `head` is incremented past it,
+// and only by the consumer after that until `tail` is incremented past it
+unsafe impl Sync for WorkRing {}
+
+impl WorkRing {
+    fn new() -> Self {
+        WorkRing {
+            slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; DEFERRED_WORK_RING_SIZE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    /// Returns `item` back if the ring is full
+    ///
+    /// # Safety
+    ///
+    /// Only one thread may push at a time
+    unsafe fn push(&self, item: WorkItem) -> Result<(), WorkItem> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head.wrapping_sub(self.tail.load(Ordering::Acquire)) == DEFERRED_WORK_RING_SIZE {
+            return Err(item);
+        }
+
+        unsafe {
+            (*self.slots[head % DEFERRED_WORK_RING_SIZE].get()).write(item);
+        }
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// Only one thread may pop at a time
+    unsafe fn pop(&self) -> Option<WorkItem> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let item = unsafe {
+            (*self.slots[tail % DEFERRED_WORK_RING_SIZE].get()).assume_init_read()
+        };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+
+        Some(item)
+    }
+}
+
+impl Drop for WorkRing {
+    fn drop(&mut self) {
+        // safety: nothing else can use the ring while it is being dropped
+        while let Some(item) = unsafe { self.pop() } {
+            drop(item);
+        }
+    }
+}
+
+/// The deferred work of one cpu, stored in its cpu local data
+pub struct DeferredWorkQueue {
+    ring: WorkRing,
+    /// Work deferred while the ring was full, or while this list was not empty
+    overflow: IMutex<Vec<WorkItem>>,
+    /// Set while `overflow` is not empty, so pushing doesn't have to lock it
+    overflowed: AtomicBool,
+    /// Thread which runs the work, pinned to this queue's cpu
+    worker: Once<Arc<Thread>>,
+    /// Set when the worker suspends itself because there is no work
+    worker_sleeping: AtomicBool,
+}
+
+impl DeferredWorkQueue {
+    pub fn new() -> Self {
+        DeferredWorkQueue {
+            ring: WorkRing::new(),
+            overflow: IMutex::new(Vec::new(root_alloc_ref())),
+            overflowed: AtomicBool::new(false),
+            worker: Once::new(),
+            worker_sleeping: AtomicBool::new(false),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ring.is_empty() && !self.overflowed.load(Ordering::Acquire)
+    }
+
+    /// Adds `item` to the end of the queue, this only fails if the ring is full and the overflow list can't grow
+    ///
+    /// # Safety
+    ///
+    /// Only one thread may push at a time, for a cpu's own queue this is ensured by disabling interrupts
+    unsafe fn push(&self, item: WorkItem) -> KResult<()> {
+        let item = if self.overflowed.load(Ordering::Acquire) {
+            item
+        } else {
+            match unsafe { self.ring.push(item) } {
+                Ok(()) => return Ok(()),
+                Err(item) => item,
+            }
+        };
+
+        let mut overflow = self.overflow.lock();
+        overflow.push(item)?;
+        self.overflowed.store(true, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Removes the oldest item from the queue
+    ///
+    /// # Safety
+    ///
+    /// Only one thread may pop at a time
+    unsafe fn pop(&self) -> Option<WorkItem> {
+        // everything in the ring is older than the overflow list, since nothing goes in the ring while the list has work
+        if let Some(item) = unsafe { self.ring.pop() } {
+            return Some(item);
+        }
+
+        if !self.overflowed.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let mut overflow = self.overflow.lock();
+        let item = overflow.pop_front();
+
+        if overflow.is_empty() {
+            // overflowing should be rare, so the list's memory is not kept around
+            *overflow = Vec::new(root_alloc_ref());
+            self.overflowed.store(false, Ordering::Release);
+        }
+
+        item
+    }
+
+    fn wake_worker(&self) {
+        if self.worker_sleeping.swap(false, Ordering::AcqRel) {
+            if let Some(worker) = self.worker.get() {
+                // the worker is always suspended when it is sleeping
+                let _ = Thread::resume_suspended_thread(worker);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DeferredWorkQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeferredWorkQueue")
+            .field("overflowed", &self.overflowed)
+            .field("worker_sleeping", &self.worker_sleeping)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Queues `item` to be run by the current cpu's deferred work worker, this can be called from interrupt handlers
+///
+/// Fails with [`SysErr::OutOfMem`] if the queue is full and its overflow list can't grow.
+pub fn defer_work(item: WorkItem) -> KResult<()> {
+    let _int_disable = IntDisable::new();
+    let queue = &cpu_local_data().deferred_work;
+
+    // safety: interrupts are disabled, so nothing else can push to this cpu's queue
+    unsafe {
+        queue.push(item)?;
+    }
+
+    queue.wake_worker();
+
+    Ok(())
+}
+
+/// Runs the work deferred on the cpu this thread is pinned to
+extern "C" fn worker_main() -> ! {
+    let queue = &cpu_local_data().deferred_work;
+
+    loop {
+        // safety: the worker is the only thread which pops from its cpu's queue
+        while let Some(item) = unsafe { queue.pop() } {
+            item.run();
+        }
+
+        let int_disable = IntDisable::new();
+
+        // work deferred after the last pop did not wake the worker, since it was not sleeping yet
+        if !queue.is_empty() {
+            continue;
+        }
+
+        queue.worker_sleeping.store(true, Ordering::Release);
+        switch_current_thread_to(
+            ThreadState::Suspended,
+            int_disable,
+            PostSwitchAction::None,
+            false,
+        ).expect("no thread to switch to from deferred work worker");
+    }
+}
+
+/// Starts the deferred work worker of the current cpu
+pub(super) fn start_worker(
+    thread_group: &Arc<ThreadGroup>,
+    address_space: &Arc<AddressSpace>,
+    capability_space: &Arc<CapabilitySpace>,
+) -> KResult<()> {
+    let worker = ThreadGroup::create_kernel_thread(
+        thread_group,
+        address_space.clone(),
+        capability_space.clone(),
+        String::from_str(root_alloc_ref(), "deferred_work_worker")?,
+        ThreadStartMode::Suspended,
+        worker_main,
+    )?;
+
+    worker.set_cpu_mask(1 << prid().into());
+    // something is usually waiting on deferred work, so it runs before other threads
+    worker.set_priority(ThreadPriority::Highest);
+
+    cpu_local_data().deferred_work.worker.call_once(|| worker.clone());
+    Thread::resume_suspended_thread(&worker)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+    use crate::time::monotonic_nsec;
+
+    /// Data of each function work item, in the order they ran
+    static RUN_ORDER: [AtomicUsize; FLOOD_COUNT] = [const { AtomicUsize::new(0) }; FLOOD_COUNT];
+    static RUN_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    /// Enough work to overflow the ring several times
+    const FLOOD_COUNT: usize = 4 * DEFERRED_WORK_RING_SIZE;
+
+    fn record_run(data: usize) {
+        let index = RUN_COUNT.fetch_add(1, Ordering::AcqRel);
+        RUN_ORDER[index].store(data, Ordering::Release);
+    }
+
+    fn data_of(item: WorkItem) -> usize {
+        match item {
+            WorkItem::Function { data, .. } => data,
+            WorkItem::Closure(_) => panic!("expected function work item"),
+        }
+    }
+
+    #[test_case]
+    fn queue_keeps_order_through_overflow() {
+        let queue = DeferredWorkQueue::new();
+
+        // safety: only this thread uses the queue
+        unsafe {
+            for i in 0..(DEFERRED_WORK_RING_SIZE + 8) {
+                queue.push(WorkItem::function(record_run, i)).unwrap();
+            }
+            assert!(queue.overflowed.load(Ordering::Acquire));
+
+            // popping from the ring doesn't let new work skip ahead of the overflow list
+            assert_eq!(queue.pop().map(data_of), Some(0));
+            queue.push(WorkItem::function(record_run, DEFERRED_WORK_RING_SIZE + 8)).unwrap();
+
+            for i in 1..(DEFERRED_WORK_RING_SIZE + 9) {
+                assert_eq!(queue.pop().map(data_of), Some(i));
+            }
+            assert!(queue.pop().is_none());
+            assert!(queue.is_empty());
+        }
+    }
+
+    #[test_case]
+    fn flooded_work_from_interrupts_runs_in_order() {
+        const RUN_TIMEOUT: Duration = Duration::from_secs(1);
+
+        RUN_COUNT.store(0, Ordering::Release);
+
+        // work only keeps its order on one cpu
+        let int_disable = IntDisable::new();
+        let thread = cpu_local_data().current_thread();
+        thread.set_cpu_mask(1 << prid().into());
+
+        // like an interrupt handler, this defers more work than fits in the ring before the worker can run
+        for i in 0..(FLOOD_COUNT / 2) {
+            defer_work(WorkItem::function(record_run, i)).unwrap();
+        }
+        drop(int_disable);
+
+        // the rest is deferred while the worker may be running
+        for i in (FLOOD_COUNT / 2)..FLOOD_COUNT {
+            let work = WorkItem::closure(move || record_run(i)).unwrap();
+            defer_work(work).unwrap();
+        }
+
+        let deadline = monotonic_nsec() + RUN_TIMEOUT.as_nanos() as u64;
+        while RUN_COUNT.load(Ordering::Acquire) < FLOOD_COUNT {
+            assert!(monotonic_nsec() < deadline, "only {} work items ran", RUN_COUNT.load(Ordering::Acquire));
+            core::hint::spin_loop();
+        }
+        thread.set_cpu_mask(u64::MAX);
+
+        assert_eq!(RUN_COUNT.load(Ordering::Acquire), FLOOD_COUNT);
+        for (i, data) in RUN_ORDER.iter().enumerate() {
+            assert_eq!(data.load(Ordering::Acquire), i);
+        }
+    }
+}
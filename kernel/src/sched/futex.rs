@@ -0,0 +1,61 @@
+use crate::alloc::HeapRef;
+use crate::container::{HashMap, Vec};
+use crate::prelude::*;
+use super::{ThreadRef, WakeReason};
+
+/// Threads blocked in `futex_wait` in one address space, keyed by the user address they are waiting on
+#[derive(Debug)]
+pub struct FutexQueues {
+    waiters: HashMap<usize, Vec<ThreadRef>>,
+    allocer: HeapRef,
+}
+
+impl FutexQueues {
+    pub fn new(allocer: HeapRef) -> Self {
+        FutexQueues {
+            waiters: HashMap::new(allocer.clone()),
+            allocer,
+        }
+    }
+
+    /// Adds `thread` to the queue of threads waiting on `address`
+    pub fn add_waiter(&mut self, address: usize, thread: ThreadRef) -> KResult<()> {
+        if let Some(queue) = self.waiters.get_mut(&address) {
+            return queue.push(thread);
+        }
+
+        let mut queue = Vec::new(self.allocer.clone());
+        queue.push(thread)?;
+        self.waiters.insert(address, queue)?;
+
+        Ok(())
+    }
+
+    /// Wakes up to `count` threads waiting on `address` in the order they started waiting
+    /// 
+    /// Threads which already stopped waiting, because they timed out or were killed, are discarded and not counted
+    /// 
+    /// Returns the number of threads woken
+    pub fn wake(&mut self, address: usize, count: usize) -> usize {
+        let Some(queue) = self.waiters.get_mut(&address) else {
+            return 0;
+        };
+
+        let mut woken = 0;
+        while woken < count {
+            let Some(thread) = queue.pop_front() else {
+                break;
+            };
+
+            if thread.move_to_ready_list(WakeReason::FutexWake) {
+                woken += 1;
+            }
+        }
+
+        if queue.is_empty() {
+            self.waiters.remove(&address);
+        }
+
+        woken
+    }
+}
\ No newline at end of file
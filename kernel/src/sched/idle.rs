@@ -0,0 +1,267 @@
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+
+use spin::Once;
+
+use crate::arch::x64::{IntDisable, cpuid, monitor, rdtsc, sti_hlt, sti_mwait, tsc_ticks_to_nsec};
+use crate::config::MAX_CPUS;
+use crate::gs_data::Prid;
+use crate::int::IPI_RESCHEDULE;
+use crate::int::apic::{Ipi, IpiDest};
+use crate::prelude::*;
+
+use super::{ThreadState, PostSwitchAction, switch_current_thread_to, thread_map};
+
+/// The cpu is running something other than the idle loop, or the idle loop is about to check for ready threads again
+const BUSY: u32 = 0;
+/// The cpu is waiting in mwait, writing the wakeup word wakes it up
+const WAITING_MWAIT: u32 = 1;
+/// The cpu is waiting in hlt, a reschedule ipi is needed to wake it up
+const WAITING_HLT: u32 = 2;
+/// Another cpu has inserted a thread for the waiting cpu to run
+const KICKED: u32 = 3;
+
+/// Idle state of 1 cpu
+///
+/// Each cpu's state is on its own cache line, so the monitored wakeup word is only written when the cpu is kicked
+#[derive(Debug)]
+#[repr(align(64))]
+struct IdleState {
+    /// Monitored by the idle loop while waiting
+    wakeup: AtomicU32,
+    /// Time stamp counter when the cpu last started waiting
+    idle_entry_tsc: AtomicU64,
+    /// Time stamp counter ticks this cpu has spent waiting in the idle loop
+    idle_ticks: AtomicU64,
+}
+
+impl IdleState {
+    const fn new() -> Self {
+        IdleState {
+            wakeup: AtomicU32::new(BUSY),
+            idle_entry_tsc: AtomicU64::new(0),
+            idle_ticks: AtomicU64::new(0),
+        }
+    }
+}
+
+static IDLE_STATES: [IdleState; MAX_CPUS] = [const { IdleState::new() }; MAX_CPUS];
+
+static HAS_MWAIT: Once<bool> = Once::new();
+
+fn idle_state(cpu: Prid) -> &'static IdleState {
+    &IDLE_STATES[cpu.into()]
+}
+
+fn is_waiting(state: u32) -> bool {
+    state == WAITING_MWAIT || state == WAITING_HLT
+}
+
+/// Runs on the idle threads, waits until a thread is ready to run on the current cpu and switches to it
+///
+/// Monitor and mwait are used to wait if they are supported, so other cpus can wake this one by writing its wakeup word,
+/// otherwise hlt is used and other cpus send a reschedule ipi
+pub fn idle_loop() -> ! {
+    let has_mwait = *HAS_MWAIT.call_once(cpuid::has_monitor_mwait);
+    let waiting_state = if has_mwait { WAITING_MWAIT } else { WAITING_HLT };
+
+    loop {
+        let int_disable = IntDisable::new();
+        // the idle thread may be moved to another cpu whenever it switches away
+        let state = idle_state(prid());
+
+        // the waiting state is published before checking for threads, so a thread inserted after the check always kicks this cpu
+        state.wakeup.store(waiting_state, Ordering::SeqCst);
+
+        if thread_map().has_ready_thread() {
+            state.wakeup.store(BUSY, Ordering::SeqCst);
+
+            // ignore error, another cpu may have taken the thread first
+            let _ = switch_current_thread_to(
+                ThreadState::Ready,
+                int_disable,
+                PostSwitchAction::InsertReadyQueue,
+                false,
+            );
+            continue;
+        }
+
+        let entry_tsc = rdtsc();
+        state.idle_entry_tsc.store(entry_tsc, Ordering::Relaxed);
+
+        if has_mwait {
+            monitor(state.wakeup.as_ptr());
+
+            // a kick which happened before the monitor was armed would not end the mwait
+            if state.wakeup.load(Ordering::SeqCst) == WAITING_MWAIT {
+                sti_mwait();
+            }
+        } else {
+            // the reschedule ipi is held pending until sti, which takes effect after hlt starts
+            sti_hlt();
+        }
+
+        state.idle_ticks.fetch_add(rdtsc() - entry_tsc, Ordering::Relaxed);
+        state.wakeup.store(BUSY, Ordering::SeqCst);
+
+        drop(int_disable);
+    }
+}
+
+/// Wakes up an idle cpu in `cpu_mask` to run a thread which was just made ready
+///
+/// The cpu which has been waiting the longest is picked, since it is the least likely to have other work coming
+pub(super) fn kick_idle_cpu(cpu_mask: u64) {
+    let current_cpu = prid();
+
+    // an interrupt on a waiting cpu returns to the idle loop, which checks for ready threads again
+    if cpu_mask & (1 << current_cpu.into()) != 0 && is_waiting(idle_state(current_cpu).wakeup.load(Ordering::SeqCst)) {
+        return;
+    }
+
+    let target = (0..MAX_CPUS)
+        .filter(|cpu| *cpu != current_cpu.into() && cpu_mask & (1 << cpu) != 0)
+        .filter(|cpu| is_waiting(IDLE_STATES[*cpu].wakeup.load(Ordering::SeqCst)))
+        .min_by_key(|cpu| IDLE_STATES[*cpu].idle_entry_tsc.load(Ordering::Relaxed));
+
+    let Some(target) = target else {
+        return;
+    };
+
+    let wakeup = &IDLE_STATES[target].wakeup;
+    let Ok(old_state) = wakeup.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |state| is_waiting(state).then_some(KICKED)) else {
+        // the cpu stopped waiting, so it will see the thread when it checks the ready queue
+        return;
+    };
+
+    if old_state == WAITING_HLT {
+        cpu_local_data().local_apic().send_ipi(Ipi::To(IpiDest::to_prid(Prid::from(target)), IPI_RESCHEDULE));
+    }
+}
+
+/// Returns the time `cpu` has spent waiting in the idle loop
+pub fn cpu_idle_time(cpu: Prid) -> Duration {
+    Duration::from_nanos(tsc_ticks_to_nsec(idle_state(cpu).idle_ticks.load(Ordering::Relaxed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicUsize;
+
+    use super::*;
+    use crate::config::{SCHED_TIME, cpu_count};
+    use crate::sched::{Thread, ThreadGroup, ThreadRef, ThreadStartMode, WakeReason, online_cpu_mask};
+    use crate::sched::{KERNEL_ADDRESS_SPACE, KERNEL_CAPABILITY_SPACE, KERNEL_THREAD_GROUP};
+    use crate::alloc::root_alloc_ref;
+    use crate::sync::IMutex;
+    use crate::time::monotonic_nsec;
+
+    const WAKE_ROUNDS: usize = 32;
+
+    /// Reference to the latency test thread once it is about to suspend itself
+    static WAITER: IMutex<Option<ThreadRef>> = IMutex::new(None);
+    /// Time stamp counter when the latency test thread last started running after being woken
+    static WOKEN_TSC: AtomicU64 = AtomicU64::new(0);
+    static ROUNDS_DONE: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn waiter_main() -> ! {
+        loop {
+            let int_disable = IntDisable::new();
+
+            *WAITER.lock() = Some(ThreadRef::future_ref(&cpu_local_data().current_thread()));
+            switch_current_thread_to(
+                ThreadState::Suspended,
+                int_disable,
+                PostSwitchAction::None,
+                false,
+            ).expect("no thread to switch to from latency test thread");
+
+            WOKEN_TSC.store(rdtsc(), Ordering::Release);
+            ROUNDS_DONE.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Spins until the latency test thread has put itself in [`WAITER`], and takes it
+    fn take_waiter() -> ThreadRef {
+        const WAIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+        let deadline = monotonic_nsec() + WAIT_TIMEOUT.as_nanos() as u64;
+        loop {
+            if let Some(waiter) = WAITER.lock().take() {
+                return waiter;
+            }
+
+            assert!(monotonic_nsec() < deadline, "latency test thread never waited");
+            core::hint::spin_loop();
+        }
+    }
+
+    fn spin_for(duration: Duration) {
+        let end_nsec = monotonic_nsec() + duration.as_nanos() as u64;
+        while monotonic_nsec() < end_nsec {
+            core::hint::spin_loop();
+        }
+    }
+
+    #[test_case]
+    fn idle_cpu_wakes_quickly() {
+        // long enough that the other cpu is waiting in the idle loop when it is woken
+        const SETTLE_TIME: Duration = Duration::from_millis(1);
+
+        if cpu_count() < 2 {
+            return;
+        }
+
+        let thread = cpu_local_data().current_thread();
+        let start_cpu = prid().into();
+        thread.set_cpu_mask(1 << start_cpu);
+
+        ROUNDS_DONE.store(0, Ordering::Release);
+
+        let waiter_thread = ThreadGroup::create_kernel_thread(
+            KERNEL_THREAD_GROUP.get().unwrap(),
+            KERNEL_ADDRESS_SPACE.get().unwrap().clone(),
+            KERNEL_CAPABILITY_SPACE.get().unwrap().clone(),
+            String::from_str(root_alloc_ref(), "idle_latency_test").unwrap(),
+            ThreadStartMode::Suspended,
+            waiter_main,
+        ).unwrap();
+        // the waiter is always woken by a different cpu than the one it runs on
+        waiter_thread.set_cpu_mask(online_cpu_mask() & !(1 << start_cpu));
+        Thread::resume_suspended_thread(&waiter_thread).unwrap();
+
+        let mut latencies = [0; WAKE_ROUNDS];
+        for (round, latency) in latencies.iter_mut().enumerate() {
+            let waiter = take_waiter();
+            spin_for(SETTLE_TIME);
+
+            // this is how sync_send and futex wakes make a blocked thread ready
+            let wake_tsc = loop {
+                let wake_tsc = rdtsc();
+                if waiter.move_to_ready_list(WakeReason::None) {
+                    break wake_tsc;
+                }
+
+                // the waiter might not have finished suspending yet
+                core::hint::spin_loop();
+            };
+
+            while ROUNDS_DONE.load(Ordering::Acquire) == round {
+                core::hint::spin_loop();
+            }
+
+            *latency = tsc_ticks_to_nsec(WOKEN_TSC.load(Ordering::Acquire).saturating_sub(wake_tsc));
+        }
+
+        take_waiter();
+        while Thread::destroy_suspended_thread(&waiter_thread).is_err() {
+            core::hint::spin_loop();
+        }
+        thread.set_cpu_mask(u64::MAX);
+
+        // waiting for the next timer tick would take half a time slice on average
+        latencies.sort_unstable();
+        let median = Duration::from_nanos(latencies[WAKE_ROUNDS / 2]);
+        assert!(median < SCHED_TIME / 4, "median wakeup latency of idle cpu was {:?}", median);
+    }
+}
\ No newline at end of file
@@ -1,22 +1,32 @@
 use core::sync::atomic::Ordering;
 
 use spin::Once;
+use sys::{EventData, ThreadPriority};
 
-pub use thread::{ThreadState, Thread, ThreadRef, WakeReason};
-pub use thread_group::{ThreadGroup, ThreadStartMode};
+pub use deferred_work::{defer_work, WorkItem, DeferredWorkQueue};
+pub use futex::FutexQueues;
+pub use idle::{idle_loop, cpu_idle_time};
+pub use thread::{ThreadState, Thread, ThreadRef, WakeReason, Tid};
+pub use thread_group::{ThreadGroup, ThreadStartMode, StraceSettings, CpuTimeCounters, STRACE_NAME_MAX_LEN};
 use thread_map::ThreadMap;
 use crate::alloc::{root_alloc_ref, root_alloc_page_ref};
-use crate::arch::x64::{IntDisable, set_cr3};
+use crate::arch::x64::{IntDisable, set_cr3, rdtsc};
 use crate::cap::address_space::AddressSpace;
 use crate::cap::capability_space::CapabilitySpace;
-use crate::config::SCHED_TIME;
+use crate::config::{SCHED_TIME, cpu_count};
 use crate::prelude::*;
 use crate::sync::IMutex;
+use crate::time;
 use crate::arch::x64::asm_switch_thread;
 use crate::container::Arc;
+use crate::event::EventPoolListenerRef;
+use crate::vmem_manager::tlb::handle_shootdown;
 use timeout_queue::TimeoutQueue;
 use kernel_stack::KernelStack;
 
+mod deferred_work;
+mod futex;
+mod idle;
 pub mod kernel_stack;
 mod thread;
 mod thread_group;
@@ -34,6 +44,11 @@ pub fn timeout_queue() -> &'static IMutex<TimeoutQueue> {
     TIMEOUT_QUEUE.get().unwrap()
 }
 
+/// Cpu mask with a bit set for every cpu
+pub fn online_cpu_mask() -> u64 {
+    (1 << cpu_count()) - 1
+}
+
 /// This stores a reference to the current thread and process for easy retrieval
 /// 
 /// It is stored in the cpu local global variables
@@ -47,7 +62,8 @@ pub fn timer_handler() {
     let current_nsec = cpu_local_data().local_apic().nsec();
     let last_switch_nsec = cpu_local_data().last_thread_switch_nsec.load(Ordering::Acquire);
 
-    timeout_queue().lock().wake_threads(current_nsec);
+    // timeouts use the monotonic clock because the local apic time is different on each cpu
+    timeout_queue().lock().wake_threads(time::monotonic_nsec());
 
     if current_nsec - last_switch_nsec > SCHED_TIME.as_nanos() as u64 {
         let _ = switch_current_thread_to(
@@ -93,6 +109,13 @@ pub enum PostSwitchAction {
     InsertReadyQueue,
     /// Inserts the thread into the timeout queue to wake up at the given nanosecond
     SetTimeout(u64),
+    /// Writes an event to an event pool once the old thread is no longer running
+    /// 
+    /// If the event can't be written, the old thread is destroyed, since nothing would know to resume it
+    WriteEvent {
+        listener: EventPoolListenerRef,
+        event_data: EventData,
+    },
 }
 
 /// This is the function that runs after thread switch
@@ -108,6 +131,9 @@ extern "C" fn post_switch_handler(old_rsp: usize) {
         .expect("post switch data was none after switching threads");
 
     old_thread.rsp.store(old_rsp, Ordering::Release);
+    if !old_thread.is_current_thread() {
+        old_thread.on_cpu.store(false, Ordering::Release);
+    }
 
     match post_switch_action {
         PostSwitchAction::None => (),
@@ -119,7 +145,13 @@ extern "C" fn post_switch_handler(old_rsp: usize) {
         PostSwitchAction::SetTimeout(timeout_nsec) => timeout_queue()
             .lock()
             .insert_thread(ThreadRef::new(&old_thread), timeout_nsec)
-            .expect("failed to add thread to timeout queue")
+            .expect("failed to add thread to timeout queue"),
+        PostSwitchAction::WriteEvent { listener, event_data } => {
+            if listener.write_event(event_data).is_err() {
+                // ignore error, the thread is only not suspended if someone else already destroyed it
+                let _ = Thread::destroy_suspended_thread(&old_thread);
+            }
+        },
     }
 
     if send_eoi {
@@ -150,6 +182,20 @@ pub fn switch_current_thread_to(state: ThreadState, _int_disable: IntDisable, po
 
     let old_thread = global_sched_state.current_thread.clone();
 
+    // a thread can be made ready before the cpu it last ran on has saved its rsp in the post switch handler
+    if !Arc::ptr_eq(&old_thread, &new_thread) {
+        while new_thread.on_cpu.load(Ordering::Acquire) {
+            handle_shootdown();
+            core::hint::spin_loop();
+        }
+        new_thread.on_cpu.store(true, Ordering::Relaxed);
+    }
+
+    // charge the time since the last switch to the old thread
+    let current_tsc = rdtsc();
+    let last_switch_tsc = cpu_local_data().last_thread_switch_tsc.swap(current_tsc, Ordering::Relaxed);
+    old_thread.add_time_slice(current_tsc - last_switch_tsc);
+
     // change all thread states that need to be changed
     old_thread.set_state(state);
     new_thread.set_state(ThreadState::Running);
@@ -158,12 +204,22 @@ pub fn switch_current_thread_to(state: ThreadState, _int_disable: IntDisable, po
     let new_rsp = new_thread.rsp.load(Ordering::Acquire);
     let new_addr_space = new_thread.address_space().get_cr3().as_usize();
 
+    // track which cpus have each address space loaded for tlb shootdowns,
+    // the new address space must be marked before it is loaded so no shootdown is missed
+    if !Arc::ptr_eq(old_thread.address_space(), new_thread.address_space()) {
+        new_thread.address_space().active_cpus().insert(prid());
+        // the old address space's tlb entries are flushed when cr3 is loaded
+        old_thread.address_space().active_cpus().remove(prid());
+    }
+
     new_thread.load_thread_local_pointer();
 
     // set syscall rsp
     cpu_local_data().syscall_rsp.store(new_thread.syscall_rsp(), Ordering::Release);
     // set interrupt rsp (rsp0 in tss is used when cpl of interrupts changes)
     cpu_local_data().tss.lock().rsp0 = new_thread.syscall_rsp() as u64;
+    // set strace settings checked by syscall entry
+    cpu_local_data().set_current_strace(new_thread.strace());
 
     // change current thread and process in the scheduler state
     global_sched_state.current_thread = new_thread;
@@ -200,7 +256,7 @@ static KERNEL_CAPABILITY_SPACE: Once<Arc<CapabilitySpace>> = Once::new();
 /// Initializes thread group, address space, and capability space used by kernel threads
 pub fn init_kernel_context() -> KResult<()> {
     let thread_group = Arc::new(
-        ThreadGroup::new(root_alloc_page_ref(), root_alloc_ref()),
+        ThreadGroup::new(root_alloc_page_ref(), root_alloc_ref())?,
         root_alloc_ref(),
     )?;
 
@@ -228,6 +284,7 @@ pub fn init_cpu_local(stack: AVirtRange) -> KResult<()> {
     let capability_space = KERNEL_CAPABILITY_SPACE.get().unwrap();
 
     set_cr3(address_space.get_cr3().as_usize());
+    address_space.active_cpus().insert(prid());
 
     let thread = Arc::new(
         Thread::new(
@@ -236,6 +293,8 @@ pub fn init_cpu_local(stack: AVirtRange) -> KResult<()> {
             // rsp will be set on next switch
             0,
             Arc::downgrade(thread_group),
+            thread_group.strace().clone(),
+            thread_group.cpu_time().clone(),
             address_space.clone(),
             capability_space.clone(),
             root_alloc_ref(),
@@ -243,13 +302,17 @@ pub fn init_cpu_local(stack: AVirtRange) -> KResult<()> {
         root_alloc_ref(),
     )?;
 
+    // the idle thread should only run when nothing else can, aging still lets it run once in a while
+    thread.set_priority(ThreadPriority::Low);
     thread_group.add_thread(thread.clone())?;
 
     cpu_local_data().syscall_rsp.store(thread.syscall_rsp(), Ordering::Release);
+    cpu_local_data().set_current_strace(thread.strace());
+    cpu_local_data().last_thread_switch_tsc.store(rdtsc(), Ordering::Relaxed);
 
     cpu_local_data().set_sched_state(SchedState {
         current_thread: thread,
     });
 
-    Ok(())
+    deferred_work::start_worker(thread_group, address_space, capability_space)
 }
\ No newline at end of file
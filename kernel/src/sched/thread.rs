@@ -1,9 +1,10 @@
-use core::sync::atomic::{AtomicUsize, Ordering, AtomicBool};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering, AtomicBool};
+use core::time::Duration;
 
-use sys::{EventData, ThreadExit};
+use sys::{EventData, ExitReason, ThreadExit, ThreadPriority};
 
 use crate::alloc::HeapRef;
-use crate::arch::x64::{wrmsr, FSBASE_MSR};
+use crate::arch::x64::{wrmsr, rdtsc, tsc_ticks_to_nsec, FSBASE_MSR};
 use crate::cap::CapObject;
 use crate::cap::capability_space::CapabilitySpace;
 use crate::cap::address_space::AddressSpace;
@@ -12,12 +13,17 @@ use crate::container::Arc;
 use crate::event::{BroadcastEventEmitter, BroadcastEventListener};
 use crate::sync::IMutex;
 use super::kernel_stack::KernelStack;
-use super::{thread_map, ThreadGroup};
+use super::{thread_map, ThreadGroup, StraceSettings, CpuTimeCounters};
 use crate::container::Weak;
 use crate::prelude::*;
 
 use sys::CapType;
 
+crate::make_id_type!(Tid);
+
+/// Used to give every thread a unique tid
+static NEXT_TID: AtomicUsize = AtomicUsize::new(0);
+
 /// Amount status must be incramented to change generation without changing ThreadState
 const GENERATION_STEP_SIZE: usize = 0b100;
 
@@ -49,6 +55,16 @@ impl ThreadState {
     }
 }
 
+/// Whether a thread is parked, or has a wake token for its next park
+#[derive(Debug)]
+enum ParkState {
+    Empty,
+    /// The thread was unparked while it was not parked, so its next park returns immediately
+    Token,
+    /// The thread is parked, or about to park, and is woken through this reference
+    Parked(ThreadRef),
+}
+
 /// Notifies a thread why it was woken up
 #[derive(Debug, Clone, Copy)]
 pub enum WakeReason {
@@ -61,26 +77,51 @@ pub enum WakeReason {
     },
     /// Thread was woken up after recieving a message
     MsgRecv(RecieveResult),
+    /// Thread was woken up because a message could not be written into its recieve buffer
+    MsgRecvError(SysErr),
+    /// Thread was woken up because the channel or reply it was waiting on was destroyed
+    PeerGone,
     /// The event pool this thread was waiting on recieved an event
     EventPoolEventRecieved {
         event_range: UVirtRange,
+        event_count: usize,
     },
     /// An event was recieved
     EventRecieved(EventData),
+    /// Another thread called `futex_wake` on the address this thread was waiting on
+    FutexWake,
+    /// Another thread called `thread_unpark` on this thread while it was parked
+    Unparked,
 }
 
 #[derive(Debug)]
 pub struct Thread {
     name: String,
+    tid: Tid,
     status: AtomicUsize,
+    /// A [`ThreadPriority`], which decides which ready queue this thread is inserted into
+    priority: AtomicUsize,
+    /// Bit n is set if this thread may run on cpu n, all bits are set by default
+    cpu_mask: AtomicU64,
     wake_reason: IMutex<WakeReason>,
+    park_state: IMutex<ParkState>,
     pub is_alive: AtomicBool,
+    /// Sent to exit event listeners when the thread is dropped, or exit code 0 if it was never set
+    exit_reason: IMutex<Option<ExitReason>>,
     // this has to be atomic usize because it is written to in assembly
     pub rsp: AtomicUsize,
+    /// Set from when a cpu switches to this thread until the cpu has saved `rsp` after switching away from it
+    pub on_cpu: AtomicBool,
     // address of thread local data for userspace
     pub thread_local_pointer: AtomicUsize,
     kernel_stack: KernelStack,
     thread_group: Weak<ThreadGroup>,
+    /// Strace settings of this thread's thread group, kept here so the syscall entry path doesn't have to upgrade `thread_group`
+    strace: Arc<StraceSettings>,
+    /// Time stamp counter ticks this thread has spent running
+    runtime_ticks: AtomicU64,
+    /// Cpu time counters of this thread's thread group
+    group_cpu_time: Arc<CpuTimeCounters>,
     address_space: Arc<AddressSpace>,
     capability_space: Arc<CapabilitySpace>,
     exit_event: IMutex<BroadcastEventEmitter>,
@@ -92,25 +133,65 @@ impl Thread {
         kernel_stack: KernelStack,
         rsp: usize,
         thread_group: Weak<ThreadGroup>,
+        strace: Arc<StraceSettings>,
+        group_cpu_time: Arc<CpuTimeCounters>,
         address_space: Arc<AddressSpace>,
         capability_space: Arc<CapabilitySpace>,
         heap_ref: HeapRef,
     ) -> Self {
         Thread {
             name,
+            tid: Tid::from(NEXT_TID.fetch_add(1, Ordering::Relaxed)),
             status: AtomicUsize::new(ThreadState::Suspended.to_status(0)),
+            priority: AtomicUsize::new(ThreadPriority::default() as usize),
+            cpu_mask: AtomicU64::new(u64::MAX),
             wake_reason: IMutex::new(WakeReason::None),
+            park_state: IMutex::new(ParkState::Empty),
             is_alive: AtomicBool::new(true),
+            exit_reason: IMutex::new(None),
             rsp: AtomicUsize::new(rsp),
+            // idle threads are made from the stack their cpu is already running on
+            on_cpu: AtomicBool::new(matches!(kernel_stack, KernelStack::Existing(_))),
             thread_local_pointer: AtomicUsize::new(0),
             kernel_stack,
             thread_group,
+            strace,
+            runtime_ticks: AtomicU64::new(0),
+            group_cpu_time,
             address_space,
             capability_space,
             exit_event: IMutex::new(BroadcastEventEmitter::new(heap_ref)),
         }
     }
 
+    pub fn tid(&self) -> Tid {
+        self.tid
+    }
+
+    pub fn strace(&self) -> &Arc<StraceSettings> {
+        &self.strace
+    }
+
+    /// Records that this thread was switched away from after running for `ticks` time stamp counter ticks
+    #[inline(always)]
+    pub fn add_time_slice(&self, ticks: u64) {
+        self.runtime_ticks.fetch_add(ticks, Ordering::Relaxed);
+        self.group_cpu_time.add_time_slice(ticks);
+    }
+
+    /// Total time this thread has spent running
+    /// 
+    /// If this thread is running on the current cpu its current time slice is included,
+    /// but time slices on other cpus are not counted until they end
+    pub fn runtime(&self) -> Duration {
+        let mut ticks = self.runtime_ticks.load(Ordering::Relaxed);
+        if self.is_current_thread() {
+            ticks += rdtsc() - cpu_local_data().last_thread_switch_tsc.load(Ordering::Relaxed);
+        }
+
+        Duration::from_nanos(tsc_ticks_to_nsec(ticks))
+    }
+
     pub fn address_space(&self) -> &Arc<AddressSpace> {
         &self.address_space
     }
@@ -119,6 +200,10 @@ impl Thread {
         &self.capability_space
     }
 
+    pub fn thread_group(&self) -> &Weak<ThreadGroup> {
+        &self.thread_group
+    }
+
     /// This is the rsp value loaded when a syscall occurs for this thread
     pub fn syscall_rsp(&self) -> usize {
         self.kernel_stack.stack_top().as_usize()
@@ -147,6 +232,40 @@ impl Thread {
         self.is_alive.load(Ordering::Acquire)
     }
 
+    pub fn priority(&self) -> ThreadPriority {
+        ThreadPriority::from_repr(self.priority.load(Ordering::Relaxed))
+            .expect("invalid thread priority")
+    }
+
+    /// Sets the priority of this thread, which takes effect the next time it is inserted into the ready queue
+    pub fn set_priority(&self, priority: ThreadPriority) {
+        self.priority.store(priority as usize, Ordering::Relaxed);
+    }
+
+    pub fn cpu_mask(&self) -> u64 {
+        self.cpu_mask.load(Ordering::Relaxed)
+    }
+
+    /// Sets which cpus this thread may run on, bit n allows cpu n
+    ///
+    /// Like the priority, this takes effect the next time the thread is inserted into the ready queue,
+    /// so a running thread keeps running on a cpu which is no longer allowed until it is switched away from
+    pub fn set_cpu_mask(&self, cpu_mask: u64) {
+        self.cpu_mask.store(cpu_mask, Ordering::Relaxed);
+    }
+
+    /// Returns true if this is one of the idle threads made from each cpu's startup stack
+    pub fn is_idle_thread(&self) -> bool {
+        matches!(self.kernel_stack, KernelStack::Existing(_))
+    }
+
+    /// Sets the reason reported to exit event listeners when this thread exits
+    ///
+    /// Only the first reason is kept, so a thread which faulted is not reported as killed when its thread group later exits
+    pub fn set_exit_reason(&self, reason: ExitReason) {
+        self.exit_reason.lock().get_or_insert(reason);
+    }
+
     /// Gets the wake reason of this thread
     pub fn wake_reason(&self) -> WakeReason {
         *self.wake_reason.lock()
@@ -219,6 +338,57 @@ impl Thread {
         }
     }
 
+    /// Prepares the current thread to park, unless it has a wake token
+    /// 
+    /// Returns false if the token was consumed and the thread should not park,
+    /// otherwise the thread must suspend itself before interrupts are enabled again
+    pub fn prepare_park(thread: &Arc<Thread>) -> bool {
+        let mut park_state = thread.park_state.lock();
+
+        match *park_state {
+            ParkState::Token => {
+                *park_state = ParkState::Empty;
+                false
+            },
+            _ => {
+                *park_state = ParkState::Parked(ThreadRef::future_ref(thread));
+                true
+            },
+        }
+    }
+
+    /// Called by the current thread after it wakes up from parking
+    pub fn finish_park(&self) {
+        let mut park_state = self.park_state.lock();
+
+        // the park timed out, so the reference to wake it is stale
+        if matches!(*park_state, ParkState::Parked(_)) {
+            *park_state = ParkState::Empty;
+        }
+    }
+
+    /// Wakes this thread if it is parked, otherwise gives it a wake token so its next park returns immediately
+    pub fn unpark(&self) {
+        loop {
+            let thread_ref = {
+                let mut park_state = self.park_state.lock();
+
+                match core::mem::replace(&mut *park_state, ParkState::Token) {
+                    ParkState::Parked(thread_ref) => {
+                        *park_state = ParkState::Empty;
+                        thread_ref
+                    },
+                    _ => return,
+                }
+            };
+
+            // this only fails if the park already timed out, then the token is left for the next park instead
+            if thread_ref.move_to_ready_list(WakeReason::Unparked) {
+                return;
+            }
+        }
+    }
+
     pub fn add_exit_event_listener(&self, listener: BroadcastEventListener) -> KResult<()> {
         self.exit_event.lock().add_listener(listener)
     }
@@ -227,7 +397,8 @@ impl Thread {
 impl Drop for Thread {
     fn drop(&mut self) {
         // ignore errors, no where to report them
-        let _ = self.exit_event.lock().emit_event(EventData::ThreadExit(ThreadExit));
+        let reason = self.exit_reason.lock().unwrap_or(ExitReason::Code(0));
+        let _ = self.exit_event.lock().emit_event(EventData::ThreadExit(ThreadExit::new(reason)));
     }
 }
 
@@ -314,4 +485,77 @@ impl ThreadRef {
 
         true
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x64::IntDisable;
+    use crate::config::{cpu_count, MAX_CPUS};
+    use crate::time::monotonic_nsec;
+
+    #[test_case]
+    fn runtime_includes_current_time_slice() {
+        const SPIN_TIME: Duration = Duration::from_millis(5);
+
+        // the test thread can't be switched away from while spinning, so all of the spin time is its runtime
+        let _int_disable = IntDisable::new();
+
+        let thread = cpu_local_data().current_thread();
+        let start_runtime = thread.runtime();
+
+        let start_tsc = rdtsc();
+        while tsc_ticks_to_nsec(rdtsc() - start_tsc) < SPIN_TIME.as_nanos() as u64 {
+            core::hint::spin_loop();
+        }
+
+        let runtime = thread.runtime() - start_runtime;
+        assert!(runtime >= SPIN_TIME && runtime < 2 * SPIN_TIME, "implausible runtime after spinning: {:?}", runtime);
+    }
+    /// Spins until the current thread is running on `cpu`, the thread's cpu mask must only allow `cpu`
+    fn wait_for_migration(cpu: usize) {
+        const MIGRATE_TIMEOUT: Duration = Duration::from_secs(1);
+
+        // the new mask takes effect the next time the thread is switched away from
+        let deadline = monotonic_nsec() + MIGRATE_TIMEOUT.as_nanos() as u64;
+        while prid().into() != cpu {
+            assert!(monotonic_nsec() < deadline, "thread was not moved to cpu {}", cpu);
+            core::hint::spin_loop();
+        }
+    }
+
+    #[test_case]
+    fn pinned_thread_only_runs_on_its_cpu() {
+        const PINNED_CPU: usize = 1;
+        const RUN_TIME: Duration = Duration::from_millis(100);
+
+        if cpu_count() <= PINNED_CPU {
+            return;
+        }
+
+        let thread = cpu_local_data().current_thread();
+        let start_cpu = prid().into();
+
+        thread.set_cpu_mask(1 << PINNED_CPU);
+        wait_for_migration(PINNED_CPU);
+
+        // the thread is switched away from several times while running, which could move it if the mask was ignored
+        let mut runs_on_cpu = [0usize; MAX_CPUS];
+        let end_nsec = monotonic_nsec() + RUN_TIME.as_nanos() as u64;
+        while monotonic_nsec() < end_nsec {
+            runs_on_cpu[prid().into()] += 1;
+        }
+
+        // the other tests expect to run on the startup core
+        thread.set_cpu_mask(1 << start_cpu);
+        wait_for_migration(start_cpu);
+        thread.set_cpu_mask(u64::MAX);
+
+        assert!(runs_on_cpu[PINNED_CPU] > 0);
+        for (cpu, runs) in runs_on_cpu.iter().enumerate() {
+            if cpu != PINNED_CPU {
+                assert_eq!(*runs, 0, "pinned thread ran on cpu {}", cpu);
+            }
+        }
+    }
 }
\ No newline at end of file
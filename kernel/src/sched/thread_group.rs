@@ -1,9 +1,17 @@
+use core::cmp::min;
 use core::slice;
+use core::time::Duration;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use arrayvec::ArrayString;
+use sys::{ExitReason, FaultRecord, ThreadGroupStats};
 
 use crate::alloc::{HeapRef, PaRef};
-use crate::arch::x64::{IntDisable, asm_thread_init};
+use crate::arch::x64::{IntDisable, asm_thread_init, asm_kernel_thread_init, tsc_ticks_to_nsec};
 use crate::cap::address_space::AddressSpace;
 use crate::cap::capability_space::CapabilitySpace;
+use crate::cap::channel::Channel;
+use crate::cap::memory::Memory;
 use crate::int::IPI_PROCESS_EXIT;
 use crate::int::apic::{Ipi, IpiDest};
 use crate::cap::{CapObject, CapType};
@@ -28,6 +36,97 @@ pub enum ThreadGroupChild {
     Thread(Arc<Thread>),
 }
 
+/// Maximum length in bytes of the process name printed in strace output, longer names are truncated
+pub const STRACE_NAME_MAX_LEN: usize = 32;
+
+/// Controls which syscalls made by threads in a thread group are traced
+///
+/// This is shared with every thread in the group so the syscall entry path can check it without locking
+#[derive(Debug, Default)]
+pub struct StraceSettings {
+    enabled: AtomicBool,
+    /// Bit n is set if syscall number n is traced
+    syscall_mask: [AtomicU64; 2],
+    process_name: IMutex<ArrayString<STRACE_NAME_MAX_LEN>>,
+}
+
+impl StraceSettings {
+    /// Returns true if any syscalls are being traced
+    ///
+    /// This is checked on every syscall, so it is only a relaxed load
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if `syscall_num` should be traced
+    pub fn is_traced(&self, syscall_num: u32) -> bool {
+        let syscall_num = syscall_num as usize;
+        let Some(mask) = self.syscall_mask.get(syscall_num / 64) else {
+            return false;
+        };
+
+        self.is_enabled() && mask.load(Ordering::Relaxed) & (1 << (syscall_num % 64)) != 0
+    }
+
+    /// Sets which syscalls are traced, and the process name which is printed in the output
+    ///
+    /// `process_name` is truncated to [`STRACE_NAME_MAX_LEN`] bytes
+    pub fn set(&self, enabled: bool, syscall_mask: u128, process_name: &str) {
+        let mut name_len = min(process_name.len(), STRACE_NAME_MAX_LEN);
+        while !process_name.is_char_boundary(name_len) {
+            name_len -= 1;
+        }
+
+        let mut name = self.process_name.lock();
+        name.clear();
+        name.push_str(&process_name[..name_len]);
+        drop(name);
+
+        self.syscall_mask[0].store(syscall_mask as u64, Ordering::Relaxed);
+        self.syscall_mask[1].store((syscall_mask >> 64) as u64, Ordering::Relaxed);
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn process_name(&self) -> ArrayString<STRACE_NAME_MAX_LEN> {
+        *self.process_name.lock()
+    }
+}
+
+/// Cpu time used by the threads in a thread group
+///
+/// This is shared with every thread in the group so the scheduler can update it on every thread switch without upgrading the thread group
+#[derive(Debug, Default)]
+pub struct CpuTimeCounters {
+    /// Time stamp counter ticks spent running
+    runtime_ticks: AtomicU64,
+    /// Number of times a thread in the group was switched away from
+    context_switches: AtomicU64,
+}
+
+impl CpuTimeCounters {
+    /// Records that a thread in the group was switched away from after running for `ticks` time stamp counter ticks
+    #[inline(always)]
+    pub fn add_time_slice(&self, ticks: u64) {
+        self.runtime_ticks.fetch_add(ticks, Ordering::Relaxed);
+        self.context_switches.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Maximum number of bytes of a faulting thread's stack which are captured
+pub const FAULT_STACK_CAPTURE_MAX_SIZE: usize = 16 * PAGE_SIZE;
+
+/// Registers and stack of the first thread in a thread group which was killed by a fault
+#[derive(Debug)]
+struct FaultCapture {
+    record: Option<FaultRecord>,
+    /// Memory the stack is copied to, if it is None only the registers are captured
+    memory: Option<Arc<Memory>>,
+    /// The stack is read into this before it is written to `memory`,
+    /// it is allocated ahead of time so the fault path doesn't need to allocate
+    stack_buffer: Vec<u8>,
+}
+
 /// Capability that allows spawning processess, and manages destroying process groups
 // FIXME: figure out how drop will work
 #[derive(Debug)]
@@ -35,15 +134,69 @@ pub struct ThreadGroup {
     thread_list: IMutex<Vec<ThreadGroupChild>>,
     heap_allocator: HeapRef,
     page_allocator: PaRef,
+    strace: Arc<StraceSettings>,
+    cpu_time: Arc<CpuTimeCounters>,
+    /// Set when the thread group dies, and reported as the exit reason of every thread in the group
+    exit_reason: IMutex<Option<ExitReason>>,
+    /// Channels which threads in this group have queued waiters on
+    /// 
+    /// When the group exits its waiters are removed from these channels right away,
+    /// instead of staying queued until something tries to send to them.
+    /// Entries for destroyed channels are reused, so this only grows with the number of live channels.
+    channel_waits: IMutex<Vec<Weak<Channel>>>,
+    fault_capture: IMutex<FaultCapture>,
 }
 
 impl ThreadGroup {
-    pub fn new(page_allocator: PaRef, heap_allocator: HeapRef) -> Self {
-        ThreadGroup {
-            thread_list: IMutex::new(Vec::new(heap_allocator.clone())),
+    pub fn new(page_allocator: PaRef, heap_allocator: HeapRef) -> KResult<Self> {
+        Ok(ThreadGroup {
+            thread_list: IMutex::named("thread_group_thread_list", Vec::new(heap_allocator.clone())),
+            channel_waits: IMutex::named("thread_group_channel_waits", Vec::new(heap_allocator.clone())),
+            strace: Arc::new(StraceSettings::default(), heap_allocator.clone())?,
+            cpu_time: Arc::new(CpuTimeCounters::default(), heap_allocator.clone())?,
+            fault_capture: IMutex::named("thread_group_fault_capture", FaultCapture {
+                record: None,
+                memory: None,
+                stack_buffer: Vec::new(heap_allocator.clone()),
+            }),
             heap_allocator,
             page_allocator,
+            exit_reason: IMutex::named("thread_group_exit_reason", None),
+        })
+    }
+
+    /// Strace settings shared by every thread in this group, child thread groups have their own settings
+    pub fn strace(&self) -> &Arc<StraceSettings> {
+        &self.strace
+    }
+
+    /// Cpu time counters shared by every thread in this group, child thread groups have their own counters
+    pub fn cpu_time(&self) -> &Arc<CpuTimeCounters> {
+        &self.cpu_time
+    }
+
+    /// Returns the cpu time used by this group and the number of threads directly in this group in each state
+    /// 
+    /// The runtime does not include time slices of threads which are currently running
+    pub fn stats(&self) -> ThreadGroupStats {
+        let mut stats = ThreadGroupStats {
+            runtime: Duration::from_nanos(tsc_ticks_to_nsec(self.cpu_time.runtime_ticks.load(Ordering::Relaxed))),
+            context_switches: self.cpu_time.context_switches.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+
+        for child in self.thread_list.lock().iter() {
+            if let ThreadGroupChild::Thread(thread) = child {
+                match thread.get_state() {
+                    ThreadState::Running => stats.running_threads += 1,
+                    ThreadState::Ready => stats.ready_threads += 1,
+                    ThreadState::Suspended => stats.suspended_threads += 1,
+                    ThreadState::Dead => (),
+                }
+            }
         }
+
+        stats
     }
 
     pub fn add_thread(&self, thread: Arc<Thread>) -> KResult<()> {
@@ -72,6 +225,49 @@ impl ThreadGroup {
         start_mode: ThreadStartMode,
         rip: usize,
         rsp: usize,
+    ) -> KResult<Arc<Thread>> {
+        // setup stack the first thing the new thread does is
+        // load the specified registers and jump to userspace code
+        Self::create_thread_inner(
+            this,
+            address_space,
+            capability_space,
+            name,
+            start_mode,
+            &[rsp, rip, asm_thread_init as usize],
+        )
+    }
+
+    /// Creates a thread which runs `entry` in the kernel instead of jumping to userspace
+    pub fn create_kernel_thread(
+        this: &Arc<Self>,
+        address_space: Arc<AddressSpace>,
+        capability_space: Arc<CapabilitySpace>,
+        name: String,
+        start_mode: ThreadStartMode,
+        entry: extern "C" fn() -> !,
+    ) -> KResult<Arc<Thread>> {
+        // the 0 is where the return address would be if `entry` was called
+        Self::create_thread_inner(
+            this,
+            address_space,
+            capability_space,
+            name,
+            start_mode,
+            &[0, entry as usize, asm_kernel_thread_init as usize],
+        )
+    }
+
+    /// Creates a thread whose kernel stack starts with `initial_stack`, followed by what thread switching restores
+    ///
+    /// The last value in `initial_stack` is returned to the first time the thread is switched to
+    fn create_thread_inner(
+        this: &Arc<Self>,
+        address_space: Arc<AddressSpace>,
+        capability_space: Arc<CapabilitySpace>,
+        name: String,
+        start_mode: ThreadStartMode,
+        initial_stack: &[usize],
     ) -> KResult<Arc<Thread>> {
         let kernel_stack = KernelStack::new(this.page_allocator.clone())?;
 
@@ -89,11 +285,10 @@ impl ThreadGroup {
             push_index += 1;
         };
 
-        // setup stack the first thing the new thread does is
-        // load the specified registers and jump to userspace code
-        push(rsp);
-        push(rip);
-        push(asm_thread_init as usize);
+        for value in initial_stack {
+            push(*value);
+        }
+        // registers and rflags restored by asm_switch_thread
         push(0);
         push(0);
         push(0);
@@ -109,6 +304,8 @@ impl ThreadGroup {
                 kernel_stack,
                 kernel_rsp.as_usize(),
                 Arc::downgrade(this),
+                this.strace.clone(),
+                this.cpu_time.clone(),
                 address_space,
                 capability_space,
                 this.heap_allocator.clone(),
@@ -139,7 +336,7 @@ impl ThreadGroup {
 
     pub fn create_child_thread_group(&self, page_allocator: PaRef, heap_allocator: HeapRef) -> KResult<Arc<Self>> {
         let thread_group = Arc::new(
-            Self::new(page_allocator, heap_allocator.clone()),
+            Self::new(page_allocator, heap_allocator.clone())?,
             heap_allocator,
         )?;
 
@@ -148,9 +345,104 @@ impl ThreadGroup {
         Ok(thread_group)
     }
 
+    /// Why this thread group died, or None if it is still alive
+    pub fn exit_reason(&self) -> Option<ExitReason> {
+        *self.exit_reason.lock()
+    }
+
+    /// Registers and stack size of the first thread in this group which was killed by a fault
+    pub fn fault_record(&self) -> Option<FaultRecord> {
+        self.fault_capture.lock().record
+    }
+
+    /// Sets the memory the stack of a faulting thread is copied to
+    /// 
+    /// At most [`FAULT_STACK_CAPTURE_MAX_SIZE`] bytes are captured. The pages of `memory` are allocated now,
+    /// so writing the stack when a thread faults doesn't need to allocate.
+    /// 
+    /// # Returns
+    /// 
+    /// [`SysErr::InvlPerm`] if `memory` is sealed
+    pub fn set_fault_capture(&self, memory: Arc<Memory>) -> KResult<()> {
+        let capture_size = {
+            let mut inner = memory.inner_write();
+            if inner.is_sealed() {
+                return Err(SysErr::InvlPerm);
+            }
+
+            let capture_size = min(inner.size().bytes(), FAULT_STACK_CAPTURE_MAX_SIZE);
+            for page_index in 0..(capture_size / PAGE_SIZE) {
+                inner.get_page_for_writing(page_index)?;
+            }
+
+            capture_size
+        };
+
+        let mut stack_buffer = Vec::try_with_capacity(self.heap_allocator.clone(), capture_size)?;
+        stack_buffer.extend(core::iter::repeat(0).take(capture_size))?;
+
+        let mut fault_capture = self.fault_capture.lock();
+        fault_capture.memory = Some(memory);
+        fault_capture.stack_buffer = stack_buffer;
+
+        Ok(())
+    }
+
+    /// Records the registers of a thread in this group which is being killed by a fault
+    /// 
+    /// Only the first fault is recorded. `read_stack` is called with a buffer to read the stack into,
+    /// and returns how many bytes it read, which are then copied to the fault capture memory.
+    pub fn record_fault(&self, mut record: FaultRecord, read_stack: impl FnOnce(&mut [u8]) -> usize) {
+        let mut fault_capture = self.fault_capture.lock();
+        if fault_capture.record.is_some() {
+            return;
+        }
+
+        let FaultCapture { memory, stack_buffer, .. } = &mut *fault_capture;
+        record.stack_size = 0;
+
+        if let Some(memory) = memory && !stack_buffer.is_empty() {
+            let stack_size = read_stack(stack_buffer.as_mut_slice());
+
+            // the pages were allocated by set_fault_capture, but the memory could have been resized since then
+            if let Ok(written) = memory.inner_write().copy_from(0..stack_size, &stack_buffer[..stack_size]) {
+                record.stack_size = written.bytes();
+            }
+        }
+
+        fault_capture.record = Some(record);
+    }
+
+    /// Records that a thread in this group queued a waiter on `channel`, so the waiter can be removed when this group exits
+    /// 
+    /// This must be called with `channel` locked. Either the exit sweep will see `channel` and wait for the lock,
+    /// or this fails with `InvlOp` because the group already exited, and the waiter must not be queued.
+    pub fn add_channel_wait(&self, channel: &Arc<Channel>) -> KResult<()> {
+        let mut channel_waits = self.channel_waits.lock();
+
+        if self.exit_reason().is_some() {
+            return Err(SysErr::InvlOp);
+        }
+
+        let channel_ptr = Arc::as_ptr(channel);
+        if channel_waits.iter().any(|waited_channel| waited_channel.as_ptr() == channel_ptr) {
+            return Ok(());
+        }
+
+        let weak_channel = Arc::downgrade(channel);
+        match channel_waits.iter_mut().find(|waited_channel| waited_channel.strong_count() == 0) {
+            Some(dead_channel) => *dead_channel = weak_channel,
+            None => channel_waits.push(weak_channel)?,
+        }
+
+        Ok(())
+    }
+
     /// Kills all threads in this thread group, including the current thread
-    pub fn exit(this: Arc<Self>) {
-        let kill_self = this.exit_inner();
+    ///
+    /// Threads in child thread groups are reported as [`ExitReason::Killed`]
+    pub fn exit(this: Arc<Self>, reason: ExitReason) {
+        let kill_self = this.exit_inner(reason);
 
         cpu_local_data().local_apic().send_ipi(Ipi::To(IpiDest::AllExcludeThis, IPI_PROCESS_EXIT));
 
@@ -169,10 +461,14 @@ impl ThreadGroup {
 
     /// Kills all threads that this thread group or its child thread groups contain
     /// 
+    /// `reason` is only recorded if this thread group has not already died
+    /// 
     /// # Returns
     /// 
     /// true if the current thread is in this group, which means the caller should kill itself
-    fn exit_inner(&self) -> bool {
+    fn exit_inner(&self, reason: ExitReason) -> bool {
+        let reason = *self.exit_reason.lock().get_or_insert(reason);
+
         let mut thread_list = self.thread_list.lock();
 
         let mut kill_self = false;
@@ -180,6 +476,7 @@ impl ThreadGroup {
         while let Some(child) = thread_list.pop() {
             match child {
                 ThreadGroupChild::Thread(thread) => {
+                    thread.set_exit_reason(reason);
                     thread.set_dead();
                     if thread.is_current_thread() {
                         kill_self = true;
@@ -192,22 +489,39 @@ impl ThreadGroup {
                         continue;
                     };
 
-                    if thread_group.exit_inner() {
+                    if thread_group.exit_inner(ExitReason::Killed) {
                         kill_self = true;
                     }
                 }
             }
         }
+        drop(thread_list);
+
+        self.remove_channel_waiters();
 
         kill_self
     }
+
+    /// Removes all channel waiters queued by threads in this group, which must have already exited
+    fn remove_channel_waiters(&self) {
+        let channel_waits = core::mem::replace(
+            &mut *self.channel_waits.lock(),
+            Vec::new(self.heap_allocator.clone()),
+        );
+
+        for channel in channel_waits.iter() {
+            if let Some(channel) = channel.upgrade() {
+                channel.remove_waiters_from(self);
+            }
+        }
+    }
 }
 
 impl Drop for ThreadGroup {
     // This doesn't kill the current thread, so it will run a bit before scheduler decides to switch to another thread
     // TODO: figure out how to have drop communicate to switch to new thread
     fn drop(&mut self) {
-        let _kill_self = self.exit_inner();
+        let _kill_self = self.exit_inner(ExitReason::Killed);
 
         cpu_local_data().local_apic().send_ipi(Ipi::To(IpiDest::AllExcludeThis, IPI_PROCESS_EXIT));
     }
@@ -1,34 +1,114 @@
+use sys::ThreadPriority;
+
 use crate::alloc::HeapRef;
+use crate::config::SCHED_AGING_THRESHOLD;
 use crate::container::{Arc, Weak, Vec};
 use crate::sync::IMutex;
 use crate::prelude::*;
 
 use super::Thread;
+use super::idle::kick_idle_cpu;
+
+/// Number of [`ThreadPriority`] levels
+const PRIORITY_LEVELS: usize = ThreadPriority::Highest as usize + 1;
+
+/// One queue for each thread priority
+///
+/// The highest priority non empty queue is picked from, unless a lower priority queue
+/// has been passed over [`SCHED_AGING_THRESHOLD`] times, then that queue is picked from once
+#[derive(Debug)]
+struct ReadyQueues<T> {
+    // TODO: use a better data structure than a vec
+    queues: [Vec<T>; PRIORITY_LEVELS],
+    /// Number of times each queue was not empty but a higher priority queue was picked from
+    passed_over: [usize; PRIORITY_LEVELS],
+}
+
+impl<T> ReadyQueues<T> {
+    fn new(allocer: HeapRef) -> Self {
+        ReadyQueues {
+            queues: core::array::from_fn(|_| Vec::new(allocer.clone())),
+            passed_over: [0; PRIORITY_LEVELS],
+        }
+    }
+
+    fn push(&mut self, priority: ThreadPriority, object: T) -> KResult<()> {
+        self.queues[priority as usize].push(object)
+    }
+
+    /// Returns true if `f` returns true for any object in any queue
+    fn any(&self, mut f: impl FnMut(&T) -> bool) -> bool {
+        self.queues.iter().any(|queue| queue.iter().any(&mut f))
+    }
+
+    #[cfg(test)]
+    fn pop(&mut self) -> Option<T> {
+        self.pop_where(|_| true)
+    }
+
+    /// Removes the first object that `eligible` returns true for, objects which are skipped keep their place
+    fn pop_where(&mut self, mut eligible: impl FnMut(&T) -> bool) -> Option<T> {
+        let positions: [Option<usize>; PRIORITY_LEVELS] = core::array::from_fn(|level| {
+            self.queues[level].iter().position(&mut eligible)
+        });
+
+        // the lowest priority starved queue goes first, since it has been waiting for the longest
+        let starved_level = (0..PRIORITY_LEVELS)
+            .find(|level| self.passed_over[*level] >= SCHED_AGING_THRESHOLD && positions[*level].is_some());
+
+        if let Some(level) = starved_level {
+            self.passed_over[level] = 0;
+            return positions[level].map(|index| self.queues[level].remove(index));
+        }
+
+        let level = (0..PRIORITY_LEVELS).rev()
+            .find(|level| positions[*level].is_some())?;
+
+        for lower_level in 0..level {
+            if positions[lower_level].is_some() {
+                self.passed_over[lower_level] += 1;
+            }
+        }
+
+        self.passed_over[level] = 0;
+        positions[level].map(|index| self.queues[level].remove(index))
+    }
+}
+
+/// A thread in the ready queue
+#[derive(Debug)]
+struct ReadyThread {
+    thread: Weak<Thread>,
+    /// The thread's cpu mask when it was inserted, so it can be checked without upgrading the thread
+    cpu_mask: u64,
+    is_idle: bool,
+}
 
 /// This stores all of the ready threads, used by scheduler to pick next thread
 #[derive(Debug)]
 pub struct ThreadMap {
-    // TODO: use a better data structure than a vec
-    ready_threads: IMutex<Vec<Weak<Thread>>>,
+    ready_threads: IMutex<ReadyQueues<ReadyThread>>,
 }
 
 impl ThreadMap {
-    pub const fn new(allocer: HeapRef) -> Self {
+    pub fn new(allocer: HeapRef) -> Self {
         ThreadMap {
-            ready_threads: IMutex::new(Vec::new(allocer)),
+            ready_threads: IMutex::new(ReadyQueues::new(allocer)),
         }
     }
 
-    /// Gets the next thread and process to run
+    /// Gets the next thread and process to run on the current cpu
     /// 
     /// Returns `None` if there are no available threads to run
-    /// Also removes any dead threads that are encountered from the ready threads list
+    /// Also removes any dead threads that are encountered from the ready threads list,
+    /// threads which are not allowed to run on the current cpu are left for other cpus
     pub fn get_next_thread(&self) -> Option<Arc<Thread>> {
+        let current_cpu = 1 << prid().into();
         let mut ready_threads = self.ready_threads.lock();
 
         loop {
-            let thread = ready_threads.pop_front()?;
-            let Some(thread) = thread.upgrade() else {
+            let ready_thread = ready_threads.pop_where(|ready_thread| ready_thread.cpu_mask & current_cpu != 0)?;
+            let Some(thread) = ready_thread.thread.upgrade() else {
                 continue;
             };
 
@@ -40,8 +120,97 @@ impl ThreadMap {
         }
     }
 
-    /// Adds `thread` to the list of ready threads
+    /// Returns true if a thread other than an idle thread is waiting to run on the current cpu
+    pub fn has_ready_thread(&self) -> bool {
+        let current_cpu = 1 << prid().into();
+
+        self.ready_threads.lock()
+            .any(|ready_thread| !ready_thread.is_idle && ready_thread.cpu_mask & current_cpu != 0)
+    }
+
+    /// Adds `thread` to the ready queue for its priority
+    /// 
+    /// Unless it is an idle thread, an idle cpu it can run on is woken up to run it
     pub fn insert_ready_thread(&self, thread: Weak<Thread>) -> KResult<()> {
-        self.ready_threads.lock().push(thread)
+        // the thread is upgraded before locking so it is never dropped with the ready queue locked
+        let Some((priority, cpu_mask, is_idle)) = thread.upgrade()
+            .map(|thread| (thread.priority(), thread.cpu_mask(), thread.is_idle_thread())) else {
+            // thread was already dropped, so it will never run
+            return Ok(());
+        };
+
+        self.ready_threads.lock().push(priority, ReadyThread {
+            thread,
+            cpu_mask,
+            is_idle,
+        })?;
+
+        // the thread is in the queue before any idle cpu is checked, so a cpu which starts waiting after this still sees it
+        if !is_idle {
+            kick_idle_cpu(cpu_mask);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::root_alloc_ref;
+
+    #[test_case]
+    fn ready_queues_pick_highest_priority() {
+        let mut queues = ReadyQueues::new(root_alloc_ref());
+        queues.push(ThreadPriority::Normal, 1).unwrap();
+        queues.push(ThreadPriority::High, 2).unwrap();
+        queues.push(ThreadPriority::Low, 3).unwrap();
+
+        assert_eq!(queues.pop(), Some(2));
+        assert_eq!(queues.pop(), Some(1));
+        assert_eq!(queues.pop(), Some(3));
+        assert_eq!(queues.pop(), None);
+    }
+
+    #[test_case]
+    fn ready_queues_age_starved_threads() {
+        const SPINNER: usize = 0;
+        const LOW_THREAD: usize = 1;
+
+        let mut queues = ReadyQueues::new(root_alloc_ref());
+        queues.push(ThreadPriority::Highest, SPINNER).unwrap();
+        queues.push(ThreadPriority::Low, LOW_THREAD).unwrap();
+
+        let mut low_thread_runs = 0;
+        for _ in 0..(4 * (SCHED_AGING_THRESHOLD + 1)) {
+            // like the scheduler, a thread whose time slice ran out is put back in its ready queue
+            match queues.pop() {
+                Some(SPINNER) => queues.push(ThreadPriority::Highest, SPINNER).unwrap(),
+                Some(LOW_THREAD) => {
+                    low_thread_runs += 1;
+                    queues.push(ThreadPriority::Low, LOW_THREAD).unwrap();
+                },
+                _ => panic!("ready queue lost a thread"),
+            }
+        }
+
+        assert_eq!(low_thread_runs, 4);
+    }
+
+    #[test_case]
+    fn ready_queues_skip_ineligible_objects() {
+        let mut queues = ReadyQueues::new(root_alloc_ref());
+        queues.push(ThreadPriority::High, 1).unwrap();
+        queues.push(ThreadPriority::Normal, 2).unwrap();
+        queues.push(ThreadPriority::Normal, 3).unwrap();
+
+        // like a cpu which the first 2 threads are not allowed to run on
+        assert_eq!(queues.pop_where(|object| *object == 3), Some(3));
+        assert_eq!(queues.pop_where(|object| *object == 3), None);
+
+        // skipped objects keep their place in the queue
+        assert_eq!(queues.pop(), Some(1));
+        assert_eq!(queues.pop(), Some(2));
+        assert_eq!(queues.pop(), None);
     }
 }
\ No newline at end of file
@@ -1,72 +1,38 @@
 use core::mem::size_of;
 
-use bytemuck::{Pod, Zeroable, from_bytes, cast_slice, bytes_of};
-use sys::{CapFlags, InitInfo, ProcessInitData, ProcessMemoryEntry, StackInfo, Rsdp};
-use elf::{ElfBytes, endian::NativeEndian, abi::{PT_LOAD, PF_R, PF_W, PF_X}};
+use bytemuck::{cast_slice, bytes_of, Zeroable};
+use initrd_archive::InitrdData;
+use sys::{CapFlags, InitInfo, ProcessInitData, ProcessMemoryEntry, PROCESS_INIT_DATA_MAGIC, PROCESS_INIT_DATA_VERSION, StackInfo, Rsdp};
+use elf::{ElfBytes, endian::NativeEndian, abi::{PT_LOAD, PT_TLS, PF_R, PF_W, PF_X}};
 use aser::to_bytes_count_cap;
 
-use crate::{prelude::*, alloc::{root_alloc, root_alloc_page_ref, root_alloc_ref, MmioAllocator}, cap::{Capability, StrongCapability, memory::{Memory, PageSource, MapMemoryArgs}, address_space::AddressSpace, capability_space::CapabilitySpace, WeakCapability}, sched::{ThreadGroup, Thread, ThreadStartMode}, vmem_manager::PageMappingOptions, int::userspace_interrupt::IntAllocator};
+use crate::{prelude::*, alloc::{root_alloc, root_alloc_page_ref, root_alloc_ref, MmioAllocator}, cap::{Capability, StrongCapability, memory::{Memory, PageSource, MapMemoryArgs}, address_space::AddressSpace, capability_space::CapabilitySpace, WeakCapability, debug::DebugCap, io_port::IoPortAccess}, sched::{ThreadGroup, Thread, ThreadStartMode}, vmem_manager::PageMappingOptions, int::userspace_interrupt::IntAllocator};
 use crate::container::Arc;
 
-const INITRD_MAGIC: u64 = 0x39f298aa4b92e836;
-const EARLY_INIT_ENTRY_TYPE: u64 = 1;
-
 // hardcode these addressess to things which won't conflict
 const STACK_ADDRESS: usize = 0x100000000;
 const STACK_SIZE: Size = Size::from_pages(16);
+const STACK_GUARD_SIZE: Size = Size::from_pages(16);
 const STARTUP_DATA_ADDRESS: usize = 0x200000000;
 const INITRD_MAPPING_ADDRESS: usize = 0x300000000;
 
 const EARLY_INIT_ASLR_SEED: [u8; 32] =
     [12, 64, 89, 134, 11, 255, 123, 98, 12, 31, 2, 90, 38, 234, 3, 49, 32, 58, 238, 220, 1, 0, 24, 23, 9, 48, 28, 65, 1, 43, 54, 55];
 
-#[derive(Debug, Clone, Copy, Pod, Zeroable)]
-#[repr(C)]
-struct InitRdHeader {
-    magic: u64,
-    len: u64,
-}
-
-#[derive(Debug, Clone, Copy, Pod, Zeroable)]
-#[repr(C)]
-struct InitRdProgram {
-    program_type: u64,
-    name: u64,
-    name_size: u64,
-    data: u64,
-    data_size: u64,
-}
-
 /// Looks through the initrd and returns a slice to the elf binary data
 fn find_early_init_data(initrd: &[u8]) -> &[u8] {
-    let header: &InitRdHeader = from_bytes(&initrd[0..size_of::<InitRdHeader>()]);
-
-    if header.magic != INITRD_MAGIC {
-        panic!("invalid magic number on initrd");
-    }
-
-    let initrd_entry_bytes = &initrd[size_of::<InitRdHeader>()..];
-    let initrd_entry_bytes = &initrd_entry_bytes[..header.len as usize * size_of::<InitRdProgram>()];
-    let initrd_entries: &[InitRdProgram] = cast_slice(initrd_entry_bytes);
-
-    for entry in initrd_entries {
-        if entry.program_type == EARLY_INIT_ENTRY_TYPE {
-            let start_index = entry.data as usize;
-            let end_index = (entry.data + entry.data_size) as usize;
-            return &initrd[start_index..end_index];
-        }
-    }
-
-    panic!("could not find early init program in initrd");
+    InitrdData::parse(initrd)
+        .file("early-init")
+        .expect("could not find early init program in initrd")
 }
 
 /// Parses the initrd and creates the early init process, which is the first userspace process
 /// 
 /// This code is not very robust for handling errors, but it doesn't need to be since if error occurs os will need to panic anyways
-pub fn start_early_init_process(initrd: &[u8], mmio_allocator: Arc<MmioAllocator>, rsdp: Rsdp) -> KResult<()> {
+pub fn start_early_init_process(initrd: &[u8], mmio_allocator: Arc<MmioAllocator>, rsdp: Rsdp, run_userland_tests: bool) -> KResult<()> {
     // create first process context, and insert needed capabilities
     let thread_group = Arc::new(
-        ThreadGroup::new(root_alloc_page_ref(), root_alloc_ref()),
+        ThreadGroup::new(root_alloc_page_ref(), root_alloc_ref())?,
         root_alloc_ref(),
     )?;
     let thread_group_capability = Capability::Strong(StrongCapability::new_flags(
@@ -138,6 +104,7 @@ pub fn start_early_init_process(initrd: &[u8], mmio_allocator: Arc<MmioAllocator
             map_size: size.bytes(),
             padding_start: 0,
             padding_end: 0,
+            padding_start_guard: 0,
         };
 
         memory_regions.push(region)?;
@@ -149,8 +116,13 @@ pub fn start_early_init_process(initrd: &[u8], mmio_allocator: Arc<MmioAllocator
     let early_init_bytes = find_early_init_data(initrd);
     let elf_data = ElfBytes::<NativeEndian>::minimal_parse(early_init_bytes).unwrap();
 
+    // early init finds its tls image in the segment that was loaded for it
+    let mut tls_segment = None;
+
     for phdr in elf_data.segments().unwrap().iter() {
-        if phdr.p_type == PT_LOAD {
+        if phdr.p_type == PT_TLS {
+            tls_segment = Some(phdr);
+        } else if phdr.p_type == PT_LOAD {
             let map_options = PageMappingOptions {
                 read: phdr.p_flags & PF_R != 0,
                 write: phdr.p_flags & PF_W != 0,
@@ -213,6 +185,19 @@ pub fn start_early_init_process(initrd: &[u8], mmio_allocator: Arc<MmioAllocator
 
     initrd_memory.inner_write().copy_from(.., initrd)?;
 
+    // reserve a guard region below the stack so early-init stack overflows are detected
+    address_space.map_guard(AVirtRange::new(
+        VirtAddr::new(STACK_ADDRESS - STACK_GUARD_SIZE.bytes()),
+        STACK_GUARD_SIZE.bytes(),
+    ))?;
+
+    // panic safety: the stack was mapped above
+    let stack_region = memory_regions.iter_mut()
+        .find(|region| region.map_address == STACK_ADDRESS)
+        .unwrap();
+    stack_region.padding_start = STACK_GUARD_SIZE.bytes();
+    stack_region.padding_start_guard = 1;
+
 
     // create first thread
     let rip = elf_data.ehdr.e_entry as usize;
@@ -239,7 +224,9 @@ pub fn start_early_init_process(initrd: &[u8], mmio_allocator: Arc<MmioAllocator
     let capability_space_id = capability_space.insert_capability_space(cspace_capability)?.into();
     let allocator_id = capability_space.insert_allocator(allocator_capability)?.into();
     let thread_id = capability_space.insert_thread(thread_capability)?.into();
-    let process_init_data = ProcessInitData {
+    let mut process_init_data = ProcessInitData {
+        magic: PROCESS_INIT_DATA_MAGIC,
+        version: PROCESS_INIT_DATA_VERSION,
         thread_group_id,
         address_space_id,
         capability_space_id,
@@ -247,7 +234,9 @@ pub fn start_early_init_process(initrd: &[u8], mmio_allocator: Arc<MmioAllocator
         main_thread_id: thread_id,
         stack_region_start_address: STACK_ADDRESS,
         aslr_seed: EARLY_INIT_ASLR_SEED,
+        ..ProcessInitData::zeroed()
     };
+    process_init_data.set_process_name("early-init");
 
     let mmio_allocator_capability = StrongCapability::new_flags(mmio_allocator, CapFlags::all());
     let mmio_allocator_id = capability_space.insert_mmio_allocator(Capability::Strong(mmio_allocator_capability))?;
@@ -256,6 +245,14 @@ pub fn start_early_init_process(initrd: &[u8], mmio_allocator: Arc<MmioAllocator
     let int_allocator_capability = StrongCapability::new_flags(int_allocator, CapFlags::all());
     let int_allocator_id = capability_space.insert_int_allocator(Capability::Strong(int_allocator_capability))?;
 
+    let debug_cap = Arc::new(DebugCap, root_alloc_ref())?;
+    let debug_cap_capability = StrongCapability::new_flags(debug_cap, CapFlags::all());
+    let debug_cap_id = capability_space.insert_debug_cap(Capability::Strong(debug_cap_capability))?;
+
+    let io_port_access = Arc::new(IoPortAccess::new_all_unreserved(root_alloc_ref())?, root_alloc_ref())?;
+    let io_port_access_capability = StrongCapability::new_flags(io_port_access, CapFlags::all());
+    let io_port_access_id = capability_space.insert_io_port_access(Capability::Strong(io_port_access_capability))?;
+
 
     // create startup data for early-init
     let mut startup_data = Vec::new(root_alloc_ref());
@@ -266,9 +263,13 @@ pub fn start_early_init_process(initrd: &[u8], mmio_allocator: Arc<MmioAllocator
     // append init info to startup data
     let init_info = InitInfo {
         initrd_address: INITRD_MAPPING_ADDRESS,
+        initrd_size: initrd.len(),
         mmio_allocator: sys::MmioAllocator::from_cap_id(mmio_allocator_id).unwrap(),
         int_allocator: sys::IntAllocator::from_cap_id(int_allocator_id).unwrap(),
+        debug_cap: sys::DebugCap::from_cap_id(debug_cap_id).unwrap(),
+        io_port_access: sys::IoPortAccess::from_cap_id(io_port_access_id).unwrap(),
         rsdp,
+        run_userland_tests,
     };
 
     let namespace_data: Vec<u8> = to_bytes_count_cap(&init_info)
@@ -289,13 +290,22 @@ pub fn start_early_init_process(initrd: &[u8], mmio_allocator: Arc<MmioAllocator
 
 
     // write pointers to stack
-    let stack_info = StackInfo {
+    let mut stack_info = StackInfo {
         process_data_address,
         process_data_size,
         namespace_data_address,
         namespace_data_size,
+        // early init is not given any environment variables
+        ..StackInfo::zeroed()
     };
 
+    if let Some(tls_segment) = tls_segment {
+        stack_info.tls_image_address = tls_segment.p_vaddr as usize;
+        stack_info.tls_image_size = tls_segment.p_filesz as usize;
+        stack_info.tls_block_size = tls_segment.p_memsz as usize;
+        stack_info.tls_block_align = tls_segment.p_align as usize;
+    }
+
     let mut stack_memory_inner = stack_memory.inner_write();
     let stack_memory_size = stack_memory_inner.size().bytes();
     stack_memory_inner.copy_from(stack_memory_size - size_of::<StackInfo>().., bytes_of(&stack_info))?;
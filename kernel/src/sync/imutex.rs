@@ -1,40 +1,101 @@
 use core::ops::{Deref, DerefMut};
+#[cfg(feature = "lock-tracking")]
+use core::panic::Location;
 
 use spin::{Mutex, MutexGuard};
 
 use crate::arch::x64::IntDisable;
+use crate::vmem_manager::tlb::handle_shootdown;
+#[cfg(feature = "lock-tracking")]
+use super::lock_tracking::{self, HeldLock, LockClass};
 
 /// A Mutex that also disables interrupts when locked
 #[derive(Debug)]
-pub struct IMutex<T: ?Sized>(Mutex<T>);
+pub struct IMutex<T: ?Sized> {
+    /// Class used to check lock order, only locks made with [`IMutex::named`] have one
+    #[cfg(feature = "lock-tracking")]
+    class: Option<LockClass>,
+    inner: Mutex<T>,
+}
 
 impl<T> IMutex<T> {
     pub const fn new(user_data: T) -> Self {
-        IMutex(Mutex::new(user_data))
+        IMutex {
+            #[cfg(feature = "lock-tracking")]
+            class: None,
+            inner: Mutex::new(user_data),
+        }
+    }
+
+    /// Creates a mutex whose lock order is checked against other named locks when the `lock-tracking` feature is enabled
+    ///
+    /// All locks with the same name are treated as 1 class
+    #[cfg_attr(not(feature = "lock-tracking"), allow(unused_variables))]
+    pub const fn named(name: &'static str, user_data: T) -> Self {
+        IMutex {
+            #[cfg(feature = "lock-tracking")]
+            class: Some(LockClass::new(name)),
+            inner: Mutex::new(user_data),
+        }
     }
 
     pub fn into_inner(self) -> T {
-        self.0.into_inner()
+        self.inner.into_inner()
+    }
+
+    #[cfg(feature = "lock-tracking")]
+    pub fn class(&self) -> Option<&LockClass> {
+        self.class.as_ref()
     }
 
+    #[track_caller]
     pub fn lock(&self) -> IMutexGuard<T> {
         let int_disable = IntDisable::new();
-        IMutexGuard(self.0.lock(), int_disable)
+        // checked before spinning, so an inversion is reported instead of deadlocking
+        #[cfg(feature = "lock-tracking")]
+        let held_lock = lock_tracking::lock_acquire(self.class.as_ref());
+
+        loop {
+            if let Some(guard) = self.inner.try_lock() {
+                return IMutexGuard {
+                    guard,
+                    #[cfg(feature = "lock-tracking")]
+                    _held_lock: held_lock,
+                    _int_disable: int_disable,
+                };
+            }
+
+            // interrupts are disabled while spinning, so tlb shootdown ipis can't be received,
+            // they are handled here so a cpu holding this lock while waiting for a shootdown doesn't deadlock
+            handle_shootdown();
+            core::hint::spin_loop();
+        }
     }
 
+    /// Trying to lock can't deadlock, so the lock order is not checked, but the lock is still recorded as held
+    #[track_caller]
     pub fn try_lock(&self) -> Option<IMutexGuard<T>> {
         let int_disable = IntDisable::new();
-        self.0.try_lock().map(|guard| IMutexGuard(guard, int_disable))
+        // closures don't inherit track_caller, so the location is found here
+        #[cfg(feature = "lock-tracking")]
+        let location = Location::caller();
+
+        self.inner.try_lock().map(|guard| IMutexGuard {
+            guard,
+            #[cfg(feature = "lock-tracking")]
+            _held_lock: HeldLock::acquired(self.class.as_ref(), location),
+            _int_disable: int_disable,
+        })
     }
 
     pub unsafe fn force_unlock(&self) {
         unsafe {
-            self.0.force_unlock();
+            self.inner.force_unlock();
         }
     }
 
     pub fn get_mut(&mut self) -> &mut T {
-        self.0.get_mut()
+        self.inner.get_mut()
     }
 }
 
@@ -48,18 +109,24 @@ unsafe impl<T: ?Sized + Send> Send for IMutex<T> {}
 unsafe impl<T: ?Sized + Send> Sync for IMutex<T> {}
 
 #[derive(Debug)]
-pub struct IMutexGuard<'a, T: ?Sized + 'a>(MutexGuard<'a, T>, IntDisable);
+pub struct IMutexGuard<'a, T: ?Sized + 'a> {
+    guard: MutexGuard<'a, T>,
+    /// Dropped before interrupts are enabled again, so it is removed from the cpu it was added to
+    #[cfg(feature = "lock-tracking")]
+    _held_lock: HeldLock,
+    _int_disable: IntDisable,
+}
 
 impl<T> Deref for IMutexGuard<'_, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.guard
     }
 }
 
 impl<T> DerefMut for IMutexGuard<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.guard
     }
 }
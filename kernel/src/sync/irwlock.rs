@@ -1,40 +1,123 @@
 use core::ops::{Deref, DerefMut};
+#[cfg(feature = "lock-tracking")]
+use core::panic::Location;
 
 use spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use crate::arch::x64::IntDisable;
+use crate::vmem_manager::tlb::handle_shootdown;
+#[cfg(feature = "lock-tracking")]
+use super::lock_tracking::{self, HeldLock, LockClass};
 
 /// A RwLock that also disables interrupts when locked
 #[derive(Debug)]
-pub struct IrwLock<T: ?Sized>(RwLock<T>);
+pub struct IrwLock<T: ?Sized> {
+    /// Class used to check lock order, only locks made with [`IrwLock::named`] have one
+    #[cfg(feature = "lock-tracking")]
+    class: Option<LockClass>,
+    inner: RwLock<T>,
+}
 
 impl<T> IrwLock<T> {
     pub const fn new(user_data: T) -> Self {
-        IrwLock(RwLock::new(user_data))
+        IrwLock {
+            #[cfg(feature = "lock-tracking")]
+            class: None,
+            inner: RwLock::new(user_data),
+        }
+    }
+
+    /// Creates a rwlock whose lock order is checked against other named locks when the `lock-tracking` feature is enabled
+    ///
+    /// All locks with the same name are treated as 1 class, and reads and writes are checked the same way
+    #[cfg_attr(not(feature = "lock-tracking"), allow(unused_variables))]
+    pub const fn named(name: &'static str, user_data: T) -> Self {
+        IrwLock {
+            #[cfg(feature = "lock-tracking")]
+            class: Some(LockClass::new(name)),
+            inner: RwLock::new(user_data),
+        }
     }
 
     pub fn into_inner(self) -> T {
-        self.0.into_inner()
+        self.inner.into_inner()
     }
 
+    #[cfg(feature = "lock-tracking")]
+    pub fn class(&self) -> Option<&LockClass> {
+        self.class.as_ref()
+    }
+
+    #[track_caller]
     pub fn read(&self) -> IrwLockReadGuard<T> {
         let int_disable = IntDisable::new();
-        IrwLockReadGuard(self.0.read(), int_disable)
+        #[cfg(feature = "lock-tracking")]
+        let held_lock = lock_tracking::lock_acquire(self.class.as_ref());
+
+        loop {
+            if let Some(guard) = self.inner.try_read() {
+                return IrwLockReadGuard {
+                    guard,
+                    #[cfg(feature = "lock-tracking")]
+                    _held_lock: held_lock,
+                    _int_disable: int_disable,
+                };
+            }
+
+            // same as IMutex::lock, shootdowns must be handled while spinning with interrupts disabled
+            handle_shootdown();
+            core::hint::spin_loop();
+        }
     }
 
+    #[track_caller]
     pub fn try_read(&self) -> Option<IrwLockReadGuard<T>> {
         let int_disable = IntDisable::new();
-        self.0.try_read().map(|guard| IrwLockReadGuard(guard, int_disable))
+        #[cfg(feature = "lock-tracking")]
+        let location = Location::caller();
+
+        self.inner.try_read().map(|guard| IrwLockReadGuard {
+            guard,
+            #[cfg(feature = "lock-tracking")]
+            _held_lock: HeldLock::acquired(self.class.as_ref(), location),
+            _int_disable: int_disable,
+        })
     }
 
+    #[track_caller]
     pub fn write(&self) -> IrwLockWriteGuard<T> {
         let int_disable = IntDisable::new();
-        IrwLockWriteGuard(self.0.write(), int_disable)
+        #[cfg(feature = "lock-tracking")]
+        let held_lock = lock_tracking::lock_acquire(self.class.as_ref());
+
+        loop {
+            if let Some(guard) = self.inner.try_write() {
+                return IrwLockWriteGuard {
+                    guard,
+                    #[cfg(feature = "lock-tracking")]
+                    _held_lock: held_lock,
+                    _int_disable: int_disable,
+                };
+            }
+
+            // same as IMutex::lock, shootdowns must be handled while spinning with interrupts disabled
+            handle_shootdown();
+            core::hint::spin_loop();
+        }
     }
 
+    #[track_caller]
     pub fn try_write(&self) -> Option<IrwLockWriteGuard<T>> {
         let int_disable = IntDisable::new();
-        self.0.try_write().map(|guard| IrwLockWriteGuard(guard, int_disable))
+        #[cfg(feature = "lock-tracking")]
+        let location = Location::caller();
+
+        self.inner.try_write().map(|guard| IrwLockWriteGuard {
+            guard,
+            #[cfg(feature = "lock-tracking")]
+            _held_lock: HeldLock::acquired(self.class.as_ref(), location),
+            _int_disable: int_disable,
+        })
     }
 }
 
@@ -48,29 +131,39 @@ unsafe impl<T: ?Sized + Send> Send for IrwLock<T> {}
 unsafe impl<T: ?Sized + Send> Sync for IrwLock<T> {}
 
 #[derive(Debug)]
-pub struct IrwLockReadGuard<'a, T: ?Sized + 'a>(RwLockReadGuard<'a, T>, IntDisable);
+pub struct IrwLockReadGuard<'a, T: ?Sized + 'a> {
+    guard: RwLockReadGuard<'a, T>,
+    #[cfg(feature = "lock-tracking")]
+    _held_lock: HeldLock,
+    _int_disable: IntDisable,
+}
 
 impl<T> Deref for IrwLockReadGuard<'_, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.guard
     }
 }
 
 #[derive(Debug)]
-pub struct IrwLockWriteGuard<'a, T: ?Sized + 'a>(RwLockWriteGuard<'a, T>, IntDisable);
+pub struct IrwLockWriteGuard<'a, T: ?Sized + 'a> {
+    guard: RwLockWriteGuard<'a, T>,
+    #[cfg(feature = "lock-tracking")]
+    _held_lock: HeldLock,
+    _int_disable: IntDisable,
+}
 
 impl<T> Deref for IrwLockWriteGuard<'_, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.guard
     }
 }
 
 impl<T> DerefMut for IrwLockWriteGuard<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.guard
     }
 }
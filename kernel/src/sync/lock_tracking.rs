@@ -0,0 +1,332 @@
+//! Lock order tracking, compiled in with the `lock-tracking` feature
+//!
+//! Every [`IMutex`](super::IMutex) and [`IrwLock`](super::IrwLock) made with `named` belongs to the lock class with its name.
+//! Each cpu keeps a stack of the tracked locks it holds, and when a lock is acquired while others are held,
+//! the order of their classes is recorded. If 2 classes are ever acquired in both orders the kernel panics,
+//! since 2 cpus taking them at the same time could deadlock.
+//!
+//! Locks made with `new` are not tracked, which includes the scheduler's locks, since they are held across thread switches.
+//! Holding 2 locks of the same class is not reported, because the order between locks of 1 class is not known.
+//! Code which is known to take locks in a safe order that can't be checked, such as handlers running with interrupts disabled
+//! which only nest locks briefly, can stop tracking on the current cpu with [`exempt`].
+
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use crate::prelude::*;
+
+/// Maximum number of lock classes, each class has a bit in the order mask of every other class
+const MAX_LOCK_CLASSES: usize = 64;
+/// Maximum number of tracked locks a cpu can hold at once
+const MAX_HELD_LOCKS: usize = 16;
+
+/// Set once every cpu has its cpu local data, locks acquired before this are not tracked
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Name of each class, a class's id is its index
+static CLASS_NAMES: Mutex<[Option<&'static str>; MAX_LOCK_CLASSES]> = Mutex::new([None; MAX_LOCK_CLASSES]);
+
+/// Bit `b` of entry `a` is set once class `b` has been acquired while class `a` was held
+static ACQUIRED_AFTER: [AtomicU64; MAX_LOCK_CLASSES] = [const { AtomicU64::new(0) }; MAX_LOCK_CLASSES];
+
+/// Entry `[a][b]` is where class `b` was first acquired while class `a` was held
+static ORDER_LOCATIONS: [[AtomicPtr<Location<'static>>; MAX_LOCK_CLASSES]; MAX_LOCK_CLASSES] =
+    [const { [const { AtomicPtr::new(null_mut()) }; MAX_LOCK_CLASSES] }; MAX_LOCK_CLASSES];
+
+/// Starts tracking locks, called once every cpu has initialized its cpu local data
+pub fn enable() {
+    ENABLED.store(true, Ordering::Release);
+}
+
+/// The class a tracked lock belongs to
+#[derive(Debug)]
+pub struct LockClass {
+    name: &'static str,
+    /// The class id plus 1, or 0 if it has not been looked up yet
+    id: AtomicUsize,
+}
+
+impl LockClass {
+    pub const fn new(name: &'static str) -> Self {
+        LockClass {
+            name,
+            id: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Gets the class id, locks with the same name share an id
+    fn id(&self) -> usize {
+        let id = self.id.load(Ordering::Relaxed);
+        if id != 0 {
+            return id - 1;
+        }
+
+        let mut class_names = CLASS_NAMES.lock();
+        let id = match class_names.iter().position(|name| *name == Some(self.name)) {
+            Some(id) => id,
+            None => {
+                let id = class_names.iter().position(Option::is_none)
+                    .expect("too many lock classes for lock tracking");
+                class_names[id] = Some(self.name);
+                id
+            },
+        };
+
+        self.id.store(id + 1, Ordering::Relaxed);
+        id
+    }
+}
+
+fn class_name(id: usize) -> &'static str {
+    CLASS_NAMES.lock()[id].unwrap_or("<unknown>")
+}
+
+/// Stack of tracked locks held by 1 cpu
+///
+/// This is only accessed by its own cpu with interrupts disabled, so relaxed atomics are enough
+#[derive(Debug)]
+pub struct HeldLocks {
+    classes: [AtomicUsize; MAX_HELD_LOCKS],
+    locations: [AtomicPtr<Location<'static>>; MAX_HELD_LOCKS],
+    len: AtomicUsize,
+    /// Number of [`ExemptGuard`]s on this cpu, nothing is tracked while this is not 0
+    exempt_depth: AtomicUsize,
+}
+
+impl HeldLocks {
+    pub const fn new() -> Self {
+        HeldLocks {
+            classes: [const { AtomicUsize::new(0) }; MAX_HELD_LOCKS],
+            locations: [const { AtomicPtr::new(null_mut()) }; MAX_HELD_LOCKS],
+            len: AtomicUsize::new(0),
+            exempt_depth: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, class_id: usize, location: &'static Location<'static>) {
+        let len = self.len.load(Ordering::Relaxed);
+        assert!(len < MAX_HELD_LOCKS, "too many tracked locks held at once");
+
+        self.classes[len].store(class_id, Ordering::Relaxed);
+        self.locations[len].store(location as *const _ as *mut _, Ordering::Relaxed);
+        self.len.store(len + 1, Ordering::Relaxed);
+    }
+
+    /// Removes the most recently acquired lock of `class_id`, locks don't have to be released in order
+    fn remove(&self, class_id: usize) {
+        let len = self.len.load(Ordering::Relaxed);
+        let Some(index) = (0..len).rev().find(|index| self.classes[*index].load(Ordering::Relaxed) == class_id) else {
+            return;
+        };
+
+        for i in index..(len - 1) {
+            self.classes[i].store(self.classes[i + 1].load(Ordering::Relaxed), Ordering::Relaxed);
+            self.locations[i].store(self.locations[i + 1].load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        self.len.store(len - 1, Ordering::Relaxed);
+    }
+
+    fn held(&self) -> impl Iterator<Item = (usize, &'static Location<'static>)> + '_ {
+        (0..self.len.load(Ordering::Relaxed)).map(|index| {
+            // safety: only references to static locations are stored
+            let location = unsafe { &*self.locations[index].load(Ordering::Relaxed) };
+            (self.classes[index].load(Ordering::Relaxed), location)
+        })
+    }
+}
+
+/// Returns the current cpu's held locks, or None if locks aren't tracked right now
+fn current_held_locks() -> Option<&'static HeldLocks> {
+    if !ENABLED.load(Ordering::Acquire) {
+        return None;
+    }
+
+    let held_locks = &cpu_local_data().held_locks;
+    if held_locks.exempt_depth.load(Ordering::Relaxed) != 0 {
+        return None;
+    }
+
+    Some(held_locks)
+}
+
+/// Stops lock tracking on the current cpu until it is dropped
+///
+/// This must be created and dropped with interrupts disabled
+#[derive(Debug)]
+pub struct ExemptGuard(());
+
+impl Drop for ExemptGuard {
+    fn drop(&mut self) {
+        cpu_local_data().held_locks.exempt_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Stops tracking locks acquired on the current cpu until the returned guard is dropped
+pub fn exempt() -> Option<ExemptGuard> {
+    if !ENABLED.load(Ordering::Acquire) {
+        return None;
+    }
+
+    cpu_local_data().held_locks.exempt_depth.fetch_add(1, Ordering::Relaxed);
+    Some(ExemptGuard(()))
+}
+
+/// An acquisition of a lock while a lock of another class was held, which is the reverse of an earlier order
+#[derive(Debug)]
+pub struct LockOrderViolation {
+    /// The class being acquired
+    pub acquired: &'static str,
+    pub acquired_at: &'static Location<'static>,
+    /// The class which was held
+    pub held: &'static str,
+    pub held_at: &'static Location<'static>,
+    /// Where `held` was first acquired while `acquired` was held
+    pub reverse_order_at: &'static Location<'static>,
+}
+
+impl fmt::Display for LockOrderViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "lock order inversion: {} acquired at {} while holding {} acquired at {}, but {} was previously acquired at {} while holding {}",
+            self.acquired, self.acquired_at, self.held, self.held_at, self.held, self.reverse_order_at, self.acquired,
+        )
+    }
+}
+
+/// Checks that acquiring `class` while holding the current cpu's locks doesn't reverse an order seen before,
+/// and records the new orders
+pub fn check_order(class: &LockClass, location: &'static Location<'static>) -> Result<(), LockOrderViolation> {
+    let Some(held_locks) = current_held_locks() else {
+        return Ok(());
+    };
+
+    let class_id = class.id();
+
+    for (held_id, held_at) in held_locks.held() {
+        if held_id == class_id {
+            continue;
+        }
+
+        if ACQUIRED_AFTER[class_id].load(Ordering::Acquire) & (1 << held_id) != 0 {
+            // safety: only references to static locations are stored
+            let reverse_order_at = unsafe { &*ORDER_LOCATIONS[class_id][held_id].load(Ordering::Acquire) };
+
+            return Err(LockOrderViolation {
+                acquired: class.name,
+                acquired_at: location,
+                held: class_name(held_id),
+                held_at,
+                reverse_order_at,
+            });
+        }
+
+        // the location is stored before the bit is set, so anyone who sees the bit can read the location
+        let _ = ORDER_LOCATIONS[held_id][class_id].compare_exchange(
+            null_mut(),
+            location as *const _ as *mut _,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+        ACQUIRED_AFTER[held_id].fetch_or(1 << class_id, Ordering::AcqRel);
+    }
+
+    Ok(())
+}
+
+/// Removes a tracked lock from the current cpu's held locks when dropped
+#[derive(Debug)]
+pub struct HeldLock {
+    class_id: Option<usize>,
+}
+
+impl HeldLock {
+    /// Adds `class` to the current cpu's held locks
+    ///
+    /// Must be called with interrupts disabled, and the returned value must be dropped before they are enabled again,
+    /// so the lock is removed from the same cpu
+    pub fn acquired(class: Option<&LockClass>, location: &'static Location<'static>) -> Self {
+        let class_id = class.zip(current_held_locks()).map(|(class, held_locks)| {
+            let class_id = class.id();
+            held_locks.push(class_id, location);
+            class_id
+        });
+
+        HeldLock {
+            class_id,
+        }
+    }
+}
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        if let Some(class_id) = self.class_id {
+            cpu_local_data().held_locks.remove(class_id);
+        }
+    }
+}
+
+/// Panics if acquiring `class` would reverse a lock order seen before, then adds it to the current cpu's held locks
+#[track_caller]
+pub fn lock_acquire(class: Option<&LockClass>) -> HeldLock {
+    let location = Location::caller();
+
+    if let Some(class) = class {
+        if let Err(violation) = check_order(class, location) {
+            panic!("{}", violation);
+        }
+    }
+
+    HeldLock::acquired(class, location)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arch::x64::IntDisable;
+    use crate::sync::IMutex;
+
+    static FIRST: IMutex<()> = IMutex::named("lock_tracking_test_first", ());
+    static SECOND: IMutex<()> = IMutex::named("lock_tracking_test_second", ());
+
+    #[test_case]
+    fn inverted_lock_order_is_detected() {
+        {
+            let _first = FIRST.lock();
+            let _second = SECOND.lock();
+        }
+
+        let _int_disable = IntDisable::new();
+        let _second = SECOND.lock();
+
+        // this is what locking FIRST would check before panicking
+        let violation = check_order(FIRST.class().unwrap(), Location::caller())
+            .expect_err("inverted lock order was not detected");
+
+        assert_eq!(violation.acquired, "lock_tracking_test_first");
+        assert_eq!(violation.held, "lock_tracking_test_second");
+        assert_eq!(violation.reverse_order_at.file(), file!());
+    }
+
+    #[test_case]
+    fn consistent_lock_order_is_allowed() {
+        static OUTER: IMutex<()> = IMutex::named("lock_tracking_test_outer", ());
+        static INNER: IMutex<()> = IMutex::named("lock_tracking_test_inner", ());
+
+        for _ in 0..2 {
+            let _outer = OUTER.lock();
+            let _inner = INNER.lock();
+        }
+
+        let _int_disable = IntDisable::new();
+        let _outer = OUTER.lock();
+        assert!(check_order(INNER.class().unwrap(), Location::caller()).is_ok());
+    }
+}
@@ -1,6 +1,8 @@
 mod dmutex;
 mod imutex;
 mod irwlock;
+#[cfg(feature = "lock-tracking")]
+pub mod lock_tracking;
 
 pub use dmutex::*;
 pub use imutex::*;
@@ -0,0 +1,85 @@
+use sys::CapFlags;
+
+use crate::alloc::{CapAllocator, HeapRef};
+use crate::arch::x64::IntDisable;
+use crate::cap::{Capability, StrongCapability};
+use crate::cap::capability_space::CapabilitySpace;
+use crate::container::Arc;
+use crate::prelude::*;
+use super::options_weak_autodestroy;
+
+/// Creates a new allocator which gets its memory from `allocator`
+/// 
+/// At most `limit` bytes can be allocated from the new allocator, `limit` is rounded down to a multiple of the page size.
+/// Anything allocated from the new allocator also counts against the limit of `allocator`.
+/// 
+/// # Options
+/// bits 0-3 (allocator_cap_flags): specifies the permissions of the returned allocator capability
+/// 
+/// # Required Capability Permissions
+/// `allocator`: cap_prod
+/// 
+/// # Returns
+/// allocator: allocator capability id of the new allocator
+pub fn allocator_new_child(options: u32, allocator_id: usize, limit: usize) -> KResult<usize> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+    let allocator_cap_flags = CapFlags::from_bits_truncate(get_bits(options as usize, 0..4));
+
+    let _int_disable = IntDisable::new();
+
+    let cspace = CapabilitySpace::current();
+
+    let parent = cspace
+        .get_allocator_with_perms(allocator_id, CapFlags::PROD, weak_auto_destroy)?
+        .into_inner();
+    let heap_ref = HeapRef::from_arc(parent.clone());
+
+    let allocator = StrongCapability::new_flags(
+        Arc::new(CapAllocator::new_child(parent, limit), heap_ref)?,
+        allocator_cap_flags,
+    );
+
+    Ok(cspace.insert_allocator(Capability::Strong(allocator))?.into())
+}
+
+/// Sets the maximum number of bytes which can be allocated from `allocator`
+/// 
+/// `limit` is rounded down to a multiple of the page size
+/// 
+/// # Required Capability Permissions
+/// `allocator`: cap_write
+/// 
+/// # Returns
+/// [`SysErr::InvlOp`] if `allocator` is the root allocator,
+/// [`SysErr::InvlArgs`] if more than `limit` bytes are already allocated
+pub fn allocator_set_limit(options: u32, allocator_id: usize, limit: usize) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    CapabilitySpace::current()
+        .get_allocator_with_perms(allocator_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner()
+        .set_limit(limit)
+}
+
+/// Gets the memory usage of `allocator`
+/// 
+/// # Required Capability Permissions
+/// `allocator`: cap_read
+/// 
+/// # Returns
+/// used: number of bytes allocated from the allocator, including memory given to child allocators
+/// limit: maximum number of bytes which can be allocated from the allocator
+pub fn allocator_usage(options: u32, allocator_id: usize) -> KResult<(usize, usize)> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let usage = CapabilitySpace::current()
+        .get_allocator_with_perms(allocator_id, CapFlags::READ, weak_auto_destroy)?
+        .into_inner()
+        .usage();
+
+    Ok(usage)
+}
\ No newline at end of file
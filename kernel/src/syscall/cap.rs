@@ -4,7 +4,10 @@ use crate::cap::capability_space::CapCloneWeakness;
 use crate::prelude::*;
 use crate::{arch::x64::IntDisable, cap::capability_space::CapabilitySpace};
 
-use super::options_weak_autodestroy;
+use super::{options_weak_autodestroy, copy_to_userspace};
+
+/// Number of capability ids [`capability_space_list`] collects at once before copying them to userspace
+const CAPABILITY_LIST_CHUNK_SIZE: usize = 64;
 
 pub fn cap_clone(
     options: u32,
@@ -101,8 +104,74 @@ pub fn cap_destroy(
         CapType::PhysMem => { cspace.remove_phys_mem(cap_id)?; },
         CapType::IntAllocator => { cspace.remove_int_allocator(cap_id)?; },
         CapType::Interrupt => { cspace.remove_interrupt(cap_id)?; },
+        CapType::DebugCap => { cspace.remove_debug_cap(cap_id)?; },
+        CapType::IoPortAccess => { cspace.remove_io_port_access(cap_id)?; },
+        CapType::IoPort => { cspace.remove_io_port(cap_id)?; },
         _ => todo!(),
     }
 
     Ok(())
+}
+
+/// Lists the ids of capabilities in the capability space `cspace`
+/// 
+/// Up to `buffer_len` capability ids are written to the array at `buffer`, in order of increasing base id,
+/// starting with the first capability which has a base id of at least `cursor`.
+/// To list the entire capability space, start with a cursor of 0 and pass in the returned cursor on each subsequent call,
+/// until fewer than `buffer_len` ids are written.
+/// 
+/// Capabilities inserted or removed while listing may or may not be returned,
+/// but ids from a capability space other than `cspace` are never returned.
+/// 
+/// # Required Capability Permissions
+/// `cspace`: cap_read
+/// 
+/// # Syserr Code
+/// InvlBuffer: `buffer` is not valid for writing `buffer_len` capability ids
+/// 
+/// # Returns
+/// count: number of capability ids written to `buffer`
+/// cursor: cursor to continue listing from
+pub fn capability_space_list(
+    options: u32,
+    cspace_id: usize,
+    buffer: usize,
+    buffer_len: usize,
+    cursor: usize,
+) -> KResult<(usize, usize)> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let buffer = buffer as *mut usize;
+
+    let _int_disable = IntDisable::new();
+
+    let cspace = CapabilitySpace::current()
+        .get_capability_space_with_perms(cspace_id, CapFlags::READ, weak_auto_destroy)?
+        .into_inner();
+
+    let mut cursor = cursor;
+    let mut write_count = 0;
+
+    while write_count < buffer_len {
+        let chunk_len = core::cmp::min(CAPABILITY_LIST_CHUNK_SIZE, buffer_len - write_count);
+
+        let mut cap_ids = [CapId::null(); CAPABILITY_LIST_CHUNK_SIZE];
+        let (count, next_cursor) = cspace.list_capabilities(cursor, &mut cap_ids[..chunk_len]);
+
+        let mut raw_ids = [0; CAPABILITY_LIST_CHUNK_SIZE];
+        for (raw_id, cap_id) in raw_ids.iter_mut().zip(cap_ids[..count].iter()) {
+            *raw_id = usize::from(*cap_id);
+        }
+
+        copy_to_userspace(buffer.wrapping_add(write_count), &raw_ids[..count])?;
+
+        write_count += count;
+        cursor = next_cursor;
+
+        if count < chunk_len {
+            break;
+        }
+    }
+
+    Ok((write_count, cursor))
 }
\ No newline at end of file
@@ -1,8 +1,8 @@
-use sys::{CapId, CapFlags, ChannelSyncFlags, ChannelAsyncRecvFlags, EventId};
+use sys::{CapId, CapFlags, ChannelSyncFlags, ChannelSendFlags, ChannelAsyncRecvFlags, ChannelAsyncCallFlags, EventId, NO_SENDER_KEY_ID};
 
 use crate::alloc::HeapRef;
 use crate::cap::capability_space::CapabilitySpace;
-use crate::cap::channel::ChannelSyncResult;
+use crate::cap::channel::{ChannelSyncResult, RecieveResult};
 use crate::cap::{Capability, StrongCapability, channel::Channel};
 use crate::container::Arc;
 use crate::event::{UserspaceBuffer, EventPoolListenerRef};
@@ -12,10 +12,14 @@ use crate::sched::{switch_current_thread_to, ThreadState, PostSwitchAction, Wake
 
 use super::options_weak_autodestroy;
 
-pub fn channel_new(options: u32, allocator_id: usize) -> KResult<usize> {
+pub fn channel_new(options: u32, allocator_id: usize, async_sender_limit: usize) -> KResult<usize> {
     let weak_auto_destroy = options_weak_autodestroy(options);
     let channel_cap_flags = CapFlags::from_bits_truncate(get_bits(options as usize, 0..4));
 
+    if async_sender_limit == 0 {
+        return Err(SysErr::InvlArgs);
+    }
+
     let _int_disable = IntDisable::new();
 
     let cspace = CapabilitySpace::current();
@@ -26,13 +30,27 @@ pub fn channel_new(options: u32, allocator_id: usize) -> KResult<usize> {
     let heap_ref = HeapRef::from_arc(allocator);
 
     let channel = StrongCapability::new_flags(
-        Arc::new(Channel::new(heap_ref.clone()), heap_ref)?,
+        Arc::new(Channel::with_sender_limit(heap_ref.clone(), async_sender_limit), heap_ref)?,
         channel_cap_flags,
     );
 
     Ok(cspace.insert_channel(Capability::Strong(channel))?.into())
 }
 
+/// Returns true if the `SEND_IDENTITY` flag is set, so the sender's identity key id is attached to the message
+fn options_send_identity(options: u32) -> bool {
+    ChannelSendFlags::from_bits_truncate(options).contains(ChannelSendFlags::SEND_IDENTITY)
+}
+
+/// Converts a recieve result to the recieve size, reply capability id, and sender key id returned to userspace
+fn recieve_result_values(recieve_result: RecieveResult) -> (usize, usize, usize) {
+    (
+        recieve_result.recieve_size.bytes(),
+        recieve_result.reply_cap_id.unwrap_or(CapId::null()).into(),
+        recieve_result.sender_key_id.map_or(NO_SENDER_KEY_ID, |key_id| key_id as usize),
+    )
+}
+
 /// Used for `channel_try_send`, `channel_sync_send`, `channel_try_recv`, `channel_sync_recv` to process common arguments
 fn channel_handle_args(
     options: u32,
@@ -82,7 +100,7 @@ pub fn channel_try_send(
         CapFlags::READ,
     )?;
 
-    channel.try_send(&buffer, &cspace).map(Size::bytes)
+    Channel::try_send(&channel, &buffer, &cspace, options_send_identity(options)).map(Size::bytes)
 }
 
 pub fn channel_sync_send(
@@ -107,7 +125,7 @@ pub fn channel_sync_send(
         CapFlags::READ,
     )?;
 
-    match channel.sync_send(&buffer, &cspace) {
+    match Channel::sync_send(&channel, &buffer, &cspace, options_send_identity(options)) {
         ChannelSyncResult::Success(write_size) => Ok(write_size.bytes()),
         ChannelSyncResult::Error(error) => Err(error),
         ChannelSyncResult::Block => {
@@ -129,6 +147,7 @@ pub fn channel_sync_send(
             let _int_disable = IntDisable::new();
             match cpu_local_data().current_thread().wake_reason() {
                 WakeReason::MsgSend { msg_size } => Ok(msg_size.bytes()),
+                WakeReason::PeerGone => Err(SysErr::PeerGone),
                 WakeReason::Timeout => Err(SysErr::OkTimeout),
                 _ => unreachable!(),
             }
@@ -142,7 +161,7 @@ pub fn channel_try_recv(
     msg_buf_id: usize,
     msg_buf_offset: usize,
     msg_buf_size: usize,
-) -> KResult<(usize, usize)> {
+) -> KResult<(usize, usize, usize)> {
     let _int_disable = IntDisable::new();
 
     let (channel, buffer, cspace) = channel_handle_args(
@@ -157,10 +176,7 @@ pub fn channel_try_recv(
     
     let recv_result = channel.try_recv(&buffer, &cspace)?;
 
-    Ok((
-        recv_result.recieve_size.bytes(),
-        recv_result.reply_cap_id.unwrap_or(CapId::null()).into(),
-    ))
+    Ok(recieve_result_values(recv_result))
 }
 
 pub fn channel_sync_recv(
@@ -170,7 +186,7 @@ pub fn channel_sync_recv(
     msg_buf_offset: usize,
     msg_buf_size: usize,
     timeout: usize,
-) -> KResult<(usize, usize)> {
+) -> KResult<(usize, usize, usize)> {
     let flags = ChannelSyncFlags::from_bits_truncate(options);
 
     let int_disable = IntDisable::new();
@@ -185,11 +201,8 @@ pub fn channel_sync_recv(
         CapFlags::WRITE,
     )?;
 
-    match channel.sync_recv(&buffer, &cspace) {
-        ChannelSyncResult::Success(recv_result) => Ok((
-            recv_result.recieve_size.bytes(),
-            recv_result.reply_cap_id.unwrap_or(CapId::null()).into(),
-        )),
+    match Channel::sync_recv(&channel, &buffer, &cspace) {
+        ChannelSyncResult::Success(recv_result) => Ok(recieve_result_values(recv_result)),
         ChannelSyncResult::Error(error) => Err(error),
         ChannelSyncResult::Block => {
             drop(channel);
@@ -211,10 +224,9 @@ pub fn channel_sync_recv(
 
             let _int_disable = IntDisable::new();
             match cpu_local_data().current_thread().wake_reason() {
-                WakeReason::MsgRecv(recieve_result) => Ok((
-                    recieve_result.recieve_size.bytes(),
-                    recieve_result.reply_cap_id.unwrap_or(CapId::null()).into(),
-                )),
+                WakeReason::MsgRecv(recieve_result) => Ok(recieve_result_values(recieve_result)),
+                WakeReason::MsgRecvError(error) => Err(error),
+                WakeReason::PeerGone => Err(SysErr::PeerGone),
                 WakeReason::Timeout => Err(SysErr::OkTimeout),
                 _ => unreachable!(),
             }
@@ -254,7 +266,35 @@ pub fn channel_async_send(
         event_id,
     };
 
-    channel.async_send(event_pool_listener, &buffer, &cspace)
+    Channel::async_send(&channel, event_pool_listener, &buffer, &cspace, options_send_identity(options))
+}
+
+pub fn channel_async_wait_space(
+    options: u32,
+    channel_id: usize,
+    event_pool_id: usize,
+    event_id: usize,
+) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let event_id = EventId::from_u64(event_id as u64);
+
+    let _int_disable = IntDisable::new();
+
+    let cspace = CapabilitySpace::current();
+
+    let channel = cspace
+        .get_channel_with_perms(channel_id, CapFlags::PROD, weak_auto_destroy)?
+        .into_inner();
+
+    let event_pool = cspace
+        .get_event_pool_with_perms(event_pool_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner();
+
+    channel.async_wait_space(EventPoolListenerRef {
+        event_pool: Arc::downgrade(&event_pool),
+        event_id,
+    })
 }
 
 pub fn channel_async_recv(
@@ -285,7 +325,7 @@ pub fn channel_async_recv(
         event_id,
     };
 
-    channel.async_recv(event_pool_listener, flags.contains(ChannelAsyncRecvFlags::AUTO_REQUE), &cspace)
+    Channel::async_recv(&channel, event_pool_listener, flags.contains(ChannelAsyncRecvFlags::AUTO_REQUE), &cspace)
 }
 
 pub fn channel_sync_call(
@@ -324,7 +364,7 @@ pub fn channel_sync_call(
                 weak_auto_destroy,
             )?;
         
-        channel.sync_call(&send_buffer, &recv_buffer, &cspace)?;
+        Channel::sync_call(&channel, &send_buffer, &recv_buffer, &cspace, options_send_identity(options))?;
     }
 
     let post_switch_hook = if flags.contains(ChannelSyncFlags::TIMEOUT) {
@@ -343,6 +383,8 @@ pub fn channel_sync_call(
     let _int_disable = IntDisable::new();
     match cpu_local_data().current_thread().wake_reason() {
         WakeReason::MsgRecv(recieve_result) => Ok(recieve_result.recieve_size.bytes()),
+        WakeReason::MsgRecvError(error) => Err(error),
+        WakeReason::PeerGone => Err(SysErr::PeerGone),
         WakeReason::Timeout => Err(SysErr::OkTimeout),
         _ => unreachable!(),
     }
@@ -356,7 +398,10 @@ pub fn channel_async_call(
     send_buf_size: usize,
     event_pool_id: usize,
     event_id: usize,
+    response_buf_id: usize,
+    response_buf_size: usize,
 ) -> KResult<()> {
+    let flags = ChannelAsyncCallFlags::from_bits_truncate(options);
     let event_id = EventId::from_u64(event_id as u64);
 
     let _int_disable = IntDisable::new();
@@ -380,7 +425,27 @@ pub fn channel_async_call(
         event_id,
     };
 
-    channel.async_call(event_pool_listener, &buffer, &cspace)
+    // the response buffer always starts at the beginning of its memory, so it fits in the syscall arguments
+    let response_buffer = if flags.contains(ChannelAsyncCallFlags::RESPONSE_BUFFER) {
+        Some(cspace.get_userspace_buffer(
+            response_buf_id,
+            0,
+            response_buf_size,
+            CapFlags::WRITE,
+            options_weak_autodestroy(options),
+        )?)
+    } else {
+        None
+    };
+
+    Channel::async_call(
+        &channel,
+        event_pool_listener,
+        &buffer,
+        response_buffer.as_ref(),
+        &cspace,
+        options_send_identity(options),
+    )
 }
 
 pub fn reply_reply(
@@ -1,5 +1,21 @@
+use core::cmp::min;
+use core::str;
+
+use sys::{CapFlags, DebugSetStraceFlags, MemInfo, ZoneMemInfo, PRINT_DEBUG_BUFFER_MAX_LEN};
+
+use crate::alloc::{heap, root_alloc_ref, zm};
+use crate::arch::x64::IntDisable;
+use crate::cap::capability_space::CapabilitySpace;
+use crate::cap::memory::memory_stats;
+use crate::config::KLOG_SIZE;
 use crate::prelude::*;
-use crate::io::R_WRITER;
+use crate::io::{R_WRITER, E_WRITER, QemuExitCode, exit_qemu, hand_off_console};
+use crate::klog::read_klog;
+use crate::sched::STRACE_NAME_MAX_LEN;
+use super::{options_weak_autodestroy, copy_from_userspace, copy_to_userspace};
+
+/// Number of zone infos [`system_mem_info`] collects before copying them to userspace
+const ZONE_INFO_CHUNK_SIZE: usize = 16;
 
 /// Prints the characters specified in the arguments to the debug console
 /// 
@@ -46,3 +62,242 @@ pub fn print_debug(
 
     Ok(())
 }
+
+/// Prints bytes from a buffer in userspace to the debug console
+///
+/// Everything printed by one call is written while holding the debug console lock with interrupts disabled,
+/// so output from other cpus and interrupt handlers is never mixed into it.
+///
+/// # Arguments
+/// `data_ptr` and `data_len` are the bytes to print, only the first [`PRINT_DEBUG_BUFFER_MAX_LEN`] bytes are printed
+///
+/// # Returns
+/// The number of bytes printed
+pub fn print_debug_buffer(
+    _options: u32,
+    data_ptr: usize,
+    data_len: usize,
+) -> KResult<usize> {
+    let mut data_buf = [0u8; PRINT_DEBUG_BUFFER_MAX_LEN];
+    let data = &mut data_buf[..min(data_len, PRINT_DEBUG_BUFFER_MAX_LEN)];
+    copy_from_userspace(data, data_ptr as *const u8)?;
+
+    let writer = E_WRITER.lock();
+    for byte in data.iter() {
+        writer.write_byte(*byte);
+    }
+
+    Ok(data.len())
+}
+
+/// Sets which syscalls made by threads in a thread group are printed to the kernel debug log
+///
+/// Child thread groups are not affected, their strace settings must be set separately
+///
+/// # Options
+/// bit 0 (enabled): syscalls in the syscall mask are traced, if not set tracing is disabled
+///
+/// # Required Capability Permissions
+/// `thread_group`: cap_write
+///
+/// # Arguments
+/// `syscall_mask_low` and `syscall_mask_high` are the low and high 64 bits of a mask where bit n is set if syscall number n is traced
+/// `name_ptr` and `name_len` are the process name printed in the trace output, only the first 32 bytes are used
+pub fn debug_set_strace(
+    options: u32,
+    thread_group_id: usize,
+    syscall_mask_low: usize,
+    syscall_mask_high: usize,
+    name_ptr: usize,
+    name_len: usize,
+) -> KResult<()> {
+    let flags = DebugSetStraceFlags::from_bits_truncate(options);
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let mut name_buf = [0u8; STRACE_NAME_MAX_LEN];
+    let name_buf = &mut name_buf[..min(name_len, STRACE_NAME_MAX_LEN)];
+    copy_from_userspace(name_buf, name_ptr as *const u8)?;
+
+    // truncating the name may have split a character
+    let name = match str::from_utf8(name_buf) {
+        Ok(name) => name,
+        Err(error) if error.error_len().is_none() => str::from_utf8(&name_buf[..error.valid_up_to()]).unwrap(),
+        Err(_) => return Err(SysErr::InvlArgs),
+    };
+
+    let syscall_mask = (syscall_mask_low as u128) | ((syscall_mask_high as u128) << 64);
+
+    let _int_disable = IntDisable::new();
+
+    let thread_group = CapabilitySpace::current()
+        .get_thread_group_with_perms(thread_group_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner();
+
+    thread_group.strace().set(flags.contains(DebugSetStraceFlags::ENABLED), syscall_mask, name);
+
+    Ok(())
+}
+
+/// Copies messages from the kernel log into a memory capability
+///
+/// Each message printed by the kernel is stored as a record with a sequence number, records are only ever copied whole.
+/// Records with a sequence number of at least `since_seq` are copied to the buffer in order until the next record does not fit.
+/// If some of those records have already been overwritten, copying starts at the oldest record still in the kernel log.
+///
+/// # Required Capability Permissions
+/// `debug_cap`: cap_read
+/// `memory`: cap_write
+///
+/// # Returns
+/// The number of bytes written, and the sequence number to pass in as `since_seq` to read the records after the ones written
+///
+/// Returns [`SysErr::OutOfCapacity`] if the first record to copy is bigger than the buffer
+pub fn debug_read_klog(
+    options: u32,
+    debug_cap_id: usize,
+    memory_id: usize,
+    offset: usize,
+    size: usize,
+    since_seq: usize,
+) -> KResult<(usize, usize)> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let cspace = CapabilitySpace::current();
+
+    cspace.get_debug_cap_with_perms(debug_cap_id, CapFlags::READ, weak_auto_destroy)?;
+
+    let buffer = cspace.get_userspace_buffer(memory_id, offset, size, CapFlags::WRITE, weak_auto_destroy)?;
+
+    // records are copied out of the kernel log first so it is not locked while writing to memory
+    let mut klog_data = Vec::try_with_capacity(root_alloc_ref(), min(size, KLOG_SIZE))?;
+    klog_data.extend(core::iter::repeat(0u8).take(min(size, KLOG_SIZE)))?;
+
+    let (klog_size, next_seq) = read_klog(since_seq as u64, klog_data.as_mut_slice())?;
+
+    buffer.copy_from(&klog_data.as_slice()[..klog_size])?;
+
+    Ok((klog_size, next_seq as usize))
+}
+
+/// Gets memory usage counters for the whole system
+///
+/// The counters are maintained as memory is allocated and freed, and read without locking,
+/// so they may be slightly inconsistent with each other while other cpus are allocating.
+///
+/// # Required Capability Permissions
+/// `debug_cap`: cap_read
+///
+/// # Arguments
+/// `mem_info_ptr` is where a [`MemInfo`] is written
+/// `zones_ptr` and `zones_len` are a buffer of [`ZoneMemInfo`], only the first `zones_len` zones are written
+///
+/// # Syserr Code
+/// InvlBuffer: `mem_info_ptr` or `zones_ptr` are not valid for writing
+pub fn system_mem_info(
+    options: u32,
+    debug_cap_id: usize,
+    mem_info_ptr: usize,
+    zones_ptr: usize,
+    zones_len: usize,
+) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    {
+        let _int_disable = IntDisable::new();
+
+        CapabilitySpace::current()
+            .get_debug_cap_with_perms(debug_cap_id, CapFlags::READ, weak_auto_destroy)?;
+    }
+
+    let zones_ptr = zones_ptr as *mut ZoneMemInfo;
+    let mut zone_count = 0;
+    let mut zone_chunk = [ZoneMemInfo::default(); ZONE_INFO_CHUNK_SIZE];
+    let mut chunk_len = 0;
+
+    for zone_info in zm().zone_infos() {
+        if zone_count < zones_len {
+            zone_chunk[chunk_len] = zone_info;
+            chunk_len += 1;
+
+            if chunk_len == ZONE_INFO_CHUNK_SIZE {
+                copy_to_userspace(zones_ptr.wrapping_add(zone_count + 1 - chunk_len), &zone_chunk)?;
+                chunk_len = 0;
+            }
+        }
+
+        zone_count += 1;
+    }
+
+    let written_count = zone_count.min(zones_len);
+    copy_to_userspace(zones_ptr.wrapping_add(written_count - chunk_len), &zone_chunk[..chunk_len])?;
+
+    let (memory_count, memory_pages) = memory_stats();
+    let mem_info = MemInfo {
+        total_pages: zm().total_pages(),
+        free_pages: zm().free_pages(),
+        heap_bytes: heap().allocated_bytes(),
+        memory_count,
+        memory_pages,
+        zone_count,
+    };
+
+    copy_to_userspace(mem_info_ptr as *mut MemInfo, &[mem_info])?;
+
+    Ok(())
+}
+
+/// Exits the emulator the kernel is running in, used to report the result of an automated test run
+///
+/// The emulator exits with a status indicating success if `success` is not 0, and a status indicating failure otherwise.
+///
+/// # Required Capability Permissions
+/// `debug_cap`: cap_write
+///
+/// # Returns
+/// Returns normally if the kernel is not running in an emulator which can be exited this way
+pub fn debug_exit_emulator(
+    options: u32,
+    debug_cap_id: usize,
+    success: usize,
+) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    {
+        let _int_disable = IntDisable::new();
+
+        CapabilitySpace::current()
+            .get_debug_cap_with_perms(debug_cap_id, CapFlags::WRITE, weak_auto_destroy)?;
+    }
+
+    let exit_code = if success != 0 {
+        QemuExitCode::Success
+    } else {
+        QemuExitCode::Failed
+    };
+    exit_qemu(exit_code);
+
+    Ok(())
+}
+
+/// Stops the kernel from printing its messages to the screen and the debug console, so a userspace console server can own the console
+///
+/// Kernel messages are still written to the kernel log, which can be read with [`debug_read_klog`].
+/// If the kernel panics, it takes the console back and prints the messages it logged since the handoff.
+/// This does not affect the [`print_debug`] syscalls. Handing off the console again does nothing.
+///
+/// # Required Capability Permissions
+/// `debug_cap`: cap_write
+pub fn debug_console_handoff(options: u32, debug_cap_id: usize) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    CapabilitySpace::current()
+        .get_debug_cap_with_perms(debug_cap_id, CapFlags::WRITE, weak_auto_destroy)?;
+
+    hand_off_console();
+
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,60 @@
+use crate::prelude::*;
+use crate::arch::x64::{cpuid, rdrand, rdseed, rdtsc};
+
+/// Number of timestamp counter samples mixed together for each word of jitter entropy
+const JITTER_SAMPLE_COUNT: usize = 64;
+
+/// Mixes `value` into `state`, every bit of `value` affects every bit of the output
+fn mix(state: u64, value: u64) -> u64 {
+    // finalizer from splitmix64
+    let mut z = (state ^ value).wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Gathers entropy from jitter in the timestamp counter
+///
+/// This is a lot weaker than hardware random numbers, and is only used when they are not available
+fn tsc_jitter_entropy() -> u64 {
+    let mut state = rdtsc();
+    let mut last_time = state;
+
+    for i in 0..JITTER_SAMPLE_COUNT {
+        // do some work that takes a variable amount of time, depending on cache and pipeline state
+        let mut work = state;
+        for _ in 0..(get_bits(last_time as usize, 0..4) + 1) {
+            work = mix(work, i as u64);
+        }
+
+        let time = rdtsc();
+        state = mix(state, time.wrapping_sub(last_time) ^ work.rotate_left(32));
+        last_time = time;
+    }
+
+    state
+}
+
+/// Gets one word of entropy, using the best source available
+fn entropy_word() -> usize {
+    let hardware_random = if cpuid::has_rdseed() {
+        rdseed().or_else(rdrand)
+    } else if cpuid::has_rdrand() {
+        rdrand()
+    } else {
+        None
+    };
+
+    hardware_random.unwrap_or_else(tsc_jitter_entropy) as usize
+}
+
+/// Returns random data from the kernel, intended for seeding userspace random number generators
+///
+/// The random data comes from rdseed or rdrand if they are supported,
+/// otherwise it is gathered from jitter in the timestamp counter
+///
+/// # Returns
+/// 3 words of random data
+pub fn system_entropy(_options: u32) -> KResult<(usize, usize, usize)> {
+    Ok((entropy_word(), entropy_word(), entropy_word()))
+}
\ No newline at end of file
@@ -1,4 +1,4 @@
-use sys::{CapFlags, EventPoolAwaitFlags};
+use sys::{CapFlags, EventPoolAwaitFlags, EVENT_LAYOUT_VERSION};
 
 use crate::alloc::{HeapRef, PaRef};
 use crate::cap::{StrongCapability, Capability};
@@ -11,8 +11,14 @@ use crate::sched::{switch_current_thread_to, ThreadState, PostSwitchAction, Wake
 
 use super::options_weak_autodestroy;
 
-pub fn event_pool_new(options: u32, allocator_id: usize, max_size: usize) -> KResult<usize> {
+pub fn event_pool_new(options: u32, allocator_id: usize, max_size: usize, layout_version: usize) -> KResult<usize> {
     let weak_auto_destroy = options_weak_autodestroy(options);
+
+    // userspace would misparse every event if it expects a different layout
+    if layout_version != EVENT_LAYOUT_VERSION as usize {
+        return Err(SysErr::InvlArgs);
+    }
+
     let event_pool_size = Size::try_from_pages(max_size)
         .ok_or(SysErr::Overflow)?;
 
@@ -64,7 +70,10 @@ pub fn event_pool_map(
         .map(Size::pages_rounded)
 }
 
-pub fn event_pool_await(options: u32, event_pool_id: usize, timeout: usize) -> KResult<(usize, usize)> {
+/// Waits for events to be written to the event pool, and maps all events written since the last await
+/// 
+/// Returns the address and size of the mapped events, and the number of events
+pub fn event_pool_await(options: u32, event_pool_id: usize, timeout: usize) -> KResult<(usize, usize, usize)> {
     let weak_auto_destroy = options_weak_autodestroy(options);
     let flags = EventPoolAwaitFlags::from_bits_truncate(options);
 
@@ -81,8 +90,9 @@ pub fn event_pool_await(options: u32, event_pool_id: usize, timeout: usize) -> K
     match await_result {
         AwaitStatus::Success {
             event_range,
+            event_count,
         } => {
-            Ok((event_range.as_usize(), event_range.size()))
+            Ok((event_range.as_usize(), event_range.size(), event_count))
         },
         AwaitStatus::Block => {
             let post_switch_action = if flags.contains(EventPoolAwaitFlags::TIMEOUT) {
@@ -99,8 +109,8 @@ pub fn event_pool_await(options: u32, event_pool_id: usize, timeout: usize) -> K
             ).expect("Failed to wait on event pool");
 
             match cpu_local_data().current_thread().wake_reason() {
-                WakeReason::EventPoolEventRecieved { event_range } => {
-                    Ok((event_range.as_usize(), event_range.size()))
+                WakeReason::EventPoolEventRecieved { event_range, event_count } => {
+                    Ok((event_range.as_usize(), event_range.size(), event_count))
                 },
                 WakeReason::Timeout => Err(SysErr::OkTimeout),
                 _ => unreachable!(),
@@ -0,0 +1,70 @@
+use core::mem::align_of;
+
+use sys::FutexWaitFlags;
+
+use crate::arch::x64::IntDisable;
+use crate::prelude::*;
+use crate::sched::{switch_current_thread_to, PostSwitchAction, ThreadRef, ThreadState, WakeReason};
+use super::copy_from_userspace;
+
+/// Blocks the current thread until `futex_wake` is called on `address`, if the u32 at `address` is still `expected_value`
+/// 
+/// If the timeout flag is set, the thread is woken with [`SysErr::OkTimeout`] once the monotonic clock reaches `timeout` nanoseconds
+/// 
+/// Returns 1 if the thread waited and was woken, or 0 if the value at `address` was not `expected_value`
+pub fn futex_wait(options: u32, address: usize, expected_value: usize, timeout: usize) -> KResult<usize> {
+    let flags = FutexWaitFlags::from_bits_truncate(options);
+
+    if address % align_of::<u32>() != 0 {
+        return Err(SysErr::InvlAlign);
+    }
+
+    let post_switch_action = if flags.contains(FutexWaitFlags::TIMEOUT) {
+        PostSwitchAction::SetTimeout(timeout as u64)
+    } else {
+        PostSwitchAction::None
+    };
+
+    let _int_disable = IntDisable::new();
+
+    {
+        let current_thread = cpu_local_data().current_thread();
+        let mut futexes = current_thread.address_space().futexes().lock();
+
+        // the value is read with the futex queues locked, so a wake after the value is changed can't be missed
+        let mut value = [0u32];
+        copy_from_userspace(&mut value, address as *const u32)?;
+        if value[0] != expected_value as u32 {
+            return Ok(0);
+        }
+
+        futexes.add_waiter(address, ThreadRef::future_ref(&current_thread))?;
+    }
+    // all reference counted objects should be dropped here
+
+    switch_current_thread_to(
+        ThreadState::Suspended,
+        _int_disable,
+        post_switch_action,
+        false,
+    ).unwrap();
+
+    let _int_disable = IntDisable::new();
+
+    match cpu_local_data().current_thread().wake_reason() {
+        WakeReason::Timeout => Err(SysErr::OkTimeout),
+        _ => Ok(1),
+    }
+}
+
+/// Wakes up to `count` threads waiting on `address` in the current address space
+/// 
+/// Returns the number of threads woken
+pub fn futex_wake(_options: u32, address: usize, count: usize) -> KResult<usize> {
+    let _int_disable = IntDisable::new();
+
+    let current_thread = cpu_local_data().current_thread();
+    let woken = current_thread.address_space().futexes().lock().wake(address, count);
+
+    Ok(woken)
+}
\ No newline at end of file
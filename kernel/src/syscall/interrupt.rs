@@ -1,16 +1,18 @@
-use sys::{CapFlags, InterruptTrigger};
+use sys::{CapFlags, InterruptTrigger, InterruptNewFlags};
 
 use crate::alloc::HeapRef;
 use crate::cap::{Capability, StrongCapability};
 use crate::cap::capability_space::CapabilitySpace;
 use crate::container::Arc;
 use crate::int::userspace_interrupt::Interrupt;
+use crate::int::apic::prid_to_apic_id;
 use crate::prelude::*;
 use crate::arch::x64::IntDisable;
 use super::options_weak_autodestroy;
 
 pub fn interrupt_new(options: u32, int_allocator_id: usize, allocator_id: usize) -> KResult<(usize, usize, usize)> {
     let weak_auto_destroy = options_weak_autodestroy(options);
+    let flags = InterruptNewFlags::from_bits_truncate(options);
 
     let _int_disable = IntDisable::new();
 
@@ -24,7 +26,7 @@ pub fn interrupt_new(options: u32, int_allocator_id: usize, allocator_id: usize)
         .into_inner();
     let allocator = HeapRef::from_arc(allocator);
 
-    let interrupt = Interrupt::new(&allocator)?;
+    let interrupt = Interrupt::new(&allocator, flags.contains(InterruptNewFlags::AUTO_MASK))?;
     let interrupt_id = interrupt.interrupt_id();
 
     let int_capability = StrongCapability::new_flags(
@@ -42,7 +44,12 @@ pub fn interrupt_new(options: u32, int_allocator_id: usize, allocator_id: usize)
 }
 
 /// Gets the interrupt id for a given interrupt
-pub fn interrupt_id(options: u32, interrupt_id: usize) -> KResult<(usize, usize)> {
+/// 
+/// # Returns
+/// cpu: the processor id of the cpu the interrupt is delivered to
+/// interrupt_num: the interrupt vector on that cpu
+/// apic_id: the local apic id of the cpu the interrupt is delivered to, used for routing msi interrupts
+pub fn interrupt_id(options: u32, interrupt_id: usize) -> KResult<(usize, usize, usize)> {
     let weak_auto_destroy = options_weak_autodestroy(options);
 
     let _int_disable = IntDisable::new();
@@ -55,7 +62,32 @@ pub fn interrupt_id(options: u32, interrupt_id: usize) -> KResult<(usize, usize)
     Ok((
         interrupt_id.cpu.into(),
         interrupt_id.interrupt_num as usize,
+        prid_to_apic_id(interrupt_id.cpu) as usize,
     ))
 }
 
+/// Masks the interrupt if `masked` is non zero, or unmasks it otherwise
+pub fn interrupt_mask(options: u32, interrupt_id: usize, masked: usize) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    CapabilitySpace::current()
+        .get_interrupt_with_perms(interrupt_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner()
+        .set_masked(masked != 0)
+}
+
+/// Acknowledges the last delivered interrupt
+pub fn interrupt_ack(options: u32, interrupt_id: usize) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    CapabilitySpace::current()
+        .get_interrupt_with_perms(interrupt_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner()
+        .ack()
+}
+
 crate::generate_event_syscall!(interrupt, InterruptTrigger, interrupt_trigger, CapFlags::PROD, Interrupt::add_interrupt_listener);
\ No newline at end of file
@@ -0,0 +1,111 @@
+use sys::CapFlags;
+
+use crate::alloc::HeapRef;
+use crate::arch::x64::{IntDisable, inb, inw, ind, outb, outw, outd};
+use crate::cap::{StrongCapability, Capability};
+use crate::cap::capability_space::CapabilitySpace;
+use crate::container::Arc;
+use crate::prelude::*;
+
+use super::options_weak_autodestroy;
+
+/// Creates an io port capability for the `count` ports starting at `base`
+/// 
+/// # Required Capability Permissions
+/// `io_port_access`: cap_prod
+/// `allocator`: cap_prod
+/// 
+/// # Returns
+/// io_port: capability id of the new io port, fails with [`SysErr::InvlPerm`] if any of the ports are not allowed by `io_port_access`
+pub fn io_port_new(options: u32, io_port_access_id: usize, allocator_id: usize, base: usize, count: usize) -> KResult<usize> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+    let base = u16::try_from(base).map_err(|_| SysErr::InvlArgs)?;
+    let count = u32::try_from(count).map_err(|_| SysErr::Overflow)?;
+
+    let _int_disable = IntDisable::new();
+
+    let cspace = CapabilitySpace::current();
+
+    let io_port_access = cspace
+        .get_io_port_access_with_perms(io_port_access_id, CapFlags::PROD, weak_auto_destroy)?
+        .into_inner();
+
+    let allocator = cspace
+        .get_allocator_with_perms(allocator_id, CapFlags::PROD, weak_auto_destroy)?
+        .into_inner();
+    let heap_ref = HeapRef::from_arc(allocator);
+
+    let io_port = io_port_access.new_port(base, count)?;
+    let io_port_cap = StrongCapability::new_flags(
+        Arc::new(io_port, heap_ref)?,
+        CapFlags::all(),
+    );
+
+    let cap_id = cspace.insert_io_port(Capability::Strong(io_port_cap))?;
+    Ok(cap_id.into())
+}
+
+/// Checks that `width` is a valid io port access width in bytes
+fn validate_width(width: usize) -> KResult<u32> {
+    match width {
+        1 | 2 | 4 => Ok(width as u32),
+        _ => Err(SysErr::InvlArgs),
+    }
+}
+
+/// Reads `width` bytes from io port `port`
+/// 
+/// `width` must be 1, 2, or 4, and every port in the access must be in the range of `io_port`
+/// 
+/// # Required Capability Permissions
+/// `io_port`: cap_read
+/// 
+/// # Returns
+/// value: the value read from the port, zero extended
+pub fn io_port_in(options: u32, io_port_id: usize, port: usize, width: usize) -> KResult<usize> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+    let port = u16::try_from(port).map_err(|_| SysErr::InvlArgs)?;
+    let width = validate_width(width)?;
+
+    let _int_disable = IntDisable::new();
+
+    let io_port = CapabilitySpace::current()
+        .get_io_port_with_perms(io_port_id, CapFlags::READ, weak_auto_destroy)?
+        .into_inner();
+
+    io_port.check_access(port, width)?;
+
+    Ok(match width {
+        1 => inb(port) as usize,
+        2 => inw(port) as usize,
+        _ => ind(port) as usize,
+    })
+}
+
+/// Writes the low `width` bytes of `value` to io port `port`
+/// 
+/// `width` must be 1, 2, or 4, and every port in the access must be in the range of `io_port`
+/// 
+/// # Required Capability Permissions
+/// `io_port`: cap_write
+pub fn io_port_out(options: u32, io_port_id: usize, port: usize, width: usize, value: usize) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+    let port = u16::try_from(port).map_err(|_| SysErr::InvlArgs)?;
+    let width = validate_width(width)?;
+
+    let _int_disable = IntDisable::new();
+
+    let io_port = CapabilitySpace::current()
+        .get_io_port_with_perms(io_port_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner();
+
+    io_port.check_access(port, width)?;
+
+    match width {
+        1 => outb(port, value as u8),
+        2 => outw(port, value as u16),
+        _ => outd(port, value as u32),
+    }
+
+    Ok(())
+}
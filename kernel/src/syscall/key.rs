@@ -58,4 +58,28 @@ pub fn key_id(options: u32, key_cap_id: usize) -> KResult<usize> {
         .id() as usize;
 
     Ok(key_id)
-}
\ No newline at end of file
+}
+/// Registers `key` as the identity of the current process
+///
+/// Channel messages sent with the `SEND_IDENTITY` flag have the identity key's id attached by the kernel,
+/// so servers can tell which process made a call. The identity can only be registered once.
+///
+/// # Required Capability Permissions
+/// `key`: cap_read
+///
+/// # Returns
+/// InvlOp: the process already has an identity
+pub fn key_register_identity(options: u32, key_cap_id: usize) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let cspace = CapabilitySpace::current();
+
+    let key_id = cspace
+        .get_key_with_perms(key_cap_id, CapFlags::READ, weak_auto_destroy)?
+        .into_inner()
+        .id();
+
+    cspace.register_identity(key_id)
+}
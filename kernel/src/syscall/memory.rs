@@ -1,7 +1,10 @@
-use sys::{MemoryNewFlags, MemoryResizeFlags, MemoryMapFlags, MemoryUpdateMappingFlags, MemoryMappingFlags};
+use sys::{
+    MemoryNewFlags, MemoryResizeFlags, MemoryMapFlags, MemoryUpdateMappingFlags, MemoryMappingFlags, AddressSpaceSetFaultHandlerFlags,
+    AddressSpaceListMappingsFlags, MemoryQueryDirtyFlags, CapId, EventId, MappingInfo, MappingKind,
+};
 
 use crate::alloc::{PaRef, HeapRef};
-use crate::cap::address_space::AddressSpace;
+use crate::cap::address_space::{AddressSpace, AddrSpaceMapping, FaultHandler};
 use crate::cap::capability_space::CapabilitySpace;
 use crate::cap::memory::{PageSource, MapMemoryArgs, UpdateValue, UpdateMappingAgs};
 use crate::cap::{StrongCapability, Capability};
@@ -9,8 +12,15 @@ use crate::cap::{CapFlags, memory::Memory};
 use crate::prelude::*;
 use crate::arch::x64::IntDisable;
 use crate::container::Arc;
+use crate::event::EventPoolListenerRef;
 use crate::vmem_manager::PageMappingOptions;
-use super::options_weak_autodestroy;
+use super::{options_weak_autodestroy, copy_to_userspace};
+
+/// Number of mappings collected at once by `address_space_list_mappings` before they are copied to userspace
+const MAPPING_LIST_CHUNK_SIZE: usize = 16;
+
+/// Number of pages queried at once by `memory_query_dirty` before the bitmap is copied to userspace
+const DIRTY_QUERY_CHUNK_PAGES: usize = 512;
 
 pub fn address_space_new(options: u32, allocator_id: usize) -> KResult<usize> {
     let weak_auto_destroy = options_weak_autodestroy(options);
@@ -68,6 +78,246 @@ pub fn address_space_unmap(
     addr_space.unmap(address)
 }
 
+/// Unmaps everything in the `pages` pages starting at `address`
+/// 
+/// Memory mappings only partially covered by the range are shrunk, or split in 2 if the range is in the middle of them.
+/// Other mappings, such as event pools, can't be split, and must be entirely inside of the range.
+/// Parts of the range where nothing is mapped are ignored.
+/// 
+/// # Required Capability Permissions
+/// `addr_space`: cap_write
+/// 
+/// # Syserr Code
+/// InvlArgs: `pages` was 0
+/// Overflow: the range extends past the end of the address space
+/// InvlOp: the range partially covers a mapping which can't be split
+pub fn address_space_unmap_range(
+    options: u32,
+    addr_space_id: usize,
+    address: usize,
+    pages: usize,
+) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+    let address = VirtAddr::try_new_aligned(address)?;
+
+    if pages == 0 {
+        return Err(SysErr::InvlArgs);
+    }
+    let size = Size::try_from_pages(pages).ok_or(SysErr::Overflow)?;
+    address.as_usize().checked_add(size.bytes()).ok_or(SysErr::Overflow)?;
+
+    let _int_disable = IntDisable::new();
+
+    let addr_space = CapabilitySpace::current()
+        .get_address_space_with_perms(addr_space_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner();
+
+    addr_space.unmap_range(AVirtRange::new(address, size.bytes()))
+}
+
+/// Lists the mappings in the address space `addr_space`
+/// 
+/// Up to `buffer_len` `MappingInfo` records are written to the array at `buffer`, in address order,
+/// starting with the first mapping at an address of at least `cursor`.
+/// To list the entire address space, start with a cursor of 0 and pass in the returned cursor on each subsequent call,
+/// until fewer than `buffer_len` records are written.
+/// 
+/// If the `CAP_IDS` option is set, each record also holds the id of a capability in `cspace` which references the mapped object,
+/// or the null id if `cspace` has no such capability. Otherwise `cspace` is ignored.
+/// 
+/// Mappings changed while listing may or may not be returned.
+/// 
+/// The number of pages used by the address space's page tables is also returned, not counting the tables
+/// of kernel memory which are shared by every address space. It can be read without listing anything
+/// by passing a `buffer_len` of 0.
+/// 
+/// # Required Capability Permissions
+/// `addr_space`: cap_read
+/// `cspace`: cap_read
+/// 
+/// # Syserr Code
+/// InvlBuffer: `buffer` is not valid for writing `buffer_len` records
+/// 
+/// # Returns
+/// count: number of records written to `buffer`
+/// cursor: cursor to continue listing from
+/// page_table_pages: number of pages used by page tables
+pub fn address_space_list_mappings(
+    options: u32,
+    addr_space_id: usize,
+    cspace_id: usize,
+    buffer: usize,
+    buffer_len: usize,
+    cursor: usize,
+) -> KResult<(usize, usize, usize)> {
+    let flags = AddressSpaceListMappingsFlags::from_bits_truncate(options);
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let buffer = buffer as *mut MappingInfo;
+
+    let _int_disable = IntDisable::new();
+
+    let current_cspace = CapabilitySpace::current();
+
+    let addr_space = current_cspace
+        .get_address_space_with_perms(addr_space_id, CapFlags::READ, weak_auto_destroy)?
+        .into_inner();
+
+    let cspace = if flags.contains(AddressSpaceListMappingsFlags::CAP_IDS) {
+        Some(current_cspace
+            .get_capability_space_with_perms(cspace_id, CapFlags::READ, weak_auto_destroy)?
+            .into_inner())
+    } else {
+        None
+    };
+
+    let mut cursor = cursor;
+    let mut write_count = 0;
+
+    while write_count < buffer_len {
+        let chunk_len = core::cmp::min(MAPPING_LIST_CHUNK_SIZE, buffer_len - write_count);
+
+        let mut mappings = [const { None }; MAPPING_LIST_CHUNK_SIZE];
+        let count = addr_space.list_mappings(cursor, &mut mappings[..chunk_len]);
+
+        // the address space lock is no longer held here, so looking up capability ids and copying
+        // to userspace (which may page fault) can't deadlock
+        let mut infos = [MappingInfo::default(); MAPPING_LIST_CHUNK_SIZE];
+        for (info, mapping) in infos.iter_mut().zip(mappings[..count].iter()) {
+            let mapping = mapping.as_ref().unwrap();
+            *info = mapping_info(mapping, cspace.as_deref());
+            cursor = info.address + 1;
+        }
+
+        copy_to_userspace(buffer.wrapping_add(write_count), &infos[..count])?;
+
+        write_count += count;
+
+        if count < chunk_len {
+            break;
+        }
+    }
+
+    Ok((write_count, cursor, addr_space.page_table_pages()))
+}
+
+fn mapping_info(mapping: &AddrSpaceMapping, cspace: Option<&CapabilitySpace>) -> MappingInfo {
+    let (kind, flags, cap_id) = match mapping {
+        AddrSpaceMapping::Memory(mapping) => (
+            MappingKind::Memory,
+            MemoryMappingFlags::from(mapping.location.options),
+            cspace.and_then(|cspace| cspace.find_memory_id(&mapping.memory)),
+        ),
+        AddrSpaceMapping::EventPool(mapping) => (
+            MappingKind::EventPool,
+            MemoryMappingFlags::READ | MemoryMappingFlags::WRITE,
+            cspace.and_then(|cspace| cspace.find_event_pool_id(&mapping.event_pool)),
+        ),
+        AddrSpaceMapping::PhysMem(mapping) => (
+            MappingKind::PhysMem,
+            MemoryMappingFlags::from(mapping.options),
+            cspace.and_then(|cspace| cspace.find_phys_mem_id(&mapping.phys_mem)),
+        ),
+        AddrSpaceMapping::Guard(_) => (MappingKind::Guard, MemoryMappingFlags::empty(), None),
+    };
+
+    let map_range = mapping.map_range();
+
+    MappingInfo {
+        address: map_range.addr().as_usize(),
+        size: map_range.size(),
+        kind: kind as usize,
+        flags: flags.bits() as usize,
+        cap_id: cap_id.unwrap_or_else(CapId::null).into(),
+    }
+}
+
+/// Reserves `pages` pages starting at `address` as a guard region
+/// 
+/// Nothing can be mapped in a guard region until it is unmapped with `address_space_unmap`.
+/// A thread which faults inside of a guard region exits with a stack overflow exit reason.
+/// 
+/// # Required Capability Permissions
+/// `addr_space`: cap_write
+/// 
+/// # Syserr Code
+/// InvlArgs: `pages` was 0
+/// InvlMemZone: the guard region overlaps with another mapping
+pub fn address_space_map_guard(
+    options: u32,
+    addr_space_id: usize,
+    address: usize,
+    pages: usize,
+) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+    let address = VirtAddr::try_new_aligned(address)?;
+
+    if pages == 0 {
+        return Err(SysErr::InvlArgs);
+    }
+    let size = Size::try_from_pages(pages).ok_or(SysErr::Overflow)?;
+
+    let _int_disable = IntDisable::new();
+
+    let addr_space = CapabilitySpace::current()
+        .get_address_space_with_perms(addr_space_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner();
+
+    addr_space.map_guard(AVirtRange::new(address, size.bytes()))
+}
+
+/// Sets the handler which is notified of page faults in an address space that the kernel can't resolve
+/// 
+/// When a thread faults on such an address, it is suspended and a page fault event with `event_id` is sent to `event_pool`.
+/// A weak capability to the faulting thread is inserted into the current capability space, and its id is sent in the event.
+/// The fault handler can then resume the thread to retry the access, or destroy it.
+/// Any previous fault handler is replaced.
+/// 
+/// If there is no fault handler, faults which can't be resolved are fatal.
+/// 
+/// # Options
+/// bit 0 (clear): removes the fault handler, `event_pool` and `event_id` are ignored
+/// 
+/// # Required Capability Permissions
+/// `addr_space`: cap_write
+/// `event_pool`: cap_write
+pub fn address_space_set_fault_handler(
+    options: u32,
+    addr_space_id: usize,
+    event_pool_id: usize,
+    event_id: usize,
+) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+    let flags = AddressSpaceSetFaultHandlerFlags::from_bits_truncate(options);
+
+    let _int_disable = IntDisable::new();
+
+    let cspace = CapabilitySpace::current();
+
+    let addr_space = cspace
+        .get_address_space_with_perms(addr_space_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner();
+
+    if flags.contains(AddressSpaceSetFaultHandlerFlags::CLEAR) {
+        addr_space.set_fault_handler(None);
+        return Ok(());
+    }
+
+    let event_pool = cspace
+        .get_event_pool_with_perms(event_pool_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner();
+
+    addr_space.set_fault_handler(Some(FaultHandler {
+        listener: EventPoolListenerRef {
+            event_pool: Arc::downgrade(&event_pool),
+            event_id: EventId::from_u64(event_id as u64),
+        },
+        cspace: Arc::downgrade(&cspace),
+    }));
+
+    Ok(())
+}
+
 /// Allocate a memory capability at least `pages` big
 /// 
 /// returns the capability referencing the memory
@@ -139,6 +389,47 @@ pub fn memory_get_size(options: u32, memory_id: usize) -> KResult<usize> {
     Ok(inner.size().pages_rounded())
 }
 
+/// Permanently makes `memory` read only
+/// 
+/// Existing writable mappings of `memory` are remapped read only. Afterwards `memory` can't be mapped writable,
+/// resized, copied into, or written by channel messages. Copy on write clones made before sealing are not affected.
+/// Sealing memory which is already sealed does nothing.
+/// 
+/// # Required Capability Permissions
+/// `memory`: cap_write, cap_prod
+pub fn memory_seal(options: u32, memory_id: usize) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let memory = CapabilitySpace::current()
+        .get_memory_with_perms(memory_id, CapFlags::WRITE | CapFlags::PROD, weak_auto_destroy)?
+        .into_inner();
+
+    memory.seal()
+}
+
+/// Checks if the memory capability has been sealed with [`memory_seal`]
+/// 
+/// # Required Capability Permissions
+/// `memory`: cap_read
+/// 
+/// # Returns
+/// sealed: 1 if `memory` is sealed, 0 otherwise
+pub fn memory_is_sealed(options: u32, memory_id: usize) -> KResult<usize> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let memory = CapabilitySpace::current()
+        .get_memory_with_perms(memory_id, CapFlags::READ, weak_auto_destroy)?
+        .into_inner();
+
+    let inner = memory.inner_read();
+
+    Ok(inner.is_sealed() as usize)
+}
+
 /// maps a capability `mem` that can be mapped into memory into the memory of process `process` starting at address `addr`
 /// 
 /// the cap id of `mem` is looked up in the process that is having memory mapped into it
@@ -164,6 +455,7 @@ pub fn memory_get_size(options: u32, memory_id: usize) -> KResult<usize> {
 /// InvlMemZone: the value passed in for `addr` causes the mapped memory to overlap with other virtual memory
 /// InvlWeak: `mem` is a weak capability, mapping a weak capability is not allowed
 /// InvlArgs: options has no bits set indicating read, write, or exec permissions
+/// InvlPerm: the mapping is writable and `mem` is sealed
 /// 
 /// # Returns
 /// size: size of the memory that was mapped into address space in pages
@@ -228,6 +520,7 @@ pub fn memory_map(
 /// # Syserr Code
 /// InvlOp: `mem` is not mapped into `process` address space
 /// InvlWeak: `mem` is a weak capability
+/// InvlPerm: the new mapping flags are writable and `mem` is sealed
 /// 
 /// # Returns
 /// Returns the size of the new mapping in pages
@@ -294,6 +587,7 @@ pub fn memory_update_mapping(
 /// # Syserr Code
 /// InvlOp: `memory` is mapped into memory somewhere when it shouldn't be
 /// InvlArgs: `new_page_size` is 0
+/// InvlPerm: `memory` is sealed
 /// 
 /// # Returns
 /// The new size of the memory capability in pages
@@ -332,4 +626,175 @@ pub fn memory_resize(
     } else {
         memory.resize(new_page_size, page_source)
     }.map(Size::pages_rounded)
+}
+
+/// Copies `size` bytes from `src_memory` at byte offset `src_offset` into `dst_memory` at byte offset `dst_offset`
+/// 
+/// Neither memory capability needs to be mapped, lazily allocated and copy on write pages are resolved as needed.
+/// `dst_memory` and `src_memory` may reference the same memory, as long as the source and destination ranges do not overlap.
+/// 
+/// NOTE: weak auto destroy applies to both memory capabilities
+/// 
+/// # Required Capability Permissions
+/// `dst_memory`: cap_write
+/// `src_memory`: cap_read
+/// 
+/// # Syserr Code
+/// InvlMemZone: the source or destination range extends past the end of its memory capability
+/// InvlArgs: the source and destination ranges overlap in the same memory
+/// Overflow: an offset plus `size` overflows
+/// InvlPerm: `dst_memory` is sealed
+pub fn memory_copy(
+    options: u32,
+    dst_memory_id: usize,
+    dst_offset: usize,
+    src_memory_id: usize,
+    src_offset: usize,
+    size: usize,
+) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let cspace = CapabilitySpace::current();
+
+    let dst_memory = cspace
+        .get_memory_with_perms(dst_memory_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner();
+
+    let src_memory = cspace
+        .get_memory_with_perms(src_memory_id, CapFlags::READ, weak_auto_destroy)?
+        .into_inner();
+
+    Memory::copy_memory(&dst_memory, dst_offset, &src_memory, src_offset, size)
+}
+
+/// Gets the physical address of the page at `page_index` in `memory`, for use in dma by device drivers
+/// 
+/// If the page is lazily allocated or copy on write, it is allocated or copied first.
+/// The page keeps the same physical address until `memory` is resized to not contain it,
+/// or a copy on write clone of `memory` is made.
+/// 
+/// # Required Capability Permissions
+/// `memory`: cap_read, cap_write
+/// 
+/// # Syserr Code
+/// InvlMemZone: `page_index` is past the end of the memory
+/// InvlPerm: `memory` is sealed
+/// 
+/// # Returns
+/// phys_addr: physical address of the page
+pub fn memory_phys_addr(options: u32, memory_id: usize, page_index: usize) -> KResult<usize> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let memory = CapabilitySpace::current()
+        .get_memory_with_perms(memory_id, CapFlags::READ | CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner();
+
+    memory.page_phys_addr(page_index).map(|phys_addr| phys_addr.as_usize())
+}
+
+/// Finds which of the `page_count` pages starting at `start_page` in `memory` were written since they were last reset
+/// 
+/// The hardware dirty bits of every mapping of `memory` are combined, pages which are not mapped anywhere are clean.
+/// Writes made by the kernel, like channel messages and `memory_copy`, are not tracked.
+/// 
+/// # Options
+/// bit 0 (reset): clears the dirty bits of the queried pages
+/// 
+/// # Required Capability Permissions
+/// `memory`: cap_read, and cap_write if reset is set
+/// 
+/// # Syserr Code
+/// InvlMemZone: the queried pages go past the end of `memory`
+/// InvlBuffer: `bitmap` is not valid for writing `(page_count + 7) / 8` bytes
+/// 
+/// # Returns
+/// dirty_count: number of dirty pages, bit `i` of `bitmap` is set if page `start_page + i` is dirty
+pub fn memory_query_dirty(
+    options: u32,
+    memory_id: usize,
+    start_page: usize,
+    page_count: usize,
+    bitmap: usize,
+) -> KResult<usize> {
+    let flags = MemoryQueryDirtyFlags::from_bits_truncate(options);
+    let weak_auto_destroy = options_weak_autodestroy(options);
+    let reset = flags.contains(MemoryQueryDirtyFlags::RESET);
+
+    let required_perms = if reset {
+        CapFlags::READ | CapFlags::WRITE
+    } else {
+        CapFlags::READ
+    };
+
+    let bitmap = bitmap as *mut u8;
+    let end_page = start_page.checked_add(page_count).ok_or(SysErr::Overflow)?;
+
+    let _int_disable = IntDisable::new();
+
+    let memory = CapabilitySpace::current()
+        .get_memory_with_perms(memory_id, required_perms, weak_auto_destroy)?
+        .into_inner();
+
+    let mut dirty_count = 0;
+    for chunk_start in (start_page..end_page).step_by(DIRTY_QUERY_CHUNK_PAGES) {
+        let chunk_end = core::cmp::min(chunk_start + DIRTY_QUERY_CHUNK_PAGES, end_page);
+
+        // the memory and address space locks are not held while copying, since copying may page fault
+        let mut chunk_bitmap = [0u8; DIRTY_QUERY_CHUNK_PAGES / 8];
+        dirty_count += memory.query_dirty(chunk_start..chunk_end, reset, &mut chunk_bitmap)?;
+
+        let byte_offset = (chunk_start - start_page) / 8;
+        let byte_count = (chunk_end - chunk_start).div_ceil(8);
+        copy_to_userspace(bitmap.wrapping_add(byte_offset), &chunk_bitmap[..byte_count])?;
+    }
+
+    Ok(dirty_count)
+}
+
+/// Creates a new memory capability which is a copy on write clone of `src_memory`
+/// 
+/// Pages are shared between both memory capabilities until one of them writes to a page,
+/// at which point that page is copied. Existing mappings of `src_memory` are remapped read only.
+/// 
+/// NOTE: weak auto destroy applies to both the allocator and memory capability
+/// 
+/// # Required Capability Permissions
+/// `allocator`: cap_prod
+/// `src_memory`: cap_read
+/// 
+/// # Returns
+/// mem: cid of the new memory
+/// size: size of the new memory capability in pages
+pub fn memory_new_cow(options: u32, allocator_id: usize, src_memory_id: usize) -> KResult<(usize, usize)> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let cspace = CapabilitySpace::current();
+
+    let allocator = cspace
+        .get_allocator_with_perms(allocator_id, CapFlags::PROD, weak_auto_destroy)?
+        .into_inner();
+    let page_allocator = PaRef::from_arc(allocator.clone());
+    let heap_allocator = HeapRef::from_arc(allocator);
+
+    let src_memory = cspace
+        .get_memory_with_perms(src_memory_id, CapFlags::READ, weak_auto_destroy)?
+        .into_inner();
+
+    let memory = StrongCapability::new_flags(
+        Arc::new(
+            src_memory.new_cow(page_allocator, heap_allocator.clone())?,
+            heap_allocator,
+        )?,
+        CapFlags::all(),
+    );
+
+    let size = memory.inner().inner_read().size();
+
+    Ok((cspace.insert_memory(Capability::Strong(memory))?.into(), size.pages_rounded()))
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use sys::{CapFlags, MemoryMappingFlags};
 
-use crate::alloc::HeapRef;
+use crate::alloc::{HeapRef, MmioAllocator, PhysMem};
 use crate::cap::{StrongCapability, Capability};
 use crate::cap::capability_space::CapabilitySpace;
 use crate::prelude::*;
@@ -11,6 +11,15 @@ use crate::vmem_manager::PageMappingOptions;
 use super::options_weak_autodestroy;
 
 pub fn mmio_allocator_alloc(options: u32, mmio_allocator_id: usize, allocator_id: usize, phys_address: usize, page_count: usize) -> KResult<usize> {
+    create_phys_mem(options, mmio_allocator_id, allocator_id, phys_address, page_count, false)
+}
+
+pub fn mmio_allocator_request_region(options: u32, mmio_allocator_id: usize, allocator_id: usize, phys_address: usize, page_count: usize) -> KResult<usize> {
+    create_phys_mem(options, mmio_allocator_id, allocator_id, phys_address, page_count, true)
+}
+
+/// Creates a phys mem capability for the given region, if `claim` is true the region is exclusively claimed
+fn create_phys_mem(options: u32, mmio_allocator_id: usize, allocator_id: usize, phys_address: usize, page_count: usize, claim: bool) -> KResult<usize> {
     let weak_auto_destroy = options_weak_autodestroy(options);
     let phys_address = PhysAddr::try_new(phys_address)
         .ok_or(SysErr::InvlPhysAddr)?;
@@ -34,7 +43,11 @@ pub fn mmio_allocator_alloc(options: u32, mmio_allocator_id: usize, allocator_id
         .into_inner();
     let heap_ref = HeapRef::from_arc(allocator);
 
-    let phys_mem = mmio_allocator.alloc(phys_range)?;
+    let phys_mem = if claim {
+        MmioAllocator::request_region(&mmio_allocator, phys_range)?
+    } else {
+        mmio_allocator.alloc(phys_range)?
+    };
     let phys_mem_cap = StrongCapability::new_flags(
         Arc::new(
             phys_mem,
@@ -67,7 +80,7 @@ pub fn phys_mem_map(options: u32, addr_space_id: usize, phys_mem_id: usize, addr
         .get_phys_mem_with_perms(phys_mem_id, map_options.required_cap_flags(), weak_auto_destroy)?
         .into_inner();
 
-    phys_mem.map(&addr_space, address, map_options)
+    PhysMem::map(&phys_mem, &addr_space, address, map_options)
         .map(Size::pages_rounded)
 }
 
@@ -8,19 +8,27 @@ use crate::arch::x64::{
 	rdmsr, wrmsr, EFER_MSR, EFER_SYSCALL_ENABLE, FMASK_MSR, LSTAR_MSR, STAR_MSR, asm_user_copy,
 };
 
+mod allocator;
+use allocator::*;
 mod cap;
 use cap::*;
 mod channel;
 use channel::*;
 mod debug;
 use debug::*;
+mod entropy;
+use entropy::*;
 mod event;
 mod drop_check;
 use drop_check::*;
 mod event_pool;
 use event_pool::*;
+mod futex;
+use futex::*;
 mod interrupt;
 use interrupt::*;
+mod io_port;
+use io_port::*;
 mod key;
 use key::*;
 mod memory;
@@ -31,6 +39,8 @@ mod thread;
 use thread::*;
 mod thread_group;
 use thread_group::*;
+mod time;
+use time::*;
 
 mod strace;
 
@@ -257,64 +267,104 @@ macro_rules! sysret_5 {
 /// This function is called by the assembly syscall entry point
 #[no_mangle]
 extern "C" fn rust_syscall_entry(syscall_num: u32, vals: &mut SyscallVals) {
-	let strace_args_string = if syscall_num != PRINT_DEBUG {
-		Some(strace::get_strace_args_string(syscall_num, vals))
-	} else {
-		None
+	// when strace is disabled this is just a cpu local load, a relaxed load of the enabled flag, and a branch
+	let strace_args_string = match cpu_local_data().current_strace() {
+		Some(strace) if strace.is_enabled() && syscall_num != PRINT_DEBUG && syscall_num != PRINT_DEBUG_BUFFER && strace.is_traced(syscall_num) => {
+			Some(strace::get_strace_args_string(syscall_num, vals))
+		},
+		_ => None,
 	};
 
     match syscall_num {
 		PRINT_DEBUG => sysret_0!(syscall_8!(print_debug, vals), vals),
+		PRINT_DEBUG_BUFFER => sysret_1!(syscall_2!(print_debug_buffer, vals), vals),
+		DEBUG_SET_STRACE => sysret_0!(syscall_5!(debug_set_strace, vals), vals),
+		DEBUG_READ_KLOG => sysret_2!(syscall_5!(debug_read_klog, vals), vals),
+		DEBUG_EXIT_EMULATOR => sysret_0!(syscall_2!(debug_exit_emulator, vals), vals),
+		DEBUG_CONSOLE_HANDOFF => sysret_0!(syscall_1!(debug_console_handoff, vals), vals),
 		THREAD_GROUP_NEW => sysret_1!(syscall_2!(thread_group_new, vals), vals),
-		THREAD_GROUP_EXIT => sysret_0!(syscall_1!(thread_group_exit, vals), vals),
+		THREAD_GROUP_EXIT => sysret_0!(syscall_2!(thread_group_exit, vals), vals),
+		THREAD_GROUP_STATS => sysret_5!(syscall_1!(thread_group_stats, vals), vals),
+		THREAD_GROUP_FAULT_INFO => sysret_1!(syscall_2!(thread_group_fault_info, vals), vals),
+		THREAD_GROUP_SET_FAULT_CAPTURE => sysret_0!(syscall_2!(thread_group_set_fault_capture, vals), vals),
 		THREAD_NEW => sysret_2!(syscall_6!(thread_new, vals), vals),
 		THREAD_YIELD => sysret_0!(thread_yield(), vals),
 		THREAD_DESTROY => sysret_0!(syscall_1!(thread_destroy, vals), vals),
 		THREAD_SUSPEND => sysret_0!(syscall_1!(thread_suspend, vals), vals),
 		THREAD_RESUME => sysret_0!(syscall_1!(thread_resume, vals), vals),
-		THREAD_SET_PROPERTY => sysret_0!(syscall_2!(thread_set_property, vals), vals),
-		THREAD_HANDLE_THREAD_EXIT_SYNC => sysret_0!(syscall_2!(thread_handle_thread_exit_sync, vals), vals),
+		THREAD_SET_PROPERTY => sysret_0!(syscall_3!(thread_set_property, vals), vals),
+		THREAD_HANDLE_THREAD_EXIT_SYNC => sysret_1!(syscall_2!(thread_handle_thread_exit_sync, vals), vals),
 		THREAD_HANDLE_THREAD_EXIT_ASYNC => sysret_0!(syscall_3!(thread_handle_thread_exit_async, vals), vals),
+		THREAD_RUNTIME => sysret_1!(syscall_1!(thread_runtime, vals), vals),
+		THREAD_PARK => sysret_0!(syscall_1!(thread_park, vals), vals),
+		THREAD_UNPARK => sysret_0!(syscall_1!(thread_unpark, vals), vals),
 		CAP_CLONE => sysret_1!(syscall_3!(cap_clone, vals), vals),
 		CAP_DESTROY => sysret_0!(syscall_2!(cap_destroy, vals), vals),
 		ADDRESS_SPACE_NEW => sysret_1!(syscall_1!(address_space_new, vals), vals),
 		ADDRESS_SPACE_UNMAP => sysret_0!(syscall_2!(address_space_unmap, vals), vals),
+		ADDRESS_SPACE_SET_FAULT_HANDLER => sysret_0!(syscall_3!(address_space_set_fault_handler, vals), vals),
+		ADDRESS_SPACE_MAP_GUARD => sysret_0!(syscall_3!(address_space_map_guard, vals), vals),
+		ADDRESS_SPACE_UNMAP_RANGE => sysret_0!(syscall_3!(address_space_unmap_range, vals), vals),
+		ADDRESS_SPACE_LIST_MAPPINGS => sysret_3!(syscall_5!(address_space_list_mappings, vals), vals),
 		MEMORY_MAP => sysret_1!(syscall_5!(memory_map, vals), vals),
 		MEMORY_UPDATE_MAPPING => sysret_1!(syscall_3!(memory_update_mapping, vals), vals),
 		MEMORY_NEW => sysret_2!(syscall_2!(memory_new, vals), vals),
 		MEMORY_GET_SIZE => sysret_1!(syscall_1!(memory_get_size, vals), vals),
 		MEMORY_RESIZE => sysret_1!(syscall_2!(memory_resize, vals), vals),
-		EVENT_POOL_NEW => sysret_1!(syscall_2!(event_pool_new, vals), vals),
+		MEMORY_COPY => sysret_0!(syscall_5!(memory_copy, vals), vals),
+		MEMORY_NEW_COW => sysret_2!(syscall_2!(memory_new_cow, vals), vals),
+		MEMORY_PHYS_ADDR => sysret_1!(syscall_2!(memory_phys_addr, vals), vals),
+		MEMORY_SEAL => sysret_0!(syscall_1!(memory_seal, vals), vals),
+		MEMORY_IS_SEALED => sysret_1!(syscall_1!(memory_is_sealed, vals), vals),
+		MEMORY_QUERY_DIRTY => sysret_1!(syscall_4!(memory_query_dirty, vals), vals),
+		EVENT_POOL_NEW => sysret_1!(syscall_3!(event_pool_new, vals), vals),
 		EVENT_POOL_MAP => sysret_1!(syscall_3!(event_pool_map, vals), vals),
-		EVENT_POOL_AWAIT => sysret_2!(syscall_2!(event_pool_await, vals), vals),
-		CHANNEL_NEW => sysret_1!(syscall_1!(channel_new, vals), vals),
+		EVENT_POOL_AWAIT => sysret_3!(syscall_2!(event_pool_await, vals), vals),
+		CHANNEL_NEW => sysret_1!(syscall_2!(channel_new, vals), vals),
 		CHANNEL_TRY_SEND => sysret_1!(syscall_4!(channel_try_send, vals), vals),
 		CHANNEL_SYNC_SEND => sysret_1!(syscall_5!(channel_sync_send, vals), vals),
 		CHANNEL_ASYNC_SEND => sysret_0!(syscall_6!(channel_async_send, vals), vals),
-		CHANNEL_TRY_RECV => sysret_2!(syscall_4!(channel_try_recv, vals), vals),
-		CHANNEL_SYNC_RECV => sysret_2!(syscall_5!(channel_sync_recv, vals), vals),
+		CHANNEL_ASYNC_WAIT_SPACE => sysret_0!(syscall_3!(channel_async_wait_space, vals), vals),
+		CHANNEL_TRY_RECV => sysret_3!(syscall_4!(channel_try_recv, vals), vals),
+		CHANNEL_SYNC_RECV => sysret_3!(syscall_5!(channel_sync_recv, vals), vals),
 		CHANNEL_ASYNC_RECV => sysret_0!(syscall_3!(channel_async_recv, vals), vals),
 		CHANNEL_SYNC_CALL => sysret_1!(syscall_8!(channel_sync_call, vals), vals),
-		CHANNEL_ASYNC_CALL => sysret_0!(syscall_6!(channel_async_call, vals), vals),
+		CHANNEL_ASYNC_CALL => sysret_0!(syscall_8!(channel_async_call, vals), vals),
 		REPLY_REPLY => sysret_1!(syscall_4!(reply_reply, vals), vals),
 		KEY_NEW => sysret_1!(syscall_1!(key_new, vals), vals),
 		KEY_ID => sysret_1!(syscall_1!(key_id, vals), vals),
+		KEY_REGISTER_IDENTITY => sysret_0!(syscall_1!(key_register_identity, vals), vals),
 		DROP_CHECK_NEW => sysret_2!(syscall_2!(drop_check_new, vals), vals),
 		DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_SYNC => sysret_1!(syscall_2!(drop_check_reciever_handle_cap_drop_sync, vals), vals),
 		DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_ASYNC => sysret_0!(syscall_3!(drop_check_reciever_handle_cap_drop_async, vals), vals),
 		MMIO_ALLOCATOR_ALLOC => sysret_1!(syscall_4!(mmio_allocator_alloc, vals), vals),
 		PHYS_MEM_MAP => sysret_1!(syscall_3!(phys_mem_map, vals), vals),
 		PHYS_MEM_GET_SIZE => sysret_1!(syscall_1!(phys_mem_get_size, vals), vals),
+		MMIO_ALLOCATOR_REQUEST_REGION => sysret_1!(syscall_4!(mmio_allocator_request_region, vals), vals),
 		INTERRUPT_NEW => sysret_3!(syscall_2!(interrupt_new, vals), vals),
-		INTERRUPT_ID => sysret_2!(syscall_1!(interrupt_id, vals), vals),
+		INTERRUPT_ID => sysret_3!(syscall_1!(interrupt_id, vals), vals),
 		INTERRUPT_HANDLE_INTERRUPT_TRIGGER_SYNC => sysret_0!(syscall_2!(interrupt_handle_interrupt_trigger_sync, vals), vals),
 		INTERRUPT_HANDLE_INTERRUPT_TRIGGER_ASYNC => sysret_0!(syscall_3!(interrupt_handle_interrupt_trigger_async, vals), vals),
+		INTERRUPT_MASK => sysret_0!(syscall_2!(interrupt_mask, vals), vals),
+		INTERRUPT_ACK => sysret_0!(syscall_1!(interrupt_ack, vals), vals),
+		IO_PORT_NEW => sysret_1!(syscall_4!(io_port_new, vals), vals),
+		IO_PORT_IN => sysret_1!(syscall_3!(io_port_in, vals), vals),
+		IO_PORT_OUT => sysret_0!(syscall_4!(io_port_out, vals), vals),
+		CAPABILITY_SPACE_LIST => sysret_2!(syscall_4!(capability_space_list, vals), vals),
+		ALLOCATOR_NEW_CHILD => sysret_1!(syscall_2!(allocator_new_child, vals), vals),
+		ALLOCATOR_SET_LIMIT => sysret_0!(syscall_2!(allocator_set_limit, vals), vals),
+		ALLOCATOR_USAGE => sysret_2!(syscall_1!(allocator_usage, vals), vals),
+		SYSTEM_ENTROPY => sysret_3!(syscall_0!(system_entropy, vals), vals),
+		SYSTEM_MEM_INFO => sysret_0!(syscall_4!(system_mem_info, vals), vals),
+		FUTEX_WAIT => sysret_1!(syscall_3!(futex_wait, vals), vals),
+		FUTEX_WAKE => sysret_1!(syscall_2!(futex_wake, vals), vals),
+		TIME_GET => sysret_2!(syscall_1!(time_get, vals), vals),
         _ => vals.a1 = SysErr::InvlSyscall.num(),
     }
 
 	if let Some(args_string) = strace_args_string {
 		let ret_string = strace::get_strace_return_string(syscall_num, vals);
-		eprintln!("{} -> {}", args_string, ret_string);
+		strace::print_strace(&args_string, &ret_string);
 	}
 }
 
@@ -327,52 +377,78 @@ fn options_weak_autodestroy(options: u32) -> bool {
 	is_option_set(options, 1 << 31)
 }
 
-fn copy_from_userspace<T: Pod>(dst: &mut [T], src: *const T) -> KResult<()> {
-	let copy_count = dst.len() * size_of::<T>();
-	let end_read_addr = (src as usize).checked_add(copy_count)
-		.ok_or(SysErr::Overflow)?;
+/// Error from copying to or from userspace
+///
+/// Handlers only copy from userspace before changing any kernel state, and copy to userspace after
+/// every change is made, so a failed copy never leaves kernel state half updated. When copying to userspace fails,
+/// the part of the user buffer before the fault has been written, which is fine since the syscall returns an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserCopyError {
+	/// The buffer was rejected before anything was copied
+	Rejected(SysErr),
+	/// A page fault which could not be resolved occured part way through the buffer
+	PartialCopy {
+		/// Number of bytes copied before the fault
+		copied: usize,
+	},
+}
+
+impl From<UserCopyError> for SysErr {
+	fn from(error: UserCopyError) -> Self {
+		match error {
+			UserCopyError::Rejected(error) => error,
+			UserCopyError::PartialCopy { .. } => SysErr::InvlBuffer,
+		}
+	}
+}
+
+/// Checks that the `copy_count` bytes at `user_addr` are all in the lower half
+fn check_user_buffer(user_addr: usize, copy_count: usize) -> Result<(), UserCopyError> {
+	let end_addr = user_addr.checked_add(copy_count)
+		.ok_or(UserCopyError::Rejected(SysErr::Overflow))?;
+
+	// forbid accessing kernel memory
+	if end_addr > *KERNEL_VMA {
+		Err(UserCopyError::Rejected(SysErr::InvlBuffer))
+	} else {
+		Ok(())
+	}
+}
 
-	// forbid reading from kernel memory
-	if end_read_addr > *KERNEL_VMA {
-		return Err(SysErr::InvlBuffer);
+fn user_copy_result(copy_count: usize, copied: usize) -> Result<(), UserCopyError> {
+	if copied == copy_count {
+		Ok(())
+	} else {
+		Err(UserCopyError::PartialCopy { copied })
 	}
+}
+
+pub(crate) fn copy_from_userspace<T: Pod>(dst: &mut [T], src: *const T) -> Result<(), UserCopyError> {
+	let copy_count = dst.len() * size_of::<T>();
+	check_user_buffer(src as usize, copy_count)?;
 
 	// safety: it is checked no kernel memory that isn't expecting to be read is read
 	// dst is mutable slice to it can be written to
 	// reads are valid for T because T is Pod
-	let copy_success = unsafe {
+	let copied = unsafe {
 		asm_user_copy(dst.as_mut_ptr() as *mut u8, src as *const u8, copy_count)
 	};
 
-	if !copy_success {
-		Err(SysErr::InvlBuffer)
-	} else {
-		Ok(())
-	}
+	user_copy_result(copy_count, copied)
 }
 
-fn copy_to_userspace<T: Pod>(dst: *mut T, src: &[T]) -> KResult<()> {
+fn copy_to_userspace<T: Pod>(dst: *mut T, src: &[T]) -> Result<(), UserCopyError> {
 	let copy_count = src.len() * size_of::<T>();
-	let end_write_addr = (dst as usize).checked_add(copy_count)
-		.ok_or(SysErr::Overflow)?;
-
-	// forbid writing to kernel memory
-	if end_write_addr > *KERNEL_VMA {
-		return Err(SysErr::InvlBuffer);
-	}
+	check_user_buffer(dst as usize, copy_count)?;
 
 	// safety: it is checked no kernel memory that isn't expecting to be writen to is writen to
 	// src is slice so it can be read from
 	// reads are valid for T because T is Pod
-	let copy_success = unsafe {
+	let copied = unsafe {
 		asm_user_copy(dst as *mut u8, src.as_ptr() as *const u8, copy_count)
 	};
 
-	if !copy_success {
-		Err(SysErr::InvlBuffer)
-	} else {
-		Ok(())
-	}
+	user_copy_result(copy_count, copied)
 }
 
 /// Initializes the syscall entry point and enables the syscall instruction
@@ -389,4 +465,92 @@ pub fn init() {
 
 	// load correct segment values after syscall and sysret
 	wrmsr(STAR_MSR, 0x0013000800000000);
+}
+
+#[cfg(test)]
+mod tests {
+	use bit_utils::Size;
+
+	use super::*;
+	use crate::alloc::root_alloc_page_ref;
+	use crate::cap::address_space::AddressSpace;
+	use crate::cap::memory::{Memory, MapMemoryArgs, PageSource};
+	use crate::container::Arc;
+	use crate::vmem_manager::PageMappingOptions;
+
+	const TEST_MAP_ADDR: usize = 0x40000000;
+	/// Number of bytes copied from the end of the mapped page, the rest of the buffer is on the unmapped page after it
+	const MAPPED_LEN: usize = 64;
+
+	/// Maps 1 lazily allocated page into the current address space, with nothing mapped after it
+	fn map_test_page() -> Arc<AddressSpace> {
+		let address_space = AddressSpace::current();
+		let memory = Arc::new(
+			Memory::new_with_page_source(root_alloc_page_ref(), root_alloc_ref(), 1, PageSource::LazyZeroAlloc).unwrap(),
+			root_alloc_ref(),
+		).unwrap();
+
+		Memory::map_memory(memory, address_space.clone(), MapMemoryArgs {
+			map_addr: VirtAddr::new(TEST_MAP_ADDR),
+			map_size: None,
+			offset: Size::zero(),
+			options: PageMappingOptions {
+				read: true,
+				write: true,
+				..Default::default()
+			},
+		}).unwrap();
+
+		address_space
+	}
+
+	#[test_case]
+	fn user_copy_across_unmapped_page_reports_progress() {
+		let address_space = map_test_page();
+		let user_buffer = (TEST_MAP_ADDR + PAGE_SIZE - MAPPED_LEN) as *mut u8;
+
+		let data = [0xab_u8; 2 * MAPPED_LEN];
+		assert_eq!(
+			copy_to_userspace(user_buffer, &data),
+			Err(UserCopyError::PartialCopy { copied: MAPPED_LEN }),
+		);
+
+		let mut buffer = [0u8; 2 * MAPPED_LEN];
+		assert_eq!(
+			copy_from_userspace(&mut buffer, user_buffer),
+			Err(UserCopyError::PartialCopy { copied: MAPPED_LEN }),
+		);
+		assert!(buffer[..MAPPED_LEN].iter().all(|byte| *byte == 0xab));
+		assert!(buffer[MAPPED_LEN..].iter().all(|byte| *byte == 0));
+
+		// the part on the mapped page can still be copied
+		assert_eq!(copy_from_userspace(&mut buffer[..MAPPED_LEN], user_buffer), Ok(()));
+
+		address_space.unmap(VirtAddr::new(TEST_MAP_ADDR)).unwrap();
+	}
+
+	#[test_case]
+	fn syscall_fails_on_partial_user_copy() {
+		let address_space = map_test_page();
+		let user_buffer = TEST_MAP_ADDR + PAGE_SIZE - MAPPED_LEN;
+
+		// nothing is printed, the whole buffer is copied before the console is written
+		assert_eq!(print_debug_buffer(0, user_buffer, 2 * MAPPED_LEN), Err(SysErr::InvlBuffer));
+		assert_eq!(print_debug_buffer(0, user_buffer, 0), Ok(0));
+
+		address_space.unmap(VirtAddr::new(TEST_MAP_ADDR)).unwrap();
+	}
+
+	#[test_case]
+	fn kernel_buffer_is_rejected() {
+		let mut buffer = [0u8; 8];
+		assert_eq!(
+			copy_from_userspace(&mut buffer, *KERNEL_VMA as *const u8),
+			Err(UserCopyError::Rejected(SysErr::InvlBuffer)),
+		);
+		assert_eq!(
+			copy_from_userspace(&mut buffer, usize::MAX as *const u8),
+			Err(UserCopyError::Rejected(SysErr::Overflow)),
+		);
+	}
 }
\ No newline at end of file
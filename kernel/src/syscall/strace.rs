@@ -1,307 +1,325 @@
 //! Gets a human readable view of each syscall invocation
+//!
+//! Every syscall is described once in [`SYSCALL_DESCS`], by the flags in its options and the kinds of its arguments and return values.
+//! Capability ids are printed decoded, like `Memory#42(R|W|strong)`, and flags are printed by name.
 // TODO: put this in a seperate library
 
-use core::fmt::{self, Display, Write};
+use core::cmp::min;
+use core::fmt::Write;
 
-use sys::{CapId, syscall_nums::*, ThreadNewFlags, ThreadDestroyFlags, ThreadSuspendFlags, HandleEventSyncFlags, HandleEventAsyncFlags, CapCloneFlags, CapDestroyFlags, MemoryNewFlags, MemoryUpdateMappingFlags, MemoryResizeFlags, EventPoolAwaitFlags, ChannelSyncFlags, ChannelAsyncRecvFlags, MemoryMappingFlags};
+use bit_utils::get_bits;
 use bitflags::Flags;
+use sys::{CapId, CapFlags, syscall_nums::*, ThreadNewFlags, ThreadDestroyFlags, ThreadSuspendFlags, ThreadParkFlags, HandleEventSyncFlags, HandleEventAsyncFlags, CapCloneFlags, CapDestroyFlags, MemoryNewFlags, MemoryMapFlags, MemoryUpdateMappingFlags, MemoryResizeFlags, MemoryQueryDirtyFlags, EventPoolAwaitFlags, ChannelSyncFlags, ChannelSendFlags, ChannelAsyncRecvFlags, ChannelAsyncCallFlags, MemoryMappingFlags, AddressSpaceSetFaultHandlerFlags, AddressSpaceListMappingsFlags, DebugSetStraceFlags, FutexWaitFlags, InterruptNewFlags};
 
 use crate::prelude::*;
-use crate::alloc::{HeapRef, root_alloc_ref};
-use super::SyscallVals;
-
-#[derive(Debug, Clone, Copy)]
-pub enum Arg {
-    Address(usize),
-    CapId(Option<CapId>),
-    Num(usize),
-}
+use crate::alloc::root_alloc_ref;
+use crate::cap::address_space::AddressSpace;
+use crate::cap::capability_space::CapabilitySpace;
+use super::{SyscallVals, UserCopyError, copy_from_userspace};
 
-impl Display for Arg {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Address(addr) => write!(f, "0x{:x}", addr),
-            Self::CapId(None) => write!(f, "<invalid capid>"),
-            Self::CapId(Some(cap_id)) => write!(f, "{}", cap_id),
-            Self::Num(num) => write!(f, "{}", num),
-        }
-    }
-}
+/// Number of bytes of a sent message which are printed
+const MESSAGE_DUMP_LEN: usize = 32;
 
-#[derive(Debug, Clone, Copy)]
-pub enum ArgType {
+/// How an argument or return value of a syscall is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
     Address,
     CapId,
     Num,
+    /// A message buffer, which takes up 3 registers: the memory capability id, the offset, and the size
+    MessageBuffer,
+    /// A message buffer which is being sent, the start of the message is printed as well
+    SentMessage,
 }
 
-struct StraceArgsBuilder {
-    options: String,
-    args: Vec<Arg>,
-}
-
-impl StraceArgsBuilder {
-    pub fn new(allocator: HeapRef) -> Self {
-        StraceArgsBuilder {
-            options: String::new(allocator.clone()),
-            args: Vec::new(allocator),
-        }
-    }
-
-    // TODO: detect weak autodestroy
-    pub fn options<T: Flags>(&mut self, flags: T) {
-        for (i, (flag_name, _)) in flags.iter_names().enumerate() {
-            if i != 0 {
-                write!(self.options, " | ").unwrap();
-            }
-
-            write!(self.options, "{}", flag_name).unwrap();
+impl ArgKind {
+    /// Number of registers this argument takes up
+    const fn register_count(self) -> usize {
+        match self {
+            Self::MessageBuffer | Self::SentMessage => 3,
+            _ => 1,
         }
     }
+}
 
-    // alot of these can panic on oom, but panic safety is not very important for a debug feature only
-    pub fn addr(&mut self, addr: usize) {
-        self.args.push(Arg::Address(addr)).unwrap();
-    }
+/// Calls `f` with the name of each flag of one type which is set in a syscall's options
+type FlagNamesFn = fn(u32, &mut dyn FnMut(&'static str));
 
-    pub fn cap_id(&mut self, cap_id: usize) {
-        self.args.push(Arg::CapId(CapId::try_from(cap_id))).unwrap();
+fn flag_names<T: Flags<Bits = u32>>(options: u32, f: &mut dyn FnMut(&'static str)) {
+    for (name, _) in T::from_bits_truncate(options).iter_names() {
+        f(name);
     }
+}
 
-    pub fn num(&mut self, num: usize) {
-        self.args.push(Arg::Num(num)).unwrap();
+/// Used by syscalls which make a new capability, the low 4 bits of the options are its permissions
+fn cap_flag_names(options: u32, f: &mut dyn FnMut(&'static str)) {
+    for (name, _) in CapFlags::from_bits_truncate(get_bits(options as usize, 0..4)).iter_names() {
+        f(name);
     }
+}
 
-    pub fn arg(&mut self, arg_type: ArgType, n: usize) {
-        match arg_type {
-            ArgType::Address => self.addr(n),
-            ArgType::CapId => self.cap_id(n),
-            ArgType::Num => self.num(n),
-        }
-    }
+/// Used by `memory_update_mapping`, where only the low 3 bits are memory mapping flags
+fn mapping_permission_names(options: u32, f: &mut dyn FnMut(&'static str)) {
+    flag_names::<MemoryMappingFlags>(options & 0b111, f);
 }
 
-impl Display for StraceArgsBuilder {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.options)?;
+/// Describes how a syscall's options, arguments, and return values are printed
+pub struct SyscallDesc {
+    pub syscall_num: u32,
+    /// Each type of flags which can be in the options
+    options: &'static [FlagNamesFn],
+    args: &'static [ArgKind],
+    returns: &'static [ArgKind],
+}
 
-        for arg in self.args.iter() {
-            write!(f, ", {}", arg)?;
+macro_rules! desc {
+    ($syscall_num:ident $([$($flags:expr),*])? ($($arg:ident),*) -> ($($ret:ident),*)) => {
+        SyscallDesc {
+            syscall_num: $syscall_num,
+            options: &[$($($flags as FlagNamesFn),*)?],
+            args: &[$(ArgKind::$arg),*],
+            returns: &[$(ArgKind::$ret),*],
         }
+    };
+}
 
-        Ok(())
-    }
+/// Every syscall which can be traced, adding a syscall only requires adding it here
+static SYSCALL_DESCS: &[SyscallDesc] = &[
+    desc!(PRINT_DEBUG () -> ()),
+    desc!(PRINT_DEBUG_BUFFER (Address, Num) -> (Num)),
+    desc!(DEBUG_SET_STRACE [flag_names::<DebugSetStraceFlags>] (CapId, Num, Num, Address, Num) -> ()),
+    desc!(DEBUG_READ_KLOG (CapId, MessageBuffer, Num) -> (Num, Num)),
+    desc!(DEBUG_EXIT_EMULATOR (CapId, Num) -> ()),
+    desc!(DEBUG_CONSOLE_HANDOFF (CapId) -> ()),
+    desc!(THREAD_GROUP_NEW (CapId, CapId) -> (CapId)),
+    desc!(THREAD_GROUP_EXIT (CapId, Num) -> ()),
+    desc!(THREAD_GROUP_STATS (CapId) -> (Num, Num, Num, Num, Num)),
+    desc!(THREAD_GROUP_FAULT_INFO (CapId, Address) -> (Num)),
+    desc!(THREAD_GROUP_SET_FAULT_CAPTURE (CapId, CapId) -> ()),
+    desc!(THREAD_NEW [flag_names::<ThreadNewFlags>] (CapId, CapId, CapId, CapId, Address, Address) -> (CapId, CapId)),
+    desc!(THREAD_YIELD () -> ()),
+    desc!(THREAD_DESTROY [flag_names::<ThreadDestroyFlags>] (CapId) -> ()),
+    desc!(THREAD_SUSPEND [flag_names::<ThreadSuspendFlags>] (Num) -> ()),
+    desc!(THREAD_RESUME (CapId) -> ()),
+    desc!(THREAD_SET_PROPERTY (Num, Num, CapId) -> ()),
+    desc!(THREAD_HANDLE_THREAD_EXIT_SYNC [flag_names::<HandleEventSyncFlags>] (CapId, Num) -> (Num)),
+    desc!(THREAD_HANDLE_THREAD_EXIT_ASYNC [flag_names::<HandleEventAsyncFlags>] (CapId, CapId, Num) -> ()),
+    desc!(THREAD_RUNTIME (CapId) -> (Num)),
+    desc!(THREAD_PARK [flag_names::<ThreadParkFlags>] (Num) -> ()),
+    desc!(THREAD_UNPARK (CapId) -> ()),
+    desc!(CAP_CLONE [flag_names::<CapCloneFlags>] (CapId, CapId, CapId) -> (CapId)),
+    desc!(CAP_DESTROY [flag_names::<CapDestroyFlags>] (CapId, CapId) -> ()),
+    desc!(ADDRESS_SPACE_NEW (CapId) -> (CapId)),
+    desc!(ADDRESS_SPACE_UNMAP (CapId, Address) -> ()),
+    desc!(ADDRESS_SPACE_SET_FAULT_HANDLER [flag_names::<AddressSpaceSetFaultHandlerFlags>] (CapId, CapId, Num) -> ()),
+    desc!(ADDRESS_SPACE_MAP_GUARD (CapId, Address, Num) -> ()),
+    desc!(ADDRESS_SPACE_UNMAP_RANGE (CapId, Address, Num) -> ()),
+    desc!(ADDRESS_SPACE_LIST_MAPPINGS [flag_names::<AddressSpaceListMappingsFlags>] (CapId, CapId, Address, Num, Num) -> (Num, Num, Num)),
+    desc!(MEMORY_MAP [flag_names::<MemoryMappingFlags>, flag_names::<MemoryMapFlags>] (CapId, CapId, Address, Num, Num) -> (Num)),
+    desc!(MEMORY_UPDATE_MAPPING [mapping_permission_names, flag_names::<MemoryUpdateMappingFlags>] (CapId, Address, Num) -> (Num)),
+    desc!(MEMORY_NEW [flag_names::<MemoryNewFlags>] (CapId, Num) -> (CapId, Num)),
+    desc!(MEMORY_GET_SIZE (CapId) -> (Num)),
+    desc!(MEMORY_RESIZE [flag_names::<MemoryResizeFlags>] (CapId, Num) -> (Num)),
+    desc!(MEMORY_COPY (CapId, Num, CapId, Num, Num) -> ()),
+    desc!(MEMORY_NEW_COW (CapId, CapId) -> (CapId, Num)),
+    desc!(MEMORY_PHYS_ADDR (CapId, Num) -> (Address)),
+    desc!(MEMORY_SEAL (CapId) -> ()),
+    desc!(MEMORY_IS_SEALED (CapId) -> (Num)),
+    desc!(MEMORY_QUERY_DIRTY [flag_names::<MemoryQueryDirtyFlags>] (CapId, Num, Num, Address) -> (Num)),
+    desc!(EVENT_POOL_NEW (CapId, Num, Num) -> (CapId)),
+    desc!(EVENT_POOL_MAP (CapId, CapId, Address) -> (Num)),
+    desc!(EVENT_POOL_AWAIT [flag_names::<EventPoolAwaitFlags>] (CapId, Num) -> (Address, Num, Num)),
+    desc!(CHANNEL_NEW [cap_flag_names] (CapId, Num) -> (CapId)),
+    desc!(CHANNEL_TRY_SEND [flag_names::<ChannelSendFlags>] (CapId, SentMessage) -> (Num)),
+    desc!(CHANNEL_SYNC_SEND [flag_names::<ChannelSyncFlags>, flag_names::<ChannelSendFlags>] (CapId, SentMessage, Num) -> (Num)),
+    desc!(CHANNEL_ASYNC_SEND [flag_names::<ChannelSendFlags>] (CapId, SentMessage, CapId, Num) -> ()),
+    desc!(CHANNEL_ASYNC_WAIT_SPACE (CapId, CapId, Num) -> ()),
+    desc!(CHANNEL_TRY_RECV (CapId, MessageBuffer) -> (Num, CapId, Num)),
+    desc!(CHANNEL_SYNC_RECV [flag_names::<ChannelSyncFlags>] (CapId, MessageBuffer, Num) -> (Num, CapId, Num)),
+    desc!(CHANNEL_ASYNC_RECV [flag_names::<ChannelAsyncRecvFlags>] (CapId, CapId, Num) -> ()),
+    desc!(CHANNEL_SYNC_CALL [flag_names::<ChannelSyncFlags>, flag_names::<ChannelSendFlags>] (CapId, SentMessage, MessageBuffer, Num) -> (Num)),
+    desc!(CHANNEL_ASYNC_CALL [flag_names::<ChannelAsyncCallFlags>, flag_names::<ChannelSendFlags>] (CapId, SentMessage, CapId, Num, CapId, Num) -> ()),
+    desc!(REPLY_REPLY (CapId, SentMessage) -> (Num)),
+    desc!(KEY_NEW [cap_flag_names] (CapId) -> (CapId)),
+    desc!(KEY_ID (CapId) -> (Num)),
+    desc!(KEY_REGISTER_IDENTITY (CapId) -> ()),
+    desc!(DROP_CHECK_NEW (CapId, Num) -> (CapId, CapId)),
+    desc!(DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_SYNC [flag_names::<HandleEventSyncFlags>] (CapId, Num) -> (Num)),
+    desc!(DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_ASYNC [flag_names::<HandleEventAsyncFlags>] (CapId, CapId, Num) -> ()),
+    desc!(MMIO_ALLOCATOR_ALLOC (CapId, CapId, Address, Num) -> (CapId)),
+    desc!(PHYS_MEM_MAP [flag_names::<MemoryMappingFlags>] (CapId, CapId, Address) -> (Num)),
+    desc!(PHYS_MEM_GET_SIZE (CapId) -> (Num)),
+    desc!(MMIO_ALLOCATOR_REQUEST_REGION (CapId, CapId, Address, Num) -> (CapId)),
+    desc!(INTERRUPT_NEW [flag_names::<InterruptNewFlags>] (CapId, CapId) -> (CapId, Num, Num)),
+    desc!(INTERRUPT_ID (CapId) -> (Num, Num, Num)),
+    desc!(INTERRUPT_HANDLE_INTERRUPT_TRIGGER_SYNC [flag_names::<HandleEventSyncFlags>] (CapId, Num) -> ()),
+    desc!(INTERRUPT_HANDLE_INTERRUPT_TRIGGER_ASYNC [flag_names::<HandleEventAsyncFlags>] (CapId, CapId, Num) -> ()),
+    desc!(INTERRUPT_MASK (CapId, Num) -> ()),
+    desc!(INTERRUPT_ACK (CapId) -> ()),
+    desc!(IO_PORT_NEW (CapId, CapId, Num, Num) -> (CapId)),
+    desc!(IO_PORT_IN (CapId, Num, Num) -> (Num)),
+    desc!(IO_PORT_OUT (CapId, Num, Num, Num) -> ()),
+    desc!(CAPABILITY_SPACE_LIST (CapId, Address, Num, Num) -> (Num, Num)),
+    desc!(ALLOCATOR_NEW_CHILD [cap_flag_names] (CapId, Num) -> (CapId)),
+    desc!(ALLOCATOR_SET_LIMIT (CapId, Num) -> ()),
+    desc!(ALLOCATOR_USAGE (CapId) -> (Num, Num)),
+    desc!(SYSTEM_ENTROPY () -> (Num, Num, Num)),
+    desc!(SYSTEM_MEM_INFO (CapId, Address, Address, Num) -> ()),
+    desc!(FUTEX_WAIT [flag_names::<FutexWaitFlags>] (Address, Num, Num) -> (Num)),
+    desc!(FUTEX_WAKE (Address, Num) -> (Num)),
+    desc!(TIME_GET (Num) -> (Num, Num)),
+];
+
+fn syscall_desc(syscall_num: u32) -> Option<&'static SyscallDesc> {
+    SYSCALL_DESCS.iter().find(|desc| desc.syscall_num == syscall_num)
 }
 
-macro_rules! argsf {
-    ($vals:expr, $flag_ty:ty, $($args:ident,)*) => {{
-        let mut args = StraceArgsBuilder::new(root_alloc_ref());
-        let flags = <$flag_ty>::from_bits_truncate($vals.options);
-        args.options(flags);
+// writing to a string can only fail on oom, and panic safety is not very important for a debug feature only
 
-        let arg_types = [$(ArgType::$args,)*];
-        for (i, arg_type) in arg_types.iter().enumerate() {
-            args.arg(*arg_type, $vals.get(i).expect("too many args"));
-        }
+/// Writes a capability id like `Memory#42(R|W|strong)`
+fn write_cap_id(out: &mut String, cap_id: usize) {
+    let Some(cap_id) = CapId::try_from(cap_id) else {
+        write!(out, "<invalid capid 0x{:x}>", cap_id).unwrap();
+        return;
+    };
 
-        args
-    }};
-}
+    if cap_id.is_null() {
+        write!(out, "null").unwrap();
+        return;
+    }
 
-macro_rules! args {
-    ($vals:expr, $($args:ident,)*) => {{
-        let mut args = StraceArgsBuilder::new(root_alloc_ref());
+    write!(out, "{}#{}(", cap_id.cap_type(), cap_id.base_id()).unwrap();
 
-        let arg_types = [$(ArgType::$args,)*];
-        for (i, arg_type) in arg_types.iter().enumerate() {
-            args.arg(*arg_type, $vals.get(i).expect("too many args"));
+    let flags = cap_id.flags();
+    for (flag, letter) in [(CapFlags::READ, "R"), (CapFlags::PROD, "P"), (CapFlags::WRITE, "W"), (CapFlags::UPGRADE, "U")] {
+        if flags.contains(flag) {
+            write!(out, "{}|", letter).unwrap();
         }
-
-        args
-    }};
-}
-
-macro_rules! event_sync {
-    ($vals:tt) => {
-        argsf!($vals, HandleEventSyncFlags, CapId, Num,)
     }
-}
 
-macro_rules! event_async {
-    ($vals:tt) => {
-        argsf!($vals, HandleEventAsyncFlags, CapId, CapId, Num,)
-    }
+    let weakness = if cap_id.is_weak() { "weak" } else { "strong" };
+    write!(out, "{})", weakness).unwrap();
 }
 
-pub fn get_strace_args_string(syscall_num: u32, vals: &SyscallVals) -> String {
-	let syscall_name = String::from_str(root_alloc_ref(), syscall_name(syscall_num)).unwrap();
-
-    let args = match syscall_num {
-        PRINT_DEBUG => return syscall_name,
-        THREAD_GROUP_NEW => args!(vals, CapId, CapId,),
-        THREAD_GROUP_EXIT => args!(vals, CapId,),
-        THREAD_NEW => argsf!(vals, ThreadNewFlags, CapId, CapId, CapId, CapId, Address, Address,),
-        THREAD_YIELD => args!(vals,),
-        THREAD_DESTROY => argsf!(vals, ThreadDestroyFlags, CapId,),
-        THREAD_SUSPEND => argsf!(vals, ThreadSuspendFlags, Num,),
-        THREAD_RESUME => args!(vals, CapId,),
-        THREAD_SET_PROPERTY => args!(vals, Num, Address,),
-        THREAD_HANDLE_THREAD_EXIT_SYNC => event_sync!(vals),
-        THREAD_HANDLE_THREAD_EXIT_ASYNC => event_async!(vals),
-        // TODO: fix flags
-        CAP_CLONE => argsf!(vals, CapCloneFlags, CapId, CapId, CapId,),
-        CAP_DESTROY => argsf!(vals, CapDestroyFlags, CapId, CapId,),
-        ADDRESS_SPACE_NEW => args!(vals, CapId,),
-        ADDRESS_SPACE_UNMAP => args!(vals, CapId, Address,),
-        // TODO: include MemoryMapFlags options as well
-        MEMORY_MAP => argsf!(vals, MemoryMappingFlags, CapId, CapId, Address, Num, Num,),
-        MEMORY_UPDATE_MAPPING => argsf!(vals, MemoryUpdateMappingFlags, CapId, Address, Num,),
-        MEMORY_NEW => argsf!(vals, MemoryNewFlags, CapId, Num,),
-        MEMORY_GET_SIZE => args!(vals, CapId,),
-        MEMORY_RESIZE => argsf!(vals, MemoryResizeFlags, CapId, Num,),
-        EVENT_POOL_NEW => args!(vals, CapId, Num,),
-        EVENT_POOL_MAP => args!(vals, CapId, CapId, Address,),
-        EVENT_POOL_AWAIT => argsf!(vals, EventPoolAwaitFlags, CapId, Num,),
-        // TODO: cap flags
-        CHANNEL_NEW => args!(vals, CapId,),
-        CHANNEL_TRY_SEND => args!(vals, CapId, CapId, Num, Num,),
-        CHANNEL_SYNC_SEND => argsf!(vals, ChannelSyncFlags, CapId, CapId, Num, Num, Num,),
-        CHANNEL_ASYNC_SEND => args!(vals, CapId, CapId, Num, Num, CapId, Num,),
-        CHANNEL_TRY_RECV => args!(vals, CapId, CapId, Num, Num,),
-        CHANNEL_SYNC_RECV => argsf!(vals, ChannelSyncFlags, CapId, CapId, Num, Num, Num,),
-        CHANNEL_ASYNC_RECV => argsf!(vals, ChannelAsyncRecvFlags, CapId, CapId, Num,),
-        CHANNEL_SYNC_CALL => argsf!(vals, ChannelSyncFlags, CapId, CapId, Num, Num, CapId, Num, Num, Num,),
-        CHANNEL_ASYNC_CALL => args!(vals, CapId, CapId, Num, Num, CapId, Num,),
-        REPLY_REPLY => args!(vals, CapId, CapId, Num, Num,),
-        // TODO: cap flags
-        KEY_NEW => args!(vals, CapId,),
-        KEY_ID => args!(vals, CapId,),
-        DROP_CHECK_NEW => args!(vals, CapId, Num,),
-        DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_SYNC => event_sync!(vals),
-        DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_ASYNC => event_async!(vals),
-        MMIO_ALLOCATOR_ALLOC => args!(vals, CapId, CapId, Address, Num,),
-        PHYS_MEM_MAP => argsf!(vals, MemoryMappingFlags, CapId, CapId, Address,),
-        PHYS_MEM_GET_SIZE => args!(vals, CapId,),
-        _ => return syscall_name,
+/// Writes the first bytes of a message which is being sent
+///
+/// Message buffers are memory capabilities, so the bytes can only be read if the memory is mapped in the current address space.
+/// They are read with the same copy used by syscalls, so a fault part way through only cuts off the dump.
+fn write_message_start(out: &mut String, memory_id: usize, offset: usize, size: usize) {
+    let Ok(memory) = CapabilitySpace::current().get_memory_with_perms(memory_id, CapFlags::READ, false) else {
+        return;
     };
 
-	format!(root_alloc_ref(), "sys {}({})", syscall_name, args)
-}
+    let Some(address) = memory.into_inner().mapped_address(&AddressSpace::current(), offset) else {
+        write!(out, ", data=<not mapped>").unwrap();
+        return;
+    };
 
-struct StraceRetBuilder {
-    args: Vec<Arg>,
-}
+    let mut data = [0u8; MESSAGE_DUMP_LEN];
+    let data = &mut data[..min(size, MESSAGE_DUMP_LEN)];
+    let (copied, faulted) = match copy_from_userspace(data, address.as_usize() as *const u8) {
+        Ok(()) => (data.len(), false),
+        Err(UserCopyError::PartialCopy { copied }) => (copied, true),
+        Err(UserCopyError::Rejected(_)) => (0, true),
+    };
 
-impl StraceRetBuilder {
-    pub fn new(allocator: HeapRef) -> Self {
-        StraceRetBuilder {
-            args: Vec::new(allocator),
+    write!(out, ", data=[").unwrap();
+    for (i, byte) in data[..copied].iter().enumerate() {
+        if i != 0 {
+            write!(out, " ").unwrap();
         }
+        write!(out, "{:02x}", byte).unwrap();
     }
 
-    // alot of these can panic on oom, but panic safety is not very important for a debug feature only
-    pub fn addr(&mut self, addr: usize) {
-        self.args.push(Arg::Address(addr)).unwrap();
+    if faulted {
+        write!(out, " <fault>").unwrap();
+    } else if size > MESSAGE_DUMP_LEN {
+        write!(out, " ...").unwrap();
     }
+    write!(out, "]").unwrap();
+}
 
-    pub fn cap_id(&mut self, cap_id: usize) {
-        self.args.push(Arg::CapId(CapId::try_from(cap_id))).unwrap();
-    }
+/// Writes an argument which starts at register `index`
+fn write_arg(out: &mut String, kind: ArgKind, vals: &SyscallVals, index: usize) {
+    let val = |i: usize| vals.get(index + i).expect("too many args");
+
+    match kind {
+        ArgKind::Address => write!(out, "0x{:x}", val(0)).unwrap(),
+        ArgKind::CapId => write_cap_id(out, val(0)),
+        ArgKind::Num => write!(out, "{}", val(0)).unwrap(),
+        ArgKind::MessageBuffer | ArgKind::SentMessage => {
+            write!(out, "{{mem=").unwrap();
+            write_cap_id(out, val(0));
+            write!(out, ", off=0x{:x}, len={}", val(1), val(2)).unwrap();
+
+            if kind == ArgKind::SentMessage {
+                write_message_start(out, val(0), val(1), val(2));
+            }
 
-    pub fn num(&mut self, num: usize) {
-        self.args.push(Arg::Num(num)).unwrap();
+            write!(out, "}}").unwrap();
+        },
     }
+}
 
-    pub fn arg(&mut self, arg_type: ArgType, n: usize) {
-        match arg_type {
-            ArgType::Address => self.addr(n),
-            ArgType::CapId => self.cap_id(n),
-            ArgType::Num => self.num(n),
+/// Writes each argument in `kinds` seperated by commas, `first_index` is the register of the first one
+fn write_args(out: &mut String, kinds: &[ArgKind], vals: &SyscallVals, first_index: usize, mut first: bool) {
+    let mut index = first_index;
+
+    for kind in kinds {
+        if !first {
+            write!(out, ", ").unwrap();
         }
+        first = false;
+
+        write_arg(out, *kind, vals, index);
+        index += kind.register_count();
     }
 }
 
-impl Display for StraceRetBuilder {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (i, arg) in self.args.iter().enumerate() {
-            if i != 0 {
-                write!(f, ", ")?;
-            }
-
-            write!(f, "{}", arg)?;
+/// Writes the flags set in `options` seperated by ` | `, and returns true if any were written
+fn write_options(out: &mut String, desc: &SyscallDesc, options: u32) -> bool {
+    let mut written = false;
+    let mut write_name = |name: &'static str| {
+        if written {
+            write!(out, " | ").unwrap();
         }
+        write!(out, "{}", name).unwrap();
+        written = true;
+    };
+
+    for flag_names in desc.options {
+        flag_names(options, &mut write_name);
+    }
 
-        Ok(())
+    if super::options_weak_autodestroy(options) {
+        write_name("WEAK_AUTO_DESTROY");
     }
+
+    written
 }
 
-macro_rules! ret {
-    () => {
-        StraceRetBuilder::new(root_alloc_ref())
-    };
-    ($vals:expr, $($ret:ident,)*) => {{
-        let mut args = StraceRetBuilder::new(root_alloc_ref());
+pub fn get_strace_args_string(syscall_num: u32, vals: &SyscallVals) -> String {
+    let mut out = format!(root_alloc_ref(), "sys {}(", syscall_name(syscall_num));
 
-        let arg_types = [$(ArgType::$ret,)*];
-        for (i, arg_type) in arg_types.iter().enumerate() {
-            args.arg(*arg_type, $vals.get(i + 1).expect("too many args"));
-        }
+    if let Some(desc) = syscall_desc(syscall_num) {
+        let options_written = write_options(&mut out, desc, vals.options);
+        write_args(&mut out, desc.args, vals, 0, !options_written);
+    }
 
-        args
-    }};
+    write!(out, ")").unwrap();
+    out
 }
 
 pub fn get_strace_return_string(syscall_num: u32, vals: &SyscallVals) -> String {
     if vals.a1 == SysErr::Ok.num() {
         let mut out = String::from_str(root_alloc_ref(), "Ok(").unwrap();
 
-        let ret_values = match syscall_num {
-            PRINT_DEBUG => ret!(),
-            THREAD_GROUP_NEW => ret!(vals, CapId,),
-            THREAD_GROUP_EXIT => ret!(),
-            THREAD_NEW => ret!(vals, CapId, CapId,),
-            THREAD_YIELD => ret!(),
-            THREAD_DESTROY => ret!(),
-            THREAD_SUSPEND => ret!(),
-            THREAD_RESUME => ret!(),
-            THREAD_SET_PROPERTY => ret!(),
-            THREAD_HANDLE_THREAD_EXIT_SYNC => ret!(),
-            THREAD_HANDLE_THREAD_EXIT_ASYNC => ret!(),
-            CAP_CLONE => ret!(vals, CapId,),
-            CAP_DESTROY => ret!(),
-            ADDRESS_SPACE_NEW => ret!(vals, CapId,),
-            ADDRESS_SPACE_UNMAP => ret!(),
-            MEMORY_MAP => ret!(vals, Num,),
-            MEMORY_UPDATE_MAPPING => ret!(vals, Num,),
-            MEMORY_NEW => ret!(vals, CapId, Num,),
-            MEMORY_GET_SIZE => ret!(vals, Num,),
-            MEMORY_RESIZE => ret!(vals, Num,),
-            EVENT_POOL_NEW => ret!(vals, CapId,),
-            EVENT_POOL_MAP => ret!(vals, Num,),
-            EVENT_POOL_AWAIT => ret!(vals, Address, Num,),
-            CHANNEL_NEW => ret!(vals, CapId,),
-            CHANNEL_TRY_SEND => ret!(vals, Num,),
-            CHANNEL_SYNC_SEND => ret!(vals, Num,),
-            CHANNEL_ASYNC_SEND => ret!(),
-            CHANNEL_TRY_RECV => ret!(vals, Num, CapId,),
-            CHANNEL_SYNC_RECV => ret!(vals, Num, CapId,),
-            CHANNEL_ASYNC_RECV => ret!(),
-            CHANNEL_SYNC_CALL => ret!(vals, Num,),
-            CHANNEL_ASYNC_CALL => ret!(),
-            REPLY_REPLY => ret!(vals, Num,),
-            KEY_NEW => ret!(vals, CapId,),
-            KEY_ID => ret!(vals, Num,),
-            DROP_CHECK_NEW => ret!(vals, CapId, CapId,),
-            DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_SYNC => ret!(vals, Num,),
-            DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_ASYNC => ret!(),
-            MMIO_ALLOCATOR_ALLOC => ret!(vals, CapId,),
-            PHYS_MEM_MAP => ret!(vals, Num,),
-            PHYS_MEM_GET_SIZE => ret!(vals, Num,),
-            _ => unreachable!(),
-        };
-
-        write!(out, "{})", ret_values).unwrap();
+        if let Some(desc) = syscall_desc(syscall_num) {
+            // the first register is the error code
+            write_args(&mut out, desc.returns, vals, 1, true);
+        }
 
+        write!(out, ")").unwrap();
         out
     } else {
         if let Some(err) = SysErr::new(vals.a1) {
@@ -310,4 +328,65 @@ pub fn get_strace_return_string(syscall_num: u32, vals: &SyscallVals) -> String
             String::from_str(root_alloc_ref(), "Err(<invalid syserr>)").unwrap()
         }
     }
-}
\ No newline at end of file
+}
+
+/// Prints a traced syscall, prefixed with the current process name and thread id
+pub fn print_strace(args_string: &str, ret_string: &str) {
+    let thread = cpu_local_data().current_thread();
+
+    eprintln!("[{} tid {}] {} -> {}", thread.strace().process_name(), thread.tid(), args_string, ret_string);
+}
+
+#[cfg(test)]
+mod tests {
+    use sys::CapType;
+
+    use super::*;
+
+    #[test_case]
+    fn every_syscall_has_a_desc() {
+        for syscall_num in 0..128 {
+            if syscall_name(syscall_num) != "invalid syscall" {
+                kassert!(syscall_desc(syscall_num).is_some(), "syscall {} has no strace desc", syscall_num);
+            }
+        }
+    }
+
+    #[test_case]
+    fn syscall_descs_fit_in_registers() {
+        let register_count = |kinds: &[ArgKind]| kinds.iter().map(|kind| kind.register_count()).sum::<usize>();
+
+        for desc in SYSCALL_DESCS {
+            assert!(register_count(desc.args) <= 8);
+            // the first return register is the error code
+            assert!(register_count(desc.returns) <= 7);
+        }
+    }
+
+    #[test_case]
+    fn cap_ids_are_decoded() {
+        let mut out = String::new(root_alloc_ref());
+        let cap_id = CapId::new(CapType::Memory, CapFlags::READ | CapFlags::WRITE, false, 42);
+        write_cap_id(&mut out, usize::from(cap_id));
+        assert_eq!(&*out, "Memory#42(R|W|strong)");
+
+        let mut out = String::new(root_alloc_ref());
+        let cap_id = CapId::new(CapType::Channel, CapFlags::PROD, true, 7);
+        write_cap_id(&mut out, usize::from(cap_id));
+        assert_eq!(&*out, "Channel#7(P|weak)");
+    }
+
+    #[test_case]
+    fn options_are_decoded() {
+        let desc = syscall_desc(CHANNEL_SYNC_SEND).unwrap();
+
+        let mut out = String::new(root_alloc_ref());
+        let options = ChannelSyncFlags::TIMEOUT.bits() | ChannelSendFlags::SEND_IDENTITY.bits() | (1 << 31);
+        assert!(write_options(&mut out, desc, options));
+        assert_eq!(&*out, "TIMEOUT | SEND_IDENTITY | WEAK_AUTO_DESTROY");
+
+        let mut out = String::new(root_alloc_ref());
+        assert!(!write_options(&mut out, desc, 0));
+        assert_eq!(&*out, "");
+    }
+}
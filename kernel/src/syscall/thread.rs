@@ -1,4 +1,4 @@
-use sys::{CapFlags, ThreadNewFlags, ThreadSuspendFlags, ThreadDestroyFlags, ThreadProperty, ThreadExit};
+use sys::{CapFlags, ThreadNewFlags, ThreadSuspendFlags, ThreadParkFlags, ThreadDestroyFlags, ThreadProperty, ThreadPriority, ThreadExit};
 
 use crate::alloc::HeapRef;
 use crate::arch::x64::IntDisable;
@@ -6,7 +6,7 @@ use crate::cap::{WeakCapability, Capability};
 use crate::container::Arc;
 use crate::cap::capability_space::CapabilitySpace;
 use crate::prelude::*;
-use crate::sched::{ThreadGroup, ThreadStartMode, switch_current_thread_to, ThreadState, PostSwitchAction, WakeReason, Thread};
+use crate::sched::{ThreadGroup, ThreadStartMode, switch_current_thread_to, ThreadState, PostSwitchAction, WakeReason, Thread, online_cpu_mask};
 use super::options_weak_autodestroy;
 
 pub fn thread_new(
@@ -207,19 +207,118 @@ pub fn thread_resume(options: u32, thread_id: usize) -> KResult<()> {
     Thread::resume_suspended_thread(&thread)
 }
 
-pub fn thread_set_property(_options: u32, property: usize, data: usize) -> KResult<()> {
-    let property = ThreadProperty::from_repr(property)
-        .ok_or(SysErr::InvlArgs)?;
+/// Parks the currently running thread until another thread calls `thread_unpark` on it
+///
+/// If the thread was unparked since it last parked, the wake token is consumed and this returns immediately
+///
+/// # Options
+/// bit 0 (park_timeout): the thread is woken with [`SysErr::OkTimeout`] once the monotonic clock reaches `timeout_nsec` nanoseconds
+pub fn thread_park(options: u32, timeout_nsec: usize) -> KResult<()> {
+    let flags = ThreadParkFlags::from_bits_truncate(options);
+
+    let post_switch_action = if flags.contains(ThreadParkFlags::PARK_TIMEOUT) {
+        PostSwitchAction::SetTimeout(timeout_nsec as u64)
+    } else {
+        PostSwitchAction::None
+    };
+
+    let int_disable = IntDisable::new();
+
+    if !Thread::prepare_park(&cpu_local_data().current_thread()) {
+        return Ok(());
+    }
+
+    switch_current_thread_to(
+        ThreadState::Suspended,
+        int_disable,
+        post_switch_action,
+        false,
+    ).expect("could not find idle thread to switch to");
 
     let _int_disable = IntDisable::new();
 
     let current_thread = cpu_local_data().current_thread();
+    current_thread.finish_park();
+
+    match current_thread.wake_reason() {
+        WakeReason::Timeout => Err(SysErr::OkTimeout),
+        _ => Ok(()),
+    }
+}
+
+/// Wakes the given thread if it is parked, otherwise its next `thread_park` returns immediately
+pub fn thread_unpark(options: u32, thread_id: usize) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let thread = CapabilitySpace::current()
+        .get_thread_with_perms(thread_id, CapFlags::PROD, weak_auto_destroy)?
+        .into_inner();
+
+    thread.unpark();
+
+    Ok(())
+}
+
+/// Returns the time the thread has spent running in nanoseconds
+pub fn thread_runtime(options: u32, thread_id: usize) -> KResult<usize> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let thread = CapabilitySpace::current()
+        .get_thread_with_perms(thread_id, CapFlags::READ, weak_auto_destroy)?
+        .into_inner();
+
+    Ok(thread.runtime().as_nanos() as usize)
+}
+
+pub fn thread_set_property(options: u32, property: usize, data: usize, thread_id: usize) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let property = ThreadProperty::from_repr(property)
+        .ok_or(SysErr::InvlArgs)?;
+
+    let _int_disable = IntDisable::new();
 
     match property {
         ThreadProperty::ThreadLocalPointer => {
+            let current_thread = cpu_local_data().current_thread();
+
             current_thread.set_thread_local_pointer(data);
             current_thread.load_thread_local_pointer();
         },
+        ThreadProperty::Priority => {
+            let priority = ThreadPriority::from_repr(data)
+                .ok_or(SysErr::InvlArgs)?;
+
+            // raising priority lets a thread take cpu time from everything else, so it needs extra permissions
+            let required_perms = if priority > ThreadPriority::default() {
+                CapFlags::WRITE | CapFlags::PROD
+            } else {
+                CapFlags::WRITE
+            };
+
+            let thread = CapabilitySpace::current()
+                .get_thread_with_perms(thread_id, required_perms, weak_auto_destroy)?
+                .into_inner();
+
+            thread.set_priority(priority);
+        },
+        ThreadProperty::CpuMask => {
+            let cpu_mask = data as u64;
+
+            if cpu_mask == 0 || cpu_mask & !online_cpu_mask() != 0 {
+                return Err(SysErr::InvlArgs);
+            }
+
+            let thread = CapabilitySpace::current()
+                .get_thread_with_perms(thread_id, CapFlags::WRITE, weak_auto_destroy)?
+                .into_inner();
+
+            thread.set_cpu_mask(cpu_mask);
+        },
     }
 
     Ok(())
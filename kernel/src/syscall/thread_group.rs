@@ -1,4 +1,6 @@
-use sys::CapFlags;
+use core::slice;
+
+use sys::{CapFlags, ExitReason, FaultRecord};
 
 use crate::arch::x64::IntDisable;
 use crate::cap::{Capability, StrongCapability};
@@ -6,7 +8,7 @@ use crate::cap::capability_space::CapabilitySpace;
 use crate::alloc::{HeapRef, PaRef};
 use crate::prelude::*;
 use crate::sched::ThreadGroup;
-use super::options_weak_autodestroy;
+use super::{options_weak_autodestroy, copy_to_userspace};
 
 pub fn thread_group_new(options: u32, parent_group_id: usize, allocator_id: usize) -> KResult<usize> {
     let weak_auto_destroy = options_weak_autodestroy(options);
@@ -38,7 +40,27 @@ pub fn thread_group_new(options: u32, parent_group_id: usize, allocator_id: usiz
     Ok(threadad_group_cap_id.into())
 }
 
-pub fn thread_group_exit(options: u32, thread_group_id: usize) -> KResult<()> {
+/// Returns the runtime in nanoseconds, context switch count, and number of running, ready, and suspended threads
+pub fn thread_group_stats(options: u32, thread_group_id: usize) -> KResult<(usize, usize, usize, usize, usize)> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let stats = CapabilitySpace::current()
+        .get_thread_group_with_perms(thread_group_id, CapFlags::READ, weak_auto_destroy)?
+        .into_inner()
+        .stats();
+
+    Ok((
+        stats.runtime.as_nanos() as usize,
+        stats.context_switches as usize,
+        stats.running_threads,
+        stats.ready_threads,
+        stats.suspended_threads,
+    ))
+}
+
+pub fn thread_group_exit(options: u32, thread_group_id: usize, exit_code: usize) -> KResult<()> {
     let weak_auto_destroy = options_weak_autodestroy(options);
 
     let _int_disable = IntDisable::new();
@@ -47,7 +69,49 @@ pub fn thread_group_exit(options: u32, thread_group_id: usize) -> KResult<()> {
         .get_thread_group_with_perms(thread_group_id, CapFlags::WRITE, weak_auto_destroy)?
         .into_inner();
 
-    ThreadGroup::exit(thread_group);
+    ThreadGroup::exit(thread_group, ExitReason::Code(exit_code as u32 as i32));
 
     Ok(())
+}
+
+/// Copies the fault record of the thread group to `record_ptr`
+/// 
+/// Returns 1 if a thread in the group has faulted and the record was written, or 0 if none have
+pub fn thread_group_fault_info(options: u32, thread_group_id: usize, record_ptr: usize) -> KResult<usize> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let record = {
+        let _int_disable = IntDisable::new();
+
+        CapabilitySpace::current()
+            .get_thread_group_with_perms(thread_group_id, CapFlags::READ, weak_auto_destroy)?
+            .into_inner()
+            .fault_record()
+    };
+
+    match record {
+        Some(record) => {
+            copy_to_userspace(record_ptr as *mut FaultRecord, slice::from_ref(&record))?;
+            Ok(1)
+        },
+        None => Ok(0),
+    }
+}
+
+pub fn thread_group_set_fault_capture(options: u32, thread_group_id: usize, memory_id: usize) -> KResult<()> {
+    let weak_auto_destroy = options_weak_autodestroy(options);
+
+    let _int_disable = IntDisable::new();
+
+    let cspace = CapabilitySpace::current();
+
+    let thread_group = cspace
+        .get_thread_group_with_perms(thread_group_id, CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner();
+
+    let memory = cspace
+        .get_memory_with_perms(memory_id, CapFlags::READ | CapFlags::WRITE, weak_auto_destroy)?
+        .into_inner();
+
+    thread_group.set_fault_capture(memory)
 }
\ No newline at end of file
@@ -0,0 +1,23 @@
+use sys::ClockId;
+
+use crate::prelude::*;
+use crate::time;
+
+/// Gets the current time of the given clock
+/// 
+/// # Returns
+/// seconds: whole seconds of the time
+/// nanoseconds: nanoseconds past the last whole second
+/// 
+/// Returns [`SysErr::InvlOp`] for the realtime clock if the real time clock could not be read at boot
+pub fn time_get(_options: u32, clock_id: usize) -> KResult<(usize, usize)> {
+    let clock_id = ClockId::from_repr(clock_id)
+        .ok_or(SysErr::InvlArgs)?;
+
+    let time = match clock_id {
+        ClockId::Monotonic => time::monotonic(),
+        ClockId::Realtime => time::realtime().ok_or(SysErr::InvlOp)?,
+    };
+
+    Ok((time.as_secs() as usize, time.subsec_nanos() as usize))
+}
\ No newline at end of file
@@ -1,4 +1,135 @@
-pub mod pit;
-pub mod apic_timer;
+//! Kernel clocks
+//!
+//! The monotonic clock counts nanoseconds since boot using the time stamp counter, which is calibrated against the pit
+//! when the local apic timer is initialized. Reading it is just an rdtsc and an atomic, so it is cheap enough to be
+//! read on every syscall that needs it, and could later be exposed to userspace without a syscall.
+//!
+//! The realtime clock is the monotonic clock offset by the unix time read from the cmos real time clock at boot.
 
-pub use apic_timer::{ApicTimer, apic_timer};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use spin::Once;
+
+use crate::arch::x64::{cpuid, rdtsc, tsc_ticks_to_nsec};
+use crate::prelude::*;
+
+mod rtc;
+
+/// Value of the time stamp counter on the startup core when the monotonic clock was initialized
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// Largest monotonic time returned so far on any core
+static LAST_MONOTONIC_NSEC: AtomicU64 = AtomicU64::new(0);
+
+/// Unix time in nanoseconds when the monotonic clock was at 0, this is not set if the rtc could not be read
+static REALTIME_BOOT_NSEC: Once<u64> = Once::new();
+
+/// How far an ap core's time stamp counter can lag behind the startup core before a warning is printed
+const TSC_SYNC_TOLERANCE: Duration = Duration::from_micros(50);
+
+/// Starts the monotonic clock and reads the realtime clock
+/// 
+/// Must be called on the startup core after the local apic timer has calibrated the time stamp counter
+pub fn init() {
+    BOOT_TSC.store(rdtsc(), Ordering::Release);
+
+    if !cpuid::has_invariant_tsc() {
+        eprintln!("warning: time stamp counter is not invariant, monotonic clock may drift");
+    }
+
+    match rtc::read_unix_time() {
+        Some(unix_time) => {
+            let boot_nsec = (unix_time.as_nanos() as u64).saturating_sub(monotonic_nsec());
+            REALTIME_BOOT_NSEC.call_once(|| boot_nsec);
+        },
+        None => eprintln!("warning: could not read real time clock, realtime clock is unavailable"),
+    }
+}
+
+/// Checks that the time stamp counter of the current core is in sync with the startup core
+/// 
+/// Called when ap cores start, if the counters are out of sync [`monotonic_nsec`] stays monotonic,
+/// but it will stall on cores with a lagging counter until they catch up
+pub fn check_tsc_sync() {
+    let core_nsec = tsc_nsec();
+    let last_nsec = LAST_MONOTONIC_NSEC.load(Ordering::Acquire);
+
+    if last_nsec > core_nsec && last_nsec - core_nsec > TSC_SYNC_TOLERANCE.as_nanos() as u64 {
+        eprintln!(
+            "warning: time stamp counter on cpu {:?} is {}ns behind the startup cpu",
+            prid(),
+            last_nsec - core_nsec,
+        );
+    }
+}
+
+/// Nanoseconds since boot according to the time stamp counter of the current core
+fn tsc_nsec() -> u64 {
+    tsc_ticks_to_nsec(rdtsc().saturating_sub(BOOT_TSC.load(Ordering::Acquire)))
+}
+
+/// Returns the number of nanoseconds since boot
+/// 
+/// This never goes backwards, even across different cores
+pub fn monotonic_nsec() -> u64 {
+    let nsec = tsc_nsec();
+
+    // time stamp counters on different cores can be slightly out of sync,
+    // so never return a time earlier than one which has already been returned
+    let last_nsec = LAST_MONOTONIC_NSEC.fetch_max(nsec, Ordering::AcqRel);
+    nsec.max(last_nsec)
+}
+
+/// Returns the time since boot
+pub fn monotonic() -> Duration {
+    Duration::from_nanos(monotonic_nsec())
+}
+
+/// Returns the time since the unix epoch, or None if the real time clock could not be read
+pub fn realtime() -> Option<Duration> {
+    let boot_nsec = *REALTIME_BOOT_NSEC.get()?;
+
+    Some(Duration::from_nanos(boot_nsec + monotonic_nsec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn monotonic_clock_advances() {
+        let start = monotonic();
+
+        let mut value = 0u64;
+        for i in 0..1_000_000 {
+            value = core::hint::black_box(value.wrapping_add(i));
+        }
+
+        let end = monotonic();
+
+        assert!(end > start);
+        // a million additions should never take a whole second
+        assert!(end - start < Duration::from_secs(1));
+    }
+
+    #[test_case]
+    fn monotonic_clock_never_goes_backwards() {
+        let mut last = monotonic_nsec();
+        for _ in 0..10_000 {
+            let now = monotonic_nsec();
+            assert!(now >= last);
+            last = now;
+        }
+    }
+
+    #[test_case]
+    fn realtime_is_after_2020() {
+        // 2020-01-01T00:00:00Z
+        let unix_2020 = Duration::from_secs(1_577_836_800);
+
+        if let Some(realtime) = realtime() {
+            assert!(realtime > unix_2020);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,168 @@
+//! Reads the date and time from the cmos real time clock
+
+use core::time::Duration;
+
+use crate::arch::x64::{inb, outb, IntDisable};
+
+const CMOS_SELECT: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+/// Setting this bit when selecting a cmos register keeps non maskable interrupts disabled
+const CMOS_NMI_DISABLE: u8 = 0x80;
+
+const RTC_SECONDS: u8 = 0x00;
+const RTC_MINUTES: u8 = 0x02;
+const RTC_HOURS: u8 = 0x04;
+const RTC_DAY: u8 = 0x07;
+const RTC_MONTH: u8 = 0x08;
+const RTC_YEAR: u8 = 0x09;
+const RTC_STATUS_A: u8 = 0x0a;
+const RTC_STATUS_B: u8 = 0x0b;
+
+/// Set in status register a while the rtc is updating its registers
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 0x80;
+/// Set in status register b if hours use 24 hour format
+const STATUS_B_24_HOUR: u8 = 0x02;
+/// Set in status register b if values are binary instead of bcd
+const STATUS_B_BINARY: u8 = 0x04;
+/// Set in the hours register for pm times in 12 hour format
+const HOURS_PM: u8 = 0x80;
+
+/// Maximum number of times the rtc registers are read while waiting for a consistent reading
+const READ_RETRY_COUNT: usize = 16;
+
+fn read_register(register: u8) -> u8 {
+    outb(CMOS_SELECT, CMOS_NMI_DISABLE | register);
+    inb(CMOS_DATA)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RtcTime {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+}
+
+impl RtcTime {
+    /// Reads the rtc registers once no update is in progress
+    fn read() -> Self {
+        while read_register(RTC_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+            core::hint::spin_loop();
+        }
+
+        RtcTime {
+            seconds: read_register(RTC_SECONDS),
+            minutes: read_register(RTC_MINUTES),
+            hours: read_register(RTC_HOURS),
+            day: read_register(RTC_DAY),
+            month: read_register(RTC_MONTH),
+            year: read_register(RTC_YEAR),
+        }
+    }
+
+    /// Converts the time to binary and 24 hour format according to the format bits in status register b
+    fn normalize(mut self, status_b: u8) -> Self {
+        let pm = self.hours & HOURS_PM != 0;
+        self.hours &= !HOURS_PM;
+
+        if status_b & STATUS_B_BINARY == 0 {
+            self.seconds = from_bcd(self.seconds);
+            self.minutes = from_bcd(self.minutes);
+            self.hours = from_bcd(self.hours);
+            self.day = from_bcd(self.day);
+            self.month = from_bcd(self.month);
+            self.year = from_bcd(self.year);
+        }
+
+        if status_b & STATUS_B_24_HOUR == 0 {
+            // 12 am is hour 0 and 12 pm is hour 12
+            self.hours %= 12;
+            if pm {
+                self.hours += 12;
+            }
+        }
+
+        self
+    }
+
+    /// Returns the time since the unix epoch, or None if any field is out of range
+    fn unix_time(&self) -> Option<Duration> {
+        if self.seconds > 59 || self.minutes > 59 || self.hours > 23
+            || self.day == 0 || self.day > 31 || self.month == 0 || self.month > 12 || self.year > 99 {
+            return None;
+        }
+
+        // the century register is not reliably present, so assume the 21st century
+        let year = 2000 + self.year as i64;
+        let days = days_from_civil(year, self.month as i64, self.day as i64);
+
+        let seconds = days * 86400
+            + self.hours as i64 * 3600
+            + self.minutes as i64 * 60
+            + self.seconds as i64;
+
+        Some(Duration::from_secs(seconds as u64))
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0xf)
+}
+
+/// Returns the number of days between the unix epoch and the given date in the gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // algorithm from http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// Reads the current time from the real time clock, returns None if the rtc contains an invalid time
+pub fn read_unix_time() -> Option<Duration> {
+    let _int_disable = IntDisable::new();
+
+    // registers are read until 2 reads in a row match, so an update which started during the read is not observed halfway
+    let mut time = RtcTime::read();
+    for _ in 0..READ_RETRY_COUNT {
+        let next_time = RtcTime::read();
+        if next_time == time {
+            let status_b = read_register(RTC_STATUS_B);
+            return time.normalize(status_b).unix_time();
+        }
+        time = next_time;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn rtc_date_conversion() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+
+        // 2024-02-29 1:45:30 pm in bcd 12 hour format
+        let time = RtcTime {
+            seconds: 0x30,
+            minutes: 0x45,
+            hours: 0x01 | HOURS_PM,
+            day: 0x29,
+            month: 0x02,
+            year: 0x24,
+        };
+
+        let unix_time = time.normalize(0).unix_time().unwrap();
+        assert_eq!(unix_time, Duration::from_secs(1_709_214_330));
+    }
+}
\ No newline at end of file
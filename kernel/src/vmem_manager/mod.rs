@@ -1,20 +1,26 @@
 //! This has all the functions that have to do with mappind physical memory into virtual memory
 
+use core::sync::atomic::{fence, Ordering};
+
 use lazy_static::lazy_static;
 use spin::Once;
 use sys::CapFlags;
 use sys::{MemoryCacheSetting, MemoryMappingFlags};
 
-use crate::arch::x64::invlpg;
+use crate::arch::x64::{get_cr3, invlpg, set_cr3};
 use crate::mem::PageSize;
 use crate::mem::PhysFrame;
 use crate::mem::VirtFrame;
 use crate::prelude::*;
 use crate::consts;
 use crate::alloc::PaRef;
-use page_table::{PageTable, PageTablePointer, PageTableFlags};
+use crate::container::Arc;
+use page_table::{PageTable, PageTableAllocator, PageTablePointer, PageTableFlags, RemovedTables};
+use tlb::{TlbFlush, MAX_INVALIDATE_PAGES};
+pub use tlb::CpuSet;
 
 mod page_table;
+pub mod tlb;
 
 lazy_static! {
     /// Most permissive page table flags used by parent tables
@@ -86,30 +92,65 @@ impl From<MemoryMappingFlags> for PageMappingOptions {
     }
 }
 
+impl From<PageMappingOptions> for MemoryMappingFlags {
+    fn from(options: PageMappingOptions) -> Self {
+        let mut flags = MemoryMappingFlags::from(options.cacheing);
+
+        if options.read {
+            flags |= MemoryMappingFlags::READ;
+        }
+        if options.write {
+            flags |= MemoryMappingFlags::WRITE;
+        }
+        if options.exec {
+            flags |= MemoryMappingFlags::EXEC;
+        }
+
+        flags
+    }
+}
+
 /// This represents a virtual address space that can have memory mapped into it
 #[derive(Debug)]
 pub struct VirtAddrSpace {
     /// Page table pointer which will go in the cr3 register, it points to the pml4 table
     cr3: PageTablePointer,
-    /// Page allocator used to allocate page frames for page tables
-    page_allocator: PaRef,
+    /// Allocates the page frames for page tables, and counts how many this address space uses
+    page_allocator: PageTableAllocator,
+    /// Cpus which currently have this address space loaded
+    active_cpus: Arc<CpuSet>,
+    /// Pages which were modified since the last tlb shootdown
+    pending_flush: TlbFlush,
 }
 
 impl VirtAddrSpace {
-    pub fn new(mut page_allocator: PaRef) -> KResult<Self> {
+    pub fn new(page_allocator: PaRef, active_cpus: Arc<CpuSet>) -> KResult<Self> {
+        let mut page_allocator = PageTableAllocator::new(page_allocator);
         let pml4_table = PageTable::new(&mut page_allocator, PageTableFlags::empty())
             .ok_or(SysErr::OutOfMem)?;
 
         let mut out = VirtAddrSpace {
             cr3: pml4_table,
             page_allocator,
+            active_cpus,
+            pending_flush: TlbFlush::default(),
         };
 
         out.initialize_kernel_mapping();
 
+        // the kernel tables are shared by every address space and never freed,
+        // so only the pml4 table is counted for a new address space
+        let kernel_tables = out.page_allocator.table_count() - 1;
+        out.page_allocator.forget_tables(kernel_tables);
+
         Ok(out)
     }
 
+    /// Returns the number of pages used by this address space's page tables, not including the shared kernel tables
+    pub fn page_table_pages(&self) -> usize {
+        self.page_allocator.table_count()
+    }
+
     /// Sets up the kernel memory mapping
     /// 
     /// Kernel memory is the last 512 GiB of the virtual address space
@@ -212,6 +253,25 @@ impl VirtAddrSpace {
         self.cr3.address()
     }
 
+    /// Invalidates all pages modified since the last flush on other cpus which have this address space loaded
+    /// 
+    /// The current cpu's tlb is always invalidated as soon as a page is modified,
+    /// so if no other cpu has this address space loaded no ipi is sent
+    pub fn flush_tlb(&mut self) {
+        if self.pending_flush.is_empty() {
+            return;
+        }
+
+        // page table writes must be visible before checking which cpus have this address space loaded,
+        // a cpu that loads it after this point will see the new page tables
+        fence(Ordering::SeqCst);
+
+        let targets = self.active_cpus.bits() & !(1 << prid().into());
+        tlb::shootdown(targets, &self.pending_flush);
+
+        self.pending_flush.clear();
+    }
+
     /// Deallocates all the page tables in this address space
     /// 
     /// Call this before dropping the address space otherwise there will be a memory leak
@@ -279,6 +339,12 @@ impl VirtAddrSpace {
 
             if level == 3 {
                 // last level
+                // other cpus only need to be flushed if an existing mapping changed,
+                // non present entries are never cached in the tlb
+                if page_table.present(index) {
+                    self.pending_flush.add_page(VirtAddr::new(virt_addr));
+                }
+
                 unsafe {
                     page_table.add_entry(index, new_page_pointer);
                 }
@@ -289,7 +355,6 @@ impl VirtAddrSpace {
             }
         }
 
-        // TODO: check if address space is loaded
         invlpg(virt_addr);
 
         Ok(())
@@ -313,55 +378,216 @@ impl VirtAddrSpace {
 
         let mut tables = [self.cr3.as_mut_ptr(), null_mut(), null_mut(), null_mut()];
 
-        for a in 1..4 {
-            unsafe {
-                tables[a] = if let Some(page_table) = tables[a - 1].as_mut() {
-                    page_table.get(page_table_indicies[a - 1])
-                } else {
-                    return None;
-                };
+        for level in 1..4 {
+            // safety: every table in this address space is valid, and only referenced through self
+            tables[level] = unsafe {
+                tables[level - 1].as_mut()?.get(page_table_indicies[level - 1])
+            };
+        }
+
+        // safety: see above
+        let tables = unsafe { tables.map(|table| table.as_mut()) };
+        let [Some(pml4), Some(pdp), Some(pd), Some(pt)] = tables else {
+            return None;
+        };
+        let mut tables = [pml4, pdp, pd, pt];
+
+        let leaf_index = page_table_indicies[3];
+        if !tables[3].present(leaf_index) {
+            return None;
+        }
+
+        let out = VirtAddr::new(tables[3].get(leaf_index) as usize).to_phys();
+
+        let first_freed = first_freed_level(tables.each_ref().map(|table| table.entry_count()));
+
+        tables[3].remove(leaf_index);
+        invlpg(virt_addr);
+        self.pending_flush.add_page(VirtAddr::new(virt_addr));
+
+        // unlink every table which is now empty from its parent
+        for level in first_freed..4 {
+            tables[level - 1].remove(page_table_indicies[level - 1]);
+        }
+
+        if first_freed < 4 {
+            // other cpus could still be caching the page tables, so they must be flushed before the tables are freed
+            self.flush_tlb();
+
+            for table in &mut tables[first_freed..] {
+                unsafe { table.dealloc(&mut self.page_allocator); }
             }
         }
 
-        // the index of the first entry in tables that needs to be deallocated
-        let mut dealloc_start_index = 4;
+        Some(out)
+    }
 
-        let mut out = None;
-        for i in (0..4).rev() {
-            let current_table = unsafe {
-                if let Some(table) = tables[i].as_mut() {
-                    if i == 3 {
-                        // get addres of table we are unmapping
-                        out = Some(VirtAddr::new(table.get(page_table_indicies[i]) as usize).to_phys());
-                    }
-                    table
-                } else {
-                    continue;
-                }
+    /// Unmaps every page in `range`, and frees the page tables which are left empty
+    /// 
+    /// Returns the number of pages which were mapped. Each page table is only visited once,
+    /// so this is much faster than calling [`unmap_page`](Self::unmap_page) for every page of a large range.
+    pub unsafe fn unmap_range(&mut self, range: AVirtRange) -> usize {
+        assert!(range.end_addr().as_usize() <= *consts::KERNEL_START);
+
+        if range.size() == 0 {
+            return 0;
+        }
+
+        let mut range_unmap = RangeUnmap {
+            start: range.addr().as_usize(),
+            end: range.end_addr().as_usize(),
+            pending_flush: &mut self.pending_flush,
+            removed: RemovedTables::new(),
+            unmapped_pages: 0,
+        };
+
+        // safety: every table in this address space is valid, and only referenced through self
+        unsafe {
+            range_unmap.unmap_table(self.cr3.as_mut_ptr().as_mut().unwrap(), 0, 0);
+        }
+
+        let RangeUnmap { removed, unmapped_pages, .. } = range_unmap;
+
+        // only the first pages were invalidated individually
+        if unmapped_pages > MAX_INVALIDATE_PAGES && self.active_cpus.contains(prid()) {
+            set_cr3(get_cr3());
+        }
+
+        if !removed.is_empty() {
+            // other cpus could still be caching the page tables, so they must be flushed before the tables are freed
+            self.flush_tlb();
+
+            unsafe { removed.dealloc_all(&mut self.page_allocator); }
+        }
+
+        unmapped_pages
+    }
+
+    /// Returns true if the page at `virt_addr` was written since its dirty bit was last cleared, or None if it is not mapped
+    /// 
+    /// If `clear` is true the dirty bit is cleared. Other cpus may have cached the translation with the dirty bit set,
+    /// and would not set it again on the next write, so [`flush_tlb`](Self::flush_tlb) must be called before relying on the bit.
+    pub fn take_page_dirty(&mut self, virt_addr: VirtAddr, clear: bool) -> Option<bool> {
+        let virt_addr = virt_addr.as_usize();
+
+        assert!(virt_addr < *consts::KERNEL_START);
+        assert!(page_aligned(virt_addr));
+
+        let page_table_indicies = [
+            get_bits(virt_addr, 39..48),
+            get_bits(virt_addr, 30..39),
+            get_bits(virt_addr, 21..30),
+            get_bits(virt_addr, 12..21),
+        ];
+
+        let mut page_table = unsafe {
+            self.cr3.as_mut_ptr().as_mut().unwrap()
+        };
+
+        // userspace pages are never mapped with huge pages, so every level but the last is a page table
+        for index in &page_table_indicies[..3] {
+            page_table = unsafe {
+                page_table.get(*index).as_mut()?
             };
+        }
 
-            current_table.remove(page_table_indicies[i]);
+        let index = page_table_indicies[3];
+        if !page_table.present(index) {
+            return None;
+        }
 
-            if i != 0 && current_table.entry_count() == 0 {
-                dealloc_start_index = i;
-            } else {
-                // don't continue removing this page table unless we have deallocated this table 
-                break;
-            }
+        let clear_flags = if clear {
+            PageTableFlags::DIRTY
+        } else {
+            PageTableFlags::empty()
+        };
+
+        let dirty = page_table.take_flags(index, clear_flags).contains(PageTableFlags::DIRTY);
+        if dirty && clear {
+            invlpg(virt_addr);
+            self.pending_flush.add_page(VirtAddr::new(virt_addr));
         }
 
-        // dealloc these in a later pass after all indexes are removed
-        for i in dealloc_start_index..4 {
-            unsafe {
-                if let Some(table) = tables[i].as_mut() {
-                    table.dealloc(&mut self.page_allocator)
-                } else {
-                    break;
+        Some(dirty)
+    }
+}
+
+/// Returns the first level of page table which is left empty and must be freed when a page is unmapped,
+/// or 4 if no table is freed
+/// 
+/// `entry_counts` are the number of entries in the tables of each level above the page before it is unmapped.
+/// Removing the page's entry only empties its table if it was the last entry, and each table freed removes
+/// an entry from its parent, so the freed tables are always the bottom levels. The pml4 table is never freed.
+fn first_freed_level(entry_counts: [usize; 4]) -> usize {
+    let mut first_freed = 4;
+
+    for level in (1..4).rev() {
+        if entry_counts[level] != 1 {
+            break;
+        }
+
+        first_freed = level;
+    }
+
+    first_freed
+}
+
+/// Walks the page tables for [`unmap_range`](VirtAddrSpace::unmap_range)
+struct RangeUnmap<'a> {
+    start: usize,
+    /// Exclusive end of the range
+    end: usize,
+    pending_flush: &'a mut TlbFlush,
+    /// Tables left empty, which are freed after the tlb is flushed
+    removed: RemovedTables,
+    unmapped_pages: usize,
+}
+
+impl RangeUnmap<'_> {
+    /// Unmaps the part of the range mapped by `table`, which is at page table level `level` and starts at `table_addr`
+    /// 
+    /// Child tables left empty are removed from `table`. Returns true if `table` is left empty.
+    /// 
+    /// # Safety
+    /// 
+    /// `table` must be a page table of the address space being unmapped, and must not map any huge pages
+    unsafe fn unmap_table(&mut self, table: &mut PageTable, level: usize, table_addr: usize) -> bool {
+        let entry_shift = 39 - 9 * level;
+
+        let first_index = self.start.saturating_sub(table_addr) >> entry_shift;
+        let last_index = core::cmp::min(
+            (self.end - 1 - table_addr) >> entry_shift,
+            page_table::NUM_ENTRIES - 1,
+        );
+
+        for index in first_index..=last_index {
+            if !table.present(index) {
+                continue;
+            }
+
+            let entry_addr = table_addr + (index << entry_shift);
+
+            if level == 3 {
+                table.remove(index);
+
+                // the tlb is reloaded after the walk if too many pages are unmapped
+                if self.unmapped_pages < MAX_INVALIDATE_PAGES {
+                    invlpg(entry_addr);
+                }
+                self.pending_flush.add_page(VirtAddr::new(entry_addr));
+                self.unmapped_pages += 1;
+            } else {
+                // safety: the entry is present, and user memory is never mapped with huge pages
+                let child = unsafe { table.get(index).as_mut().unwrap() };
+
+                if unsafe { self.unmap_table(child, level + 1, entry_addr) } {
+                    table.remove(index);
+                    unsafe { self.removed.push(child); }
                 }
             }
         }
 
-        out
+        table.entry_count() == 0
     }
 }
 
@@ -520,4 +746,98 @@ impl VirtAddrSpace {
 
         Ok(())
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::{root_alloc_page_ref, root_alloc_ref};
+
+    #[test_case]
+    fn first_freed_level_only_frees_emptied_tables() {
+        assert_eq!(first_freed_level([3, 2, 5, 7]), 4);
+        assert_eq!(first_freed_level([2, 1, 1, 2]), 4);
+        assert_eq!(first_freed_level([2, 1, 3, 1]), 3);
+        assert_eq!(first_freed_level([2, 4, 1, 1]), 2);
+        // the pml4 table is never freed
+        assert_eq!(first_freed_level([1, 1, 1, 1]), 1);
+    }
+
+    fn map_test_range(addr_space: &mut VirtAddrSpace, range: AVirtRange) {
+        let options = PageMappingOptions {
+            read: true,
+            ..Default::default()
+        };
+
+        // the address space is never loaded, so the physical memory is never accessed
+        for i in 0..range.page_size() {
+            unsafe {
+                addr_space.map_page(range.addr() + i * PAGE_SIZE, PhysAddr::new(i * PAGE_SIZE), options).unwrap();
+            }
+        }
+    }
+
+    #[test_case]
+    fn unmap_frees_page_tables() {
+        let active_cpus = Arc::new(CpuSet::new(), root_alloc_ref()).unwrap();
+        let mut addr_space = VirtAddrSpace::new(root_alloc_page_ref(), active_cpus).unwrap();
+
+        // only the pml4 table belongs to a new address space
+        let baseline = addr_space.page_table_pages();
+        assert_eq!(baseline, 1);
+
+        // 16 MiB crossing a 1 GiB boundary, not aligned to a 2 MiB boundary
+        let start = VirtAddr::new(PageSize::G1 as usize - 4 * PageSize::M2 as usize + 3 * PAGE_SIZE);
+        let range = AVirtRange::new(start, 8 * PageSize::M2 as usize);
+
+        for _ in 0..4 {
+            map_test_range(&mut addr_space, range);
+            // 1 pdp table, 2 pd tables, and 9 page tables
+            assert_eq!(addr_space.page_table_pages(), baseline + 12);
+
+            // only the first page table is completely unmapped
+            let first_part = AVirtRange::new(start, PageSize::M2 as usize);
+            let unmapped_pages = unsafe { addr_space.unmap_range(first_part) };
+            assert_eq!(unmapped_pages, PageSize::M2 as usize / PAGE_SIZE);
+            assert_eq!(addr_space.page_table_pages(), baseline + 11);
+
+            let unmapped_pages = unsafe { addr_space.unmap_range(range) };
+            assert_eq!(unmapped_pages, range.page_size() - PageSize::M2 as usize / PAGE_SIZE);
+            assert_eq!(addr_space.page_table_pages(), baseline);
+        }
+
+        map_test_range(&mut addr_space, range);
+        for i in 0..range.page_size() {
+            let phys_addr = unsafe { addr_space.unmap_page(range.addr() + i * PAGE_SIZE) };
+            assert_eq!(phys_addr, Some(PhysAddr::new(i * PAGE_SIZE)));
+        }
+        assert_eq!(addr_space.page_table_pages(), baseline);
+
+        unsafe {
+            addr_space.dealloc_addr_space();
+        }
+    }
+
+    #[test_case]
+    fn unmap_page_not_mapped() {
+        let active_cpus = Arc::new(CpuSet::new(), root_alloc_ref()).unwrap();
+        let mut addr_space = VirtAddrSpace::new(root_alloc_page_ref(), active_cpus).unwrap();
+
+        let range = AVirtRange::new(VirtAddr::new(PageSize::G1 as usize), PAGE_SIZE);
+        map_test_range(&mut addr_space, range);
+        let page_table_pages = addr_space.page_table_pages();
+
+        unsafe {
+            // the page table for this page exists, but the page is not mapped
+            assert_eq!(addr_space.unmap_page(range.end_addr()), None);
+            // no page table exists for this page
+            assert_eq!(addr_space.unmap_page(VirtAddr::new(2 * PageSize::G1 as usize)), None);
+            assert_eq!(addr_space.page_table_pages(), page_table_pages);
+
+            assert_eq!(addr_space.unmap_page(range.addr()), Some(PhysAddr::new(0)));
+            assert_eq!(addr_space.page_table_pages(), 1);
+
+            addr_space.dealloc_addr_space();
+        }
+    }
 }
\ No newline at end of file
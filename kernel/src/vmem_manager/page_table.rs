@@ -1,6 +1,8 @@
 //! Contains functions for creating and manipulating page tables
 // FIXME: this module has some super unsafe code that should be fixed
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use bitflags::bitflags;
 
 use crate::arch::x64::PatEntry;
@@ -124,6 +126,73 @@ impl PageTablePointer {
     }
 }
 
+/// Allocates the page tables of 1 address space, and counts how many pages they use
+#[derive(Debug)]
+pub struct PageTableAllocator {
+	allocer: PaRef,
+	/// Number of page tables currently allocated with this allocator
+	table_count: usize,
+}
+
+impl PageTableAllocator {
+	pub fn new(allocer: PaRef) -> Self {
+		PageTableAllocator {
+			allocer,
+			table_count: 0,
+		}
+	}
+
+	/// Returns the number of pages used by page tables allocated with this allocator
+	pub fn table_count(&self) -> usize {
+		self.table_count
+	}
+
+	/// Stops counting `count` tables which are still allocated, but are not owned by this address space
+	pub fn forget_tables(&mut self, count: usize) {
+		self.table_count -= count;
+	}
+}
+
+/// Page tables which were removed from an address space, but can't be freed until no cpu can be caching them
+/// 
+/// The tables are empty, so they are linked together through their first entry instead of needing an allocation.
+#[derive(Debug)]
+pub struct RemovedTables {
+	head: *mut PageTable,
+}
+
+impl RemovedTables {
+	pub fn new() -> Self {
+		RemovedTables {
+			head: null_mut(),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.head.is_null()
+	}
+
+	/// # Safety
+	/// 
+	/// `table` must have been removed from its parent, and must not be pushed more than once
+	pub unsafe fn push(&mut self, table: &mut PageTable) {
+		table.0[0] = PageTablePointer(self.head as usize);
+		self.head = table;
+	}
+
+	/// # Safety
+	/// 
+	/// No cpu can still be caching any of the removed tables
+	pub unsafe fn dealloc_all(self, allocer: &mut PageTableAllocator) {
+		let mut current = self.head;
+
+		while let Some(table) = unsafe { current.as_mut() } {
+			current = table.0[0].0 as *mut PageTable;
+			unsafe { table.dealloc(allocer); }
+		}
+	}
+}
+
 #[repr(transparent)]
 #[derive(Debug)]
 pub struct PageTable([PageTablePointer; NUM_ENTRIES]);
@@ -135,12 +204,12 @@ impl PageTable {
     /// Returns a PageTablePointer with the provided `flags`
     /// Returns None if there is not enough memory
 	pub fn new(
-		allocer: &mut PaRef,
+		allocer: &mut PageTableAllocator,
 		flags: PageTableFlags,
 	) -> Option<PageTablePointer> {
         // FIXME: handle case where allocator gives us back more than 1 frame
         // this is technically allowed to happen, but with current implementation it won't
-		let frame = allocer.alloc(
+		let frame = allocer.allocer.alloc(
             // This should never panic
             PageLayout::new_rounded(PAGE_SIZE, PAGE_SIZE).unwrap()
         )?.as_usize();
@@ -149,6 +218,8 @@ impl PageTable {
 			memset(frame as *mut u8, PAGE_SIZE, 0);
 		}
 
+		allocer.table_count += 1;
+
 		let addr = virt_to_phys(frame);
 		let flags = flags | PageTableFlags::PRESENT | PageTableFlags::PAGE_TABLE;
 
@@ -176,22 +247,40 @@ impl PageTable {
     /// # Panics
     /// 
     /// panics if `index` is out of the page table bounds
-	fn present(&self, index: usize) -> bool {
+	pub fn present(&self, index: usize) -> bool {
 		(self.0[index].0 & PageTableFlags::PRESENT.bits()) != 0
 	}
 
-	pub unsafe fn dealloc(&mut self, allocer: &mut PaRef) {
+	/// Clears `flags` from the entry at `index`, and returns the flags it had before
+	/// 
+	/// This is atomic, so the accessed and dirty bits the cpu sets while this runs are not lost.
+	/// 
+	/// # Panics
+	/// 
+	/// panics if `index` is out of the page table bounds
+	pub fn take_flags(&self, index: usize, flags: PageTableFlags) -> PageTableFlags {
+		// safety: the entry is a usize which is only written while the address space lock is held,
+		// or atomically by the cpu when setting the accessed and dirty bits
+		let entry = unsafe {
+			&*(&self.0[index] as *const PageTablePointer as *const AtomicUsize)
+		};
+
+		PageTableFlags::from_bits_truncate(entry.fetch_and(!flags.bits(), Ordering::SeqCst))
+	}
+
+	pub unsafe fn dealloc(&mut self, allocer: &mut PageTableAllocator) {
 		let frame = Allocation::new(self.addr(), PAGE_SIZE);
 		// TODO: maybe use regular dealloc and store the zindex in unused bits of page tabel entries
-        unsafe { allocer.dealloc(frame); }
+        unsafe { allocer.allocer.dealloc(frame); }
+		allocer.table_count -= 1;
 	}
 
-	pub unsafe fn dealloc_all(&mut self, allocer: &mut PaRef) {
+	pub unsafe fn dealloc_all(&mut self, allocer: &mut PageTableAllocator) {
 		unsafe { self.dealloc_recurse(allocer, 3); }
 	}
 
     // FIXME: this is super unsafe
-	unsafe fn dealloc_recurse(&mut self, allocer: &mut PaRef, level: usize) {
+	unsafe fn dealloc_recurse(&mut self, allocer: &mut PageTableAllocator, level: usize) {
 		let last_entry_index = self.0.len() - 1;
 
 		if level > 0 {
@@ -248,7 +337,7 @@ impl PageTable {
 	pub fn get_or_alloc<'a>(
 		&'a mut self,
 		index: usize,
-		allocer: &mut PaRef,
+		allocer: &mut PageTableAllocator,
 		flags: PageTableFlags,
 	) -> Option<&'a mut PageTable> {
 		// safety: page tables form a tree (no recursive mapping)
@@ -0,0 +1,310 @@
+//! Tlb shootdown, which invalidates stale tlb entries on other cpus after their page tables are modified
+//!
+//! Each address space tracks which cpus currently have it loaded in a [`CpuSet`].
+//! Changes to page tables are recorded in a [`TlbFlush`], and once the whole operation is done
+//! the pages are invalidated on every other cpu in the set with an [`IPI_TLB_SHOOTDOWN`] ipi.
+//!
+//! Only one shootdown is in progress at a time, and the sender waits until all targeted cpus acknowledge it.
+//! Cpus which spin on a lock with interrupts disabled can't receive the ipi,
+//! so [`IMutex`](crate::sync::IMutex) and [`IrwLock`](crate::sync::IrwLock) handle pending shootdowns while they spin to avoid deadlocks.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering, fence};
+
+use spin::Mutex;
+
+use crate::arch::x64::{get_cr3, invlpg, set_cr3};
+use crate::config::MAX_CPUS;
+use crate::gs_data::Prid;
+use crate::int::IPI_TLB_SHOOTDOWN;
+use crate::int::apic::{Ipi, IpiDest};
+use crate::prelude::*;
+
+const _: () = assert!(MAX_CPUS <= u64::BITS as usize, "cpu set cannot store all cpus");
+
+/// Maximum number of pages invalidated one at a time in a shootdown, bigger shootdowns flush the whole tlb
+pub const MAX_INVALIDATE_PAGES: usize = 32;
+
+/// A set of cpus
+#[derive(Debug, Default)]
+pub struct CpuSet(AtomicU64);
+
+impl CpuSet {
+    pub const fn new() -> Self {
+        CpuSet(AtomicU64::new(0))
+    }
+
+    pub fn insert(&self, prid: Prid) {
+        self.0.fetch_or(1 << prid.into(), Ordering::AcqRel);
+    }
+
+    pub fn remove(&self, prid: Prid) {
+        self.0.fetch_and(!(1 << prid.into()), Ordering::AcqRel);
+    }
+
+    pub fn contains(&self, prid: Prid) -> bool {
+        self.bits() & (1 << prid.into()) != 0
+    }
+
+    pub fn bits(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Pages whose tlb entries need to be invalidated on other cpus
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TlbFlush {
+    pages: [usize; MAX_INVALIDATE_PAGES],
+    page_count: usize,
+    /// Set when too many pages were added to invalidate individually
+    flush_all: bool,
+}
+
+impl TlbFlush {
+    pub fn add_page(&mut self, virt_addr: VirtAddr) {
+        if self.page_count < MAX_INVALIDATE_PAGES {
+            self.pages[self.page_count] = virt_addr.as_usize();
+            self.page_count += 1;
+        } else {
+            self.flush_all = true;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.page_count == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.page_count = 0;
+        self.flush_all = false;
+    }
+}
+
+/// The shootdown currently being processed by other cpus
+struct ShootdownRequest {
+    pages: [AtomicUsize; MAX_INVALIDATE_PAGES],
+    page_count: AtomicUsize,
+    flush_all: AtomicBool,
+    /// Cpus which have not yet invalidated their tlb for this request
+    targets: AtomicU64,
+}
+
+static REQUEST: ShootdownRequest = ShootdownRequest {
+    pages: [const { AtomicUsize::new(0) }; MAX_INVALIDATE_PAGES],
+    page_count: AtomicUsize::new(0),
+    flush_all: AtomicBool::new(false),
+    targets: AtomicU64::new(0),
+};
+
+/// Held by the cpu sending a shootdown
+static SHOOTDOWN_LOCK: Mutex<()> = Mutex::new(());
+
+/// Invalidates the pages in `flush` on all the cpus in `targets`, and waits for them to finish
+/// 
+/// `targets` must not contain the current cpu
+pub fn shootdown(targets: u64, flush: &TlbFlush) {
+    debug_assert!(targets & (1 << prid().into()) == 0, "tlb shootdown sent to current cpu");
+
+    if targets == 0 || flush.is_empty() {
+        return;
+    }
+
+    let _lock = loop {
+        if let Some(lock) = SHOOTDOWN_LOCK.try_lock() {
+            break lock;
+        }
+
+        // another cpu might be waiting on this one to handle its shootdown
+        handle_shootdown();
+        core::hint::spin_loop();
+    };
+
+    for (request_page, page) in REQUEST.pages.iter().zip(&flush.pages[..flush.page_count]) {
+        request_page.store(*page, Ordering::Relaxed);
+    }
+    REQUEST.page_count.store(flush.page_count, Ordering::Relaxed);
+    REQUEST.flush_all.store(flush.flush_all, Ordering::Relaxed);
+    REQUEST.targets.store(targets, Ordering::Release);
+
+    let mut local_apic = cpu_local_data().local_apic();
+    for cpu in 0..MAX_CPUS {
+        if targets & (1 << cpu) != 0 {
+            local_apic.send_ipi(Ipi::To(IpiDest::to_prid(Prid::from(cpu)), IPI_TLB_SHOOTDOWN));
+        }
+    }
+    drop(local_apic);
+
+    while REQUEST.targets.load(Ordering::Acquire) != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Invalidates the tlb of the current cpu if it is targeted by the current shootdown
+/// 
+/// Called by the shootdown ipi handler, and anywhere a cpu spins with interrupts disabled
+pub fn handle_shootdown() {
+    let targets = REQUEST.targets.load(Ordering::Acquire);
+    // this is checked first, because prid can't be read before cpu local data is initialized,
+    // and no shootdowns are sent until all cpus have started
+    if targets == 0 {
+        return;
+    }
+
+    let cpu_bit = 1 << prid().into();
+    if targets & cpu_bit == 0 {
+        return;
+    }
+
+    if REQUEST.flush_all.load(Ordering::Relaxed) {
+        // reloading cr3 flushes all non global tlb entries
+        set_cr3(get_cr3());
+    } else {
+        let page_count = REQUEST.page_count.load(Ordering::Relaxed);
+        for page in &REQUEST.pages[..page_count] {
+            invlpg(page.load(Ordering::Relaxed));
+        }
+    }
+
+    // all reads of the request must be finished before acknowledging it, since the sender may then write a new request
+    fence(Ordering::Release);
+    REQUEST.targets.fetch_and(!cpu_bit, Ordering::AcqRel);
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use bit_utils::Size;
+
+    use super::*;
+    use crate::alloc::{root_alloc_page_ref, root_alloc_ref};
+    use crate::arch::x64::IntDisable;
+    use crate::cap::address_space::AddressSpace;
+    use crate::cap::memory::{Memory, MapMemoryArgs, PageSource};
+    use crate::config::cpu_count;
+    use crate::container::Arc;
+    use crate::sched::{defer_work, WorkItem};
+    use crate::syscall::{copy_from_userspace, UserCopyError};
+    use crate::time::monotonic_nsec;
+    use crate::vmem_manager::PageMappingOptions;
+
+    const TEST_MAP_ADDR: usize = 0x40000000;
+    const STEP_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Set by the test thread once the test page is in its cpu's tlb
+    static PAGE_TOUCHED: AtomicBool = AtomicBool::new(false);
+    /// Set by the deferred work on the startup cpu once the test page is unmapped
+    static PAGE_UNMAPPED: AtomicBool = AtomicBool::new(false);
+
+    fn wait_until(flag: &AtomicBool, what: &str) {
+        let deadline = monotonic_nsec() + STEP_TIMEOUT.as_nanos() as u64;
+        while !flag.load(Ordering::Acquire) {
+            assert!(monotonic_nsec() < deadline, "timed out waiting until {}", what);
+            // the test thread waits with interrupts disabled, so it must handle the shootdown itself
+            handle_shootdown();
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Spins until the current thread is running on `cpu`, the thread's cpu mask must only allow `cpu`
+    fn wait_for_migration(cpu: usize) {
+        let deadline = monotonic_nsec() + STEP_TIMEOUT.as_nanos() as u64;
+        while prid().into() != cpu {
+            assert!(monotonic_nsec() < deadline, "thread was not moved to cpu {}", cpu);
+            core::hint::spin_loop();
+        }
+    }
+
+    #[test_case]
+    fn tlb_flush_overflows_to_flush_all() {
+        let mut flush = TlbFlush::default();
+        assert!(flush.is_empty());
+
+        for i in 0..MAX_INVALIDATE_PAGES {
+            flush.add_page(VirtAddr::new(i * PAGE_SIZE));
+        }
+        assert!(!flush.flush_all);
+
+        flush.add_page(VirtAddr::new(MAX_INVALIDATE_PAGES * PAGE_SIZE));
+        assert!(flush.flush_all);
+
+        flush.clear();
+        assert!(flush.is_empty() && !flush.flush_all);
+    }
+
+    #[test_case]
+    fn shootdown_acknowledged_by_all_cpus() {
+        let current_cpu = prid().into();
+        let targets = (0..cpu_count())
+            .filter(|cpu| *cpu != current_cpu)
+            .fold(0, |targets, cpu| targets | (1 << cpu));
+
+        let mut flush = TlbFlush::default();
+        flush.add_page(VirtAddr::new(0x1000));
+
+        // returns once every other cpu handled the ipi
+        shootdown(targets, &flush);
+        assert_eq!(REQUEST.targets.load(Ordering::Acquire), 0);
+    }
+
+    #[test_case]
+    fn unmap_on_other_cpu_invalidates_tlb() {
+        const TOUCH_CPU: usize = 1;
+
+        if cpu_count() <= TOUCH_CPU {
+            return;
+        }
+
+        PAGE_TOUCHED.store(false, Ordering::Release);
+        PAGE_UNMAPPED.store(false, Ordering::Release);
+
+        let start_cpu = prid().into();
+        let address_space = AddressSpace::current();
+        let memory = Arc::new(
+            Memory::new_with_page_source(root_alloc_page_ref(), root_alloc_ref(), 1, PageSource::LazyZeroAlloc).unwrap(),
+            root_alloc_ref(),
+        ).unwrap();
+
+        Memory::map_memory(memory, address_space.clone(), MapMemoryArgs {
+            map_addr: VirtAddr::new(TEST_MAP_ADDR),
+            map_size: None,
+            offset: Size::zero(),
+            options: PageMappingOptions {
+                read: true,
+                write: true,
+                ..Default::default()
+            },
+        }).unwrap();
+
+        // the deferred work runs on this cpu once the test thread has moved away from it
+        let unmap_address_space = address_space.clone();
+        defer_work(WorkItem::closure(move || {
+            wait_until(&PAGE_TOUCHED, "the test page is touched");
+            unmap_address_space.unmap(VirtAddr::new(TEST_MAP_ADDR)).unwrap();
+            PAGE_UNMAPPED.store(true, Ordering::Release);
+        }).unwrap()).unwrap();
+
+        let thread = cpu_local_data().current_thread();
+        thread.set_cpu_mask(1 << TOUCH_CPU);
+        wait_for_migration(TOUCH_CPU);
+
+        let mut buffer = [0u8; 8];
+        let user_buffer = TEST_MAP_ADDR as *const u8;
+
+        // no thread switch can reload cr3 while interrupts are disabled, so only the shootdown removes the tlb entry
+        let int_disable = IntDisable::new();
+        assert_eq!(copy_from_userspace(&mut buffer, user_buffer), Ok(()));
+        PAGE_TOUCHED.store(true, Ordering::Release);
+
+        wait_until(&PAGE_UNMAPPED, "the test page is unmapped");
+        let result = copy_from_userspace(&mut buffer, user_buffer);
+        drop(int_disable);
+
+        // the other tests expect to run on the startup core
+        thread.set_cpu_mask(1 << start_cpu);
+        wait_for_migration(start_cpu);
+        thread.set_cpu_mask(u64::MAX);
+
+        // a stale tlb entry would let the read of the freed page succeed
+        assert_eq!(result, Err(UserCopyError::PartialCopy { copied: 0 }));
+    }
+}
\ No newline at end of file
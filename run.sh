@@ -27,16 +27,42 @@ fi
 if [[ $1 = debug ]]
 then
 	# FIXME: use $TERM environment variable instead of konsole
-	qemu-system-x86_64 -M q35 -m 5120 -smp cpus=1,cores=1 -debugcon stdio -s -S -drive file=$IMG,format=raw & konsole -e "$HOME/.cargo/bin/rust-gdb" "--nh" "-x" "debug.gdb"
+	qemu-system-x86_64 -M q35 -m 5120 -smp cpus=1,cores=1 -debugcon stdio -s -S -drive file=$IMG,format=raw,if=virtio & konsole -e "$HOME/.cargo/bin/rust-gdb" "--nh" "-x" "debug.gdb"
 elif [[ $1 = release ]] && [[ $2 = debug ]]
 then
-	qemu-system-x86_64 -M q35 -m 5120 -debugcon stdio -s -S -drive file=$IMG,format=raw & konsole -e "$HOME/.cargo/bin/rust-gdb" "--nh" "-x" "debug-release.gdb"
+	qemu-system-x86_64 -M q35 -m 5120 -debugcon stdio -s -S -drive file=$IMG,format=raw,if=virtio & konsole -e "$HOME/.cargo/bin/rust-gdb" "--nh" "-x" "debug-release.gdb"
 elif [[ $1 = bochs ]]
 then
 	konsole -e bochs -f bochsrc
-elif [[ -z $1 ]] || [[ $1 = release ]] || [[ $1 = test ]]
+elif [[ $1 = test ]]
+then
+	# the kernel writes to the isa-debug-exit port once the tests finish, qemu then exits with status (code << 1) | 1
+	# pass ktest=<filter> on the kernel command line in boot/grub/grub.cfg to only run some tests
+	qemu-system-x86_64 -M q35 -m 5120 -smp cpus=4,cores=4 -debugcon stdio -drive file=$IMG,format=raw,if=virtio \
+		-device isa-debug-exit,iobase=0xf4,iosize=0x04
+	# 33 means every test passed
+	[[ $? = 33 ]]
+	exit
+elif [[ $1 = utest ]]
+then
+	# runs the userland test binaries, add utest to the multiboot2 line in boot/grub/grub.cfg to start the test runner
+	# the test runner tells the kernel to write to the isa-debug-exit port once every test binary has run
+	qemu-system-x86_64 -M q35 -m 5120 -smp cpus=4,cores=4 -debugcon stdio -drive file=$IMG,format=raw,if=virtio \
+		-device isa-debug-exit,iobase=0xf4,iosize=0x04
+	# 33 means every test passed
+	[[ $? = 33 ]]
+	exit
+elif [[ $1 = shutdowntest ]]
+then
+	# early-init shuts down the machine once it has started everything, qemu then exits with status 0
+	# the isa-debug-exit device is left out so the acpi shutdown path is used
+	timeout 120 qemu-system-x86_64 -M q35 -m 5120 -smp cpus=4,cores=4 -debugcon stdio -drive file=$IMG,format=raw,if=virtio
+	# timeout exits with 124 if qemu was still running
+	[[ $? = 0 ]]
+	exit
+elif [[ -z $1 ]] || [[ $1 = release ]]
 then
 	# the -M q35 option is necessery for qemu to support the mcfg acpi table
 	# this table is used to find the memory mapped pcie devices
-	qemu-system-x86_64 -M q35 -m 5120 -smp cpus=4,cores=4 -debugcon stdio -drive file=$IMG,format=raw
+	qemu-system-x86_64 -M q35 -m 5120 -smp cpus=4,cores=4 -debugcon stdio -drive file=$IMG,format=raw,if=virtio
 fi
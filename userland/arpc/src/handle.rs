@@ -0,0 +1,122 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+use core::task::{Poll, Waker};
+
+use futures::future::poll_fn;
+
+/// State shared between a running service loop and its [`ServiceHandle`]s
+#[derive(Default)]
+pub(crate) struct ServiceState {
+    shutting_down: Cell<bool>,
+    stopped: Cell<bool>,
+    /// Woken when a shutdown is requested
+    loop_waker: Cell<Option<Waker>>,
+    /// Tasks waiting for the service loop to stop
+    stop_wakers: RefCell<Vec<Waker>>,
+    respond_failures: Cell<usize>,
+    calls: Cell<usize>,
+    sent_messages: Cell<usize>,
+}
+
+impl ServiceState {
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.get()
+    }
+
+    /// Returns a future which resolves once a shutdown has been requested
+    pub(crate) fn shutdown_requested(self: &Rc<Self>) -> impl Future<Output = ()> {
+        let state = self.clone();
+
+        poll_fn(move |cx| {
+            if state.shutting_down.get() {
+                Poll::Ready(())
+            } else {
+                state.loop_waker.set(Some(cx.waker().clone()));
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Records that the service could not respond to a call
+    pub(crate) fn add_respond_failure(&self) {
+        self.respond_failures.set(self.respond_failures.get() + 1);
+    }
+
+    /// Records that the service recieved a message, `has_reply` is false if it was sent instead of called
+    pub(crate) fn add_message(&self, has_reply: bool) {
+        let count = if has_reply {
+            &self.calls
+        } else {
+            &self.sent_messages
+        };
+
+        count.set(count.get() + 1);
+    }
+
+    /// Called by the service loop once it has stopped, for any reason
+    pub(crate) fn set_stopped(&self) {
+        self.stopped.set(true);
+
+        for waker in self.stop_wakers.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Counters describing a running rpc service, returned by [`ServiceHandle::metrics`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServiceMetrics {
+    /// Number of responses which could not be sent, because they could not be serialized or the caller had exited
+    pub respond_failures: usize,
+    /// Number of messages recieved with a reply capability, which the caller waits for a response to
+    pub calls: usize,
+    /// Number of messages recieved without a reply capability, which are how oneway methods are sent
+    pub sent_messages: usize,
+}
+
+/// A handle used to stop a running rpc service
+/// 
+/// The handle can be cloned and used from any task on the thread running the service.
+#[derive(Clone)]
+pub struct ServiceHandle(pub(crate) Rc<ServiceState>);
+
+impl ServiceHandle {
+    /// Returns true once the service loop has exited
+    pub fn is_stopped(&self) -> bool {
+        self.0.stopped.get()
+    }
+
+    pub fn metrics(&self) -> ServiceMetrics {
+        ServiceMetrics {
+            respond_failures: self.0.respond_failures.get(),
+            calls: self.0.calls.get(),
+            sent_messages: self.0.sent_messages.get(),
+        }
+    }
+
+    /// Gracefully shuts down the service
+    /// 
+    /// The service stops handling new messages as soon as this is called, without waiting for the future to be polled.
+    /// Callers whose messages are recieved after this point get [`RpcError::ServiceShuttingDown`](crate::RpcError::ServiceShuttingDown).
+    /// The returned future resolves once every call the service was already handling has finished and the service loop has exited.
+    pub fn shutdown(&self) -> impl Future<Output = ()> {
+        let state = self.0.clone();
+
+        if !state.shutting_down.replace(true) {
+            if let Some(waker) = state.loop_waker.take() {
+                waker.wake();
+            }
+        }
+
+        poll_fn(move |cx| {
+            if state.stopped.get() {
+                Poll::Ready(())
+            } else {
+                state.stop_wakers.borrow_mut().push(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+    }
+}
\ No newline at end of file
@@ -1,22 +1,54 @@
 #![no_std]
 
-use serde::{Serialize, Deserialize};
+// the loopback tests make their service with the derive macro, whose output needs these
+#![cfg_attr(all(test, feature = "loopback"), feature(associated_type_defaults, decl_macro))]
+
+extern crate alloc;
+
+use alloc::rc::Rc;
+use core::future::Future;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
 use thiserror_no_std::Error;
-use sys::{Reply, DropCheck, KResult, Channel, CapFlags, CspaceTarget, SysErr, cap_clone};
-use futures::{select_biased, StreamExt};
-use aurora_core::{this_context, collections::MessageVec};
-use asynca::async_sys::{AsyncChannel, AsyncDropCheckReciever};
+use sys::{Reply, DropCheck, KResult, Channel, ChannelSendFlags, CapFlags, CspaceTarget, SysErr, Weak, cap_clone};
+use futures::{select_biased, FutureExt, StreamExt};
+use futures::future::FusedFuture;
+use futures::stream::FusedStream;
+use aurora_core::{this_context, collections::{MessageVec, MessageArena, MessageArenaError}};
+use aurora_core::sync::Mutex;
+use asynca::MessageRecievedEvent;
+use asynca::async_sys::{AsyncChannel, AsyncDropCheckReciever, AsyncHandleDrop, CallResponse};
+use bit_utils::Size;
 pub use arpc_derive::{service, service_impl};
+
+mod handle;
+pub use handle::{ServiceHandle, ServiceMetrics};
+use handle::ServiceState;
+mod reply;
+pub use reply::ReplyGuard;
+use reply::{InFlightReplies, ReplySender};
+mod response_buffer;
+use response_buffer::{ResponseBuffer, DEFAULT_RESPONSE_BUFFER_SIZE};
+pub mod replay;
+use replay::{MessageSink, Recorder};
+mod mux;
+pub use mux::{ServiceMux, ServiceIds};
+#[cfg(feature = "loopback")]
+pub mod testing;
+
 // reexport sys, aser, and asynca for arpc_derive macro so dependancy on sys is not required
 pub use sys;
 pub use aser;
 pub use asynca;
 
+/// Wrap capability arguments in these to move them to the service, or to share them with fewer permissions
+pub use aser::{CapMove, CapShare};
+
 /// A version of `RpcCall` which doesn't contain the arguments
 /// 
 /// This is so we can check which method is called first,
 /// and let that method deserialize the data it is expecting
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct RpcCallMethod {
     pub service_id: u64,
     pub method_id: u32,
@@ -29,61 +61,399 @@ pub struct RpcCall<T> {
     pub args: T,
 }
 
+/// Permissions of the channel held by a [`ClientRpcEndpoint`], which let it call the service but not recieve its calls
+const CLIENT_CHANNEL_FLAGS: CapFlags = CapFlags::READ.union(CapFlags::PROD).union(CapFlags::UPGRADE);
+
+/// Limits used when deserializing messages recieved by a service, since they come from untrusted clients
+pub const SERVICE_DESERIALIZER_LIMITS: aser::DeserializerLimits = aser::DeserializerLimits {
+    max_depth: 32,
+    max_elements: 1 << 20,
+};
+
+/// Deserializes a message recieved by a service with [`SERVICE_DESERIALIZER_LIMITS`]
+pub fn deserialize_call<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<T, aser::AserError> {
+    aser::from_bytes_with_limits(data, SERVICE_DESERIALIZER_LIMITS)
+}
+
+/// Method id which every service answers with its [`ServiceInfo`]
+pub const SERVICE_INFO_METHOD_ID: u32 = u32::MAX;
+
+/// Describes which version of an rpc interface a service implements
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceInfo {
+    pub service_id: u64,
+    pub version: u32,
+    /// One more than the highest method id the service has
+    pub method_count: u32,
+}
+
+impl ServiceInfo {
+    /// Returns true if a client compiled against `client_info` can call every method it knows about on this service
+    /// 
+    /// Methods are only ever added to the end of an interface, so a newer version of the service is compatible with older clients.
+    pub fn is_compatible_with(&self, client_info: &ServiceInfo) -> bool {
+        self.service_id == client_info.service_id
+            && self.version >= client_info.version
+            && self.method_count >= client_info.method_count
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Error)]
 pub enum RpcError {
     #[error("Invalid rpc service id")]
     InvalidServiceId,
-    #[error("Invalid rpc method id")]
-    InvalidMethodId,
+    /// The service does not have the called method, which happens when the client was built against a newer version of the interface
+    #[error("Invalid rpc method id, service is version {version} with {method_count} methods")]
+    InvalidMethodId {
+        version: u32,
+        method_count: u32,
+    },
     #[error("Failed to deserialize rpc method arguments: {0}")]
     SerializationError(#[from] aser::AserError),
     #[error("A system error occured: {0}")]
+    SysErr(SysErr),
+    #[error("Message arena does not have enough space left for rpc message")]
+    ArenaOutOfSpace,
+    #[error("The receiver's event pool is full, the call should be retried later")]
+    ReceiverBusy,
+    #[error("The service stopped handling the call without responding")]
+    ServiceError,
+    #[error("The service is shutting down and is not accepting new calls")]
+    ServiceShuttingDown,
+    /// Only returned by loopback endpoints used for testing, which have no kernel to send capabilities with
+    #[error("Capabilities can't be sent over a loopback endpoint")]
+    LoopbackCapability,
+}
+
+impl From<SysErr> for RpcError {
+    fn from(error: SysErr) -> Self {
+        match error {
+            SysErr::EventPoolFull => RpcError::ReceiverBusy,
+            SysErr::PeerGone => RpcError::ServiceError,
+            error => RpcError::SysErr(error),
+        }
+    }
+}
+
+impl From<MessageArenaError> for RpcError {
+    fn from(error: MessageArenaError) -> Self {
+        match error {
+            MessageArenaError::OutOfSpace => RpcError::ArenaOutOfSpace,
+            MessageArenaError::SerializationError(error) => RpcError::SerializationError(error),
+        }
+    }
+}
+
+/// Why a service could not respond to a call
+#[derive(Debug, Clone, Error)]
+pub enum RespondError {
+    #[error("Failed to serialize rpc response: {0}")]
+    SerializationError(#[from] aser::AserError),
+    #[error("Message arena does not have enough space left for rpc response")]
+    ArenaOutOfSpace,
+    /// Sending the response failed, which happens when the caller exited before the response was sent
+    #[error("Failed to send rpc response: {0}")]
     SysErr(#[from] SysErr),
+    /// The caller is sent [`RpcError::LoopbackCapability`] instead
+    #[error("Rpc response holds capabilities, which can't be sent over a loopback endpoint")]
+    LoopbackCapability,
+}
+
+/// Logs that the response to a call could not be sent, and counts it in the metrics of the service which recieved the call
+///
+/// This can't use the log server, since the log server is an rpc service itself,
+/// so it is printed to the kernel debug log in the same format the log facade falls back to.
+pub(crate) fn report_respond_error(call: Option<&RpcCallMethod>, service_state: Option<&ServiceState>, error: &RespondError) {
+    if let Some(service_state) = service_state {
+        service_state.add_respond_failure();
+    }
+
+    match call {
+        Some(call) => sys::dprintln!(
+            "[WARN] arpc: failed to respond to call of method {} on service {}: {error}",
+            call.method_id,
+            call.service_id,
+        ),
+        None => sys::dprintln!("[WARN] arpc: failed to respond to call: {error}"),
+    }
 }
 
-pub fn respond_success<T: Serialize>(reply: Reply, data: T) {
-    match aser::to_bytes_count_cap::<Result<T, RpcError>, MessageVec<u8>>(&Ok(data)) {
-        // panic safety: response data should have non zero size
-        Ok(data) => {
-            // TODO: log error if error occurs
-            let _ = reply.reply(&data.message_buffer().unwrap());
+/// Logs that a message for a method which is not oneway was sent without a reply, so it was dropped
+///
+/// The method's return value would have nowhere to go, so the method is not run.
+#[doc(hidden)]
+pub fn warn_message_without_reply(service_id: u64, method_id: u32) {
+    sys::dprintln!(
+        "[WARN] arpc: dropped message for method {method_id} on service {service_id}, it was sent without a reply but the method is not oneway",
+    );
+}
+
+/// Sends `response` to the caller, an empty response is still sent as a zero sized message
+pub(crate) fn send_response(reply: Reply, response: &mut MessageVec<u8>) -> Result<(), RespondError> {
+    reply.reply(&response.try_message_buffer()?)?;
+
+    Ok(())
+}
+
+// responses encode enum variants by name, so the result and rpc error can still be decoded
+// by clients built against a different version of arpc
+
+/// Responds to the caller with the return value of the method
+///
+/// If `data` can't be serialized the caller is sent the serialization error instead,
+/// and the serialization error is still returned so the failure can be logged.
+pub fn respond_success<T: Serialize>(reply: Reply, data: T) -> Result<(), RespondError> {
+    match aser::to_bytes_count_cap_named::<Result<T, RpcError>, MessageVec<u8>>(&Ok(data)) {
+        Ok(mut response) => send_response(reply, &mut response),
+        Err(error) => {
+            respond_error(reply, RpcError::SerializationError(error.clone()))?;
+            Err(RespondError::SerializationError(error))
         },
-        Err(error) => respond_error(reply, RpcError::SerializationError(error)),
     }
 }
 
-pub fn respond_error(reply: Reply, error: RpcError) {
+/// Same as [`respond_success`], but the response is serialized into `arena` instead of a heap allocation
+pub fn respond_success_in<T: Serialize>(reply: Reply, data: T, arena: &MessageArena) -> Result<(), RespondError> {
+    match arena.serialize_named::<Result<T, RpcError>>(&Ok(data)) {
+        Ok(message_buffer) => {
+            reply.reply(&message_buffer)?;
+            Ok(())
+        },
+        Err(MessageArenaError::OutOfSpace) => {
+            respond_error(reply, RpcError::ArenaOutOfSpace)?;
+            Err(RespondError::ArenaOutOfSpace)
+        },
+        Err(MessageArenaError::SerializationError(error)) => {
+            respond_error(reply, RpcError::SerializationError(error.clone()))?;
+            Err(RespondError::SerializationError(error))
+        },
+    }
+}
+
+/// Same as [`respond_success`], but does nothing if there is no reply, which happens when the method was sent instead of called
+pub fn respond_success_opt<T: Serialize>(reply: Option<Reply>, data: T) -> Result<(), RespondError> {
+    match reply {
+        Some(reply) => respond_success(reply, data),
+        None => Ok(()),
+    }
+}
+
+/// Responds to the caller with an error
+///
+/// If even the error can't be serialized the reply is dropped, and the caller sees the service stop handling the call.
+pub fn respond_error(reply: Reply, error: RpcError) -> Result<(), RespondError> {
     let error: Result<(), RpcError> = Err(error);
-    let response_data: MessageVec<u8> = aser::to_bytes(&error, 0)
-        .expect("failed to serialize rpc error response");
+    let mut response = aser::to_bytes_named::<_, MessageVec<u8>>(&error, 0)?;
 
-    // panic safety: response data should have non zero size
-    // TODO: log error if error occurs
-    let _ = reply.reply(&response_data.message_buffer().unwrap());
+    send_response(reply, &mut response)
+}
+
+/// Same as [`respond_error`], but does nothing if there is no reply
+pub fn respond_error_opt(reply: Option<Reply>, error: RpcError) -> Result<(), RespondError> {
+    match reply {
+        Some(reply) => respond_error(reply, error),
+        None => Ok(()),
+    }
 }
 
 pub trait RpcClient {
     fn from_endpoint(endpoint: ClientRpcEndpoint) -> Self;
 }
 
+/// Lets services with no client type of their own, like [`ServiceMux`], be launched with [`launch_service`]
+impl RpcClient for ClientRpcEndpoint {
+    fn from_endpoint(endpoint: ClientRpcEndpoint) -> Self {
+        endpoint
+    }
+}
+
+/// Information about the caller of an rpc method, methods marked `#[arpc(with_context)]` take this as their first argument
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallContext {
+    /// Id of the caller's identity key, or None if the caller did not [send its identity](ClientRpcEndpoint::with_identity)
+    ///
+    /// This is attached by the kernel, so the caller can't pretend to be another process.
+    pub sender_key_id: Option<u64>,
+}
+
 pub trait RpcService {
     type Client: RpcClient;
 
-    fn call(&self, data: &[u8], reply: Reply);
+    /// Returns the ids of the services this answers calls for, which includes the services it extends
+    fn service_ids(&self) -> ServiceIds;
+
+    /// Handles an rpc message whose service and method ids have already been read into `call_data`
+    fn call_method(&self, call_data: &RpcCallMethod, data: &[u8], reply: ReplyGuard, context: CallContext);
+
+    /// Handles an rpc message, `reply` has no reply capability if the message was sent instead of called
+    fn call(&self, data: &[u8], reply: ReplyGuard, context: CallContext) {
+        match deserialize_call::<RpcCallMethod>(data) {
+            Ok(call_data) => self.call_method(&call_data, data, reply, context),
+            Err(error) => reply.reply_error(RpcError::SerializationError(error)),
+        }
+    }
 }
 
+/// Capabilities of a client endpoint which reaches its service through the kernel, this is what is sent when the endpoint is serialized
 #[derive(Serialize, Deserialize)]
-pub struct ClientRpcEndpoint {
+#[serde(rename = "ClientRpcEndpoint")]
+struct KernelClientEndpoint {
     channel: AsyncChannel,
     drop_check: DropCheck,
+    /// Notified when every server endpoint for this service has been dropped
+    server_drop_reciever: AsyncDropCheckReciever,
+}
+
+impl KernelClientEndpoint {
+    fn try_clone(&self) -> KResult<Self> {
+        let channel = cap_clone(CspaceTarget::Current, CspaceTarget::Current, self.channel.inner(), CLIENT_CHANNEL_FLAGS)?;
+        let drop_check = cap_clone(CspaceTarget::Current, CspaceTarget::Current, &self.drop_check, CapFlags::all())?;
+        let server_drop_reciever = cap_clone(
+            CspaceTarget::Current,
+            CspaceTarget::Current,
+            self.server_drop_reciever.inner(),
+            CapFlags::all(),
+        )?;
+
+        Ok(KernelClientEndpoint {
+            channel: channel.into(),
+            drop_check,
+            server_drop_reciever: server_drop_reciever.into(),
+        })
+    }
+}
+
+/// How a client endpoint sends messages to its service
+enum ClientTransport {
+    Kernel(KernelClientEndpoint),
+    /// Made by [`testing::loopback_endpoints`]
+    #[cfg(feature = "loopback")]
+    Loopback(testing::LoopbackClient),
+}
+
+pub struct ClientRpcEndpoint {
+    transport: ClientTransport,
+    /// Used by [`call_large_response`](Self::call_large_response), None until the first such call or while one is in progress
+    response_buffer: Mutex<Option<ResponseBuffer>>,
+    /// Flags passed when sending messages, set by [`with_identity`](Self::with_identity)
+    ///
+    /// This is not sent with the endpoint, since the process which recieves it has a different identity.
+    send_flags: ChannelSendFlags,
+}
+
+impl Serialize for ClientRpcEndpoint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.transport {
+            ClientTransport::Kernel(endpoint) => endpoint.serialize(serializer),
+            #[cfg(feature = "loopback")]
+            ClientTransport::Loopback(_) => Err(serde::ser::Error::custom("loopback endpoints have no capabilities and can't be sent")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ClientRpcEndpoint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let endpoint = KernelClientEndpoint::deserialize(deserializer)?;
+        Ok(ClientRpcEndpoint::new(ClientTransport::Kernel(endpoint)))
+    }
 }
 
 impl ClientRpcEndpoint {
+    fn new(transport: ClientTransport) -> Self {
+        ClientRpcEndpoint {
+            transport,
+            response_buffer: Mutex::default(),
+            send_flags: ChannelSendFlags::empty(),
+        }
+    }
+
+    /// Makes calls on this endpoint carry the identity of this process, which the service sees in its [`CallContext`]
+    ///
+    /// The identity is registered with [`Key::register_identity`](sys::Key::register_identity),
+    /// if this process has none calls are made without one.
+    pub fn with_identity(mut self) -> Self {
+        self.send_flags |= ChannelSendFlags::SEND_IDENTITY;
+        self
+    }
+
+    /// Creates another client endpoint for the same service, which can be used at the same time as this one
+    ///
+    /// Each call waits on its own reply, so calls made through different clones are never mixed up.
+    /// The clone shares this endpoint's drop check instead of getting its own, so the service sees its clients
+    /// disconnect and stops only once every clone has been dropped, including clones sent to other processes.
+    /// Calls from the clone carry this process's identity if this endpoint was made [`with_identity`](Self::with_identity).
+    pub fn try_clone(&self) -> KResult<Self> {
+        let transport = match &self.transport {
+            ClientTransport::Kernel(endpoint) => ClientTransport::Kernel(endpoint.try_clone()?),
+            #[cfg(feature = "loopback")]
+            ClientTransport::Loopback(client) => ClientTransport::Loopback(client.clone()),
+        };
+
+        Ok(ClientRpcEndpoint {
+            transport,
+            response_buffer: Mutex::default(),
+            send_flags: self.send_flags,
+        })
+    }
+
+    /// Makes a client of type `T` from a clone of this endpoint
+    ///
+    /// This is how clients for each service on an endpoint served by a [`ServiceMux`] are made.
+    pub fn try_client<T: RpcClient>(&self) -> KResult<T> {
+        Ok(T::from_endpoint(self.try_clone()?))
+    }
+
+    /// Makes a [`WeakClientRpcEndpoint`] for the same service, which does not keep the service running
+    ///
+    /// Fails with [`SysErr::InvlOp`] for loopback endpoints, which have no capabilities to weaken.
+    pub fn downgrade(&self) -> KResult<WeakClientRpcEndpoint> {
+        let endpoint = match &self.transport {
+            ClientTransport::Kernel(endpoint) => endpoint,
+            #[cfg(feature = "loopback")]
+            ClientTransport::Loopback(_) => return Err(SysErr::InvlOp),
+        };
+
+        let server_drop_reciever = cap_clone(
+            CspaceTarget::Current,
+            CspaceTarget::Current,
+            endpoint.server_drop_reciever.inner(),
+            CapFlags::all(),
+        )?;
+
+        Ok(WeakClientRpcEndpoint {
+            channel: Weak::new(endpoint.channel.inner())?,
+            drop_check: Weak::new(&endpoint.drop_check)?,
+            server_drop_reciever: server_drop_reciever.into(),
+        })
+    }
+
+    /// Resolves once the server endpoint has been dropped, which happens when the service stops or its process exits
+    ///
+    /// Only drops which happen after this future is first polled are seen
+    pub async fn server_dropped(&self) -> KResult<usize> {
+        match &self.transport {
+            ClientTransport::Kernel(endpoint) => endpoint.server_drop_reciever.handle_drop().await,
+            #[cfg(feature = "loopback")]
+            ClientTransport::Loopback(client) => client.server_dropped().await,
+        }
+    }
+
     pub async fn call<T: Serialize, U: for<'de> Deserialize<'de>>(&self, data: RpcCall<T>) -> Result<U, RpcError> {
-        let serialized_data: MessageVec<u8> = aser::to_bytes_count_cap(&data)?;
+        let endpoint = match &self.transport {
+            ClientTransport::Kernel(endpoint) => endpoint,
+            #[cfg(feature = "loopback")]
+            ClientTransport::Loopback(client) => {
+                let response = client.call(&testing::serialize_message(&data)?).await?;
+                return aser::from_bytes(&response)?;
+            },
+        };
+
+        let (serialized_data, cap_policies) = aser::to_bytes_with_policies::<_, MessageVec<u8>>(&data)?;
 
         // panic safety: the serialized data should have non zero length
-        let response = self.channel.call(serialized_data.message_buffer().unwrap()).await?;
+        let response = endpoint.channel.call(serialized_data.message_buffer().unwrap(), self.send_flags).await?;
+        // the service has recieved the capabilities, so moved ones can be destroyed
+        cap_policies.finish_transfer(serialized_data.as_slice());
 
         let response = unsafe {
             // safety: this is called as soon as await resolves
@@ -92,12 +462,219 @@ impl ClientRpcEndpoint {
 
         response
     }
+
+    /// Same as [`call`](Self::call), but the kernel copies the response into memory owned by this endpoint instead of the event pool
+    ///
+    /// This is meant for methods with large responses, which would otherwise have to fit in the event pool.
+    /// A response which does not fit is recieved through the event pool, and the buffer is grown for the next call.
+    pub async fn call_large_response<T: Serialize, U: for<'de> Deserialize<'de>>(&self, data: RpcCall<T>) -> Result<U, RpcError> {
+        let endpoint = match &self.transport {
+            ClientTransport::Kernel(endpoint) => endpoint,
+            // loopback responses are never in the event pool
+            #[cfg(feature = "loopback")]
+            ClientTransport::Loopback(_) => return self.call(data).await,
+        };
+
+        let (serialized_data, cap_policies) = aser::to_bytes_with_policies::<_, MessageVec<u8>>(&data)?;
+
+        // another call could be using the endpoint's buffer, in which case this call gets its own
+        let response_buffer = self.response_buffer.lock().take();
+        let mut response_buffer = match response_buffer {
+            Some(response_buffer) => response_buffer,
+            None => ResponseBuffer::new(DEFAULT_RESPONSE_BUFFER_SIZE)?,
+        };
+
+        let response = endpoint.channel.call_with_response(
+            // panic safety: the serialized data should have non zero length
+            serialized_data.message_buffer().unwrap(),
+            response_buffer.memory(),
+            response_buffer.size(),
+            self.send_flags,
+        ).await?;
+        cap_policies.finish_transfer(serialized_data.as_slice());
+
+        let response = match response {
+            CallResponse::ResponseMemory(response_size) => unsafe {
+                // safety: the call has finished, so the kernel is no longer writing to the buffer
+                aser::from_bytes(response_buffer.as_slice(response_size))?
+            },
+            CallResponse::Message(response) => {
+                let response_size = Size::from_bytes(unsafe { response.as_slice() }.len());
+                let response = unsafe {
+                    // safety: this is called as soon as await resolves
+                    aser::from_bytes(response.as_slice())?
+                };
+
+                if response_size > response_buffer.size() {
+                    // if a bigger buffer can't be made, keep the old one
+                    response_buffer = ResponseBuffer::new(response_size).unwrap_or(response_buffer);
+                }
+
+                response
+            },
+        };
+
+        let mut slot = self.response_buffer.lock();
+        if slot.is_none() {
+            *slot = Some(response_buffer);
+        }
+
+        response
+    }
+
+    /// Sends an rpc message for a oneway method, which waits for the service to recieve it but not for the method to run
+    ///
+    /// No reply capability is created, so the result only says whether the message was sent.
+    pub async fn send<T: Serialize>(&self, data: RpcCall<T>) -> Result<(), RpcError> {
+        let endpoint = match &self.transport {
+            ClientTransport::Kernel(endpoint) => endpoint,
+            #[cfg(feature = "loopback")]
+            ClientTransport::Loopback(client) => return client.send(&testing::serialize_message(&data)?).await,
+        };
+
+        let (serialized_data, cap_policies) = aser::to_bytes_with_policies::<_, MessageVec<u8>>(&data)?;
+
+        // panic safety: the serialized data should have non zero length
+        endpoint.channel.send(serialized_data.message_buffer().unwrap(), self.send_flags).await?;
+        cap_policies.finish_transfer(serialized_data.as_slice());
+
+        Ok(())
+    }
+
+    /// Sends an rpc message for a oneway method without waiting for the service to recieve it
+    ///
+    /// This never blocks, and fails if the server is not currently listening for messages
+    pub fn try_send<T: Serialize>(&self, data: RpcCall<T>) -> Result<(), RpcError> {
+        let endpoint = match &self.transport {
+            ClientTransport::Kernel(endpoint) => endpoint,
+            #[cfg(feature = "loopback")]
+            ClientTransport::Loopback(client) => return client.try_send(&testing::serialize_message(&data)?),
+        };
+
+        let (serialized_data, cap_policies) = aser::to_bytes_with_policies::<_, MessageVec<u8>>(&data)?;
+
+        // panic safety: the serialized data should have non zero length
+        endpoint.channel.try_send(&serialized_data.message_buffer().unwrap(), self.send_flags)?;
+        cap_policies.finish_transfer(serialized_data.as_slice());
+
+        Ok(())
+    }
+
+    /// Same as [`call`](Self::call), but the call arguments are serialized into `arena` instead of a heap allocation
+    pub async fn call_in<T: Serialize, U: for<'de> Deserialize<'de>>(&self, data: RpcCall<T>, arena: &MessageArena) -> Result<U, RpcError> {
+        let endpoint = match &self.transport {
+            ClientTransport::Kernel(endpoint) => endpoint,
+            // loopback messages are copied into the queue, so there is nothing to gain from the arena
+            #[cfg(feature = "loopback")]
+            ClientTransport::Loopback(_) => return self.call(data).await,
+        };
+
+        let message_buffer = arena.serialize(&data)?;
+
+        let response = endpoint.channel.call(message_buffer, self.send_flags).await?;
+
+        let response = unsafe {
+            // safety: this is called as soon as await resolves
+            aser::from_bytes(response.as_slice())?
+        };
+
+        response
+    }
+}
+
+/// A client endpoint which is not counted as a client of the service, made with [`ClientRpcEndpoint::downgrade`]
+///
+/// The service stops once every strong client endpoint is dropped, even if weak endpoints still exist.
+/// This is meant for things like the registry server, which should not keep services running forever.
+pub struct WeakClientRpcEndpoint {
+    channel: Weak<Channel>,
+    drop_check: Weak<DropCheck>,
+    server_drop_reciever: AsyncDropCheckReciever,
 }
 
+impl WeakClientRpcEndpoint {
+    /// Makes a client endpoint which can be used to call the service
+    ///
+    /// Fails with [`SysErr::InvlWeak`] if the service has stopped.
+    pub fn upgrade(&self) -> KResult<ClientRpcEndpoint> {
+        let channel = self.channel.upgrade()?.into_inner();
+        let drop_check = self.drop_check.upgrade()?.into_inner();
+        let server_drop_reciever = cap_clone(
+            CspaceTarget::Current,
+            CspaceTarget::Current,
+            self.server_drop_reciever.inner(),
+            CapFlags::all(),
+        )?;
+
+        Ok(ClientRpcEndpoint::new(ClientTransport::Kernel(KernelClientEndpoint {
+            channel: channel.into(),
+            drop_check,
+            server_drop_reciever: server_drop_reciever.into(),
+        })))
+    }
+
+    /// Same as [`ClientRpcEndpoint::server_dropped`]
+    pub fn server_dropped(&self) -> AsyncHandleDrop {
+        self.server_drop_reciever.handle_drop()
+    }
+}
+
+/// Capabilities of a server endpoint which recieves messages through the kernel, this is what is sent when the endpoint is serialized
 #[derive(Serialize, Deserialize)]
-pub struct ServerRpcEndpoint {
+#[serde(rename = "ServerRpcEndpoint")]
+struct KernelServerEndpoint {
     channel: AsyncChannel,
     drop_check_reciever: AsyncDropCheckReciever,
+    /// Lets clients know when the server goes away
+    server_drop_check: DropCheck,
+}
+
+/// How a server endpoint recieves messages from its clients
+enum ServerTransport {
+    Kernel(KernelServerEndpoint),
+    /// Made by [`testing::loopback_endpoints`]
+    #[cfg(feature = "loopback")]
+    Loopback(testing::LoopbackServer),
+}
+
+pub struct ServerRpcEndpoint {
+    transport: ServerTransport,
+    /// Set by [`with_recorder`](Self::with_recorder), this is not sent with the endpoint
+    recorder: Option<Recorder>,
+}
+
+impl Serialize for ServerRpcEndpoint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.transport {
+            ServerTransport::Kernel(endpoint) => endpoint.serialize(serializer),
+            #[cfg(feature = "loopback")]
+            ServerTransport::Loopback(_) => Err(serde::ser::Error::custom("loopback endpoints have no capabilities and can't be sent")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ServerRpcEndpoint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let endpoint = KernelServerEndpoint::deserialize(deserializer)?;
+        Ok(ServerRpcEndpoint::new(ServerTransport::Kernel(endpoint)))
+    }
+}
+
+impl ServerRpcEndpoint {
+    fn new(transport: ServerTransport) -> Self {
+        ServerRpcEndpoint {
+            transport,
+            recorder: None,
+        }
+    }
+
+    /// Records every message the service recieves on this endpoint, and every response it sends, to `sink`
+    ///
+    /// See the [`replay`] module for the format of the recording and how to replay it.
+    pub fn with_recorder(mut self, sink: impl MessageSink + 'static) -> Self {
+        self.recorder = Some(Recorder::new(sink));
+        self
+    }
 }
 
 /// Creates a client and server endpoint for rpc
@@ -107,20 +684,23 @@ pub fn make_endpoints() -> KResult<(ClientRpcEndpoint, ServerRpcEndpoint)> {
         CspaceTarget::Current,
         CspaceTarget::Current,
         &server_channel,
-        CapFlags::READ | CapFlags::PROD | CapFlags::UPGRADE,
+        CLIENT_CHANNEL_FLAGS,
     )?;
 
     let (drop_check, drop_check_reciever) = DropCheck::new(&this_context().allocator, 0)?;
+    let (server_drop_check, server_drop_reciever) = DropCheck::new(&this_context().allocator, 0)?;
 
-    let client_endpoint = ClientRpcEndpoint {
+    let client_endpoint = ClientRpcEndpoint::new(ClientTransport::Kernel(KernelClientEndpoint {
         channel: client_channel.into(),
         drop_check,
-    };
+        server_drop_reciever: server_drop_reciever.into(),
+    }));
 
-    let server_endpoint = ServerRpcEndpoint {
+    let server_endpoint = ServerRpcEndpoint::new(ServerTransport::Kernel(KernelServerEndpoint {
         channel: server_channel.into(),
         drop_check_reciever: drop_check_reciever.into(),
-    };
+        server_drop_check,
+    }));
 
     Ok((client_endpoint, server_endpoint))
 }
@@ -135,28 +715,152 @@ pub fn launch_service<T: RpcService + 'static>(service: T) -> KResult<T::Client>
     Ok(client)
 }
 
+/// Same as [`launch_service`], but also returns a handle which can be used to shut down the service
+pub fn launch_service_with_handle<T: RpcService + 'static>(service: T) -> KResult<(T::Client, ServiceHandle)> {
+    let (client_endpoint, server_endpoint) = make_endpoints()?;
+
+    let client = T::Client::from_endpoint(client_endpoint);
+
+    let (service_future, handle) = run_rpc_service_with_handle(server_endpoint, service);
+    asynca::spawn(service_future);
+
+    Ok((client, handle))
+}
+
+/// Handles rpc messages for `service` until every client endpoint is dropped
 pub async fn run_rpc_service<T: RpcService>(
     server_endpoint: ServerRpcEndpoint,
     service: T,
 ) {
-    let mut message_stream = server_endpoint.channel.recv_repeat();
-    let mut drop_future = server_endpoint.drop_check_reciever.handle_drop();
+    run_rpc_service_inner(server_endpoint, service, Rc::default()).await
+}
+
+/// Same as [`run_rpc_service`], but also returns a handle which can be used to shut down the service
+/// 
+/// The service does not run until the returned future is polled.
+pub fn run_rpc_service_with_handle<T: RpcService>(
+    server_endpoint: ServerRpcEndpoint,
+    service: T,
+) -> (impl Future<Output = ()>, ServiceHandle) {
+    let state = Rc::<ServiceState>::default();
+    let handle = ServiceHandle(state.clone());
+
+    (run_rpc_service_inner(server_endpoint, service, state), handle)
+}
+
+/// A message recieved by a service
+enum ServiceMessage {
+    Kernel(MessageRecievedEvent),
+    #[cfg(feature = "loopback")]
+    Loopback(testing::LoopbackMessage),
+}
+
+impl ServiceMessage {
+    /// # Safety
+    ///
+    /// Same as [`MessageRecievedEvent::as_slice`]
+    unsafe fn as_slice(&self) -> &[u8] {
+        match self {
+            ServiceMessage::Kernel(message) => unsafe { message.as_slice() },
+            #[cfg(feature = "loopback")]
+            ServiceMessage::Loopback(message) => &message.data,
+        }
+    }
+
+    fn has_reply(&self) -> bool {
+        match self {
+            ServiceMessage::Kernel(message) => message.reply.is_some(),
+            #[cfg(feature = "loopback")]
+            ServiceMessage::Loopback(message) => message.reply.is_some(),
+        }
+    }
+
+    fn take_reply(&mut self) -> Option<ReplySender> {
+        match self {
+            ServiceMessage::Kernel(message) => message.reply.take().map(ReplySender::Kernel),
+            #[cfg(feature = "loopback")]
+            ServiceMessage::Loopback(message) => message.reply.take().map(ReplySender::Loopback),
+        }
+    }
+
+    /// Loopback messages never have an identity, since it is attached by the kernel
+    fn sender_key_id(&self) -> Option<u64> {
+        match self {
+            ServiceMessage::Kernel(message) => message.sender_key_id,
+            #[cfg(feature = "loopback")]
+            ServiceMessage::Loopback(_) => None,
+        }
+    }
+
+    /// Responds with `error` if the message was called, otherwise does nothing
+    fn respond_error(&mut self, error: RpcError) -> Result<(), RespondError> {
+        match self.take_reply() {
+            Some(reply) => reply.respond_error(error),
+            None => Ok(()),
+        }
+    }
+}
+
+async fn run_rpc_service_inner<T: RpcService>(
+    server_endpoint: ServerRpcEndpoint,
+    service: T,
+    state: Rc<ServiceState>,
+) {
+    let recorder = server_endpoint.recorder.map(Rc::new);
+
+    match server_endpoint.transport {
+        ServerTransport::Kernel(endpoint) => {
+            let message_stream = endpoint.channel.recv_repeat().map(ServiceMessage::Kernel);
+            let drop_future = endpoint.drop_check_reciever.handle_drop();
+
+            serve_messages(message_stream, drop_future, service, state, recorder).await
+        },
+        #[cfg(feature = "loopback")]
+        ServerTransport::Loopback(mut server) => {
+            // the message stream ends once every client is dropped, so there is no drop check to wait for
+            let message_stream = server.messages().map(ServiceMessage::Loopback);
+
+            serve_messages(message_stream, futures::future::pending(), service, state, recorder).await
+        },
+    }
+}
+
+/// Handles each message from `message_stream` until it ends, `drop_future` resolves, or the service is shut down
+async fn serve_messages<T: RpcService>(
+    mut message_stream: impl FusedStream<Item = ServiceMessage> + Unpin,
+    mut drop_future: impl FusedFuture<Output = KResult<usize>> + Unpin,
+    service: T,
+    state: Rc<ServiceState>,
+    recorder: Option<Rc<Recorder>>,
+) {
+    let mut in_flight = InFlightReplies::new(state.clone());
 
     loop {
         select_biased! {
+            _ = state.shutdown_requested().fuse() => break,
             message = message_stream.next() => {
                 let Some(mut message) = message else {
                     break;
                 };
 
-                // ignore messages which don't have a reply (only handle call, not send)
-                let Some(reply) = message.reply.take() else {
-                    continue;
+                // safety: the event pool should not yet have been invalidated since we just recived the event
+                let data = unsafe { message.as_slice() };
+                let observer = recorder.as_ref().and_then(|recorder| {
+                    recorder.record_request(data, message.has_reply())
+                });
+                // only used to say which method a failed response was for, the service reads the call itself
+                let call = deserialize_call::<RpcCallMethod>(data).ok();
+
+                // messages which were sent instead of called have no reply
+                state.add_message(message.has_reply());
+                let reply = in_flight.track(message.take_reply(), observer, call);
+                let context = CallContext {
+                    sender_key_id: message.sender_key_id(),
                 };
 
                 // safety: the event pool should not yet have been invalidated since we just recived the event
                 unsafe {
-                    service.call(message.as_slice(), reply);
+                    service.call(message.as_slice(), reply, context);
                 }
             },
             result = drop_future => {
@@ -165,4 +869,160 @@ pub async fn run_rpc_service<T: RpcService>(
             },
         }
     }
-}
\ No newline at end of file
+
+    if state.is_shutting_down() {
+        // let handlers which are already running finish, but turn away any new callers
+        loop {
+            select_biased! {
+                _ = in_flight.all_finished().fuse() => break,
+                message = message_stream.next() => {
+                    let Some(mut message) = message else {
+                        break;
+                    };
+
+                    if let Err(error) = message.respond_error(RpcError::ServiceShuttingDown) {
+                        report_respond_error(None, Some(&state), &error);
+                    }
+                },
+            }
+        }
+
+        // respond to messages which were already queued when the handlers finished
+        while let Some(Some(mut message)) = message_stream.next().now_or_never() {
+            if let Err(error) = message.respond_error(RpcError::ServiceShuttingDown) {
+                report_respond_error(None, Some(&state), &error);
+            }
+        }
+    }
+
+    // tasks still handling calls may never get to respond now that the service is stopping
+    in_flight.drain();
+    state.set_stopped();
+}
+
+/// Tests of the message envelopes, these need the `std` feature since aurora_core's allocator only works on aurora
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn call_bytes<T: Serialize>(service_id: u64, method_id: u32, args: T) -> Vec<u8> {
+        aser::to_bytes_count_cap(&RpcCall { service_id, method_id, args }).unwrap()
+    }
+
+    /// Serializes a response the way [`respond_success`] and [`respond_error`] do
+    fn response_bytes<T: Serialize>(response: &Result<T, RpcError>) -> Vec<u8> {
+        aser::to_bytes_count_cap_named(response).unwrap()
+    }
+
+    fn rpc_errors() -> [RpcError; 9] {
+        [
+            RpcError::InvalidServiceId,
+            RpcError::InvalidMethodId { version: 3, method_count: 7 },
+            RpcError::SerializationError(aser::AserError::EndOfInput),
+            RpcError::SysErr(SysErr::InvlPerm),
+            RpcError::ArenaOutOfSpace,
+            RpcError::ReceiverBusy,
+            RpcError::ServiceError,
+            RpcError::ServiceShuttingDown,
+            RpcError::LoopbackCapability,
+        ]
+    }
+
+    #[test]
+    fn rpc_errors_round_trip() {
+        for error in rpc_errors() {
+            let response: Result<u32, RpcError> = Err(error);
+            let decoded: Result<u32, RpcError> = aser::from_bytes(&response_bytes(&response)).unwrap();
+
+            assert_eq!(format!("{decoded:?}"), format!("{response:?}"));
+        }
+    }
+
+    /// Vecs nested to any depth, used to build arguments which are too deep
+    #[derive(Serialize, Deserialize)]
+    struct Nested(Vec<Nested>);
+
+    fn nested(depth: usize) -> Nested {
+        (0..depth).fold(Nested(Vec::new()), |inner, _| Nested(Vec::from([inner])))
+    }
+
+    #[test]
+    fn deeply_nested_args_are_rejected() {
+        let bytes = call_bytes(1, 2, nested(4));
+        assert!(deserialize_call::<RpcCall<Nested>>(&bytes).is_ok());
+
+        let bytes = call_bytes(1, 2, nested(SERVICE_DESERIALIZER_LIMITS.max_depth));
+        assert!(matches!(deserialize_call::<RpcCall<Nested>>(&bytes), Err(aser::AserError::DepthLimitExceeded)));
+
+        // the arguments are still checked when they are skipped to read only the method
+        assert!(matches!(deserialize_call::<RpcCallMethod>(&bytes), Err(aser::AserError::DepthLimitExceeded)));
+    }
+
+    fn service_info() -> impl Strategy<Value = ServiceInfo> {
+        (0u64..4, any::<u32>(), any::<u32>()).prop_map(|(service_id, version, method_count)| ServiceInfo {
+            service_id,
+            version,
+            method_count,
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn call_method_is_read_without_args(
+            service_id: u64,
+            method_id: u32,
+            args: (u64, Option<i32>, Vec<u8>),
+            name in proptest::collection::vec(any::<char>(), 0..16).prop_map(String::from_iter),
+        ) {
+            let bytes = call_bytes(service_id, method_id, (&args, &name));
+
+            let method = deserialize_call::<RpcCallMethod>(&bytes).unwrap();
+            prop_assert_eq!(method.service_id, service_id);
+            prop_assert_eq!(method.method_id, method_id);
+
+            let call = deserialize_call::<RpcCall<((u64, Option<i32>, Vec<u8>), String)>>(&bytes).unwrap();
+            prop_assert_eq!(call.args, (args, name));
+        }
+
+        #[test]
+        fn responses_round_trip(value: (u64, bool, Vec<i16>), error_index in 0usize..9, is_ok: bool) {
+            let response = if is_ok {
+                Ok(value)
+            } else {
+                Err(rpc_errors().into_iter().nth(error_index).unwrap())
+            };
+
+            let decoded: Result<(u64, bool, Vec<i16>), RpcError> = aser::from_bytes(&response_bytes(&response)).unwrap();
+            prop_assert_eq!(format!("{decoded:?}"), format!("{response:?}"));
+        }
+
+        #[test]
+        fn arbitrary_calls_do_not_panic(bytes in proptest::collection::vec(any::<u8>(), 0..128)) {
+            let _ = deserialize_call::<RpcCallMethod>(&bytes);
+            let _ = deserialize_call::<RpcCall<(u64, String, Vec<u32>)>>(&bytes);
+            let _ = aser::from_bytes::<Result<ServiceInfo, RpcError>>(&bytes);
+        }
+
+        #[test]
+        fn compatibility_allows_older_clients(service in service_info(), client in service_info()) {
+            prop_assert!(service.is_compatible_with(&service));
+
+            let older_client = ServiceInfo {
+                version: client.version.min(service.version),
+                method_count: client.method_count.min(service.method_count),
+                ..service
+            };
+            prop_assert!(service.is_compatible_with(&older_client));
+
+            if service.is_compatible_with(&client) && client.is_compatible_with(&service) {
+                prop_assert_eq!(service, client);
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Serving several unrelated services on one endpoint
+//!
+//! Every call says which service it is for with its service id, so a [`ServiceMux`] can route calls to services
+//! which do not extend each other. Clients for each service are made from the same endpoint with
+//! [`ClientRpcEndpoint::try_client`](crate::ClientRpcEndpoint::try_client).
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::any::type_name;
+
+use crate::{RpcService, RpcCallMethod, RpcError, CallContext, ReplyGuard, ClientRpcEndpoint};
+
+/// Ids of the services an [`RpcService`] answers calls for, which includes the services it extends
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServiceIds(Vec<u64>);
+
+impl ServiceIds {
+    /// Adds `service_id`, this does nothing if it was already added
+    pub fn insert(&mut self, service_id: u64) {
+        if !self.contains(service_id) {
+            self.0.push(service_id);
+        }
+    }
+
+    pub fn contains(&self, service_id: u64) -> bool {
+        self.0.contains(&service_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+/// The part of [`RpcService`] the mux uses, which can be a trait object
+trait MuxedService {
+    fn call_method(&self, call_data: &RpcCallMethod, data: &[u8], reply: ReplyGuard, context: CallContext);
+}
+
+impl<T: RpcService> MuxedService for T {
+    fn call_method(&self, call_data: &RpcCallMethod, data: &[u8], reply: ReplyGuard, context: CallContext) {
+        RpcService::call_method(self, call_data, data, reply, context);
+    }
+}
+
+/// Serves several services on one endpoint, each call is routed to the service with the call's service id
+///
+/// A service also gets the calls for every service it extends,
+/// so 2 services which extend the same service can't be in the same mux.
+/// Calls with a service id no service has are responded to with [`RpcError::InvalidServiceId`].
+#[derive(Default)]
+pub struct ServiceMux {
+    services: Vec<(ServiceIds, Box<dyn MuxedService>)>,
+}
+
+impl ServiceMux {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `service` to the mux
+    ///
+    /// # Panics
+    ///
+    /// Panics if `service` or a service it extends has the same id as a service already in the mux
+    #[track_caller]
+    pub fn add<T: RpcService + 'static>(mut self, service: T) -> Self {
+        let service_ids = service.service_ids();
+
+        for (existing_ids, _) in self.services.iter() {
+            if let Some(duplicate_id) = service_ids.iter().find(|id| existing_ids.contains(*id)) {
+                panic!(
+                    "can't add {} to service mux, it answers calls for service id {duplicate_id} which another service in the mux already answers",
+                    type_name::<T>(),
+                );
+            }
+        }
+
+        self.services.push((service_ids, Box::new(service)));
+        self
+    }
+}
+
+impl RpcService for ServiceMux {
+    /// The mux has no client of its own, a client for each service is made from the endpoint
+    type Client = ClientRpcEndpoint;
+
+    fn service_ids(&self) -> ServiceIds {
+        let mut ids = ServiceIds::default();
+        for service_id in self.services.iter().flat_map(|(service_ids, _)| service_ids.iter()) {
+            ids.insert(service_id);
+        }
+
+        ids
+    }
+
+    fn call_method(&self, call_data: &RpcCallMethod, data: &[u8], reply: ReplyGuard, context: CallContext) {
+        let service = self.services.iter()
+            .find(|(service_ids, _)| service_ids.contains(call_data.service_id));
+
+        match service {
+            Some((_, service)) => service.call_method(call_data, data, reply, context),
+            None => reply.reply_error(RpcError::InvalidServiceId),
+        }
+    }
+}
\ No newline at end of file
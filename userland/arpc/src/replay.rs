@@ -0,0 +1,334 @@
+//! Recording the messages a service recieves and the responses it sends, and replaying them later
+//!
+//! A service is recorded by running it on an endpoint made with [`ServerRpcEndpoint::with_recorder`](crate::ServerRpcEndpoint::with_recorder).
+//! The recording can be replayed against a service implementation with [`drive_service`], without the original client,
+//! to check that it still responds the same way.
+//!
+//! # Recording format
+//!
+//! A recording is a sequence of records. Each record is a 34 byte header followed by the message data,
+//! the header fields are in this order and every integer is little endian:
+//! - kind (1 byte): 0 for a request the service recieved, 1 for a response it sent
+//! - expects response (1 byte): 1 if the request was called, 0 if it was sent and has no response
+//! - call index (8 bytes): counts up from 0 for each request, and a response has the index of its request
+//! - timestamp (8 bytes): monotonic time in nanoseconds when the record was made
+//! - capability count (8 bytes): number of capabilities in the message
+//! - data length (8 bytes)
+//!
+//! Message data starts with the capability table, which is the 8 byte capability count followed by an 8 byte id for each capability.
+//! Capability ids only mean something in the process which recorded them, so they are replaced with placeholders
+//! when replaying, and are not compared.
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::ops::Range;
+use core::task::{Poll, Waker};
+use core::time::Duration;
+
+use futures::future::poll_fn;
+use sys::{CapId, ClockId, time_get};
+use thiserror_no_std::Error;
+
+use crate::{RpcService, RpcCallMethod, CallContext, ReplyGuard, deserialize_call};
+use crate::reply::ResponseObserver;
+
+const RECORD_HEADER_SIZE: usize = 34;
+
+/// Size of the capability count and of each id in the capability table at the start of a message
+const CAP_TABLE_ENTRY_SIZE: usize = 8;
+
+/// Somewhere a recording is written to
+pub trait MessageSink {
+    /// Appends `data` to the end of the recording, each call appends 1 whole record
+    fn append(&mut self, data: &[u8]);
+}
+
+impl MessageSink for Vec<u8> {
+    fn append(&mut self, data: &[u8]) {
+        self.extend_from_slice(data);
+    }
+}
+
+/// Lets the recording be read while the service is still running
+impl<T: MessageSink + ?Sized> MessageSink for Rc<RefCell<T>> {
+    fn append(&mut self, data: &[u8]) {
+        self.borrow_mut().append(data);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Request,
+    Response,
+}
+
+impl RecordKind {
+    fn as_u8(self) -> u8 {
+        match self {
+            RecordKind::Request => 0,
+            RecordKind::Response => 1,
+        }
+    }
+
+    fn from_u8(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(RecordKind::Request),
+            1 => Some(RecordKind::Response),
+            _ => None,
+        }
+    }
+}
+
+/// A record parsed from a recording by [`parse_recording`]
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub kind: RecordKind,
+    pub expects_response: bool,
+    pub call_index: u64,
+    /// Time since boot when the record was made
+    pub timestamp: Duration,
+    pub cap_count: usize,
+    pub data: &'a [u8],
+}
+
+impl Record<'_> {
+    /// Range of `data` which holds capability ids
+    fn cap_id_range(&self) -> Range<usize> {
+        let end = self.cap_count.saturating_add(1).saturating_mul(CAP_TABLE_ENTRY_SIZE);
+        CAP_TABLE_ENTRY_SIZE.min(self.data.len())..end.min(self.data.len())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ReplayError {
+    #[error("Recording is corrupted at byte {offset}")]
+    InvalidRecording {
+        offset: usize,
+    },
+    /// The replayed response first differs from the recorded one at byte `offset` of the response
+    #[error("Call {call_index} to method {method_id:?} responded differently at byte {offset}")]
+    Divergence {
+        call_index: u64,
+        /// None if the recorded request could not be parsed
+        method_id: Option<u32>,
+        offset: usize,
+    },
+}
+
+/// Parses every record in `recording`
+pub fn parse_recording(recording: &[u8]) -> Result<Vec<Record<'_>>, ReplayError> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    while offset < recording.len() {
+        let invalid = ReplayError::InvalidRecording { offset };
+
+        let header = recording.get(offset..(offset + RECORD_HEADER_SIZE))
+            .ok_or(invalid.clone())?;
+        // panic safety: every field is inside of the header
+        let read_u64 = |start: usize| u64::from_le_bytes(header[start..(start + 8)].try_into().unwrap());
+
+        let kind = RecordKind::from_u8(header[0]).ok_or(invalid.clone())?;
+        let data_len = usize::try_from(read_u64(26)).map_err(|_| invalid.clone())?;
+
+        let data_start = offset + RECORD_HEADER_SIZE;
+        let data = data_start.checked_add(data_len)
+            .and_then(|data_end| recording.get(data_start..data_end))
+            .ok_or(invalid.clone())?;
+
+        records.push(Record {
+            kind,
+            expects_response: header[1] != 0,
+            call_index: read_u64(2),
+            timestamp: Duration::from_nanos(read_u64(10)),
+            cap_count: usize::try_from(read_u64(18)).map_err(|_| invalid)?,
+            data,
+        });
+
+        offset = data_start + data_len;
+    }
+
+    Ok(records)
+}
+
+/// Returns the number of capabilities in the capability table at the start of `message`
+pub(crate) fn message_cap_count(message: &[u8]) -> usize {
+    message.get(..CAP_TABLE_ENTRY_SIZE)
+        // panic safety: the slice is 8 bytes long
+        .map(|count| u64::from_le_bytes(count.try_into().unwrap()) as usize)
+        .unwrap_or(0)
+}
+
+/// Writes the messages a service recieves and its responses to a [`MessageSink`]
+pub(crate) struct Recorder {
+    sink: RefCell<Box<dyn MessageSink>>,
+    next_call_index: Cell<u64>,
+}
+
+impl Recorder {
+    pub(crate) fn new(sink: impl MessageSink + 'static) -> Self {
+        Recorder {
+            sink: RefCell::new(Box::new(sink)),
+            next_call_index: Cell::new(0),
+        }
+    }
+
+    fn write_record(&self, kind: RecordKind, expects_response: bool, call_index: u64, data: &[u8]) {
+        // a missing timestamp does not make the rest of the recording any less useful
+        let timestamp = time_get(ClockId::Monotonic)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + data.len());
+        record.push(kind.as_u8());
+        record.push(expects_response as u8);
+        record.extend_from_slice(&call_index.to_le_bytes());
+        record.extend_from_slice(&timestamp.to_le_bytes());
+        record.extend_from_slice(&(message_cap_count(data) as u64).to_le_bytes());
+        record.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        record.extend_from_slice(data);
+
+        self.sink.borrow_mut().append(&record);
+    }
+
+    /// Records a message recieved by the service
+    ///
+    /// Returns an observer which records the response, if the message expects one
+    pub(crate) fn record_request(self: &Rc<Self>, data: &[u8], expects_response: bool) -> Option<Box<dyn ResponseObserver>> {
+        let call_index = self.next_call_index.get();
+        self.next_call_index.set(call_index + 1);
+
+        self.write_record(RecordKind::Request, expects_response, call_index, data);
+
+        expects_response.then(|| Box::new(RecordResponse {
+            recorder: self.clone(),
+            call_index,
+        }) as Box<dyn ResponseObserver>)
+    }
+}
+
+struct RecordResponse {
+    recorder: Rc<Recorder>,
+    call_index: u64,
+}
+
+impl ResponseObserver for RecordResponse {
+    fn observe(&self, response: &[u8]) {
+        self.recorder.write_record(RecordKind::Response, false, self.call_index, response);
+    }
+}
+
+/// Holds the response to a replayed call
+#[derive(Default)]
+struct CapturedResponse {
+    response: RefCell<Option<Vec<u8>>>,
+    /// Woken once the response arrives
+    waker: Cell<Option<Waker>>,
+}
+
+impl ResponseObserver for Rc<CapturedResponse> {
+    fn observe(&self, response: &[u8]) {
+        *self.response.borrow_mut() = Some(response.to_vec());
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl CapturedResponse {
+    /// Waits for the response, which always comes eventually since the reply guard responds with an error when dropped
+    async fn wait(&self) -> Vec<u8> {
+        poll_fn(|cx| {
+            match self.response.borrow_mut().take() {
+                Some(response) => Poll::Ready(response),
+                None => {
+                    self.waker.set(Some(cx.waker().clone()));
+                    Poll::Pending
+                },
+            }
+        }).await
+    }
+}
+
+/// Replaces every capability id in the capability table of `message` with a placeholder
+///
+/// The placeholder has the same type and permissions, but a base id which is never given out, so using it fails with an invalid id error.
+fn replace_capabilities(message: &mut [u8], cap_count: usize) {
+    for index in 0..cap_count {
+        let start = (index + 1) * CAP_TABLE_ENTRY_SIZE;
+        let Some(entry) = message.get_mut(start..(start + CAP_TABLE_ENTRY_SIZE)) else {
+            return;
+        };
+
+        // panic safety: the entry is 8 bytes long
+        let cap_id = u64::from_le_bytes(entry[..].try_into().unwrap()) as usize;
+        let Some(cap_id) = CapId::try_from(cap_id).filter(|cap_id| !cap_id.is_null()) else {
+            continue;
+        };
+
+        let placeholder = CapId::new(cap_id.cap_type(), cap_id.flags(), cap_id.is_weak(), CapId::MAX_BASE_ID);
+        entry.copy_from_slice(&(usize::from(placeholder) as u64).to_le_bytes());
+    }
+}
+
+/// Returns the offset of the first byte where `replayed` differs from the `recorded` response, ignoring capability ids
+fn first_difference(recorded: &Record, replayed: &[u8]) -> Option<usize> {
+    let cap_ids = recorded.cap_id_range();
+    let compare_len = recorded.data.len().min(replayed.len());
+
+    (0..compare_len)
+        .filter(|offset| !cap_ids.contains(offset))
+        .find(|&offset| recorded.data[offset] != replayed[offset])
+        .or((recorded.data.len() != replayed.len()).then_some(compare_len))
+}
+
+/// Replays the requests in `recording` against `service`, and checks that it responds the same way it did when recorded
+///
+/// Each request is passed to [`RpcService::call`] with a reply guard which captures the response,
+/// and the next request is not sent until the response arrives. So async methods which ran concurrently
+/// when recorded run one at a time when replayed. Calls are replayed without a caller identity,
+/// and capabilities in requests are replaced with placeholder ids, see the [module docs](self).
+/// Requests whose response was not recorded are replayed, but their response is not checked.
+///
+/// # Returns
+///
+/// The number of requests replayed, or the first response which differs from the recording
+pub async fn drive_service<T: RpcService>(service: &T, recording: &[u8]) -> Result<usize, ReplayError> {
+    let records = parse_recording(recording)?;
+
+    let mut replayed_count = 0;
+    for request in records.iter().filter(|record| record.kind == RecordKind::Request) {
+        let mut data = request.data.to_vec();
+        replace_capabilities(&mut data, request.cap_count);
+        replayed_count += 1;
+
+        if !request.expects_response {
+            service.call(&data, ReplyGuard::new(None), CallContext::default());
+            continue;
+        }
+
+        let captured = Rc::<CapturedResponse>::default();
+        let reply = ReplyGuard::with_observer(None, Some(Box::new(captured.clone())));
+        service.call(&data, reply, CallContext::default());
+        let response = captured.wait().await;
+
+        let recorded_response = records.iter().find(|record| {
+            record.kind == RecordKind::Response && record.call_index == request.call_index
+        });
+        let Some(recorded_response) = recorded_response else {
+            continue;
+        };
+
+        if let Some(offset) = first_difference(recorded_response, &response) {
+            return Err(ReplayError::Divergence {
+                call_index: request.call_index,
+                method_id: deserialize_call::<RpcCallMethod>(&data).ok().map(|call| call.method_id),
+                offset,
+            });
+        }
+    }
+
+    Ok(replayed_count)
+}
\ No newline at end of file
@@ -0,0 +1,325 @@
+use alloc::boxed::Box;
+use alloc::rc::{Rc, Weak};
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+use core::task::{Poll, Waker};
+
+use futures::future::poll_fn;
+
+use aser::AserError;
+use serde::Serialize;
+use sys::Reply;
+use aurora_core::collections::{MessageArena, MessageVec};
+
+use crate::{RpcCallMethod, RpcError, RespondError, respond_success_in, send_response, report_respond_error};
+use crate::handle::ServiceState;
+#[cfg(feature = "loopback")]
+use crate::testing::LoopbackReply;
+
+/// Sees a copy of every response sent through a [`ReplyGuard`], this is how services are recorded and replayed
+pub(crate) trait ResponseObserver {
+    fn observe(&self, response: &[u8]);
+}
+
+/// How the response to a call gets back to the caller
+pub(crate) enum ReplySender {
+    Kernel(Reply),
+    #[cfg(feature = "loopback")]
+    Loopback(LoopbackReply),
+}
+
+impl ReplySender {
+    /// Serializes `response` into the buffer `reply` sends from, responses with no reply are only observed
+    fn serialize<T: Serialize>(reply: Option<&Self>, response: &Result<T, RpcError>) -> Result<ResponseBuf, AserError> {
+        match reply {
+            #[cfg(feature = "loopback")]
+            Some(ReplySender::Loopback(_)) => aser::to_bytes_count_cap_named(response).map(ResponseBuf::Heap),
+            _ => aser::to_bytes_count_cap_named(response).map(ResponseBuf::Message),
+        }
+    }
+
+    fn send(self, response: &mut ResponseBuf) -> Result<(), RespondError> {
+        match (self, response) {
+            (ReplySender::Kernel(reply), ResponseBuf::Message(response)) => send_response(reply, response),
+            #[cfg(feature = "loopback")]
+            (ReplySender::Loopback(reply), response) => reply.send(response.as_slice()),
+            #[cfg(feature = "loopback")]
+            (ReplySender::Kernel(_), ResponseBuf::Heap(_)) => unreachable!("kernel responses are serialized into a message buffer"),
+        }
+    }
+
+    /// Responds to the caller with an error
+    pub(crate) fn respond_error(self, error: RpcError) -> Result<(), RespondError> {
+        let error: Result<(), RpcError> = Err(error);
+        let mut response = Self::serialize(Some(&self), &error)?;
+
+        self.send(&mut response)
+    }
+}
+
+/// A serialized response
+enum ResponseBuf {
+    Message(MessageVec<u8>),
+    /// Loopback replies copy the response, so it does not need to be in a message buffer
+    #[cfg(feature = "loopback")]
+    Heap(Vec<u8>),
+}
+
+impl ResponseBuf {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            ResponseBuf::Message(response) => response.as_slice(),
+            #[cfg(feature = "loopback")]
+            ResponseBuf::Heap(response) => response,
+        }
+    }
+}
+
+/// Where the response to a call goes
+struct ReplyTarget {
+    /// None when the call is being replayed, then the response only goes to the observer
+    reply: Option<ReplySender>,
+    observer: Option<Box<dyn ResponseObserver>>,
+}
+
+impl ReplyTarget {
+    fn send(self, response: &mut ResponseBuf) -> Result<(), RespondError> {
+        if let Some(observer) = &self.observer {
+            observer.observe(response.as_slice());
+        }
+
+        match self.reply {
+            Some(reply) => reply.send(response),
+            None => Ok(()),
+        }
+    }
+
+    fn respond_success<T: Serialize>(self, data: T) -> Result<(), RespondError> {
+        match ReplySender::serialize(self.reply.as_ref(), &Ok(data)) {
+            Ok(mut response) => self.send(&mut response),
+            Err(error) => {
+                self.respond_error(RpcError::SerializationError(error.clone()))?;
+                Err(RespondError::SerializationError(error))
+            },
+        }
+    }
+
+    fn respond_success_in<T: Serialize>(self, data: T, arena: &MessageArena) -> Result<(), RespondError> {
+        match self {
+            ReplyTarget { reply: Some(ReplySender::Kernel(reply)), observer: None } => respond_success_in(reply, data, arena),
+            // the observer and loopback replies need the response bytes, which are only easy to get from a heap allocation
+            target => target.respond_success(data),
+        }
+    }
+
+    fn respond_error(self, error: RpcError) -> Result<(), RespondError> {
+        let error: Result<(), RpcError> = Err(error);
+        let mut response = ReplySender::serialize(self.reply.as_ref(), &error)?;
+
+        self.send(&mut response)
+    }
+}
+
+/// A call which has not been responded to yet
+pub(crate) struct PendingReply {
+    target: ReplyTarget,
+    /// Which method was called, this is None if the call could not be parsed
+    call: Option<RpcCallMethod>,
+    /// The service which recieved the call, responses which can't be sent are counted in its metrics
+    service_state: Option<Rc<ServiceState>>,
+}
+
+impl PendingReply {
+    /// Sends the response with `respond`, and logs it if it fails
+    fn respond(self, respond: impl FnOnce(ReplyTarget) -> Result<(), RespondError>) {
+        if let Err(error) = respond(self.target) {
+            report_respond_error(self.call.as_ref(), self.service_state.as_deref(), &error);
+        }
+    }
+}
+
+type ReplySlot = RefCell<Option<PendingReply>>;
+
+/// Holds the reply capability for an rpc message until the method responds
+/// 
+/// If the guard is dropped without a response having been sent, the caller is sent [`RpcError::ServiceError`].
+/// This covers methods which return early, and async methods whose task is aborted before it completes,
+/// so the caller gets an error instead of waiting forever.
+/// 
+/// Messages which were sent instead of called have no reply, in that case responding does nothing.
+/// Responses which can't be sent, like when the caller has exited, are logged
+/// and counted in the service's [`ServiceMetrics`](crate::ServiceMetrics).
+pub struct ReplyGuard {
+    slot: Option<Rc<ReplySlot>>,
+    /// Lets the service which handed out this guard know when the call is finished
+    _in_flight: Option<InFlightToken>,
+}
+
+impl ReplyGuard {
+    /// Creates a guard for `reply` which is not tracked by any running service
+    pub fn new(reply: Option<Reply>) -> Self {
+        Self::with_observer(reply.map(ReplySender::Kernel), None)
+    }
+
+    /// Creates a guard whose response is also passed to `observer`
+    /// 
+    /// If `reply` is None but there is an observer, the guard still waits for a response which only the observer sees.
+    pub(crate) fn with_observer(reply: Option<ReplySender>, observer: Option<Box<dyn ResponseObserver>>) -> Self {
+        let pending = match (reply, observer) {
+            (None, None) => None,
+            (reply, observer) => Some(PendingReply {
+                target: ReplyTarget { reply, observer },
+                call: None,
+                service_state: None,
+            }),
+        };
+
+        ReplyGuard {
+            slot: pending.map(|pending| Rc::new(RefCell::new(Some(pending)))),
+            _in_flight: None,
+        }
+    }
+
+    /// Returns true if the caller is still waiting for a response
+    pub fn is_pending(&self) -> bool {
+        match &self.slot {
+            Some(slot) => slot.borrow().is_some(),
+            None => false,
+        }
+    }
+
+    /// Takes the reply out of the guard, returns none if there is no reply or the service already responded to it
+    fn take(&mut self) -> Option<PendingReply> {
+        self.slot.take()?.borrow_mut().take()
+    }
+
+    /// Responds to the caller with the return value of the method
+    pub fn reply<T: Serialize>(mut self, data: T) {
+        if let Some(pending) = self.take() {
+            pending.respond(|target| target.respond_success(data));
+        }
+    }
+
+    /// Same as [`reply`](Self::reply), but the response is serialized into `arena` instead of a heap allocation
+    pub fn reply_in<T: Serialize>(mut self, data: T, arena: &MessageArena) {
+        if let Some(pending) = self.take() {
+            pending.respond(|target| target.respond_success_in(data, arena));
+        }
+    }
+
+    /// Responds to the caller with an error
+    pub fn reply_error(mut self, error: RpcError) {
+        if let Some(pending) = self.take() {
+            pending.respond(|target| target.respond_error(error));
+        }
+    }
+}
+
+impl Drop for ReplyGuard {
+    fn drop(&mut self) {
+        if let Some(pending) = self.take() {
+            pending.respond(|target| target.respond_error(RpcError::ServiceError));
+        }
+    }
+}
+
+#[derive(Default)]
+struct InFlightCount {
+    count: Cell<usize>,
+    /// Woken when the count drops to 0
+    waker: Cell<Option<Waker>>,
+}
+
+/// Counts as 1 in flight call for as long as it is alive
+struct InFlightToken(Rc<InFlightCount>);
+
+impl InFlightToken {
+    fn new(in_flight: Rc<InFlightCount>) -> Self {
+        in_flight.count.set(in_flight.count.get() + 1);
+        InFlightToken(in_flight)
+    }
+}
+
+impl Drop for InFlightToken {
+    fn drop(&mut self) {
+        let count = self.0.count.get() - 1;
+        self.0.count.set(count);
+
+        if count == 0 {
+            if let Some(waker) = self.0.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Every rpc message a service has recieved but not yet finished handling
+pub(crate) struct InFlightReplies {
+    /// Replies which may still be waiting for a response
+    replies: Vec<Weak<ReplySlot>>,
+    count: Rc<InFlightCount>,
+    service_state: Rc<ServiceState>,
+}
+
+impl InFlightReplies {
+    pub(crate) fn new(service_state: Rc<ServiceState>) -> Self {
+        InFlightReplies {
+            replies: Vec::new(),
+            count: Rc::default(),
+            service_state,
+        }
+    }
+
+    /// Creates a guard for `reply` which will be responded to by [`drain`](Self::drain) if it is still pending
+    /// 
+    /// The message counts as in flight until the guard is dropped, even if it has no reply.
+    /// `call` is the method being called, which is logged if the response can't be sent.
+    pub(crate) fn track(
+        &mut self,
+        reply: Option<ReplySender>,
+        observer: Option<Box<dyn ResponseObserver>>,
+        call: Option<RpcCallMethod>,
+    ) -> ReplyGuard {
+        let mut guard = ReplyGuard::with_observer(reply, observer);
+        guard._in_flight = Some(InFlightToken::new(self.count.clone()));
+
+        if let Some(slot) = &guard.slot {
+            if let Some(pending) = slot.borrow_mut().as_mut() {
+                pending.call = call;
+                pending.service_state = Some(self.service_state.clone());
+            }
+
+            // forget about guards which have already been dropped
+            self.replies.retain(|slot| slot.strong_count() > 0);
+            self.replies.push(Rc::downgrade(slot));
+        }
+
+        guard
+    }
+
+    /// Returns a future which resolves once every guard handed out by [`track`](Self::track) has been dropped
+    pub(crate) fn all_finished(&self) -> impl Future<Output = ()> {
+        let in_flight = self.count.clone();
+
+        poll_fn(move |cx| {
+            if in_flight.count.get() == 0 {
+                Poll::Ready(())
+            } else {
+                in_flight.waker.set(Some(cx.waker().clone()));
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Responds with [`RpcError::ServiceError`] to every call which is still waiting for a response
+    /// 
+    /// Guards for these calls can still be used afterwards, but responding with them does nothing.
+    pub(crate) fn drain(&mut self) {
+        for slot in self.replies.drain(..) {
+            if let Some(pending) = slot.upgrade().and_then(|slot| slot.borrow_mut().take()) {
+                pending.respond(|target| target.respond_error(RpcError::ServiceError));
+            }
+        }
+    }
+}
\ No newline at end of file
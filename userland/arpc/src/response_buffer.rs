@@ -0,0 +1,90 @@
+use sys::{Memory, CapFlags, CspaceTarget, KResult, SysErr, cap_clone, MemoryMappingOptions};
+use aurora_core::addr_space;
+use aurora_core::allocator::addr_space::{MapMemoryArgs, MapMemoryResult, AddrSpaceError};
+use bit_utils::Size;
+
+/// Size of the response buffer a client endpoint starts with
+pub const DEFAULT_RESPONSE_BUFFER_SIZE: Size = Size::from_pages(16);
+
+/// Memory which the kernel copies the reply of a call into directly, instead of going through the event pool
+///
+/// The memory is mapped read only in this process, the kernel writes to it through a separate capability.
+#[derive(Debug)]
+pub struct ResponseBuffer {
+    /// Writable capability passed to the kernel with each call
+    memory: Memory,
+    address: usize,
+    size: Size,
+}
+
+impl ResponseBuffer {
+    /// Creates a response buffer that can hold at least `size` bytes
+    pub fn new(size: Size) -> KResult<Self> {
+        let mut addr_space = addr_space();
+
+        let MapMemoryResult {
+            address,
+            size,
+            memory,
+        } = addr_space.map_memory(MapMemoryArgs {
+            size: Some(size),
+            options: MemoryMappingOptions {
+                read: true,
+                write: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        }).map_err(|error| match error {
+            AddrSpaceError::MemorySyscallError(error) => error,
+            _ => SysErr::OutOfMem,
+        })?;
+
+        // panic safety: map_memory always creates memory for a non zero size mapping
+        let memory = match cap_clone(CspaceTarget::Current, CspaceTarget::Current, memory.unwrap(), CapFlags::READ | CapFlags::WRITE) {
+            Ok(memory) => memory,
+            Err(error) => {
+                // safety: nothing has been given a reference to this mapping yet
+                unsafe {
+                    let _ = addr_space.unmap_memory(address);
+                }
+                return Err(error);
+            },
+        };
+
+        Ok(ResponseBuffer {
+            memory,
+            address,
+            size,
+        })
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Returns the first `len` bytes of the buffer
+    ///
+    /// # Safety
+    ///
+    /// No call using this buffer may be in progress, otherwise the kernel could write to the buffer while it is borrowed
+    pub unsafe fn as_slice(&self, len: Size) -> &[u8] {
+        assert!(len <= self.size, "response size is larger than response buffer");
+
+        unsafe {
+            core::slice::from_raw_parts(self.address as *const u8, len.bytes())
+        }
+    }
+}
+
+impl Drop for ResponseBuffer {
+    fn drop(&mut self) {
+        // safety: the slices returned by as_slice borrow self, so none are left
+        unsafe {
+            let _ = addr_space().unmap_memory(self.address);
+        }
+    }
+}
\ No newline at end of file
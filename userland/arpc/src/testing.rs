@@ -0,0 +1,325 @@
+//! Endpoints for testing services without kernel channels, enabled with the `loopback` feature
+//!
+//! [`loopback_endpoints`] connects a client and server endpoint with a queue in this process.
+//! Calls are serialized and dispatched the same way they are over a kernel channel,
+//! so argument encoding and the service's method dispatch are still tested.
+//!
+//! Capabilities can't be sent without the kernel, so a call or response which holds one
+//! fails with [`RpcError::LoopbackCapability`], and a loopback endpoint can't be serialized at all.
+//! Loopback endpoints also differ from kernel endpoints in a few other ways:
+//! - calls never carry the caller's identity, even from an endpoint made [`with_identity`](ClientRpcEndpoint::with_identity)
+//! - [`ClientRpcEndpoint::downgrade`] fails with [`SysErr::InvlOp`]
+//! - the service stops once every client endpoint is dropped and the messages already sent have been recieved
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::task::{Context, Poll, Waker};
+
+use aser::AserError;
+use futures::future::poll_fn;
+use futures::stream::{self, FusedStream, StreamExt};
+use asynca::channel::{Sender, Receiver, TrySendError, mpsc};
+use serde::Serialize;
+use sys::{KResult, SysErr, DEFAULT_CHANNEL_SENDER_LIMIT};
+
+use crate::{ClientRpcEndpoint, ClientTransport, ServerRpcEndpoint, ServerTransport, RpcError, RespondError};
+use crate::replay::message_cap_count;
+
+/// Creates a client and server endpoint which are connected through this process instead of the kernel
+///
+/// Like a kernel channel, at most [`DEFAULT_CHANNEL_SENDER_LIMIT`] messages can be waiting for the service,
+/// and sending more waits for the service to recieve some.
+pub fn loopback_endpoints() -> (ClientRpcEndpoint, ServerRpcEndpoint) {
+    let (sender, reciever) = mpsc(DEFAULT_CHANNEL_SENDER_LIMIT);
+    let server_state = Rc::<ServerState>::default();
+
+    let client = LoopbackClient {
+        sender,
+        server_state: server_state.clone(),
+    };
+
+    let server = LoopbackServer {
+        reciever,
+        server_state,
+    };
+
+    (
+        ClientRpcEndpoint::new(ClientTransport::Loopback(client)),
+        ServerRpcEndpoint::new(ServerTransport::Loopback(server)),
+    )
+}
+
+/// Serializes a message sent over a loopback endpoint
+///
+/// The message is copied into the queue, so it is serialized into a heap allocation instead of a message buffer.
+/// This also keeps loopback endpoints working on the host, where there are no message buffers.
+pub(crate) fn serialize_message<T: Serialize>(data: &T) -> Result<Vec<u8>, AserError> {
+    let (message, _cap_policies) = aser::to_bytes_with_policies(data)?;
+    Ok(message)
+}
+
+/// Fails if `message` has capabilities, since they can only be sent through the kernel
+fn check_no_capabilities(message: &[u8]) -> Result<(), RpcError> {
+    if message_cap_count(message) == 0 {
+        Ok(())
+    } else {
+        Err(RpcError::LoopbackCapability)
+    }
+}
+
+/// Whether the server endpoint still exists, shared by every client endpoint
+#[derive(Default)]
+struct ServerState {
+    dropped: Cell<bool>,
+    /// Clients waiting for the server endpoint to be dropped
+    drop_wakers: RefCell<Vec<Waker>>,
+}
+
+/// A message sent over a loopback endpoint
+pub(crate) struct LoopbackMessage {
+    pub(crate) data: Vec<u8>,
+    /// None if the message was sent instead of called
+    pub(crate) reply: Option<LoopbackReply>,
+}
+
+/// Holds the response to a loopback call until the caller takes it
+#[derive(Default)]
+struct ResponseSlot {
+    response: RefCell<Option<Vec<u8>>>,
+    /// Set once the reply is dropped, no response can arrive after this
+    closed: Cell<bool>,
+    /// Woken when the reply is dropped
+    waker: Cell<Option<Waker>>,
+}
+
+impl ResponseSlot {
+    /// Waits for the reply to be dropped, and returns the response if there was one
+    async fn wait(&self) -> Result<Vec<u8>, RpcError> {
+        poll_fn(|cx| {
+            if let Some(response) = self.response.borrow_mut().take() {
+                Poll::Ready(Ok(response))
+            } else if self.closed.get() {
+                // same as a kernel reply which is destroyed without a response
+                Poll::Ready(Err(RpcError::ServiceError))
+            } else {
+                self.waker.set(Some(cx.waker().clone()));
+                Poll::Pending
+            }
+        }).await
+    }
+}
+
+/// Sends the response to a loopback call, dropping it without responding gives the caller [`RpcError::ServiceError`]
+pub(crate) struct LoopbackReply(Rc<ResponseSlot>);
+
+impl LoopbackReply {
+    pub(crate) fn send(self, response: &[u8]) -> Result<(), RespondError> {
+        if check_no_capabilities(response).is_err() {
+            // let the caller know why it did not get the response
+            let error: Result<(), RpcError> = Err(RpcError::LoopbackCapability);
+            *self.0.response.borrow_mut() = Some(aser::to_bytes_named(&error, 0)?);
+
+            return Err(RespondError::LoopbackCapability);
+        }
+
+        *self.0.response.borrow_mut() = Some(response.to_vec());
+        Ok(())
+    }
+}
+
+impl Drop for LoopbackReply {
+    fn drop(&mut self) {
+        self.0.closed.set(true);
+
+        if let Some(waker) = self.0.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The queue a loopback client endpoint sends its messages to
+#[derive(Clone)]
+pub(crate) struct LoopbackClient {
+    sender: Sender<LoopbackMessage>,
+    server_state: Rc<ServerState>,
+}
+
+impl LoopbackClient {
+    pub(crate) async fn call(&self, data: &[u8]) -> Result<Vec<u8>, RpcError> {
+        check_no_capabilities(data)?;
+
+        let slot = Rc::<ResponseSlot>::default();
+        let message = LoopbackMessage {
+            data: data.to_vec(),
+            reply: Some(LoopbackReply(slot.clone())),
+        };
+
+        // sending only fails if the server endpoint was dropped
+        self.sender.send(message).await.or(Err(RpcError::ServiceError))?;
+
+        slot.wait().await
+    }
+
+    pub(crate) async fn send(&self, data: &[u8]) -> Result<(), RpcError> {
+        check_no_capabilities(data)?;
+
+        let message = LoopbackMessage {
+            data: data.to_vec(),
+            reply: None,
+        };
+
+        self.sender.send(message).await.or(Err(RpcError::ServiceError))
+    }
+
+    pub(crate) fn try_send(&self, data: &[u8]) -> Result<(), RpcError> {
+        check_no_capabilities(data)?;
+
+        let message = LoopbackMessage {
+            data: data.to_vec(),
+            reply: None,
+        };
+
+        match self.sender.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(SysErr::ChannelFull.into()),
+            Err(TrySendError::Closed(_)) => Err(RpcError::ServiceError),
+        }
+    }
+
+    /// Resolves once the server endpoint has been dropped, including if it was dropped before this was called
+    pub(crate) async fn server_dropped(&self) -> KResult<usize> {
+        poll_fn(|cx| {
+            if self.server_state.dropped.get() {
+                Poll::Ready(Ok(0))
+            } else {
+                self.server_state.drop_wakers.borrow_mut().push(cx.waker().clone());
+                Poll::Pending
+            }
+        }).await
+    }
+}
+
+/// The queue a loopback server endpoint recieves messages from
+pub(crate) struct LoopbackServer {
+    reciever: Receiver<LoopbackMessage>,
+    server_state: Rc<ServerState>,
+}
+
+impl LoopbackServer {
+    /// Returns every message sent to the service, ending once every client endpoint is dropped
+    pub(crate) fn messages(&mut self) -> impl FusedStream<Item = LoopbackMessage> + Unpin + '_ {
+        stream::poll_fn(|cx| self.reciever.poll_recv(cx)).fuse()
+    }
+}
+
+impl Drop for LoopbackServer {
+    fn drop(&mut self) {
+        self.server_state.dropped.set(true);
+
+        for waker in self.server_state.drop_wakers.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Tests which serve a service over loopback endpoints on the host, like the tests in the crate root these need the `std` feature
+#[cfg(all(test, feature = "loopback", feature = "std"))]
+mod tests {
+    use alloc::collections::BTreeMap;
+    use alloc::string::String;
+    use core::future::Future;
+    use core::pin::pin;
+
+    use futures::future::{self as future_util, Either};
+    use futures::task::noop_waker_ref;
+    use sys::{CapFlags, CapId, CapType};
+
+    use super::*;
+    use crate as arpc;
+    use crate::{RpcCall, RpcService};
+
+    /// A small file service, like the one fs-server serves
+    #[arpc::service(service_id = 1, name = "Fs")]
+    trait FsServer {
+        /// Adds a file called `name` holding `data`, fails if the name is already used
+        fn add(&self, name: String, data: Vec<u8>) -> bool;
+
+        /// Returns the size of the file called `name`
+        fn size(&self, name: String) -> Option<usize>;
+    }
+
+    #[derive(Default)]
+    struct FsServerImpl {
+        files: RefCell<BTreeMap<String, Vec<u8>>>,
+    }
+
+    #[arpc::service_impl]
+    impl FsServer for FsServerImpl {
+        fn add(&self, name: String, data: Vec<u8>) -> bool {
+            let mut files = self.files.borrow_mut();
+            if files.contains_key(&name) {
+                return false;
+            }
+
+            files.insert(name, data);
+            true
+        }
+
+        fn size(&self, name: String) -> Option<usize> {
+            self.files.borrow().get(&name).map(Vec::len)
+        }
+    }
+
+    /// Polls `future` until it completes, there is no executor on the host but everything it waits for is in this process
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(noop_waker_ref());
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Runs `calls` with a client for a fresh [`FsServerImpl`] served over loopback endpoints
+    fn with_fs<T, F: Future<Output = T>>(calls: impl FnOnce(Fs) -> F) -> T {
+        let (client_endpoint, server_endpoint) = loopback_endpoints();
+        let service = pin!(crate::run_rpc_service(server_endpoint, FsServerImpl::default()));
+        let calls = pin!(calls(Fs::from(client_endpoint)));
+
+        match block_on(future_util::select(calls, service)) {
+            Either::Left((output, _)) => output,
+            Either::Right(_) => panic!("fs service stopped while its client was still making calls"),
+        }
+    }
+
+    #[test]
+    fn fs_add_over_loopback() {
+        let results = with_fs(|fs| async move {
+            (
+                fs.add("a".into(), Vec::from([1, 2, 3])).await,
+                fs.add("a".into(), Vec::new()).await,
+                fs.size("a".into()).await,
+                fs.size("b".into()).await,
+            )
+        });
+
+        assert_eq!(results, (true, false, Some(3), None));
+    }
+
+    #[test]
+    fn capabilities_are_rejected_over_loopback() {
+        let result = with_fs(|fs| async move {
+            let channel_id = CapId::new(CapType::Channel, CapFlags::all(), false, 1);
+
+            fs.endpoint().call::<_, bool>(RpcCall {
+                service_id: Fs::SERVICE_INFO.service_id,
+                method_id: 0,
+                args: channel_id,
+            }).await
+        });
+
+        assert!(matches!(result, Err(RpcError::LoopbackCapability)), "call with a capability over loopback gave {result:?}");
+    }
+}
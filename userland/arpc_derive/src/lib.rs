@@ -1,9 +1,9 @@
 #![feature(let_chains)]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use proc_macro2::{TokenStream, Span};
-use syn::ExprLit;
+use syn::{ExprLit, Attribute, LitInt, TraitItemFn, ItemTrait, parse_quote};
 use syn::{parse_macro_input, punctuated::Punctuated, TraitItem, FnArg, Ident, Type, TypeReference, Index, TypeParamBound, Signature, ReturnType, Pat, Path, ExprAssign, Expr, Lit, Token};
 use syn::parse::{ParseStream, Parse, Result, Error};
 use syn::spanned::Spanned;
@@ -35,28 +35,142 @@ fn is_async(signature: &Signature) -> bool {
     signature.asyncness.is_some()
 }
 
-/// Returns an ident for the name of the macro that will implement the client trait
-fn client_impl_macro_name(trait_ident: &Ident) -> Ident {
-    format_ident!("__arpc_impl_{}_async_client", trait_ident.to_string().to_case(Case::Snake))
+/// Checks if the given function has no return value, or returns `()`
+fn returns_unit(signature: &Signature) -> bool {
+    match &signature.output {
+        ReturnType::Default => true,
+        ReturnType::Type(_, ret_type) => matches!(&**ret_type, Type::Tuple(tuple) if tuple.elems.is_empty()),
+    }
+}
+
+/// Options set on a trait method with `#[arpc(...)]`
+#[derive(Default)]
+struct MethodOptions {
+    /// The method is not callable over rpc
+    skip: bool,
+    /// The method is callable over rpc even though it has a default body
+    remote: bool,
+    /// Method id pinned with `id = N`
+    id: Option<u32>,
+    /// The client recieves the response in a dedicated buffer instead of the event pool
+    large_response: bool,
+    /// The first argument after `self` is an `arpc::CallContext` filled in by the service instead of sent by the client
+    with_context: bool,
+    /// The client sends the message without a reply capability and does not wait for the method to run
+    oneway: bool,
+}
+
+impl MethodOptions {
+    fn parse(attrs: &[Attribute]) -> Result<Self> {
+        let mut options = MethodOptions::default();
+
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("arpc")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    options.skip = true;
+                } else if meta.path.is_ident("remote") {
+                    options.remote = true;
+                } else if meta.path.is_ident("large_response") {
+                    options.large_response = true;
+                } else if meta.path.is_ident("with_context") {
+                    options.with_context = true;
+                } else if meta.path.is_ident("oneway") {
+                    options.oneway = true;
+                } else if meta.path.is_ident("id") {
+                    let id: LitInt = meta.value()?.parse()?;
+                    let id = id.base10_parse()?;
+                    if id == u32::MAX {
+                        return Err(meta.error("method id u32::MAX is reserved for the service info method"));
+                    }
+
+                    options.id = Some(id);
+                } else {
+                    return Err(meta.error("unknown arpc method option"));
+                }
+
+                Ok(())
+            })?;
+
+            if options.skip && (options.remote || options.id.is_some() || options.large_response || options.with_context || options.oneway) {
+                return Err(Error::new(attr.span(), "skipped arpc method cannot be remote, have an id, have a large response, take a context, or be oneway"));
+            }
+
+            if options.oneway && options.large_response {
+                return Err(Error::new(attr.span(), "oneway arpc method cannot have a large response, since it has no response"));
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Returns true if the method should be callable over rpc
+    ///
+    /// Methods with a default body run locally unless they are marked remote
+    fn is_remote(&self, fn_item: &TraitItemFn) -> bool {
+        !self.skip && (fn_item.default.is_none() || self.remote)
+    }
 }
 
-/// Returns an ident for the name of the macro that will get the name of the client trait
-fn client_resolve_macro_name(trait_ident: &Ident) -> Ident {
-    format_ident!("__arpc_resolve_{}_async_client", trait_ident.to_string().to_case(Case::Snake))
+/// Returns an ident for the name of the hidden module the argument structs of a service's methods are put in
+/// 
+/// This keeps them out of the module the service is defined in, so methods with the same name in different services don't collide
+fn args_module_name(trait_ident: &Ident) -> Ident {
+    format_ident!("__arpc_{}", trait_ident.to_string().to_case(Case::Snake))
+}
+
+/// Returns an ident for the name of the macro every service exports for services which extend it
+/// 
+/// The macro has 2 forms:
+/// - `alias $alias`: defines `$alias` as a trait alias for the service's async client trait
+/// - `impl_client $client_struct`: implements the async client trait of the service and all of its supertraits for `$client_struct`
+fn service_macro_name(trait_ident: &Ident) -> Ident {
+    format_ident!("__arpc_{}_service", trait_ident.to_string().to_case(Case::Snake))
 }
 
 struct Args {
     service_id: u64,
+    /// Version of the interface, defaults to 0
+    version: u32,
     /// Name used to generate clients
     name: String,
     supertrait_paths: HashMap<Ident, Path>,
 }
 
+impl Args {
+    /// Returns the path to the service macro of the arpc supertrait `supertrait`
+    /// 
+    /// The supertrait's argument is either the path of the module the trait is defined in,
+    /// or the full path of the trait, which is needed if the trait was imported under a different name.
+    fn supertrait_macro_path(&self, supertrait: &Path) -> Result<Path> {
+        // panic safety: paths always have at least 1 segment
+        let local_ident = &supertrait.segments.last().unwrap().ident;
+
+        let Some(path) = self.supertrait_paths.get(local_ident) else {
+            return Err(Error::new(
+                supertrait.span(),
+                format!("path to arpc supertrait not specified, add a `{local_ident} = path::to::module` argument to the service"),
+            ));
+        };
+
+        let mut path = path.clone();
+        let trait_ident = match path.segments.last() {
+            Some(segment) if segment.ident.to_string().starts_with(char::is_uppercase) => {
+                path.segments.pop().unwrap().into_value().ident
+            },
+            _ => local_ident.clone(),
+        };
+
+        path.segments.push(service_macro_name(&trait_ident).into());
+        Ok(path)
+    }
+}
+
 impl Parse for Args {
     fn parse(input: ParseStream) -> Result<Self> {
         let args = Punctuated::<ExprAssign, Token!(,)>::parse_terminated(input)?;
 
         let mut service_id = None;
+        let mut version = None;
         let mut name = None;
         let mut supertrait_paths = HashMap::new();
 
@@ -79,6 +193,17 @@ impl Parse for Args {
 
                     service_id = Some(arg_value.base10_parse()?);
                 },
+                "version" => {
+                    if version.is_some() {
+                        return Err(Error::new(arg.span(), "version argument can only be specified once"));
+                    }
+
+                    let Expr::Lit(ExprLit { lit: Lit::Int(arg_value), .. }) = &*arg.right else {
+                        return Err(Error::new(arg.span(), "invalid argument value for version"));
+                    };
+
+                    version = Some(arg_value.base10_parse()?);
+                },
                 "name" => {
                     if name.is_some() {
                         return Err(Error::new(arg.span(), "name argument can only be specified once"));
@@ -109,20 +234,214 @@ impl Parse for Args {
 
         Ok(Args {
             service_id: service_id.ok_or_else(|| input.error("service_id argument not specified"))?,
+            version: version.unwrap_or(0),
             name: name.ok_or_else(|| input.error("name argument not specified"))?,
             supertrait_paths,
         })
     }
 }
 
+/// Returns the ident of the struct the arguments of a method are sent in
+fn args_struct_name(method_ident: &Ident) -> Ident {
+    format_ident!("{}Args", method_ident.to_string().to_case(Case::UpperCamel))
+}
+
+/// Collects every error found while validating a service, so they are all reported instead of only the first
+#[derive(Default)]
+struct Errors(Option<Error>);
+
+impl Errors {
+    fn push(&mut self, error: Error) {
+        match &mut self.0 {
+            Some(errors) => errors.combine(error),
+            None => self.0 = Some(error),
+        }
+    }
+
+    fn into_result(self) -> Result<()> {
+        match self.0 {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An rpc method of a service which passed validation
+struct ValidMethod<'a> {
+    fn_item: &'a TraitItemFn,
+    options: MethodOptions,
+    method_id: u32,
+}
+
+/// A service which passed validation, everything needed to generate its code
+struct ValidService<'a> {
+    methods: Vec<ValidMethod<'a>>,
+    /// Paths to the service macros of the service's supertraits
+    supertrait_macros: Vec<Path>,
+}
+
+/// Checks every method and supertrait of a service, and assigns method ids
+/// 
+/// All errors are returned together, since generating code after an invalid method is skipped
+/// would bury the real errors under errors about generated items that don't line up.
+fn validate_service<'a>(args: &Args, input: &'a ItemTrait) -> Result<ValidService<'a>> {
+    let mut errors = Errors::default();
+
+    let mut remote_methods = Vec::new();
+    for item in input.items.iter() {
+        let TraitItem::Fn(fn_item) = item else {
+            continue;
+        };
+
+        match MethodOptions::parse(&fn_item.attrs) {
+            Ok(options) if options.is_remote(fn_item) => remote_methods.push((fn_item, options)),
+            Ok(_) => (),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    // ids pinned with `#[arpc(id = N)]`, which are skipped when assigning ids to the other methods
+    let pinned_ids = remote_methods.iter()
+        .filter_map(|(_, options)| options.id)
+        .collect::<HashSet<_>>();
+    let mut used_ids = HashSet::new();
+    let mut next_method_id = 0;
+
+    // methods whose names only differ in case would generate the same argument struct
+    let mut args_struct_names = HashMap::new();
+
+    let mut methods = Vec::new();
+    for (fn_item, options) in remote_methods {
+        let signature = &fn_item.sig;
+        let method_ident = &signature.ident;
+
+        let method_id = match options.id {
+            Some(id) => id,
+            None => {
+                while pinned_ids.contains(&next_method_id) {
+                    next_method_id += 1;
+                }
+
+                next_method_id += 1;
+                next_method_id - 1
+            },
+        };
+
+        if !used_ids.insert(method_id) {
+            errors.push(Error::new(method_ident.span(), "arpc method id is already used by another method"));
+        }
+
+        let args_struct_ident = args_struct_name(method_ident);
+        if let Some(other_ident) = args_struct_names.insert(args_struct_ident.to_string(), method_ident) {
+            let message = if other_ident == method_ident {
+                format!("arpc method name `{method_ident}` is already used by another method")
+            } else {
+                format!("arpc method `{method_ident}` has the same argument struct name `{args_struct_ident}` as method `{other_ident}`")
+            };
+            errors.push(Error::new(method_ident.span(), message));
+        }
+
+        if let Some(unsafety) = signature.unsafety {
+            errors.push(Error::new(unsafety.span, "arpc method must be safe"));
+        }
+
+        match signature.receiver() {
+            Some(reciever) => {
+                if !matches!(&*reciever.ty, Type::Reference(TypeReference { mutability: None, .. })) {
+                    errors.push(Error::new(reciever.span(), "arpc method must have &self reciever"));
+                }
+            },
+            None => errors.push(Error::new(method_ident.span(), "arpc method must have &self reciever")),
+        }
+
+        if options.oneway && !returns_unit(signature) {
+            errors.push(Error::new(signature.output.span(), "oneway arpc method must return ()"));
+        }
+
+        if options.with_context && !signature.inputs.iter().any(|arg| matches!(arg, FnArg::Typed(_))) {
+            errors.push(Error::new(method_ident.span(), "arpc method with context must take an arpc::CallContext after &self"));
+        }
+
+        methods.push(ValidMethod {
+            fn_item,
+            options,
+            method_id,
+        });
+    }
+
+    let mut supertrait_macros = Vec::new();
+    for supertrait in input.supertraits.iter() {
+        if let TypeParamBound::Trait(supertrait) = supertrait {
+            match args.supertrait_macro_path(&supertrait.path) {
+                Ok(macro_path) => supertrait_macros.push(macro_path),
+                Err(error) => errors.push(error),
+            }
+        }
+    }
+
+    errors.into_result()?;
+
+    Ok(ValidService {
+        methods,
+        supertrait_macros,
+    })
+}
+
+/// Removes `#[arpc(...)]` attributes from the trait's methods, they are not real attributes so they can't be left on the trait
+fn strip_arpc_attributes(input: &mut ItemTrait) {
+    for item in input.items.iter_mut() {
+        if let TraitItem::Fn(fn_item) = item {
+            fn_item.attrs.retain(|attr| !attr.path().is_ident("arpc"));
+        }
+    }
+}
+
+/// Generates rpc wrappers and a client for a service trait
+/// 
+/// Methods are assigned ids sequentially in the order they are declared, skipping any pinned ids,
+/// so reordering or removing methods changes the ids of the methods after them and breaks older clients.
+/// Methods can be annotated with `#[arpc(...)]` to change how they are handled:
+/// - `skip`: the method is not callable over rpc, it is only available on the server
+/// - `remote`: methods with a default body are skipped unless marked remote
+/// - `id = N`: pins the method's id to `N`
+/// - `large_response`: the client has the kernel copy the response into a buffer it owns instead of its event pool,
+///   use this for methods which return a lot of data
+/// - `with_context`: the first argument after `self` is an `arpc::CallContext` describing the caller,
+///   it is filled in by the service and is not part of the client's method
+/// - `oneway`: the method must return `()`, the client sends it without a reply capability and does not wait for it to run,
+///   so the client's method returns `Result<(), arpc::RpcError>` saying only if the message was sent.
+///   The client also gets a `try_send_<method>` method which does not wait for the service to recieve the message.
+///   Other methods are not run if they are sent without a reply.
+///
+/// If any method or supertrait is invalid, nothing is generated, the trait is emitted unchanged along with every error found.
 #[proc_macro_attribute]
 pub fn service(args: proc_macro::TokenStream, input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let args = parse_macro_input!(args as Args);
+    let mut input = parse_macro_input!(input as ItemTrait);
+
+    let out = match validate_service(&args, &input) {
+        Ok(service) => generate_service(&args, &input, service),
+        Err(error) => {
+            // only the trait is emitted with the errors, so code using the trait doesn't get errors of its own
+            let error = error.to_compile_error();
+            strip_arpc_attributes(&mut input);
+            quote! {
+                #input
+                #error
+            }
+        },
+    };
+
+    out.into()
+}
+
+fn generate_service(args: &Args, input: &ItemTrait, service: ValidService) -> TokenStream {
     let service_id = args.service_id;
+    let version = args.version;
     let client_struct_ident = format_ident!("{}", args.name);
 
-    let input = parse_macro_input!(input as syn::ItemTrait);
-    let trait_ident = input.ident;
+    let trait_ident = &input.ident;
+    let args_module_ident = args_module_name(trait_ident);
 
     // otuput tokens
     let mut out = TokenStream::new();
@@ -133,43 +452,47 @@ pub fn service(args: proc_macro::TokenStream, input: proc_macro::TokenStream) ->
     // methods for the async client
     let mut client_async_impls = TokenStream::new();
 
+    // methods on the client struct which send a message without waiting for a response
+    let mut client_send_impls = TokenStream::new();
+
+    // argument structs, which go in the args module
+    let mut args_structs = TokenStream::new();
+
     // list of arpc methods
     let mut arpc_methods = Vec::new();
 
-    for item in input.items.iter() {
+    let mut trait_input = input.clone();
+    strip_arpc_attributes(&mut trait_input);
+    for item in trait_input.items.iter() {
         items.extend(quote! { #item });
-        let TraitItem::Fn(fn_item) = item else {
-            continue;
-        };
+    }
 
+    for ValidMethod { fn_item, options, method_id } in service.methods {
         let signature = &fn_item.sig;
         let method_ident = &signature.ident;
 
-        if let Some(unsafety) = signature.unsafety {
-            out.extend(quote_spanned! {
-                unsafety.span => compile_error!("arpc method must be safe");
-            });
-            continue;
+        let mut client_async_signature = signature.clone();
+        client_async_signature.asyncness = Some(Token!(async)(Span::call_site()));
+        if options.oneway {
+            // the client only learns whether the message was sent
+            client_async_signature.output = parse_quote! { -> Result<(), arpc::RpcError> };
         }
 
-        let Some(reciever) = signature.receiver() else {
-            out.extend(quote_spanned! {
-                method_ident.span() => compile_error!("arpc method must have &self reciever");
-            });
-            continue;
-        };
-
-        let Type::Reference(TypeReference { mutability: None, .. }) = &*reciever.ty else {
-            out.extend(quote_spanned! {
-                reciever.self_token.span => compile_error("arpc method must have &self reciever")
-            });
-            continue;
-        };
-
-        // len makes ids sequentially assigned
-        let method_id = arpc_methods.len() as u32;
+        if options.with_context {
+            // the context is filled in by the service, so the client does not pass it
+            // panic safety: validation checked the method takes an argument after self
+            let context_index = client_async_signature.inputs.iter()
+                .position(|arg| matches!(arg, FnArg::Typed(_)))
+                .unwrap();
+
+            client_async_signature.inputs = client_async_signature.inputs.into_iter()
+                .enumerate()
+                .filter(|(i, _)| *i != context_index)
+                .map(|(_, arg)| arg)
+                .collect();
+        }
 
-        let fn_arg_types = signature.inputs.iter()
+        let fn_arg_types = client_async_signature.inputs.iter()
             .filter_map(|arg| {
                 if let FnArg::Typed(arg) = arg {
                     Some(&*arg.ty)
@@ -180,53 +503,89 @@ pub fn service(args: proc_macro::TokenStream, input: proc_macro::TokenStream) ->
         
         let fn_arg_count = fn_arg_types.clone().count();
         
-        let args_struct_ident = format_ident!("{}Args", signature.ident.to_string().to_case(Case::UpperCamel));
+        let args_struct_ident = args_struct_name(method_ident);
+
+        // each argument is checked on its own, so an argument which can't be sent is reported at the argument
+        let serde_asserts = fn_arg_types.clone()
+            .map(|arg_type| quote_spanned! { arg_type.span() => _assert_serde::<#arg_type>(); });
 
-        out.extend(quote! {
+        let args_struct_doc = format!("Arguments of [`{}::{}`]", trait_ident, signature.ident);
+        args_structs.extend(quote! {
+            #[doc = #args_struct_doc]
             #[derive(serde::Serialize, serde::Deserialize)]
             pub struct #args_struct_ident(#(pub #fn_arg_types),*);
+
+            const _: fn() = || {
+                #(#serde_asserts)*
+            };
         });
+        let args_struct_path = quote! { #args_module_ident::#args_struct_ident };
 
-        let method_wrapper_ident = format_ident!("{}_wrapper", signature.ident);
+        let method_wrapper_ident = format_ident!("__arpc_{}_wrapper", signature.ident);
 
         let arg_struct_fields = (0..fn_arg_count).map(Index::from);
 
+        let (context_ident, context_arg) = if options.with_context {
+            (format_ident!("context"), quote! { context, })
+        } else {
+            (format_ident!("_context"), quote! {})
+        };
+
+        // only oneway methods can be sent without a reply, others would have their return value thrown away
+        let reply_check = if options.oneway {
+            quote! {}
+        } else {
+            quote! {
+                if !reply.is_pending() {
+                    arpc::warn_message_without_reply(#service_id, #method_id);
+                    return;
+                }
+            }
+        };
+
         if is_async(signature) {
+            let task_name = signature.ident.to_string();
+
             items.extend(quote! {
-                fn #method_wrapper_ident(&self, data: &[u8], reply: arpc::sys::Reply) {
-                    let message = match arpc::aser::from_bytes::<arpc::RpcCall<#args_struct_ident>>(data) {
+                #[doc(hidden)]
+                fn #method_wrapper_ident(&self, data: &[u8], reply: arpc::ReplyGuard, #context_ident: arpc::CallContext) {
+                    #reply_check
+
+                    let message = match arpc::deserialize_call::<arpc::RpcCall<#args_struct_path>>(data) {
                         Ok(data) => data,
                         Err(error) => {
-                            arpc::respond_error(reply, arpc::RpcError::SerializationError(error));
+                            reply.reply_error(arpc::RpcError::SerializationError(error));
                             return;
                         },
                     };
 
-                    arpc::asynca::spawn(async {
-                        let result = #trait_ident::#method_ident(self, #(message.args.#arg_struct_fields),*).await;
-                        arpc::respond_success(reply, result);
+                    // if the task is aborted the reply guard is dropped with it, and the caller is sent an error
+                    arpc::asynca::spawn_named(#task_name, async move {
+                        let result = #trait_ident::#method_ident(self, #context_arg #(message.args.#arg_struct_fields),*).await;
+                        reply.reply(result);
                     });
                 }
             });
         } else {
             items.extend(quote! {
-                fn #method_wrapper_ident(&self, data: &[u8], reply: arpc::sys::Reply) {
-                    let message = match arpc::aser::from_bytes::<arpc::RpcCall<#args_struct_ident>>(data) {
+                #[doc(hidden)]
+                fn #method_wrapper_ident(&self, data: &[u8], reply: arpc::ReplyGuard, #context_ident: arpc::CallContext) {
+                    #reply_check
+
+                    let message = match arpc::deserialize_call::<arpc::RpcCall<#args_struct_path>>(data) {
                         Ok(data) => data,
                         Err(error) => {
-                            arpc::respond_error(reply, arpc::RpcError::SerializationError(error));
+                            reply.reply_error(arpc::RpcError::SerializationError(error));
                             return;
                         },
                     };
 
-                    let result = #trait_ident::#method_ident(self, #(message.args.#arg_struct_fields),*);
-                    arpc::respond_success(reply, result);
+                    let result = #trait_ident::#method_ident(self, #context_arg #(message.args.#arg_struct_fields),*);
+                    reply.reply(result);
                 }
             });
         }
 
-        let mut client_async_signature = signature.clone();
-        client_async_signature.asyncness = Some(Token!(async)(Span::call_site()));
         let mut unnamed_arg_count = 0u32;
         let args = client_async_signature.inputs.iter()
             .filter_map(|arg| {
@@ -241,22 +600,63 @@ pub fn service(args: proc_macro::TokenStream, input: proc_macro::TokenStream) ->
                 } else {
                     None
                 }
+            })
+            .collect::<Vec<_>>();
+
+        let call_ident = if options.large_response {
+            format_ident!("call_large_response")
+        } else {
+            format_ident!("call")
+        };
+
+        if options.oneway {
+            client_async_impls.extend(quote! {
+                #client_async_signature {
+                    let args = #args_struct_path(#(#args),*);
+                    let message = arpc::RpcCall {
+                        service_id: #service_id,
+                        method_id: #method_id,
+                        args,
+                    };
+
+                    self.endpoint().send(message).await
+                }
             });
+        } else {
+            client_async_impls.extend(quote! {
+                #client_async_signature {
+                    let args = #args_struct_path(#(#args),*);
+                    let message = arpc::RpcCall {
+                        service_id: #service_id,
+                        method_id: #method_id,
+                        args,
+                    };
 
+                    // TODO: make try_ version which does not panic when rpc fails
+                    self.endpoint().#call_ident(message).await.expect("failed to make rpc call")
+                }
+            });
+        }
 
-        client_async_impls.extend(quote! {
-            #client_async_signature {
-                let args = #args_struct_ident(#(#args),*);
-                let message = arpc::RpcCall {
-                    service_id: #service_id,
-                    method_id: #method_id,
-                    args,
-                };
+        // only oneway methods can be sent, since the service drops other methods which are sent without a reply
+        if options.oneway {
+            let send_ident = format_ident!("try_send_{}", method_ident);
+            let send_inputs = &client_async_signature.inputs;
+
+            client_send_impls.extend(quote! {
+                /// Sends the rpc message without waiting for the method to run
+                pub fn #send_ident(#send_inputs) -> Result<(), arpc::RpcError> {
+                    let args = #args_struct_path(#(#args),*);
+                    let message = arpc::RpcCall {
+                        service_id: #service_id,
+                        method_id: #method_id,
+                        args,
+                    };
 
-                // TODO: make try_ version which does not panic when rpc fails
-                self.endpoint().call(message).await.expect("failed to make rpc call")
-            }
-        });
+                    self.endpoint().try_send(message)
+                }
+            });
+        }
 
         arpc_methods.push(ArpcMethod {
             wrapper_ident: method_wrapper_ident,
@@ -265,22 +665,27 @@ pub fn service(args: proc_macro::TokenStream, input: proc_macro::TokenStream) ->
         });
     }
 
-    let trait_vis = input.vis;
+    // one more than the highest id, so every method a client knows about is below the count
+    let method_count = arpc_methods.iter()
+        .map(|method| method.method_id + 1)
+        .max()
+        .unwrap_or(0);
+    let trait_vis = &input.vis;
     let method_ids = arpc_methods.iter()
         .map(|m| m.method_id);
     let wrapper_idents = arpc_methods.iter()
         .map(|m| &m.wrapper_ident);
     let supertraits = &input.supertraits;
-    let arpc_supertraits_iter = supertraits.iter()
+    let arpc_supertraits = supertraits.iter()
         .filter_map(|t| {
             if let TypeParamBound::Trait(t) = t {
                 Some(&t.path)
             } else {
                 None
             }
-        });
-    let supertrait_count = arpc_supertraits_iter.clone().count();
-    let arpc_supertraits = arpc_supertraits_iter.clone();
+        })
+        .collect::<Vec<_>>();
+    let supertrait_macros = service.supertrait_macros;
 
     out.extend(quote! {
         #trait_vis trait #trait_ident: #supertraits {
@@ -288,86 +693,129 @@ pub fn service(args: proc_macro::TokenStream, input: proc_macro::TokenStream) ->
 
             type Client: arpc::RpcClient = #client_struct_ident;
 
-            fn call_inner(&self, call_data: &arpc::RpcCallMethod, data: &[u8], reply_id: arpc::sys::CapId) -> bool {
+            const SERVICE_ID: u64 = #service_id;
+            const SERVICE_VERSION: u32 = #version;
+            const METHOD_COUNT: u32 = #method_count;
+
+            /// Adds the id of this service and of every service it extends to `ids`
+            #[doc(hidden)]
+            fn add_service_ids(ids: &mut arpc::ServiceIds) where Self: Sized {
+                ids.insert(#service_id);
+                #(<Self as #arpc_supertraits>::add_service_ids(ids);)*
+            }
+
+            /// Returns the reply guard back if the call is not for this service or any of its supertraits
+            #[doc(hidden)]
+            fn call_inner(
+                &self,
+                call_data: &arpc::RpcCallMethod,
+                data: &[u8],
+                reply: arpc::ReplyGuard,
+                context: arpc::CallContext,
+            ) -> Result<(), arpc::ReplyGuard> {
                 if call_data.service_id != #service_id {
                     #(
-                        if #arpc_supertraits::call_inner(self, call_data, data, reply_id) {
-                            return true;
-                        }
+                        let reply = match #arpc_supertraits::call_inner(self, call_data, data, reply, context) {
+                            Ok(()) => return Ok(()),
+                            Err(reply) => reply,
+                        };
                     )*
 
-                    false
+                    Err(reply)
                 } else {
-                    let reply = arpc::sys::Reply::from_cap_id(reply_id).unwrap();
                     match call_data.method_id {
-                        #(#method_ids => #trait_ident::#wrapper_idents(self, data, reply),)*
-                        _ => arpc::respond_error(reply, arpc::RpcError::InvalidMethodId),
+                        #(#method_ids => #trait_ident::#wrapper_idents(self, data, reply, context),)*
+                        arpc::SERVICE_INFO_METHOD_ID => reply.reply(#client_struct_ident::SERVICE_INFO),
+                        _ => reply.reply_error(arpc::RpcError::InvalidMethodId {
+                            version: #version,
+                            method_count: #method_count,
+                        }),
                     }
 
-                    true
+                    Ok(())
                 }
             }
 
-            fn call(&self, data: &[u8], reply: arpc::sys::Reply) {
-                let call_data = match arpc::aser::from_bytes::<arpc::RpcCallMethod>(data) {
+            #[doc(hidden)]
+            fn call(&self, data: &[u8], reply: arpc::ReplyGuard, context: arpc::CallContext) {
+                let call_data = match arpc::deserialize_call::<arpc::RpcCallMethod>(data) {
                     Ok(data) => data,
                     Err(error) => {
-                        arpc::respond_error(reply, arpc::RpcError::SerializationError(error));
+                        reply.reply_error(arpc::RpcError::SerializationError(error));
                         return;
                     },
                 };
 
-                let cap_id = arpc::sys::Capability::cap_id(&reply);
-                core::mem::forget(reply);
-
-                if !#trait_ident::call_inner(self, &call_data, data, cap_id) {
-                    let reply = arpc::sys::Reply::from_cap_id(cap_id).unwrap();
-                    arpc::respond_error(reply, arpc::RpcError::InvalidServiceId);
+                if let Err(reply) = #trait_ident::call_inner(self, &call_data, data, reply, context) {
+                    reply.reply_error(arpc::RpcError::InvalidServiceId);
                 }
             }
         }
     });
 
     let client_async_trait = format_ident!("{}Async", args.name);
-    let resolve_client_macro_ident = client_resolve_macro_name(&trait_ident);
-    let impl_client_macro_ident = client_impl_macro_name(&trait_ident);
+    let service_macro_ident = service_macro_name(trait_ident);
 
     let client_async_sigs = arpc_methods
         .iter()
         .map(|method| &method.client_async_signature);
 
-    let supertrait_paths = arpc_supertraits_iter
-        .clone()
-        .map(|t| {
-            let trait_ident = &t.segments.last().unwrap().ident;
-            args.supertrait_paths.get(trait_ident)
-                .expect("path to supetrait not specified")
-        });
-    let supertrait_paths2 = supertrait_paths.clone();
-
-    // fixme: these are wrong, need macro to get them
-    let supertrait_resolve_macros = arpc_supertraits_iter
-        .clone()
-        .map(|t| {
-            let trait_ident = &t.segments.last().unwrap().ident;
-            client_resolve_macro_name(trait_ident)
-        });
-
-    let supertrait_impl_macros = arpc_supertraits_iter
-        .map(|t| {
-            let trait_ident = &t.segments.last().unwrap().ident;
-            client_impl_macro_name(trait_ident)
-        });
-    
-    let supertrait_aliases = (0..supertrait_count)
+    let supertrait_aliases = (0..supertrait_macros.len())
         .map(|n| format_ident!("__arpc_{}_alias{}", trait_ident, n))
         .collect::<Vec<_>>();
 
     out.extend(quote! {
+        #[doc(hidden)]
+        #[allow(non_camel_case_types)]
+        pub mod #args_module_ident {
+            #[allow(unused_imports)]
+            use super::*;
+
+            fn _assert_serde<T: serde::Serialize + serde::de::DeserializeOwned>() {}
+
+            #args_structs
+        }
+
         #[derive(serde::Serialize, serde::Deserialize)]
         pub struct #client_struct_ident(arpc::ClientRpcEndpoint);
 
         impl #client_struct_ident {
+            /// Version of the interface this client was compiled against
+            pub const SERVICE_INFO: arpc::ServiceInfo = arpc::ServiceInfo {
+                service_id: #service_id,
+                version: #version,
+                method_count: #method_count,
+            };
+
+            /// Asks the service which version of the interface it implements
+            pub async fn service_info(&self) -> Result<arpc::ServiceInfo, arpc::RpcError> {
+                let message = arpc::RpcCall {
+                    service_id: #service_id,
+                    method_id: arpc::SERVICE_INFO_METHOD_ID,
+                    args: (),
+                };
+
+                self.endpoint().call(message).await
+            }
+
+            /// Returns true if the service implements every method this client was compiled with
+            pub async fn check_compatible(&self) -> Result<bool, arpc::RpcError> {
+                let info = self.service_info().await?;
+                Ok(info.is_compatible_with(&Self::SERVICE_INFO))
+            }
+
+            /// Makes calls from this client carry the identity of this process, see [`arpc::ClientRpcEndpoint::with_identity`]
+            pub fn with_identity(self) -> Self {
+                Self(self.0.with_identity())
+            }
+
+            /// Makes another client for the same service, see [`arpc::ClientRpcEndpoint::try_clone`]
+            ///
+            /// Spawned tasks have to own the clients they use, so each task which calls the service should get its own clone.
+            pub fn try_clone(&self) -> arpc::sys::KResult<Self> {
+                Ok(Self(self.0.try_clone()?))
+            }
+
             pub fn into_endpoint(self) -> arpc::ClientRpcEndpoint {
                 self.0
             }
@@ -375,14 +823,18 @@ pub fn service(args: proc_macro::TokenStream, input: proc_macro::TokenStream) ->
             pub fn endpoint(&self) -> &arpc::ClientRpcEndpoint {
                 &self.0
             }
+
+            #client_send_impls
         }
 
+        #[automatically_derived]
         impl arpc::RpcClient for #client_struct_ident {
             fn from_endpoint(endpoint: arpc::ClientRpcEndpoint) -> Self {
                 Self(endpoint)
             }
         }
 
+        #[automatically_derived]
         impl From<arpc::ClientRpcEndpoint> for #client_struct_ident {
             fn from(endpoint: arpc::ClientRpcEndpoint) -> Self {
                 Self(endpoint)
@@ -391,7 +843,7 @@ pub fn service(args: proc_macro::TokenStream, input: proc_macro::TokenStream) ->
 
         #(
             // generate aliases for client supertraits so we know what they are clled
-            #supertrait_paths::#supertrait_resolve_macros!(#supertrait_aliases);
+            #supertrait_macros!(alias #supertrait_aliases);
         )*
 
         pub trait #client_async_trait: #(#supertrait_aliases)+* {
@@ -400,27 +852,32 @@ pub fn service(args: proc_macro::TokenStream, input: proc_macro::TokenStream) ->
             #(#client_async_sigs;)*
         }
 
-        pub macro #resolve_client_macro_ident($alias:ident) {
-            #[allow(non_camel_case_types)]
-            trait $alias = #client_async_trait;
-        }
-
-        pub macro #impl_client_macro_ident($client_struct:ident) {
-            #(#supertrait_paths2::#supertrait_impl_macros!($client_struct);)*
+        #[doc(hidden)]
+        pub macro #service_macro_ident {
+            (alias $alias:ident) => {
+                #[doc(hidden)]
+                #[allow(non_camel_case_types)]
+                trait $alias = #client_async_trait;
+            },
+            (impl_client $client_struct:ident) => {
+                // each supertrait's macro implements its own supertraits, so chains of any depth are covered
+                #(#supertrait_macros!(impl_client $client_struct);)*
+
+                #[automatically_derived]
+                impl #client_async_trait for $client_struct {
+                    fn downcast(self) -> #client_struct_ident {
+                        #client_struct_ident::from(self.into_endpoint())
+                    }
 
-            impl #client_async_trait for $client_struct {
-                fn downcast(self) -> #client_struct_ident {
-                    #client_struct_ident::from(self.into_endpoint())
+                    #client_async_impls
                 }
-
-                #client_async_impls
-            }
+            },
         }
 
-        #impl_client_macro_ident!(#client_struct_ident);
+        #service_macro_ident!(impl_client #client_struct_ident);
     });
 
-    out.into()
+    out
 }
 
 #[proc_macro_attribute]
@@ -433,11 +890,20 @@ pub fn service_impl(_args: proc_macro::TokenStream, input: proc_macro::TokenStre
     quote! {
         #input
 
+        #[automatically_derived]
         impl arpc::RpcService for #impl_type {
             type Client = <Self as #arpc_trait>::Client;
 
-            fn call(&self, data: &[u8], reply: arpc::sys::Reply) {
-                #arpc_trait::call(self, data, reply);
+            fn service_ids(&self) -> arpc::ServiceIds {
+                let mut ids = arpc::ServiceIds::default();
+                <Self as #arpc_trait>::add_service_ids(&mut ids);
+                ids
+            }
+
+            fn call_method(&self, call_data: &arpc::RpcCallMethod, data: &[u8], reply: arpc::ReplyGuard, context: arpc::CallContext) {
+                if let Err(reply) = #arpc_trait::call_inner(self, call_data, data, reply, context) {
+                    reply.reply_error(arpc::RpcError::InvalidServiceId);
+                }
             }
         }
     }.into()
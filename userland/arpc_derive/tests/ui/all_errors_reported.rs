@@ -0,0 +1,18 @@
+// every invalid method is reported, and the valid methods after them don't add errors of their own
+#[arpc_derive::service(service_id = 1, name = "TestClient")]
+trait Test {
+    unsafe fn first(&self);
+
+    #[arpc(oneway)]
+    fn second(&self) -> u32;
+
+    fn third(&self, value: u32) -> u32;
+
+    #[arpc(id = 2)]
+    fn fourth(&self);
+
+    #[arpc(unknown)]
+    fn fifth(&self);
+}
+
+fn main() {}
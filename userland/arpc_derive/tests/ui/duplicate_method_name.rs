@@ -0,0 +1,13 @@
+#[arpc_derive::service(service_id = 1, name = "TestClient")]
+trait Test {
+    fn get_value(&self) -> u32;
+
+    #[allow(non_snake_case)]
+    fn getValue(&self) -> u32;
+
+    // skipped methods don't generate an argument struct
+    #[arpc(skip)]
+    fn get_Value(&self) -> u32;
+}
+
+fn main() {}
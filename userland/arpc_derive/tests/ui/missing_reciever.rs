@@ -0,0 +1,6 @@
+#[arpc_derive::service(service_id = 1, name = "TestClient")]
+trait Test {
+    fn create() -> u32;
+}
+
+fn main() {}
@@ -0,0 +1,25 @@
+#![feature(associated_type_defaults)]
+#![feature(trait_alias)]
+#![feature(decl_macro)]
+
+#[path = "support/arpc.rs"]
+mod arpc;
+
+mod base {
+    use crate::arpc;
+
+    #[arpc_derive::service(service_id = 1, name = "Base")]
+    pub trait BaseServer {
+        fn base_value(&self) -> u32;
+    }
+}
+
+use base::BaseServer;
+
+// the path to the module of the supertrait is not given, so its service macro can't be found
+#[arpc_derive::service(service_id = 2, name = "Middle")]
+trait MiddleServer: BaseServer {
+    fn middle_value(&self) -> u32;
+}
+
+fn main() {}
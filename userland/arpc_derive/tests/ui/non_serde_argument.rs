@@ -0,0 +1,15 @@
+#![feature(associated_type_defaults)]
+#![feature(trait_alias)]
+#![feature(decl_macro)]
+
+#[path = "support/arpc.rs"]
+mod arpc;
+
+struct NotSerializable;
+
+#[arpc_derive::service(service_id = 1, name = "TestClient")]
+trait Test {
+    fn send(&self, count: u32, value: NotSerializable);
+}
+
+fn main() {}
@@ -0,0 +1,46 @@
+// a service which extends a service from another module, which itself extends a service from a third module
+#![feature(associated_type_defaults)]
+#![feature(trait_alias)]
+#![feature(decl_macro)]
+
+#[path = "../support/arpc.rs"]
+mod arpc;
+
+mod base {
+    use crate::arpc;
+
+    #[arpc_derive::service(service_id = 1, name = "Base")]
+    pub trait BaseServer {
+        fn base_value(&self) -> u32;
+    }
+}
+
+mod middle {
+    use crate::arpc;
+    use crate::base::BaseServer;
+
+    #[arpc_derive::service(service_id = 2, name = "Middle", BaseServer = crate::base)]
+    pub trait MiddleServer: BaseServer {
+        fn middle_value(&self) -> u32;
+    }
+}
+
+// imported under another name, so the argument is the full path of the trait instead of its module
+use middle::MiddleServer as Parent;
+
+#[arpc_derive::service(service_id = 3, name = "Top", Parent = crate::middle::MiddleServer)]
+trait TopServer: Parent {
+    fn top_value(&self) -> u32;
+}
+
+use base::BaseAsync;
+use middle::MiddleAsync;
+
+// the client of the last service can call the methods of every service it extends
+async fn call_every_level(client: &Top) -> u32 {
+    client.base_value().await + client.middle_value().await + client.top_value().await
+}
+
+fn main() {
+    let _ = call_every_level;
+}
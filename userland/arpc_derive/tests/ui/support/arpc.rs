@@ -0,0 +1,107 @@
+//! Stands in for the arpc crate, with just enough of its interface for generated services to type check on the host
+
+#![allow(dead_code, unused_variables)]
+
+use serde::{Deserialize, Serialize};
+
+pub mod sys {
+    pub type KResult<T> = Result<T, ()>;
+}
+
+#[derive(Debug)]
+pub enum RpcError {
+    SerializationError(()),
+    InvalidMethodId {
+        version: u32,
+        method_count: u32,
+    },
+    InvalidServiceId,
+}
+
+pub const SERVICE_INFO_METHOD_ID: u32 = u32::MAX;
+
+#[derive(Serialize, Deserialize)]
+pub struct ServiceInfo {
+    pub service_id: u64,
+    pub version: u32,
+    pub method_count: u32,
+}
+
+impl ServiceInfo {
+    pub fn is_compatible_with(&self, client_info: &ServiceInfo) -> bool {
+        unimplemented!()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcCallMethod {
+    pub service_id: u64,
+    pub method_id: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcCall<T> {
+    pub service_id: u64,
+    pub method_id: u32,
+    pub args: T,
+}
+
+pub fn deserialize_call<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<T, ()> {
+    unimplemented!()
+}
+
+pub fn warn_message_without_reply(service_id: u64, method_id: u32) {}
+
+#[derive(Default)]
+pub struct ServiceIds;
+
+impl ServiceIds {
+    pub fn insert(&mut self, service_id: u64) {}
+}
+
+pub struct CallContext;
+
+pub struct ReplyGuard;
+
+impl ReplyGuard {
+    pub fn is_pending(&self) -> bool {
+        unimplemented!()
+    }
+
+    pub fn reply<T: Serialize>(self, data: T) {}
+
+    pub fn reply_error(self, error: RpcError) {}
+}
+
+pub trait RpcClient {
+    fn from_endpoint(endpoint: ClientRpcEndpoint) -> Self;
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClientRpcEndpoint;
+
+impl ClientRpcEndpoint {
+    pub fn with_identity(self) -> Self {
+        self
+    }
+
+    pub fn try_clone(&self) -> sys::KResult<Self> {
+        unimplemented!()
+    }
+
+    pub async fn call<T: Serialize, U: for<'de> Deserialize<'de>>(&self, data: RpcCall<T>) -> Result<U, RpcError> {
+        unimplemented!()
+    }
+
+    pub async fn call_large_response<T: Serialize, U: for<'de> Deserialize<'de>>(&self, data: RpcCall<T>) -> Result<U, RpcError> {
+        unimplemented!()
+    }
+
+    pub async fn send<T: Serialize>(&self, data: RpcCall<T>) -> Result<(), RpcError> {
+        unimplemented!()
+    }
+
+    pub fn try_send<T: Serialize>(&self, data: RpcCall<T>) -> Result<(), RpcError> {
+        unimplemented!()
+    }
+}
@@ -0,0 +1,15 @@
+#[arpc_derive::service(service_id = 1, name = "TestClient")]
+trait Test {
+    unsafe fn read(&self, address: usize) -> u8;
+}
+
+// the trait is still emitted, so using it causes no more errors
+struct TestServer;
+
+impl Test for TestServer {
+    unsafe fn read(&self, _address: usize) -> u8 {
+        0
+    }
+}
+
+fn main() {}
@@ -1,18 +1,28 @@
-pub trait ByteBuf: Default {
-    fn push(&mut self, byte: u8);
-    fn extend_from_slice(&mut self, slice: &[u8]);
+use crate::AserError;
+
+/// Growable buffer the serializer writes into
+/// 
+/// Writes return [`AserError::OutOfMemory`] when the buffer can't grow,
+/// so running out of memory fails serialization instead of panicking.
+pub trait ByteBuf {
+    fn push(&mut self, byte: u8) -> Result<(), AserError>;
+    fn extend_from_slice(&mut self, slice: &[u8]) -> Result<(), AserError>;
     fn as_slice(&mut self) -> &mut [u8];
     fn len(&self) -> usize;
 }
 
 #[cfg(feature = "alloc")]
 impl ByteBuf for alloc::vec::Vec<u8> {
-    fn push(&mut self, byte: u8) {
+    fn push(&mut self, byte: u8) -> Result<(), AserError> {
+        self.try_reserve(1).or(Err(AserError::OutOfMemory))?;
         self.push(byte);
+        Ok(())
     }
 
-    fn extend_from_slice(&mut self, slice: &[u8]) {
+    fn extend_from_slice(&mut self, slice: &[u8]) -> Result<(), AserError> {
+        self.try_reserve(slice.len()).or(Err(AserError::OutOfMemory))?;
         self.extend_from_slice(slice);
+        Ok(())
     }
 
     fn as_slice(&mut self) -> &mut [u8] {
@@ -0,0 +1,191 @@
+//! Wrappers which control what happens to a capability when the message it is in is sent
+//!
+//! By default a capability is cloned into the recieving capability space with the same permissions,
+//! and the sender keeps its copy. [`CapMove`] gives the capability away, and [`CapShare`] sends it with fewer permissions.
+//! The wrappers are serialized exactly like the capability they wrap, so the reciever can deserialize either the wrapper or the capability.
+//!
+//! The serializer recognizes the wrappers by their newtype names, and records a [`CapPolicy`] for the capability they hold.
+//! The policies are returned by [`to_bytes_with_policies`](crate::to_bytes_with_policies), and are applied by
+//! [`clone_caps_to_cspace_with_policies`](crate::clone_caps_to_cspace_with_policies), or by [`finish_transfer`](CapPolicies::finish_transfer)
+//! once a message has been sent over a channel.
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use serde::{Serialize, Deserialize};
+use sys::{Capability, CapFlags, CspaceTarget, KResult, cap_clone};
+#[cfg(feature = "alloc")]
+use sys::CapId;
+
+#[cfg(feature = "alloc")]
+use crate::CapTable;
+
+/// Newtype struct name [`CapMove`] serializes with
+pub const CAP_MOVE_NEWTYPE_NAME: &str = "__aser_cap_move";
+/// Newtype variant name [`CapShare`] serializes with, the variant index holds the permissions to share with
+pub const CAP_SHARE_NEWTYPE_NAME: &str = "__aser_cap_share";
+
+/// What happens to a capability when it is transferred to another capability space
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CapPolicy {
+    /// Clone the capability with the same permissions, and leave the source alone
+    #[default]
+    Clone,
+    /// Clone the capability, and destroy the source once every capability in the message was transferred
+    Move,
+    /// Clone the capability with only the given permissions
+    Share(CapFlags),
+}
+
+impl CapPolicy {
+    /// Permissions the transferred capability is cloned with
+    pub fn clone_flags(&self) -> CapFlags {
+        match self {
+            CapPolicy::Clone | CapPolicy::Move => CapFlags::all(),
+            CapPolicy::Share(flags) => *flags,
+        }
+    }
+}
+
+/// Policy of each capability in a serialized message, indexed by position in the capability table
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapPolicies {
+    /// Only capabilities which are not [`CapPolicy::Clone`] are stored
+    policies: Vec<(usize, CapPolicy)>,
+}
+
+#[cfg(feature = "alloc")]
+impl CapPolicies {
+    /// Returns the policy of the capability at `index` in the capability table
+    pub fn get(&self, index: usize) -> CapPolicy {
+        self.policies.iter()
+            .find(|(cap_index, _)| *cap_index == index)
+            .map(|(_, policy)| *policy)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn set(&mut self, index: usize, policy: CapPolicy) {
+        self.policies.retain(|(cap_index, _)| *cap_index != index);
+
+        if policy != CapPolicy::Clone {
+            self.policies.push((index, policy));
+        }
+    }
+
+    /// Returns true if every capability is cloned normally
+    pub fn is_empty(&self) -> bool {
+        self.policies.is_empty()
+    }
+
+    /// Destroys the capabilities which were moved, call this once `data` has been sent over a channel
+    ///
+    /// The kernel clones capabilities sent over a channel, so the source of a moved capability is left behind until this is called.
+    /// If the send failed, don't call this, and the sender keeps every capability.
+    pub fn finish_transfer(&self, data: &[u8]) {
+        self.finish_transfer_with(&mut CspaceTarget::Current, data)
+    }
+
+    /// Same as [`finish_transfer`](Self::finish_transfer), but moved capabilities are destroyed with [`CapTable::destroy_source`]
+    pub fn finish_transfer_with<T: CapTable + ?Sized>(&self, table: &mut T, data: &[u8]) {
+        for (index, policy) in self.policies.iter() {
+            if *policy != CapPolicy::Move {
+                continue;
+            }
+
+            let cap_id = crate::get_usize(data, index + 1).ok().and_then(CapId::try_from);
+            if let Some(cap_id) = cap_id {
+                // the wrapper also destroys the capability when it is dropped, so it is fine if it is already gone
+                let _ = table.destroy_source(cap_id);
+            }
+        }
+    }
+}
+
+/// Gives a capability away when sent, the sender's copy is destroyed once the transfer succeeds
+///
+/// If the transfer fails the capability is not destroyed, and can be taken back with [`into_inner`](Self::into_inner).
+/// Dropping the wrapper destroys the capability like dropping `T` would.
+#[derive(Debug)]
+pub struct CapMove<T>(T);
+
+impl<T: Capability> CapMove<T> {
+    pub fn new(cap: T) -> Self {
+        CapMove(cap)
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Capability + Serialize> Serialize for CapMove<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(CAP_MOVE_NEWTYPE_NAME, &self.0)
+    }
+}
+
+impl<'de, T: Capability + Deserialize<'de>> Deserialize<'de> for CapMove<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(CapMove)
+    }
+}
+
+/// Sends a capability with only some of its permissions, for example to give another process a read only view of [`Memory`](sys::Memory)
+///
+/// The wrapper holds its own clone of the capability, so the caller keeps theirs with every permission.
+#[derive(Debug)]
+pub struct CapShare<T> {
+    cap: T,
+    flags: CapFlags,
+}
+
+impl<T: Capability> CapShare<T> {
+    /// Clones `cap` with only the permissions in `flags`
+    ///
+    /// Permissions `cap` does not have are never added.
+    pub fn new(cap: &T, flags: CapFlags) -> KResult<Self> {
+        Ok(CapShare {
+            cap: cap_clone(CspaceTarget::Current, CspaceTarget::Current, cap, flags)?,
+            flags,
+        })
+    }
+
+    /// Permissions the capability is sent with
+    pub fn flags(&self) -> CapFlags {
+        self.flags
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.cap
+    }
+
+    pub fn into_inner(self) -> T {
+        self.cap
+    }
+}
+
+impl<T: Capability + Serialize> Serialize for CapShare<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_variant(
+            CAP_SHARE_NEWTYPE_NAME,
+            self.flags.bits() as u32,
+            CAP_SHARE_NEWTYPE_NAME,
+            &self.cap,
+        )
+    }
+}
+
+impl<'de, T: Capability + Deserialize<'de>> Deserialize<'de> for CapShare<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let cap = T::deserialize(deserializer)?;
+
+        Ok(CapShare {
+            flags: cap.cap_id().flags(),
+            cap,
+        })
+    }
+}
\ No newline at end of file
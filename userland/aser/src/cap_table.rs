@@ -0,0 +1,33 @@
+//! The capability operations used to transfer the capabilities in a serialized message
+//!
+//! [`clone_caps_with`](crate::clone_caps_with) and [`finish_transfer_with`](crate::CapPolicies::finish_transfer_with)
+//! only touch capabilities through a [`CapTable`], so the transfer logic can run without the kernel.
+//! [`CspaceTarget`] implements it with system calls, and is what the non generic versions use.
+use sys::{CapFlags, CapId, CapabilityWeakness, CspaceTarget, KResult, cap_clone_inner, cap_destroy};
+
+/// A destination capability space, which capabilities in the current capability space are cloned into
+pub trait CapTable {
+    /// Clones `cap_id` from the current capability space into this table with at most the permissions in `flags`,
+    /// and returns the id of the clone
+    fn clone_in(&mut self, cap_id: CapId, flags: CapFlags) -> KResult<CapId>;
+
+    /// Destroys a capability in this table, used to undo [`clone_in`](Self::clone_in) when a transfer fails
+    fn destroy(&mut self, cap_id: CapId) -> KResult<()>;
+
+    /// Destroys a capability in the current capability space once it has been moved into this table
+    fn destroy_source(&mut self, cap_id: CapId) -> KResult<()>;
+}
+
+impl CapTable for CspaceTarget<'_> {
+    fn clone_in(&mut self, cap_id: CapId, flags: CapFlags) -> KResult<CapId> {
+        cap_clone_inner(*self, CspaceTarget::Current, cap_id, flags, CapabilityWeakness::Current, false)
+    }
+
+    fn destroy(&mut self, cap_id: CapId) -> KResult<()> {
+        cap_destroy(*self, cap_id)
+    }
+
+    fn destroy_source(&mut self, cap_id: CapId) -> KResult<()> {
+        cap_destroy(CspaceTarget::Current, cap_id)
+    }
+}
\ No newline at end of file
@@ -1,10 +1,15 @@
-use serde::{de::{self, Visitor, SeqAccess, MapAccess, EnumAccess, VariantAccess, IntoDeserializer}, forward_to_deserialize_any, Deserialize};
+use serde::{de::{self, Visitor, SeqAccess, MapAccess, EnumAccess, VariantAccess, IntoDeserializer, value::BorrowedStrDeserializer}, forward_to_deserialize_any, Deserialize};
 
 use super::capability_deserializer::CapabilityDeserializer;
 use super::{AserError, DataType};
 
 pub fn from_bytes<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<T, AserError> {
-    let mut deserializer = Deserializer::from_bytes(bytes)?;
+    from_bytes_with_limits(bytes, DeserializerLimits::default())
+}
+
+/// Same as [`from_bytes`], but with custom limits on the input
+pub fn from_bytes_with_limits<'a, T: Deserialize<'a>>(bytes: &'a [u8], limits: DeserializerLimits) -> Result<T, AserError> {
+    let mut deserializer = Deserializer::from_bytes(bytes)?.with_limits(limits);
     let out = T::deserialize(&mut deserializer)?;
 
     if deserializer.input.is_empty() {
@@ -14,9 +19,37 @@ pub fn from_bytes<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<T, AserErro
     }
 }
 
+/// Limits checked while deserializing, so untrusted input can't use unbounded resources
+/// 
+/// Lengths of strings and bytes are always checked against the size of the remaining input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeserializerLimits {
+    /// Maximum number of nested sequences, maps, options, newtypes, and enum variants with data
+    pub max_depth: usize,
+    /// Maximum number of elements in a single sequence, or entries in a single map
+    pub max_elements: usize,
+}
+
+impl DeserializerLimits {
+    pub const DEFAULT_MAX_DEPTH: usize = 128;
+}
+
+impl Default for DeserializerLimits {
+    fn default() -> Self {
+        DeserializerLimits {
+            max_depth: Self::DEFAULT_MAX_DEPTH,
+            max_elements: usize::MAX,
+        }
+    }
+}
+
 pub struct Deserializer<'de> {
-    capabilities: &'de [u64],
+    /// Capability table, each capability is 8 bytes
+    capabilities: &'de [u8],
     input: &'de [u8],
+    limits: DeserializerLimits,
+    /// Number of values currently being deserialized which contain the current value
+    depth: usize,
 }
 
 impl<'de> Deserializer<'de> {
@@ -26,19 +59,46 @@ impl<'de> Deserializer<'de> {
 
         let num_capabilities = usize::from_le_bytes(num_capabilities.try_into().unwrap());
 
-        let capabilities = data.take(..8 * num_capabilities)
+        let capabilities_size = num_capabilities.checked_mul(8)
             .ok_or(AserError::EndOfInput)?;
 
-        let capabilities = unsafe {
-            core::slice::from_raw_parts(capabilities.as_ptr() as *const u64, capabilities.len() / 8)
-        };
+        let capabilities = data.take(..capabilities_size)
+            .ok_or(AserError::EndOfInput)?;
 
         Ok(Deserializer {
             capabilities,
             input: data,
+            limits: DeserializerLimits::default(),
+            depth: 0,
         })
     }
 
+    pub fn with_limits(mut self, limits: DeserializerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    fn get_capability(&self, index: u16) -> Result<u64, AserError> {
+        let offset = index as usize * 8;
+        let bytes = self.capabilities.get(offset..(offset + 8))
+            .ok_or(AserError::InvalidCapabilityIndex)?;
+
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Runs `f` one nesting level deeper, fails if that would go over the depth limit
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, AserError>) -> Result<T, AserError> {
+        if self.depth >= self.limits.max_depth {
+            return Err(AserError::DepthLimitExceeded);
+        }
+
+        self.depth += 1;
+        let out = f(self);
+        self.depth -= 1;
+
+        out
+    }
+
     fn take_u8(&mut self) -> Result<u8, AserError> {
         self.input.take_first().copied().ok_or(AserError::EndOfInput)
     }
@@ -168,31 +228,41 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
                 visitor.visit_borrowed_bytes(self.take_bytes(num_bytes)?)
             },
 
-            DataType::Newtype => visitor.visit_newtype_struct(self),
-            DataType::Some => visitor.visit_some(self),
+            DataType::Newtype => self.nested(|this| visitor.visit_newtype_struct(this)),
+            DataType::Some => self.nested(|this| visitor.visit_some(this)),
 
-            DataType::SequenceStart => visitor.visit_seq(SequenceDeserializer::try_from(self)?),
+            DataType::SequenceStart => self.nested(|this| visitor.visit_seq(SequenceDeserializer::try_from(this)?)),
             DataType::SequenceEnd => Err(AserError::UnexpectedTerminator),
 
-            DataType::MapStart => visitor.visit_map(MapDeserializer::try_from(self)?),
+            DataType::MapStart => self.nested(|this| visitor.visit_map(MapDeserializer::try_from(this)?)),
             DataType::MapEnd => Err(AserError::UnexpectedTerminator),
 
             DataType::Variant => visitor.visit_enum(EnumDeserializer {
                 deserializer: self,
                 has_data: false,
+                named: false,
             }),
-            DataType::VariantValue => visitor.visit_enum(EnumDeserializer {
-                deserializer: self,
+            DataType::VariantValue => self.nested(|this| visitor.visit_enum(EnumDeserializer {
+                deserializer: this,
                 has_data: true,
+                named: false,
+            })),
+            DataType::VariantNamed => visitor.visit_enum(EnumDeserializer {
+                deserializer: self,
+                has_data: false,
+                named: true,
             }),
+            DataType::VariantNamedValue => self.nested(|this| visitor.visit_enum(EnumDeserializer {
+                deserializer: this,
+                has_data: true,
+                named: true,
+            })),
 
             DataType::Capability => {
                 let index = self.take_u16()?;
-                let value = *self.capabilities.get(index as usize)
-                    .ok_or(AserError::InvalidCapabilityIndex)?;
 
                 let cap_deserializer = CapabilityDeserializer {
-                    cap_id: value,
+                    cap_id: self.get_capability(index)?,
                 };
 
                 visitor.visit_enum(cap_deserializer)
@@ -210,6 +280,8 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
 struct SequenceDeserializer<'a, 'de: 'a> {
     deserializer: &'a mut Deserializer<'de>,
     finished: bool,
+    /// Number of elements deserialized so far
+    count: usize,
 }
 
 impl SequenceDeserializer<'_, '_> {
@@ -231,6 +303,7 @@ impl<'a, 'de: 'a> TryFrom<&'a mut Deserializer<'de>> for SequenceDeserializer<'a
         let mut out = SequenceDeserializer {
             deserializer,
             finished: false,
+            count: 0,
         };
 
         out.check_if_finished()?;
@@ -249,6 +322,11 @@ impl<'a, 'de> SeqAccess<'de> for SequenceDeserializer<'a, 'de> {
             return Ok(None);
         }
 
+        if self.count == self.deserializer.limits.max_elements {
+            return Err(AserError::ElementLimitExceeded);
+        }
+        self.count += 1;
+
         let out = seed.deserialize(&mut *self.deserializer).map(Some);
 
         self.check_if_finished()?;
@@ -260,6 +338,8 @@ impl<'a, 'de> SeqAccess<'de> for SequenceDeserializer<'a, 'de> {
 struct MapDeserializer<'a, 'de: 'a> {
     deserializer: &'a mut Deserializer<'de>,
     finished: bool,
+    /// Number of entries deserialized so far
+    count: usize,
 }
 
 impl MapDeserializer<'_, '_> {
@@ -281,6 +361,7 @@ impl<'a, 'de: 'a> TryFrom<&'a mut Deserializer<'de>> for MapDeserializer<'a, 'de
         let mut out = MapDeserializer {
             deserializer,
             finished: false,
+            count: 0,
         };
 
         out.check_if_finished()?;
@@ -299,6 +380,11 @@ impl<'a, 'de> MapAccess<'de> for MapDeserializer<'a, 'de> {
             return Ok(None);
         }
 
+        if self.count == self.deserializer.limits.max_elements {
+            return Err(AserError::ElementLimitExceeded);
+        }
+        self.count += 1;
+
         seed.deserialize(&mut *self.deserializer).map(Some)
     }
 
@@ -317,6 +403,8 @@ struct EnumDeserializer<'a, 'de: 'a> {
     deserializer: &'a mut Deserializer<'de>,
     // will be false if this EnumDeserializer was made for a Variant with no value
     has_data: bool,
+    /// The variant is identified by its name instead of its index
+    named: bool,
 }
 
 impl<'a, 'de> EnumAccess<'de> for EnumDeserializer<'a, 'de> {
@@ -326,9 +414,17 @@ impl<'a, 'de> EnumAccess<'de> for EnumDeserializer<'a, 'de> {
     fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
     where
         V: de::DeserializeSeed<'de> {
-        let val = self.deserializer.take_u32()?;
+        if self.named {
+            let name_len = self.deserializer.take_u16()? as usize;
+            let name = self.deserializer.take_str(name_len)?;
+
+            // serde's generated variant identifiers match on variant names as well as indexes
+            Ok((seed.deserialize(BorrowedStrDeserializer::new(name))?, self))
+        } else {
+            let val = self.deserializer.take_u32()?;
 
-        Ok((seed.deserialize(val.into_deserializer())?, self))
+            Ok((seed.deserialize(val.into_deserializer())?, self))
+        }
     }
 }
 
@@ -11,26 +11,38 @@ use core::fmt::Display;
 use core::mem::size_of;
 #[cfg(feature = "alloc")]
 use alloc::string::{String, ToString};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
 
 use serde::{Serialize, Deserialize};
-use sys::{CspaceTarget, CapId, cap_clone_inner, CapFlags, SysErr, CapabilityWeakness};
+use sys::SysErr;
+#[cfg(feature = "alloc")]
+use sys::{CspaceTarget, CapId};
 use thiserror_no_std::Error;
 use num_enum::{TryFromPrimitive, IntoPrimitive};
 
 mod byte_buf;
 pub use byte_buf::ByteBuf;
+pub mod cap_policy;
+pub use cap_policy::{CapMove, CapShare, CapPolicy};
+#[cfg(feature = "alloc")]
+pub use cap_policy::CapPolicies;
+mod cap_table;
+pub use cap_table::CapTable;
 mod capability_counter;
 pub use capability_counter::count_capabilties;
 mod capability_serializer;
 mod capability_deserializer;
 mod ser;
-pub use ser::{Serializer, to_bytes, to_bytes_count_cap};
+pub use ser::{Serializer, to_bytes, to_byte_buf, to_bytes_count_cap, to_bytes_named, to_byte_buf_named, to_bytes_count_cap_named};
+#[cfg(feature = "alloc")]
+pub use ser::to_bytes_with_policies;
 mod de;
-pub use de::{Deserializer, from_bytes};
+pub use de::{Deserializer, DeserializerLimits, from_bytes, from_bytes_with_limits};
 #[cfg(feature = "alloc")]
 mod value;
 #[cfg(feature = "alloc")]
-pub use value::{Value, Integer, Float};
+pub use value::{Value, ValueKind, Integer, Float};
 
 pub type Result<T> = core::result::Result<T, AserError>;
 
@@ -79,6 +91,16 @@ pub enum AserError {
     InvalidCapabilityId,
     #[error("There are trailing characters on the end of the input")]
     TrailingInput,
+    #[error("Values are nested deeper than the deserializer's depth limit")]
+    DepthLimitExceeded,
+    #[error("A sequence or map has more elements than the deserializer's element limit")]
+    ElementLimitExceeded,
+    #[error("Failed to allocate memory for serialized data")]
+    OutOfMemory,
+    /// The data had a different type than the type being deserialized, for example a string where a number was expected
+    #[cfg(feature = "alloc")]
+    #[error("Deserialize failed: {0}")]
+    InvalidType(String),
 }
 
 #[cfg(feature = "alloc")]
@@ -93,6 +115,10 @@ impl serde::de::Error for AserError {
     fn custom<T: Display>(msg: T) -> Self {
         Self::DeserializeMessage(msg.to_string())
     }
+
+    fn invalid_type(unexpected: serde::de::Unexpected, expected: &dyn serde::de::Expected) -> Self {
+        Self::InvalidType(alloc::format!("invalid type: {unexpected}, expected {expected}"))
+    }
 }
 
 #[cfg(not(feature = "alloc"))]
@@ -158,6 +184,12 @@ enum DataType {
     VariantValue = 31,
     /// Followed by 16 bit index into capability array
     Capability = 32,
+    /// Enum member identified by name instead of index
+    /// 
+    /// Followed by 16 bit length of the name, and the name
+    VariantNamed = 33,
+    /// Same as `VariantValue`, but the member is identified by a 16 bit name length and name instead of an index
+    VariantNamedValue = 34,
     Filler = 0xff,
 }
 
@@ -198,29 +230,684 @@ fn set_usize(data: &mut [u8], index: usize, num: usize) -> CloneCapsResult<()> {
 
 /// Clones all the capabilities in the serialized aser data to the given capability space
 /// 
-/// Updates the capability ids in the array to be the new ids
-// FIXME: make this remove capabilities transfered on failure
+/// Updates the capability ids in the array to be the new ids.
+/// If any capability fails to clone, the ones already cloned are destroyed and the array is left unchanged.
+#[cfg(feature = "alloc")]
 pub fn clone_caps_to_cspace(cspace: CspaceTarget, data: &mut [u8]) -> CloneCapsResult<()> {
+    clone_caps_to_cspace_with_policies(cspace, data, &CapPolicies::default())
+}
+
+/// Same as [`clone_caps_to_cspace`], but each capability is cloned according to its policy in `policies`
+/// 
+/// Moved capabilities are only destroyed once every capability has been cloned, so nothing is lost if the transfer fails.
+#[cfg(feature = "alloc")]
+pub fn clone_caps_to_cspace_with_policies(mut cspace: CspaceTarget, data: &mut [u8], policies: &CapPolicies) -> CloneCapsResult<()> {
+    clone_caps_with(&mut cspace, data, policies)
+}
+
+/// Same as [`clone_caps_to_cspace_with_policies`], but capabilities are cloned into `table` instead of a capability space
+#[cfg(feature = "alloc")]
+pub fn clone_caps_with<T: CapTable + ?Sized>(table: &mut T, data: &mut [u8], policies: &CapPolicies) -> CloneCapsResult<()> {
     let cap_count = get_usize(data, 0)?;
+    let mut cloned_ids = Vec::new();
+
+    let mut clone_all = || -> CloneCapsResult<()> {
+        for i in 0..cap_count {
+            let cap_id = CapId::try_from(get_usize(data, i + 1)?)
+                .ok_or(AserCloneCapsError::InvalidCapabilityId)?;
+
+            let new_cap_id = table.clone_in(cap_id, policies.get(i).clone_flags())?;
 
-    for i in 1..(cap_count + 1) {
-        let cap = get_usize(data, i)?;
+            cloned_ids.push(new_cap_id);
+        }
 
-        let cap_id = CapId::try_from(cap)
-            .ok_or(AserCloneCapsError::InvalidCapabilityId)?;
+        Ok(())
+    };
+
+    if let Err(error) = clone_all() {
+        for cap_id in cloned_ids {
+            // the capability was just created, so this only fails if the other process already destroyed it
+            let _ = table.destroy(cap_id);
+        }
+
+        return Err(error);
+    }
 
-        let new_cap_id = cap_clone_inner(
-            cspace,
-            CspaceTarget::Current,
-            cap_id,
-            CapFlags::all(),
-            CapabilityWeakness::Current,
-            false,
-        )?;
+    for (i, new_cap_id) in cloned_ids.into_iter().enumerate() {
+        // panic safety: every index was accessed while cloning
+        let old_cap_id = get_usize(data, i + 1).unwrap();
+        set_usize(data, i + 1, new_cap_id.into()).unwrap();
 
-        // panic safety: this index was just accessed
-        set_usize(data, i, new_cap_id.into()).unwrap();
+        if policies.get(i) == CapPolicy::Move {
+            // panic safety: the old id was cloned, so it is valid
+            let _ = table.destroy_source(CapId::try_from(old_cap_id).unwrap());
+        }
     }
 
     Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::mem::ManuallyDrop;
+
+    use sys::{CapFlags, Capability, Channel};
+
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Message {
+        Empty,
+        Number(u32),
+        Pair(u8, bool),
+        Named { id: u64, name: String },
+    }
+
+    fn named_bytes<T: Serialize>(data: &T) -> Vec<u8> {
+        to_bytes_count_cap_named(data).unwrap()
+    }
+
+    fn index_bytes<T: Serialize>(data: &T) -> Vec<u8> {
+        to_bytes_count_cap(data).unwrap()
+    }
+
+    #[test]
+    fn named_variants_round_trip() {
+        let messages = [
+            Message::Empty,
+            Message::Number(12),
+            Message::Pair(3, true),
+            Message::Named { id: 7, name: "seven".into() },
+        ];
+
+        for message in messages {
+            assert_eq!(from_bytes::<Message>(&named_bytes(&message)).unwrap(), message);
+            assert_eq!(from_bytes::<Message>(&index_bytes(&message)).unwrap(), message);
+        }
+
+        let result: core::result::Result<Message, Message> = Err(Message::Number(5));
+        let decoded: core::result::Result<Message, Message> = from_bytes(&named_bytes(&result)).unwrap();
+        assert_eq!(decoded, result);
+    }
+
+    mod old {
+        use super::*;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        pub enum Status {
+            Running,
+            Stopped(u32),
+            Failed { code: i32 },
+        }
+    }
+
+    mod new {
+        use super::*;
+
+        /// Same as [`old::Status`](super::old::Status), but with variants reordered and a new variant inserted
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        pub enum Status {
+            Starting,
+            Failed { code: i32 },
+            Stopped(u32),
+            Running,
+        }
+    }
+
+    #[test]
+    fn named_variants_survive_reordering() {
+        let decoded: new::Status = from_bytes(&named_bytes(&old::Status::Running)).unwrap();
+        assert_eq!(decoded, new::Status::Running);
+
+        let decoded: new::Status = from_bytes(&named_bytes(&old::Status::Stopped(4))).unwrap();
+        assert_eq!(decoded, new::Status::Stopped(4));
+
+        let decoded: new::Status = from_bytes(&named_bytes(&old::Status::Failed { code: -1 })).unwrap();
+        assert_eq!(decoded, new::Status::Failed { code: -1 });
+
+        // with indexes the old variant is silently decoded as a different variant
+        let decoded: new::Status = from_bytes(&index_bytes(&old::Status::Running)).unwrap();
+        assert_eq!(decoded, new::Status::Starting);
+    }
+
+    /// Fields are in alphabetical order, since values store maps sorted by key
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Envelope {
+        cap: CapId,
+        flags: (bool, char, i16),
+        list: Vec<Message>,
+        maybe: Option<f64>,
+        nothing: Option<u128>,
+        wrapped: Wrapper,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Wrapper(u64);
+
+    fn test_envelope() -> Envelope {
+        Envelope {
+            cap: CapId::new(sys::CapType::Channel, CapFlags::READ | CapFlags::WRITE, false, 42),
+            flags: (true, 'x', -3),
+            list: Vec::from([
+                Message::Empty,
+                Message::Number(12),
+                Message::Pair(3, false),
+                Message::Named { id: 7, name: "seven".into() },
+            ]),
+            maybe: Some(1.5),
+            nothing: None,
+            wrapped: Wrapper(u64::MAX),
+        }
+    }
+
+    /// Dropping a capability struct destroys its capability, so channels made with this should not be dropped
+    fn test_channel(base_id: usize, flags: CapFlags) -> Channel {
+        Channel::from_cap_id(CapId::new(sys::CapType::Channel, flags, false, base_id)).unwrap()
+    }
+
+    #[test]
+    fn cap_wrappers_serialize_like_capabilities() {
+        let plain = ManuallyDrop::new((test_channel(1, CapFlags::all()), 5u8, test_channel(2, CapFlags::READ)));
+        let plain_bytes = index_bytes(&*plain);
+
+        let shared = from_bytes::<CapShare<Channel>>(&index_bytes(&plain.2)).unwrap();
+        assert_eq!(shared.flags(), CapFlags::READ);
+
+        let wrapped = ManuallyDrop::new((CapMove::new(test_channel(1, CapFlags::all())), 5u8, shared));
+        let (wrapped_bytes, policies) = to_bytes_with_policies::<_, Vec<u8>>(&*wrapped).unwrap();
+        assert_eq!(wrapped_bytes, plain_bytes);
+        assert_eq!(policies.get(0), CapPolicy::Move);
+        assert_eq!(policies.get(1), CapPolicy::Share(CapFlags::READ));
+        assert_eq!(to_bytes_with_policies::<_, Vec<u8>>(&*plain).unwrap().1, CapPolicies::default());
+
+        // the reciever does not have to use the wrappers
+        let decoded = ManuallyDrop::new(from_bytes::<(Channel, u8, Channel)>(&wrapped_bytes).unwrap());
+        assert_eq!(decoded.0.cap_id(), plain.0.cap_id());
+        assert_eq!(decoded.2.cap_id(), plain.2.cap_id());
+    }
+
+    #[test]
+    fn value_forwards_messages_unchanged() {
+        let bytes = index_bytes(&test_envelope());
+
+        let value: Value = from_bytes(&bytes).unwrap();
+        assert_eq!(index_bytes(&value), bytes);
+        assert_eq!(value.into_deserialize::<Envelope>().unwrap(), test_envelope());
+    }
+
+    #[test]
+    fn value_holds_capabilities() {
+        let envelope = test_envelope();
+        let value: Value = from_bytes(&index_bytes(&envelope)).unwrap();
+
+        let Value::Map(fields) = value else {
+            panic!("struct was not deserialized as a map");
+        };
+        assert_eq!(fields.get(&Value::String("cap".into())), Some(&Value::Capability(envelope.cap)));
+    }
+
+    #[test]
+    fn value_type_mismatch_is_invalid_type() {
+        let value = Value::String("not a number".into());
+        assert_eq!(value.kind(), ValueKind::String);
+
+        assert!(matches!(value.into_deserialize::<u32>(), Err(AserError::InvalidType(_))));
+        assert!(matches!(from_bytes::<u32>(&index_bytes(&"not a number")), Err(AserError::InvalidType(_))));
+        assert_eq!(value.into_deserialize::<String>().unwrap(), "not a number");
+    }
+
+    /// Small xorshift generator, so corrupted inputs are the same every run
+    struct TestRng(u64);
+
+    impl TestRng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    #[test]
+    fn truncated_input_errors() {
+        let bytes = index_bytes(&test_envelope());
+
+        for len in 0..bytes.len() {
+            assert!(from_bytes::<Envelope>(&bytes[..len]).is_err());
+            assert!(from_bytes::<Value>(&bytes[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn corrupted_input_does_not_panic() {
+        let mut rng = TestRng(0x2545f4914f6cdd1d);
+
+        for bytes in [index_bytes(&test_envelope()), named_bytes(&test_envelope())] {
+            for _ in 0..20000 {
+                let mut corrupted = bytes.clone();
+                for _ in 0..(rng.next() % 4 + 1) {
+                    let index = rng.next() as usize % corrupted.len();
+                    corrupted[index] = rng.next() as u8;
+                }
+
+                let _ = from_bytes::<Envelope>(&corrupted);
+                let _ = from_bytes::<Value>(&corrupted);
+            }
+        }
+    }
+
+    #[test]
+    fn huge_lengths_are_rejected() {
+        let mut bytes = Vec::from(0usize.to_le_bytes());
+        bytes.push(DataType::String64 as u8);
+        bytes.extend_from_slice(&(1u64 << 60).to_le_bytes());
+        bytes.extend_from_slice(b"short");
+        assert!(matches!(from_bytes::<String>(&bytes), Err(AserError::EndOfInput)));
+
+        // capability table size overflows
+        let bytes = Vec::from(usize::MAX.to_le_bytes());
+        assert!(matches!(from_bytes::<Value>(&bytes), Err(AserError::EndOfInput)));
+
+        let mut bytes = Vec::from(1usize.to_le_bytes());
+        bytes.extend_from_slice(&5u64.to_le_bytes());
+        bytes.push(DataType::Capability as u8);
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        assert!(matches!(from_bytes::<Value>(&bytes), Err(AserError::InvalidCapabilityIndex)));
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let mut bytes = Vec::from(0usize.to_le_bytes());
+        bytes.resize(bytes.len() + 100_000, DataType::SequenceStart as u8);
+        assert!(matches!(from_bytes::<Value>(&bytes), Err(AserError::DepthLimitExceeded)));
+
+        let mut bytes = Vec::from(0usize.to_le_bytes());
+        bytes.resize(bytes.len() + 100_000, DataType::Some as u8);
+        assert!(matches!(from_bytes::<Value>(&bytes), Err(AserError::DepthLimitExceeded)));
+
+        // nesting within the limit still works
+        let nested = Some(Some(Some(Vec::from([Vec::from([1u8])]))));
+        assert_eq!(from_bytes::<Option<Option<Option<Vec<Vec<u8>>>>>>(&index_bytes(&nested)).unwrap(), nested);
+    }
+
+    #[test]
+    fn element_limit_is_enforced() {
+        let limits = DeserializerLimits {
+            max_elements: 4,
+            ..Default::default()
+        };
+
+        let bytes = index_bytes(&[1u8, 2, 3, 4]);
+        assert!(from_bytes_with_limits::<Vec<u8>>(&bytes, limits).is_ok());
+
+        let bytes = index_bytes(&[1u8, 2, 3, 4, 5]);
+        assert!(matches!(from_bytes_with_limits::<Vec<u8>>(&bytes, limits), Err(AserError::ElementLimitExceeded)));
+    }
+
+    #[test]
+    fn unknown_variant_name_fails() {
+        let bytes = named_bytes(&new::Status::Starting);
+        assert!(from_bytes::<old::Status>(&bytes).is_err());
+    }
+
+    /// Byte buffer which fails to grow after a fixed number of writes, like an allocator that hit its memory limit
+    #[derive(Default)]
+    struct LimitedBuf {
+        data: Vec<u8>,
+        writes_left: usize,
+    }
+
+    impl ByteBuf for LimitedBuf {
+        fn push(&mut self, byte: u8) -> Result<()> {
+            self.extend_from_slice(&[byte])
+        }
+
+        fn extend_from_slice(&mut self, slice: &[u8]) -> Result<()> {
+            self.writes_left = self.writes_left.checked_sub(1).ok_or(AserError::OutOfMemory)?;
+            self.data.extend_from_slice(slice);
+            Ok(())
+        }
+
+        fn as_slice(&mut self) -> &mut [u8] {
+            &mut self.data
+        }
+
+        fn len(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    /// Serialized with [`collect_str`](serde::Serializer::collect_str), which writes through [`core::fmt::Write`]
+    struct Displayed(u64);
+
+    impl Serialize for Displayed {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error> {
+            serializer.collect_str(&self.0)
+        }
+    }
+
+    #[test]
+    fn out_of_memory_fails_serialization() {
+        let envelope = test_envelope();
+        let data = (&envelope, Displayed(12345));
+        let num_capabilities = count_capabilties(&data).unwrap();
+
+        let mut writes_needed = 0;
+        loop {
+            let buf = LimitedBuf {
+                writes_left: writes_needed,
+                ..Default::default()
+            };
+
+            match to_byte_buf(&data, num_capabilities, buf) {
+                Ok(buf) => {
+                    assert_eq!(buf.data, index_bytes(&data));
+                    break;
+                },
+                Err(error) => assert!(matches!(error, AserError::OutOfMemory), "unexpected error: {error}"),
+            }
+
+            writes_needed += 1;
+        }
+
+        assert!(writes_needed > 0);
+    }
+
+    /// Capability table which keeps capabilities in vecs instead of making system calls
+    #[derive(Debug, Default)]
+    struct VecCapTable {
+        /// Capabilities in the current capability space
+        source: Vec<CapId>,
+        /// Capabilities which were cloned into this table
+        cloned: Vec<CapId>,
+        /// Number of clones which succeed before the rest fail with [`SysErr::OutOfMem`]
+        clones_left: usize,
+        next_base_id: usize,
+    }
+
+    impl VecCapTable {
+        fn with_source(source: Vec<CapId>) -> Self {
+            VecCapTable {
+                source,
+                clones_left: usize::MAX,
+                next_base_id: 1000,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl CapTable for VecCapTable {
+        fn clone_in(&mut self, cap_id: CapId, flags: CapFlags) -> sys::KResult<CapId> {
+            if !self.source.contains(&cap_id) {
+                return Err(SysErr::InvlId);
+            }
+            self.clones_left = self.clones_left.checked_sub(1).ok_or(SysErr::OutOfMem)?;
+
+            let new_cap_id = CapId::new(cap_id.cap_type(), cap_id.flags() & flags, cap_id.is_weak(), self.next_base_id);
+            self.next_base_id += 1;
+            self.cloned.push(new_cap_id);
+
+            Ok(new_cap_id)
+        }
+
+        fn destroy(&mut self, cap_id: CapId) -> sys::KResult<()> {
+            let index = self.cloned.iter().position(|id| *id == cap_id).ok_or(SysErr::InvlId)?;
+            self.cloned.remove(index);
+            Ok(())
+        }
+
+        fn destroy_source(&mut self, cap_id: CapId) -> sys::KResult<()> {
+            let index = self.source.iter().position(|id| *id == cap_id).ok_or(SysErr::InvlId)?;
+            self.source.remove(index);
+            Ok(())
+        }
+    }
+
+    /// Serializes a cloned, a moved, and a shared channel, and returns the channels' ids with the message
+    fn policy_message() -> (Vec<CapId>, Vec<u8>, CapPolicies) {
+        let shared = from_bytes::<CapShare<Channel>>(&index_bytes(&*ManuallyDrop::new(test_channel(3, CapFlags::READ)))).unwrap();
+        let channels = ManuallyDrop::new((
+            test_channel(1, CapFlags::all()),
+            CapMove::new(test_channel(2, CapFlags::all())),
+            shared,
+        ));
+        let (bytes, policies) = to_bytes_with_policies::<_, Vec<u8>>(&*channels).unwrap();
+
+        let cap_ids = Vec::from([channels.0.cap_id(), channels.1.inner().cap_id(), channels.2.inner().cap_id()]);
+        (cap_ids, bytes, policies)
+    }
+
+    #[test]
+    fn caps_are_cloned_into_table_with_policies() {
+        let (cap_ids, mut bytes, policies) = policy_message();
+        let mut table = VecCapTable::with_source(cap_ids.clone());
+
+        clone_caps_with(&mut table, &mut bytes, &policies).unwrap();
+        assert_eq!(table.cloned.len(), 3);
+
+        // the message now refers to the clones
+        let decoded = ManuallyDrop::new(from_bytes::<(Channel, Channel, Channel)>(&bytes).unwrap());
+        assert_eq!([decoded.0.cap_id(), decoded.1.cap_id(), decoded.2.cap_id()], *table.cloned);
+        assert_eq!(decoded.0.cap_id().flags(), CapFlags::all());
+        assert_eq!(decoded.2.cap_id().flags(), CapFlags::READ);
+
+        // only the moved capability is gone from the source
+        assert_eq!(table.source, [cap_ids[0], cap_ids[2]]);
+    }
+
+    #[test]
+    fn failed_clone_is_rolled_back() {
+        let (cap_ids, mut bytes, policies) = policy_message();
+        let original_bytes = bytes.clone();
+        let mut table = VecCapTable {
+            clones_left: 2,
+            ..VecCapTable::with_source(cap_ids.clone())
+        };
+
+        let result = clone_caps_with(&mut table, &mut bytes, &policies);
+        assert!(matches!(result, Err(AserCloneCapsError::SysErr(SysErr::OutOfMem))));
+
+        assert!(table.cloned.is_empty());
+        assert_eq!(table.source, cap_ids);
+        assert_eq!(bytes, original_bytes);
+    }
+
+    #[test]
+    fn finished_transfer_destroys_moved_sources() {
+        let (cap_ids, bytes, policies) = policy_message();
+        let mut table = VecCapTable::with_source(cap_ids.clone());
+
+        policies.finish_transfer_with(&mut table, &bytes);
+        assert_eq!(table.source, [cap_ids[0], cap_ids[2]]);
+        assert!(table.cloned.is_empty());
+    }
+
+    /// Property tests, which need std for proptest
+    #[cfg(feature = "std")]
+    mod properties {
+        use alloc::boxed::Box;
+        use alloc::collections::BTreeMap;
+
+        use proptest::collection::{btree_map, vec};
+        use proptest::prelude::*;
+
+        use super::*;
+
+        fn string() -> impl Strategy<Value = String> {
+            vec(any::<char>(), 0..16).prop_map(|chars| chars.into_iter().collect())
+        }
+
+        fn cap_id() -> impl Strategy<Value = CapId> {
+            (1usize..=22, 0usize..16, any::<bool>(), 0..=CapId::MAX_BASE_ID).prop_map(|(cap_type, flags, is_weak, base_id)| {
+                CapId::new(sys::CapType::from(cap_type).unwrap(), CapFlags::from_bits_truncate(flags), is_weak, base_id)
+            })
+        }
+
+        fn message() -> impl Strategy<Value = Message> {
+            prop_oneof![
+                Just(Message::Empty),
+                any::<u32>().prop_map(Message::Number),
+                any::<(u8, bool)>().prop_map(|(a, b)| Message::Pair(a, b)),
+                (any::<u64>(), string()).prop_map(|(id, name)| Message::Named { id, name }),
+            ]
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Record {
+            small: (i8, u16, i32),
+            large: (i64, u128, i128),
+            letter: char,
+            maybe: Option<Vec<u16>>,
+            cap: CapId,
+            messages: Vec<Message>,
+            table: BTreeMap<String, Wrapper>,
+        }
+
+        fn record() -> impl Strategy<Value = Record> {
+            (
+                any::<(i8, u16, i32)>(),
+                any::<(i64, u128, i128)>(),
+                any::<char>(),
+                any::<Option<Vec<u16>>>(),
+                cap_id(),
+                vec(message(), 0..8),
+                btree_map(string(), any::<u64>().prop_map(Wrapper), 0..8),
+            ).prop_map(|(small, large, letter, maybe, cap, messages, table)| Record {
+                small,
+                large,
+                letter,
+                maybe,
+                cap,
+                messages,
+                table,
+            })
+        }
+
+        fn integer() -> impl Strategy<Value = Integer> {
+            prop_oneof![
+                any::<i8>().prop_map(Integer::I8),
+                any::<i16>().prop_map(Integer::I16),
+                any::<i32>().prop_map(Integer::I32),
+                any::<i64>().prop_map(Integer::I64),
+                any::<i128>().prop_map(Integer::I128),
+                any::<u8>().prop_map(Integer::U8),
+                any::<u16>().prop_map(Integer::U16),
+                any::<u32>().prop_map(Integer::U32),
+                any::<u64>().prop_map(Integer::U64),
+                any::<u128>().prop_map(Integer::U128),
+            ]
+        }
+
+        fn value() -> impl Strategy<Value = Value> {
+            let leaf = prop_oneof![
+                Just(Value::Null),
+                any::<bool>().prop_map(Value::Bool),
+                integer().prop_map(Value::Integer),
+                any::<f32>().prop_map(|n| Value::Float(Float::F32(n))),
+                any::<f64>().prop_map(|n| Value::Float(Float::F64(n))),
+                any::<char>().prop_map(Value::Char),
+                string().prop_map(Value::String),
+                vec(any::<u8>(), 0..32).prop_map(Value::Bytes),
+                cap_id().prop_map(Value::Capability),
+            ];
+
+            leaf.prop_recursive(4, 64, 8, |inner| prop_oneof![
+                vec(inner.clone(), 0..8).prop_map(Value::Sequence),
+                btree_map(inner.clone(), inner.clone(), 0..8).prop_map(Value::Map),
+                inner.clone().prop_map(|value| Value::Newtype(Box::new(value))),
+                inner.clone().prop_map(|value| Value::Some(Box::new(value))),
+                // large variant indexes are reserved for capabilities
+                (0u32..1000, inner).prop_map(|(variant_index, value)| Value::EnumVariant {
+                    variant_index,
+                    value: Box::new(value),
+                }),
+            ])
+        }
+
+        proptest! {
+            #[test]
+            fn typed_values_round_trip(record in record()) {
+                prop_assert_eq!(&from_bytes::<Record>(&index_bytes(&record)).unwrap(), &record);
+                prop_assert_eq!(&from_bytes::<Record>(&named_bytes(&record)).unwrap(), &record);
+            }
+
+            #[test]
+            fn values_round_trip(value in value()) {
+                let bytes = index_bytes(&value);
+                let decoded: Value = from_bytes(&bytes).unwrap();
+
+                prop_assert_eq!(&decoded, &value);
+                prop_assert_eq!(index_bytes(&decoded), bytes);
+            }
+
+            #[test]
+            fn arbitrary_input_does_not_panic(bytes in vec(any::<u8>(), 0..256)) {
+                let _ = from_bytes::<Value>(&bytes);
+                let _ = from_bytes::<Record>(&bytes);
+            }
+
+            #[test]
+            fn corrupted_values_do_not_panic(value in value(), corruptions in vec((any::<usize>(), any::<u8>()), 1..8)) {
+                let mut bytes = index_bytes(&value);
+                for (index, byte) in corruptions {
+                    let len = bytes.len();
+                    bytes[index % len] = byte;
+                }
+
+                let _ = from_bytes::<Value>(&bytes);
+            }
+
+            #[test]
+            fn serializer_only_fails_when_out_of_memory(value in value(), writes_left in 0usize..64) {
+                let num_capabilities = count_capabilties(&value).unwrap();
+                let buf = LimitedBuf {
+                    writes_left,
+                    ..Default::default()
+                };
+
+                match to_byte_buf(&value, num_capabilities, buf) {
+                    Ok(buf) => prop_assert_eq!(buf.data, index_bytes(&value)),
+                    Err(error) => prop_assert!(matches!(error, AserError::OutOfMemory), "unexpected error: {}", error),
+                }
+            }
+
+            #[test]
+            fn clone_failures_leave_nothing_behind(
+                policies in vec(prop_oneof![Just(CapPolicy::Clone), Just(CapPolicy::Move), Just(CapPolicy::Share(CapFlags::READ))], 0..8),
+                clones_left in 0usize..10,
+            ) {
+                let cap_ids: Vec<CapId> = (0..policies.len())
+                    .map(|i| CapId::new(sys::CapType::Channel, CapFlags::all(), false, i + 1))
+                    .collect();
+                let mut cap_policies = CapPolicies::default();
+                for (i, policy) in policies.iter().enumerate() {
+                    cap_policies.set(i, *policy);
+                }
+
+                let mut bytes = index_bytes(&cap_ids);
+                let original_bytes = bytes.clone();
+                let mut table = VecCapTable {
+                    clones_left,
+                    ..VecCapTable::with_source(cap_ids.clone())
+                };
+
+                if clone_caps_with(&mut table, &mut bytes, &cap_policies).is_ok() {
+                    prop_assert!(clones_left >= cap_ids.len());
+                    prop_assert_eq!(from_bytes::<Vec<CapId>>(&bytes).unwrap(), table.cloned.clone());
+
+                    let kept: Vec<CapId> = cap_ids.iter().zip(&policies)
+                        .filter(|(_, policy)| **policy != CapPolicy::Move)
+                        .map(|(cap_id, _)| *cap_id)
+                        .collect();
+                    prop_assert_eq!(table.source, kept);
+                } else {
+                    prop_assert!(clones_left < cap_ids.len());
+                    prop_assert!(table.cloned.is_empty());
+                    prop_assert_eq!(table.source, cap_ids);
+                    prop_assert_eq!(bytes, original_bytes);
+                }
+            }
+        }
+    }
 }
\ No newline at end of file
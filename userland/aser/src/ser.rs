@@ -1,27 +1,69 @@
 use core::fmt::Write;
 
 use serde::{ser, Serialize};
-use sys::CapId;
+use sys::{CapId, CapFlags};
 
 use crate::ByteBuf;
+use crate::cap_policy::{CapPolicy, CAP_MOVE_NEWTYPE_NAME, CAP_SHARE_NEWTYPE_NAME};
+#[cfg(feature = "alloc")]
+use crate::cap_policy::CapPolicies;
 
 use super::{AserError, DataType, capability_serializer::CapabilitySerializer, count_capabilties, get_usize, set_usize};
 
-pub fn to_bytes<T: Serialize, B: ByteBuf>(data: &T, num_capabilities: usize) -> Result<B, AserError> {
-    let mut serializer = Serializer::new(num_capabilities);
+pub fn to_bytes<T: Serialize, B: ByteBuf + Default>(data: &T, num_capabilities: usize) -> Result<B, AserError> {
+    to_byte_buf(data, num_capabilities, B::default())
+}
+
+/// Serializes `data` into `buf`, which must be empty
+pub fn to_byte_buf<T: Serialize, B: ByteBuf>(data: &T, num_capabilities: usize, buf: B) -> Result<B, AserError> {
+    serialize_into(data, Serializer::with_buf(num_capabilities, buf)?)
+}
+
+pub fn to_bytes_count_cap<T: Serialize, B: ByteBuf + Default>(data: &T) -> Result<B, AserError> {
+    let num_capabilities = count_capabilties(data)?;
+    to_bytes(data, num_capabilities)
+}
+
+/// Same as [`to_bytes`], but enum variants are encoded by name, see [`Serializer::with_named_variants`]
+pub fn to_bytes_named<T: Serialize, B: ByteBuf + Default>(data: &T, num_capabilities: usize) -> Result<B, AserError> {
+    to_byte_buf_named(data, num_capabilities, B::default())
+}
+
+/// Same as [`to_byte_buf`], but enum variants are encoded by name, see [`Serializer::with_named_variants`]
+pub fn to_byte_buf_named<T: Serialize, B: ByteBuf>(data: &T, num_capabilities: usize, buf: B) -> Result<B, AserError> {
+    serialize_into(data, Serializer::with_buf(num_capabilities, buf)?.with_named_variants())
+}
+
+/// Same as [`to_bytes_count_cap`], but enum variants are encoded by name, see [`Serializer::with_named_variants`]
+pub fn to_bytes_count_cap_named<T: Serialize, B: ByteBuf + Default>(data: &T) -> Result<B, AserError> {
+    let num_capabilities = count_capabilties(data)?;
+    to_bytes_named(data, num_capabilities)
+}
+
+/// Same as [`to_bytes_count_cap`], but also returns the policy of each capability, see the [`cap_policy`](crate::cap_policy) module
+#[cfg(feature = "alloc")]
+pub fn to_bytes_with_policies<T: Serialize, B: ByteBuf + Default>(data: &T) -> Result<(B, CapPolicies), AserError> {
+    let mut serializer = Serializer::new(count_capabilties(data)?)?;
     data.serialize(&mut serializer)?;
 
     let Serializer {
         buf,
+        cap_policies,
         ..
     } = serializer;
 
-    Ok(buf)
+    Ok((buf, cap_policies))
 }
 
-pub fn to_bytes_count_cap<T: Serialize, B: ByteBuf>(data: &T) -> Result<B, AserError> {
-    let num_capabilities = count_capabilties(data)?;
-    to_bytes(data, num_capabilities)
+fn serialize_into<T: Serialize, B: ByteBuf>(data: &T, mut serializer: Serializer<B>) -> Result<B, AserError> {
+    data.serialize(&mut serializer)?;
+
+    let Serializer {
+        buf,
+        ..
+    } = serializer;
+
+    Ok(buf)
 }
 
 pub struct Serializer<B: ByteBuf> {
@@ -29,44 +71,98 @@ pub struct Serializer<B: ByteBuf> {
     capability_index: usize,
     /// Offset from start of array to data, beginning of array contains capabilities, and first 8 byte cap count
     data_offset: usize,
+    /// Encode enum variants by name instead of index
+    named_variants: bool,
+    /// Error from the buffer while writing formatted output, since [`Write`] can't return it
+    write_error: Option<AserError>,
+    /// Policies of capabilities wrapped in [`CapMove`](crate::CapMove) or [`CapShare`](crate::CapShare)
+    #[cfg(feature = "alloc")]
+    cap_policies: CapPolicies,
     buf: B,
 }
 
-impl<B: ByteBuf> Serializer<B> {
-    pub fn new(num_capabilties: usize) -> Self {
-        let mut buf = B::default();
+impl<B: ByteBuf + Default> Serializer<B> {
+    pub fn new(num_capabilties: usize) -> Result<Self, AserError> {
+        Self::with_buf(num_capabilties, B::default())
+    }
+}
 
-        buf.extend_from_slice(&0usize.to_le_bytes());
+impl<B: ByteBuf> Serializer<B> {
+    /// Creates a serializer which writes into `buf`, `buf` must be empty
+    pub fn with_buf(num_capabilties: usize, mut buf: B) -> Result<Self, AserError> {
+        buf.extend_from_slice(&0usize.to_le_bytes())?;
         for _ in 0..(num_capabilties * 8) {
             // filler byte for capabilties that end up being not set
-            buf.push(DataType::Filler as u8);
+            buf.push(DataType::Filler as u8)?;
         }
 
-        Serializer {
+        Ok(Serializer {
             capability_index: 1,
             data_offset: buf.len(),
+            named_variants: false,
+            write_error: None,
+            #[cfg(feature = "alloc")]
+            cap_policies: CapPolicies::default(),
             buf,
-        }
+        })
+    }
+
+    /// Makes the serializer encode enum variants by name instead of by index
+    /// 
+    /// This makes the output larger, but it can still be deserialized after variants of an enum are reordered or inserted,
+    /// so it is useful for messages between processes which may be built from different versions of the enum.
+    /// [`Deserializer`](crate::Deserializer) accepts both encodings.
+    /// 
+    /// [`Value`](crate::Value) only supports variants encoded by index.
+    pub fn with_named_variants(mut self) -> Self {
+        self.named_variants = true;
+        self
     }
 
-    fn push_u16(&mut self, val: u16) {
-        self.buf.extend_from_slice(&val.to_le_bytes());
+    fn push_u16(&mut self, val: u16) -> Result<(), AserError> {
+        self.buf.extend_from_slice(&val.to_le_bytes())
     }
 
-    fn push_u32(&mut self, val: u32) {
-        self.buf.extend_from_slice(&val.to_le_bytes());
+    fn push_u32(&mut self, val: u32) -> Result<(), AserError> {
+        self.buf.extend_from_slice(&val.to_le_bytes())
     }
 
-    fn push_u64(&mut self, val: u64) {
-        self.buf.extend_from_slice(&val.to_le_bytes());
+    fn push_u64(&mut self, val: u64) -> Result<(), AserError> {
+        self.buf.extend_from_slice(&val.to_le_bytes())
     }
 
-    fn push_u128(&mut self, val: u128) {
-        self.buf.extend_from_slice(&val.to_le_bytes());
+    fn push_u128(&mut self, val: u128) -> Result<(), AserError> {
+        self.buf.extend_from_slice(&val.to_le_bytes())
     }
 
-    fn push_type(&mut self, data_type: DataType) {
-        self.buf.push(data_type.into());
+    fn push_type(&mut self, data_type: DataType) -> Result<(), AserError> {
+        self.buf.push(data_type.into())
+    }
+
+    /// Pushes the type and identifier of an enum variant, `has_data` is true if a value follows the variant
+    fn push_variant(&mut self, variant_index: u32, variant: &str, has_data: bool) -> Result<(), AserError> {
+        if self.named_variants {
+            if has_data {
+                self.push_type(DataType::VariantNamedValue)?;
+            } else {
+                self.push_type(DataType::VariantNamed)?;
+            }
+
+            // variant names are identifiers, so they are never anywhere close to this long
+            let name = &variant.as_bytes()[..variant.len().min(u16::MAX as usize)];
+            self.push_u16(name.len() as u16)?;
+            self.buf.extend_from_slice(name)?;
+        } else {
+            if has_data {
+                self.push_type(DataType::VariantValue)?;
+            } else {
+                self.push_type(DataType::Variant)?;
+            }
+
+            self.push_u32(variant_index)?;
+        }
+
+        Ok(())
     }
 
     fn push_capability(&mut self, cap_id: u64) -> Result<(), AserError> {
@@ -83,22 +179,43 @@ impl<B: ByteBuf> Serializer<B> {
 
         Ok(())
     }
+
+    /// Serializes `value` like normal, and records `policy` for the capability it holds
+    ///
+    /// `value` must hold exactly 1 capability.
+    fn serialize_with_policy<T: Serialize + ?Sized>(&mut self, policy: CapPolicy, value: &T) -> Result<(), AserError> {
+        let cap_index = self.capability_index;
+        value.serialize(&mut *self)?;
+
+        match self.capability_index - cap_index {
+            0 => Err(AserError::ExpectedCapablity),
+            1 => {
+                #[cfg(feature = "alloc")]
+                self.cap_policies.set(cap_index - 1, policy);
+                #[cfg(not(feature = "alloc"))]
+                let _ = policy;
+
+                Ok(())
+            },
+            _ => Err(AserError::MultipleCapabilties),
+        }
+    }
 }
 
 macro_rules! push_correct_size_type {
     ($self:expr, $size:expr, $t8:expr, $t16:expr, $t32:expr, $t64:expr) => {
         if $size <= u8::MAX as usize {
-            $self.push_type($t8);
-            $self.buf.push($size as u8);
+            $self.push_type($t8)?;
+            $self.buf.push($size as u8)?;
         } else if $size <= u16::MAX as usize {
-            $self.push_type($t16);
-            $self.push_u16($size as u16);
+            $self.push_type($t16)?;
+            $self.push_u16($size as u16)?;
         } else if $size <= u32::MAX as usize {
-            $self.push_type($t32);
-            $self.push_u32($size as u32);
+            $self.push_type($t32)?;
+            $self.push_u32($size as u32)?;
         } else {
-            $self.push_type($t64);
-            $self.push_u64($size as u64);
+            $self.push_type($t64)?;
+            $self.push_u64($size as u64)?;
         }
     };
 }
@@ -121,102 +238,102 @@ impl<'a, B: ByteBuf> ser::Serializer for &'a mut Serializer<B> {
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
         if v {
-            self.push_type(DataType::True);
+            self.push_type(DataType::True)?;
         } else {
-            self.push_type(DataType::False);
+            self.push_type(DataType::False)?;
         }
 
         Ok(())
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::I8);
-        self.buf.push(v as u8);
+        self.push_type(DataType::I8)?;
+        self.buf.push(v as u8)?;
 
         Ok(())
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::I16);
-        self.push_u16(v as u16);
+        self.push_type(DataType::I16)?;
+        self.push_u16(v as u16)?;
 
         Ok(())
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::I32);
-        self.push_u32(v as u32);
+        self.push_type(DataType::I32)?;
+        self.push_u32(v as u32)?;
 
         Ok(())
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::I64);
-        self.push_u64(v as u64);
+        self.push_type(DataType::I64)?;
+        self.push_u64(v as u64)?;
 
         Ok(())
     }
 
     fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::I128);
-        self.push_u128(v as u128);
+        self.push_type(DataType::I128)?;
+        self.push_u128(v as u128)?;
 
         Ok(())
     }
 
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::U8);
-        self.buf.push(v);
+        self.push_type(DataType::U8)?;
+        self.buf.push(v)?;
 
         Ok(())
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::U16);
-        self.push_u16(v);
+        self.push_type(DataType::U16)?;
+        self.push_u16(v)?;
 
         Ok(())
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::U32);
-        self.push_u32(v);
+        self.push_type(DataType::U32)?;
+        self.push_u32(v)?;
 
         Ok(())
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::U64);
-        self.push_u64(v);
+        self.push_type(DataType::U64)?;
+        self.push_u64(v)?;
 
         Ok(())
     }
 
     fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::U128);
-        self.push_u128(v);
+        self.push_type(DataType::U128)?;
+        self.push_u128(v)?;
 
         Ok(())
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::F32);
-        self.push_u32(v.to_bits());
+        self.push_type(DataType::F32)?;
+        self.push_u32(v.to_bits())?;
 
         Ok(())
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::F64);
-        self.push_u64(v.to_bits());
+        self.push_type(DataType::F64)?;
+        self.push_u64(v.to_bits())?;
 
         Ok(())
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::Char);
-        self.push_u32(v as u32);
+        self.push_type(DataType::Char)?;
+        self.push_u32(v as u32)?;
 
         Ok(())
     }
@@ -233,7 +350,7 @@ impl<'a, B: ByteBuf> ser::Serializer for &'a mut Serializer<B> {
             DataType::String64
         );
 
-        self.buf.extend_from_slice(data);
+        self.buf.extend_from_slice(data)?;
 
         Ok(())
     }
@@ -248,13 +365,13 @@ impl<'a, B: ByteBuf> ser::Serializer for &'a mut Serializer<B> {
             DataType::Bytes64
         );
 
-        self.buf.extend_from_slice(v);
+        self.buf.extend_from_slice(v)?;
 
         Ok(())
     }
 
     fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::Null);
+        self.push_type(DataType::Null)?;
 
         Ok(())
     }
@@ -266,7 +383,7 @@ impl<'a, B: ByteBuf> ser::Serializer for &'a mut Serializer<B> {
     fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
         T: serde::Serialize {
-        self.push_type(DataType::Some);
+        self.push_type(DataType::Some)?;
         value.serialize(self)
     }
 
@@ -278,52 +395,57 @@ impl<'a, B: ByteBuf> ser::Serializer for &'a mut Serializer<B> {
         self,
         _name: &'static str,
         variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
-        self.push_type(DataType::Variant);
-        self.push_u32(variant_index);
+        self.push_variant(variant_index, variant, false)?;
 
         Ok(())
     }
 
     fn serialize_newtype_struct<T: ?Sized>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
         T: serde::Serialize {
-        self.push_type(DataType::Newtype);
+        if name == CAP_MOVE_NEWTYPE_NAME {
+            return self.serialize_with_policy(CapPolicy::Move, value);
+        }
+
+        self.push_type(DataType::Newtype)?;
         value.serialize(self)
     }
 
     fn serialize_newtype_variant<T: ?Sized>(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
         T: serde::Serialize {
-        if variant_index == CapId::SERIALIZE_ENUM_VARIANT {
-            self.push_type(DataType::Capability);
-            self.push_u16(self.capability_index as u16 - 1);
+        if name == CAP_SHARE_NEWTYPE_NAME {
+            let flags = CapFlags::from_bits_truncate(variant_index as usize);
+            self.serialize_with_policy(CapPolicy::Share(flags), value)
+        } else if variant_index == CapId::SERIALIZE_ENUM_VARIANT {
+            self.push_type(DataType::Capability)?;
+            self.push_u16(self.capability_index as u16 - 1)?;
 
             let mut capability_serializer = CapabilitySerializer::default();
             value.serialize(&mut capability_serializer)?;
             
             self.push_capability(capability_serializer.get_capability()?)
         } else {
-            self.push_type(DataType::VariantValue);
-            self.push_u32(variant_index);
+            self.push_variant(variant_index, variant, true)?;
 
             value.serialize(self)
         }
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        self.push_type(DataType::SequenceStart);
+        self.push_type(DataType::SequenceStart)?;
 
         Ok(self)
     }
@@ -344,17 +466,16 @@ impl<'a, B: ByteBuf> ser::Serializer for &'a mut Serializer<B> {
         self,
         _name: &'static str,
         variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        self.push_type(DataType::VariantValue);
-        self.push_u32(variant_index);
+        self.push_variant(variant_index, variant, true)?;
 
         self.serialize_seq(Some(len))
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        self.push_type(DataType::MapStart);
+        self.push_type(DataType::MapStart)?;
 
         Ok(self)
     }
@@ -371,11 +492,10 @@ impl<'a, B: ByteBuf> ser::Serializer for &'a mut Serializer<B> {
         self,
         _name: &'static str,
         variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
         len: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        self.push_type(DataType::VariantValue);
-        self.push_u32(variant_index);
+        self.push_variant(variant_index, variant, true)?;
 
         self.serialize_map(Some(len))
     }
@@ -384,14 +504,16 @@ impl<'a, B: ByteBuf> ser::Serializer for &'a mut Serializer<B> {
     where
         T: core::fmt::Display {
         // since we don't know the size of the string yet, use 64 byte size to write it
-        self.push_type(DataType::String64);
+        self.push_type(DataType::String64)?;
         
         let size_index = self.buf.len();
         // for now specify size of 0
-        self.push_u64(0);
+        self.push_u64(0)?;
 
         let start_write_index = self.buf.len();
-        write!(self, "{}", value).or(Err(AserError::FormattingError))?;
+        if write!(self, "{}", value).is_err() {
+            return Err(self.write_error.take().unwrap_or(AserError::FormattingError));
+        }
         let end_write_index = self.buf.len();
 
         let write_size = end_write_index - start_write_index;
@@ -408,9 +530,10 @@ impl<'a, B: ByteBuf> Write for Serializer<B> {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         let data = s.as_bytes();
 
-        self.buf.extend_from_slice(data);
-
-        Ok(())
+        self.buf.extend_from_slice(data).map_err(|error| {
+            self.write_error = Some(error);
+            core::fmt::Error
+        })
     }
 }
 
@@ -426,7 +549,7 @@ impl<'a, B: ByteBuf> ser::SerializeSeq for &'a mut Serializer<B> {
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
         // TODO: figure out if this will always be used
-        self.push_type(DataType::SequenceEnd);
+        self.push_type(DataType::SequenceEnd)?;
 
         Ok(())
     }
@@ -444,7 +567,7 @@ impl<'a, B: ByteBuf> ser::SerializeTuple for &'a mut Serializer<B> {
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
         // TODO: figure out if this will always be used
-        self.push_type(DataType::SequenceEnd);
+        self.push_type(DataType::SequenceEnd)?;
 
         Ok(())
     }
@@ -462,7 +585,7 @@ impl<'a, B: ByteBuf> ser::SerializeTupleStruct for &'a mut Serializer<B> {
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
         // TODO: figure out if this will always be used
-        self.push_type(DataType::SequenceEnd);
+        self.push_type(DataType::SequenceEnd)?;
 
         Ok(())
     }
@@ -480,7 +603,7 @@ impl<'a, B: ByteBuf> ser::SerializeTupleVariant for &'a mut Serializer<B> {
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
         // TODO: figure out if this will always be used
-        self.push_type(DataType::SequenceEnd);
+        self.push_type(DataType::SequenceEnd)?;
 
         Ok(())
     }
@@ -504,7 +627,7 @@ impl<'a, B: ByteBuf> ser::SerializeMap for &'a mut Serializer<B> {
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
         // TODO: figure out if this will always be used
-        self.push_type(DataType::MapEnd);
+        self.push_type(DataType::MapEnd)?;
 
         Ok(())
     }
@@ -521,13 +644,14 @@ impl<'a, B: ByteBuf> ser::SerializeStruct for &'a mut Serializer<B> {
     ) -> Result<(), Self::Error>
     where
         T: serde::Serialize {
-        ser::Serializer::collect_str(&mut **self, key)?;
+        // keys are written like any other string, so a struct deserialized as a value serializes back to the same bytes
+        ser::Serializer::serialize_str(&mut **self, key)?;
         value.serialize(&mut **self)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
         // TODO: figure out if this will always be used
-        self.push_type(DataType::MapEnd);
+        self.push_type(DataType::MapEnd)?;
 
         Ok(())
     }
@@ -544,13 +668,14 @@ impl<'a, B: ByteBuf> ser::SerializeStructVariant for &'a mut Serializer<B> {
     ) -> Result<(), Self::Error>
     where
         T: serde::Serialize {
-        ser::Serializer::collect_str(&mut **self, key)?;
+        // keys are written like any other string, so a struct deserialized as a value serializes back to the same bytes
+        ser::Serializer::serialize_str(&mut **self, key)?;
         value.serialize(&mut **self)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
         // TODO: figure out if this will always be used
-        self.push_type(DataType::MapEnd);
+        self.push_type(DataType::MapEnd)?;
 
         Ok(())
     }
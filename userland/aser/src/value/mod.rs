@@ -1,4 +1,5 @@
 use core::cmp::{Ord, Ordering};
+use core::fmt;
 use alloc::collections::BTreeMap;
 use alloc::{string::String, vec::Vec, boxed::Box};
 
@@ -93,6 +94,10 @@ impl Ord for Float {
     }
 }
 
+/// Any aser message, deserialized without knowing its type
+/// 
+/// Serializing a value produces the same bytes it was deserialized from, as long as any structs and maps in it
+/// had their keys in sorted order, since maps are stored sorted by key.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Value {
     Null,
@@ -113,7 +118,69 @@ pub enum Value {
     },
 }
 
+/// The type of a [`Value`], without its data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueKind {
+    Null,
+    Bool,
+    Integer,
+    Float,
+    Char,
+    String,
+    Bytes,
+    Sequence,
+    Map,
+    Capability,
+    Newtype,
+    Some,
+    EnumVariant,
+}
+
+impl ValueKind {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Bool => "bool",
+            Self::Integer => "integer",
+            Self::Float => "float",
+            Self::Char => "char",
+            Self::String => "string",
+            Self::Bytes => "bytes",
+            Self::Sequence => "sequence",
+            Self::Map => "map",
+            Self::Capability => "capability",
+            Self::Newtype => "newtype",
+            Self::Some => "some",
+            Self::EnumVariant => "enum variant",
+        }
+    }
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 impl Value {
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Self::Null => ValueKind::Null,
+            Self::Bool(_) => ValueKind::Bool,
+            Self::Integer(_) => ValueKind::Integer,
+            Self::Float(_) => ValueKind::Float,
+            Self::Char(_) => ValueKind::Char,
+            Self::String(_) => ValueKind::String,
+            Self::Bytes(_) => ValueKind::Bytes,
+            Self::Sequence(_) => ValueKind::Sequence,
+            Self::Map(_) => ValueKind::Map,
+            Self::Capability(_) => ValueKind::Capability,
+            Self::Newtype(_) => ValueKind::Newtype,
+            Self::Some(_) => ValueKind::Some,
+            Self::EnumVariant { .. } => ValueKind::EnumVariant,
+        }
+    }
+
     pub fn from_serialize<T: Serialize>(data: &T) -> Result<Self, AserError> {
         data.serialize(value_serializer::ValueSerializer)
     }
@@ -17,6 +17,7 @@ use sys::CapId;
 
 use crate::AserError;
 use crate::capability_serializer::CapabilitySerializer;
+use crate::cap_policy::{CAP_MOVE_NEWTYPE_NAME, CAP_SHARE_NEWTYPE_NAME};
 use super::{Value, Integer, Float};
 
 pub struct ValueSerializer;
@@ -136,11 +137,16 @@ impl<'a> Serializer for ValueSerializer {
 
     fn serialize_newtype_struct<T: ?Sized>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
         T: serde::Serialize {
+        // values have nowhere to store capability policies, so the capability is stored as if it was not wrapped
+        if name == CAP_MOVE_NEWTYPE_NAME {
+            return value.serialize(self);
+        }
+
         Ok(Value::Newtype(
             Box::new(value.serialize(self)?),
         ))
@@ -148,14 +154,16 @@ impl<'a> Serializer for ValueSerializer {
 
     fn serialize_newtype_variant<T: ?Sized>(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
         T: serde::Serialize {
-        if variant_index == CapId::SERIALIZE_ENUM_VARIANT {
+        if name == CAP_SHARE_NEWTYPE_NAME {
+            value.serialize(self)
+        } else if variant_index == CapId::SERIALIZE_ENUM_VARIANT {
             let mut capability_serializer = CapabilitySerializer::default();
             value.serialize(&mut capability_serializer)?;
 
@@ -0,0 +1,271 @@
+//! A minimal shell, which runs programs read from the fs server
+//!
+//! Lines are read from standard input and split on whitespace, there is no quoting. The first word is the path of the
+//! program to run, relative paths are looked up in the current directory, and the rest of the words are passed to it as
+//! positional arguments. The program is given a copy of the shell's handle table, so it shares the shell's standard streams,
+//! and it gets the shell's registry and the `fs_server` named argument. The shell waits for the program to exit and prints
+//! its exit status, unless the line ends with `&`, in which case the exit status is printed at a later prompt.
+//!
+//! There are 2 builtins:
+//! - `cd [path]` changes the current directory, with no path it goes to the root
+//! - `exit [code]` exits the shell with `code`, which defaults to 0
+//!
+//! The fs server is passed in the `fs_server` named argument.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use core::cmp::min;
+
+use alloc::format;
+
+use aurora::collections::MessageVec;
+use aurora::env;
+use aurora::fs::{self, FileKind, FileInfo, FsAsync, FsError, fs_client, normalize_path};
+use aurora::io::{CHANNEL_MESSAGE_MAX_SIZE, flush_stdio};
+use aurora::process::{self, Child, Command, ExitStatus, PANIC_EXIT_CODE};
+use aurora::time::Instant;
+use std::prelude::*;
+use sys::{Channel, KResult, SysErr};
+
+/// Printed after the current directory to show the shell is waiting for a line
+const PROMPT_SUFFIX: &str = "$ ";
+
+/// Reads lines from a channel, where a message can hold part of a line or several lines
+struct LineReader {
+    channel: &'static Channel,
+    /// Recieved bytes which are not part of a line that has been returned yet
+    pending: Vec<u8>,
+}
+
+impl LineReader {
+    fn new(channel: &'static Channel) -> Self {
+        LineReader {
+            channel,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the next line without its newline, invalid utf-8 is replaced
+    fn read_line(&mut self) -> KResult<String> {
+        let buffer = MessageVec::try_from_slice(&[0u8; CHANNEL_MESSAGE_MAX_SIZE])?;
+
+        loop {
+            if let Some(newline) = self.pending.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.pending.drain(..=newline).collect();
+                return Ok(String::from_utf8_lossy(&line[..newline]).into_owned());
+            }
+
+            // panic safety: the buffer is not empty, so it has a message buffer
+            let recieve_result = self.channel.sync_recv(&buffer.message_buffer().unwrap(), None)?;
+            let recieve_size = min(recieve_result.recieve_size.bytes(), CHANNEL_MESSAGE_MAX_SIZE);
+            self.pending.extend_from_slice(&buffer.as_slice()[..recieve_size]);
+        }
+    }
+}
+
+/// What the shell does after running a line
+enum Flow {
+    Continue,
+    Exit(i32),
+}
+
+fn stat(path: String) -> Result<FileInfo, FsError> {
+    let fs = fs_client().ok_or(FsError::NoFsServer)?;
+    asynca::block_in_place(async move { fs.stat(path).await })
+}
+
+fn read_file(path: String) -> Result<Vec<u8>, FsError> {
+    asynca::block_in_place(async move { fs::read(&path).await })
+}
+
+/// Prints how a program exited, panics are pointed out since the exit code alone does not make it clear
+fn print_status(program: &str, status: ExitStatus) {
+    if status.code() == Some(PANIC_EXIT_CODE) {
+        eprintln!("ash: {program} panicked");
+    }
+
+    println!("[{program}: {status}]");
+}
+
+struct Shell {
+    /// Normalized absolute path which relative paths are looked up in
+    current_dir: String,
+    /// Programs started with `&` which have not exited yet
+    background: Vec<(String, Child)>,
+}
+
+impl Shell {
+    fn new() -> Self {
+        Shell {
+            current_dir: "/".to_owned(),
+            background: Vec::new(),
+        }
+    }
+
+    fn resolve_path(&self, path: &str) -> Result<String, FsError> {
+        if path.starts_with('/') {
+            normalize_path(path)
+        } else {
+            normalize_path(&format!("{}/{path}", self.current_dir))
+        }
+    }
+
+    fn run_line(&mut self, line: &str) -> Flow {
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+
+        let background = words.last() == Some(&"&");
+        if background {
+            words.pop();
+        }
+
+        let Some((&program, args)) = words.split_first() else {
+            return Flow::Continue;
+        };
+
+        match program {
+            "cd" => self.change_dir(args),
+            "exit" => return exit_flow(args),
+            _ => self.run_program(program, args, background),
+        }
+
+        Flow::Continue
+    }
+
+    fn change_dir(&mut self, args: &[&str]) {
+        let path = match args {
+            [] => "/",
+            [path] => *path,
+            _ => {
+                eprintln!("cd: too many arguments");
+                return;
+            },
+        };
+
+        let result = self.resolve_path(path).and_then(|new_dir| {
+            match stat(new_dir.clone())?.kind {
+                FileKind::Directory => Ok(new_dir),
+                FileKind::File => Err(FsError::NotDirectory),
+            }
+        });
+
+        match result {
+            Ok(new_dir) => self.current_dir = new_dir,
+            Err(error) => eprintln!("cd: {path}: {error}"),
+        }
+    }
+
+    fn run_program(&mut self, program: &str, args: &[&str], background: bool) {
+        let child = match self.spawn(program, args) {
+            Ok(child) => child,
+            Err(error) => {
+                eprintln!("ash: {error}");
+                return;
+            },
+        };
+
+        if background {
+            println!("started {program} in the background");
+            self.background.push((program.to_owned(), child));
+            return;
+        }
+
+        match child.wait() {
+            Ok(status) => print_status(program, status),
+            Err(error) => eprintln!("ash: failed to wait for {program}: {error}"),
+        }
+    }
+
+    fn spawn(&self, program: &str, args: &[&str]) -> Result<Child, String> {
+        let elf_data = self.resolve_path(program)
+            .and_then(read_file)
+            .map_err(|error| format!("{program}: {error}"))?;
+
+        let name = program.rsplit('/').next().unwrap_or(program);
+
+        let mut command = Command::from_bytes(elf_data);
+        command.name(name.to_owned())
+            .inherit_handles()
+            .args(args);
+
+        if let Some(fs) = fs_client() {
+            command.named_arg("fs_server".to_owned(), fs);
+        }
+
+        // the program shares the output streams, so anything the shell printed has to be sent before it starts
+        flush_stdio();
+
+        command.spawn()
+            .map_err(|error| format!("failed to start {program}: {error}"))
+    }
+
+    /// Prints the exit status of every background program which has exited
+    fn reap_background(&mut self) {
+        self.background.retain(|(program, child)| {
+            match child.wait_until(Instant::now()) {
+                Ok(status) => {
+                    print_status(program, status);
+                    false
+                },
+                Err(SysErr::OkTimeout) => true,
+                Err(error) => {
+                    eprintln!("ash: failed to check if {program} exited: {error}");
+                    false
+                },
+            }
+        });
+    }
+}
+
+fn exit_flow(args: &[&str]) -> Flow {
+    match args {
+        [] => Flow::Exit(0),
+        [code] => match code.parse() {
+            Ok(code) => Flow::Exit(code),
+            Err(_) => {
+                eprintln!("exit: invalid exit code `{code}`");
+                Flow::Continue
+            },
+        },
+        _ => {
+            eprintln!("exit: too many arguments");
+            Flow::Continue
+        },
+    }
+}
+
+fn main() {
+    let Some(stdin) = env::stdin() else {
+        eprintln!("ash: no standard input");
+        flush_stdio();
+        process::exit_with_code(1);
+    };
+
+    let mut lines = LineReader::new(stdin);
+    let mut shell = Shell::new();
+
+    let exit_code = loop {
+        shell.reap_background();
+
+        // the prompt has no newline, so it has to be flushed
+        print!("{}{PROMPT_SUFFIX}", shell.current_dir);
+        flush_stdio();
+
+        let line = match lines.read_line() {
+            Ok(line) => line,
+            Err(error) => {
+                eprintln!("ash: failed to read standard input: {error}");
+                break 1;
+            },
+        };
+
+        if let Flow::Exit(code) = shell.run_line(&line) {
+            break code;
+        }
+    };
+
+    flush_stdio();
+    process::exit_with_code(exit_code);
+}
\ No newline at end of file
@@ -0,0 +1,93 @@
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use futures::Stream;
+use futures::stream::FusedStream;
+use sys::{AddressSpace, KResult, EventId, PageFault, EventData, Event};
+
+use crate::EXECUTOR;
+use crate::executor::{EventReciever, RecievedEvent};
+
+/// Returns a stream of the page faults in `address_space` which the kernel could not resolve
+/// 
+/// This sets the fault handler of `address_space`, and clears it when the stream is dropped
+pub fn page_faults(address_space: &AddressSpace) -> AsyncPageFaults {
+    AsyncPageFaults::Unpolled(address_space)
+}
+
+#[derive(Debug)]
+pub enum AsyncPageFaults<'a> {
+    Unpolled(&'a AddressSpace),
+    Polled(&'a AddressSpace, EventId, EventReciever),
+    Closed,
+}
+
+impl Stream for AsyncPageFaults<'_> {
+    type Item = PageFault;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        match this {
+            Self::Unpolled(address_space) => {
+                let address_space = *address_space;
+
+                let event_reciever: KResult<(EventId, EventReciever)> = EXECUTOR.with(|executor| {
+                    let event_id = executor.alloc_event_id(|event_pool, event_id| address_space.set_fault_handler(event_pool, event_id))?;
+
+                    let event_reciever = EventReciever::default();
+                    executor.register_event_waiter_repeat(event_id, cx.waker().clone(), event_reciever.clone());
+
+                    Ok((event_id, event_reciever))
+                });
+
+                match event_reciever {
+                    Ok((event_id, event_reciever)) => *this = Self::Polled(address_space, event_id, event_reciever),
+                    Err(_) => *this = Self::Closed,
+                }
+
+                Poll::Pending
+            },
+            Self::Polled(_, _, event_reciever) => {
+                match event_reciever.take_event() {
+                    Some(RecievedEvent::OwnedEvent {
+                        event: Event {
+                            event_data: EventData::PageFault(page_fault),
+                            ..
+                        },
+                        ..
+                    }) => Poll::Ready(Some(page_fault)),
+                    Some(RecievedEvent::EventsDropped(count)) => {
+                        // the faulting threads stay suspended, and there is no way to find them
+                        sys::dprintln!("warning: {} page faults were dropped because the event pool was full", count);
+                        Poll::Pending
+                    },
+                    None => Poll::Pending,
+                    _ => panic!("invalid event recieved"),
+                }
+            },
+            Self::Closed => Poll::Ready(None),
+        }
+    }
+}
+
+impl FusedStream for AsyncPageFaults<'_> {
+    fn is_terminated(&self) -> bool {
+        matches!(self, Self::Closed)
+    }
+}
+
+impl Drop for AsyncPageFaults<'_> {
+    fn drop(&mut self) {
+        if let Self::Polled(address_space, event_id, _) = self {
+            // ignore error, address space may have already been destroyed
+            let _ = address_space.clear_fault_handler();
+
+            EXECUTOR.with(|executor| {
+                executor.remove_event_waiter(*event_id);
+            });
+        }
+    }
+}
+
+impl Unpin for AsyncPageFaults<'_> {}
\ No newline at end of file
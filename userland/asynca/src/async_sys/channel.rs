@@ -6,40 +6,54 @@ use futures::Stream;
 use futures::future::FusedFuture;
 use futures::stream::FusedStream;
 use serde::{Serialize, Deserialize};
-use sys::{Channel, MessageBuffer, KResult, RecieveResult, MessageSent, EventId};
+use sys::{Channel, ChannelSendFlags, EventPool, Memory, MessageBuffer, KResult, SysErr, RecieveResult, MessageSent, EventId, Event, EventData, ReplyWritten};
 use bit_utils::Size;
 
 use crate::EXECUTOR;
 use crate::executor::{EventReciever, RecievedEvent, MessageRecievedEvent};
-use crate::generate_async_wrapper;
 
 #[derive(Serialize, Deserialize)]
 pub struct AsyncChannel(Channel);
 
 impl AsyncChannel {
-    pub fn try_send(&self, buffer: &MessageBuffer) -> KResult<Size> {
-        self.0.try_send(buffer)
+    pub fn try_send(&self, buffer: &MessageBuffer, send_flags: ChannelSendFlags) -> KResult<Size> {
+        self.0.try_send(buffer, send_flags)
     }
 
     pub fn try_recv(&self, buffer: &MessageBuffer) -> KResult<RecieveResult> {
         self.0.try_recv(buffer)
     }
 
-    pub fn send(&self, buffer: MessageBuffer) -> AsyncSend {
-        AsyncSend::Unpolled((&self.0, buffer))
+    pub fn send(&self, buffer: MessageBuffer, send_flags: ChannelSendFlags) -> AsyncSend {
+        AsyncSend::Unpolled(&self.0, buffer, send_flags)
     }
 
     pub fn recv(&self) -> AsyncRecv {
         AsyncRecv::Unpolled(&self.0)
     }
 
-    pub fn call(&self, buffer: MessageBuffer) -> AsyncCall {
-        AsyncCall::Unpolled(&self.0, buffer)
+    pub fn call(&self, buffer: MessageBuffer, send_flags: ChannelSendFlags) -> AsyncCall {
+        AsyncCall::Unpolled(&self.0, buffer, send_flags)
+    }
+
+    /// Makes a call where the reply is copied into the first `response_size` bytes of `response_memory` if it fits
+    pub fn call_with_response<'a>(
+        &'a self,
+        buffer: MessageBuffer,
+        response_memory: &'a Memory,
+        response_size: Size,
+        send_flags: ChannelSendFlags,
+    ) -> AsyncCallWithResponse<'a> {
+        AsyncCallWithResponse::Unpolled(&self.0, buffer, response_memory, response_size, send_flags)
     }
 
     pub fn recv_repeat(&self) -> AsyncRecvRepeat {
         AsyncRecvRepeat::Unpolled(&self.0)
     }
+
+    pub fn inner(&self) -> &Channel {
+        &self.0
+    }
 }
 
 impl From<Channel> for AsyncChannel {
@@ -48,20 +62,134 @@ impl From<Channel> for AsyncChannel {
     }
 }
 
-generate_async_wrapper!(
-    AsyncSend,
-    (&'a Channel, MessageBuffer),
-    Size,
-    MessageSent,
-    |data: (&Channel, MessageBuffer), event_pool, event_id| {
-        data.0.async_send(&data.1, event_pool, event_id)
-    },
-    |event: MessageSent| event.recieved_size,
-);
+/// Waits for a channel's sender queue to have space, after a send or call failed with [`SysErr::ChannelFull`]
+pub struct SpaceWait(EventId, EventReciever);
+
+impl SpaceWait {
+    fn has_space(&self) -> bool {
+        // a dropped space event still means there is space
+        self.1.take_event().is_some()
+    }
+}
+
+impl Drop for SpaceWait {
+    fn drop(&mut self) {
+        EXECUTOR.with(|executor| {
+            executor.remove_event_waiter(self.0);
+        });
+    }
+}
+
+enum SendStart {
+    Started(EventId, EventReciever),
+    QueueFull(SpaceWait),
+}
+
+/// Starts an async send or call with `start`, or if the channel's sender queue is full, starts waiting for space
+fn start_send(channel: &Channel, cx: &Context<'_>, start: impl FnOnce(&EventPool, EventId) -> KResult<()>) -> KResult<SendStart> {
+    EXECUTOR.with(|executor| {
+        let register_waiter = |event_id| {
+            let event_reciever = EventReciever::default();
+            executor.register_event_waiter_oneshot(event_id, cx.waker().clone(), event_reciever.clone());
+            event_reciever
+        };
+
+        match executor.alloc_event_id(start) {
+            Ok(event_id) => Ok(SendStart::Started(event_id, register_waiter(event_id))),
+            Err(SysErr::ChannelFull) => {
+                let event_id = executor.alloc_event_id(|event_pool, event_id| channel.async_wait_space(event_pool, event_id))?;
+                Ok(SendStart::QueueFull(SpaceWait(event_id, register_waiter(event_id))))
+            },
+            Err(error) => Err(error),
+        }
+    })
+}
+
+/// Sends a message, waiting for space first if the channel's sender queue is full
+pub enum AsyncSend<'a> {
+    Unpolled(&'a Channel, MessageBuffer, ChannelSendFlags),
+    WaitingForSpace(&'a Channel, MessageBuffer, ChannelSendFlags, SpaceWait),
+    Polled(EventId, EventReciever),
+    Finished,
+}
+
+impl Future for AsyncSend<'_> {
+    type Output = KResult<Size>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        // once there is space the send is retried from the start
+        if let Self::WaitingForSpace(channel, buffer, send_flags, space_wait) = this {
+            if !space_wait.has_space() {
+                return Poll::Pending;
+            }
+
+            *this = Self::Unpolled(*channel, *buffer, *send_flags);
+        }
+
+        match this {
+            Self::Unpolled(channel, buffer, send_flags) => {
+                let send_start = start_send(channel, cx, |event_pool, event_id| {
+                    channel.async_send(buffer, event_pool, event_id, *send_flags)
+                })?;
+
+                *this = match send_start {
+                    SendStart::Started(event_id, event_reciever) => Self::Polled(event_id, event_reciever),
+                    SendStart::QueueFull(space_wait) => Self::WaitingForSpace(*channel, *buffer, *send_flags, space_wait),
+                };
+
+                Poll::Pending
+            },
+            Self::WaitingForSpace(..) => unreachable!(),
+            Self::Polled(_, event_reciever) => {
+                match event_reciever.take_event() {
+                    Some(RecievedEvent::OwnedEvent {
+                        event: Event {
+                            event_data: EventData::MessageSent(MessageSent { recieved_size }),
+                            ..
+                        },
+                        ..
+                    }) => {
+                        *this = Self::Finished;
+                        Poll::Ready(Ok(recieved_size))
+                    },
+                    // the message was sent, but its size was lost
+                    Some(RecievedEvent::EventsDropped(_)) => {
+                        *this = Self::Finished;
+                        Poll::Ready(Err(SysErr::EventPoolFull))
+                    },
+                    None => Poll::Pending,
+                    _ => panic!("invalid event recieved"),
+                }
+            },
+            Self::Finished => Poll::Pending,
+        }
+    }
+}
+
+impl FusedFuture for AsyncSend<'_> {
+    fn is_terminated(&self) -> bool {
+        matches!(self, Self::Finished)
+    }
+}
+
+impl Drop for AsyncSend<'_> {
+    fn drop(&mut self) {
+        if let Self::Polled(event_id, _) = self {
+            // the kernel may still send the event, it is dropped as stale
+            EXECUTOR.with(|executor| {
+                executor.remove_event_waiter(*event_id);
+            });
+        }
+    }
+}
+
+impl Unpin for AsyncSend<'_> {}
 
 pub enum AsyncRecv<'a> {
     Unpolled(&'a Channel),
-    Polled(EventReciever),
+    Polled(EventId, EventReciever),
     Finished,
 }
 
@@ -73,21 +201,20 @@ impl Future for AsyncRecv<'_> {
 
         match this {
             Self::Unpolled(channel) => {
-                let event_reciever = EXECUTOR.with(|executor| {
-                    let event_id = EventId::new();
-                    channel.async_recv(executor.event_pool(), false, event_id)?;
+                let (event_id, event_reciever) = EXECUTOR.with(|executor| {
+                    let event_id = executor.alloc_event_id(|event_pool, event_id| channel.async_recv(event_pool, false, event_id))?;
 
                     let event_reciever = EventReciever::default();
                     executor.register_event_waiter_oneshot(event_id, cx.waker().clone(), event_reciever.clone());
 
-                    Ok(event_reciever)
+                    Ok((event_id, event_reciever))
                 })?;
 
-                *this = Self::Polled(event_reciever);
+                *this = Self::Polled(event_id, event_reciever);
 
                 Poll::Pending
             },
-            Self::Polled(event_reciever) => {
+            Self::Polled(_, event_reciever) => {
                 match event_reciever.take_event() {
                     Some(RecievedEvent::MessageRecievedEvent(event)) => {
                         *this = Self::Finished;
@@ -108,11 +235,24 @@ impl FusedFuture for AsyncRecv<'_> {
     }
 }
 
+impl Drop for AsyncRecv<'_> {
+    fn drop(&mut self) {
+        if let Self::Polled(event_id, _) = self {
+            // the kernel may still send the event, it is dropped as stale
+            EXECUTOR.with(|executor| {
+                executor.remove_event_waiter(*event_id);
+            });
+        }
+    }
+}
+
 impl Unpin for AsyncRecv<'_> {}
 
+/// Makes a call, waiting for space first if the channel's sender queue is full
 pub enum AsyncCall<'a> {
-    Unpolled(&'a Channel, MessageBuffer),
-    Polled(EventReciever),
+    Unpolled(&'a Channel, MessageBuffer, ChannelSendFlags),
+    WaitingForSpace(&'a Channel, MessageBuffer, ChannelSendFlags, SpaceWait),
+    Polled(EventId, EventReciever),
     Finished,
 }
 
@@ -122,24 +262,39 @@ impl Future for AsyncCall<'_> {
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
 
-        match this {
-            Self::Unpolled(channel, buffer) => {
-                let event_reciever = EXECUTOR.with(|executor| {
-                    let event_id = EventId::new();
-                    channel.async_call(buffer, executor.event_pool(), event_id)?;
+        if let Self::WaitingForSpace(channel, buffer, send_flags, space_wait) = this {
+            if !space_wait.has_space() {
+                return Poll::Pending;
+            }
 
-                    let event_reciever = EventReciever::default();
-                    executor.register_event_waiter_oneshot(event_id, cx.waker().clone(), event_reciever.clone());
+            *this = Self::Unpolled(*channel, *buffer, *send_flags);
+        }
 
-                    Ok(event_reciever)
+        match this {
+            Self::Unpolled(channel, buffer, send_flags) => {
+                let send_start = start_send(channel, cx, |event_pool, event_id| {
+                    channel.async_call(buffer, event_pool, event_id, *send_flags)
                 })?;
 
-                *this = Self::Polled(event_reciever);
+                *this = match send_start {
+                    SendStart::Started(event_id, event_reciever) => Self::Polled(event_id, event_reciever),
+                    SendStart::QueueFull(space_wait) => Self::WaitingForSpace(*channel, *buffer, *send_flags, space_wait),
+                };
 
                 Poll::Pending
             },
-            Self::Polled(event_reciever) => {
-                match event_reciever.take_event() {
+            Self::WaitingForSpace(..) => unreachable!(),
+            Self::Polled(event_id, event_reciever) => {
+                // the reply must be for the event id this call was made with, not another call's
+                let event = match event_reciever.take_event_for(*event_id) {
+                    Ok(event) => event,
+                    Err(error) => {
+                        *this = Self::Finished;
+                        return Poll::Ready(Err(error));
+                    },
+                };
+
+                match event {
                     Some(RecievedEvent::MessageRecievedEvent(event)) => {
                         *this = Self::Finished;
                         Poll::Ready(Ok(event))
@@ -159,8 +314,127 @@ impl FusedFuture for AsyncCall<'_> {
     }
 }
 
+impl Drop for AsyncCall<'_> {
+    fn drop(&mut self) {
+        if let Self::Polled(event_id, _) = self {
+            // the kernel may still send the event, it is dropped as stale
+            EXECUTOR.with(|executor| {
+                executor.remove_event_waiter(*event_id);
+            });
+        }
+    }
+}
+
 impl Unpin for AsyncCall<'_> {}
 
+/// Where the reply of a call made with [`AsyncChannel::call_with_response`] was written
+#[derive(Debug)]
+pub enum CallResponse {
+    /// The reply was written to the start of the response memory, and is this many bytes long
+    ResponseMemory(Size),
+    /// The reply did not fit in the response memory, so it was sent as a normal message
+    Message(MessageRecievedEvent),
+}
+
+/// Makes a call with a response buffer, waiting for space first if the channel's sender queue is full
+pub enum AsyncCallWithResponse<'a> {
+    Unpolled(&'a Channel, MessageBuffer, &'a Memory, Size, ChannelSendFlags),
+    WaitingForSpace(&'a Channel, MessageBuffer, &'a Memory, Size, ChannelSendFlags, SpaceWait),
+    Polled(EventId, EventReciever),
+    Finished,
+}
+
+impl Future for AsyncCallWithResponse<'_> {
+    type Output = KResult<CallResponse>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        if let Self::WaitingForSpace(channel, buffer, response_memory, response_size, send_flags, space_wait) = this {
+            if !space_wait.has_space() {
+                return Poll::Pending;
+            }
+
+            *this = Self::Unpolled(*channel, *buffer, *response_memory, *response_size, *send_flags);
+        }
+
+        match this {
+            Self::Unpolled(channel, buffer, response_memory, response_size, send_flags) => {
+                let send_start = start_send(channel, cx, |event_pool, event_id| {
+                    channel.async_call_with_response(
+                        buffer,
+                        response_memory,
+                        *response_size,
+                        event_pool,
+                        event_id,
+                        *send_flags,
+                    )
+                })?;
+
+                *this = match send_start {
+                    SendStart::Started(event_id, event_reciever) => Self::Polled(event_id, event_reciever),
+                    SendStart::QueueFull(space_wait) => {
+                        Self::WaitingForSpace(*channel, *buffer, *response_memory, *response_size, *send_flags, space_wait)
+                    },
+                };
+
+                Poll::Pending
+            },
+            Self::WaitingForSpace(..) => unreachable!(),
+            Self::Polled(event_id, event_reciever) => {
+                let event = match event_reciever.take_event_for(*event_id) {
+                    Ok(event) => event,
+                    Err(error) => {
+                        *this = Self::Finished;
+                        return Poll::Ready(Err(error));
+                    },
+                };
+
+                let response = match event {
+                    Some(RecievedEvent::OwnedEvent {
+                        event: Event {
+                            event_data: EventData::ReplyWritten(ReplyWritten { response_size }),
+                            ..
+                        },
+                        ..
+                    }) => CallResponse::ResponseMemory(response_size),
+                    Some(RecievedEvent::MessageRecievedEvent(event)) => CallResponse::Message(event),
+                    Some(RecievedEvent::EventsDropped(_)) => {
+                        // the reply was written to the response memory, but its size was lost
+                        *this = Self::Finished;
+                        return Poll::Ready(Err(SysErr::EventPoolFull));
+                    },
+                    None => return Poll::Pending,
+                    _ => panic!("invalid event recieved"),
+                };
+
+                *this = Self::Finished;
+                Poll::Ready(Ok(response))
+            },
+            Self::Finished => Poll::Pending,
+        }
+    }
+}
+
+impl FusedFuture for AsyncCallWithResponse<'_> {
+    fn is_terminated(&self) -> bool {
+        matches!(self, Self::Finished)
+    }
+}
+
+impl Drop for AsyncCallWithResponse<'_> {
+    fn drop(&mut self) {
+        if let Self::Polled(event_id, _) = self {
+            // the kernel may still send the event, it is dropped as stale
+            EXECUTOR.with(|executor| {
+                executor.remove_event_waiter(*event_id);
+            });
+        }
+    }
+}
+
+impl Unpin for AsyncCallWithResponse<'_> {}
+
 #[derive(Debug)]
 pub enum AsyncRecvRepeat<'a> {
     Unpolled(&'a Channel),
@@ -177,8 +451,7 @@ impl Stream for AsyncRecvRepeat<'_> {
         match this {
             Self::Unpolled(channel) => {
                 let event_reciever: KResult<(EventId, EventReciever)> = EXECUTOR.with(|executor| {
-                    let event_id = EventId::new();
-                    channel.async_recv(executor.event_pool(), true, event_id)?;
+                    let event_id = executor.alloc_event_id(|event_pool, event_id| channel.async_recv(event_pool, true, event_id))?;
 
                     let event_reciever = EventReciever::default();
                     executor.register_event_waiter_repeat(event_id, cx.waker().clone(), event_reciever.clone());
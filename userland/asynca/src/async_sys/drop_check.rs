@@ -10,6 +10,10 @@ impl AsyncDropCheckReciever {
     pub fn handle_drop(&self) -> AsyncHandleDrop {
         AsyncHandleDrop::Unpolled((&self.0,))
     }
+
+    pub fn inner(&self) -> &DropCheckReciever {
+        &self.0
+    }
 }
 
 impl From<DropCheckReciever> for AsyncDropCheckReciever {
@@ -0,0 +1,90 @@
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use futures::Stream;
+use futures::stream::FusedStream;
+use sys::{Interrupt, KResult, EventId, EventData, Event};
+
+use crate::EXECUTOR;
+use crate::executor::{EventReciever, RecievedEvent};
+
+/// Returns a stream which yields the number of times `interrupt` triggered since the previous item
+/// 
+/// Triggers which arrive faster than the stream is polled are combined into one item instead of being lost.
+pub fn interrupt_triggers(interrupt: &Interrupt) -> AsyncInterruptTriggers {
+    AsyncInterruptTriggers::Unpolled(interrupt)
+}
+
+#[derive(Debug)]
+pub enum AsyncInterruptTriggers<'a> {
+    Unpolled(&'a Interrupt),
+    Polled(EventId, EventReciever),
+    Closed,
+}
+
+impl Stream for AsyncInterruptTriggers<'_> {
+    type Item = usize;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        match this {
+            Self::Unpolled(interrupt) => {
+                let interrupt = *interrupt;
+
+                let event_reciever: KResult<(EventId, EventReciever)> = EXECUTOR.with(|executor| {
+                    let event_id = executor.alloc_event_id(|event_pool, event_id| {
+                        interrupt.handle_interrupt_trigger_async(event_pool, event_id, false)
+                    })?;
+
+                    let event_reciever = EventReciever::default();
+                    executor.register_event_waiter_repeat(event_id, cx.waker().clone(), event_reciever.clone());
+
+                    Ok((event_id, event_reciever))
+                });
+
+                match event_reciever {
+                    Ok((event_id, event_reciever)) => *this = Self::Polled(event_id, event_reciever),
+                    Err(_) => *this = Self::Closed,
+                }
+
+                Poll::Pending
+            },
+            Self::Polled(_, event_reciever) => {
+                match event_reciever.take_event() {
+                    Some(RecievedEvent::OwnedEvent {
+                        event: Event {
+                            event_data: EventData::InterruptTrigger(_),
+                            ..
+                        },
+                        count,
+                    }) => Poll::Ready(Some(count)),
+                    // dropped triggers still happened
+                    Some(RecievedEvent::EventsDropped(count)) => Poll::Ready(Some(count)),
+                    None => Poll::Pending,
+                    _ => panic!("invalid event recieved"),
+                }
+            },
+            Self::Closed => Poll::Ready(None),
+        }
+    }
+}
+
+impl FusedStream for AsyncInterruptTriggers<'_> {
+    fn is_terminated(&self) -> bool {
+        matches!(self, Self::Closed)
+    }
+}
+
+impl Drop for AsyncInterruptTriggers<'_> {
+    fn drop(&mut self) {
+        if let Self::Polled(event_id, _) = self {
+            // the kernel keeps sending triggers, but the executor ignores events nothing is waiting for
+            EXECUTOR.with(|executor| {
+                executor.remove_event_waiter(*event_id);
+            });
+        }
+    }
+}
+
+impl Unpin for AsyncInterruptTriggers<'_> {}
\ No newline at end of file
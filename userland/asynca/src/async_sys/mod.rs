@@ -1,14 +1,18 @@
+mod address_space;
+pub use address_space::*;
 mod channel;
 pub use channel::*;
 mod drop_check;
 pub use drop_check::*;
+mod interrupt;
+pub use interrupt::*;
 
 #[macro_export]
 macro_rules! generate_async_wrapper {
     ($name:ident, $data:ty, $return_type:ty, $event_type:ident, $action:expr, $get_return:expr,) => {
         pub enum $name<'a> {
             Unpolled($data),
-            Polled($crate::executor::EventReciever),
+            Polled(sys::EventId, $crate::executor::EventReciever),
             Finished,
         }
         
@@ -20,29 +24,36 @@ macro_rules! generate_async_wrapper {
 
                 match this {
                     Self::Unpolled(data) => {
-                        let event_reciever = $crate::EXECUTOR.with(|executor| {
-                            let event_id = sys::EventId::new();
-                            $action(*data, executor.event_pool(), event_id)?;
+                        let (event_id, event_reciever) = $crate::EXECUTOR.with(|executor| {
+                            let event_id = executor.alloc_event_id(|event_pool, event_id| $action(*data, event_pool, event_id))?;
 
                             let event_reciever = $crate::executor::EventReciever::default();
                             executor.register_event_waiter_oneshot(event_id, cx.waker().clone(), event_reciever.clone());
         
-                            Ok(event_reciever)
+                            Ok((event_id, event_reciever))
                         })?;
 
-                        *this = Self::Polled(event_reciever);
+                        *this = Self::Polled(event_id, event_reciever);
         
                         core::task::Poll::Pending
                     },
-                    Self::Polled(event_reciever) => {
+                    Self::Polled(_, event_reciever) => {
                         match event_reciever.take_event() {
-                            Some($crate::executor::RecievedEvent::OwnedEvent(sys::Event {
-                                event_data: sys::EventData::$event_type(event),
+                            Some($crate::executor::RecievedEvent::OwnedEvent {
+                                event: sys::Event {
+                                    event_data: sys::EventData::$event_type(event),
+                                    ..
+                                },
                                 ..
-                            })) => {
+                            }) => {
                                 *this = Self::Finished;
                                 core::task::Poll::Ready(Ok($get_return(event)))
                             },
+                            // the event happened, but what it contained was lost
+                            Some($crate::executor::RecievedEvent::EventsDropped(_)) => {
+                                *this = Self::Finished;
+                                core::task::Poll::Ready(Err(sys::SysErr::EventPoolFull))
+                            },
                             None => core::task::Poll::Pending,
                             _ => panic!("invalid event recieved"),
                         }
@@ -57,6 +68,17 @@ macro_rules! generate_async_wrapper {
                 matches!(self, Self::Finished)
             }
         }
+
+        impl Drop for $name<'_> {
+            fn drop(&mut self) {
+                if let Self::Polled(event_id, _) = self {
+                    // the event may still arrive, it is dropped as stale
+                    $crate::EXECUTOR.with(|executor| {
+                        executor.remove_event_waiter(*event_id);
+                    });
+                }
+            }
+        }
         
         impl Unpin for $name<'_> {}
     };
@@ -0,0 +1,449 @@
+//! Channels for sending values between tasks in the same process
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use aurora_core::sync::Mutex;
+
+/// Returned by [`Sender::send`] when the receiver has been dropped, holds the value that could not be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("channel closed")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is at capacity
+    Full(T),
+    /// The receiver has been dropped
+    Closed(T),
+}
+
+impl<T> TrySendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Full(value) | Self::Closed(value) => value,
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.write_str("channel full"),
+            Self::Closed(_) => f.write_str("channel closed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// There are no values in the channel
+    Empty,
+    /// There are no values in the channel and all senders have been dropped
+    Closed,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("channel empty"),
+            Self::Closed => f.write_str("channel closed"),
+        }
+    }
+}
+
+struct ChannelState<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    sender_count: usize,
+    receiver_alive: bool,
+    receiver_waker: Option<Waker>,
+    /// Senders waiting for space in the queue, in the order they started waiting
+    sender_wakers: VecDeque<(usize, Waker)>,
+    next_waiter_id: usize,
+}
+
+impl<T> ChannelState<T> {
+    fn is_full(&self) -> bool {
+        self.queue.len() >= self.capacity
+    }
+
+    fn wake_receiver(&mut self) {
+        if let Some(waker) = self.receiver_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_sender(&mut self) {
+        if let Some((_, waker)) = self.sender_wakers.pop_front() {
+            waker.wake();
+        }
+    }
+}
+
+struct Channel<T> {
+    state: Mutex<ChannelState<T>>,
+}
+
+/// Creates a bounded multi producer single consumer channel which holds at most `capacity` values
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0
+pub fn mpsc<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must be at least 1");
+
+    let channel = Arc::new(Channel {
+        state: Mutex::new(ChannelState {
+            queue: VecDeque::with_capacity(capacity),
+            capacity,
+            sender_count: 1,
+            receiver_alive: true,
+            receiver_waker: None,
+            sender_wakers: VecDeque::new(),
+            next_waiter_id: 0,
+        }),
+    });
+
+    (
+        Sender {
+            channel: channel.clone(),
+        },
+        Receiver {
+            channel,
+        },
+    )
+}
+
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, waiting until there is space in the channel
+    ///
+    /// Fails if the receiver is dropped before the value could be sent.
+    pub fn send(&self, value: T) -> SendFuture<'_, T> {
+        SendFuture {
+            sender: self,
+            value: Some(value),
+            waiter_id: None,
+        }
+    }
+
+    /// Sends `value` if there is space in the channel
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.channel.state.lock();
+
+        if !state.receiver_alive {
+            Err(TrySendError::Closed(value))
+        } else if state.is_full() {
+            Err(TrySendError::Full(value))
+        } else {
+            state.queue.push_back(value);
+            state.wake_receiver();
+            Ok(())
+        }
+    }
+
+    /// Returns true if the receiver has been dropped
+    pub fn is_closed(&self) -> bool {
+        !self.channel.state.lock().receiver_alive
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.state.lock().sender_count += 1;
+
+        Sender {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock();
+
+        state.sender_count -= 1;
+        if state.sender_count == 0 {
+            // let the receiver see the channel is closed
+            state.wake_receiver();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+/// Future returned by [`Sender::send`]
+///
+/// No space is reserved while waiting, so dropping this future before it completes
+/// just passes its wakeup on to the next waiting sender.
+pub struct SendFuture<'a, T> {
+    sender: &'a Sender<T>,
+    value: Option<T>,
+    /// Set while this future is registered as waiting for space
+    waiter_id: Option<usize>,
+}
+
+// the value is never pinned, it is only moved into the channel
+impl<T> Unpin for SendFuture<'_, T> {}
+
+impl<T> Future for SendFuture<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut state = this.sender.channel.state.lock();
+
+        if let Some(waiter_id) = this.waiter_id.take() {
+            state.sender_wakers.retain(|(id, _)| *id != waiter_id);
+        }
+
+        let value = this.value.take().expect("send future polled after completion");
+
+        if !state.receiver_alive {
+            return Poll::Ready(Err(SendError(value)));
+        }
+
+        if state.is_full() {
+            let waiter_id = state.next_waiter_id;
+            state.next_waiter_id = state.next_waiter_id.wrapping_add(1);
+            state.sender_wakers.push_back((waiter_id, cx.waker().clone()));
+
+            this.waiter_id = Some(waiter_id);
+            this.value = Some(value);
+            return Poll::Pending;
+        }
+
+        state.queue.push_back(value);
+        state.wake_receiver();
+
+        // there may still be space left for other waiting senders
+        if !state.is_full() {
+            state.wake_sender();
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> Drop for SendFuture<'_, T> {
+    fn drop(&mut self) {
+        let Some(waiter_id) = self.waiter_id else {
+            return;
+        };
+
+        let mut state = self.sender.channel.state.lock();
+
+        let waiter_count = state.sender_wakers.len();
+        state.sender_wakers.retain(|(id, _)| *id != waiter_id);
+
+        // if this future was already woken for space it will never use, another sender has to be woken instead
+        if state.sender_wakers.len() == waiter_count && !state.is_full() {
+            state.wake_sender();
+        }
+    }
+}
+
+impl<T> fmt::Debug for SendFuture<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendFuture").finish_non_exhaustive()
+    }
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receives the next value, waiting until one is available
+    ///
+    /// Returns `None` once the channel is empty and all senders have been dropped.
+    pub async fn recv(&mut self) -> Option<T> {
+        core::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Receives the next value if one is available
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.channel.state.lock();
+
+        match Self::take_value(&mut state) {
+            Some(value) => Ok(value),
+            None if state.sender_count == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.channel.state.lock();
+
+        if let Some(value) = Self::take_value(&mut state) {
+            Poll::Ready(Some(value))
+        } else if state.sender_count == 0 {
+            Poll::Ready(None)
+        } else {
+            state.receiver_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Removes the next value from the queue, and wakes a sender waiting for the space it frees
+    fn take_value(state: &mut ChannelState<T>) -> Option<T> {
+        let value = state.queue.pop_front()?;
+        state.wake_sender();
+        Some(value)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock();
+
+        state.receiver_alive = false;
+        for (_, waker) in state.sender_wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::test_util::TestTask;
+    use super::*;
+
+    #[test]
+    fn full_channel_applies_backpressure() {
+        let (sender, mut receiver) = mpsc(2);
+
+        sender.try_send(1).unwrap();
+        let mut second = TestTask::new(sender.send(2));
+        assert!(second.poll());
+
+        assert_eq!(sender.try_send(3), Err(TrySendError::Full(3)));
+        let mut third = TestTask::new(sender.send(3));
+        assert!(!third.poll());
+
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert!(third.poll());
+        assert_eq!(third.take_output(), Ok(()));
+
+        let received: Vec<_> = core::iter::from_fn(|| receiver.try_recv().ok()).collect();
+        assert_eq!(received, [2, 3]);
+    }
+
+    #[test]
+    fn waiting_sender_is_woken_when_space_frees() {
+        let (sender, mut receiver) = mpsc(1);
+        sender.try_send(0).unwrap();
+
+        let mut senders: Vec<_> = (1..=2).map(|value| TestTask::new(sender.send(value))).collect();
+        for sender in senders.iter_mut() {
+            assert!(!sender.poll());
+        }
+
+        // each received value frees space for one sender, in the order they started waiting
+        assert_eq!(receiver.try_recv(), Ok(0));
+        assert_eq!(senders.iter().map(TestTask::wake_count).collect::<Vec<_>>(), [1, 0]);
+        assert!(senders[0].poll());
+        assert!(!senders[1].poll());
+
+        assert_eq!(receiver.try_recv(), Ok(1));
+        assert_eq!(senders[1].wake_count(), 1);
+        assert!(senders[1].poll());
+        assert_eq!(receiver.try_recv(), Ok(2));
+    }
+
+    #[test]
+    fn dropped_waiting_sender_passes_wakeup_on() {
+        let (sender, mut receiver) = mpsc(1);
+        sender.try_send(0).unwrap();
+
+        let mut first = TestTask::new(sender.send(1));
+        let mut second = TestTask::new(sender.send(2));
+        assert!(!first.poll());
+        assert!(!second.poll());
+
+        // first is woken for the free space, but is dropped without using it
+        assert_eq!(receiver.try_recv(), Ok(0));
+        drop(first);
+
+        assert_eq!(second.wake_count(), 1);
+        assert!(second.poll());
+        assert_eq!(receiver.try_recv(), Ok(2));
+    }
+
+    #[test]
+    fn recv_returns_none_after_senders_drop() {
+        let (sender, mut receiver) = mpsc(4);
+        let other_sender = sender.clone();
+
+        sender.try_send(1).unwrap();
+
+        let mut recv = TestTask::new(async move {
+            let mut received = Vec::new();
+            while let Some(value) = receiver.recv().await {
+                received.push(value);
+            }
+            received
+        });
+        assert!(!recv.poll());
+
+        // values sent before the senders are dropped are still received
+        other_sender.try_send(2).unwrap();
+        drop(sender);
+        assert!(!recv.poll());
+
+        drop(other_sender);
+        assert!(recv.wake_count() > 0);
+        assert!(recv.poll());
+        assert_eq!(recv.take_output(), [1, 2]);
+    }
+
+    #[test]
+    fn try_recv_reports_closed_channel() {
+        let (sender, mut receiver) = mpsc::<u32>(1);
+
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        drop(sender);
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Closed));
+    }
+
+    #[test]
+    fn dropping_receiver_fails_waiting_senders() {
+        let (sender, receiver) = mpsc(1);
+        sender.try_send(1).unwrap();
+
+        let mut waiting = TestTask::new(sender.send(2));
+        assert!(!waiting.poll());
+
+        drop(receiver);
+        assert_eq!(waiting.wake_count(), 1);
+        assert!(waiting.poll());
+        assert_eq!(waiting.take_output(), Err(SendError(2)));
+        assert!(sender.is_closed());
+    }
+}
\ No newline at end of file
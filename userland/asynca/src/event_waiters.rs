@@ -0,0 +1,126 @@
+//! Allocating the event ids used with the executor's event pool, and tracking what is waiting on each one
+//!
+//! An event id is the index of a slot in a slab in its low 32 bits, and the generation of the slot in its high 32 bits.
+//! Freed slots are reused, and the generation is incremented every time a slot is freed,
+//! so an event which arrives for an id after it was freed is recognized as stale instead of being given to whatever reused the slot.
+
+use core::task::Waker;
+
+use aurora_core::prelude::*;
+use sys::EventId;
+
+use crate::executor::EventReciever;
+
+/// Something that is waiting on an event
+#[derive(Debug)]
+pub(crate) struct EventWaiter {
+    pub waker: Waker,
+    pub event_reciever: EventReciever,
+    /// If it is oneshot, it is removed and its id is freed on the next event
+    pub oneshot: bool,
+}
+
+fn pack_event_id(index: u32, generation: u32) -> EventId {
+    EventId::from_u64(((generation as u64) << 32) | index as u64)
+}
+
+/// Returns the slot index and generation of `event_id`
+fn unpack_event_id(event_id: EventId) -> (usize, u32) {
+    let id = event_id.as_u64();
+    ((id & 0xffff_ffff) as usize, (id >> 32) as u32)
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    generation: u32,
+    allocated: bool,
+    /// None until a waiter is registered for the allocated id
+    waiter: Option<EventWaiter>,
+}
+
+/// Result of looking up the waiter for an event which arrived
+pub(crate) enum EventLookup<'a> {
+    Waiter(&'a EventWaiter),
+    /// The id is allocated, but nothing is waiting on it yet
+    NoWaiter,
+    /// The id was freed, or was never allocated by this registry
+    Stale,
+}
+
+/// Slab of event ids and the waiters registered for them
+#[derive(Debug, Default)]
+pub(crate) struct EventWaiters {
+    slots: Vec<Slot>,
+    /// Indexes of slots which are not allocated
+    free_slots: Vec<u32>,
+}
+
+impl EventWaiters {
+    /// Allocates an event id, reusing a freed one if there is one
+    pub fn alloc_id(&mut self) -> EventId {
+        let index = match self.free_slots.pop() {
+            Some(index) => index,
+            None => {
+                let index = u32::try_from(self.slots.len()).expect("ran out of event ids");
+                self.slots.push(Slot::default());
+                index
+            },
+        };
+
+        let slot = &mut self.slots[index as usize];
+        slot.allocated = true;
+
+        pack_event_id(index, slot.generation)
+    }
+
+    fn live_slot_mut(&mut self, event_id: EventId) -> Option<&mut Slot> {
+        let (index, generation) = unpack_event_id(event_id);
+
+        self.slots.get_mut(index)
+            .filter(|slot| slot.allocated && slot.generation == generation)
+    }
+
+    /// Registers `waiter` to recieve the events for `event_id`, replacing the previous waiter
+    ///
+    /// # Panics
+    ///
+    /// Panics if `event_id` is not allocated
+    pub fn register(&mut self, event_id: EventId, waiter: EventWaiter) {
+        let slot = self.live_slot_mut(event_id)
+            .expect("registered waiter for event id which is not allocated");
+
+        slot.waiter = Some(waiter);
+    }
+
+    /// Removes the waiter for `event_id` and frees the id, does nothing if it was already freed
+    ///
+    /// Events which arrive for the id after this are stale.
+    pub fn remove(&mut self, event_id: EventId) -> Option<EventWaiter> {
+        let (index, _) = unpack_event_id(event_id);
+        let slot = self.live_slot_mut(event_id)?;
+
+        let waiter = slot.waiter.take();
+        slot.allocated = false;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(index as u32);
+
+        waiter
+    }
+
+    pub fn get(&self, event_id: EventId) -> EventLookup<'_> {
+        let (index, generation) = unpack_event_id(event_id);
+
+        match self.slots.get(index) {
+            Some(slot) if slot.allocated && slot.generation == generation => match &slot.waiter {
+                Some(waiter) => EventLookup::Waiter(waiter),
+                None => EventLookup::NoWaiter,
+            },
+            _ => EventLookup::Stale,
+        }
+    }
+
+    /// Number of slots in the slab, which is the most event ids that have been allocated at once
+    pub fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+}
\ No newline at end of file
@@ -1,30 +1,65 @@
 use core::future::Future;
 use core::task::Poll;
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::task::Waker;
 use alloc::rc::Rc;
 use alloc::sync::Arc;
 
 use crossbeam_queue::SegQueue;
-use sys::{EventPool, Reply, EventId, Event, CspaceTarget, CapFlags, cap_clone, EventParser, EventParseResult};
+use sys::{EventPool, Reply, EventId, Event, CspaceTarget, CapFlags, KResult, SysErr, cap_clone, EventParser, EventParseResult};
 use bit_utils::Size;
 use aurora_core::allocator::addr_space::{MapEventPoolArgs, RegionPadding};
 use aurora_core::{prelude::*, this_context, addr_space};
 use aurora_core::collections::HashMap;
 
 use super::AsyncError;
-use super::task::{TaskId, Task, JoinHandle, TaskHandle};
+use super::event_waiters::{EventWaiters, EventWaiter, EventLookup};
+use super::task::{TaskId, Task, JoinHandle, TaskHandle, TaskName};
 
 const ASYNC_EVENT_POOL_MAX_SIZE: Size = Size::from_pages(1000);
 
+fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        core::arch::asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack));
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+/// Counters describing what an executor has done, used to diagnose stalled or slow tasks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecutorMetrics {
+    /// Number of tasks which have not yet completed or been dropped
+    pub alive_tasks: usize,
+    /// Total number of tasks ever spawned
+    pub spawned_tasks: u64,
+    /// Total number of times any task was polled
+    pub polls: u64,
+    /// Number of times the executor blocked waiting on the event pool
+    pub event_waits: u64,
+    /// Total number of events received from the event pool, all events which are ready are received with 1 wait
+    pub events_received: u64,
+    /// Number of events dropped because their event id was freed before they arrived
+    pub stale_events: u64,
+    /// Number of event id slots, which is the most event ids that have been in use at once
+    pub event_id_slots: usize,
+    /// Duration of the longest single poll in tsc ticks
+    pub longest_poll_ticks: u64,
+    /// Name of the task which had the longest poll, if it was named
+    pub longest_poll_task: Option<TaskName>,
+}
+
 pub struct Executor {
     tasks: RefCell<HashMap<TaskId, TaskHandle>>,
     /// A queue of tasks that are ready to be run
     task_queue: Arc<SegQueue<TaskId>>,
     /// Event pool used by this executor
     event_pool: EventPool,
-    /// Tasks which are waiting on an event
-    event_waiters: RefCell<HashMap<EventId, EventWaiter>>,
+    /// Allocates event ids, and tracks the tasks which are waiting on each one
+    event_waiters: RefCell<EventWaiters>,
+    /// Counters reported by [`Executor::metrics`], `alive_tasks` and `event_id_slots` are not updated here
+    metrics: Cell<ExecutorMetrics>,
 }
 
 impl Executor {
@@ -42,7 +77,8 @@ impl Executor {
             tasks: RefCell::new(HashMap::default()),
             task_queue: Arc::new(SegQueue::new()),
             event_pool,
-            event_waiters: RefCell::new(HashMap::default()),
+            event_waiters: RefCell::new(EventWaiters::default()),
+            metrics: Cell::new(ExecutorMetrics::default()),
         })
     }
 
@@ -51,22 +87,77 @@ impl Executor {
     }
 
     pub fn spawn<T: 'static>(&self, task: impl Future<Output = T> + 'static) -> JoinHandle<T> {
-        let (task_handle, join_handle) = Task::new(task, self.task_queue.clone());
+        self.spawn_inner(None, task)
+    }
+
+    /// Spawns a task with a name which is shown in diagnostics, the name is truncated if it is too long
+    pub fn spawn_named<T: 'static>(&self, name: &str, task: impl Future<Output = T> + 'static) -> JoinHandle<T> {
+        self.spawn_inner(Some(TaskName::new(name)), task)
+    }
+
+    fn spawn_inner<T: 'static>(&self, name: Option<TaskName>, task: impl Future<Output = T> + 'static) -> JoinHandle<T> {
+        let (task_handle, join_handle) = Task::new(name, task, self.task_queue.clone());
 
         let task_id = task_handle.id();
         self.tasks.borrow_mut().insert(task_id, task_handle);
         self.task_queue.push(task_id);
 
+        let mut metrics = self.metrics.get();
+        metrics.spawned_tasks += 1;
+        self.metrics.set(metrics);
+
         join_handle
     }
 
+    pub fn metrics(&self) -> ExecutorMetrics {
+        ExecutorMetrics {
+            alive_tasks: self.tasks.borrow().len(),
+            event_id_slots: self.event_waiters.borrow().slot_count(),
+            ..self.metrics.get()
+        }
+    }
+
+    /// Prints the name, state, and poll count of every task which has not completed
+    pub fn dump_tasks(&self) {
+        let tasks = self.tasks.borrow();
+
+        sys::dprintln!("executor tasks ({} alive):", tasks.len());
+        for (task_id, task) in tasks.iter() {
+            let name = task.name();
+            let name = name.as_ref().map_or("<unnamed>", TaskName::as_str);
+
+            // the poll count is not available for the task calling this, since it is being polled
+            match task.poll_count() {
+                Some(poll_count) => sys::dprintln!("  {:?} {}: {:?}, polled {} times", task_id, name, task.state(), poll_count),
+                None => sys::dprintln!("  {:?} {}: {:?}", task_id, name, task.state()),
+            }
+        }
+    }
+
+    /// Allocates an event id and passes it to `start`, which should make the kernel send events with that id to the event pool
+    ///
+    /// The id must be freed with [`remove_event_waiter`](Self::remove_event_waiter) once it is no longer used,
+    /// or it is freed after the first event if a oneshot waiter is registered for it. If `start` fails the id is freed.
+    pub fn alloc_event_id(&self, start: impl FnOnce(&EventPool, EventId) -> KResult<()>) -> KResult<EventId> {
+        let event_id = self.event_waiters.borrow_mut().alloc_id();
+
+        match start(&self.event_pool, event_id) {
+            Ok(()) => Ok(event_id),
+            Err(error) => {
+                self.remove_event_waiter(event_id);
+                Err(error)
+            },
+        }
+    }
+
+    /// Wakes `waker` for the next event with `event_id`, and frees the id after that event
     pub fn register_event_waiter_oneshot(
         &self,
         event_id: EventId,
         waker: Waker,
         event_reciever: EventReciever,
     ) {
-        self.event_waiters.borrow_mut().insert(
+        self.event_waiters.borrow_mut().register(
             event_id,
             EventWaiter {
                 waker,
@@ -76,13 +167,14 @@ impl Executor {
         );
     }
 
+    /// Wakes `waker` for every event with `event_id` until it is removed
     pub fn register_event_waiter_repeat(
         &self,
         event_id: EventId,
         waker: Waker,
         event_reciever: EventReciever,
     ) {
-        self.event_waiters.borrow_mut().insert(
+        self.event_waiters.borrow_mut().register(
             event_id,
             EventWaiter {
                 waker,
@@ -92,8 +184,11 @@ impl Executor {
         );
     }
 
+    /// Removes the waiter for `event_id` and frees the id, events which arrive for it afterwards are dropped
+    ///
+    /// Does nothing if the id was already freed.
     pub fn remove_event_waiter(&self, event_id: EventId) {
-        self.event_waiters.borrow_mut().remove(&event_id);
+        self.event_waiters.borrow_mut().remove(event_id);
     }
 
     /// Runs all the tasks in this executor, returns on error or when the last task has completed
@@ -110,75 +205,125 @@ impl Executor {
 
     fn run_ready_tasks(&self) {
         while let Some(task_id) = self.task_queue.pop() {
-            let task = self.tasks.borrow().get(&task_id)
-                .expect("task id found in ready queue but no task with given id exists")
-                .clone();
+            // the task may have been woken again after it finished or was cancelled
+            let Some(task) = self.tasks.borrow().get(&task_id).cloned() else {
+                continue;
+            };
 
-            if let Poll::Ready(()) = task.poll() {
+            if task.is_aborted() {
+                self.tasks.borrow_mut().remove(&task_id);
+                task.cancel();
+                continue;
+            }
+
+            let poll_start = rdtsc();
+            let poll_result = task.poll();
+            self.record_poll(&task, rdtsc() - poll_start);
+
+            if let Poll::Ready(()) = poll_result {
                 self.tasks.borrow_mut().remove(&task_id);
             }
         }
     }
 
+    fn record_poll(&self, task: &TaskHandle, poll_ticks: u64) {
+        let mut metrics = self.metrics.get();
+
+        metrics.polls += 1;
+        if poll_ticks > metrics.longest_poll_ticks {
+            metrics.longest_poll_ticks = poll_ticks;
+            metrics.longest_poll_task = task.name();
+        }
+
+        self.metrics.set(metrics);
+    }
+
     /// Blocks the calling thread until any events arrive, and wakes any tasks waiting for those events
+    ///
+    /// Every event is given to the waiter registered for its event id. Waiters are registered as soon as their id is allocated,
+    /// so an event for an allocated id with no waiter means events are being mixed up. This panics with debug assertions,
+    /// and otherwise the remaining events are delivered and [`AsyncError::UnroutedEvent`] is returned.
     pub fn await_event(&self) -> Result<(), AsyncError> {
+        let mut metrics = self.metrics.get();
+        metrics.event_waits += 1;
+        self.metrics.set(metrics);
+
         let event_data = self.event_pool.await_event(None)?;
+
+        let mut metrics = self.metrics.get();
+        metrics.events_received += event_data.event_count as u64;
+        self.metrics.set(metrics);
+
         let mut event_waiters = self.event_waiters.borrow_mut();
 
         // safety: async context is non send so no one is calling event_data::as_slice at the same time
         let event_parser = EventParser::new(unsafe { event_data.as_slice() });
 
+        let mut unrouted_event_id = None;
+
         for event in event_parser {
             let event_id = event.event_id();
-            let Some(waiter) = event_waiters.get(&event_id) else {
-                continue;
-            };
-
-            match event {
-                EventParseResult::Event(event) => {
-                    *waiter.event_reciever.0.borrow_mut() = Some(RecievedEvent::OwnedEvent(event));
+            let waiter = match event_waiters.get(event_id) {
+                EventLookup::Waiter(waiter) => waiter,
+                EventLookup::NoWaiter => {
+                    if cfg!(debug_assertions) {
+                        panic!("async executor: event for event id {:#x} which has no waiter", event_id.as_u64());
+                    }
+
+                    sys::dprintln!("async executor: event for event id {:#x} which has no waiter", event_id.as_u64());
+                    unrouted_event_id.get_or_insert(event_id);
+                    continue;
                 },
-                EventParseResult::MessageRecieved(mut message_event) => {
-                    *waiter.event_reciever.0.borrow_mut() = Some(RecievedEvent::MessageRecievedEvent(MessageRecievedEvent {
-                        data: message_event.message_data.as_ptr(),
-                        len: message_event.message_data.len(),
-                        reply: message_event.reply.take(),
-                    }));
+                EventLookup::Stale => {
+                    sys::dprintln!("async executor: dropped event for stale event id {:#x}", event_id.as_u64());
+
+                    let mut metrics = self.metrics.get();
+                    metrics.stale_events += 1;
+                    self.metrics.set(metrics);
+                    continue;
                 },
-            }
+            };
+
+            let event = match event {
+                EventParseResult::Event { event, count } => RecievedEvent::OwnedEvent { event, count },
+                EventParseResult::EventsDropped { count, .. } => RecievedEvent::EventsDropped(count),
+                EventParseResult::MessageRecieved(mut message_event) => RecievedEvent::MessageRecievedEvent(MessageRecievedEvent {
+                    data: message_event.message_data.as_ptr(),
+                    len: message_event.message_data.len(),
+                    reply: message_event.reply.take(),
+                    sender_key_id: message_event.sender_key_id,
+                }),
+            };
+            waiter.event_reciever.recieve(event_id, event);
 
             waiter.waker.wake_by_ref();
 
             if waiter.oneshot {
-                event_waiters.remove(&event_id);
+                event_waiters.remove(event_id);
             }
         }
 
-        Ok(())
+        match unrouted_event_id {
+            Some(event_id) => Err(AsyncError::UnroutedEvent(event_id.as_u64())),
+            None => Ok(()),
+        }
     }
 }
 
 impl !Send for Executor {}
 
-/// Something that is waiting on an event
-#[derive(Debug)]
-struct EventWaiter {
-    waker: Waker,
-    event_reciever: EventReciever,
-    // if it is oneshot, it will be removed on next event
-    oneshot: bool,
-}
-
 #[derive(Debug)]
 pub struct MessageRecievedEvent {
     data: *const u8,
     len: usize,
     pub reply: Option<Reply>,
+    /// Id of the sender's identity key, if it sent one
+    pub sender_key_id: Option<u64>,
 }
 
 impl MessageRecievedEvent {
     /// # Safety
-    /// 
+    ///
     /// This must not be called after the event range for the current event pool is invalidated (when `await_event` is called again)
     pub unsafe fn as_slice(&self) -> &[u8] {
         unsafe {
@@ -187,17 +332,68 @@ impl MessageRecievedEvent {
     }
 }
 
+/// Holds the latest event delivered to a waiter, along with the event id it was delivered for
 #[derive(Debug, Clone, Default)]
-pub struct EventReciever(Rc<RefCell<Option<RecievedEvent>>>);
+pub struct EventReciever(Rc<RefCell<Option<(EventId, RecievedEvent)>>>);
 
 impl EventReciever {
     pub fn take_event(&self) -> Option<RecievedEvent> {
-        self.0.borrow_mut().take()
+        self.0.borrow_mut().take().map(|(_, event)| event)
+    }
+
+    /// Takes the event, checking that it was delivered for `event_id`
+    ///
+    /// An event for another id means the executor gave this waiter an event meant for something else.
+    /// This panics with debug assertions, and otherwise the event is dropped and [`SysErr::InvlId`] is returned.
+    pub fn take_event_for(&self, event_id: EventId) -> KResult<Option<RecievedEvent>> {
+        match self.0.borrow_mut().take() {
+            Some((recieved_id, event)) if recieved_id == event_id => Ok(Some(event)),
+            Some((recieved_id, _)) => {
+                if cfg!(debug_assertions) {
+                    panic!(
+                        "event for event id {:#x} delivered to waiter for event id {:#x}",
+                        recieved_id.as_u64(),
+                        event_id.as_u64(),
+                    );
+                }
+
+                Err(SysErr::InvlId)
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Stores a new event, combining it with the event which has not been taken yet if both only count occurences
+    ///
+    /// Counts of [coalescable](sys::EventData::is_coalescable) events and dropped events are added,
+    /// so a waiter which is polled less often than the events arrive still sees how many there were.
+    fn recieve(&self, event_id: EventId, event: RecievedEvent) {
+        let mut current_event = self.0.borrow_mut();
+
+        let event = match (current_event.take().map(|(_, event)| event), event) {
+            (Some(RecievedEvent::OwnedEvent { count: old_count, .. }), RecievedEvent::OwnedEvent { event, count })
+                if event.event_data.is_coalescable() => RecievedEvent::OwnedEvent { event, count: old_count + count },
+            (Some(RecievedEvent::OwnedEvent { event, count }), RecievedEvent::EventsDropped(dropped_count))
+                if event.event_data.is_coalescable() => RecievedEvent::OwnedEvent { event, count: count + dropped_count },
+            (Some(RecievedEvent::EventsDropped(dropped_count)), RecievedEvent::OwnedEvent { event, count })
+                if event.event_data.is_coalescable() => RecievedEvent::OwnedEvent { event, count: count + dropped_count },
+            (Some(RecievedEvent::EventsDropped(old_count)), RecievedEvent::EventsDropped(count)) =>
+                RecievedEvent::EventsDropped(old_count + count),
+            (_, event) => event,
+        };
+
+        *current_event = Some((event_id, event));
     }
 }
 
 #[derive(Debug)]
 pub enum RecievedEvent {
-    OwnedEvent(Event),
+    OwnedEvent {
+        event: Event,
+        /// Number of times the event occured since the last time it was taken
+        count: usize,
+    },
     MessageRecievedEvent(MessageRecievedEvent),
+    /// This many events were dropped because the event pool was full
+    EventsDropped(usize),
 }
\ No newline at end of file
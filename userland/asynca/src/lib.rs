@@ -12,11 +12,17 @@ use aurora_core::allocator::addr_space::AddrSpaceError;
 
 use executor::Executor;
 
-use self::task::JoinHandle;
 
 pub mod async_sys;
+pub mod channel;
+mod event_waiters;
 mod executor;
+pub use executor::{ExecutorMetrics, MessageRecievedEvent};
+pub mod sync;
 mod task;
+#[cfg(test)]
+mod test_util;
+pub use task::{JoinHandle, Aborted, TaskName, TASK_NAME_MAX_LEN};
 
 #[derive(Debug, Error)]
 pub enum AsyncError {
@@ -24,6 +30,8 @@ pub enum AsyncError {
     MapError(#[from] AddrSpaceError),
     #[error("A system error occured: {0}")]
     SysErr(#[from] SysErr),
+    #[error("An event arrived for event id {0:#x}, which has no waiter")]
+    UnroutedEvent(u64),
 }
 
 aurora_core::thread_local! {
@@ -37,6 +45,7 @@ pub fn block_in_place<T: 'static>(task: impl Future<Output = T> + 'static) -> T
         executor.run().expect("block in place: failed to run executor");
 
         join_handle.get_output()
+            .expect("block in place: task was aborted")
     })
 }
 
@@ -45,4 +54,11 @@ pub fn spawn<T: 'static>(task: impl Future<Output = T> + 'static) -> JoinHandle<
     EXECUTOR.with(|executor| {
         executor.spawn(task)
     })
+}
+
+/// Spawns a new asynchronous task with a name used by executor diagnostics
+pub fn spawn_named<T: 'static>(name: &str, task: impl Future<Output = T> + 'static) -> JoinHandle<T> {
+    EXECUTOR.with(|executor| {
+        executor.spawn_named(name, task)
+    })
 }
\ No newline at end of file
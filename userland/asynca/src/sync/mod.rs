@@ -0,0 +1,175 @@
+//! Synchronization primitives which suspend the waiting task instead of blocking the executor thread
+
+mod mutex;
+pub use mutex::{Mutex, MutexGuard};
+mod raw_lock;
+mod rwlock;
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::test_util::{TestTask, yield_once};
+    use super::*;
+
+    #[test]
+    fn uncontended_lock_is_ready() {
+        let mutex = Mutex::new(0);
+
+        let mut task = TestTask::new(mutex.lock());
+        assert!(task.poll());
+        assert!(mutex.is_locked());
+
+        drop(task.take_output());
+        assert!(!mutex.is_locked());
+    }
+
+    #[test]
+    fn mutex_guard_drop_wakes_one_waiter_in_order() {
+        let mutex = Mutex::new(());
+        let guard = mutex.try_lock().unwrap();
+
+        let mut tasks: Vec<_> = (0..3).map(|_| TestTask::new(mutex.lock())).collect();
+        for task in tasks.iter_mut() {
+            assert!(!task.poll());
+        }
+
+        drop(guard);
+        assert_eq!(tasks.iter().map(TestTask::wake_count).collect::<Vec<_>>(), [1, 0, 0]);
+
+        // the lock was handed to the first waiter, so new lockers can't take it
+        assert!(mutex.try_lock().is_none());
+        assert!(tasks[0].poll());
+        assert!(!tasks[1].poll());
+
+        drop(tasks[0].take_output());
+        assert_eq!(tasks.iter().map(TestTask::wake_count).collect::<Vec<_>>(), [1, 1, 0]);
+        assert!(tasks[1].poll());
+        assert!(!tasks[2].poll());
+
+        drop(tasks[1].take_output());
+        assert!(tasks[2].poll());
+        drop(tasks[2].take_output());
+
+        assert!(!mutex.is_locked());
+    }
+
+    #[test]
+    fn dropped_waiter_passes_lock_on() {
+        let mutex = Mutex::new(());
+        let guard = mutex.try_lock().unwrap();
+
+        let mut first = TestTask::new(mutex.lock());
+        let mut second = TestTask::new(mutex.lock());
+        assert!(!first.poll());
+        assert!(!second.poll());
+
+        // first is granted the lock, but is dropped before it sees it
+        drop(guard);
+        drop(first);
+
+        assert_eq!(second.wake_count(), 1);
+        assert!(second.poll());
+    }
+
+    #[test]
+    fn contended_tasks_increment_counter() {
+        const TASK_COUNT: usize = 300;
+
+        let counter = Mutex::new(0);
+
+        let mut tasks: Vec<_> = (0..TASK_COUNT).map(|_| TestTask::new(async {
+            let mut count = counter.lock().await;
+            let old_count = *count;
+            // another task running while the lock is held would see the old count
+            yield_once().await;
+            *count = old_count + 1;
+        })).collect();
+
+        // poll every unfinished task until they are all done, like a single threaded executor would
+        let mut rounds = 0;
+        while !tasks.iter_mut().fold(true, |done, task| task.poll() && done) {
+            rounds += 1;
+            assert!(rounds <= 2 * TASK_COUNT, "tasks stopped making progress");
+        }
+
+        assert_eq!(*counter.try_lock().unwrap(), TASK_COUNT);
+    }
+
+    #[test]
+    fn readers_share_lock() {
+        let rwlock = RwLock::new(5);
+
+        let first = rwlock.try_read().unwrap();
+        let mut second = TestTask::new(rwlock.read());
+        assert!(second.poll());
+        assert!(rwlock.try_write().is_none());
+
+        assert_eq!(*first + *second.take_output(), 10);
+        drop(first);
+
+        assert!(rwlock.try_write().is_some());
+    }
+
+    #[test]
+    fn waiting_writer_blocks_new_readers() {
+        let rwlock = RwLock::new(());
+        let read_guard = rwlock.try_read().unwrap();
+
+        let mut writer = TestTask::new(rwlock.write());
+        assert!(!writer.poll());
+
+        // readers queue up behind the writer rather than sharing the current read lock
+        assert!(rwlock.try_read().is_none());
+        let mut readers: Vec<_> = (0..2).map(|_| TestTask::new(rwlock.read())).collect();
+        for reader in readers.iter_mut() {
+            assert!(!reader.poll());
+        }
+
+        drop(read_guard);
+        assert_eq!(writer.wake_count(), 1);
+        assert!(writer.poll());
+        assert_eq!(readers.iter().map(TestTask::wake_count).collect::<Vec<_>>(), [0, 0]);
+
+        // releasing the write lock wakes every reader at the front of the queue together
+        drop(writer.take_output());
+        assert_eq!(readers.iter().map(TestTask::wake_count).collect::<Vec<_>>(), [1, 1]);
+        for reader in readers.iter_mut() {
+            assert!(reader.poll());
+        }
+    }
+
+    #[test]
+    fn writer_release_stops_waking_readers_at_next_writer() {
+        let rwlock = RwLock::new(());
+        let write_guard = rwlock.try_write().unwrap();
+
+        let mut first_readers: Vec<_> = (0..2).map(|_| TestTask::new(rwlock.read())).collect();
+        let mut writer = TestTask::new(rwlock.write());
+        let mut last_reader = TestTask::new(rwlock.read());
+        for reader in first_readers.iter_mut() {
+            assert!(!reader.poll());
+        }
+        assert!(!writer.poll());
+        assert!(!last_reader.poll());
+
+        drop(write_guard);
+        for reader in first_readers.iter_mut() {
+            assert_eq!(reader.wake_count(), 1);
+            assert!(reader.poll());
+        }
+        assert_eq!(writer.wake_count(), 0);
+        assert_eq!(last_reader.wake_count(), 0);
+
+        // the writer only gets the lock once both readers are done
+        drop(first_readers[0].take_output());
+        assert_eq!(writer.wake_count(), 0);
+        drop(first_readers[1].take_output());
+        assert_eq!(writer.wake_count(), 1);
+        assert!(writer.poll());
+
+        drop(writer.take_output());
+        assert!(last_reader.poll());
+    }
+}
\ No newline at end of file
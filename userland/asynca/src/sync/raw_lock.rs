@@ -0,0 +1,260 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use aurora_core::sync::Mutex;
+
+/// Set when the lock is held by a writer
+const WRITER: usize = 1;
+/// Set while the waiter queue is not empty, forces new lockers onto the slow path
+const WAITING: usize = 1 << 1;
+/// Amount added to the state for each reader holding the lock
+const READER: usize = 1 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum LockKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    kind: LockKind,
+    /// Set once the lock has been handed off to this waiter
+    granted: Arc<AtomicBool>,
+    waker: Waker,
+}
+
+/// Reader writer lock state shared by [`Mutex`](super::Mutex) and [`RwLock`](super::RwLock)
+///
+/// Uncontended acquisition and release only touch `state`. Once a task has to wait,
+/// every new locker goes through the queue, and released locks are handed directly
+/// to the waiters at the front of the queue, so the lock is fair and writers can't be starved by readers.
+#[derive(Debug)]
+pub(super) struct RawLock {
+    state: AtomicUsize,
+    waiters: Mutex<VecDeque<Waiter>>,
+}
+
+impl RawLock {
+    pub(super) const fn new() -> Self {
+        RawLock {
+            state: AtomicUsize::new(0),
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub(super) fn try_lock(&self, kind: LockKind) -> bool {
+        match kind {
+            LockKind::Read => {
+                let mut state = self.state.load(Ordering::Relaxed);
+
+                // readers don't skip ahead of anything in the queue
+                while state & (WRITER | WAITING) == 0 {
+                    match self.state.compare_exchange_weak(state, state + READER, Ordering::Acquire, Ordering::Relaxed) {
+                        Ok(_) => return true,
+                        Err(new_state) => state = new_state,
+                    }
+                }
+
+                false
+            },
+            LockKind::Write => self.state
+                .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok(),
+        }
+    }
+
+    pub(super) fn lock(&self, kind: LockKind) -> Acquire<'_> {
+        Acquire {
+            lock: self,
+            kind,
+            state: AcquireState::Idle,
+        }
+    }
+
+    pub(super) fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & !WAITING != 0
+    }
+
+    /// # Safety
+    ///
+    /// The lock must be held by the caller with the given kind
+    pub(super) unsafe fn unlock(&self, kind: LockKind) {
+        let released = match kind {
+            LockKind::Read => self.state.fetch_sub(READER, Ordering::Release) != READER | WAITING,
+            LockKind::Write => self.state
+                .compare_exchange(WRITER, 0, Ordering::Release, Ordering::Relaxed)
+                .is_ok(),
+        };
+
+        if !released {
+            let mut waiters = self.waiters.lock();
+            self.unlock_contended(kind, &mut waiters);
+        }
+    }
+
+    /// Releases a lock that may have to be handed to a waiter, called with the waiter queue locked
+    fn unlock_contended(&self, kind: LockKind, waiters: &mut VecDeque<Waiter>) {
+        // a read lock has already been released by the time this is called
+        if kind == LockKind::Write {
+            self.state.fetch_and(!WRITER, Ordering::Release);
+        }
+
+        self.grant_waiters(waiters);
+    }
+
+    /// Hands the lock to as many waiters from the front of the queue as possible
+    ///
+    /// This is either a single writer, or every reader up to the next writer in the queue.
+    fn grant_waiters(&self, waiters: &mut VecDeque<Waiter>) {
+        let Some(front) = waiters.front() else {
+            self.state.fetch_and(!WAITING, Ordering::Relaxed);
+            return;
+        };
+
+        let kind = front.kind;
+        let granted_count = match kind {
+            LockKind::Read => waiters.iter()
+                .take_while(|waiter| waiter.kind == LockKind::Read)
+                .count(),
+            LockKind::Write => 1,
+        };
+        let waiting = if waiters.len() > granted_count { WAITING } else { 0 };
+
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            let new_state = match kind {
+                LockKind::Read if state & WRITER == 0 => ((state & !WAITING) + granted_count * READER) | waiting,
+                LockKind::Write if state & !WAITING == 0 => WRITER | waiting,
+                // the lock is still held in a way that excludes the front waiter
+                _ => return,
+            };
+
+            // readers can release concurrently, so the state is only changed if it has not been modified
+            match self.state.compare_exchange_weak(state, new_state, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(new_state) => state = new_state,
+            }
+        }
+
+        for waiter in waiters.drain(..granted_count) {
+            waiter.granted.store(true, Ordering::Release);
+            waiter.waker.wake();
+        }
+    }
+}
+
+#[derive(Debug)]
+enum AcquireState {
+    Idle,
+    Waiting(Arc<AtomicBool>),
+    Done,
+}
+
+/// Future which resolves once the lock is held with the requested kind
+#[derive(Debug)]
+pub(super) struct Acquire<'a> {
+    lock: &'a RawLock,
+    kind: LockKind,
+    state: AcquireState,
+}
+
+impl Future for Acquire<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let lock = self.lock;
+        let kind = self.kind;
+
+        match &self.state {
+            AcquireState::Idle => {
+                if lock.try_lock(kind) {
+                    self.state = AcquireState::Done;
+                    return Poll::Ready(());
+                }
+
+                let mut waiters = lock.waiters.lock();
+
+                // retry with the queue locked, the lock may have been released in the meantime
+                if waiters.is_empty() && lock.try_lock(kind) {
+                    self.state = AcquireState::Done;
+                    return Poll::Ready(());
+                }
+
+                let granted = Arc::new(AtomicBool::new(false));
+                lock.state.fetch_or(WAITING, Ordering::Relaxed);
+                waiters.push_back(Waiter {
+                    kind,
+                    granted: granted.clone(),
+                    waker: cx.waker().clone(),
+                });
+
+                // the holder could have released the lock before the waiting bit was set,
+                // in which case it would not have checked the queue
+                lock.grant_waiters(&mut waiters);
+                drop(waiters);
+
+                if granted.load(Ordering::Acquire) {
+                    self.state = AcquireState::Done;
+                    Poll::Ready(())
+                } else {
+                    self.state = AcquireState::Waiting(granted);
+                    Poll::Pending
+                }
+            },
+            AcquireState::Waiting(granted) => {
+                if granted.load(Ordering::Acquire) {
+                    self.state = AcquireState::Done;
+                    return Poll::Ready(());
+                }
+
+                let mut waiters = lock.waiters.lock();
+
+                // the lock may have been granted after the first check
+                if granted.load(Ordering::Acquire) {
+                    drop(waiters);
+                    self.state = AcquireState::Done;
+                    return Poll::Ready(());
+                }
+
+                let waiter = waiters.iter_mut()
+                    .find(|waiter| Arc::ptr_eq(&waiter.granted, granted))
+                    .expect("waiting lock future was not in the waiter queue");
+
+                if !waiter.waker.will_wake(cx.waker()) {
+                    waiter.waker = cx.waker().clone();
+                }
+
+                Poll::Pending
+            },
+            AcquireState::Done => panic!("lock future polled after completion"),
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let AcquireState::Waiting(granted) = &self.state else {
+            return;
+        };
+
+        let mut waiters = self.lock.waiters.lock();
+
+        if granted.load(Ordering::Acquire) {
+            // the lock was handed to this future but it will never be used, so pass it on
+            if self.kind == LockKind::Read {
+                self.lock.state.fetch_sub(READER, Ordering::Release);
+            }
+            self.lock.unlock_contended(self.kind, &mut waiters);
+        } else {
+            waiters.retain(|waiter| !Arc::ptr_eq(&waiter.granted, granted));
+            // removing a writer from the front could let the readers behind it go
+            self.lock.grant_waiters(&mut waiters);
+        }
+    }
+}
\ No newline at end of file
@@ -5,14 +5,70 @@ use core::pin::Pin;
 use core::task::{Waker, Poll, Context};
 use core::cell::RefCell;
 use core::any::Any;
+use core::fmt;
 use alloc::rc::Rc;
 use alloc::sync::Arc;
 use alloc::task::Wake;
 
 use crossbeam_queue::SegQueue;
+use thiserror_no_std::Error;
 
 use aurora_core::prelude::*;
 
+/// Maximum length in bytes of a task name, longer names are truncated
+pub const TASK_NAME_MAX_LEN: usize = 32;
+
+/// Short name of a task used when reporting executor diagnostics
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct TaskName {
+    name: [u8; TASK_NAME_MAX_LEN],
+    len: usize,
+}
+
+impl TaskName {
+    pub fn new(name: &str) -> Self {
+        let mut len = name.len().min(TASK_NAME_MAX_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        let mut out = TaskName {
+            name: [0; TASK_NAME_MAX_LEN],
+            len,
+        };
+        out.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+
+        out
+    }
+
+    pub fn as_str(&self) -> &str {
+        // name is only ever constructed from a valid str cut on a char boundary
+        core::str::from_utf8(&self.name[..self.len]).unwrap()
+    }
+}
+
+impl fmt::Debug for TaskName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for TaskName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    /// The task is currently being polled
+    Running,
+    /// The task has been aborted, but the executor has not dropped it yet
+    Aborted,
+    /// The task is waiting to be woken or polled
+    Idle,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct TaskId(u64);
 
@@ -20,32 +76,71 @@ impl TaskId {
     pub fn new() -> TaskId {
         static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(0);
 
-        TaskId(NEXT_TASK_ID.load(Ordering::Relaxed))
+        TaskId(NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed))
     }
 }
 
+/// The id and name are kept outside of the task, so they can be read while the task is being polled
 #[derive(Clone)]
-pub struct TaskHandle(Rc<RefCell<Task>>);
+pub struct TaskHandle {
+    id: TaskId,
+    name: Option<TaskName>,
+    task: Rc<RefCell<Task>>,
+}
 
 impl TaskHandle {
     pub fn id(&self) -> TaskId {
-        self.0.borrow().id
+        self.id
+    }
+
+    pub fn name(&self) -> Option<TaskName> {
+        self.name
+    }
+
+    /// Returns the number of times the task has been polled, or None if it is currently being polled
+    pub fn poll_count(&self) -> Option<u64> {
+        Some(self.task.try_borrow().ok()?.poll_count)
+    }
+
+    pub fn state(&self) -> TaskState {
+        // the task is only borrowed mutably while it is being polled or cancelled
+        match self.task.try_borrow() {
+            Err(_) => TaskState::Running,
+            Ok(task) if task.task_join.borrow().aborted => TaskState::Aborted,
+            Ok(_) => TaskState::Idle,
+        }
     }
 
     pub fn poll(&self) -> Poll<()> {
-        self.0.borrow_mut().poll()
+        self.task.borrow_mut().poll()
+    }
+
+    /// Returns true if [`JoinHandle::abort`] was called on this task
+    pub fn is_aborted(&self) -> bool {
+        self.task.borrow().task_join.borrow().aborted
+    }
+
+    /// Drops the task's future and reports to the join handle that the task was aborted
+    pub fn cancel(&self) {
+        self.task.borrow_mut().cancel()
     }
 }
 
 pub struct Task {
-    id: TaskId,
-    future: Pin<Box<dyn Future<Output = Box<dyn Any>>>>,
+    /// Number of times this task has been polled
+    poll_count: u64,
+    /// This is `None` once the task has been cancelled
+    future: Option<Pin<Box<dyn Future<Output = Box<dyn Any>>>>>,
     waker: Waker,
     task_join: Rc<RefCell<TaskJoinInner>>,
 }
 
 impl Task {
-    pub fn new<T: 'static>(task: impl Future<Output = T> + 'static, task_queue: Arc<SegQueue<TaskId>>) -> (TaskHandle, JoinHandle<T>) {
+    pub fn new<T: 'static>(
+        name: Option<TaskName>,
+        task: impl Future<Output = T> + 'static,
+        task_queue: Arc<SegQueue<TaskId>>,
+    ) -> (TaskHandle, JoinHandle<T>) {
         // make a future that wraps the original future's return value in a Box<dyn Any>
         let future = async {
             let task_result = task.await;
@@ -53,15 +148,22 @@ impl Task {
         };
 
         let task_id = TaskId::new();
+        let waker: Waker = Arc::new(TaskWaker {
+            task_id,
+            task_queue,
+        }).into();
 
         let task = Task {
-            id: task_id,
-            future: Box::pin(future),
-            waker: Arc::new(TaskWaker {
-                task_id,
-                task_queue,
-            }).into(),
-            task_join: Rc::default(),
+            poll_count: 0,
+            future: Some(Box::pin(future)),
+            waker: waker.clone(),
+            task_join: Rc::new(RefCell::new(TaskJoinInner {
+                is_finished: false,
+                aborted: false,
+                value: None,
+                waiting_waker: None,
+                task_waker: waker,
+            })),
         };
 
         let join_handle = JoinHandle {
@@ -69,7 +171,11 @@ impl Task {
             _marker: PhantomData,
         };
 
-        let task_handle = TaskHandle(Rc::new(RefCell::new(task)));
+        let task_handle = TaskHandle {
+            id: task_id,
+            name,
+            task: Rc::new(RefCell::new(task)),
+        };
 
         (task_handle, join_handle)
     }
@@ -78,10 +184,18 @@ impl Task {
         let Task {
             future,
             waker,
+            poll_count,
             ..
         } = self;
 
-        let mut context = Context::from_waker(&waker);
+        let Some(future) = future else {
+            // the task was cancelled, so it is already finished
+            return Poll::Ready(());
+        };
+
+        *poll_count += 1;
+
+        let mut context = Context::from_waker(waker);
         match future.as_mut().poll(&mut context) {
             Poll::Ready(result) => {
                 let mut task_join = self.task_join.borrow_mut();
@@ -95,42 +209,96 @@ impl Task {
             Poll::Pending => Poll::Pending,
         }
     }
+
+    fn cancel(&mut self) {
+        // drop the future before reporting the task as finished so all of its destructors have run
+        self.future = None;
+
+        let mut task_join = self.task_join.borrow_mut();
+
+        task_join.is_finished = true;
+        task_join.waiting_waker.take().map(Waker::wake);
+    }
 }
 
-#[derive(Default)]
+/// Returned when waiting on a task that was aborted before it completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("task was aborted")]
+pub struct Aborted;
+
 pub(super) struct TaskJoinInner {
     is_finished: bool,
+    aborted: bool,
     pub(super) value: Option<Box<dyn Any>>,
     waiting_waker: Option<Waker>,
+    /// Waker of the task itself, used to let the executor know the task was aborted
+    task_waker: Waker,
 }
 
+/// A handle used to wait for a spawned task to complete
+/// 
+/// Dropping the join handle does not stop the task, it continues running detached from the handle.
 pub struct JoinHandle<T> {
     pub(super) inner: Rc<RefCell<TaskJoinInner>>,
     _marker: PhantomData<Box<T>>,
 }
 
 impl<T: 'static> JoinHandle<T> {
+    /// Returns true if the task has completed or its future has been dropped after being aborted
     pub fn is_finished(&self) -> bool {
         self.inner.borrow().is_finished
     }
 
+    /// Cancels the task
+    /// 
+    /// The task's future is dropped by the executor the next time it would be polled,
+    /// it is never dropped while it is being polled.
+    /// Waiting tasks are woken with `Err(Aborted)` once the future has been dropped.
+    /// This does nothing if the task has already finished.
+    pub fn abort(&self) {
+        let mut inner = self.inner.borrow_mut();
+
+        if inner.is_finished || inner.aborted {
+            return;
+        }
+
+        inner.aborted = true;
+        // schedule the task so the executor drops it even if it is waiting for something that never happens
+        inner.task_waker.wake_by_ref();
+    }
+
+    /// Lets the task keep running without anything waiting on its output
+    /// 
+    /// This is the same as dropping the join handle.
+    pub fn detach(self) {}
+
     /// Gets the output of this join handle
     /// 
     /// # Panics
     /// 
     /// panics if the task has not finished
-    pub(super) fn get_output(self) -> T {
-        *self.inner.borrow_mut().value.take().unwrap().downcast().unwrap()
+    pub(super) fn get_output(self) -> Result<T, Aborted> {
+        let mut inner = self.inner.borrow_mut();
+        assert!(inner.is_finished, "task has not finished");
+
+        match inner.value.take() {
+            Some(value) => Ok(*value.downcast().expect("task returned wrong type of value")),
+            None => Err(Aborted),
+        }
     }
 }
 
 impl<T: 'static> Future for JoinHandle<T> {
-    type Output = T;
+    type Output = Result<T, Aborted>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let mut inner = self.inner.borrow_mut();
 
         let Some(value) = inner.value.take() else {
+            if inner.is_finished {
+                return Poll::Ready(Err(Aborted));
+            }
+
             assert!(inner.waiting_waker.is_none(), "multiple tasks cannot wait on 1 task");
             inner.waiting_waker = Some(cx.waker().clone());
             return Poll::Pending;
@@ -139,7 +307,7 @@ impl<T: 'static> Future for JoinHandle<T> {
         let out = value.downcast::<T>()
             .expect("task returned wrong type of value");
 
-        Poll::Ready(*out)
+        Poll::Ready(Ok(*out))
     }
 }
 
@@ -0,0 +1,83 @@
+//! Helpers for polling futures by hand in tests
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+
+/// Waker which counts how many times it has been woken
+#[derive(Default)]
+struct CountingWaker(AtomicUsize);
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// A future polled by hand with its own waker, so tests can see exactly which tasks are woken
+pub struct TestTask<'a, T> {
+    future: Pin<Box<dyn Future<Output = T> + 'a>>,
+    waker: Arc<CountingWaker>,
+    output: Option<T>,
+}
+
+impl<'a, T> TestTask<'a, T> {
+    pub fn new(future: impl Future<Output = T> + 'a) -> Self {
+        TestTask {
+            future: Box::pin(future),
+            waker: Arc::default(),
+            output: None,
+        }
+    }
+
+    /// Polls the future if it has not finished yet, and returns true if it has finished
+    pub fn poll(&mut self) -> bool {
+        if self.output.is_none() {
+            let waker = Waker::from(self.waker.clone());
+            if let Poll::Ready(output) = self.future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                self.output = Some(output);
+            }
+        }
+
+        self.output.is_some()
+    }
+
+    pub fn wake_count(&self) -> usize {
+        self.waker.0.load(Ordering::SeqCst)
+    }
+
+    /// Takes the output of a finished task, such as a lock guard, so it can be dropped
+    pub fn take_output(&mut self) -> T {
+        self.output.take().expect("task has not finished")
+    }
+}
+
+/// Returns a future which is pending once, after waking its task, like a task yielding to the executor
+pub fn yield_once() -> YieldOnce {
+    YieldOnce(false)
+}
+
+pub struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,136 @@
+//! Printing to the kernel debug log, reading kernel log messages, and describing address spaces and memory usage
+use core::fmt::Write;
+
+use alloc::vec;
+
+use bit_utils::Size;
+use sys::{
+    cap_clone, AddressSpace, CapFlags, CapabilitySpace, CspaceTarget, DebugCap, KResult, MappingKind,
+    Memory, MemoryMappingFlags, MemoryMappingOptions, MemoryNewFlags, ZoneMemInfo,
+};
+
+pub use sys::{dprint, dprintln};
+
+use crate::prelude::*;
+use crate::{addr_space, this_context};
+use crate::allocator::addr_space::{AddrSpaceError, MapMemoryArgs, MapMemoryResult};
+
+/// Size of the buffer kernel log records are read into, this must be bigger than the longest kernel log record
+const KLOG_BUFFER_SIZE: Size = Size::from_pages(16);
+
+/// Reads every message still stored in the kernel log
+pub fn klog(debug_cap: &DebugCap) -> Result<String, AddrSpaceError> {
+    let buffer = Memory::new(&this_context().allocator, KLOG_BUFFER_SIZE, MemoryNewFlags::empty())?;
+    let mapped_buffer = cap_clone(CspaceTarget::Current, CspaceTarget::Current, &buffer, CapFlags::all())?;
+
+    let MapMemoryResult { address, size, .. } = addr_space().map_memory(MapMemoryArgs {
+        memory: Some(mapped_buffer),
+        options: MemoryMappingOptions {
+            read: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    })?;
+    let size = size.bytes();
+
+    let mut messages = Vec::new();
+    let mut seq = 0;
+    let result = loop {
+        let (bytes_written, next_seq) = match debug_cap.read_klog(&buffer, 0, size, seq) {
+            Ok(read) => read,
+            Err(error) => break Err(error.into()),
+        };
+
+        if bytes_written == 0 {
+            break Ok(());
+        }
+
+        // safety: the kernel only writes whole records to the buffer, and bytes_written is within the mapping
+        let data = unsafe { core::slice::from_raw_parts(address as *const u8, bytes_written) };
+        messages.extend_from_slice(data);
+        seq = next_seq;
+    };
+
+    unsafe {
+        addr_space().unmap_memory(address)
+            .expect("failed to unmap kernel log buffer");
+    }
+
+    result.map(|_| String::from_utf8_lossy(&messages).into_owned())
+}
+
+/// Formats every mapping in `address_space`, one line per mapping in address order
+/// 
+/// Each line has the address range, size, permissions, and kind of the mapping.
+/// If `cspace` is given, the id of a capability in it which references the mapped object is also shown.
+pub fn pmap(address_space: &AddressSpace, cspace: Option<&CapabilitySpace>) -> KResult<String> {
+    let mut out = String::new();
+
+    for mapping in address_space.mappings(cspace)? {
+        let flags = mapping.flags();
+        let permissions = [
+            (MemoryMappingFlags::READ, 'r'),
+            (MemoryMappingFlags::WRITE, 'w'),
+            (MemoryMappingFlags::EXEC, 'x'),
+        ].map(|(flag, c)| if flags.contains(flag) { c } else { '-' });
+
+        let kind = match mapping.kind() {
+            Some(MappingKind::Memory) => "memory",
+            Some(MappingKind::EventPool) => "event pool",
+            Some(MappingKind::PhysMem) => "phys mem",
+            Some(MappingKind::Guard) => "guard",
+            None => "unknown",
+        };
+
+        write!(
+            out,
+            "{:#018x}-{:#018x} {:>8} KiB {}{}{} {}",
+            mapping.address,
+            mapping.address + mapping.size,
+            mapping.size / 1024,
+            permissions[0],
+            permissions[1],
+            permissions[2],
+            kind,
+        ).unwrap();
+
+        if let Some(cap_id) = mapping.cap_id() {
+            write!(out, " {cap_id:?}").unwrap();
+        }
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+/// Formats the memory usage of the whole system, followed by one line for each physical memory zone
+/// 
+/// The largest free block of a zone is the biggest allocation which could currently be made from it,
+/// if it is much smaller than the zone's free memory then the zone is fragmented.
+pub fn meminfo(debug_cap: &DebugCap) -> KResult<String> {
+    let zone_count = debug_cap.system_mem_info(&mut [])?.zone_count;
+    let mut zones = vec![ZoneMemInfo::default(); zone_count];
+    let mem_info = debug_cap.system_mem_info(&mut zones)?;
+
+    let kib = |pages: usize| Size::from_pages(pages).bytes() / 1024;
+
+    let mut out = String::new();
+    writeln!(out, "total:  {:>10} KiB", kib(mem_info.total_pages)).unwrap();
+    writeln!(out, "used:   {:>10} KiB", kib(mem_info.used_pages())).unwrap();
+    writeln!(out, "free:   {:>10} KiB", kib(mem_info.free_pages)).unwrap();
+    writeln!(out, "heap:   {:>10} KiB", mem_info.heap_bytes / 1024).unwrap();
+    writeln!(out, "memory: {:>10} KiB in {} capabilities", kib(mem_info.memory_pages), mem_info.memory_count).unwrap();
+
+    for zone in zones.iter() {
+        writeln!(
+            out,
+            "zone {:#018x}: {:>10} KiB total {:>10} KiB free {:>10} KiB largest free block",
+            zone.start_address,
+            kib(zone.total_pages),
+            kib(zone.free_pages),
+            kib(zone.largest_free_pages),
+        ).unwrap();
+    }
+
+    Ok(out)
+}
\ No newline at end of file
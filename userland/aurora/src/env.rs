@@ -1,19 +1,78 @@
+use core::any::type_name;
+use core::fmt::{self, Display};
+
 use thiserror_no_std::Error;
-use aser::{Value, AserError};
+use aser::{Value, ValueKind, AserError};
 use serde::{Serialize, Deserialize};
 use aurora_core::prelude::*;
 use aurora_core::collections::HashMap;
 use aurora_core::sync::Once;
+use arpc::RpcClient;
+use asynca::async_sys::AsyncChannel;
+use sys::Channel;
+
+use crate::io::OutputStream;
+use crate::log::Log;
+use crate::registry::{Registry, RegistryAsync};
 
 #[derive(Debug, Error)]
 pub enum EnvError {
     #[error("Serialization error: {0}")]
     AserError(#[from] AserError),
-    #[error("No argument with the given name exists")]
-    InvalidNamedArg,
 }
 
+/// Identifies the argument an [`ArgError`] is about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgName {
+    Named(String),
+    Positional(usize),
+    /// A slot in the handle table, see [`handle`]
+    Handle(usize),
+}
+
+impl Display for ArgName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Named(name) => write!(f, "`{name}`"),
+            Self::Positional(index) => write!(f, "at position {index}"),
+            Self::Handle(index) => write!(f, "in handle slot {index}"),
+        }
+    }
+}
+
+/// Why an argument could not be read from [`Args`]
+#[derive(Debug, Error)]
+pub enum ArgError {
+    #[error("No argument {name} was passed")]
+    Missing {
+        name: ArgName,
+    },
+    #[error("Argument {name} should be a {expected}, but a {found} was passed")]
+    WrongType {
+        name: ArgName,
+        expected: &'static str,
+        found: ValueKind,
+    },
+    #[error("Argument {name} could not be deserialized: {source}")]
+    Deserialize {
+        name: ArgName,
+        source: AserError,
+    },
+}
+
+/// Handle table slot of the channel standard input is read from
+pub const STDIN: usize = 0;
+/// Handle table slot of the channel [`stdout`] sends output on
+pub const STDOUT: usize = 1;
+/// Handle table slot of the channel [`stderr`] sends output on
+pub const STDERR: usize = 2;
+
 static THIS_NAMESPACE: Once<Namespace> = Once::new();
+static ENV_VARS: Once<HashMap<String, String>> = Once::new();
+
+static STDIN_CHANNEL: Once<Option<Channel>> = Once::new();
+static STDOUT_STREAM: Once<OutputStream> = Once::new();
+static STDERR_STREAM: Once<OutputStream> = Once::new();
 
 pub fn this_namespace() -> &'static Namespace {
     THIS_NAMESPACE.get().expect("namespace not initialized")
@@ -23,9 +82,101 @@ pub fn args() -> &'static Args {
     &this_namespace().args
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Gets the registry this process was given, if the namespace is initialized and has one
+pub(crate) fn try_registry() -> Option<&'static Registry> {
+    THIS_NAMESPACE.get()?.registry.as_ref()
+}
+
+/// Gets the log endpoint this process was given, if the namespace is initialized and has one
+pub(crate) fn try_log() -> Option<&'static Log> {
+    THIS_NAMESPACE.get()?.log.as_ref()
+}
+
+/// Gets the handle table this process was given, if the namespace is initialized
+pub(crate) fn try_handles() -> Option<&'static [Option<Value>]> {
+    Some(&THIS_NAMESPACE.get()?.handles)
+}
+
+/// Gets the channel the parent sends shutdown requests on, if the namespace is initialized and has one
+pub(crate) fn try_shutdown_channel() -> Option<&'static AsyncChannel> {
+    THIS_NAMESPACE.get()?.shutdown_channel.as_ref()
+}
+
+/// Gets the service registry this process was given by its parent
+///
+/// # Panics
+///
+/// Panics if this process was not given a registry
+pub fn registry() -> &'static Registry {
+    try_registry().expect("process was not given a service registry")
+}
+
+/// Gets the value in slot `index` of the handle table this process was given by its parent
+///
+/// Slots [`STDIN`], [`STDOUT`], and [`STDERR`] hold the standard streams, the meaning of any other slot is up to the program.
+/// Capabilities are not cloned, so a slot holding a capability should only be read once.
+/// Every slot is empty if the namespace is not initialized.
+pub fn handle<T: Deserialize<'static>>(index: usize) -> Result<T, ArgError> {
+    let value = THIS_NAMESPACE.get()
+        .and_then(|namespace| namespace.handles.get(index))
+        .and_then(Option::as_ref);
+    deserialize_arg(ArgName::Handle(index), value)
+}
+
+fn output_stream(index: usize) -> OutputStream {
+    match handle::<Channel>(index) {
+        Ok(channel) => OutputStream::from_channel(channel),
+        Err(ArgError::Missing { .. }) => OutputStream::debug(),
+        Err(error) => {
+            dprintln!("invalid output stream, printing to debug log instead: {error}");
+            OutputStream::debug()
+        },
+    }
+}
+
+/// Gets the channel standard input is read from, or None if the parent did not set one
+pub fn stdin() -> Option<&'static Channel> {
+    STDIN_CHANNEL.call_once(|| handle(STDIN).ok()).as_ref()
+}
+
+/// Gets standard output, which prints to the kernel debug log if the parent did not set it
+pub fn stdout() -> &'static OutputStream {
+    STDOUT_STREAM.call_once(|| output_stream(STDOUT))
+}
+
+/// Gets standard error, which prints to the kernel debug log if the parent did not set it
+pub fn stderr() -> &'static OutputStream {
+    STDERR_STREAM.call_once(|| output_stream(STDERR))
+}
+
+/// Looks up the service registered under `name` in the [`registry`], and makes a client for it
+pub async fn service<T: RpcClient>(name: &str) -> Option<T> {
+    let endpoint = registry().lookup(name.to_owned()).await?;
+
+    Some(T::from_endpoint(endpoint))
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Namespace {
     pub(crate) args: Args,
+    /// Standard streams and other handles, indexed by slot, see [`handle`]
+    pub(crate) handles: Vec<Option<Value>>,
+    /// Registry used to look up services, every process spawned after the registry server gets one
+    pub(crate) registry: Option<Registry>,
+    /// Where messages from the [`log`](crate::log) macros are sent
+    pub(crate) log: Option<Log>,
+    /// Recieves shutdown requests from the parent, see [`spawn_shutdown_listener`](crate::process::spawn_shutdown_listener)
+    pub(crate) shutdown_channel: Option<AsyncChannel>,
+}
+
+/// Same as [`Namespace`], but borrows its fields so spawning a process does not need to clone capabilities
+#[derive(Serialize)]
+pub(crate) struct NamespaceRef<'a> {
+    pub(crate) args: &'a Args,
+    pub(crate) handles: &'a [Option<Value>],
+    pub(crate) registry: Option<&'a Registry>,
+    pub(crate) log: Option<&'a Log>,
+    pub(crate) shutdown_channel: Option<&'a AsyncChannel>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -43,14 +194,171 @@ impl Args {
         }
     }
 
-    pub fn named_arg<'a, T: Deserialize<'a>>(&'a self, name: &str) -> Result<T, EnvError> {
-        let value = self.named_args.get(name).ok_or(EnvError::InvalidNamedArg)?;
-        Ok(value.into_deserialize()?)
+    /// Gets the argument passed with the name `name`
+    pub fn named_arg<'a, T: Deserialize<'a>>(&'a self, name: &str) -> Result<T, ArgError> {
+        deserialize_arg(ArgName::Named(name.to_owned()), self.named_args.get(name))
+    }
+
+    /// Gets the positional argument at `index`
+    pub fn positional<'a, T: Deserialize<'a>>(&'a self, index: usize) -> Result<T, ArgError> {
+        deserialize_arg(ArgName::Positional(index), self.positional_args.get(index))
+    }
+
+    /// Same as [`named_arg`](Self::named_arg), but panics with a message listing the arguments which were passed
+    /// if the argument can't be read
+    #[track_caller]
+    pub fn require<'a, T: Deserialize<'a>>(&'a self, name: &str) -> T {
+        self.named_arg(name).unwrap_or_else(|error| self.invalid_arg(error))
     }
+
+    /// Same as [`positional`](Self::positional), but panics with a message listing the arguments which were passed
+    /// if the argument can't be read
+    #[track_caller]
+    pub fn require_positional<'a, T: Deserialize<'a>>(&'a self, index: usize) -> T {
+        self.positional(index).unwrap_or_else(|error| self.invalid_arg(error))
+    }
+
+    /// Returns the names of every named argument, in sorted order
+    pub fn named_arg_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.named_args.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn positional_count(&self) -> usize {
+        self.positional_args.len()
+    }
+
+    #[track_caller]
+    fn invalid_arg(&self, error: ArgError) -> ! {
+        panic!(
+            "{}: {error}, named arguments passed: [{}], positional arguments passed: {}",
+            aurora_core::process_name().unwrap_or("unnamed process"),
+            self.named_arg_names().join(", "),
+            self.positional_count(),
+        );
+    }
+}
+
+fn deserialize_arg<'a, T: Deserialize<'a>>(name: ArgName, value: Option<&'a Value>) -> Result<T, ArgError> {
+    let Some(value) = value else {
+        return Err(ArgError::Missing { name });
+    };
+
+    value.into_deserialize().map_err(|error| match error {
+        AserError::InvalidType(_) => ArgError::WrongType {
+            name,
+            expected: type_name::<T>(),
+            found: value.kind(),
+        },
+        source => ArgError::Deserialize { name, source },
+    })
 }
 
 pub fn init_namespace(namespace_data: &[u8]) -> Result<(), EnvError> {
     let namespace: Namespace = aser::from_bytes(namespace_data)?;
     THIS_NAMESPACE.call_once(|| namespace);
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Gets the environment variables of this process, if they are initialized
+pub(crate) fn try_vars() -> Option<&'static HashMap<String, String>> {
+    ENV_VARS.get()
+}
+
+/// Returns all environment variables this process was given by its parent
+pub fn vars() -> &'static HashMap<String, String> {
+    try_vars().expect("environment variables not initialized")
+}
+
+/// Gets the value of the environment variable `key`, or None if it is not set
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().get(key).map(String::as_str)
+}
+
+/// Initializes environment variables from the data passed in on startup, empty data means there are none
+pub fn init_env_vars(env_data: &[u8]) -> Result<(), EnvError> {
+    let vars = if env_data.is_empty() {
+        HashMap::default()
+    } else {
+        aser::from_bytes(env_data)?
+    };
+
+    ENV_VARS.call_once(|| vars);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::num::NonZeroU32;
+
+    use super::*;
+
+    fn test_args() -> Args {
+        let mut args = Args::default();
+        args.positional_args.push(Value::from_serialize(&"first").unwrap());
+        args.named_args.insert("count".to_owned(), Value::from_serialize(&12u64).unwrap());
+        args.named_args.insert("name".to_owned(), Value::from_serialize(&"aurora").unwrap());
+        args.named_args.insert("zero".to_owned(), Value::from_serialize(&0u32).unwrap());
+        args
+    }
+
+    #[test]
+    fn present_args_are_read() {
+        let args = test_args();
+
+        assert_eq!(args.named_arg::<u64>("count").unwrap(), 12);
+        assert_eq!(args.named_arg::<&str>("name").unwrap(), "aurora");
+        assert_eq!(args.positional::<&str>(0).unwrap(), "first");
+        assert_eq!(args.require::<u64>("count"), 12);
+    }
+
+    #[test]
+    fn missing_args() {
+        let args = test_args();
+
+        assert!(matches!(
+            args.named_arg::<u64>("cuont"),
+            Err(ArgError::Missing { name: ArgName::Named(name) }) if name == "cuont"
+        ));
+        assert!(matches!(
+            args.positional::<u64>(1),
+            Err(ArgError::Missing { name: ArgName::Positional(1) })
+        ));
+    }
+
+    #[test]
+    fn wrong_type_args() {
+        let args = test_args();
+
+        match args.named_arg::<u64>("name") {
+            Err(ArgError::WrongType { name, expected, found }) => {
+                assert_eq!(name, ArgName::Named("name".to_owned()));
+                assert_eq!(expected, "u64");
+                assert_eq!(found, ValueKind::String);
+            },
+            other => panic!("expected WrongType error, got {other:?}"),
+        }
+
+        assert!(matches!(
+            args.positional::<bool>(0),
+            Err(ArgError::WrongType { name: ArgName::Positional(0), found: ValueKind::String, .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_value_fails_to_deserialize() {
+        let args = test_args();
+
+        assert!(matches!(
+            args.named_arg::<NonZeroU32>("zero"),
+            Err(ArgError::Deserialize { name: ArgName::Named(name), .. }) if name == "zero"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "named arguments passed: [count, name, zero], positional arguments passed: 1")]
+    fn require_lists_passed_args() {
+        test_args().require::<u64>("cuont");
+    }
+}
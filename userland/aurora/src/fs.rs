@@ -1,32 +1,421 @@
+use serde::{Serialize, Deserialize};
 use thiserror_no_std::Error;
+use aurora_core::sync::Once;
+use sys::{KResult, SysErr};
 
-use crate::{prelude::*, service::AppService};
+use crate::prelude::*;
+use crate::env;
+use crate::registry::RegistryAsync;
+use crate::io::{AsyncRead, AsyncWrite};
 
-#[derive(Debug, Error)]
+/// Maximum number of bytes requested from the fs server in 1 read
+const READ_CHUNK_SIZE: u32 = 0x10000;
+/// Maximum number of bytes sent to the fs server in 1 write
+const WRITE_CHUNK_SIZE: usize = 0x10000;
+
+/// Offset of the boot signature in the master boot record
+pub const BOOT_SIGNATURE_OFFSET: usize = 510;
+/// Ends the master boot record of a disk which can be booted from
+pub const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Error)]
 pub enum FsError {
+    #[error("Path is not a valid absolute path")]
     InvalidPath,
+    #[error("No file or directory exists at the given path")]
+    NotFound,
+    #[error("Path refers to a directory")]
+    IsDirectory,
+    #[error("Part of the path is not a directory")]
+    NotDirectory,
+    #[error("File handle is not open")]
+    InvalidHandle,
+    #[error("Invalid combination of open flags")]
+    InvalidFlags,
+    #[error("File was not opened with the access needed for the operation")]
+    AccessDenied,
+    #[error("File would be too large")]
+    FileTooLarge,
+    #[error("This process was not given an fs server")]
+    NoFsServer,
+    #[error("Seek to a position before the start of the file")]
+    InvalidSeek,
+}
+
+/// Options for opening a file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpenFlags {
+    pub read: bool,
+    pub write: bool,
+    /// Create the file if it does not exist, requires `write`
+    pub create: bool,
+    /// Truncate the file to 0 bytes when it is opened, requires `write`
+    pub truncate: bool,
+}
+
+/// Refers to a file opened with [`FsServer::open`], it is only valid until it is closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FileHandle(pub u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileKind {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileInfo {
+    pub kind: FileKind,
+    /// Size in bytes, always 0 for directories
+    pub size: u64,
+}
+
+/// A disk found by the fs server when it started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskInfo {
+    /// Size of a block in bytes
+    pub block_size: u64,
+    /// Number of blocks on the disk
+    pub capacity: u64,
+    /// The 2 bytes at [`BOOT_SIGNATURE_OFFSET`] in the first block, None if the first block could not be read
+    pub boot_signature: Option<[u8; 2]>,
+}
+
+/// Paths are absolute, and are normalized by the server with [`normalize_path`] before being used
+#[arpc::service(service_id = 11, name = "Fs")]
+pub trait FsServer {
+    /// Opens the file at `path`
+    fn open(&self, path: String, flags: OpenFlags) -> Result<FileHandle, FsError>;
+
+    /// Reads up to `len` bytes starting at `offset`, fewer bytes are returned if the end of the file is reached
+    fn read(&self, handle: FileHandle, offset: u64, len: u32) -> Result<Vec<u8>, FsError>;
+
+    /// Writes `data` starting at `offset`, extending the file with zeros if `offset` is past the end
+    ///
+    /// Returns the number of bytes written, or [`FsError::FileTooLarge`] if the file would grow past the server's maximum file size
+    fn write(&self, handle: FileHandle, offset: u64, data: Vec<u8>) -> Result<u32, FsError>;
+
+    fn stat(&self, path: String) -> Result<FileInfo, FsError>;
+
+    /// Closes the file, the handle may be reused for files opened after this
+    fn close(&self, handle: FileHandle);
+
+    /// Returns the disks the server found when it started
+    fn disks(&self) -> Vec<DiskInfo>;
+}
+
+/// Normalizes an absolute path
+///
+/// Empty components and `.` are removed, and `..` removes the previous component.
+/// `..` at the root stays at the root. The returned path always starts with `/`, and never ends with `/` unless it is the root.
+pub fn normalize_path(path: &str) -> Result<String, FsError> {
+    let Some(relative_path) = path.strip_prefix('/') else {
+        return Err(FsError::InvalidPath);
+    };
+
+    let mut components = Vec::new();
+
+    for component in relative_path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => {
+                components.pop();
+            },
+            _ if component.contains('\0') => return Err(FsError::InvalidPath),
+            _ => components.push(component),
+        }
+    }
+
+    let mut out = String::with_capacity(path.len());
+    for component in components.iter() {
+        out.push('/');
+        out.push_str(component);
+    }
+
+    if out.is_empty() {
+        out.push('/');
+    }
+
+    Ok(out)
+}
+
+static FS_CLIENT: Once<Option<Fs>> = Once::new();
+static REGISTRY_FS_CLIENT: Once<Fs> = Once::new();
+
+/// Gets the fs server this process was given in the `fs_server` named argument
+pub fn fs_client() -> Option<&'static Fs> {
+    FS_CLIENT.call_once(|| env::args().named_arg("fs_server").ok()).as_ref()
+}
+
+/// Gets the fs server used by the path based functions in this module
+///
+/// This is the one from [`fs_client`], or if this process was not given one, the `fs` service in the [registry](env::registry).
+///
+/// # Panics
+///
+/// Panics if neither is available
+pub async fn client() -> &'static Fs {
+    if let Some(fs) = fs_client().or_else(|| REGISTRY_FS_CLIENT.get()) {
+        return fs;
+    }
+
+    let endpoint = match env::try_registry() {
+        Some(registry) => registry.lookup("fs".to_owned()).await,
+        None => None,
+    };
+    let fs = endpoint.map(Fs::from)
+        .expect("no fs server available, this process was not given an `fs_server` argument and there is no `fs` service in its registry");
+
+    // another task may have looked it up at the same time, in which case this client is dropped
+    REGISTRY_FS_CLIENT.call_once(|| fs)
+}
+
+/// Reads the whole file at `path`
+pub async fn read(path: &str) -> Result<Vec<u8>, FsError> {
+    let mut file = File::open(path, OpenFlags {
+        read: true,
+        ..Default::default()
+    }).await?;
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).await?;
+    file.close().await;
+
+    Ok(data)
+}
+
+/// Writes `data` to the file at `path`, creating it if it doesn't exist and replacing its contents if it does
+pub async fn write(path: &str, data: &[u8]) -> Result<(), FsError> {
+    let mut file = File::open(path, OpenFlags {
+        write: true,
+        create: true,
+        truncate: true,
+        ..Default::default()
+    }).await?;
+
+    file.write_all(data).await?;
+    file.close().await;
+
+    Ok(())
 }
 
-//#[arpc::service(service_id = 2, name = "Fs", AppService = crate::service)]
-pub trait FsService: AppService {
-    fn access(&self, path: String) -> Result<FsEntry, FsError>;
+/// Gets information about the file or directory at `path`
+pub async fn metadata(path: &str) -> Result<FileInfo, FsError> {
+    client().await.stat(path.to_owned()).await
 }
 
-pub enum FsEntry {
-    File(File),
-    Directory(Directory),
-    Link(Link),
+/// Position to [seek](File::seek) to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    /// Offset from the start of the file
+    Start(u64),
+    /// Offset from the end of the file
+    End(i64),
+    /// Offset from the current position
+    Current(i64),
 }
 
+/// A file opened on the fs server, which is closed when it is dropped
+///
+/// Reads and writes start at the file's position and move it forward. Every operation takes `&mut self`,
+/// so tasks which share a file have to synchronize with each other, for example with an [`asynca::sync::Mutex`].
+/// A task which holds the lock for a whole read or write can't have another task move the position part way through.
 pub struct File {
-    name: String,
+    fs: &'static Fs,
+    handle: FileHandle,
+    /// Normalized path the file was opened with, used to find the size of the file when seeking from the end
+    path: String,
+    position: u64,
 }
 
-pub struct Directory {
-    name: String,
+impl File {
+    /// Opens the file at `path` with the fs server from [`client`], the position starts at the start of the file
+    pub async fn open(path: &str, flags: OpenFlags) -> Result<File, FsError> {
+        let path = normalize_path(path)?;
+
+        let fs = client().await;
+        let handle = fs.open(path.clone(), flags).await?;
+
+        Ok(File {
+            fs,
+            handle,
+            path,
+            position: 0,
+        })
+    }
+
+    /// Returns the offset in the file the next read or write will happen at
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Moves the position, and returns the new position
+    ///
+    /// Seeking past the end of the file is allowed, a write there extends the file with zeros.
+    /// Fails with [`FsError::InvalidSeek`] if the position would be before the start of the file.
+    pub async fn seek(&mut self, position: SeekFrom) -> Result<u64, FsError> {
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            },
+            SeekFrom::End(offset) => (self.metadata().await?.size, offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+
+        self.position = base.checked_add_signed(offset).ok_or(FsError::InvalidSeek)?;
+        Ok(self.position)
+    }
+
+    pub async fn metadata(&self) -> Result<FileInfo, FsError> {
+        self.fs.stat(self.path.clone()).await
+    }
+
+    /// Reads some bytes into `buffer`, and returns how many were read
+    ///
+    /// This can read fewer bytes than fit in `buffer` even before the end of the file, 0 is only returned at the end of the file
+    /// or if `buffer` is empty.
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let len = buffer.len().min(READ_CHUNK_SIZE as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let data = self.fs.read(self.handle, self.position, len as u32).await?;
+        // don't trust the server to return at most what was asked for
+        let read_size = data.len().min(len);
+
+        buffer[..read_size].copy_from_slice(&data[..read_size]);
+        self.position += read_size as u64;
+
+        Ok(read_size)
+    }
+
+    /// Reads until the end of the file, appending the bytes to `buffer` and returning how many were read
+    pub async fn read_to_end(&mut self, buffer: &mut Vec<u8>) -> Result<usize, FsError> {
+        let start_len = buffer.len();
+
+        // a short read does not mean the end of the file was reached, only an empty one does
+        loop {
+            let chunk = self.fs.read(self.handle, self.position, READ_CHUNK_SIZE).await?;
+            if chunk.is_empty() {
+                return Ok(buffer.len() - start_len);
+            }
+
+            buffer.extend_from_slice(&chunk);
+            self.position += chunk.len() as u64;
+        }
+    }
+
+    /// Writes some of `data`, and returns how many bytes were written
+    pub async fn write(&mut self, data: &[u8]) -> Result<usize, FsError> {
+        let data = &data[..data.len().min(WRITE_CHUNK_SIZE)];
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let written = self.fs.write(self.handle, self.position, data.to_vec()).await?;
+        let written = (written as usize).min(data.len());
+        self.position += written as u64;
+
+        Ok(written)
+    }
+
+    /// Writes all of `data`
+    pub async fn write_all(&mut self, mut data: &[u8]) -> Result<(), FsError> {
+        while !data.is_empty() {
+            match self.write(data).await? {
+                // the file can't grow any more
+                0 => return Err(FsError::FileTooLarge),
+                written => data = &data[written..],
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Closes the file and waits for the fs server to close it
+    pub async fn close(self) {
+        let fs = self.fs;
+        let handle = self.handle;
+        // dropping would close the file again
+        core::mem::forget(self);
+
+        fs.close(handle).await;
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let fs = self.fs;
+        let handle = self.handle;
+
+        // closing needs to wait for the fs server, so it is done in its own task
+        asynca::spawn(async move {
+            fs.close(handle).await;
+        });
+    }
+}
+
+/// Converts an error for the [`io`](crate::io) traits, which use [`SysErr`]
+fn fs_error_to_sys_err(error: FsError) -> SysErr {
+    match error {
+        FsError::InvalidPath | FsError::InvalidFlags | FsError::InvalidSeek => SysErr::InvlArgs,
+        FsError::NotFound | FsError::InvalidHandle => SysErr::InvlId,
+        FsError::IsDirectory | FsError::NotDirectory | FsError::NoFsServer => SysErr::InvlOp,
+        FsError::AccessDenied => SysErr::InvlPerm,
+        FsError::FileTooLarge => SysErr::Overflow,
+    }
 }
 
-pub struct Link {
-    name: String,
-    target: String,
+impl AsyncRead for File {
+    async fn async_read(&mut self, buffer: &mut [u8]) -> KResult<usize> {
+        self.read(buffer).await.map_err(fs_error_to_sys_err)
+    }
+}
+
+impl AsyncWrite for File {
+    async fn async_write(&mut self, data: &[u8]) -> KResult<usize> {
+        self.write(data).await.map_err(fs_error_to_sys_err)
+    }
+
+    /// Writes are sent to the fs server right away, so there is nothing to flush
+    async fn async_flush(&mut self) -> KResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_removes_dot_and_empty_components() {
+        assert_eq!(normalize_path("/a/./b/.").unwrap(), "/a/b");
+        assert_eq!(normalize_path("//a///b//").unwrap(), "/a/b");
+        assert_eq!(normalize_path("/").unwrap(), "/");
+        assert_eq!(normalize_path("///").unwrap(), "/");
+    }
+
+    #[test]
+    fn normalize_dot_dot_removes_previous_component() {
+        assert_eq!(normalize_path("/a/b/../c").unwrap(), "/a/c");
+        assert_eq!(normalize_path("/a/b/c/../..").unwrap(), "/a");
+        assert_eq!(normalize_path("/a/./../b").unwrap(), "/b");
+    }
+
+    #[test]
+    fn normalize_dot_dot_cannot_escape_root() {
+        assert_eq!(normalize_path("/..").unwrap(), "/");
+        assert_eq!(normalize_path("/../../a").unwrap(), "/a");
+        assert_eq!(normalize_path("/a/../../../b/..").unwrap(), "/");
+    }
+
+    #[test]
+    fn normalize_rejects_invalid_paths() {
+        assert_eq!(normalize_path(""), Err(FsError::InvalidPath));
+        assert_eq!(normalize_path("a/b"), Err(FsError::InvalidPath));
+        assert_eq!(normalize_path("../a"), Err(FsError::InvalidPath));
+        assert_eq!(normalize_path("/a/b\0c"), Err(FsError::InvalidPath));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,156 @@
+use alloc::vec;
+
+use aurora_core::prelude::*;
+use sys::{KResult, SysErr};
+
+use super::AsyncRead;
+
+/// Capacity of a [`BufReader`] made with [`BufReader::new`]
+pub const DEFAULT_BUF_READER_CAPACITY: usize = 4096;
+
+/// Reads from a stream in large pieces and keeps what is left over, so many small reads don't each go to the stream
+///
+/// This also lets the stream be read a line at a time with [`read_line`](Self::read_line) and [`lines`](Self::lines).
+pub struct BufReader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    /// Index in `buffer` of the next byte to return
+    start: usize,
+    /// Index in `buffer` after the last byte read from the stream
+    end: usize,
+}
+
+impl<R: AsyncRead> BufReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_capacity(DEFAULT_BUF_READER_CAPACITY, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: R) -> Self {
+        BufReader {
+            inner,
+            buffer: vec![0; capacity],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from the stream directly skips the bytes which are already buffered
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Returns the stream, any buffered bytes are lost
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Returns the bytes which have been read from the stream but not returned yet
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer[self.start..self.end]
+    }
+
+    /// Returns the buffered bytes, reading more from the stream first if there are none
+    ///
+    /// The returned slice is only empty at the end of the stream. The bytes stay buffered until they are [consumed](Self::consume).
+    pub async fn fill_buf(&mut self) -> KResult<&[u8]> {
+        if self.start == self.end {
+            self.end = self.inner.async_read(&mut self.buffer).await?;
+            self.start = 0;
+        }
+
+        Ok(self.buffer())
+    }
+
+    /// Marks `amount` buffered bytes as returned, so they are not returned by later reads
+    pub fn consume(&mut self, amount: usize) {
+        self.start = (self.start + amount).min(self.end);
+    }
+
+    /// Reads until a newline or the end of the stream, and appends the bytes to `line`
+    ///
+    /// The newline is included in `line`. Returns the number of bytes read, which is 0 only at the end of the stream.
+    /// Fails with [`SysErr::InvlArgs`] if the line is not valid utf-8, in which case nothing is appended.
+    pub async fn read_line(&mut self, line: &mut String) -> KResult<usize> {
+        let mut line_bytes = Vec::new();
+
+        loop {
+            let available = self.fill_buf().await?;
+            if available.is_empty() {
+                break;
+            }
+
+            match available.iter().position(|byte| *byte == b'\n') {
+                Some(newline_index) => {
+                    line_bytes.extend_from_slice(&available[..=newline_index]);
+                    self.consume(newline_index + 1);
+                    break;
+                },
+                None => {
+                    let read_size = available.len();
+                    line_bytes.extend_from_slice(available);
+                    self.consume(read_size);
+                },
+            }
+        }
+
+        let read_size = line_bytes.len();
+        line.push_str(&String::from_utf8(line_bytes).or(Err(SysErr::InvlArgs))?);
+
+        Ok(read_size)
+    }
+
+    /// Returns the lines of the stream, without their line endings
+    pub fn lines(self) -> Lines<R> {
+        Lines {
+            reader: self,
+        }
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for BufReader<R> {
+    async fn async_read(&mut self, buffer: &mut [u8]) -> KResult<usize> {
+        // nothing is gained by copying a large read through the buffer
+        if self.start == self.end && buffer.len() >= self.buffer.len() {
+            return self.inner.async_read(buffer).await;
+        }
+
+        let available = self.fill_buf().await?;
+        let read_size = available.len().min(buffer.len());
+        buffer[..read_size].copy_from_slice(&available[..read_size]);
+        self.consume(read_size);
+
+        Ok(read_size)
+    }
+}
+
+/// The lines of a stream, returned by [`BufReader::lines`]
+pub struct Lines<R> {
+    reader: BufReader<R>,
+}
+
+impl<R: AsyncRead> Lines<R> {
+    /// Returns the next line with its `\n` or `\r\n` removed, or None at the end of the stream
+    pub async fn next_line(&mut self) -> KResult<Option<String>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+
+        Ok(Some(line))
+    }
+
+    pub fn into_inner(self) -> BufReader<R> {
+        self.reader
+    }
+}
\ No newline at end of file
@@ -0,0 +1,112 @@
+use core::cmp::min;
+
+use serde::{Serialize, Deserialize};
+use aurora_core::prelude::*;
+use aurora_core::collections::MessageVec;
+use sys::{Channel, ChannelSendFlags, KResult};
+
+use super::{Read, Write};
+
+/// [`ChannelWriter`] sends at most this many bytes in one message, and [`ChannelReader`] can recieve messages up to this size
+pub const CHANNEL_MESSAGE_MAX_SIZE: usize = 4096;
+
+/// Writes to a channel, each write is sent as one message
+///
+/// Writes block until the message is recieved, there is no flow control other than that.
+/// Use a [`Pipe`](super::Pipe) for large amounts of data.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelWriter {
+    channel: Channel,
+}
+
+impl ChannelWriter {
+    pub fn new(channel: Channel) -> Self {
+        ChannelWriter {
+            channel,
+        }
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> KResult<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let message = MessageVec::try_from_slice(&data[..min(data.len(), CHANNEL_MESSAGE_MAX_SIZE)])?;
+        // panic safety: the message is not empty, so it has a message buffer
+        self.channel.sync_send(&message.message_buffer().unwrap(), None, ChannelSendFlags::empty())?;
+
+        Ok(message.len())
+    }
+
+    fn flush(&mut self) -> KResult<()> {
+        Ok(())
+    }
+}
+
+/// Reads messages from a channel as a stream of bytes
+///
+/// A message which does not fit in the buffer passed to [`read`](Read::read) is returned over several reads.
+/// Messages bigger than [`CHANNEL_MESSAGE_MAX_SIZE`] are cut off. The stream never ends,
+/// so [`read_to_end`](Read::read_to_end) will block forever.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChannelReader {
+    channel: Channel,
+    /// Part of the last message which has not been read yet
+    #[serde(skip)]
+    pending: Vec<u8>,
+    /// How much of `pending` has been read
+    #[serde(skip)]
+    pending_offset: usize,
+}
+
+impl ChannelReader {
+    pub fn new(channel: Channel) -> Self {
+        ChannelReader {
+            channel,
+            pending: Vec::new(),
+            pending_offset: 0,
+        }
+    }
+
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    fn recv_message(&mut self) -> KResult<()> {
+        let buffer = MessageVec::try_from_slice(&[0u8; CHANNEL_MESSAGE_MAX_SIZE])?;
+        // panic safety: the buffer is not empty, so it has a message buffer
+        let recieve_result = self.channel.sync_recv(&buffer.message_buffer().unwrap(), None)?;
+        let recieve_size = min(recieve_result.recieve_size.bytes(), CHANNEL_MESSAGE_MAX_SIZE);
+
+        self.pending.clear();
+        self.pending.extend_from_slice(&buffer.as_slice()[..recieve_size]);
+        self.pending_offset = 0;
+
+        Ok(())
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buffer: &mut [u8]) -> KResult<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+
+        while self.pending_offset == self.pending.len() {
+            self.recv_message()?;
+        }
+
+        let pending = &self.pending[self.pending_offset..];
+        let read_size = min(pending.len(), buffer.len());
+        buffer[..read_size].copy_from_slice(&pending[..read_size]);
+        self.pending_offset += read_size;
+
+        Ok(read_size)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,120 @@
+//! Byte streams, standard output, and the [`print`](crate::print) family of macros
+//!
+//! Channels send whole messages, the [`Read`] and [`Write`] traits (and their async versions) instead work with
+//! streams of bytes which can be read and written in pieces of any size.
+//! A [`Pipe`] is a stream between two processes with flow control, and [`ChannelReader`] and [`ChannelWriter`]
+//! turn each write into one channel message for simple cases. A [`BufReader`] buffers any async stream so it can be read by line.
+use core::future::Future;
+
+use aurora_core::prelude::*;
+use sys::{KResult, SysErr};
+
+mod buf_reader;
+pub use buf_reader::{BufReader, Lines, DEFAULT_BUF_READER_CAPACITY};
+mod channel_stream;
+pub use channel_stream::{ChannelReader, ChannelWriter, CHANNEL_MESSAGE_MAX_SIZE};
+mod pipe;
+pub use pipe::{Pipe, PipeReader, PipeWriter, PIPE_BUFFER_SIZE};
+mod stdio;
+pub use stdio::{OutputStream, OutputStreamLock, OUTPUT_BUFFER_SIZE, flush_stdio, _print, _eprint};
+
+/// Size of the chunks [`Read::read_to_end`] reads in
+const READ_TO_END_CHUNK_SIZE: usize = 4096;
+
+/// Reads bytes from a stream, like `std::io::Read`
+pub trait Read {
+    /// Reads some bytes into `buffer`, blocking until at least 1 byte is available
+    ///
+    /// Returns the number of bytes read, which is 0 once the end of the stream is reached or if `buffer` is empty.
+    fn read(&mut self, buffer: &mut [u8]) -> KResult<usize>;
+
+    /// Reads until `buffer` is full, fails with [`SysErr::PeerGone`] if the stream ends first
+    fn read_exact(&mut self, mut buffer: &mut [u8]) -> KResult<()> {
+        while !buffer.is_empty() {
+            match self.read(buffer)? {
+                0 => return Err(SysErr::PeerGone),
+                read_size => buffer = &mut buffer[read_size..],
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads until the end of the stream, appending the bytes to `buffer` and returning how many were read
+    fn read_to_end(&mut self, buffer: &mut Vec<u8>) -> KResult<usize> {
+        let start_len = buffer.len();
+        let mut chunk = [0; READ_TO_END_CHUNK_SIZE];
+
+        loop {
+            match self.read(&mut chunk)? {
+                0 => return Ok(buffer.len() - start_len),
+                read_size => {
+                    buffer.try_reserve(read_size).or(Err(SysErr::OutOfMem))?;
+                    buffer.extend_from_slice(&chunk[..read_size]);
+                },
+            }
+        }
+    }
+}
+
+/// Writes bytes to a stream, like `std::io::Write`
+pub trait Write {
+    /// Writes some of `data`, blocking until at least 1 byte can be written
+    ///
+    /// Returns the number of bytes written. Fails with [`SysErr::PeerGone`] if nothing is reading the stream anymore.
+    fn write(&mut self, data: &[u8]) -> KResult<usize>;
+
+    /// Sends any data which is buffered
+    fn flush(&mut self) -> KResult<()>;
+
+    /// Writes all of `data`
+    fn write_all(&mut self, mut data: &[u8]) -> KResult<()> {
+        while !data.is_empty() {
+            let write_size = self.write(data)?;
+            data = &data[write_size..];
+        }
+
+        Ok(())
+    }
+}
+
+/// Same as [`Read`], but waits for data without blocking the thread
+pub trait AsyncRead {
+    /// Same as [`Read::read`]
+    fn async_read(&mut self, buffer: &mut [u8]) -> impl Future<Output = KResult<usize>>;
+
+    /// Same as [`Read::read_exact`]
+    fn async_read_exact(&mut self, mut buffer: &mut [u8]) -> impl Future<Output = KResult<()>> {
+        async move {
+            while !buffer.is_empty() {
+                match self.async_read(buffer).await? {
+                    0 => return Err(SysErr::PeerGone),
+                    read_size => buffer = &mut buffer[read_size..],
+                }
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Same as [`Write`], but waits for space without blocking the thread
+pub trait AsyncWrite {
+    /// Same as [`Write::write`]
+    fn async_write(&mut self, data: &[u8]) -> impl Future<Output = KResult<usize>>;
+
+    /// Same as [`Write::flush`]
+    fn async_flush(&mut self) -> impl Future<Output = KResult<()>>;
+
+    /// Same as [`Write::write_all`]
+    fn async_write_all(&mut self, mut data: &[u8]) -> impl Future<Output = KResult<()>> {
+        async move {
+            while !data.is_empty() {
+                let write_size = self.async_write(data).await?;
+                data = &data[write_size..];
+            }
+
+            Ok(())
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,568 @@
+//! A byte stream between two processes
+//!
+//! Both ends map the same memory, which holds a ring buffer and counters of how many bytes have been written and consumed.
+//! The counters are the source of truth, messages on the pipe's channel are only used to wake an end which is waiting:
+//! the writer sends how many bytes it produced to a waiting reader, and the reader sends how many bytes it consumed
+//! as credit to a writer waiting for space.
+//!
+//! An end sets its waiting flag before checking the counters a final time and blocking on the channel.
+//! Whoever clears the flag of a waiting end must send it exactly one message, so wakeups are never lost and
+//! a message is only sent when its reciever is about to recieve it.
+//!
+//! Each end holds a [`DropCheck`] which the other end watches from a background thread. Once every copy of the writer
+//! is dropped the reader sees the end of the stream, and once every copy of the reader is dropped writes fail with
+//! [`SysErr::PeerGone`]. The closed flags are kept in the shared memory, so every copy of an end sees them.
+//! An end which is being sent to another process is not watched between the sender dropping its copy and the
+//! reciever deserializing it, so the other end should not be dropped in that window.
+//!
+//! The futures returned by [`AsyncRead::async_read`] and [`AsyncWrite::async_write`] must be polled until they complete
+//! once they start waiting, or the other end can block forever trying to wake them.
+use core::cell::UnsafeCell;
+use core::cmp::min;
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer, de};
+use alloc::sync::Arc;
+
+use aurora_core::prelude::*;
+use aurora_core::allocator::addr_space::{AddrSpaceError, MappedMemory, MapEventPoolArgs, RegionPadding, map_typed};
+use aurora_core::collections::MessageVec;
+use aurora_core::{addr_space, thread, this_context};
+use asynca::async_sys::AsyncChannel;
+use bit_utils::Size;
+use sys::{
+    cap_clone,
+    Channel,
+    ChannelSendFlags,
+    CapFlags,
+    CspaceTarget,
+    DropCheck,
+    DropCheckReciever,
+    EventId,
+    EventParser,
+    EventPool,
+    KResult,
+    Memory,
+    MemoryMappingOptions,
+    MemoryNewFlags,
+    SysErr,
+};
+
+use super::{Read, Write, AsyncRead, AsyncWrite};
+
+/// Number of bytes which can be written to a pipe before the reader has to consume some
+pub const PIPE_BUFFER_SIZE: usize = 0x10000;
+
+/// Size of the event pool each end's watcher thread waits on, it only ever recieves 2 events
+const WATCHER_EVENT_POOL_SIZE: Size = Size::from_pages(1);
+
+/// Event the watcher thread recieves when every copy of the other end is dropped
+const PEER_DROPPED_EVENT: u64 = 0;
+/// Event the watcher thread recieves when the end it is watching for is dropped
+const END_DROPPED_EVENT: u64 = 1;
+
+/// Which end of the pipe is waiting or being woken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PipeSide {
+    Reader,
+    Writer,
+}
+
+impl PipeSide {
+    fn peer(self) -> Self {
+        match self {
+            PipeSide::Reader => PipeSide::Writer,
+            PipeSide::Writer => PipeSide::Reader,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct PipeHeader {
+    /// Total number of bytes the writer has put in the buffer
+    written: AtomicU64,
+    /// Total number of bytes the reader has taken out of the buffer
+    consumed: AtomicU64,
+    reader_waiting: AtomicBool,
+    writer_waiting: AtomicBool,
+    /// Set once every copy of the reader is dropped
+    reader_closed: AtomicBool,
+    /// Set once every copy of the writer is dropped
+    writer_closed: AtomicBool,
+}
+
+/// Layout of the memory shared by both ends, zeroed memory is an empty pipe
+#[repr(C)]
+struct PipeBuffer {
+    header: PipeHeader,
+    data: UnsafeCell<[u8; PIPE_BUFFER_SIZE]>,
+}
+
+// safety: only the writer writes to data, and only to the part which the counters say the reader is not using
+unsafe impl Sync for PipeBuffer {}
+
+/// State shared by a pipe end and the thread watching for the other end to be dropped
+struct PipeShared {
+    buffer: MappedMemory<PipeBuffer>,
+    channel: AsyncChannel,
+    side: PipeSide,
+}
+
+impl PipeShared {
+    fn header(&self) -> &PipeHeader {
+        &self.buffer.header
+    }
+
+    fn waiting_flag(&self, side: PipeSide) -> &AtomicBool {
+        match side {
+            PipeSide::Reader => &self.header().reader_waiting,
+            PipeSide::Writer => &self.header().writer_waiting,
+        }
+    }
+
+    fn closed_flag(&self, side: PipeSide) -> &AtomicBool {
+        match side {
+            PipeSide::Reader => &self.header().reader_closed,
+            PipeSide::Writer => &self.header().writer_closed,
+        }
+    }
+
+    /// Number of bytes in the buffer which have not been read
+    fn available(&self) -> usize {
+        let header = self.header();
+        (header.written.load(Ordering::Acquire) - header.consumed.load(Ordering::Acquire)) as usize
+    }
+
+    fn peer_closed(&self) -> bool {
+        self.closed_flag(self.side.peer()).load(Ordering::SeqCst)
+    }
+
+    /// Returns true if this end does not need to wait
+    fn is_ready(&self) -> bool {
+        if self.peer_closed() {
+            return true;
+        }
+
+        match self.side {
+            PipeSide::Reader => self.available() > 0,
+            PipeSide::Writer => self.available() < PIPE_BUFFER_SIZE,
+        }
+    }
+
+    /// Sets this end's waiting flag, and returns true if a wakeup message must be recieved before continuing
+    fn start_wait(&self) -> bool {
+        let waiting = self.waiting_flag(self.side);
+        waiting.store(true, Ordering::SeqCst);
+
+        if !self.is_ready() {
+            return true;
+        }
+
+        // if the flag was already cleared, whoever cleared it is sending a wakeup which has to be recieved
+        !waiting.swap(false, Ordering::SeqCst)
+    }
+
+    /// Blocks until this end is woken
+    fn wait(&self) -> KResult<()> {
+        if self.start_wait() {
+            let buffer = MessageVec::try_from_slice(&0u64.to_le_bytes())?;
+            // panic safety: the buffer is not empty, so it has a message buffer
+            self.channel.inner().sync_recv(&buffer.message_buffer().unwrap(), None)?;
+        }
+
+        Ok(())
+    }
+
+    /// Same as [`wait`](Self::wait), but does not block the thread
+    async fn wait_async(&self) -> KResult<()> {
+        if self.start_wait() {
+            self.channel.recv().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Wakes `side` if it is waiting, `length` is how many bytes were produced or consumed
+    fn wake(&self, side: PipeSide, length: usize) -> KResult<()> {
+        if self.waiting_flag(side).swap(false, Ordering::SeqCst) {
+            let message = MessageVec::try_from_slice(&(length as u64).to_le_bytes())?;
+            // panic safety: the message is not empty, so it has a message buffer
+            self.channel.inner().sync_send(&message.message_buffer().unwrap(), None, ChannelSendFlags::empty())?;
+        }
+
+        Ok(())
+    }
+
+    /// Marks the other end as closed, and wakes this end if it is waiting
+    fn close_peer(&self) {
+        self.closed_flag(self.side.peer()).store(true, Ordering::SeqCst);
+        let _ = self.wake(self.side, 0);
+    }
+
+    fn ring(&self) -> *mut u8 {
+        self.buffer.data.get().cast()
+    }
+
+    /// Copies `data` into the ring buffer at stream position `position`
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the writer, and the reader must have consumed the bytes which are being overwritten
+    unsafe fn copy_in(&self, position: u64, data: &[u8]) {
+        let start = (position % PIPE_BUFFER_SIZE as u64) as usize;
+        let first_len = min(data.len(), PIPE_BUFFER_SIZE - start);
+
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.ring().add(start), first_len);
+            ptr::copy_nonoverlapping(data[first_len..].as_ptr(), self.ring(), data.len() - first_len);
+        }
+    }
+
+    /// Copies bytes starting at stream position `position` out of the ring buffer
+    ///
+    /// # Safety
+    ///
+    /// Must only be called by the reader, and the writer must have written the bytes which are being read
+    unsafe fn copy_out(&self, position: u64, buffer: &mut [u8]) {
+        let start = (position % PIPE_BUFFER_SIZE as u64) as usize;
+        let first_len = min(buffer.len(), PIPE_BUFFER_SIZE - start);
+
+        unsafe {
+            ptr::copy_nonoverlapping(self.ring().add(start), buffer.as_mut_ptr(), first_len);
+            ptr::copy_nonoverlapping(self.ring(), buffer[first_len..].as_mut_ptr(), buffer.len() - first_len);
+        }
+    }
+}
+
+/// Capabilities an end is sent as
+#[derive(Serialize)]
+struct PipeEndRef<'a> {
+    memory: &'a Memory,
+    channel: &'a Channel,
+    drop_check: &'a DropCheck,
+    peer_drop_reciever: &'a DropCheckReciever,
+}
+
+#[derive(Deserialize)]
+struct PipeEndData {
+    memory: Memory,
+    channel: Channel,
+    drop_check: DropCheck,
+    peer_drop_reciever: DropCheckReciever,
+}
+
+/// Waits on its own event pool for the other end of a pipe to be dropped
+///
+/// The drop listeners are registered when the watcher is created rather than by its thread,
+/// so a drop which happens before the thread starts running is not missed.
+struct PeerWatcher {
+    event_pool: EventPool,
+    /// Address the event pool is mapped at
+    address: usize,
+    /// Triggered when the [`PipeEnd`] being watched for is dropped, so the thread can exit
+    end_drop_reciever: DropCheckReciever,
+}
+
+impl PeerWatcher {
+    /// Returns the watcher and the drop check the end must hold to stop it
+    fn new(peer_drop_reciever: &DropCheckReciever) -> Result<(Self, DropCheck), AddrSpaceError> {
+        let allocator = &this_context().allocator;
+
+        let event_pool = EventPool::new(allocator, WATCHER_EVENT_POOL_SIZE)?;
+        let cloned_event_pool = cap_clone(CspaceTarget::Current, CspaceTarget::Current, &event_pool, CapFlags::all())?;
+
+        let address = addr_space().map_event_pool(MapEventPoolArgs {
+            event_pool: cloned_event_pool,
+            address: None,
+            padding: RegionPadding::default(),
+        })?.address;
+
+        let (end_drop_check, end_drop_reciever) = DropCheck::new(allocator, 0)?;
+        let watcher = PeerWatcher {
+            event_pool,
+            address,
+            end_drop_reciever,
+        };
+
+        peer_drop_reciever.handle_cap_drop_async(&watcher.event_pool, EventId::from_u64(PEER_DROPPED_EVENT), true)?;
+        watcher.end_drop_reciever.handle_cap_drop_async(&watcher.event_pool, EventId::from_u64(END_DROPPED_EVENT), true)?;
+
+        Ok((watcher, end_drop_check))
+    }
+
+    /// Run on the watcher thread, closes the other end of `shared` once it is dropped
+    fn run(self, shared: &PipeShared) {
+        loop {
+            let Ok(event_range) = self.event_pool.await_event(None) else {
+                // the other end can't be watched anymore, but it is not known to be closed
+                dprintln!("warning: pipe watcher failed to wait for events, the other end will not be seen closing");
+                return;
+            };
+
+            // safety: await_event is not called again while the slice is used
+            for event in EventParser::new(unsafe { event_range.as_slice() }) {
+                match event.event_id().as_u64() {
+                    PEER_DROPPED_EVENT => {
+                        shared.close_peer();
+                        return;
+                    },
+                    END_DROPPED_EVENT => return,
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for PeerWatcher {
+    fn drop(&mut self) {
+        // safety: nothing references the event pool mapping, events are only read through await_event
+        let _ = unsafe { addr_space().unmap_memory(self.address) };
+    }
+}
+
+struct PipeEnd {
+    shared: Arc<PipeShared>,
+    /// Kept so the end can be sent to other processes, the mapping has its own copy
+    memory: Memory,
+    /// The other end is told this end was dropped once every copy of this is dropped
+    drop_check: DropCheck,
+    /// Kept so the end can be sent to other processes, the watcher thread has already registered to be told of the drop
+    peer_drop_reciever: DropCheckReciever,
+    /// Stops the watcher thread when this end is dropped
+    _watcher_drop_check: DropCheck,
+}
+
+impl PipeEnd {
+    /// Maps the pipe's memory and starts the thread which watches for the other end to be dropped
+    fn new(data: PipeEndData, side: PipeSide) -> Result<Self, AddrSpaceError> {
+        let buffer = map_typed(&data.memory, 0, MemoryMappingOptions {
+            read: true,
+            write: true,
+            ..Default::default()
+        })?;
+
+        let shared = Arc::new(PipeShared {
+            buffer,
+            channel: data.channel.into(),
+            side,
+        });
+
+        let (watcher, watcher_drop_check) = PeerWatcher::new(&data.peer_drop_reciever)?;
+        let watcher_shared = shared.clone();
+        thread::spawn(move || watcher.run(&watcher_shared));
+
+        Ok(PipeEnd {
+            shared,
+            memory: data.memory,
+            drop_check: data.drop_check,
+            peer_drop_reciever: data.peer_drop_reciever,
+            _watcher_drop_check: watcher_drop_check,
+        })
+    }
+
+    fn end_ref(&self) -> PipeEndRef<'_> {
+        PipeEndRef {
+            memory: &self.memory,
+            channel: self.shared.channel.inner(),
+            drop_check: &self.drop_check,
+            peer_drop_reciever: &self.peer_drop_reciever,
+        }
+    }
+}
+
+/// Creates pipes, see [`Pipe::new`]
+pub struct Pipe;
+
+impl Pipe {
+    /// Creates a pipe, bytes written to the writer are read from the reader
+    ///
+    /// Either end can be sent to another process, for example with [`Command::named_arg`](crate::process::Command::named_arg).
+    /// Each end in this process, including ones recieved from other processes, has a thread which waits for the other end
+    /// to be dropped. The thread exits once the other end is dropped or the end it belongs to is dropped.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> Result<(PipeWriter, PipeReader), AddrSpaceError> {
+        let allocator = &this_context().allocator;
+
+        let memory = Memory::new(allocator, Size::from_bytes(size_of::<PipeBuffer>()), MemoryNewFlags::default())?;
+        let reader_memory = cap_clone(CspaceTarget::Current, CspaceTarget::Current, &memory, CapFlags::all())?;
+
+        let channel = Channel::new(CapFlags::all(), allocator)?;
+        let reader_channel = cap_clone(CspaceTarget::Current, CspaceTarget::Current, &channel, CapFlags::all())?;
+
+        let (writer_drop_check, writer_drop_reciever) = DropCheck::new(allocator, 0)?;
+        let (reader_drop_check, reader_drop_reciever) = DropCheck::new(allocator, 0)?;
+
+        let writer = PipeEnd::new(PipeEndData {
+            memory,
+            channel,
+            drop_check: writer_drop_check,
+            peer_drop_reciever: reader_drop_reciever,
+        }, PipeSide::Writer)?;
+
+        let reader = PipeEnd::new(PipeEndData {
+            memory: reader_memory,
+            channel: reader_channel,
+            drop_check: reader_drop_check,
+            peer_drop_reciever: writer_drop_reciever,
+        }, PipeSide::Reader)?;
+
+        Ok((PipeWriter(writer), PipeReader(reader)))
+    }
+}
+
+/// The end of a [`Pipe`] which is written to
+pub struct PipeWriter(PipeEnd);
+
+impl PipeWriter {
+    /// Writes as much of `data` as fits in the buffer, returns None if nothing fits
+    fn write_available(&self, data: &[u8]) -> Option<KResult<usize>> {
+        let shared = &self.0.shared;
+
+        if shared.peer_closed() {
+            return Some(Err(SysErr::PeerGone));
+        }
+
+        if data.is_empty() {
+            return Some(Ok(0));
+        }
+
+        let space = PIPE_BUFFER_SIZE - shared.available();
+        if space == 0 {
+            return None;
+        }
+
+        let write_size = min(space, data.len());
+        let written = shared.header().written.load(Ordering::Relaxed);
+
+        // safety: this is the writer, and there are `space` bytes the reader has already consumed
+        unsafe {
+            shared.copy_in(written, &data[..write_size]);
+        }
+        shared.header().written.store(written + write_size as u64, Ordering::Release);
+
+        Some(shared.wake(PipeSide::Reader, write_size).map(|_| write_size))
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, data: &[u8]) -> KResult<usize> {
+        loop {
+            if let Some(result) = self.write_available(data) {
+                return result;
+            }
+
+            self.0.shared.wait()?;
+        }
+    }
+
+    /// Writes are never buffered, so this does nothing
+    fn flush(&mut self) -> KResult<()> {
+        Ok(())
+    }
+}
+
+impl AsyncWrite for PipeWriter {
+    async fn async_write(&mut self, data: &[u8]) -> KResult<usize> {
+        loop {
+            if let Some(result) = self.write_available(data) {
+                return result;
+            }
+
+            self.0.shared.wait_async().await?;
+        }
+    }
+
+    async fn async_flush(&mut self) -> KResult<()> {
+        Ok(())
+    }
+}
+
+/// The end of a [`Pipe`] which is read from
+pub struct PipeReader(PipeEnd);
+
+impl PipeReader {
+    /// Reads the bytes which are in the buffer, returns None if there are none and the writer has not been dropped
+    fn read_available(&self, buffer: &mut [u8]) -> Option<KResult<usize>> {
+        let shared = &self.0.shared;
+
+        if buffer.is_empty() {
+            return Some(Ok(0));
+        }
+
+        // checked first, so bytes written before the writer was dropped are still read
+        let peer_closed = shared.peer_closed();
+
+        let available = shared.available();
+        if available == 0 {
+            return peer_closed.then_some(Ok(0));
+        }
+
+        let read_size = min(available, buffer.len());
+        let consumed = shared.header().consumed.load(Ordering::Relaxed);
+
+        // safety: this is the reader, and the writer has written `available` bytes past `consumed`
+        unsafe {
+            shared.copy_out(consumed, &mut buffer[..read_size]);
+        }
+        shared.header().consumed.store(consumed + read_size as u64, Ordering::Release);
+
+        // the writer is gone, so there is nobody to give credit to
+        if peer_closed {
+            return Some(Ok(read_size));
+        }
+
+        Some(shared.wake(PipeSide::Writer, read_size).map(|_| read_size))
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buffer: &mut [u8]) -> KResult<usize> {
+        loop {
+            if let Some(result) = self.read_available(buffer) {
+                return result;
+            }
+
+            self.0.shared.wait()?;
+        }
+    }
+}
+
+impl AsyncRead for PipeReader {
+    async fn async_read(&mut self, buffer: &mut [u8]) -> KResult<usize> {
+        loop {
+            if let Some(result) = self.read_available(buffer) {
+                return result;
+            }
+
+            self.0.shared.wait_async().await?;
+        }
+    }
+}
+
+macro_rules! impl_pipe_end_serde {
+    ($end:ident, $side:expr) => {
+        impl Serialize for $end {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.0.end_ref().serialize(serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $end {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let data = PipeEndData::deserialize(deserializer)?;
+
+                PipeEnd::new(data, $side)
+                    .map($end)
+                    .map_err(de::Error::custom)
+            }
+        }
+    };
+}
+
+impl_pipe_end_serde!(PipeWriter, PipeSide::Writer);
+impl_pipe_end_serde!(PipeReader, PipeSide::Reader);
\ No newline at end of file
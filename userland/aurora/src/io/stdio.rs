@@ -0,0 +1,170 @@
+//! Standard output streams, and the [`print`](crate::print) family of macros which write to them
+//!
+//! A process's stdout and stderr are the [`STDOUT`](crate::env::STDOUT) and [`STDERR`](crate::env::STDERR)
+//! slots of its handle table, set by the parent with [`Command::stdout`](crate::process::Command::stdout) and
+//! [`Command::stderr`](crate::process::Command::stderr). If the slot holds a channel, output is line buffered
+//! and whole lines are sent over the channel as utf-8 bytes with a [`ChannelWriter`].
+//! If the parent did not set the slot, output goes to the kernel debug log like [`dprint`](crate::dprint).
+use core::fmt::{self, Write};
+use core::mem;
+
+use aurora_core::prelude::*;
+use aurora_core::sync::{Mutex, MutexGuard};
+use sys::{Channel, KResult, SysErr};
+
+use super::{ChannelWriter, Write as _, CHANNEL_MESSAGE_MAX_SIZE};
+
+/// Output is sent once this many bytes are buffered, even if no line has finished
+pub const OUTPUT_BUFFER_SIZE: usize = CHANNEL_MESSAGE_MAX_SIZE;
+
+/// Where an [`OutputStream`] sends its output
+#[derive(Debug)]
+enum OutputTarget {
+    Channel(ChannelWriter),
+    Debug,
+}
+
+#[derive(Debug)]
+struct OutputState {
+    target: OutputTarget,
+    /// Output which has not been sent yet because its line has not finished
+    buffer: Vec<u8>,
+}
+
+/// An output stream like stdout or stderr, see the [module docs](self)
+#[derive(Debug)]
+pub struct OutputStream {
+    state: Mutex<OutputState>,
+}
+
+impl OutputStream {
+    fn new(target: OutputTarget) -> Self {
+        OutputStream {
+            state: Mutex::new(OutputState {
+                target,
+                buffer: Vec::new(),
+            }),
+        }
+    }
+
+    /// Makes a stream which sends its output over `channel`
+    pub fn from_channel(channel: Channel) -> Self {
+        Self::new(OutputTarget::Channel(ChannelWriter::new(channel)))
+    }
+
+    /// Makes a stream which prints its output to the kernel debug log
+    pub fn debug() -> Self {
+        Self::new(OutputTarget::Debug)
+    }
+
+    /// Locks the stream, so several writes are sent without output from other threads between them
+    pub fn lock(&self) -> OutputStreamLock<'_> {
+        OutputStreamLock(self.state.lock())
+    }
+
+    /// Writes `data` to the stream, whole lines are sent right away and the rest is buffered
+    pub fn write(&self, data: &[u8]) -> KResult<()> {
+        self.lock().write(data)
+    }
+
+    /// Sends any partial line that is buffered
+    pub fn flush(&self) -> KResult<()> {
+        self.lock().flush()
+    }
+}
+
+/// A locked [`OutputStream`], returned by [`OutputStream::lock`]
+pub struct OutputStreamLock<'a>(MutexGuard<'a, OutputState>);
+
+impl OutputStreamLock<'_> {
+    fn send(&mut self, data: &[u8]) -> KResult<()> {
+        match &mut self.0.target {
+            OutputTarget::Channel(writer) => writer.write_all(data)?,
+            // debug output is already line buffered for each thread
+            OutputTarget::Debug => dprint!("{}", String::from_utf8_lossy(data)),
+        }
+
+        Ok(())
+    }
+
+    /// Writes `data` to the stream, whole lines are sent right away and the rest is buffered
+    pub fn write(&mut self, data: &[u8]) -> KResult<()> {
+        let buffer = &mut self.0.buffer;
+        buffer.try_reserve(data.len()).or(Err(SysErr::OutOfMem))?;
+        buffer.extend_from_slice(data);
+
+        if let Some(newline_index) = buffer.iter().rposition(|byte| *byte == b'\n') {
+            let rest = buffer.split_off(newline_index + 1);
+            let lines = mem::replace(buffer, rest);
+            self.send(&lines)?;
+        }
+
+        if self.0.buffer.len() >= OUTPUT_BUFFER_SIZE {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends any partial line that is buffered
+    pub fn flush(&mut self) -> KResult<()> {
+        if self.0.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let data = mem::take(&mut self.0.buffer);
+        self.send(&data)
+    }
+}
+
+impl Write for OutputStreamLock<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Flushes stdout and stderr, this is called after `main` returns
+pub fn flush_stdio() {
+    let _ = crate::env::stdout().flush();
+    let _ = crate::env::stderr().flush();
+}
+
+fn print_to(stream: &OutputStream, args: fmt::Arguments) {
+    // formatted before locking, so anything printed while formatting does not deadlock
+    let text = alloc::fmt::format(args);
+    let _ = stream.lock().write_str(&text);
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    print_to(crate::env::stdout(), args);
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    print_to(crate::env::stderr(), args);
+}
+
+/// Prints to [`stdout`](crate::env::stdout), output is buffered until a whole line is written
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Prints to [`stderr`](crate::env::stderr), output is buffered until a whole line is written
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_eprint(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
\ No newline at end of file
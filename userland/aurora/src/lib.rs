@@ -6,12 +6,17 @@
 
 extern crate alloc;
 
+pub mod debug_print;
 pub mod env;
 pub mod fs;
+pub mod io;
+pub mod log;
 pub mod prelude;
 pub mod process;
+pub mod registry;
 pub mod service;
+pub mod time;
 
-pub use aurora_core::{thread, allocator, sync, collections};
-pub use aurora_core::{this_context, addr_space};
-pub use sys::{dprint, dprintln};
\ No newline at end of file
+pub use aurora_core::{thread, allocator, backtrace, sync, collections};
+pub use aurora_core::{this_context, addr_space, process_name};
+pub use sys::{dprint, dprintln, dprint_unbuffered, dprintln_unbuffered};
\ No newline at end of file
@@ -0,0 +1,92 @@
+//! Logging through the log server
+//!
+//! Messages are sent to the log endpoint in this process's namespace without waiting for the log server,
+//! if there is no log endpoint they are printed to the kernel debug log instead.
+use core::fmt::{self, Display};
+
+use serde::{Serialize, Deserialize};
+
+use crate::prelude::*;
+use crate::env;
+use crate::io::PipeWriter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Debug => "DEBUG",
+            Self::Info => "INFO",
+            Self::Warn => "WARN",
+            Self::Error => "ERROR",
+        };
+
+        f.pad(name)
+    }
+}
+
+/// Each log endpoint is for one process, lines logged through it are prefixed with that process's name
+#[arpc::service(service_id = 3, name = "Log")]
+pub trait LogServer {
+    /// Logs `message`, this is normally sent with [`Log::try_send_log`] so the caller never waits on the log server
+    #[arpc(oneway)]
+    fn log(&self, level: LogLevel, target: String, message: String);
+
+    /// Creates a log endpoint for a new process called `process_name`
+    fn new_process_log(&self, process_name: String) -> Option<Log>;
+}
+
+/// Changes log server settings which apply to every process
+///
+/// Unlike log endpoints which every process is given, only early-init holds this endpoint.
+#[arpc::service(service_id = 4, name = "LogAdmin")]
+pub trait LogAdminServer {
+    /// Sets the lowest level which is printed for messages from `target`
+    fn set_level(&self, target: String, level: LogLevel);
+
+    /// Writes every line printed after this is called to `output` as well as the kernel debug log
+    ///
+    /// The output is removed once writing to it fails, such as when its reader is dropped.
+    fn add_output(&self, output: PipeWriter);
+}
+
+/// Sends `message` to this process's log endpoint without falling back to the kernel debug log
+///
+/// Returns false if there is no log endpoint or the message could not be sent
+pub fn send_to_log_server(level: LogLevel, target: &str, message: String) -> bool {
+    env::try_log()
+        .is_some_and(|log| log.try_send_log(level, target.to_owned(), message).is_ok())
+}
+
+#[doc(hidden)]
+pub fn _log(level: LogLevel, target: &str, args: fmt::Arguments) {
+    if !send_to_log_server(level, target, alloc::fmt::format(args)) {
+        dprintln!("[{level}] {target}: {args}");
+    }
+}
+
+pub macro log($level:expr, $($arg:tt)*) {
+    $crate::log::_log($level, core::module_path!(), core::format_args!($($arg)*))
+}
+
+pub macro debug($($arg:tt)*) {
+    $crate::log::log!($crate::log::LogLevel::Debug, $($arg)*)
+}
+
+pub macro info($($arg:tt)*) {
+    $crate::log::log!($crate::log::LogLevel::Info, $($arg)*)
+}
+
+pub macro warn($($arg:tt)*) {
+    $crate::log::log!($crate::log::LogLevel::Warn, $($arg)*)
+}
+
+pub macro error($($arg:tt)*) {
+    $crate::log::log!($crate::log::LogLevel::Error, $($arg)*)
+}
\ No newline at end of file
@@ -1 +1,2 @@
-pub use aurora_core::prelude::*;
\ No newline at end of file
+pub use aurora_core::prelude::*;
+pub use crate::{print, println, eprint, eprintln};
\ No newline at end of file
@@ -1,10 +1,291 @@
+use core::cell::RefCell;
+use core::fmt::{self, Display};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
 use serde::Serialize;
-use aser::{Value, to_bytes_count_cap};
-pub use aurora_core::process::{Child, ProcessError, exit};
-use aurora_core::process::spawn_process;
+use aser::{Value, to_bytes, to_bytes_count_cap};
+pub use aurora_core::process::{ExitStatus, ProcessError, PANIC_EXIT_CODE, exit, exit_with_code};
+use aurora_core::process::{spawn_process, StraceOptions};
 use aurora_core::prelude::*;
+use aurora_core::collections::{HashMap, MessageVec};
+use aurora_core::sync::Mutex;
+use arpc::ServiceHandle;
+use asynca::async_sys::AsyncChannel;
+use bit_utils::Size;
+use sys::{
+    cap_clone, Channel, ChannelSendFlags, CapFlags, CspaceTarget, ExitReason, FaultRecord, KResult, Memory,
+    MemoryMappingOptions, MemoryNewFlags, SysErr,
+};
+
+use crate::allocator::addr_space::{AddrSpaceError, MapMemoryArgs, MapMemoryResult};
+use crate::env::{self, NamespaceRef, Args};
+use crate::log::{Log, error, warn};
+use crate::registry::Registry;
+use crate::{addr_space, this_context};
+use crate::time::{self, Instant};
+
+/// How long exit handlers can run for before the process is exited without waiting for them
+pub const EXIT_HANDLER_BUDGET: Duration = Duration::from_secs(2);
+
+/// Number of bytes of a faulting child's stack which are included when a [`FaultSnapshot`] is displayed
+pub const FAULT_STACK_DUMP_SIZE: usize = 256;
+
+static EXIT_HANDLERS: Mutex<Vec<Box<dyn FnOnce() + Send>>> = Mutex::new(Vec::new());
+
+/// Set once a thread starts running exit handlers
+static EXITING: AtomicBool = AtomicBool::new(false);
+
+aurora_core::thread_local! {
+    /// Services running on this thread which are stopped when a shutdown request is recieved
+    static SHUTDOWN_SERVICES: RefCell<Vec<ServiceHandle>> = RefCell::new(Vec::new());
+}
+
+/// Registers `handler` to be run when the process exits with [`exit_gracefully`]
+///
+/// Handlers are run on the thread which calls [`exit_gracefully`], newest first.
+/// They are not run if the process exits any other way, like [`exit`] or a panic.
+pub fn on_exit(handler: impl FnOnce() + Send + 'static) {
+    EXIT_HANDLERS.lock().push(Box::new(handler));
+}
+
+/// Runs every handler registered with [`on_exit`], then terminates the process with `code`
+///
+/// If the handlers take longer than [`EXIT_HANDLER_BUDGET`] the process exits without waiting for the rest of them.
+/// If another thread is already exiting gracefully, this blocks until that thread terminates the process.
+/// Log messages are sent to the log server as soon as they are logged, so handlers can log and nothing needs to be flushed.
+pub fn exit_gracefully(code: i32) -> ! {
+    if EXITING.swap(true, Ordering::AcqRel) {
+        loop {
+            sys::Thread::suspend();
+        }
+    }
+
+    let deadline = Instant::now() + EXIT_HANDLER_BUDGET;
+    aurora_core::thread::spawn(move || {
+        time::sleep_until(deadline);
+        exit_with_code(code);
+    });
+
+    // the lock is not held while a handler runs, so handlers can register more handlers
+    loop {
+        let handler = EXIT_HANDLERS.lock().pop();
+        match handler {
+            Some(handler) => handler(),
+            None => break,
+        }
+    }
 
-use crate::env::{Namespace, Args};
+    exit_with_code(code)
+}
+
+/// Gracefully shuts down the service with `handle` when a shutdown request is recieved, before exit handlers are run
+///
+/// This lets the service respond to the calls it is handling before the process exits.
+/// The service must be running on the same thread that called [`spawn_shutdown_listener`].
+pub fn shutdown_service_on_exit(handle: ServiceHandle) {
+    SHUTDOWN_SERVICES.with(|services| services.borrow_mut().push(handle));
+}
+
+/// Spawns an async task which calls [`exit_gracefully`] with an exit code of 0 once the parent requests a shutdown
+///
+/// Services registered with [`shutdown_service_on_exit`] are shut down first.
+/// This does nothing if the parent did not spawn this process with [`Command::shutdown_channel`].
+pub fn spawn_shutdown_listener() {
+    let Some(shutdown_channel) = env::try_shutdown_channel() else {
+        return;
+    };
+
+    asynca::spawn_named("shutdown listener", async move {
+        if let Err(error) = shutdown_channel.recv().await {
+            warn!("failed to recieve shutdown request: {error}");
+            return;
+        }
+
+        let services = SHUTDOWN_SERVICES.with(|services| services.take());
+        for service in services {
+            service.shutdown().await;
+        }
+
+        exit_gracefully(0);
+    });
+}
+
+/// Registers and stack of a child's thread which was killed by a fault, returned by [`Child::fault_snapshot`]
+#[derive(Debug, Clone, Copy)]
+pub struct FaultSnapshot<'a> {
+    pub record: FaultRecord,
+    /// Stack of the faulting thread starting at its `rsp`, this is empty if the stack could not be read
+    pub stack: &'a [u8],
+}
+
+impl Display for FaultSnapshot<'_> {
+    /// Writes the registers, and a hexdump of the first [`FAULT_STACK_DUMP_SIZE`] bytes of the stack
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let record = &self.record;
+
+        match record.kind() {
+            Some(kind) => write!(f, "{}", ExitStatus::from(ExitReason::Fault(kind)))?,
+            None => write!(f, "unknown fault")?,
+        }
+        writeln!(f, " at rip {:#x} accessing {:#x} (error code {:#x})", record.rip, record.fault_address, record.error_code)?;
+
+        let registers = [
+            ("rax", record.rax), ("rbx", record.rbx), ("rcx", record.rcx), ("rdx", record.rdx),
+            ("rbp", record.rbp), ("rsp", record.rsp), ("rdi", record.rdi), ("rsi", record.rsi),
+            ("r8", record.r8), ("r9", record.r9), ("r10", record.r10), ("r11", record.r11),
+            ("r12", record.r12), ("r13", record.r13), ("r14", record.r14), ("r15", record.r15),
+        ];
+        for row in registers.chunks(4) {
+            for (name, value) in row {
+                write!(f, "{name:>3}: {value:016x}  ")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "rflags: {:#x}", record.rflags)?;
+
+        write!(f, "stack ({} bytes captured):", self.stack.len())?;
+        let dump_size = self.stack.len().min(FAULT_STACK_DUMP_SIZE);
+        for (i, line) in self.stack[..dump_size].chunks(16).enumerate() {
+            write!(f, "\n{:016x}:", record.rsp + i * 16)?;
+            for byte in line {
+                write!(f, " {byte:02x}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Local read only mapping of the memory the kernel copies a faulting child's stack to
+struct FaultCaptureMapping {
+    address: usize,
+    size: usize,
+}
+
+impl FaultCaptureMapping {
+    /// Creates the capture memory and maps it, the returned memory is what should be given to the child's thread group
+    fn new(size: Size) -> Result<(Memory, Self), AddrSpaceError> {
+        let memory = Memory::new(&this_context().allocator, size, MemoryNewFlags::empty())?;
+        let mapped_memory = cap_clone(CspaceTarget::Current, CspaceTarget::Current, &memory, CapFlags::all())?;
+
+        let MapMemoryResult { address, size, .. } = addr_space().map_memory(MapMemoryArgs {
+            memory: Some(mapped_memory),
+            options: MemoryMappingOptions {
+                read: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })?;
+
+        Ok((memory, FaultCaptureMapping {
+            address,
+            size: size.bytes(),
+        }))
+    }
+
+    /// The first `len` bytes of the captured stack, or less if the mapping is smaller
+    fn stack(&self, len: usize) -> &[u8] {
+        // safety: the mapping is alive as long as self, and the child can no longer write to it once it has faulted
+        unsafe { core::slice::from_raw_parts(self.address as *const u8, len.min(self.size)) }
+    }
+}
+
+impl Drop for FaultCaptureMapping {
+    fn drop(&mut self) {
+        unsafe {
+            addr_space().unmap_memory(self.address)
+                .expect("failed to unmap fault capture memory");
+        }
+    }
+}
+
+/// A process spawned by [`Command`]
+pub struct Child {
+    inner: aurora_core::process::Child,
+    name: String,
+    shutdown_channel: Option<AsyncChannel>,
+    fault_capture: Option<FaultCaptureMapping>,
+    /// Set once the fault snapshot has been logged, so waiting again doesn't log it twice
+    fault_logged: AtomicBool,
+}
+
+impl Child {
+    /// Waits for the child process to exit, and returns how it exited
+    ///
+    /// This returns once the main thread of the child has exited, which is normally when the whole process exits
+    /// If the child was spawned with [`Command::fault_capture`] and it faulted, its [`FaultSnapshot`] is logged as an error.
+    pub fn wait(&self) -> KResult<ExitStatus> {
+        let status = self.inner.wait()?;
+        self.log_fault(status);
+
+        Ok(status)
+    }
+
+    /// Same as [`wait`](Self::wait), but fails with [`SysErr::OkTimeout`] if the child has not exited by `deadline`
+    pub fn wait_until(&self, deadline: Instant) -> KResult<ExitStatus> {
+        let status = self.inner.wait_until(deadline.as_boot_time().as_nanos() as u64)?;
+        self.log_fault(status);
+
+        Ok(status)
+    }
+
+    /// Returns the registers of the first thread in the child which was killed by a fault, or None if no thread has faulted
+    ///
+    /// The stack is only captured if the child was spawned with [`Command::fault_capture`], otherwise it is empty.
+    pub fn fault_snapshot(&self) -> KResult<Option<FaultSnapshot<'_>>> {
+        let Some(record) = self.inner.fault_info()? else {
+            return Ok(None);
+        };
+
+        let stack = match &self.fault_capture {
+            Some(mapping) => mapping.stack(record.stack_size),
+            None => &[],
+        };
+
+        Ok(Some(FaultSnapshot {
+            record,
+            stack,
+        }))
+    }
+
+    fn log_fault(&self, status: ExitStatus) {
+        if self.fault_capture.is_none() || !matches!(status.reason(), ExitReason::Fault(_)) {
+            return;
+        }
+
+        if self.fault_logged.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        match self.fault_snapshot() {
+            Ok(Some(snapshot)) => error!("process {} faulted: {snapshot}", self.name),
+            Ok(None) => error!("process {} faulted, but no fault was recorded", self.name),
+            Err(error) => warn!("failed to get fault snapshot of process {}: {error}", self.name),
+        }
+    }
+
+    /// Kills the child without running its exit handlers, its main thread reports an exit code of `exit_code`
+    pub fn kill(&self, exit_code: i32) -> KResult<()> {
+        self.inner.kill(exit_code)
+    }
+
+    /// Asks the child to exit gracefully, it must have been spawned with [`Command::shutdown_channel`]
+    ///
+    /// This only delivers the request, use [`wait`](Self::wait) to wait for the child to exit.
+    /// Fails with [`SysErr::OkUnreach`] if the child is not listening for shutdown requests (see [`spawn_shutdown_listener`]),
+    /// or with [`SysErr::InvlOp`] if the child has no shutdown channel.
+    pub fn request_shutdown(&self) -> KResult<()> {
+        let shutdown_channel = self.shutdown_channel.as_ref().ok_or(SysErr::InvlOp)?;
+
+        // the contents of the message are not used, any message is a shutdown request
+        let request = MessageVec::try_from_slice(&[0u8])?;
+        // panic safety: the request is not empty, so it has a message buffer
+        shutdown_channel.try_send(&request.message_buffer().unwrap(), ChannelSendFlags::empty())?;
+
+        Ok(())
+    }
+}
 
 /// Where the elf data to launch the process is comming from
 enum ProcessDataSource {
@@ -20,11 +301,22 @@ impl ProcessDataSource {
 }
 
 /// Used to execute other processess
-/// 
+///
 /// Functions similarly to the standard library's Command
 pub struct Command {
     process_data: ProcessDataSource,
     args: Args,
+    handles: Vec<Option<Value>>,
+    env: HashMap<String, String>,
+    registry: Option<Registry>,
+    log: Option<Log>,
+    name: Option<String>,
+    /// Mask of syscalls which are traced, or None if strace is disabled
+    strace_mask: Option<u128>,
+    memory_limit: Option<Size>,
+    shutdown_channel: bool,
+    /// Size of the memory a faulting thread's stack is copied to, or None if faults are not captured
+    fault_capture_size: Option<Size>,
 }
 
 impl Command {
@@ -32,6 +324,15 @@ impl Command {
         Command {
             process_data: ProcessDataSource::Bytes(bytes),
             args: Args::default(),
+            handles: Vec::new(),
+            env: env::try_vars().cloned().unwrap_or_default(),
+            registry: None,
+            log: None,
+            name: None,
+            strace_mask: None,
+            memory_limit: None,
+            shutdown_channel: false,
+            fault_capture_size: None,
         }
     }
 
@@ -59,16 +360,183 @@ impl Command {
         self
     }
 
+    /// Puts `handle` in slot `index` of the process's handle table, see [`env::handle`]
+    pub fn handle<T: Serialize>(&mut self, index: usize, handle: &T) -> &mut Self {
+        let handle_value = Value::from_serialize(handle)
+            .expect("failed to serialize process handle");
+
+        if self.handles.len() <= index {
+            self.handles.resize(index + 1, None);
+        }
+        self.handles[index] = Some(handle_value);
+
+        self
+    }
+
+    /// Gives the process a copy of this process's handle table, including its standard streams
+    ///
+    /// The capabilities in the table are cloned into the process when it is spawned, so this process can keep using them.
+    /// This replaces any handles which were already set, so set other handles after calling this.
+    pub fn inherit_handles(&mut self) -> &mut Self {
+        self.handles = env::try_handles()
+            .map(<[_]>::to_vec)
+            .unwrap_or_default();
+
+        self
+    }
+
+    /// Sets the channel the process reads standard input from
+    pub fn stdin(&mut self, channel: &Channel) -> &mut Self {
+        self.handle(env::STDIN, channel)
+    }
+
+    /// Sets the channel the process sends standard output on, each message holds one or more whole lines
+    ///
+    /// If this is not called, standard output of the process is printed to the kernel debug log
+    pub fn stdout(&mut self, channel: &Channel) -> &mut Self {
+        self.handle(env::STDOUT, channel)
+    }
+
+    /// Sets the channel the process sends standard error on, each message holds one or more whole lines
+    ///
+    /// If this is not called, standard error of the process is printed to the kernel debug log
+    pub fn stderr(&mut self, channel: &Channel) -> &mut Self {
+        self.handle(env::STDERR, channel)
+    }
+
+    /// Sets the environment variable `key` to `value` in the process
+    ///
+    /// The process inherits this process's environment variables unless they are overridden or removed
+    pub fn env(&mut self, key: String, value: String) -> &mut Self {
+        self.env.insert(key, value);
+        self
+    }
+
+    /// Removes the environment variable `key` from the process
+    pub fn env_remove(&mut self, key: &str) -> &mut Self {
+        self.env.remove(key);
+        self
+    }
+
+    /// Sets the service registry given to the process
+    ///
+    /// If this is not called, the process is given the same registry as this process
+    pub fn registry(&mut self, registry: Registry) -> &mut Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Sets the log endpoint given to the process
+    ///
+    /// If this is not called, the process shares this process's log endpoint, so its messages are attributed to this process
+    pub fn log(&mut self, log: Log) -> &mut Self {
+        self.log = Some(log);
+        self
+    }
+
+    /// Sets the name of the process, which is printed before each line of its debug output and with its syscalls when strace is enabled
+    pub fn name(&mut self, name: String) -> &mut Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// If `enabled` the kernel prints every syscall the process makes to the debug log
+    ///
+    /// This is off by default
+    pub fn strace(&mut self, enabled: bool) -> &mut Self {
+        self.strace_mask = enabled.then_some(u128::MAX);
+        self
+    }
+
+    /// Traces only the syscalls in `syscall_mask`, where bit n is set if syscall number n is traced
+    ///
+    /// Use [`syscall_mask`](sys::syscall_nums::syscall_mask) to make the mask,
+    /// for example to trace only the channel messages of a process.
+    pub fn strace_syscalls(&mut self, syscall_mask: u128) -> &mut Self {
+        self.strace_mask = Some(syscall_mask);
+        self
+    }
+
+    /// Limits how much memory the process can allocate
+    ///
+    /// This includes the memory used for the process's executable, stack, and any kernel objects it creates.
+    /// Allocations past the limit fail with [`SysErr::QuotaExceeded`](sys::SysErr::QuotaExceeded).
+    /// By default the process shares this process's allocator, and has no limit of its own.
+    pub fn memory_limit(&mut self, limit: Size) -> &mut Self {
+        self.memory_limit = Some(limit);
+        self
+    }
+
+    /// If `enabled` the process is given a channel which [`Child::request_shutdown`] sends shutdown requests on
+    ///
+    /// The process must call [`spawn_shutdown_listener`] to act on the requests. This is off by default.
+    pub fn shutdown_channel(&mut self, enabled: bool) -> &mut Self {
+        self.shutdown_channel = enabled;
+        self
+    }
+
+    /// Captures the registers and up to `stack_size` bytes of the stack of a thread in the process which faults
+    ///
+    /// The kernel caps how much of the stack is copied. The capture is logged when the parent waits on the faulted child,
+    /// and can be read with [`Child::fault_snapshot`]. This is off by default.
+    pub fn fault_capture(&mut self, stack_size: Size) -> &mut Self {
+        self.fault_capture_size = Some(stack_size);
+        self
+    }
+
     pub fn spawn(&mut self) -> Result<Child, ProcessError> {
-        let namespace = Namespace {
-            // it is fine for only data to be cloned,
-            // spawn_process will transfer necessary capabilities
-            args: self.args.clone_data(),
+        let shutdown_channel = if self.shutdown_channel {
+            Some(AsyncChannel::from(Channel::new(CapFlags::all(), &this_context().allocator)?))
+        } else {
+            None
+        };
+
+        // spawn_process will transfer the capabilities referenced in the namespace
+        let namespace = NamespaceRef {
+            args: &self.args,
+            handles: &self.handles,
+            registry: self.registry.as_ref().or_else(|| env::try_registry()),
+            log: self.log.as_ref().or_else(|| env::try_log()),
+            shutdown_channel: shutdown_channel.as_ref(),
         };
 
         let exe_data = self.process_data.bytes();
         let mut namespace_data: Vec<u8> = to_bytes_count_cap(&namespace)?;
+        let env_data: Vec<u8> = if self.env.is_empty() {
+            Vec::new()
+        } else {
+            to_bytes(&self.env, 0)?
+        };
+
+        let process_name = self.name.as_deref().unwrap_or("unnamed");
+        let strace = self.strace_mask.map(|syscall_mask| StraceOptions {
+            syscall_mask,
+        });
+
+        let (fault_capture_memory, fault_capture) = match self.fault_capture_size {
+            Some(size) => {
+                let (memory, mapping) = FaultCaptureMapping::new(size)?;
+                (Some(memory), Some(mapping))
+            },
+            None => (None, None),
+        };
+
+        let child = spawn_process(
+            exe_data,
+            process_name,
+            &mut namespace_data,
+            &env_data,
+            strace,
+            self.memory_limit,
+            fault_capture_memory.as_ref(),
+        )?;
 
-        spawn_process(exe_data, &mut namespace_data)
+        Ok(Child {
+            inner: child,
+            name: process_name.into(),
+            shutdown_channel,
+            fault_capture,
+            fault_logged: AtomicBool::new(false),
+        })
     }
 }
\ No newline at end of file
@@ -0,0 +1,27 @@
+use serde::{Serialize, Deserialize};
+use thiserror_no_std::Error;
+use arpc::ClientRpcEndpoint;
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Error)]
+pub enum RegistryError {
+    #[error("A service is already registered with the given name")]
+    NameTaken,
+    #[error("Service names must not be empty")]
+    EmptyName,
+    #[error("The registry server ran out of memory")]
+    OutOfMemory,
+}
+
+/// Name service used by programs to find services which were not passed to them when they were spawned
+///
+/// Services are removed once their server endpoint is dropped
+#[arpc::service(service_id = 2, name = "Registry")]
+pub trait RegistryServer {
+    /// Registers `endpoint` under `name`, fails if a running service already has that name
+    fn register(&self, name: String, endpoint: ClientRpcEndpoint) -> Result<(), RegistryError>;
+
+    /// Gets an endpoint for the service registered under `name`
+    fn lookup(&self, name: String) -> Option<ClientRpcEndpoint>;
+}
\ No newline at end of file
@@ -13,7 +13,9 @@ pub trait AppService {
     /// Creates a new sesssion with the given permissions
     /// 
     /// Permissions are anded to create the new session
-    fn new_session_permissions(&self, permissions: Vec<Key>) -> Service;
+    /// 
+    /// Returns None if this service does not support making new sessions
+    fn new_session_permissions(&self, permissions: Vec<Key>) -> Option<Service>;
 }
 
 #[derive(Serialize, Deserialize)]
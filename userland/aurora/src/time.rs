@@ -0,0 +1,193 @@
+//! Time measurement, modeled after `std::time`
+
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::time::Duration;
+
+use thiserror_no_std::Error;
+use sys::{ClockId, KResult, time_get};
+
+/// A measurement of the monotonic clock, useful for measuring elapsed time
+/// 
+/// Instants never go backwards, even when compared between threads running on different cpus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    /// Returns the current time
+    pub fn now() -> Self {
+        Instant(time_get(ClockId::Monotonic).expect("failed to read monotonic clock"))
+    }
+
+    /// Returns the time since boot, this is the value timeouts passed to the kernel are compared against
+    pub fn as_boot_time(&self) -> Duration {
+        self.0
+    }
+
+    /// Returns the amount of time elapsed from `earlier` to this instant, or zero if `earlier` is later than this instant
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+
+    /// Returns the amount of time elapsed from `earlier` to this instant, or None if `earlier` is later than this instant
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0)
+    }
+
+    /// Returns the amount of time elapsed from `earlier` to this instant, or zero if `earlier` is later than this instant
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    /// Returns the amount of time elapsed since this instant was created
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration).map(Instant)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, other: Duration) -> Instant {
+        self.checked_add(other).expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, other: Duration) {
+        *self = *self + other;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, other: Duration) -> Instant {
+        self.checked_sub(other).expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, other: Duration) {
+        *self = *self - other;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    /// Returns the amount of time elapsed from `other` to this instant, or zero if `other` is later than this instant
+    fn sub(self, other: Instant) -> Duration {
+        self.duration_since(other)
+    }
+}
+
+/// Blocks the calling thread until `deadline` has passed
+pub fn sleep_until(deadline: Instant) {
+    // the thread can be resumed before the timeout, so check the time again after waking up
+    while Instant::now() < deadline {
+        sys::Thread::suspend_until(deadline.as_boot_time().as_nanos() as u64);
+    }
+}
+
+/// Blocks the calling thread for at least `duration`
+pub fn sleep(duration: Duration) {
+    sleep_until(Instant::now() + duration);
+}
+
+/// Returned by [`SystemTime::duration_since`] when the given time is later than the time it was called on
+/// 
+/// Contains how much later the other time was
+#[derive(Debug, Clone, Error)]
+#[error("second time provided was later than self")]
+pub struct SystemTimeError(Duration);
+
+impl SystemTimeError {
+    /// How much later the second time was than the first
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+}
+
+/// A measurement of the realtime clock
+/// 
+/// Unlike [`Instant`], this can go backwards and is not suitable for measuring elapsed time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime(Duration);
+
+/// 1970-01-01 00:00:00 UTC
+pub const UNIX_EPOCH: SystemTime = SystemTime(Duration::ZERO);
+
+impl SystemTime {
+    pub const UNIX_EPOCH: SystemTime = UNIX_EPOCH;
+
+    /// Returns the current time
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if the kernel could not read the real time clock, use [`SystemTime::try_now`] to handle this
+    pub fn now() -> Self {
+        Self::try_now().expect("realtime clock is unavailable")
+    }
+
+    /// Returns the current time, or an error if the kernel could not read the real time clock
+    pub fn try_now() -> KResult<Self> {
+        Ok(SystemTime(time_get(ClockId::Realtime)?))
+    }
+
+    /// Returns the amount of time elapsed from `earlier` to this time
+    pub fn duration_since(&self, earlier: SystemTime) -> Result<Duration, SystemTimeError> {
+        match self.0.checked_sub(earlier.0) {
+            Some(duration) => Ok(duration),
+            None => Err(SystemTimeError(earlier.0 - self.0)),
+        }
+    }
+
+    /// Returns the amount of time elapsed since this time
+    pub fn elapsed(&self) -> Result<Duration, SystemTimeError> {
+        SystemTime::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<SystemTime> {
+        self.0.checked_add(duration).map(SystemTime)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<SystemTime> {
+        self.0.checked_sub(duration).map(SystemTime)
+    }
+}
+
+impl Add<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn add(self, other: Duration) -> SystemTime {
+        self.checked_add(other).expect("overflow when adding duration to system time")
+    }
+}
+
+impl AddAssign<Duration> for SystemTime {
+    fn add_assign(&mut self, other: Duration) {
+        *self = *self + other;
+    }
+}
+
+impl Sub<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn sub(self, other: Duration) -> SystemTime {
+        self.checked_sub(other).expect("overflow when subtracting duration from system time")
+    }
+}
+
+impl SubAssign<Duration> for SystemTime {
+    fn sub_assign(&mut self, other: Duration) {
+        *self = *self - other;
+    }
+}
\ No newline at end of file
@@ -1,9 +1,9 @@
 use core::sync::atomic::{AtomicU64, Ordering, fence};
-use core::{ptr::NonNull, ptr, ops::Deref, mem::size_of};
+use core::{ptr::NonNull, ptr, ops::{Deref, DerefMut}, mem::{size_of, align_of}, marker::PhantomData};
 
 use rand_core::{RngCore, SeedableRng};
 use rand_chacha::ChaCha20Rng;
-use sys::{AddressSpace, MemoryNewFlags, PhysMem};
+use sys::{AddressSpace, MemoryNewFlags, PhysMem, Capability, KResult, UpdateMappingArgs, UpdateVal, MappingKind};
 use sys::Allocator;
 use sys::CspaceTarget;
 use sys::EventPool;
@@ -40,6 +40,11 @@ pub enum AddrSpaceError {
     ZeroSizeMapping,
     #[error("Specified map address causes overlap with another memory region")]
     MappingOverlap,
+    #[error("Region at address {new:#x} overlaps existing region at address {existing:#x}")]
+    OverlappingRegion {
+        existing: usize,
+        new: usize,
+    },
     #[error("Operation involving padding size, address, or mapping size caused overflow")]
     Overflow,
     #[error("There is no available region in the address space where the mapping will fit")]
@@ -48,6 +53,17 @@ pub enum AddrSpaceError {
     SizeMismatch,
     #[error("No mapping at address {0} exists")]
     InvalidAddress(usize),
+    #[error("Typed mapping of {type_size} bytes at offset {offset} does not fit in memory of size {memory_size:?}")]
+    TypedMappingOutOfBounds {
+        offset: usize,
+        type_size: usize,
+        memory_size: Size,
+    },
+    #[error("Typed mapping at offset {offset} is not aligned to {align} bytes")]
+    TypedMappingMisaligned {
+        offset: usize,
+        align: usize,
+    },
     #[error("Syscall error when mapping memory: {0:?}")]
     MemorySyscallError(#[from] SysErr),
 }
@@ -56,6 +72,11 @@ pub enum AddrSpaceError {
 pub struct RegionPadding {
     pub start: Size,
     pub end: Size,
+    /// If true, the start padding is also reserved as a guard region in the kernel
+    /// 
+    /// Faults in the guard region cause the faulting thread to exit with a stack overflow,
+    /// rather than it looking like any other bad memory access
+    pub start_guard: bool,
 }
 
 #[derive(Debug)]
@@ -118,10 +139,15 @@ impl MappedRegion {
     }
 }
 
+/// Default number of bits of randomness used when picking the address of a mapping
+/// 
+/// With 4 KiB pages this lets mappings be placed anywhere in the lower half of the address space
+pub const DEFAULT_ASLR_ENTROPY_BITS: u32 = 35;
+
 /// Maximum possible size of region list in pages
 const REGION_LIST_MAX_SIZE: Size = Size::from_pages(4096);
 
-pub trait MappedRegionStorage: Deref<Target = [MappedRegion]> {
+pub trait MappedRegionStorage: DerefMut<Target = [MappedRegion]> {
     fn len(&self) -> usize;
     
     fn insert(&mut self, index: usize, region: MappedRegion) -> Result<(), AddrSpaceError>;
@@ -152,7 +178,7 @@ impl MemoryCapStorage {
         let mut memory = Memory::new(
             &this_context().allocator,
             Size::from_pages(1),
-            MemoryNewFlags::empty(),
+            MemoryNewFlags::default(),
         ).or(Err(AddrSpaceError::RegionListOom))?;
 
         this_context().address_space
@@ -220,6 +246,14 @@ impl Deref for MemoryCapStorage {
     }
 }
 
+impl DerefMut for MemoryCapStorage {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe {
+            core::slice::from_raw_parts_mut(self.data.as_ptr(), self.len)
+        }
+    }
+}
+
 impl MappedRegionStorage for MemoryCapStorage {
     fn len(&self) -> usize {
         self.len
@@ -295,6 +329,8 @@ pub struct AddrSpaceManager<'a, T: MappedRegionStorage> {
     /// This needs to be stored here for lifetimes to work
     end_region: MappedRegion,
     aslr_rng: ChaCha20Rng,
+    /// Maximum number of bits of randomness used when picking the address of a mapping
+    aslr_entropy_bits: u32,
     /// Allocator used to allocate memory
     allocator: &'a Allocator,
     /// Address space where memory is mapped
@@ -322,6 +358,7 @@ impl LocalAddrSpaceManager {
                 padding: RegionPadding::default(),
             },
             aslr_rng,
+            aslr_entropy_bits: DEFAULT_ASLR_ENTROPY_BITS,
             allocator: &this_context().allocator,
             address_space: &this_context().address_space,
             transient_region_count: AtomicU64::new(0),
@@ -345,6 +382,7 @@ impl<'a> RemoteAddrSpaceManager<'a> {
                 padding: RegionPadding::default(),
             },
             aslr_rng: ChaCha20Rng::from_seed(aslr_seed),
+            aslr_entropy_bits: DEFAULT_ASLR_ENTROPY_BITS,
             allocator,
             address_space,
             transient_region_count: AtomicU64::new(0),
@@ -367,13 +405,50 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
     /// 
     /// Returns the index of there the region was inserted
     /// 
-    /// # Panics
-    /// 
-    /// panics if the regions start address is the same as another regions address
+    /// # Errors
     /// 
-    /// this does not check for any other type of overlap though, this is assumed to be already checked
+    /// Returns [`OverlappingRegion`](AddrSpaceError::OverlappingRegion) if the region or its padding
+    /// intersects any existing region or its padding
     pub(crate) fn insert_region(&mut self, region: MappedRegion) -> Result<usize, AddrSpaceError> {
-        let index = self.binary_search_address(region.address).unwrap_err();
+        let region_start = region.address.checked_sub(region.padding.start.bytes_aligned());
+        let region_end: Option<usize> = try {
+            region.address
+                .checked_add(region.size.bytes_aligned())?
+                .checked_add(region.padding.end.bytes_aligned())?
+        };
+        let (Some(region_start), Some(region_end)) = (region_start, region_end) else {
+            return Err(AddrSpaceError::Overflow);
+        };
+
+        let overlap_error = |existing: &MappedRegion| AddrSpaceError::OverlappingRegion {
+            existing: existing.address,
+            new: region.address,
+        };
+
+        let index = match self.binary_search_address(region.address) {
+            Ok(index) => return Err(overlap_error(&self.memory_regions[index])),
+            Err(index) => index,
+        };
+
+        // regions are sorted and do not overlap, so only the neighbors can intersect the new region
+        if index > 0 {
+            let prev_region = &self.memory_regions[index - 1];
+            let prev_end = prev_region.address
+                + prev_region.size.bytes_aligned()
+                + prev_region.padding.end.bytes_aligned();
+
+            if prev_end > region_start {
+                return Err(overlap_error(prev_region));
+            }
+        }
+
+        if let Some(next_region) = self.memory_regions.get(index) {
+            let next_start = next_region.address - next_region.padding.start.bytes_aligned();
+
+            if region_end > next_start {
+                return Err(overlap_error(next_region));
+            }
+        }
 
         self.memory_regions.insert(index, region)?;
 
@@ -439,9 +514,18 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
         }
     }
 
+    /// Sets the maximum number of bits of randomness used when picking the address of a mapping
+    /// 
+    /// Fewer bits spread the possible addresses further apart, and 0 bits disables aslr,
+    /// so mappings are always placed at the lowest available address
+    pub fn set_aslr_entropy_bits(&mut self, entropy_bits: u32) {
+        self.aslr_entropy_bits = entropy_bits;
+    }
+
     /// Finds a suitable address for the given mapping to fit
     /// 
-    /// This uses random number generator to do aslr
+    /// This uses random number generator to do aslr, only free positions are ever picked,
+    /// so the chosen address never collides with an existing region
     // TODO: align map address to make use of huge page mappings
     fn find_map_address(&mut self, size: Size, padding: RegionPadding) -> Result<usize, AddrSpaceError> {
         let region_size: Option<usize> = try {
@@ -464,9 +548,15 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
             return Err(AddrSpaceError::NoAvailableRegion);
         }
 
+        // only every `stride` positions are considered, so there are at most 2^aslr_entropy_bits choices,
+        // but they still span the whole address space
+        let max_choices = 1usize.checked_shl(self.aslr_entropy_bits).unwrap_or(usize::MAX);
+        let stride = available_map_positions.div_ceil(max_choices);
+        let choices = (available_map_positions - 1) / stride + 1;
+
         // this will technically lead to a higher chance of memory being mapped
         // lower in the address space, but probably not a big deal
-        let mut map_position = (self.aslr_rng.next_u64() as usize) % available_map_positions;
+        let mut map_position = ((self.aslr_rng.next_u64() as usize) % choices) * stride;
 
         // do a second pass to find out which address was actually selected
         for (address, size) in self.iter_free_regions() {
@@ -497,6 +587,79 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
         // Im not sure this is totally necessary, but good to have
         fence(Ordering::Acquire);
     }
+
+    /// Maps the guard region in the kernel for a region at `address` with the given padding, if the padding has a start guard
+    fn map_start_guard(&self, address: usize, padding: RegionPadding) -> Result<(), AddrSpaceError> {
+        if padding.start_guard && !padding.start.is_zero() {
+            let guard_size = padding.start.as_aligned();
+            self.address_space.map_guard(address - guard_size.bytes(), guard_size)?;
+        }
+
+        Ok(())
+    }
+
+    /// Unmaps the guard region previously mapped by [`map_start_guard`](Self::map_start_guard)
+    fn unmap_start_guard(&self, address: usize, padding: RegionPadding) {
+        if padding.start_guard && !padding.start.is_zero() {
+            self.address_space.unmap(address - padding.start.bytes_aligned())
+                .expect("failed to unmap previously mapped guard region");
+        }
+    }
+
+    /// Checks that this address space manager's view of the address space matches the kernel's
+    /// 
+    /// Every region with a mapping target must be mapped by the kernel at the same address with the same size and kind,
+    /// and every start guard must be mapped as a kernel guard region.
+    /// Kernel mappings this manager does not know about, such as the region list itself, are not checked.
+    /// 
+    /// # Panics
+    /// 
+    /// Panics describing the first difference which is found
+    pub fn verify_against_kernel(&self) -> Result<(), AddrSpaceError> {
+        let kernel_mappings = self.address_space.mappings(None)?;
+
+        let find_mapping = |address: usize| {
+            kernel_mappings.binary_search_by_key(&address, |mapping| mapping.address)
+                .ok()
+                .map(|index| &kernel_mappings[index])
+        };
+
+        for region in self.memory_regions.iter() {
+            let expected_kind = match region.map_target {
+                MappingTarget::Memory(_) => Some(MappingKind::Memory),
+                MappingTarget::EventPool(_) => Some(MappingKind::EventPool),
+                MappingTarget::PhysMem(_) => Some(MappingKind::PhysMem),
+                MappingTarget::Empty => None,
+            };
+
+            if let Some(expected_kind) = expected_kind {
+                let mapping = find_mapping(region.address)
+                    .unwrap_or_else(|| panic!("region at {:#x} is not mapped by the kernel", region.address));
+
+                assert_eq!(
+                    mapping.kind(), Some(expected_kind),
+                    "region at {:#x} has a different mapping kind in the kernel", region.address,
+                );
+                assert_eq!(
+                    mapping.size, region.size.bytes_aligned(),
+                    "region at {:#x} has a different size in the kernel", region.address,
+                );
+            }
+
+            if region.padding.start_guard && !region.padding.start.is_zero() {
+                let guard_address = region.address - region.padding.start.bytes_aligned();
+                let guard = find_mapping(guard_address)
+                    .unwrap_or_else(|| panic!("start guard of region at {:#x} is not mapped by the kernel", region.address));
+
+                assert_eq!(
+                    guard.kind(), Some(MappingKind::Guard),
+                    "start guard of region at {:#x} is not a guard region in the kernel", region.address,
+                );
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// Arguments for mapping memory in the address apce manager
@@ -519,6 +682,10 @@ pub struct MapMemoryArgs {
     pub size: Option<Size>,
     /// Padding that will be reserved before and 
     pub padding: RegionPadding,
+    /// Flags used to create memory for an ananamous mapping, ignored if `memory` is Some
+    /// 
+    /// Defaults to lazily allocated zeroed memory
+    pub memory_flags: MemoryNewFlags,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -575,7 +742,7 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
                     let memory = Memory::new(
                         self.allocator,
                         size,
-                        MemoryNewFlags::empty(),
+                        args.memory_flags,
                     ).or(Err(AddrSpaceError::AnanamousMappingOom))?;
 
                     (Some(memory), size)
@@ -631,6 +798,17 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
             }
         }
 
+        if let Err(err) = self.map_start_guard(address, padding) {
+            // panic safety: region was added earlier
+            let region = self.remove_region(address).unwrap();
+            if !region.map_target.is_empty() {
+                self.address_space.unmap(address)
+                    .expect("failed to unmap previously mapped memory");
+            }
+
+            return Err(err);
+        }
+
         Ok(MapMemoryResult {
             address,
             size,
@@ -682,6 +860,12 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
             padding,
         };
 
+        if let Err(error) = self.map_start_guard(address, padding) {
+            // panic safety: this address was just mapped
+            self.address_space.unmap(address).unwrap();
+            return Err(error);
+        }
+
         match self.insert_region(region) {
             Ok(_) => {
                 Ok(MapEventPoolResult {
@@ -690,6 +874,7 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
                 })
             },
             Err(error) => {
+                self.unmap_start_guard(address, padding);
                 // panic safety: this address was just mapped
                 self.address_space.unmap(address).unwrap();
                 Err(error)
@@ -740,6 +925,12 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
             padding,
         };
 
+        if let Err(error) = self.map_start_guard(address, padding) {
+            // panic safety: this address was just mapped
+            self.address_space.unmap(address).unwrap();
+            return Err(error);
+        }
+
         match self.insert_region(region) {
             Ok(_) => {
                 Ok(MapPhysMemResult {
@@ -748,6 +939,7 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
                 })
             },
             Err(error) => {
+                self.unmap_start_guard(address, padding);
                 // panic safety: this address was just mapped
                 self.address_space.unmap(address).unwrap();
                 Err(error)
@@ -755,15 +947,78 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
         }
     }
 
+    /// Resizes the memory mapped at `address` in place, and resizes the mapping to cover the whole memory
+    /// 
+    /// `flags` are the flags used for any newly added pages, `IN_PLACE` and `GROW_MAPPING` are always set
+    /// 
+    /// Fails with [`AddrSpaceError::MappingOverlap`] if growing the mapping would overlap the next region
+    pub fn resize_memory(&mut self, address: usize, new_size: Size, flags: MemoryResizeFlags) -> Result<Size, AddrSpaceError> {
+        self.await_transient_region_unmap();
+
+        let new_size = new_size.as_aligned();
+        if new_size.is_zero() {
+            return Err(AddrSpaceError::ZeroSizeMapping);
+        }
+
+        let index = self.binary_search_address(address)
+            .or(Err(AddrSpaceError::InvalidAddress(address)))?;
+
+        let end_address: Option<usize> = try {
+            address.checked_add(new_size.bytes())?
+                .checked_add(self.memory_regions[index].padding.end.bytes_aligned())?
+        };
+        let end_address = end_address.ok_or(AddrSpaceError::Overflow)?;
+
+        let next_region_start = self.memory_regions.get(index + 1)
+            .map(MappedRegion::start_address)
+            .unwrap_or(MAX_MAP_ADDR);
+
+        if end_address > next_region_start {
+            return Err(AddrSpaceError::MappingOverlap);
+        }
+
+        let region = &mut self.memory_regions[index];
+        let MappingTarget::Memory(memory) = &mut region.map_target else {
+            return Err(AddrSpaceError::InvalidAddress(address));
+        };
+
+        let new_pages = memory.resize(new_size, flags | MemoryResizeFlags::IN_PLACE | MemoryResizeFlags::GROW_MAPPING)?;
+        region.size = Size::from_pages(new_pages);
+
+        Ok(region.size)
+    }
+
+    /// Gets the size of the mapping at `address`, not including padding
+    pub fn get_mapping_size(&self, address: usize) -> Result<Size, AddrSpaceError> {
+        Ok(self.get_region(address)?.size)
+    }
+
     /// Gets the mapping target currently in use by the given mapping
     pub fn get_mapping_target(&self, address: usize) -> Result<&MappingTarget, AddrSpaceError> {
         Ok(&self.get_region(address)?.map_target)
     }
 
+    /// Changes the mapping options of the memory or physical memory mapped at `address`
+    pub fn protect(&mut self, address: usize, options: MemoryMappingOptions) -> Result<(), AddrSpaceError> {
+        match self.get_region(address)?.map_target {
+            MappingTarget::Memory(_) | MappingTarget::PhysMem(_) => (),
+            _ => return Err(AddrSpaceError::InvalidAddress(address)),
+        }
+
+        self.address_space.update_memory_mapping(address, UpdateMappingArgs {
+            flags: UpdateVal::Change(options.into()),
+            ..Default::default()
+        })?;
+
+        Ok(())
+    }
+
     /// Unmaps the given memory and drops the memory capability
     pub unsafe fn unmap_memory(&mut self, address: usize) -> Result<(), AddrSpaceError> {
         let region = self.remove_region(address)?;
 
+        self.unmap_start_guard(address, region.padding);
+
         if !region.map_target.is_empty() {
             self.address_space.unmap(address)
                 .expect("failed to unmap previously mapped memory");
@@ -772,12 +1027,108 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
         Ok(())
     }
 
+    /// Unmaps `size` bytes starting at `address` from the memory region containing them
+    /// 
+    /// If the range covers the whole region this is the same as [`unmap_memory`](Self::unmap_memory).
+    /// If it covers the start or end of the region the region is shrunk, and if it is in the middle
+    /// the region is split in 2, with the second region holding a clone of the memory capability.
+    /// Unmapping the start of a region also removes its start padding and guard.
+    /// 
+    /// # Safety
+    /// 
+    /// Nothing may be using the unmapped part of the memory
+    pub unsafe fn unmap_range(&mut self, address: usize, size: Size) -> Result<(), AddrSpaceError> {
+        let size = size.as_aligned();
+        if size.is_zero() {
+            return Err(AddrSpaceError::ZeroSizeMapping);
+        }
+        let end_address = address.checked_add(size.bytes()).ok_or(AddrSpaceError::Overflow)?;
+
+        // the region containing address is the last one starting at or before it
+        let index = match self.binary_search_address(address) {
+            Ok(index) => index,
+            Err(index) => index.checked_sub(1).ok_or(AddrSpaceError::InvalidAddress(address))?,
+        };
+
+        let region = &self.memory_regions[index];
+        let region_address = region.address;
+        let region_end = region.address + region.size.bytes_aligned();
+
+        let MappingTarget::Memory(memory) = &region.map_target else {
+            return Err(AddrSpaceError::InvalidAddress(address));
+        };
+        if address < region_address || end_address > region_end {
+            return Err(AddrSpaceError::InvalidAddress(address));
+        }
+
+        if address == region_address && end_address == region_end {
+            return unsafe { self.unmap_memory(address) };
+        }
+
+        if address == region_address {
+            self.address_space.unmap_range(address, size)?;
+
+            let padding = self.memory_regions[index].padding;
+            self.unmap_start_guard(region_address, padding);
+
+            let region = &mut self.memory_regions[index];
+            region.address = end_address;
+            region.size = Size::from_bytes(region_end - end_address);
+            region.padding.start = Size::default();
+            region.padding.start_guard = false;
+        } else if end_address == region_end {
+            self.address_space.unmap_range(address, size)?;
+
+            self.memory_regions[index].size = Size::from_bytes(address - region_address);
+        } else {
+            // the second half of the split region needs its own memory capability
+            let split_memory = cap_clone(CspaceTarget::Current, CspaceTarget::Current, memory, CapFlags::all())?;
+
+            let end_padding = self.memory_regions[index].padding.end;
+            let old_size = self.memory_regions[index].size;
+
+            // shrink the first half first so the second half doesn't overlap it
+            self.memory_regions[index].size = Size::from_bytes(address - region_address);
+            self.memory_regions[index].padding.end = Size::default();
+
+            let insert_result = self.insert_region(MappedRegion {
+                map_target: MappingTarget::Memory(split_memory),
+                address: end_address,
+                size: Size::from_bytes(region_end - end_address),
+                padding: RegionPadding {
+                    end: end_padding,
+                    ..Default::default()
+                },
+            }).and_then(|split_index| {
+                self.address_space.unmap_range(address, size)
+                    .map_err(|error| {
+                        self.memory_regions.remove(split_index);
+                        error.into()
+                    })
+            });
+
+            if let Err(error) = insert_result {
+                let region = &mut self.memory_regions[index];
+                region.size = old_size;
+                region.padding.end = end_padding;
+
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
     /// Unmaps the memory transiently
     /// 
     /// Returns Some(pointer to transient counter) if memory needs to be unmappd, or None if this mapping was only a reservation
+    /// 
+    /// Any guard region is unmapped immediately, since nothing can be using it
     pub fn unmap_transient(&mut self, address: usize) -> Result<Option<*const AtomicU64>, AddrSpaceError> {
         let region = self.remove_region(address)?;
 
+        self.unmap_start_guard(address, region.padding);
+
         if !region.map_target.is_empty() {
             // ordering relaxed is ok because we are not synchronising any data here
             self.transient_region_count.fetch_add(1, Ordering::Relaxed);
@@ -796,7 +1147,8 @@ impl<T: MappedRegionStorage> AddrSpaceManager<'_, T> {
             address: Some(0),
             padding: RegionPadding {
                 start: Size::default(),
-                end: Size::from_pages(1)
+                end: Size::from_pages(1),
+                start_guard: false,
             },
             ..Default::default()
         })?;
@@ -871,4 +1223,327 @@ impl RemoteAddrSpaceManager<'_> {
     }
 }
 
-unsafe impl<T: MappedRegionStorage> Send for AddrSpaceManager<'_, T> {}
\ No newline at end of file
+unsafe impl<T: MappedRegionStorage> Send for AddrSpaceManager<'_, T> {}
+
+/// A capability which can be mapped by [`map_typed`] and [`map_slice`]
+pub trait Mappable: Capability + Sized {
+    /// Gets the size of the capability's memory
+    fn mapping_size(&mut self) -> KResult<Size>;
+
+    /// Maps the capability at an address chosen by the address space manager, and returns the address
+    fn map_into(self, addr_space: &mut LocalAddrSpaceManager, options: MemoryMappingOptions) -> Result<usize, AddrSpaceError>;
+}
+
+impl Mappable for Memory {
+    fn mapping_size(&mut self) -> KResult<Size> {
+        self.size()
+    }
+
+    fn map_into(self, addr_space: &mut LocalAddrSpaceManager, options: MemoryMappingOptions) -> Result<usize, AddrSpaceError> {
+        Ok(addr_space.map_memory(MapMemoryArgs {
+            memory: Some(self),
+            options,
+            ..Default::default()
+        })?.address)
+    }
+}
+
+impl Mappable for PhysMem {
+    fn mapping_size(&mut self) -> KResult<Size> {
+        self.size()
+    }
+
+    fn map_into(self, addr_space: &mut LocalAddrSpaceManager, options: MemoryMappingOptions) -> Result<usize, AddrSpaceError> {
+        Ok(addr_space.map_phys_mem(MapPhysMemArgs {
+            phys_mem: self,
+            options,
+            address: None,
+            padding: RegionPadding::default(),
+        })?.address)
+    }
+}
+
+/// Maps a copy of `target` into the current address space
+/// 
+/// Returns the address of the mapping and the address where the data at `offset` is mapped
+fn map_raw<M: Mappable>(
+    target: &M,
+    offset: usize,
+    data_size: usize,
+    align: usize,
+    options: MemoryMappingOptions,
+) -> Result<(usize, usize), AddrSpaceError> {
+    let mut target = cap_clone(CspaceTarget::Current, CspaceTarget::Current, target, CapFlags::all())?;
+    let memory_size = target.mapping_size()?;
+
+    let end_offset = offset.checked_add(data_size).ok_or(AddrSpaceError::Overflow)?;
+    if end_offset > memory_size.bytes() {
+        return Err(AddrSpaceError::TypedMappingOutOfBounds {
+            offset,
+            type_size: data_size,
+            memory_size,
+        });
+    }
+
+    // mappings are always page aligned, so only the offset needs to be aligned
+    if offset % align != 0 {
+        return Err(AddrSpaceError::TypedMappingMisaligned {
+            offset,
+            align,
+        });
+    }
+
+    let address = target.map_into(&mut addr_space(), options)?;
+
+    Ok((address, address + offset))
+}
+
+/// Maps `target` into the current address space, and returns a reference to the `T` at `offset` bytes into the memory
+/// 
+/// The memory is unmapped when the returned [`MappedMemory`] is dropped
+pub fn map_typed<T, M: Mappable>(target: &M, offset: usize, options: MemoryMappingOptions) -> Result<MappedMemory<T>, AddrSpaceError> {
+    let (mapping_address, address) = map_raw(target, offset, size_of::<T>(), align_of::<T>(), options)?;
+
+    Ok(MappedMemory {
+        mapping_address,
+        // panic safety: mapped memory is never at address 0
+        data: NonNull::new(address as *mut T).unwrap(),
+        writable: options.write,
+        _marker: PhantomData,
+    })
+}
+
+/// Maps `target` into the current address space, and returns a slice of `len` `T`s starting `offset` bytes into the memory
+/// 
+/// The memory is unmapped when the returned [`MappedMemory`] is dropped
+pub fn map_slice<T, M: Mappable>(target: &M, offset: usize, len: usize, options: MemoryMappingOptions) -> Result<MappedMemory<[T]>, AddrSpaceError> {
+    let data_size = size_of::<T>().checked_mul(len).ok_or(AddrSpaceError::Overflow)?;
+    let (mapping_address, address) = map_raw(target, offset, data_size, align_of::<T>(), options)?;
+
+    let data = ptr::slice_from_raw_parts_mut(address as *mut T, len);
+
+    Ok(MappedMemory {
+        mapping_address,
+        // panic safety: mapped memory is never at address 0
+        data: NonNull::new(data).unwrap(),
+        writable: options.write,
+        _marker: PhantomData,
+    })
+}
+
+/// Memory mapped in the current address space which is accessed as a `T`, and is unmapped when dropped
+/// 
+/// The mapping holds its own copy of the mapped capability
+pub struct MappedMemory<T: ?Sized> {
+    /// Address of the start of the mapping, which is where the memory is unmapped from
+    mapping_address: usize,
+    data: NonNull<T>,
+    /// If false, the memory was not mapped writable and mutable access will panic
+    writable: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T: ?Sized> MappedMemory<T> {
+    /// Returns the address of the mapped data
+    pub fn address(&self) -> usize {
+        self.data.as_ptr() as *mut u8 as usize
+    }
+
+    /// Returns true if the memory is mapped writable
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Changes the mapping options of the memory
+    pub fn protect(&mut self, options: MemoryMappingOptions) -> Result<(), AddrSpaceError> {
+        addr_space().protect(self.mapping_address, options)?;
+        self.writable = options.write;
+
+        Ok(())
+    }
+
+    /// Returns a pointer to the mapped data, and never unmaps it
+    pub fn leak(self) -> NonNull<T> {
+        let data = self.data;
+        core::mem::forget(self);
+        data
+    }
+}
+
+impl<T: ?Sized> Deref for MappedMemory<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // safety: memory stays mapped as long as self exists
+        unsafe { self.data.as_ref() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MappedMemory<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        assert!(self.writable, "attempted to mutably access memory that is not mapped writable");
+
+        // safety: memory stays mapped as long as self exists, and it is mapped writable
+        unsafe { self.data.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Drop for MappedMemory<T> {
+    fn drop(&mut self) {
+        unsafe {
+            addr_space().unmap_memory(self.mapping_address)
+                .expect("failed to unmap mapped memory");
+        }
+    }
+}
+
+unsafe impl<T: ?Sized + Send> Send for MappedMemory<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for MappedMemory<T> {}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    use sys::{CapId, CapType};
+
+    use super::*;
+
+    /// Sizes in pages of the regions reserved by the layout tests
+    const LAYOUT_SIZES: [usize; 6] = [1, 4, 16, 2, 8, 1];
+
+    /// Makes a manager for a remote address space which doesn't exist
+    /// 
+    /// Only reservations can be made with it, since anything else makes syscalls.
+    /// The capabilities are leaked because dropping them would also make a syscall.
+    fn test_manager(aslr_seed: [u8; 32]) -> RemoteAddrSpaceManager<'static> {
+        let allocator = Allocator::from_cap_id(CapId::new(CapType::Allocator, CapFlags::all(), false, 1)).unwrap();
+        let address_space = AddressSpace::from_cap_id(CapId::new(CapType::AddressSpace, CapFlags::all(), false, 2)).unwrap();
+
+        RemoteAddrSpaceManager::new_remote(aslr_seed, Box::leak(Box::new(allocator)), Box::leak(Box::new(address_space)))
+            .unwrap()
+    }
+
+    /// Reserves `pages` pages at an address picked by the manager, and returns the address
+    fn reserve(manager: &mut RemoteAddrSpaceManager, pages: usize) -> usize {
+        manager.map_memory(MapMemoryArgs {
+            padding: RegionPadding {
+                end: Size::from_pages(pages),
+                ..Default::default()
+            },
+            ..Default::default()
+        }).unwrap().address
+    }
+
+    fn layout(aslr_seed: [u8; 32]) -> Vec<usize> {
+        let mut manager = test_manager(aslr_seed);
+        LAYOUT_SIZES.iter().map(|pages| reserve(&mut manager, *pages)).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_layout() {
+        assert_eq!(layout([7; 32]), layout([7; 32]));
+    }
+
+    #[test]
+    fn different_seeds_give_different_layouts() {
+        let first = layout([7; 32]);
+        let second = layout([8; 32]);
+
+        // with 35 bits of entropy, a matching address is practically impossible
+        for (first_address, second_address) in first.iter().zip(&second) {
+            assert_ne!(first_address, second_address);
+        }
+    }
+
+    #[test]
+    fn zero_entropy_bits_disables_aslr() {
+        let mut manager = test_manager([7; 32]);
+        manager.set_aslr_entropy_bits(0);
+
+        // the null page is reserved, so the lowest free address is the second page
+        assert_eq!(reserve(&mut manager, 2), PAGE_SIZE);
+        assert_eq!(reserve(&mut manager, 1), 3 * PAGE_SIZE);
+    }
+
+    /// Address of the first region inserted by the overlap tests
+    const REGION_A: usize = 0x100000;
+
+    fn region(address: usize, pages: usize, start_padding: usize, end_padding: usize) -> MappedRegion {
+        MappedRegion {
+            map_target: MappingTarget::Empty,
+            address,
+            size: Size::from_pages(pages),
+            padding: RegionPadding {
+                start: Size::from_pages(start_padding),
+                end: Size::from_pages(end_padding),
+                start_guard: false,
+            },
+        }
+    }
+
+    /// Asserts inserting `region` fails because it overlaps the region at `existing`
+    fn assert_overlaps(manager: &mut RemoteAddrSpaceManager, region: MappedRegion, existing: usize) {
+        let new = region.address;
+
+        match manager.insert_region(region) {
+            Err(AddrSpaceError::OverlappingRegion { existing: found_existing, new: found_new }) => {
+                assert_eq!(found_existing, existing);
+                assert_eq!(found_new, new);
+            },
+            result => panic!("expected overlap with region at {existing:#x}, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn insert_region_exact_overlap() {
+        let mut manager = test_manager([0; 32]);
+        manager.insert_region(region(REGION_A, 4, 0, 0)).unwrap();
+
+        assert_overlaps(&mut manager, region(REGION_A, 4, 0, 0), REGION_A);
+    }
+
+    #[test]
+    fn insert_region_partial_overlap() {
+        let mut manager = test_manager([0; 32]);
+        manager.insert_region(region(REGION_A, 4, 0, 0)).unwrap();
+
+        // overlaps the end of the existing region
+        assert_overlaps(&mut manager, region(REGION_A + 2 * PAGE_SIZE, 4, 0, 0), REGION_A);
+        // overlaps the start of the existing region
+        assert_overlaps(&mut manager, region(REGION_A - 2 * PAGE_SIZE, 4, 0, 0), REGION_A);
+    }
+
+    #[test]
+    fn insert_region_padding_only_overlap() {
+        let mut manager = test_manager([0; 32]);
+        manager.insert_region(region(REGION_A, 4, 2, 2)).unwrap();
+
+        // only the new region's start padding overlaps the existing end padding
+        assert_overlaps(&mut manager, region(REGION_A + 7 * PAGE_SIZE, 1, 2, 0), REGION_A);
+        // only the new region's end padding overlaps the existing start padding
+        assert_overlaps(&mut manager, region(REGION_A - 4 * PAGE_SIZE, 1, 0, 2), REGION_A);
+
+        // touching padding is not an overlap
+        manager.insert_region(region(REGION_A + 8 * PAGE_SIZE, 1, 2, 0)).unwrap();
+        manager.insert_region(region(REGION_A - 5 * PAGE_SIZE, 1, 0, 2)).unwrap();
+    }
+
+    #[test]
+    fn insert_region_between_padded_neighbors() {
+        let mut manager = test_manager([0; 32]);
+
+        // the gap between the end padding of A and the start padding of B is 4 pages
+        let region_b = REGION_A + 10 * PAGE_SIZE;
+        manager.insert_region(region(REGION_A, 2, 0, 2)).unwrap();
+        manager.insert_region(region(region_b, 2, 2, 0)).unwrap();
+
+        // start padding reaches into the end padding of A
+        assert_overlaps(&mut manager, region(REGION_A + 5 * PAGE_SIZE, 2, 2, 1), REGION_A);
+        // end padding reaches into the start padding of B
+        assert_overlaps(&mut manager, region(REGION_A + 5 * PAGE_SIZE, 2, 1, 2), region_b);
+
+        // fills the gap exactly
+        manager.insert_region(region(REGION_A + 5 * PAGE_SIZE, 2, 1, 1)).unwrap();
+    }
+}
\ No newline at end of file
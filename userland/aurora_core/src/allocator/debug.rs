@@ -0,0 +1,144 @@
+//! Extra checks for catching memory bugs in the allocator, enabled by the `debug-alloc` feature
+
+use core::ptr::NonNull;
+
+/// Byte written over newly allocated memory
+pub const ALLOC_POISON: u8 = 0xaa;
+/// Byte written over freed memory
+pub const FREE_POISON: u8 = 0xdd;
+
+/// Maximum number of live allocations that can be tracked, must be a power of 2
+const LIVE_SET_CAPACITY: usize = 4096;
+const EMPTY_SLOT: usize = 0;
+
+/// Overwrites all of `allocation` with `byte`
+pub fn poison(allocation: NonNull<[u8]>, byte: u8) {
+    unsafe {
+        core::ptr::write_bytes(allocation.as_mut_ptr(), byte, allocation.len());
+    }
+}
+
+/// An open addressed hash set of the addresses of every live allocation
+/// 
+/// This can't allocate, so it has a fixed capacity.
+/// Removal shifts later entries of the probe run back instead of leaving tombstones,
+/// so a long running process which keeps allocating and freeing never fills the set with dead slots.
+pub struct LiveAllocations {
+    slots: [usize; LIVE_SET_CAPACITY],
+    /// Set once an allocation could not be tracked because the set was full
+    /// 
+    /// After this point, an address missing from the set is no longer a sign of a bad free
+    overflowed: bool,
+}
+
+impl LiveAllocations {
+    pub const fn new() -> Self {
+        LiveAllocations {
+            slots: [EMPTY_SLOT; LIVE_SET_CAPACITY],
+            overflowed: false,
+        }
+    }
+
+    /// Returns the slot `address` is put in if there are no collisions
+    fn home_slot(address: usize) -> usize {
+        address.wrapping_mul(0x9e3779b97f4a7c15) >> (usize::BITS - LIVE_SET_CAPACITY.trailing_zeros())
+    }
+
+    /// Returns the slot indexes to check for `address`, in probe order
+    fn probe_sequence(address: usize) -> impl Iterator<Item = usize> {
+        let start = Self::home_slot(address);
+
+        (0..LIVE_SET_CAPACITY).map(move |i| (start + i) & (LIVE_SET_CAPACITY - 1))
+    }
+
+    pub fn insert(&mut self, address: usize) {
+        for index in Self::probe_sequence(address) {
+            if self.slots[index] == EMPTY_SLOT {
+                self.slots[index] = address;
+                return;
+            }
+        }
+
+        self.overflowed = true;
+    }
+
+    /// Removes `address` from the set
+    /// 
+    /// Returns false if `address` was not a live allocation, which means a double free or a free of a pointer that was never allocated
+    pub fn remove(&mut self, address: usize) -> bool {
+        for index in Self::probe_sequence(address) {
+            if self.slots[index] == address {
+                self.remove_slot(index);
+                return true;
+            } else if self.slots[index] == EMPTY_SLOT {
+                break;
+            }
+        }
+
+        // if some allocations could not be tracked, this could be one of them
+        self.overflowed
+    }
+
+    /// Empties the slot at `index`, and moves back any later entries in the same probe run which can no longer be found
+    fn remove_slot(&mut self, mut index: usize) {
+        self.slots[index] = EMPTY_SLOT;
+        let mut next = index;
+
+        loop {
+            next = (next + 1) & (LIVE_SET_CAPACITY - 1);
+            let address = self.slots[next];
+            if address == EMPTY_SLOT {
+                return;
+            }
+
+            // the entry stays if its home slot is still reachable from it without crossing the emptied slot
+            let home = Self::home_slot(address);
+            let distance_to_home = next.wrapping_sub(home) & (LIVE_SET_CAPACITY - 1);
+            let distance_to_empty = next.wrapping_sub(index) & (LIVE_SET_CAPACITY - 1);
+            if distance_to_home < distance_to_empty {
+                continue;
+            }
+
+            self.slots[index] = address;
+            self.slots[next] = EMPTY_SLOT;
+            index = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Addresses which are chunk aligned like real allocations, and spread out over the slots
+    fn test_address(i: usize) -> usize {
+        0x1000_0000 + i * 64
+    }
+
+    #[test]
+    fn churn_at_steady_size_leaves_no_dead_slots() {
+        const LIVE_COUNT: usize = LIVE_SET_CAPACITY / 2;
+
+        let mut set = LiveAllocations::new();
+        for i in 0..LIVE_COUNT {
+            set.insert(test_address(i));
+        }
+
+        // each round frees the oldest allocation and makes a new one, so the set always holds the same number of addresses
+        for i in LIVE_COUNT..(LIVE_COUNT + 16 * LIVE_SET_CAPACITY) {
+            assert!(set.remove(test_address(i - LIVE_COUNT)));
+            set.insert(test_address(i));
+        }
+
+        assert!(!set.overflowed);
+        assert_eq!(set.slots.iter().filter(|slot| **slot != EMPTY_SLOT).count(), LIVE_COUNT);
+
+        // freed addresses are no longer found, and every live one still is
+        let end = LIVE_COUNT + 16 * LIVE_SET_CAPACITY;
+        assert!(!set.remove(test_address(0)));
+        for i in (end - LIVE_COUNT)..end {
+            assert!(set.remove(test_address(i)), "live address {} was lost", i);
+        }
+        assert!(set.slots.iter().all(|slot| *slot == EMPTY_SLOT));
+    }
+}
\ No newline at end of file
@@ -1,10 +1,10 @@
 //! Provides memory allocation for userspace and rust alloc crate
-// FIXME: this memory allocator is shit
-// for now it is a copy paste of old kernel allocator, which is bad and splits up heap into lots of zones,
-// which is not good for userspace trying to minimize amount of syscalls made
+//! 
+//! Heap zones are grown in place with memory_resize when they run out of space,
+//! a new heap zone is only created when a zone can't be grown because another mapping is in the way
 
 use core::cell::Cell;
-use core::cmp::max;
+use core::cmp::{max, min};
 use core::ptr::{NonNull, null_mut};
 use core::mem::size_of;
 use core::alloc::Layout;
@@ -12,7 +12,7 @@ use alloc::alloc::GlobalAlloc;
 
 use bit_utils::{PAGE_SIZE, log2_up_const, align_up, align_down, align_of, Size, MemOwner};
 use bit_utils::container::{LinkedList, ListNode, ListNodeData, CursorMut};
-use sys::{MessageBuffer, CapId, Capability};
+use sys::{MessageBuffer, CapId, Capability, MemoryResizeFlags};
 
 use crate::addr_space;
 use crate::allocator::addr_space::MapMemoryResult;
@@ -20,8 +20,13 @@ use addr_space::MapMemoryArgs;
 use crate::sync::Mutex;
 
 pub mod addr_space;
+#[cfg(feature = "debug-alloc")]
+mod debug;
 
+/// Initial size of a heap zone
 const HEAP_ZONE_SIZE: usize = PAGE_SIZE * 8;
+/// Heap zones double in size when they are grown, up to this much at a time
+const MAX_HEAP_ZONE_GROW_SIZE: usize = PAGE_SIZE * 256;
 const CHUNK_SIZE: usize = 1 << log2_up_const(size_of::<Node>());
 // TODO: make not use 1 extra space in some scenarios
 const INITIAL_CHUNK_SIZE: usize = align_up(size_of::<HeapZone>(), CHUNK_SIZE);
@@ -131,22 +136,31 @@ impl HeapZone {
         let memory_cap_id = memory.unwrap().cap_id();
         drop(addr_space);
 
+        unsafe { Some(Self::init_at(address, size.bytes(), memory_cap_id)) }
+    }
+
+    /// Writes an empty heap zone to the `size` bytes at `address`
+    /// 
+    /// # Safety
+    /// 
+    /// The memory must be writable, page aligned, and not used by anything else
+    unsafe fn init_at(address: usize, size: usize, memory_cap_id: CapId) -> MemOwner<Self> {
         let ptr = address as *mut HeapZone;
 
         let mut out = HeapZone {
             list_node_data: ListNodeData::default(),
-            size: size.bytes(),
-            free_space: Cell::new(size.bytes() - INITIAL_CHUNK_SIZE),
+            size,
+            free_space: Cell::new(size - INITIAL_CHUNK_SIZE),
             list: LinkedList::new(),
             memory_cap_id,
         };
 
-        let node = unsafe { Node::new(address + INITIAL_CHUNK_SIZE, size.bytes() - INITIAL_CHUNK_SIZE) };
+        let node = unsafe { Node::new(address + INITIAL_CHUNK_SIZE, size - INITIAL_CHUNK_SIZE) };
         out.list.push(node);
 
         unsafe {
             ptr.write(out);
-            Some(MemOwner::from_raw(ptr))
+            MemOwner::from_raw(ptr)
         }
     }
 
@@ -154,6 +168,41 @@ impl HeapZone {
         self.free_space.get()
     }
 
+    /// Grows this heap zone in place by at least `grow_size` bytes, the added space becomes a new free node
+    /// 
+    /// Returns false if the heap zone could not be grown, which happens if another mapping is in the way
+    unsafe fn grow(&mut self, grow_size: usize) -> bool {
+        let Some(new_size) = self.size.checked_add(grow_size) else {
+            return false;
+        };
+
+        let resize_result = addr_space().resize_memory(
+            self.addr(),
+            Size::from_bytes(new_size),
+            MemoryResizeFlags::LAZY_ALLOC | MemoryResizeFlags::ZEROED,
+        );
+        let Ok(new_size) = resize_result else {
+            return false;
+        };
+
+        let old_end = self.addr() + self.size;
+        let added_size = new_size.bytes() - self.size;
+        self.size = new_size.bytes();
+
+        // panic safety: old end of heap zone is never null
+        let added_space = NonNull::slice_from_raw_parts(
+            NonNull::new(old_end as *mut u8).unwrap(),
+            added_size,
+        );
+
+        // dealloc will merge the added space with the last free node and update free space
+        unsafe {
+            self.dealloc(added_space);
+        }
+
+        true
+    }
+
     fn contains(&self, addr: usize, size: usize) -> bool {
         (addr >= self.addr() + CHUNK_SIZE) && (addr + size <= self.addr() + CHUNK_SIZE + self.size)
     }
@@ -224,9 +273,28 @@ impl HeapZone {
         let addr = allocation.as_mut_ptr() as usize;
         let size = allocation.len();
 
-        let new_node = unsafe { Node::new(addr, size) };
         let mut cursor = self.get_prev_next_node(addr);
 
+        // catch buffer overruns that smashed a free node header, or freeing memory that is already free
+        #[cfg(feature = "debug-alloc")]
+        {
+            if let Some(prev_node) = cursor.prev() && prev_node.addr() + prev_node.size() > addr {
+                panic!(
+                    "freed memory at {:#x} of size {} overlaps free node at {:#x} of size {}",
+                    addr, size, prev_node.addr(), prev_node.size(),
+                );
+            }
+
+            if let Some(next_node) = cursor.next() && addr + size > next_node.addr() {
+                panic!(
+                    "freed memory at {:#x} of size {} overlaps free node at {:#x} of size {}",
+                    addr, size, next_node.addr(), next_node.size(),
+                );
+            }
+        }
+
+        let new_node = unsafe { Node::new(addr, size) };
+
         if let Some(prev_node) = cursor.prev() && prev_node.merge(&new_node) {
             // nodes were merged, do nothing
         } else {
@@ -247,6 +315,60 @@ impl HeapZone {
         self.free_space.set(self.free_space() + size);
     }
 
+    /// Resizes `allocation` to `new_size` without moving it
+    /// 
+    /// `new_size` must already be aligned the same way allocation sizes are.
+    /// Growing consumes the start of the free node directly after the allocation,
+    /// and shrinking releases the tail of the allocation as a free node.
+    /// 
+    /// Returns false if the allocation could not be grown in place
+    // does not check if allocation is in this zone
+    unsafe fn realloc_in_place(&mut self, allocation: NonNull<[u8]>, new_size: usize) -> bool {
+        let addr = allocation.as_mut_ptr() as usize;
+        let old_size = allocation.len();
+        let end_addr = addr + old_size;
+
+        if new_size <= old_size {
+            if new_size < old_size {
+                // panic safety: this address is inside of the allocation, so it is not null
+                let tail = NonNull::slice_from_raw_parts(
+                    NonNull::new((addr + new_size) as *mut u8).unwrap(),
+                    old_size - new_size,
+                );
+
+                unsafe {
+                    self.dealloc(tail);
+                }
+            }
+
+            return true;
+        }
+
+        let grow_size = new_size - old_size;
+
+        let mut cursor = self.get_prev_next_node(addr);
+        let Some(next_node) = cursor.next() else {
+            return false;
+        };
+
+        if next_node.addr() != end_addr || next_node.size() < grow_size {
+            return false;
+        }
+
+        // node sizes are always a multiple of chunk size, so the remaining node is either empty or big enough to hold a node
+        let remaining_size = next_node.size() - grow_size;
+        cursor.remove_next();
+
+        if remaining_size != 0 {
+            let node = unsafe { Node::new(end_addr + grow_size, remaining_size) };
+            cursor.insert_next(node);
+        }
+
+        self.free_space.set(self.free_space() - grow_size);
+
+        true
+    }
+
     /// Returns a cursor that points between the previous and next node for the given address
     fn get_prev_next_node(&mut self, addr: usize) -> CursorMut<Node> {
         let mut cursor = self.list.cursor_start_mut();
@@ -290,16 +412,32 @@ impl ListNode for HeapZone {
 // TODO: add drop implementation that frees all page allocations
 struct LinkedListAllocatorInner {
     list: LinkedList<HeapZone>,
+    #[cfg(feature = "debug-alloc")]
+    live_allocations: debug::LiveAllocations,
 }
 
 impl LinkedListAllocatorInner {
     pub const fn new() -> Self {
         LinkedListAllocatorInner {
             list: LinkedList::new(),
+            #[cfg(feature = "debug-alloc")]
+            live_allocations: debug::LiveAllocations::new(),
         }
     }
 
     pub fn alloc(&mut self, layout: Layout) -> Option<(NonNull<[u8]>, MessageBuffer)> {
+        let allocation = self.alloc_inner(layout)?;
+
+        #[cfg(feature = "debug-alloc")]
+        {
+            debug::poison(allocation.0, debug::ALLOC_POISON);
+            self.live_allocations.insert(allocation.0.as_mut_ptr() as usize);
+        }
+
+        Some(allocation)
+    }
+
+    fn alloc_inner(&mut self, layout: Layout) -> Option<(NonNull<[u8]>, MessageBuffer)> {
         let size = layout.size();
         let align = layout.align();
 
@@ -311,7 +449,19 @@ impl LinkedListAllocatorInner {
             }
         }
 
-        // allocate new heapzone because there was no space in any others
+        // try to grow an existing heap zone in place before making a new one
+        let min_grow_size = size + max(align, CHUNK_SIZE);
+        for z in self.list.iter_mut() {
+            let grow_size = max(min(z.size, MAX_HEAP_ZONE_GROW_SIZE), min_grow_size);
+
+            if unsafe { z.grow(grow_size) } {
+                if let allocation @ Some(_) = unsafe { z.alloc(layout) } {
+                    return allocation;
+                }
+            }
+        }
+
+        // allocate new heapzone because no other zones could be grown
         let size_inc = max(HEAP_ZONE_SIZE, size + max(align, CHUNK_SIZE) + INITIAL_CHUNK_SIZE);
         let zone = match unsafe { HeapZone::new(size_inc) } {
             Some(n) => n,
@@ -332,8 +482,16 @@ impl LinkedListAllocatorInner {
         let addr = allocation.as_mut_ptr() as usize;
         let size = allocation.len();
 
+        #[cfg(feature = "debug-alloc")]
+        if !self.live_allocations.remove(addr) {
+            panic!("double free or free of unallocated memory at {:#x}", addr);
+        }
+
         for z in self.list.iter_mut() {
             if z.contains(addr, size) {
+                #[cfg(feature = "debug-alloc")]
+                debug::poison(allocation, debug::FREE_POISON);
+
                 unsafe {
                     z.dealloc(allocation);
                 }
@@ -341,7 +499,31 @@ impl LinkedListAllocatorInner {
             }
         }
 
-        panic!("invalid allocation passed to dealloc");
+        panic!("memory at {:#x} of size {} passed to dealloc is not owned by any heap zone", addr, size);
+    }
+
+    pub unsafe fn realloc_in_place(&mut self, allocation_start: NonNull<u8>, layout: Layout, new_size: usize) -> bool {
+        let allocation = LinkedListAllocator::get_allocation(allocation_start, layout)
+            .expect("invalid reallocation");
+
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            return false;
+        };
+        // panic safety: allocation start was already checked to be aligned
+        let new_size = LinkedListAllocator::get_allocation(allocation_start, new_layout)
+            .unwrap()
+            .len();
+
+        let addr = allocation.as_mut_ptr() as usize;
+        let size = allocation.len();
+
+        for z in self.list.iter_mut() {
+            if z.contains(addr, size) {
+                return unsafe { z.realloc_in_place(allocation, new_size) };
+            }
+        }
+
+        panic!("invalid allocation passed to realloc");
     }
 
     /// Deallocates all allocations in the linked list allocator
@@ -388,6 +570,21 @@ impl LinkedListAllocator {
         }
     }
 
+    /// Attempts to resize the allocation to `new_size` bytes without moving it
+    /// 
+    /// Shrinking always succeeds, growing succeeds if the memory directly after the allocation is free
+    /// 
+    /// Returns true if the allocation was resized, in which case it should now be deallocated with a layout of size `new_size`
+    /// 
+    /// # Safety
+    /// 
+    /// `allocation` must have been allocated by this allocator with `layout`
+    pub unsafe fn realloc_in_place(&self, allocation: NonNull<u8>, layout: Layout, new_size: usize) -> bool {
+        unsafe {
+            self.inner.lock().realloc_in_place(allocation, layout, new_size)
+        }
+    }
+
     pub unsafe fn realloc_with_message_buffer(
         &self,
         allocation: NonNull<u8>,
@@ -401,10 +598,12 @@ impl LinkedListAllocator {
             old_layout.size(),
         );
 
+        let copy_size = min(old_layout.size(), new_layout.size());
+
         // safety: realloc should be called with valid `allocation` pointer
         unsafe {
-            let dest_slice = &mut mem.as_mut()[..allocation_slice.len()];
-            dest_slice.copy_from_slice(allocation_slice.as_mut());
+            let dest_slice = &mut mem.as_mut()[..copy_size];
+            dest_slice.copy_from_slice(&allocation_slice.as_mut()[..copy_size]);
         }
 
         unsafe {
@@ -414,7 +613,6 @@ impl LinkedListAllocator {
     }
 }
 
-// TODO: add specialized realloc method
 unsafe impl GlobalAlloc for LinkedListAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         match self.inner.lock().alloc(layout) {
@@ -428,6 +626,22 @@ unsafe impl GlobalAlloc for LinkedListAllocator {
 
         unsafe { self.inner.lock().dealloc(ptr, layout) }
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let ptr = NonNull::new(ptr).expect("null pointer passed to allocator");
+
+        if unsafe { self.realloc_in_place(ptr, layout, new_size) } {
+            return ptr.as_ptr();
+        }
+
+        // safety: caller guarentees new size with layout's alignment is a valid layout
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+
+        match unsafe { self.realloc_with_message_buffer(ptr, layout, new_layout) } {
+            Some((mem, _)) => mem.as_ptr().as_mut_ptr(),
+            None => null_mut(),
+        }
+    }
 }
 
 impl Drop for LinkedListAllocator {
@@ -438,9 +652,193 @@ impl Drop for LinkedListAllocator {
     }
 }
 
-#[global_allocator]
+// the allocator maps memory with system calls, so it can't be used on the host
+#[cfg_attr(not(feature = "std"), global_allocator)]
 static ALLOCATOR: LinkedListAllocator = LinkedListAllocator::new();
 
 pub fn allocator() -> &'static LinkedListAllocator {
     &ALLOCATOR
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::alloc::{alloc_zeroed, dealloc};
+
+    use super::*;
+
+    const TEST_ZONE_SIZE: usize = 4 * PAGE_SIZE;
+
+    /// Host memory a heap zone is put in, since mapping memory for a real heap zone needs the kernel
+    struct ZoneMemory(NonNull<u8>);
+
+    impl ZoneMemory {
+        fn new() -> Self {
+            let ptr = unsafe { alloc_zeroed(Self::layout()) };
+            ZoneMemory(NonNull::new(ptr).expect("failed to allocate test heap zone"))
+        }
+
+        fn layout() -> Layout {
+            Layout::from_size_align(TEST_ZONE_SIZE, PAGE_SIZE).unwrap()
+        }
+
+        /// The returned zone must not be used after this memory is dropped
+        fn zone(&self) -> MemOwner<HeapZone> {
+            unsafe { HeapZone::init_at(self.0.as_ptr() as usize, TEST_ZONE_SIZE, CapId::null()) }
+        }
+    }
+
+    impl Drop for ZoneMemory {
+        fn drop(&mut self) {
+            unsafe {
+                dealloc(self.0.as_ptr(), Self::layout());
+            }
+        }
+    }
+
+    fn chunks(count: usize) -> Layout {
+        Layout::from_size_align(count * CHUNK_SIZE, 8).unwrap()
+    }
+
+    fn addr(allocation: NonNull<[u8]>) -> usize {
+        allocation.as_mut_ptr() as usize
+    }
+
+    /// Allocates 2 allocations of `count` chunks, the second is directly before the first
+    fn alloc_adjacent(zone: &mut HeapZone, count: usize) -> (NonNull<[u8]>, NonNull<[u8]>) {
+        let (after, _) = unsafe { zone.alloc(chunks(count)) }.unwrap();
+        let (before, _) = unsafe { zone.alloc(chunks(count)) }.unwrap();
+        assert_eq!(addr(before) + before.len(), addr(after));
+
+        (before, after)
+    }
+
+    #[test]
+    fn realloc_grows_into_free_node_after_allocation() {
+        let memory = ZoneMemory::new();
+        let mut zone = memory.zone();
+
+        let (allocation, after) = alloc_adjacent(&mut zone, 2);
+        unsafe {
+            zone.dealloc(after);
+        }
+        let free_space = zone.free_space();
+
+        assert!(unsafe { zone.realloc_in_place(allocation, 3 * CHUNK_SIZE) });
+        assert_eq!(zone.free_space(), free_space - CHUNK_SIZE);
+
+        // the rest of the freed allocation is still free after the grown allocation
+        let grown = NonNull::slice_from_raw_parts(allocation.as_non_null_ptr(), 3 * CHUNK_SIZE);
+        assert!(unsafe { zone.realloc_in_place(grown, 4 * CHUNK_SIZE) });
+        assert_eq!(zone.free_space(), free_space - 2 * CHUNK_SIZE);
+    }
+
+    #[test]
+    fn realloc_grow_blocked_by_adjacent_allocation() {
+        let memory = ZoneMemory::new();
+        let mut zone = memory.zone();
+
+        let (allocation, _) = alloc_adjacent(&mut zone, 2);
+        let free_space = zone.free_space();
+
+        assert!(!unsafe { zone.realloc_in_place(allocation, 3 * CHUNK_SIZE) });
+        assert_eq!(zone.free_space(), free_space);
+    }
+
+    #[test]
+    fn realloc_shrink_releases_tail() {
+        let memory = ZoneMemory::new();
+        let mut zone = memory.zone();
+
+        let (allocation, _) = alloc_adjacent(&mut zone, 4);
+        let free_space = zone.free_space();
+
+        assert!(unsafe { zone.realloc_in_place(allocation, CHUNK_SIZE) });
+        assert_eq!(zone.free_space(), free_space + 3 * CHUNK_SIZE);
+
+        // the released tail is a free node directly after the allocation, so it can be grown back into
+        let shrunk = NonNull::slice_from_raw_parts(allocation.as_non_null_ptr(), CHUNK_SIZE);
+        assert!(unsafe { zone.realloc_in_place(shrunk, 4 * CHUNK_SIZE) });
+        assert_eq!(zone.free_space(), free_space);
+    }
+
+    #[test]
+    fn realloc_rounds_new_size_to_chunks() {
+        let memory = ZoneMemory::new();
+        let mut inner = LinkedListAllocatorInner::new();
+        inner.list.push(memory.zone());
+
+        let layout = chunks(1);
+        let (after, _) = inner.alloc(layout).unwrap();
+        let (allocation, _) = inner.alloc(layout).unwrap();
+        unsafe {
+            inner.dealloc(after.as_non_null_ptr(), layout);
+        }
+        let free_space = inner.list.iter().next().unwrap().free_space();
+
+        // one byte more than a chunk needs a whole extra chunk
+        assert!(unsafe { inner.realloc_in_place(allocation.as_non_null_ptr(), layout, CHUNK_SIZE + 1) });
+        assert_eq!(inner.list.iter().next().unwrap().free_space(), free_space - CHUNK_SIZE);
+    }
+
+    /// Puts a heap zone in `memory` into a new allocator
+    #[cfg(feature = "debug-alloc")]
+    fn allocator_with_zone(memory: &ZoneMemory) -> LinkedListAllocatorInner {
+        let mut inner = LinkedListAllocatorInner::new();
+        inner.list.push(memory.zone());
+        inner
+    }
+
+    #[cfg(feature = "debug-alloc")]
+    #[test]
+    fn allocations_and_freed_memory_are_poisoned() {
+        let memory = ZoneMemory::new();
+        let mut inner = allocator_with_zone(&memory);
+
+        let layout = chunks(4);
+        let (allocation, _) = inner.alloc(layout).unwrap();
+        assert!(unsafe { allocation.as_ref() }.iter().all(|byte| *byte == debug::ALLOC_POISON));
+
+        unsafe {
+            inner.dealloc(allocation.as_non_null_ptr(), layout);
+        }
+
+        // the start of the freed memory now holds a free node header
+        let tail = unsafe { &allocation.as_ref()[CHUNK_SIZE..] };
+        assert!(tail.iter().all(|byte| *byte == debug::FREE_POISON));
+    }
+
+    #[cfg(feature = "debug-alloc")]
+    #[test]
+    #[should_panic(expected = "double free")]
+    fn double_free_panics() {
+        let memory = ZoneMemory::new();
+        let mut inner = allocator_with_zone(&memory);
+
+        let layout = chunks(1);
+        let (allocation, _) = inner.alloc(layout).unwrap();
+
+        unsafe {
+            inner.dealloc(allocation.as_non_null_ptr(), layout);
+            inner.dealloc(allocation.as_non_null_ptr(), layout);
+        }
+    }
+
+    #[cfg(feature = "debug-alloc")]
+    #[test]
+    #[should_panic(expected = "overlaps free node")]
+    fn overrun_into_free_node_panics() {
+        let memory = ZoneMemory::new();
+        let mut zone = memory.zone();
+
+        let (allocation, after) = alloc_adjacent(&mut zone, 1);
+        unsafe {
+            zone.dealloc(after);
+        }
+
+        // an allocation whose size was corrupted to cover the free node after it, like an overrun smashing a node header
+        let overrun = NonNull::slice_from_raw_parts(allocation.as_non_null_ptr(), 2 * CHUNK_SIZE);
+        unsafe {
+            zone.dealloc(overrun);
+        }
+    }
 }
\ No newline at end of file
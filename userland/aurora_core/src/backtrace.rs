@@ -0,0 +1,108 @@
+//! Captures the return addresses of the current thread by walking the frame pointer chain
+//!
+//! This relies on every function saving the frame pointer, which the userland target spec forces.
+use core::arch::asm;
+use core::fmt::{self, Display};
+use core::mem::size_of;
+
+use crate::thread;
+use crate::try_addr_space;
+
+/// Most return addresses recorded in a backtrace
+pub const MAX_BACKTRACE_FRAMES: usize = 32;
+
+/// Return addresses of each frame on the stack, from innermost to outermost
+///
+/// This does not allocate, so it can be captured when the heap is broken
+#[derive(Debug, Clone)]
+pub struct Backtrace {
+    frames: [usize; MAX_BACKTRACE_FRAMES],
+    len: usize,
+}
+
+impl Backtrace {
+    /// Captures a backtrace of the current thread
+    ///
+    /// The backtrace is empty if the current thread's stack region could not be found,
+    /// which happens if the address space is locked by the current thread
+    #[inline(never)]
+    pub fn capture() -> Self {
+        let mut backtrace = Backtrace {
+            frames: [0; MAX_BACKTRACE_FRAMES],
+            len: 0,
+        };
+
+        let Some((stack_start, stack_end)) = current_stack_range() else {
+            return backtrace;
+        };
+
+        let mut frame_pointer: usize;
+        unsafe {
+            asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack));
+        }
+
+        while backtrace.len < MAX_BACKTRACE_FRAMES {
+            // each frame starts with the caller's frame pointer followed by the return address,
+            // the stack could be corrupted so it is checked they are in the stack before reading them
+            let frame_in_stack = frame_pointer >= stack_start
+                && frame_pointer.checked_add(2 * size_of::<usize>()).is_some_and(|frame_end| frame_end <= stack_end)
+                && frame_pointer % size_of::<usize>() == 0;
+            if !frame_in_stack {
+                break;
+            }
+
+            // safety: the frame is inside the current thread's stack, which is mapped readable
+            let (next_frame_pointer, return_address) = unsafe {
+                let frame = frame_pointer as *const usize;
+                (*frame, *frame.add(1))
+            };
+
+            if return_address == 0 {
+                break;
+            }
+
+            backtrace.frames[backtrace.len] = return_address;
+            backtrace.len += 1;
+
+            // the stack grows down, so the caller's frame must be at a higher address,
+            // this also stops loops in a corrupted frame pointer chain
+            if next_frame_pointer <= frame_pointer {
+                break;
+            }
+
+            frame_pointer = next_frame_pointer;
+        }
+
+        backtrace
+    }
+
+    /// Return addresses from innermost to outermost frame
+    pub fn frames(&self) -> &[usize] {
+        &self.frames[..self.len]
+    }
+}
+
+impl Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.frames().is_empty() {
+            return write!(f, "backtrace unavailable");
+        }
+
+        write!(f, "backtrace:")?;
+        for (i, address) in self.frames().iter().enumerate() {
+            write!(f, "\n{i:>4}: {address:#x}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Gets the start and end address of the current thread's stack
+fn current_stack_range() -> Option<(usize, usize)> {
+    let stack_start = thread::current().stack_region_address();
+    let stack_size = try_addr_space()?
+        .get_mapping_size(stack_start)
+        .ok()?;
+
+    Some((stack_start, stack_start + stack_size.bytes()))
+}
\ No newline at end of file
@@ -0,0 +1,235 @@
+use core::cell::Cell;
+use core::cmp::max;
+
+use aser::{AserError, ByteBuf, count_capabilties, to_byte_buf, to_byte_buf_named};
+use bit_utils::{Size, align_up};
+use serde::Serialize;
+use sys::{CapId, Capability, MessageBuffer, MemoryMappingOptions};
+use thiserror_no_std::Error;
+
+use crate::addr_space;
+use crate::allocator::addr_space::{MapMemoryArgs, AddrSpaceError};
+
+/// Alignment of every allocation made in the arena
+const ARENA_ALIGN: usize = core::mem::size_of::<usize>();
+
+#[derive(Debug, Error)]
+pub enum MessageArenaError {
+    #[error("Message arena does not have enough space left")]
+    OutOfSpace,
+    #[error("Failed to serialize message into arena: {0}")]
+    SerializationError(#[from] AserError),
+}
+
+/// A bump allocator backed by a single memory capability, used for assembling messages
+///
+/// Every allocation also reports the [`MessageBuffer`] referencing it, so it can be sent without copying into another buffer.
+/// Allocations are only reclaimed all at once with [`reset`](MessageArena::reset).
+pub struct MessageArena {
+    /// Address the arena memory is mapped at
+    address: usize,
+    size: Size,
+    memory_cap_id: CapId,
+    /// Offset of the next free byte in the arena
+    offset: Cell<usize>,
+    /// Highest offset the arena has reached since it was created
+    high_water_mark: Cell<usize>,
+}
+
+impl MessageArena {
+    /// Creates a message arena that can hold at least `size` bytes
+    pub fn new(size: Size) -> Result<Self, AddrSpaceError> {
+        // the mapping result borrows the address space manager, so the lock is held until everything is copied out of it
+        let (address, size, memory_cap_id) = {
+            let mut addr_space = addr_space();
+            let mapping = addr_space.map_memory(MapMemoryArgs {
+                size: Some(size),
+                options: MemoryMappingOptions {
+                    read: true,
+                    write: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })?;
+
+            // panic safety: map_memory always returns memory when a non zero size is requested
+            (mapping.address, mapping.size, mapping.memory.unwrap().cap_id())
+        };
+
+        Ok(MessageArena {
+            address,
+            size,
+            memory_cap_id,
+            offset: Cell::new(0),
+            high_water_mark: Cell::new(0),
+        })
+    }
+
+    /// Total number of bytes the arena can hold
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    /// The most bytes that have been in use at once since the arena was created
+    pub fn high_water_mark(&self) -> Size {
+        Size::from_bytes(self.high_water_mark.get())
+    }
+
+    fn message_buffer(&self, offset: usize, size: usize) -> MessageBuffer {
+        MessageBuffer {
+            memory_id: self.memory_cap_id,
+            offset: Size::from_bytes(offset),
+            size: Size::from_bytes(size),
+        }
+    }
+
+    /// Moves the end of the arena to `new_offset`, returns false if there is not enough space
+    fn set_offset(&self, new_offset: usize) -> bool {
+        if new_offset > self.size.bytes() {
+            return false;
+        }
+
+        self.offset.set(new_offset);
+        self.high_water_mark.set(max(self.high_water_mark.get(), new_offset));
+
+        true
+    }
+
+    /// Allocates `size` bytes from the arena
+    ///
+    /// Returns the allocated memory and the message buffer referencing it, or None if the arena is out of space
+    pub fn alloc(&self, size: usize) -> Option<(&mut [u8], MessageBuffer)> {
+        let offset = align_up(self.offset.get(), ARENA_ALIGN);
+        let new_offset = offset.checked_add(size)?;
+
+        if !self.set_offset(new_offset) {
+            return None;
+        }
+
+        // safety: this range is within the arena mapping and is not handed out again until reset,
+        // which requires a mutable reference so no allocations can still be borrowed
+        let data = unsafe {
+            core::slice::from_raw_parts_mut((self.address + offset) as *mut u8, size)
+        };
+
+        Some((data, self.message_buffer(offset, size)))
+    }
+
+    /// Serializes `value` directly into the arena
+    ///
+    /// Returns the message buffer referencing the serialized data
+    pub fn serialize<T: Serialize>(&self, value: &T) -> Result<MessageBuffer, MessageArenaError> {
+        self.serialize_inner(value, false)
+    }
+
+    /// Same as [`serialize`](Self::serialize), but enum variants are encoded by name
+    pub fn serialize_named<T: Serialize>(&self, value: &T) -> Result<MessageBuffer, MessageArenaError> {
+        self.serialize_inner(value, true)
+    }
+
+    fn serialize_inner<T: Serialize>(&self, value: &T, named_variants: bool) -> Result<MessageBuffer, MessageArenaError> {
+        let num_capabilities = count_capabilties(value)?;
+
+        let start = align_up(self.offset.get(), ARENA_ALIGN);
+
+        // the serializer expects the capability header to always be writable
+        let header_size = (num_capabilities + 1) * core::mem::size_of::<usize>();
+        if start.saturating_add(header_size) > self.size.bytes() {
+            return Err(MessageArenaError::OutOfSpace);
+        }
+        self.set_offset(start);
+
+        let arena_buf = ArenaByteBuf {
+            arena: self,
+            start,
+            len: 0,
+            out_of_space: false,
+        };
+
+        let buf = if named_variants {
+            to_byte_buf_named(value, num_capabilities, arena_buf)
+        } else {
+            to_byte_buf(value, num_capabilities, arena_buf)
+        };
+
+        let buf = match buf {
+            Ok(buf) => buf,
+            Err(error) => {
+                self.offset.set(start);
+                return Err(error.into());
+            },
+        };
+
+        if buf.out_of_space {
+            self.offset.set(start);
+            return Err(MessageArenaError::OutOfSpace);
+        }
+
+        Ok(self.message_buffer(start, buf.len))
+    }
+
+    /// Reclaims every allocation made in the arena
+    pub fn reset(&mut self) {
+        self.offset.set(0);
+    }
+}
+
+impl Drop for MessageArena {
+    fn drop(&mut self) {
+        unsafe {
+            addr_space().unmap_memory(self.address)
+                .expect("failed to unmap message arena");
+        }
+    }
+}
+
+/// Byte buffer which grows at the end of a message arena
+///
+/// No other allocations can be made in the arena while this exists, since serialization does not yield to other code
+struct ArenaByteBuf<'a> {
+    arena: &'a MessageArena,
+    /// Offset in the arena where the buffer starts
+    start: usize,
+    len: usize,
+    /// Set if a write did not fit in the arena, all later writes are ignored
+    out_of_space: bool,
+}
+
+impl ByteBuf for ArenaByteBuf<'_> {
+    fn push(&mut self, byte: u8) -> Result<(), AserError> {
+        self.extend_from_slice(&[byte])
+    }
+
+    fn extend_from_slice(&mut self, slice: &[u8]) -> Result<(), AserError> {
+        if self.out_of_space {
+            return Ok(());
+        }
+
+        let write_offset = self.start + self.len;
+        if !self.arena.set_offset(write_offset + slice.len()) {
+            self.out_of_space = true;
+            return Ok(());
+        }
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                slice.as_ptr(),
+                (self.arena.address + write_offset) as *mut u8,
+                slice.len(),
+            );
+        }
+
+        self.len += slice.len();
+        Ok(())
+    }
+
+    fn as_slice(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut((self.arena.address + self.start) as *mut u8, self.len)
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
\ No newline at end of file
@@ -8,8 +8,8 @@ use core::slice::SliceIndex;
 use core::cmp::max;
 use core::mem::size_of;
 
-use aser::ByteBuf;
-use sys::MessageBuffer;
+use aser::{AserError, ByteBuf};
+use sys::{MessageBuffer, KResult, SysErr};
 use bit_utils::Size;
 
 use crate::allocator::allocator;
@@ -37,26 +37,26 @@ impl<T> RawMessageVec<T> {
     }
 
     // tries to create a raw vec with specified capacity, returns out of mem on failure
-    fn with_capacity(cap: usize) -> Self {
+    fn try_with_capacity(cap: usize) -> KResult<Self> {
         if size_of::<T>() == 0 {
-            RawMessageVec::new()
+            Ok(RawMessageVec::new())
         } else {
-            let layout = Layout::array::<T>(cap).unwrap();
+            let layout = Layout::array::<T>(cap).or(Err(SysErr::Overflow))?;
             let (ptr, message_buffer) = allocator()
                 .alloc_with_message_buffer(layout)
-                .expect("MessageVec: out of mem");
+                .ok_or(SysErr::OutOfMem)?;
 
-            RawMessageVec {
+            Ok(RawMessageVec {
                 ptr: ptr.cast(),
                 cap,
                 message_buffer: Some(message_buffer),
                 marker: PhantomData,
-            }
+            })
         }
     }
 
     // returns out of mem on failure
-    fn grow(&mut self, required_cap: Option<usize>) {
+    fn try_grow(&mut self, required_cap: Option<usize>) -> KResult<()> {
         // since we set the capacity to usize::MAX when T has size 0,
         // getting to here necessarily means the Vec is overfull.
         assert!(size_of::<T>() != 0, "capacity overflow");
@@ -74,7 +74,7 @@ impl<T> RawMessageVec<T> {
 
             // if required cap is less than current capacity, there is no need to grow
             if required_cap <= self.cap {
-                return;
+                return Ok(());
             }
 
             new_cap = max(new_cap, required_cap);
@@ -95,15 +95,11 @@ impl<T> RawMessageVec<T> {
             unsafe { allocator().realloc_with_message_buffer(self.ptr.cast(), old_layout, new_layout) }
         };
 
-        // If allocation fails, `new_ptr` will be null, in which case we abort.
-        match new_alloc {
-            Some((ptr, message_buffer)) => {
-                self.ptr = ptr.as_non_null_ptr().cast();
-                self.cap = new_cap;
-                self.message_buffer = Some(message_buffer);
-            },
-            None => panic!("MessageVec: out of memory"),
-        }
+        let (ptr, message_buffer) = new_alloc.ok_or(SysErr::OutOfMem)?;
+        self.ptr = ptr.as_non_null_ptr().cast();
+        self.cap = new_cap;
+        self.message_buffer = Some(message_buffer);
+        Ok(())
     }
 }
 
@@ -137,10 +133,14 @@ impl<T> MessageVec<T> {
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
-        MessageVec {
-            inner: RawMessageVec::with_capacity(capacity),
+        Self::try_with_capacity(capacity).expect("MessageVec: out of memory")
+    }
+
+    pub fn try_with_capacity(capacity: usize) -> KResult<Self> {
+        Ok(MessageVec {
+            inner: RawMessageVec::try_with_capacity(capacity)?,
             len: 0,
-        }
+        })
     }
 
     // returns a mutable pointer to the object at the specified index
@@ -164,6 +164,23 @@ impl<T> MessageVec<T> {
         Some(buffer)
     }
 
+    /// Same as [`message_buffer`](Self::message_buffer), but allocates if the vec has never allocated
+    ///
+    /// This means an empty vec still gives a valid message buffer, which has a size of 0.
+    /// Fails with [`SysErr::InvlOp`] if `T` is zero sized, since those vecs never allocate.
+    pub fn try_message_buffer(&mut self) -> KResult<MessageBuffer> {
+        if size_of::<T>() == 0 {
+            return Err(SysErr::InvlOp);
+        }
+
+        if self.inner.message_buffer.is_none() {
+            self.inner.try_grow(None)?;
+        }
+
+        // panic safety: the vec was allocated above if it wasn't already
+        Ok(self.message_buffer().unwrap())
+    }
+
     pub fn clear(&mut self) {
         while self.pop().is_some() {}
     }
@@ -192,9 +209,19 @@ impl<T> MessageVec<T> {
         index.get_mut(self)
     }
 
+    /// Makes sure the vec can hold at least `additional` more elements without reallocating
+    pub fn try_reserve(&mut self, additional: usize) -> KResult<()> {
+        let required_cap = self.len.checked_add(additional).ok_or(SysErr::Overflow)?;
+        self.inner.try_grow(Some(required_cap))
+    }
+
     pub fn push(&mut self, object: T) {
+        self.try_push(object).expect("MessageVec: out of memory")
+    }
+
+    pub fn try_push(&mut self, object: T) -> KResult<()> {
         if self.len == self.capacity() {
-            self.inner.grow(None);
+            self.inner.try_grow(None)?;
         }
 
         unsafe {
@@ -202,6 +229,8 @@ impl<T> MessageVec<T> {
         }
 
         self.len += 1;
+
+        Ok(())
     }
 
     pub fn pop(&mut self) -> Option<T> {
@@ -223,10 +252,14 @@ impl<T> MessageVec<T> {
     }
 
     pub fn insert(&mut self, index: usize, object: T) {
+        self.try_insert(index, object).expect("MessageVec: out of memory")
+    }
+
+    pub fn try_insert(&mut self, index: usize, object: T) -> KResult<()> {
         assert!(index <= self.len, "index out of bounds");
 
         if self.len == self.capacity() {
-            self.inner.grow(None);
+            self.inner.try_grow(None)?;
         }
 
         let ncpy = self.len - index;
@@ -237,6 +270,8 @@ impl<T> MessageVec<T> {
         }
 
         self.len += 1;
+
+        Ok(())
     }
 
     pub fn remove(&mut self, index: usize) -> T {
@@ -315,23 +350,31 @@ impl<T> MessageVec<T> {
 
 impl<T: Clone> MessageVec<T> {
     pub fn extend_from_slice(&mut self, slice: &[T]) {
-        self.inner.grow(Some(self.len + slice.len()));
+        self.try_extend_from_slice(slice).expect("MessageVec: out of memory")
+    }
+
+    pub fn try_extend_from_slice(&mut self, slice: &[T]) -> KResult<()> {
+        self.try_reserve(slice.len())?;
 
         for item in slice {
-            self.push(item.clone());
+            // panic safety: we have already reserved enough space so this should not fail
+            self.try_push(item.clone()).unwrap();
         }
+
+        Ok(())
     }
 }
 
 impl<T: Clone> MessageVec<T> {
     pub fn from_slice(slice: &[T]) -> Self {
-        let mut out = Self::with_capacity(slice.len());
+        Self::try_from_slice(slice).expect("MessageVec: out of memory")
+    }
 
-        for item in slice {
-            out.push(item.clone());
-        }
+    pub fn try_from_slice(slice: &[T]) -> KResult<Self> {
+        let mut out = Self::try_with_capacity(slice.len())?;
+        out.try_extend_from_slice(slice)?;
 
-        out
+        Ok(out)
     }
 }
 
@@ -379,12 +422,12 @@ impl<T> Default for MessageVec<T> {
 }
 
 impl ByteBuf for MessageVec<u8> {
-    fn push(&mut self, byte: u8) {
-        self.push(byte);
+    fn push(&mut self, byte: u8) -> Result<(), AserError> {
+        self.try_push(byte).or(Err(AserError::OutOfMemory))
     }
 
-    fn extend_from_slice(&mut self, slice: &[u8]) {
-        self.extend_from_slice(slice);
+    fn extend_from_slice(&mut self, slice: &[u8]) -> Result<(), AserError> {
+        self.try_extend_from_slice(slice).or(Err(AserError::OutOfMemory))
     }
 
     fn as_slice(&mut self) -> &mut [u8] {
@@ -1,9 +1,39 @@
+mod message_arena;
+pub use message_arena::*;
 mod message_vec;
 pub use message_vec::*;
 
-use core::hash::BuildHasherDefault;
+use core::hash::{BuildHasher, BuildHasherDefault, Hash};
 
 use hashbrown::HashMap as HashbrownMap;
 use rustc_hash::FxHasher;
+use sys::{KResult, SysErr};
 
-pub type HashMap<K, V> = HashbrownMap<K, V, BuildHasherDefault<FxHasher>>;
\ No newline at end of file
+pub type HashMap<K, V> = HashbrownMap<K, V, BuildHasherDefault<FxHasher>>;
+
+/// Fallible versions of the [`HashMap`] methods that allocate
+/// 
+/// hashbrown already has inherent `try_reserve` and `try_insert` methods with different errors and meaning,
+/// so these use different names to not be shadowed by them.
+pub trait HashMapExt<K, V> {
+    /// Makes sure the map can hold at least `additional` more entries without reallocating
+    fn try_reserve_entries(&mut self, additional: usize) -> KResult<()>;
+
+    /// Same as `insert`, but returns an error instead of panicking if the map could not grow
+    fn insert_fallible(&mut self, key: K, value: V) -> KResult<Option<V>>;
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> HashMapExt<K, V> for HashbrownMap<K, V, S> {
+    fn try_reserve_entries(&mut self, additional: usize) -> KResult<()> {
+        self.try_reserve(additional).or(Err(SysErr::OutOfMem))
+    }
+
+    fn insert_fallible(&mut self, key: K, value: V) -> KResult<Option<V>> {
+        // reserving is only needed if the key is new, and it does nothing if there is already space
+        if !self.contains_key(&key) {
+            self.try_reserve_entries(1)?;
+        }
+
+        Ok(self.insert(key, value))
+    }
+}
\ No newline at end of file
@@ -10,9 +10,9 @@
 extern crate alloc;
 
 use aser::AserError;
-use bit_utils::Size;
+use bit_utils::{Size, PAGE_SIZE, KERNEL_RESERVED_START};
 use sys::{CapId, ThreadGroup, Allocator, Memory, AddressSpace, CapabilitySpace};
-pub use sys::{ProcessInitData, ProcessMemoryEntry, Capability, process_data_from_slice};
+pub use sys::{ProcessInitData, ProcessMemoryEntry, StackInfo, Capability};
 use thiserror_no_std::Error;
 
 use allocator::addr_space::{LocalAddrSpaceManager, AddrSpaceError, RegionPadding, MappedRegion, MappingTarget};
@@ -20,36 +20,62 @@ use context::Context;
 use sync::{Once, Mutex, MutexGuard};
 
 use prelude::*;
-use thread::{ThreadLocalData, Thread};
+use thread::{ThreadLocalData, Thread, TlsTemplate};
 
 pub mod allocator;
+pub mod backtrace;
 mod context;
 pub mod collections;
 pub mod prelude;
 pub mod process;
+mod process_data;
+pub use process_data::{process_data_from_slice, ProcessDataError};
 pub mod thread;
 pub mod sync;
 
 static THIS_CONTEXT: Once<Context> = Once::new();
 
+/// Name this process was spawned with, set by [`init_allocation`]
+static PROCESS_NAME: Once<ProcessInitData> = Once::new();
+
 pub fn this_context() -> &'static Context {
     THIS_CONTEXT.get().unwrap()
 }
 
+/// Gets the name this process was spawned with, or None if it was not given one or is not initialized yet
+pub fn process_name() -> Option<&'static str> {
+    PROCESS_NAME.get()?.process_name()
+        .filter(|name| !name.is_empty())
+}
+
 static ADDR_SPACE: Once<Mutex<LocalAddrSpaceManager>> = Once::new();
 
 pub fn addr_space() -> MutexGuard<'static, LocalAddrSpaceManager> {
     ADDR_SPACE.get().unwrap().lock()
 }
 
+/// Like [`addr_space`], but returns None instead of waiting if the address space is locked or not yet initialized
+pub fn try_addr_space() -> Option<MutexGuard<'static, LocalAddrSpaceManager>> {
+    ADDR_SPACE.get()?.try_lock()
+}
+
 #[derive(Debug, Error)]
 pub enum InitError {
     #[error("Invalid capability id in the process data")]
     InvalidCapId,
     #[error("Error initilizing address space: {0}")]
     AdrSpaceError(#[from] AddrSpaceError),
+    #[error("Process memory entry has invalid map address {0:#x}")]
+    InvalidMapAddress(usize),
+    #[error("Process memory entry at address {new:#x} overlaps memory entry at address {existing:#x}")]
+    OverlappingMemoryEntry {
+        existing: usize,
+        new: usize,
+    },
     #[error("Error deserializing namespace data: {0}")]
     SerializationError(#[from] AserError),
+    #[error("The tls image is bigger than the tls block, or the tls alignment is not a power of 2")]
+    InvalidTlsTemplate,
 }
 
 impl TryFrom<ProcessInitData> for Context {
@@ -79,6 +105,13 @@ impl TryFrom<ProcessMemoryEntry> for MappedRegion {
     type Error = InitError;
 
     fn try_from(value: ProcessMemoryEntry) -> Result<Self, Self::Error> {
+        let map_end = value.map_address.checked_add(value.map_size)
+            .ok_or(InitError::InvalidMapAddress(value.map_address))?;
+
+        if value.map_address % PAGE_SIZE != 0 || map_end > KERNEL_RESERVED_START {
+            return Err(InitError::InvalidMapAddress(value.map_address));
+        }
+
         let memory_id = CapId::try_from(value.memory_cap_id).ok_or(InitError::InvalidCapId)?;
         let memory = Memory::from_capid_size(memory_id, Some(Size::from_bytes(value.memory_size)))
             .ok_or(InitError::InvalidCapId)?;
@@ -86,6 +119,7 @@ impl TryFrom<ProcessMemoryEntry> for MappedRegion {
         let padding = RegionPadding {
             start: Size::from_bytes(value.padding_start),
             end: Size::from_bytes(value.padding_end),
+            start_guard: value.padding_start_guard != 0,
         };
 
         Ok(MappedRegion {
@@ -98,7 +132,18 @@ impl TryFrom<ProcessMemoryEntry> for MappedRegion {
 }
 
 /// Performs all the initilization required for memory mapping, allocation, and threading to work
-pub fn init_allocation(init_data: ProcessInitData, memory_entries: &[ProcessMemoryEntry]) -> Result<(), InitError> {
+/// 
+/// `tls_template` is used to initialize the tls block of the main thread and every thread spawned later.
+pub fn init_allocation(
+    init_data: ProcessInitData,
+    memory_entries: &[ProcessMemoryEntry],
+    tls_template: Option<TlsTemplate>,
+) -> Result<(), InitError> {
+    let name_data = PROCESS_NAME.call_once(|| init_data);
+    if let Some(process_name) = name_data.process_name() {
+        sys::set_debug_print_name(process_name);
+    }
+
     let context = init_data.try_into()?;
     THIS_CONTEXT.call_once(|| context);
 
@@ -106,8 +151,10 @@ pub fn init_allocation(init_data: ProcessInitData, memory_entries: &[ProcessMemo
     for memory_entry in memory_entries {
         let region = (*memory_entry).try_into()?;
 
-        // TODO: add more checks to make sure regions don't overlap
-        addr_space.insert_region(region)?;
+        addr_space.insert_region(region).map_err(|error| match error {
+            AddrSpaceError::OverlappingRegion { existing, new } => InitError::OverlappingMemoryEntry { existing, new },
+            error => error.into(),
+        })?;
     }
 
     ADDR_SPACE.call_once(|| Mutex::new(addr_space));
@@ -123,7 +170,15 @@ pub fn init_allocation(init_data: ProcessInitData, memory_entries: &[ProcessMemo
         init_data.stack_region_start_address,
     );
 
+    if let Some(tls_template) = tls_template {
+        thread::set_tls_template(tls_template);
+    }
     ThreadLocalData::init(main_thread);
 
+    // safety: every thread initializes its local data before running code which prints
+    unsafe {
+        sys::set_thread_line_buffer_getter(thread::debug_line_buffer);
+    }
+
     Ok(())
 }
\ No newline at end of file
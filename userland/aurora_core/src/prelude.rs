@@ -5,4 +5,4 @@ pub use alloc::string::{String, ToString};
 pub use alloc::vec::Vec;
 pub use alloc::boxed::Box;
 
-pub use sys::{dprint, dprintln};
\ No newline at end of file
+pub use sys::{dprint, dprintln, dprint_unbuffered, dprintln_unbuffered};
\ No newline at end of file
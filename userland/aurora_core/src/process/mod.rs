@@ -1,24 +1,33 @@
+use core::fmt::{self, Display};
 use core::mem::size_of;
 
 use crate::allocator::addr_space::{RemoteAddrSpaceManager, AddrSpaceError, MapMemoryArgs, RegionPadding, MappingTarget};
 
 use aser::{AserError, AserCloneCapsError};
 use bit_utils::{align_down, PAGE_SIZE, align_up, Size};
-use elf::abi::{PT_LOAD, PF_R, PF_W, PF_X};
+use elf::abi::{PT_LOAD, PT_TLS, PF_R, PF_W, PF_X};
 use elf::{ElfBytes, ParseError};
 use elf::endian::NativeEndian;
-use sys::{CapFlags, SysErr, Thread, AddressSpace, ThreadStartMode, ProcessInitData, ProcessMemoryEntry, cap_clone, CspaceTarget, Capability, StackInfo, MemoryMappingOptions};
+use sys::{CapFlags, SysErr, KResult, Thread, ThreadGroup, ExitReason, FaultKind, FaultRecord, Memory, AddressSpace, ThreadStartMode, ProcessInitData, ProcessMemoryEntry, PROCESS_INIT_DATA_MAGIC, PROCESS_INIT_DATA_VERSION, cap_clone, CspaceTarget, Capability, StackInfo, MemoryMappingOptions, system_entropy, SYSTEM_ENTROPY_SIZE};
 use thiserror_no_std::Error;
-use bytemuck::bytes_of;
+use bytemuck::{bytes_of, Zeroable};
 
 use crate::{prelude::*, this_context};
 
 pub(crate) const DEFAULT_STACK_SIZE: Size = Size::from_pages(64);
 pub(crate) const DEFAULT_STACK_PADDING: Size = Size::from_pages(1024);
 
-/// Terminates the current process
+/// Exit code used by the panic handler, so a parent can tell a panicking child apart from one that exited normally
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+/// Terminates the current process with an exit code of 0
 pub fn exit() -> ! {
-    let _ = this_context().thread_group.exit();
+    exit_with_code(0)
+}
+
+/// Terminates the current process, the parent recieves `code` when it waits on this process
+pub fn exit_with_code(code: i32) -> ! {
+    let _ = this_context().thread_group.exit(code);
 
     loop { core::hint::spin_loop(); }
 }
@@ -41,14 +50,121 @@ pub enum ProcessError {
     TransferCapError(#[from] AserCloneCapsError),
 }
 
-pub struct Child {}
+/// Describes how a child process exited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitStatus(ExitReason);
+
+impl ExitStatus {
+    pub fn reason(&self) -> ExitReason {
+        self.0
+    }
+
+    /// Returns true if the process exited with an exit code of 0
+    pub fn success(&self) -> bool {
+        self.0 == ExitReason::Code(0)
+    }
+
+    /// The exit code of the process, or None if it was killed or faulted
+    pub fn code(&self) -> Option<i32> {
+        match self.0 {
+            ExitReason::Code(code) => Some(code),
+            _ => None,
+        }
+    }
+}
+
+impl From<ExitReason> for ExitStatus {
+    fn from(reason: ExitReason) -> Self {
+        ExitStatus(reason)
+    }
+}
+
+impl Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            ExitReason::Code(code) => write!(f, "exit code: {code}"),
+            ExitReason::Killed => write!(f, "killed"),
+            ExitReason::Fault(FaultKind::StackOverflow) => write!(f, "stack overflow"),
+            ExitReason::Fault(FaultKind::PageFault) => write!(f, "page fault"),
+        }
+    }
+}
+
+pub struct Child {
+    thread_group: ThreadGroup,
+    main_thread: Thread,
+}
+
+impl Child {
+    /// Waits for the child process to exit, and returns how it exited
+    /// 
+    /// This returns once the main thread of the child has exited, which is normally when the whole process exits
+    pub fn wait(&self) -> KResult<ExitStatus> {
+        self.wait_inner(None)
+    }
+
+    /// Same as [`wait`](Self::wait), but fails with [`SysErr::OkTimeout`] if the child has not exited
+    /// by `deadline` nanoseconds after boot
+    pub fn wait_until(&self, deadline: u64) -> KResult<ExitStatus> {
+        self.wait_inner(Some(deadline))
+    }
+
+    fn wait_inner(&self, deadline: Option<u64>) -> KResult<ExitStatus> {
+        let reason = self.main_thread.handle_thread_exit_sync(deadline)?
+            .reason()
+            .ok_or(SysErr::Unknown)?;
+
+        Ok(ExitStatus(reason))
+    }
+
+    /// Registers of the first thread in the child which was killed by a fault, or None if no thread has faulted
+    pub fn fault_info(&self) -> KResult<Option<FaultRecord>> {
+        self.thread_group.fault_info()
+    }
+
+    /// Kills every thread in the child, its main thread reports an exit code of `exit_code`
+    pub fn kill(&self, exit_code: i32) -> KResult<()> {
+        self.thread_group.exit(exit_code)
+    }
+}
+
+/// Syscall tracing to enable in a spawned process
+#[derive(Debug, Clone, Copy)]
+pub struct StraceOptions {
+    /// Bit n is set if syscall number n should be traced
+    pub syscall_mask: u128,
+}
 
-pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child, ProcessError> {
+/// Spawns a new process running the elf executable `exe_data`
+/// 
+/// `process_name` is printed by the kernel with traced syscalls, and by the process before each line of debug output.
+/// `env_data` is the serialized map of environment variables the process will see, it may be empty.
+/// If `fault_capture` is set, the stack of a thread in the process which faults is copied to it.
+pub fn spawn_process(
+    exe_data: &[u8],
+    process_name: &str,
+    namespace_data: &mut [u8],
+    env_data: &[u8],
+    strace: Option<StraceOptions>,
+    memory_limit: Option<Size>,
+    fault_capture: Option<&Memory>,
+) -> Result<Child, ProcessError> {
     let aslr_seed = gen_aslr_seed();
 
-    let allocator = &this_context().allocator;
+    // a process with a memory limit gets its own child allocator, so everything created for it is charged against the limit
+    let limited_allocator = memory_limit
+        .map(|limit| this_context().allocator.new_child(CapFlags::all(), limit.bytes()))
+        .transpose()?;
+    let allocator = limited_allocator.as_ref().unwrap_or(&this_context().allocator);
 
     let thread_group = this_context().thread_group.new_child_group(allocator)?;
+    // enable strace before the main thread is created so its first syscall is traced
+    if let Some(strace) = strace {
+        thread_group.set_strace(true, strace.syscall_mask, process_name)?;
+    }
+    if let Some(memory) = fault_capture {
+        thread_group.set_fault_capture(memory)?;
+    }
     let address_space = AddressSpace::new(allocator)?;
 
     let mut manager = RemoteAddrSpaceManager::new_remote(aslr_seed, allocator, &address_space)?;
@@ -56,8 +172,13 @@ pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child
     let elf_data = ElfBytes::<NativeEndian>::minimal_parse(exe_data)?;
     let rip = elf_data.ehdr.e_entry as usize;
 
+    // the tls image is part of a loaded segment, so the new process only needs to be told where it is
+    let mut tls_segment = None;
+
     for phdr in elf_data.segments().ok_or(ProcessError::NoElfSegments)?.iter() {
-        if phdr.p_type == PT_LOAD {
+        if phdr.p_type == PT_TLS {
+            tls_segment = Some(phdr);
+        } else if phdr.p_type == PT_LOAD {
             let map_options = elf_flags_to_memory_mapping_options(phdr.p_flags);
 
             let start_addr = phdr.p_vaddr as usize;
@@ -103,6 +224,13 @@ pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child
             unsafe {
                 core::ptr::write_bytes(padding_ptr, 0, pading_size);
             }
+
+            // read only and executable segments can't be changed once they are loaded, not even by this process
+            if !map_options.write {
+                if let Some(memory) = manager.get_mapping_target(section_mapping.remote_address)?.memory() {
+                    memory.seal()?;
+                }
+            }
         }
     }
 
@@ -117,6 +245,7 @@ pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child
         },
         padding: RegionPadding {
             start: DEFAULT_STACK_PADDING,
+            start_guard: true,
             ..Default::default()
         },
         ..Default::default()
@@ -126,7 +255,8 @@ pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child
 
     let startup_data_size = calc_process_startup_data_size(
         &manager,
-        namespace_data.len()
+        namespace_data.len(),
+        env_data.len(),
     );
 
     // map startup data memory in new process and current process
@@ -160,7 +290,8 @@ pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child
     let capability_space_id = cap_clone(dst_cspace, CspaceTarget::Current, &cspace, CapFlags::all())?
         .into_cap_id()
         .into();
-    let allocator_id = cap_clone(dst_cspace, CspaceTarget::Current, allocator, CapFlags::all())?
+    // the child doesn't get write permissions on its allocator, so it can't raise its own memory limit
+    let allocator_id = cap_clone(dst_cspace, CspaceTarget::Current, allocator, CapFlags::READ | CapFlags::PROD)?
         .into_cap_id()
         .into();
     let main_thread_id = cap_clone(dst_cspace, CspaceTarget::Current, &thread, CapFlags::all())?
@@ -168,7 +299,9 @@ pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child
         .into();
     aser::clone_caps_to_cspace(dst_cspace, namespace_data)?;
 
-    let process_init_data = ProcessInitData {
+    let mut process_init_data = ProcessInitData {
+        magic: PROCESS_INIT_DATA_MAGIC,
+        version: PROCESS_INIT_DATA_VERSION,
         thread_group_id,
         address_space_id,
         capability_space_id,
@@ -176,7 +309,9 @@ pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child
         main_thread_id,
         stack_region_start_address: stack.remote_address,
         aslr_seed,
+        ..ProcessInitData::zeroed()
     };
+    process_init_data.set_process_name(process_name);
 
     // create startup data bytes
     let mut startup_data = Vec::new();
@@ -197,6 +332,7 @@ pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child
                 map_size: mapping.size.bytes(),
                 padding_start: mapping.padding.start.bytes(),
                 padding_end: mapping.padding.end.bytes(),
+                padding_start_guard: mapping.padding.start_guard as usize,
             };
 
             startup_data.extend_from_slice(bytes_of(&memory_entry));
@@ -205,6 +341,7 @@ pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child
 
     let init_data_len = startup_data.len();
     startup_data.extend_from_slice(&namespace_data);
+    startup_data.extend_from_slice(env_data);
 
 
     // write startup data to memory in new process
@@ -218,13 +355,24 @@ pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child
 
 
     // put pointers to startup data on new stack
-    let stack_info = StackInfo {
+    let namespace_data_address = startup_data_mapping.remote_address + init_data_len;
+    let mut stack_info = StackInfo {
         process_data_address: startup_data_mapping.remote_address,
         process_data_size: init_data_len,
-        namespace_data_address: startup_data_mapping.remote_address + init_data_len,
+        namespace_data_address,
         namespace_data_size: namespace_data.len(),
+        env_data_address: namespace_data_address + namespace_data.len(),
+        env_data_size: env_data.len(),
+        ..StackInfo::zeroed()
     };
 
+    if let Some(tls_segment) = tls_segment {
+        stack_info.tls_image_address = tls_segment.p_vaddr as usize;
+        stack_info.tls_image_size = tls_segment.p_filesz as usize;
+        stack_info.tls_block_size = tls_segment.p_memsz as usize;
+        stack_info.tls_block_align = tls_segment.p_align as usize;
+    }
+
     let local_rsp = stack.local_address.unwrap() + stack.size.bytes() - size_of::<StackInfo>();
     unsafe {
         core::ptr::write(local_rsp as *mut StackInfo, stack_info);
@@ -232,12 +380,21 @@ pub fn spawn_process(exe_data: &[u8], namespace_data: &mut [u8]) -> Result<Child
 
     thread.resume()?;
 
-    Ok(Child {})
+    Ok(Child {
+        thread_group,
+        main_thread: thread,
+    })
 }
 
 fn gen_aslr_seed() -> [u8; 32] {
-    // TODO: implement once randomness is a thing
-    [12, 64, 89, 134, 11, 235, 123, 98, 12, 31, 2, 90, 38, 24, 3, 49, 32, 58, 238, 210, 1, 0, 24, 23, 9, 48, 28, 65, 1, 43, 54, 55]
+    let mut seed = [0; 32];
+
+    for chunk in seed.chunks_mut(SYSTEM_ENTROPY_SIZE) {
+        let entropy = system_entropy().expect("failed to get entropy for aslr seed");
+        chunk.copy_from_slice(&entropy[..chunk.len()]);
+    }
+
+    seed
 }
 
 fn elf_flags_to_memory_mapping_options(elf_flags: u32) -> MemoryMappingOptions {
@@ -250,11 +407,12 @@ fn elf_flags_to_memory_mapping_options(elf_flags: u32) -> MemoryMappingOptions {
 }
 
 /// Calculates the size of the memory we need to allocate to hold all the startup data
-fn calc_process_startup_data_size(addr_space_manager: &RemoteAddrSpaceManager, namespace_data_len: usize) -> Size {
+fn calc_process_startup_data_size(addr_space_manager: &RemoteAddrSpaceManager, namespace_data_len: usize, env_data_len: usize) -> Size {
     let size = size_of::<ProcessInitData>()
         // + 1 for the memory we will have to allocate to hold startup data
         + (addr_space_manager.memory_regions.len() + 1) * size_of::<ProcessMemoryEntry>()
-        + namespace_data_len;
+        + namespace_data_len
+        + env_data_len;
     
     Size::from_bytes(size)
 }
\ No newline at end of file
@@ -0,0 +1,244 @@
+//! Parsing of the process data block a program is passed on startup
+//!
+//! The block is written by whoever spawned the process, so nothing in it is trusted until it is validated here.
+
+use core::mem::size_of;
+
+use bit_utils::{PAGE_SIZE, KERNEL_RESERVED_START};
+use bytemuck::{from_bytes, cast_slice};
+use sys::{ProcessInitData, ProcessMemoryEntry, PROCESS_INIT_DATA_MAGIC, PROCESS_INIT_DATA_VERSION};
+use thiserror_no_std::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum ProcessDataError {
+    #[error("Process data is {0} bytes, which is too small to hold the process init data")]
+    TooSmall(usize),
+    #[error("Process data has {0} bytes after the last memory entry which do not form a whole entry")]
+    TrailingBytes(usize),
+    #[error("Process data has magic number {0:#x} instead of {PROCESS_INIT_DATA_MAGIC:#x}, the parent did not pass aurora process data")]
+    InvalidMagic(usize),
+    #[error("Process data is version {0}, but this program only understands version {PROCESS_INIT_DATA_VERSION}")]
+    VersionMismatch(usize),
+    #[error("Memory entry {index} has map address {map_address:#x} and map size {map_size:#x}, which are not page aligned")]
+    UnalignedMemoryEntry {
+        index: usize,
+        map_address: usize,
+        map_size: usize,
+    },
+    #[error("Memory entry {index} at address {map_address:#x} overflows when its size and padding are added")]
+    MemoryEntryOverflow {
+        index: usize,
+        map_address: usize,
+    },
+    #[error("Memory entry {index} at address {map_address:#x} extends into memory reserved for the kernel")]
+    MemoryEntryInKernelRegion {
+        index: usize,
+        map_address: usize,
+    },
+}
+
+/// Checks that the memory entry and its padding lie in userspace memory
+fn validate_memory_entry(index: usize, entry: &ProcessMemoryEntry) -> Result<(), ProcessDataError> {
+    let ProcessMemoryEntry {
+        map_address,
+        map_size,
+        padding_start,
+        padding_end,
+        ..
+    } = *entry;
+
+    if map_address % PAGE_SIZE != 0 || map_size % PAGE_SIZE != 0 {
+        return Err(ProcessDataError::UnalignedMemoryEntry {
+            index,
+            map_address,
+            map_size,
+        });
+    }
+
+    let overflow = || ProcessDataError::MemoryEntryOverflow { index, map_address };
+    map_address.checked_sub(padding_start).ok_or_else(overflow)?;
+    let end = map_address.checked_add(map_size)
+        .and_then(|end| end.checked_add(padding_end))
+        .ok_or_else(overflow)?;
+
+    if end > KERNEL_RESERVED_START {
+        return Err(ProcessDataError::MemoryEntryInKernelRegion {
+            index,
+            map_address,
+        });
+    }
+
+    Ok(())
+}
+
+/// Converts the raw block of memory passed into a program on startup into the process init data
+///
+/// The block must be exactly the init data followed by a whole number of memory entries,
+/// and every memory entry must be page aligned and lie below the kernel's reserved region.
+pub fn process_data_from_slice(data: &[u8]) -> Result<(ProcessInitData, &[ProcessMemoryEntry]), ProcessDataError> {
+    if data.len() < size_of::<ProcessInitData>() {
+        return Err(ProcessDataError::TooSmall(data.len()));
+    }
+
+    let (init_data, entry_data) = data.split_at(size_of::<ProcessInitData>());
+
+    let trailing_bytes = entry_data.len() % size_of::<ProcessMemoryEntry>();
+    if trailing_bytes != 0 {
+        return Err(ProcessDataError::TrailingBytes(trailing_bytes));
+    }
+
+    // these structs are packed, so the casts can't fail now that the sizes are checked
+    let process_init_data: ProcessInitData = *from_bytes(init_data);
+    let memory_entries: &[ProcessMemoryEntry] = cast_slice(entry_data);
+
+    let ProcessInitData { magic, version, .. } = process_init_data;
+    if magic != PROCESS_INIT_DATA_MAGIC {
+        return Err(ProcessDataError::InvalidMagic(magic));
+    }
+
+    if version != PROCESS_INIT_DATA_VERSION {
+        return Err(ProcessDataError::VersionMismatch(version));
+    }
+
+    for (index, entry) in memory_entries.iter().enumerate() {
+        validate_memory_entry(index, entry)?;
+    }
+
+    Ok((process_init_data, memory_entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use bytemuck::{bytes_of, Zeroable};
+
+    use super::*;
+
+    fn init_data() -> ProcessInitData {
+        ProcessInitData {
+            magic: PROCESS_INIT_DATA_MAGIC,
+            version: PROCESS_INIT_DATA_VERSION,
+            ..ProcessInitData::zeroed()
+        }
+    }
+
+    fn memory_entry(map_address: usize, map_size: usize) -> ProcessMemoryEntry {
+        ProcessMemoryEntry {
+            map_address,
+            map_size,
+            memory_size: map_size,
+            ..ProcessMemoryEntry::zeroed()
+        }
+    }
+
+    fn process_data(init_data: ProcessInitData, memory_entries: &[ProcessMemoryEntry]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(bytes_of(&init_data));
+        for entry in memory_entries {
+            data.extend_from_slice(bytes_of(entry));
+        }
+
+        data
+    }
+
+    fn parse_error(data: &[u8]) -> ProcessDataError {
+        process_data_from_slice(data).unwrap_err()
+    }
+
+    #[test]
+    fn valid_process_data() {
+        let entries = [memory_entry(0x1000, 0x2000), memory_entry(KERNEL_RESERVED_START - 0x1000, 0x1000)];
+        let data = process_data(init_data(), &entries);
+
+        let (_, memory_entries) = process_data_from_slice(&data).unwrap();
+        assert_eq!(memory_entries.len(), 2);
+        assert_eq!({ memory_entries[1].map_address }, KERNEL_RESERVED_START - 0x1000);
+
+        let data = process_data(init_data(), &[]);
+        assert!(process_data_from_slice(&data).unwrap().1.is_empty());
+    }
+
+    #[test]
+    fn truncated_process_data() {
+        let data = process_data(init_data(), &[memory_entry(0x1000, 0x1000)]);
+
+        assert_eq!(parse_error(&[]), ProcessDataError::TooSmall(0));
+        assert_eq!(
+            parse_error(&data[..size_of::<ProcessInitData>() - 1]),
+            ProcessDataError::TooSmall(size_of::<ProcessInitData>() - 1),
+        );
+        assert_eq!(parse_error(&data[..data.len() - 8]), ProcessDataError::TrailingBytes(size_of::<ProcessMemoryEntry>() - 8));
+        assert_eq!(parse_error(&[data.as_slice(), &[0]].concat()), ProcessDataError::TrailingBytes(1));
+    }
+
+    #[test]
+    fn wrong_protocol() {
+        let mut bad_magic = init_data();
+        bad_magic.magic = 0x1234;
+        assert_eq!(parse_error(&process_data(bad_magic, &[])), ProcessDataError::InvalidMagic(0x1234));
+
+        // data from before the magic number was added starts with the thread group id
+        assert_eq!(parse_error(&[0; size_of::<ProcessInitData>()]), ProcessDataError::InvalidMagic(0));
+
+        let mut new_version = init_data();
+        new_version.version = PROCESS_INIT_DATA_VERSION + 1;
+        assert_eq!(
+            parse_error(&process_data(new_version, &[])),
+            ProcessDataError::VersionMismatch(PROCESS_INIT_DATA_VERSION + 1),
+        );
+    }
+
+    #[test]
+    fn unaligned_memory_entry() {
+        let data = process_data(init_data(), &[memory_entry(0x1000, 0x1000), memory_entry(0x3001, 0x1000)]);
+        assert_eq!(parse_error(&data), ProcessDataError::UnalignedMemoryEntry {
+            index: 1,
+            map_address: 0x3001,
+            map_size: 0x1000,
+        });
+
+        let data = process_data(init_data(), &[memory_entry(0x1000, 0x800)]);
+        assert_eq!(parse_error(&data), ProcessDataError::UnalignedMemoryEntry {
+            index: 0,
+            map_address: 0x1000,
+            map_size: 0x800,
+        });
+    }
+
+    #[test]
+    fn overflowing_memory_entry() {
+        let overflow = ProcessDataError::MemoryEntryOverflow {
+            index: 0,
+            map_address: 0x1000,
+        };
+
+        let mut entry = memory_entry(0x1000, 0x1000);
+        entry.padding_start = 0x2000;
+        assert_eq!(parse_error(&process_data(init_data(), &[entry])), overflow);
+
+        let mut entry = memory_entry(0x1000, 0x1000);
+        entry.padding_end = usize::MAX - 0x1000;
+        assert_eq!(parse_error(&process_data(init_data(), &[entry])), overflow);
+
+        let entry = memory_entry(0x1000, usize::MAX & !(PAGE_SIZE - 1));
+        assert_eq!(parse_error(&process_data(init_data(), &[entry])), overflow);
+    }
+
+    #[test]
+    fn memory_entry_in_kernel_region() {
+        let entry = memory_entry(KERNEL_RESERVED_START, 0x1000);
+        assert_eq!(parse_error(&process_data(init_data(), &[entry])), ProcessDataError::MemoryEntryInKernelRegion {
+            index: 0,
+            map_address: KERNEL_RESERVED_START,
+        });
+
+        // the end padding is reserved in the address space too, so it can't reach the kernel region either
+        let mut entry = memory_entry(KERNEL_RESERVED_START - 0x1000, 0x1000);
+        entry.padding_end = 0x1000;
+        assert_eq!(parse_error(&process_data(init_data(), &[entry])), ProcessDataError::MemoryEntryInKernelRegion {
+            index: 0,
+            map_address: KERNEL_RESERVED_START - 0x1000,
+        });
+    }
+}
\ No newline at end of file
@@ -0,0 +1,56 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use sys::{futex_wait, futex_wake};
+
+use super::MutexGuard;
+
+/// A condition variable which blocks threads on a futex until they are notified
+#[derive(Debug, Default)]
+pub struct Condvar {
+    /// Incremented on every notify, so a notify between unlocking the mutex and waiting is not missed
+    sequence: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            sequence: AtomicU32::new(0),
+        }
+    }
+
+    /// Unlocks the mutex held by `guard` and blocks until this condvar is notified, then locks the mutex again
+    /// 
+    /// Spurious wakeups can occur, so the condition should be checked in a loop, or use [`wait_while`](Self::wait_while)
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+
+        let mutex = guard.mutex;
+        drop(guard);
+
+        // errors are ignored, spurious wakeups are allowed
+        let _ = futex_wait(&self.sequence, sequence, None);
+
+        mutex.lock()
+    }
+
+    /// Blocks until `condition` returns false, the mutex held by `guard` is locked whenever `condition` is called
+    pub fn wait_while<'a, T: ?Sized, F: FnMut(&mut T) -> bool>(&self, mut guard: MutexGuard<'a, T>, mut condition: F) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+
+        guard
+    }
+
+    /// Wakes one thread waiting on this condvar
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        let _ = futex_wake(&self.sequence, 1);
+    }
+
+    /// Wakes every thread waiting on this condvar
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        let _ = futex_wake(&self.sequence, usize::MAX);
+    }
+}
\ No newline at end of file
@@ -1,9 +1,12 @@
 //! Synchronization primitives for aurora userspace
 
-// TODO: write the kernel lock implementation for futexes, for now just reexport spin locks
+mod condvar;
+pub use condvar::Condvar;
+mod mutex;
+pub use mutex::{Mutex, MutexGuard};
+
+// TODO: write futex based versions of these too
 pub use spin::{
-    Mutex,
-    MutexGuard,
     RwLock,
     RwLockReadGuard,
     RwLockWriteGuard,
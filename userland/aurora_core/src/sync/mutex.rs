@@ -0,0 +1,166 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use sys::{futex_wait, futex_wake};
+
+const UNLOCKED: u32 = 0;
+/// Locked, and no threads are waiting in the kernel
+const LOCKED: u32 = 1;
+/// Locked, and other threads may be waiting in the kernel, so unlocking must wake one of them
+const CONTENDED: u32 = 2;
+
+/// Number of times a contended lock is polled before waiting in the kernel
+const SPIN_COUNT: usize = 100;
+
+/// A mutual exclusion lock which spins briefly, then blocks on a futex while it is held by another thread
+pub struct Mutex<T: ?Sized> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Locks the mutex, blocking the current thread until it is available
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            self.lock_contended();
+        }
+
+        MutexGuard {
+            mutex: self,
+        }
+    }
+
+    /// Locks the mutex if it is not already locked
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard {
+                mutex: self,
+            })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != UNLOCKED
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    #[cold]
+    fn lock_contended(&self) {
+        let mut state = self.spin();
+
+        if state == UNLOCKED {
+            match self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(new_state) => state = new_state,
+            }
+        }
+
+        loop {
+            // once marked contended, the lock can't be taken as just locked,
+            // since that could leave other threads waiting after it is unlocked
+            if state != CONTENDED && self.state.swap(CONTENDED, Ordering::Acquire) == UNLOCKED {
+                return;
+            }
+
+            // errors are ignored, the state is checked again either way
+            let _ = futex_wait(&self.state, CONTENDED, None);
+
+            state = self.spin();
+        }
+    }
+
+    /// Polls the lock until it is unlocked or contended, or until the spin limit is reached
+    fn spin(&self) -> u32 {
+        let mut spin_count = SPIN_COUNT;
+
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state != LOCKED || spin_count == 0 {
+                return state;
+            }
+
+            core::hint::spin_loop();
+            spin_count -= 1;
+        }
+    }
+
+    /// # Safety
+    /// 
+    /// The mutex must be locked by the caller
+    unsafe fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            let _ = futex_wake(&self.state, 1);
+        }
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
+            None => f.write_str("Mutex { <locked> }"),
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized + 'a> {
+    pub(super) mutex: &'a Mutex<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // safety: the mutex is locked while the guard exists
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // safety: the mutex is locked while the guard exists
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // safety: the guard holds the lock
+        unsafe { self.mutex.unlock() }
+    }
+}
\ No newline at end of file
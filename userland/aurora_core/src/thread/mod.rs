@@ -1,17 +1,19 @@
 use core::arch::asm;
-use core::sync::atomic::{Ordering, AtomicU8, AtomicU64};
+use core::sync::atomic::{Ordering, AtomicU64};
 use core::mem::size_of;
 use core::ptr;
+use core::time::Duration;
 use alloc::{sync::Arc, string::String};
 
 use sys::syscall_nums::{ADDRESS_SPACE_UNMAP, THREAD_DESTROY};
-use sys::{CapId, Capability, Thread as SysThread, SysErr, MemoryMappingOptions};
+use sys::{CapId, Capability, Thread as SysThread, SysErr, MemoryMappingOptions, ExitReason, FaultKind, ThreadPriority, ClockId, time_get};
 
 mod thread_local_data;
-pub use thread_local_data::{LocalKey, ThreadLocalData};
+pub use thread_local_data::{LocalKey, ThreadLocalData, TlsTemplate};
+pub(crate) use thread_local_data::{set_tls_template, debug_line_buffer};
 
 use crate::prelude::*;
-use crate::allocator::addr_space::{MapMemoryArgs, MapMemoryResult};
+use crate::allocator::addr_space::{MapMemoryArgs, MapMemoryResult, RegionPadding};
 use crate::sync::Mutex;
 use crate::{process, addr_space, this_context};
 
@@ -25,7 +27,6 @@ pub struct ThreadId(CapId);
 struct ThreadInner {
     name: Option<String>,
     thread: SysThread,
-    park_status: AtomicU8,
     /// The address to the start of the stack memory region for this thread
     stack_region_address: usize,
 }
@@ -39,7 +40,6 @@ impl Thread {
         let inner = Arc::new(ThreadInner {
             name,
             thread: sys_thread,
-            park_status: AtomicU8::new(0),
             stack_region_address,
         });
 
@@ -55,6 +55,32 @@ impl Thread {
     pub fn name(&self) -> Option<&str> {
         self.0.name.as_deref()
     }
+
+    /// Gets the total time the thread has spent running
+    pub fn runtime(&self) -> Result<Duration, SysErr> {
+        self.0.thread.runtime()
+    }
+
+    /// Sets the thread's scheduling priority
+    /// 
+    /// Priorities above [`ThreadPriority::Normal`] require the `PROD` permission on the thread capability
+    pub fn set_priority(&self, priority: ThreadPriority) -> Result<(), SysErr> {
+        self.0.thread.set_priority(priority)
+    }
+
+    /// Sets which cpus the thread may run on, bit n of `mask` allows cpu n
+    pub fn set_affinity(&self, mask: u64) -> Result<(), SysErr> {
+        self.0.thread.set_affinity(mask)
+    }
+
+    /// Wakes the thread if it is blocked in [`park`], otherwise its next call to [`park`] returns immediately
+    pub fn unpark(&self) {
+        self.0.thread.unpark().expect("failed to unpark thread");
+    }
+
+    pub(crate) fn stack_region_address(&self) -> usize {
+        self.0.stack_region_address
+    }
 }
 
 /// Gets a handle to the thread that invokes it
@@ -72,6 +98,26 @@ pub fn yield_now() {
     sys::Thread::yield_current();
 }
 
+/// Blocks the calling thread until its [`Thread::unpark`] is called
+/// 
+/// If the thread was unparked since it last parked, this returns immediately.
+/// Like `std::thread::park`, this can return spuriously, so it should be called in a loop which checks for the condition being waited on.
+pub fn park() {
+    // the only error is a timeout, which is not used here
+    let _ = SysThread::park(None);
+}
+
+/// Blocks the calling thread until its [`Thread::unpark`] is called, or `timeout` has passed
+/// 
+/// Like [`park`], this can return spuriously
+pub fn park_timeout(timeout: Duration) {
+    let now = time_get(ClockId::Monotonic).expect("failed to read monotonic clock");
+    let deadline = now.saturating_add(timeout).as_nanos().min(u64::MAX as u128) as u64;
+
+    // ignore error, timing out is not distinguished from being unparked
+    let _ = SysThread::park(Some(deadline));
+}
+
 /// An owned permission to join on a thread (block on its termination)
 pub struct JoinHandle<T> {
     thread: Thread,
@@ -90,10 +136,18 @@ impl<T> JoinHandle<T> {
     /// Waits for the associated thread to finish
     /// 
     /// This function will return immediately if the associated thread has already finished
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if the thread overflowed its stack or died from a page fault
     pub fn join(self) -> T {
         match self.thread.0.thread.handle_thread_exit_sync(None) {
             // thread has exited
-            Ok(_) => (),
+            Ok(exit) => match exit.reason() {
+                Some(ExitReason::Fault(FaultKind::StackOverflow)) => panic!("stack overflow in thread {:?}", self.thread.id()),
+                Some(ExitReason::Fault(FaultKind::PageFault)) => panic!("page fault in thread {:?}", self.thread.id()),
+                _ => (),
+            },
             // the thread id was not valid, which at this point means the thread already exited
             // TODO: stop thread later being dropped, that is just an extra syscall for nothing
             Err(SysErr::InvlId) => (),
@@ -128,6 +182,11 @@ where
             write: true,
             ..Default::default()
         },
+        padding: RegionPadding {
+            start: process::DEFAULT_STACK_PADDING,
+            start_guard: true,
+            ..Default::default()
+        },
         ..Default::default()
     }).expect("failed to map new thread stack");
 
@@ -237,7 +296,7 @@ pub fn exit() -> ! {
 /// Will not exit the thread group, even if this is the last thread
 pub fn exit_thread_only() -> ! {
     // this is a thread local variable, must call before deallocating thread local data
-    let stack_address = current().0.stack_region_address;
+    let stack_address = current().stack_region_address();
 
     let transient_pointer = addr_space().unmap_transient(stack_address)
         .expect("failed to transiently unmap stack address")
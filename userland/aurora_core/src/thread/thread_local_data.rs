@@ -1,12 +1,95 @@
+use core::alloc::Layout;
 use core::cell::{Cell, RefCell};
 use core::sync::atomic::{AtomicUsize, Ordering};
 use core::arch::asm;
-use alloc::boxed::Box;
+use core::mem::{size_of, align_of};
+use core::ptr;
+use alloc::alloc::{alloc_zeroed, dealloc, handle_alloc_error};
 use alloc::rc::Rc;
 use alloc::vec::Vec;
 use core::any::Any;
 
+use bit_utils::align_up;
+use sys::DebugLineBuffer;
+
 use super::Thread;
+use crate::InitError;
+use crate::sync::Once;
+
+/// Initial contents of the tls block every thread gets, described by the `PT_TLS` segment of the executable
+/// 
+/// Code using `#[thread_local]` statics accesses them at negative offsets from the thread pointer (tls variant II),
+/// so the tls block is placed directly below [`ThreadLocalData`], which the fs register points to.
+#[derive(Debug, Clone, Copy)]
+pub struct TlsTemplate {
+    /// Initialized part of the block (`.tdata`), the rest of the block (`.tbss`) is zeroed
+    image: &'static [u8],
+    block_size: usize,
+    align: usize,
+}
+
+impl TlsTemplate {
+    /// Creates a tls template from the fields of the `PT_TLS` segment passed to the program on startup
+    /// 
+    /// Returns `Ok(None)` if `block_size` is 0, which means the executable has no thread locals.
+    /// 
+    /// # Safety
+    /// 
+    /// `image_address` must point to `image_size` bytes which are not modified or unmapped for the rest of the program
+    pub unsafe fn from_raw_parts(image_address: usize, image_size: usize, block_size: usize, align: usize) -> Result<Option<Self>, InitError> {
+        if block_size == 0 {
+            return Ok(None);
+        }
+
+        // elf uses 0 and 1 to mean no alignment
+        let align = align.max(1);
+        // the layout checks that align is a power of 2 and the aligned block size does not overflow
+        if image_size > block_size || Layout::from_size_align(block_size, align).is_err() {
+            return Err(InitError::InvalidTlsTemplate);
+        }
+
+        let image = unsafe {
+            core::slice::from_raw_parts(image_address as *const u8, image_size)
+        };
+
+        Ok(Some(TlsTemplate {
+            image,
+            block_size,
+            align,
+        }))
+    }
+
+    /// Distance from the thread pointer to the start of the tls block, this is the same offset the linker uses
+    fn tls_offset(&self) -> usize {
+        align_up(self.block_size, self.align)
+    }
+}
+
+static TLS_TEMPLATE: Once<TlsTemplate> = Once::new();
+
+/// Sets the template every thread's tls block is initialized from
+/// 
+/// This must be called before thread local data is initialized for the first thread.
+pub(crate) fn set_tls_template(template: TlsTemplate) {
+    TLS_TEMPLATE.call_once(|| template);
+}
+
+/// Returns the layout of the allocation holding a thread's tls block and [`ThreadLocalData`],
+/// and the offset of the thread local data in that allocation
+fn local_data_layout() -> (Layout, usize) {
+    let Some(template) = TLS_TEMPLATE.get() else {
+        return (Layout::new::<ThreadLocalData>(), 0);
+    };
+
+    // the thread pointer must be aligned for both the tls block and the thread local data
+    let align = template.align.max(align_of::<ThreadLocalData>());
+    let local_data_offset = align_up(template.tls_offset(), align);
+
+    // panic safety: the block size and alignment were checked to form a valid layout when the template was created
+    let layout = Layout::from_size_align(local_data_offset + size_of::<ThreadLocalData>(), align).unwrap();
+
+    (layout, local_data_offset)
+}
 
 /// Stores all thread local variables
 #[repr(C)]
@@ -14,23 +97,43 @@ pub struct ThreadLocalData {
     self_addr: AtomicUsize,
     pub(super) thread: Thread,
     currently_dropping: Cell<bool>,
+    /// Collects this thread's debug output until a whole line is written
+    debug_line: RefCell<DebugLineBuffer>,
     // TODO: find a faster way to do this, this might be a bit slow
     data: RefCell<Vec<Option<Rc<dyn Any>>>>,
 }
 
 impl ThreadLocalData {
-    /// Initializes thread local data for the current thread
+    /// Initializes thread local data and the tls block for the current thread
     pub fn init(thread: Thread) {
-        let data = Box::new(ThreadLocalData {
-            self_addr: AtomicUsize::new(0),
-            thread,
-            currently_dropping: Cell::new(false),
-            data: RefCell::new(Vec::new()),
-        });
+        let (layout, local_data_offset) = local_data_layout();
+
+        // the allocation is zeroed so the `.tbss` part of the tls block starts out zeroed
+        let allocation = unsafe { alloc_zeroed(layout) };
+        if allocation.is_null() {
+            handle_alloc_error(layout);
+        }
+
+        if let Some(template) = TLS_TEMPLATE.get() {
+            // safety: local_data_layout reserves tls_offset bytes below the local data
+            unsafe {
+                let tls_block = allocation.add(local_data_offset - template.tls_offset());
+                ptr::copy_nonoverlapping(template.image.as_ptr(), tls_block, template.image.len());
+            }
+        }
 
-        let data = Box::leak(data);
-        let local_data_addr = data as *const ThreadLocalData as usize;
-        data.self_addr.store(local_data_addr, Ordering::Relaxed);
+        let local_data_addr = allocation as usize + local_data_offset;
+        // safety: the allocation has space for the local data at this offset, and it is aligned
+        unsafe {
+            ptr::write(local_data_addr as *mut ThreadLocalData, ThreadLocalData {
+                // this must be the first field, the abi requires that fs:0 holds the thread pointer
+                self_addr: AtomicUsize::new(local_data_addr),
+                thread,
+                currently_dropping: Cell::new(false),
+                debug_line: RefCell::new(DebugLineBuffer::new()),
+                data: RefCell::new(Vec::new()),
+            });
+        }
 
         sys::Thread::set_local_pointer(local_data_addr);
     }
@@ -54,8 +157,12 @@ impl ThreadLocalData {
     /// 
     /// local data must have been initialized
     pub unsafe fn dealloc() {
+        let (layout, local_data_offset) = local_data_layout();
+
         unsafe {
-            drop(Box::from_raw(Self::get() as *mut Self));
+            let local_data = Self::get() as *mut Self;
+            ptr::drop_in_place(local_data);
+            dealloc((local_data as *mut u8).sub(local_data_offset), layout);
         }
     }
 
@@ -110,6 +217,19 @@ impl Drop for ThreadLocalData {
         // technically at this point it is undefined behavior if a destructor references the thread local data,
         // but this will hopefully cause a panic instead of a segfault
         self.currently_dropping.set(true);
+
+        // drop the thread locals here instead of after this function, so anything they print is flushed
+        self.data.get_mut().clear();
+        self.debug_line.get_mut().flush();
+    }
+}
+
+/// Returns the debug line buffer of the current thread, registered with [`sys::set_thread_line_buffer_getter`]
+pub(crate) fn debug_line_buffer() -> Option<&'static RefCell<DebugLineBuffer>> {
+    // safety: the getter is only registered once the main thread's local data is initialized,
+    // and other threads initialize their local data before running any code that prints
+    unsafe {
+        Some(&(*ThreadLocalData::get()).debug_line)
     }
 }
 
@@ -0,0 +1,201 @@
+//! Support for userland test binaries run by `test-runner`
+//!
+//! A test binary lists its tests with [`tests`], which puts them in a static slice, and passes them to [`run_tests`] from `main`.
+//! The tests are run in order, and a [`TestReport`] for each one is sent over the channel passed in the
+//! [`TEST_CHANNEL_ARG`] named argument, followed by [`TestReport::Finished`].
+//! A panic ends the whole binary, so the runner counts a binary which exits before finishing as failed.
+
+#![no_std]
+
+extern crate alloc;
+
+use core::fmt;
+use core::time::Duration;
+
+use alloc::format;
+
+use serde::{Serialize, Deserialize};
+use aurora::prelude::*;
+use aurora::collections::MessageVec;
+use aurora::env;
+use aurora::process;
+use aurora::time::Instant;
+use sys::{Channel, ChannelSendFlags, KResult};
+
+/// Files in the initrd with names starting with this are test binaries, which the test runner runs
+pub const TEST_BINARY_PREFIX: &str = "test-";
+
+/// Named argument holding the channel test reports are sent on
+pub const TEST_CHANNEL_ARG: &str = "test_channel";
+
+/// Size of the buffer reports are recieved into, a serialized report is never bigger than this
+pub const REPORT_BUFFER_SIZE: usize = 1024;
+
+/// Test names and failure messages are truncated to this length so a report always fits in [`REPORT_BUFFER_SIZE`]
+const REPORT_STRING_MAX_LEN: usize = 384;
+
+/// How long to wait for the runner to recieve a report
+const REPORT_SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returned by a test, the error describes why the test failed
+pub type TestResult = Result<(), String>;
+
+/// A test function registered with [`tests`]
+#[derive(Debug, Clone, Copy)]
+pub struct Test {
+    pub name: &'static str,
+    pub run: fn() -> TestResult,
+}
+
+/// The result of running one test
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestOutcome {
+    pub name: String,
+    /// Why the test failed, or `None` if it passed
+    pub failure: Option<String>,
+}
+
+/// Message sent from a test binary to the test runner
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TestReport {
+    Outcome(TestOutcome),
+    /// Sent once every test has run, the binary exits right after this
+    Finished,
+}
+
+fn truncated(string: &str) -> String {
+    if string.len() <= REPORT_STRING_MAX_LEN {
+        return string.to_owned();
+    }
+
+    let mut end = REPORT_STRING_MAX_LEN;
+    while !string.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    string[..end].to_owned()
+}
+
+fn send_report(channel: &Channel, report: &TestReport) -> KResult<()> {
+    // panic safety: reports hold no capabilities, and their strings are short enough to always serialize
+    let report_data: Vec<u8> = aser::to_bytes(report, 0).expect("failed to serialize test report");
+    let message = MessageVec::try_from_slice(&report_data)?;
+    let deadline = Instant::now() + REPORT_SEND_TIMEOUT;
+
+    // panic safety: a serialized report is never empty, so it has a message buffer
+    channel.sync_send(&message.message_buffer().unwrap(), Some(deadline.as_boot_time().as_nanos() as u64), ChannelSendFlags::empty())?;
+
+    Ok(())
+}
+
+/// Runs every test in `tests`, reports the results to the test runner, and exits
+///
+/// The process exits with a code of 0 if every test passed, and 1 otherwise.
+/// Results are only printed if this binary was not started by the test runner.
+pub fn run_tests(tests: &[Test]) -> ! {
+    let channel: Option<Channel> = env::args().named_arg(TEST_CHANNEL_ARG).ok();
+
+    let mut failed = 0;
+    for test in tests {
+        dprintln!("test {} ...", test.name);
+
+        let failure = (test.run)().err();
+        match &failure {
+            None => dprintln!("test {} ... ok", test.name),
+            Some(message) => {
+                failed += 1;
+                dprintln!("test {} ... FAILED: {}", test.name, message);
+            },
+        }
+
+        if let Some(channel) = &channel {
+            let outcome = TestOutcome {
+                name: truncated(test.name),
+                failure: failure.as_deref().map(truncated),
+            };
+
+            send_report(channel, &TestReport::Outcome(outcome))
+                .unwrap_or_else(|error| panic!("failed to send test report: {error}"));
+        }
+    }
+
+    if let Some(channel) = &channel {
+        send_report(channel, &TestReport::Finished)
+            .unwrap_or_else(|error| panic!("failed to send test report: {error}"));
+    }
+
+    process::exit_with_code(if failed == 0 { 0 } else { 1 });
+}
+
+/// Called by [`test_assert`] and friends to describe a failed check
+#[doc(hidden)]
+pub fn failure_message(file: &str, line: u32, message: fmt::Arguments) -> String {
+    format!("check failed at {file}:{line}: {message}")
+}
+
+/// Registers the listed test functions in a static slice called `TESTS`, to be passed to [`run_tests`]
+///
+/// Each test is a `fn() -> TestResult`, and is reported under its path as written in the list.
+///
+/// ```ignore
+/// aurora_test::tests! {
+///     echo_round_trip,
+///     exit_code_is_reported,
+/// }
+///
+/// fn main() {
+///     aurora_test::run_tests(TESTS);
+/// }
+/// ```
+#[macro_export]
+macro_rules! tests {
+    ($($test:path),* $(,)?) => {
+        static TESTS: &[$crate::Test] = &[
+            $($crate::Test {
+                name: core::stringify!($test),
+                run: $test,
+            }),*
+        ];
+    };
+}
+
+/// Checks that a condition is true, returning a failure from the test if it is not
+#[macro_export]
+macro_rules! test_assert {
+    ($cond:expr $(,)?) => {
+        $crate::test_assert!($cond, "{}", core::stringify!($cond))
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err($crate::failure_message(core::file!(), core::line!(), core::format_args!($($arg)+)));
+        }
+    };
+}
+
+/// Checks that two values are equal, returning a failure from the test if they are not
+#[macro_export]
+macro_rules! test_assert_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => $crate::test_assert!(
+                *left == *right,
+                "`{}` == `{}`, left: {:?}, right: {:?}",
+                core::stringify!($left),
+                core::stringify!($right),
+                left,
+                right,
+            ),
+        }
+    };
+    ($left:expr, $right:expr, $($arg:tt)+) => {
+        match (&$left, &$right) {
+            (left, right) => $crate::test_assert!(
+                *left == *right,
+                "{}, left: {:?}, right: {:?}",
+                core::format_args!($($arg)+),
+                left,
+                right,
+            ),
+        }
+    };
+}
\ No newline at end of file
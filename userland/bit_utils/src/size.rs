@@ -67,4 +67,64 @@ impl Size {
     pub fn is_page_aligned(self) -> bool {
         page_aligned(self.0)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_are_converted_to_bytes() {
+        assert_eq!(Size::from_pages(3).bytes(), 3 * PAGE_SIZE);
+        assert_eq!(Size::from_pages(3).pages(), Some(3));
+        assert_eq!(Size::try_from_pages(usize::MAX), None);
+    }
+
+    #[test]
+    fn unaligned_sizes_round_up() {
+        let size = Size::from_bytes(PAGE_SIZE + 1);
+
+        assert!(!size.is_page_aligned());
+        assert_eq!(size.pages(), None);
+        assert_eq!(size.pages_rounded(), 2);
+        assert_eq!(size.as_aligned(), Size::from_pages(2));
+    }
+
+    #[test]
+    fn zero_size_is_aligned() {
+        assert!(Size::zero().is_zero());
+        assert!(Size::zero().is_page_aligned());
+        assert_eq!(Size::zero().pages(), Some(0));
+    }
+
+    #[cfg(feature = "std")]
+    mod properties {
+        use proptest::prelude::*;
+
+        use super::*;
+
+        /// Largest size which can be rounded up to a page without overflowing
+        const MAX_ALIGNABLE: usize = usize::MAX - PAGE_SIZE + 1;
+
+        proptest! {
+            #[test]
+            fn pages_round_trip(pages in 0..=(usize::MAX / PAGE_SIZE)) {
+                let size = Size::from_pages(pages);
+
+                prop_assert!(size.is_page_aligned());
+                prop_assert_eq!(size.pages(), Some(pages));
+                prop_assert_eq!(size.pages_rounded(), pages);
+            }
+
+            #[test]
+            fn aligned_size_is_smallest_covering_page_multiple(bytes in 0..=MAX_ALIGNABLE) {
+                let aligned = Size::from_bytes(bytes).as_aligned();
+
+                prop_assert!(aligned.is_page_aligned());
+                prop_assert!(aligned.bytes() >= bytes);
+                prop_assert!(aligned.bytes() - bytes < PAGE_SIZE);
+                prop_assert_eq!(aligned.pages(), Some(Size::from_bytes(bytes).pages_rounded()));
+            }
+        }
+    }
+}
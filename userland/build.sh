@@ -6,6 +6,18 @@ cd $(dirname $0)
 [[ $1 = fmt ]] && { cargo fmt; exit 0; }
 [[ $1 = release ]] && RFLAG=--release
 
+# crates with pure logic are tested on the host with their std feature,
+# cargo is run from outside the workspace so .cargo/config.toml does not select the userland target
+if [[ $1 = host-test ]]
+then
+	for crate in bit_utils sys aser arpc asynca aurora_core aurora
+	do
+		(cd .. && cargo test --manifest-path userland/$crate/Cargo.toml --features std) || exit 1
+	done
+	(cd .. && cargo test --manifest-path userland/aurora_core/Cargo.toml --features std,debug-alloc) || exit 1
+	exit 0
+fi
+
 if [[ $1 = test ]]
 then
 	# TODO
@@ -18,6 +30,10 @@ else
   [[ $1 = release ]] && TARGET_DIR=target/x86_64-os-userland/release
 fi
 
-gen-initrd -n --init $TARGET_DIR/early-init --fs $TARGET_DIR/fs-server --hwaccess $TARGET_DIR/hwaccess-server --part-list part-list -o initrd
+# the initrd is a ustar archive, programs are found in it by file name
+tar --format=ustar -cf initrd \
+  -C $TARGET_DIR ash console-echo console-server early-init fs-server hwaccess-server log-server registry-server shutdown-test tls-test \
+  test-runner test-arpc test-async test-process test-stdio test-pipe test-cap test-shell test-thread test-fs test-memory test-pci \
+  -C "$(pwd)" part-list
 
 exit 0
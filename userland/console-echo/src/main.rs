@@ -0,0 +1,57 @@
+//! Interactive test program for console-server, which writes back every line typed on the console
+//!
+//! Lines are read from the `console_input` pipe reader, and written back to the `console_output` pipe writer.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use aurora::env;
+use aurora::io::{PipeReader, PipeWriter, Read, Write};
+use aurora::log::warn;
+use std::prelude::*;
+use sys::KResult;
+
+const GREETING: &[u8] = b"type a line to have it echoed back\n";
+const PROMPT: &[u8] = b"> ";
+
+fn main() {
+    let args = env::args();
+
+    let mut input: PipeReader = args.require("console_input");
+    let mut output: PipeWriter = args.require("console_output");
+
+    if let Err(error) = echo_lines(&mut input, &mut output) {
+        warn!("console echo stopped: {error}");
+    }
+}
+
+/// Writes back each line read from `input`, returns once the console is gone
+fn echo_lines(input: &mut PipeReader, output: &mut PipeWriter) -> KResult<()> {
+    output.write_all(GREETING)?;
+    output.write_all(PROMPT)?;
+
+    let mut line = Vec::new();
+    let mut buffer = [0; 256];
+
+    loop {
+        let read_size = input.read(&mut buffer)?;
+        if read_size == 0 {
+            return Ok(());
+        }
+
+        for byte in &buffer[..read_size] {
+            if *byte != b'\n' {
+                line.push(*byte);
+                continue;
+            }
+
+            output.write_all(b"echo: ")?;
+            output.write_all(&line)?;
+            output.write_all(b"\n")?;
+            output.write_all(PROMPT)?;
+            line.clear();
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,62 @@
+use alloc::vec::Vec;
+
+use sys::KResult;
+
+use crate::line_editor::LineEditor;
+use crate::uart::Uart;
+
+/// The uart and the line which is being typed on it
+///
+/// Output is written around the line being typed, so it is never mixed into the line.
+pub struct Console {
+    uart: Uart,
+    editor: LineEditor,
+}
+
+impl Console {
+    pub fn new(uart: Uart) -> Self {
+        Console {
+            uart,
+            editor: LineEditor::default(),
+        }
+    }
+
+    /// Writes output from one of the output streams, newlines are written as `\r\n`
+    ///
+    /// The line being typed is erased first, and redrawn after the output.
+    pub fn write_output(&mut self, data: &[u8]) -> KResult<()> {
+        let mut output = Vec::with_capacity(data.len());
+        self.editor.erase_from_terminal(&mut output);
+
+        for byte in data {
+            if *byte == b'\n' {
+                output.push(b'\r');
+            }
+            output.push(*byte);
+        }
+
+        output.extend_from_slice(self.editor.line());
+
+        self.uart.write_bytes(&output)
+    }
+
+    /// Handles every byte the uart has recieved, and echoes them
+    ///
+    /// # Returns
+    ///
+    /// The lines which were finished
+    pub fn read_input(&mut self) -> KResult<Vec<Vec<u8>>> {
+        let mut echo = Vec::new();
+        let mut lines = Vec::new();
+
+        while let Some(byte) = self.uart.try_read_byte()? {
+            if let Some(line) = self.editor.push_byte(byte, &mut echo) {
+                lines.push(line);
+            }
+        }
+
+        self.uart.write_bytes(&echo)?;
+
+        Ok(lines)
+    }
+}
\ No newline at end of file
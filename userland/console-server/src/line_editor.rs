@@ -0,0 +1,111 @@
+//! Line buffered input with simple editing, like a terminal in canonical mode
+
+use alloc::vec::Vec;
+use core::mem;
+
+/// Moves the cursor back, overwrites the character there with a space, and moves back again
+const ERASE_CHAR: &[u8] = b"\x08 \x08";
+
+const BACKSPACE: u8 = 0x08;
+/// Sent by most terminals when backspace is pressed
+const DELETE: u8 = 0x7f;
+/// Ctrl-u, erases the whole line
+const KILL_LINE: u8 = 0x15;
+/// Ctrl-w, erases the word before the cursor
+const ERASE_WORD: u8 = 0x17;
+
+/// Bytes typed after a line is this long are dropped
+const MAX_LINE_LEN: usize = 1024;
+
+/// Returns true if `byte` is the first byte of a utf-8 character
+fn is_char_start(byte: u8) -> bool {
+    byte & 0xc0 != 0x80
+}
+
+/// Builds up a line from bytes recieved from a terminal, and says what to echo back to the terminal
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    line: Vec<u8>,
+    /// Set after a carriage return, so the newline of a `\r\n` sequence does not finish another line
+    after_carriage_return: bool,
+}
+
+impl LineEditor {
+    /// The line which has been typed so far
+    pub fn line(&self) -> &[u8] {
+        &self.line
+    }
+
+    /// Handles a byte recieved from the terminal
+    ///
+    /// Bytes which should be written back to the terminal are appended to `echo`.
+    ///
+    /// # Returns
+    ///
+    /// The finished line with a newline at the end, once enter is pressed
+    pub fn push_byte(&mut self, byte: u8, echo: &mut Vec<u8>) -> Option<Vec<u8>> {
+        let after_carriage_return = mem::replace(&mut self.after_carriage_return, byte == b'\r');
+
+        match byte {
+            b'\n' if after_carriage_return => (),
+            b'\r' | b'\n' => {
+                echo.extend_from_slice(b"\r\n");
+
+                let mut line = mem::take(&mut self.line);
+                line.push(b'\n');
+                return Some(line);
+            },
+            BACKSPACE | DELETE => self.erase_char(echo),
+            KILL_LINE => {
+                while !self.line.is_empty() {
+                    self.erase_char(echo);
+                }
+            },
+            ERASE_WORD => {
+                while self.line.last() == Some(&b' ') {
+                    self.erase_char(echo);
+                }
+
+                while self.line.last().is_some_and(|byte| *byte != b' ') {
+                    self.erase_char(echo);
+                }
+            },
+            // other control characters and escape sequences are not supported, so they are dropped
+            0x00..=0x1f => (),
+            _ => {
+                if self.line.len() < MAX_LINE_LEN {
+                    self.line.push(byte);
+                    echo.push(byte);
+                }
+            },
+        }
+
+        None
+    }
+
+    /// Removes the last character of the line, and erases it from the terminal
+    fn erase_char(&mut self, echo: &mut Vec<u8>) {
+        if self.line.is_empty() {
+            return;
+        }
+
+        while let Some(byte) = self.line.pop() {
+            if is_char_start(byte) {
+                break;
+            }
+        }
+
+        echo.extend_from_slice(ERASE_CHAR);
+    }
+
+    /// Appends the bytes which erase the line from the terminal to `output`, without removing it from the editor
+    pub fn erase_from_terminal(&self, output: &mut Vec<u8>) {
+        let char_count = self.line.iter()
+            .filter(|byte| is_char_start(**byte))
+            .count();
+
+        for _ in 0..char_count {
+            output.extend_from_slice(ERASE_CHAR);
+        }
+    }
+}
\ No newline at end of file
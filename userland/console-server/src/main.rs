@@ -0,0 +1,122 @@
+//! Console on the first serial port, which takes over from the kernel's boot output
+//!
+//! Output to show on the console comes from the pipe readers in the `output_streams` named argument,
+//! such as the one early-init adds to the log server. Each stream is copied by its own thread,
+//! so streams are only mixed together at the boundaries of reads from them.
+//!
+//! Input is line buffered. Recieved bytes are echoed and can be edited with backspace, ctrl-u and ctrl-w,
+//! and each line is written to the `input` pipe writer once enter is pressed.
+//!
+//! The com1 ports are claimed from hwaccess-server, which is passed as the `hwaccess_server` named argument.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+mod console;
+mod line_editor;
+mod uart;
+
+use alloc::sync::Arc;
+use core::time::Duration;
+
+use aurora::env;
+use aurora::io::{PipeReader, PipeWriter, Read, Write};
+use aurora::log::warn;
+use aurora::sync::Mutex;
+use aurora::thread;
+use aurora::time::sleep;
+use hwaccess_server::{HwAccess, HwAccessAsync, IoPortRangeAsync};
+use std::prelude::*;
+
+use console::Console;
+use uart::Uart;
+
+/// First port of the com1 serial port
+const COM1_BASE: u16 = 0x3f8;
+
+const BANNER: &[u8] = b"aurora console\r\n";
+
+/// How long to wait before checking for recieved bytes again
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Most bytes read from an output stream at once
+const OUTPUT_CHUNK_SIZE: usize = 512;
+
+fn main() {
+    let args = env::args();
+
+    let hwaccess: HwAccess = args.require("hwaccess_server");
+    let output_streams: Vec<PipeReader> = args.require("output_streams");
+    let input: PipeWriter = args.require("input");
+
+    // the claim is released once the port range client is dropped, so it is kept until the console exits
+    let (_port_range, io_port) = asynca::block_in_place(async move {
+        let port_range = hwaccess.claim_io_ports(COM1_BASE, Uart::PORT_COUNT).await
+            .expect("com1 io ports are already claimed");
+
+        let io_port = port_range.get_io_port().await
+            .expect("failed to get io port capability for com1");
+
+        (port_range, io_port)
+    });
+
+    let uart = Uart::new(io_port, COM1_BASE)
+        .expect("failed to set up com1 uart");
+
+    uart.write_bytes(BANNER)
+        .expect("failed to write to com1");
+
+    let console = Arc::new(Mutex::new(Console::new(uart)));
+
+    for stream in output_streams {
+        let console = console.clone();
+        thread::spawn(move || copy_output(&console, stream));
+    }
+
+    read_input(&console, input);
+}
+
+/// Copies everything read from `stream` to the console, until the writer is dropped
+fn copy_output(console: &Mutex<Console>, mut stream: PipeReader) {
+    let mut buffer = [0; OUTPUT_CHUNK_SIZE];
+
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return,
+            Ok(read_size) => console.lock().write_output(&buffer[..read_size])
+                .expect("failed to write to com1"),
+            Err(error) => {
+                warn!("failed to read console output stream: {error}");
+                return;
+            },
+        }
+    }
+}
+
+/// Writes each line typed on the console to `input`, lines are dropped once the reader is gone
+fn read_input(console: &Mutex<Console>, input: PipeWriter) -> ! {
+    let mut input = Some(input);
+
+    loop {
+        let lines = console.lock().read_input()
+            .expect("failed to read from com1");
+
+        if lines.is_empty() {
+            sleep(POLL_INTERVAL);
+            continue;
+        }
+
+        for line in lines {
+            let Some(writer) = &mut input else {
+                break;
+            };
+
+            if let Err(error) = writer.write_all(&line) {
+                warn!("failed to write console input, dropping further input: {error}");
+                input = None;
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,92 @@
+use sys::{IoPort, KResult};
+
+/// Offsets of the uart registers from the base port
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+/// While set in the line control register, the data and interrupt enable registers hold the baud rate divisor
+const LINE_CONTROL_DIVISOR_LATCH: u8 = 1 << 7;
+/// 8 data bits, no parity, and 1 stop bit
+const LINE_CONTROL_8N1: u8 = 0b11;
+/// Enables and clears the fifos, and sets the recieve interrupt threshold to 14 bytes
+const FIFO_CONTROL_ENABLE: u8 = 0xc7;
+/// Sets the data terminal ready and request to send lines
+const MODEM_CONTROL_READY: u8 = 0b11;
+
+/// Set in the line status register when a recieved byte can be read
+const LINE_STATUS_DATA_READY: u8 = 1;
+/// Set in the line status register when the transmit holding register can accept another byte
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// Divides the 115200 baud base rate down to 38400 baud
+const BAUD_DIVISOR: u16 = 3;
+
+/// A 16550 uart, which is accessed by polling the line status register
+pub struct Uart {
+    io_port: IoPort,
+    base: u16,
+}
+
+impl Uart {
+    /// Number of io ports the uart uses
+    pub const PORT_COUNT: u16 = 8;
+
+    /// Sets up the uart whose registers start at port `base`, `io_port` must allow accessing all of its ports
+    pub fn new(io_port: IoPort, base: u16) -> KResult<Self> {
+        let uart = Uart {
+            io_port,
+            base,
+        };
+
+        // interrupts are not used, the line status register is polled instead
+        uart.write_register(INTERRUPT_ENABLE, 0)?;
+
+        uart.write_register(LINE_CONTROL, LINE_CONTROL_DIVISOR_LATCH)?;
+        uart.write_register(DATA, BAUD_DIVISOR as u8)?;
+        uart.write_register(INTERRUPT_ENABLE, (BAUD_DIVISOR >> 8) as u8)?;
+        uart.write_register(LINE_CONTROL, LINE_CONTROL_8N1)?;
+
+        uart.write_register(FIFO_CONTROL, FIFO_CONTROL_ENABLE)?;
+        uart.write_register(MODEM_CONTROL, MODEM_CONTROL_READY)?;
+
+        Ok(uart)
+    }
+
+    fn read_register(&self, register: u16) -> KResult<u8> {
+        self.io_port.read8(self.base + register)
+    }
+
+    fn write_register(&self, register: u16, value: u8) -> KResult<()> {
+        self.io_port.write8(self.base + register, value)
+    }
+
+    /// Waits until the uart can send another byte, then sends `byte`
+    pub fn write_byte(&self, byte: u8) -> KResult<()> {
+        while self.read_register(LINE_STATUS)? & LINE_STATUS_TRANSMIT_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+
+        self.write_register(DATA, byte)
+    }
+
+    pub fn write_bytes(&self, bytes: &[u8]) -> KResult<()> {
+        for byte in bytes {
+            self.write_byte(*byte)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the next recieved byte, or None if no byte has been recieved
+    pub fn try_read_byte(&self) -> KResult<Option<u8>> {
+        if self.read_register(LINE_STATUS)? & LINE_STATUS_DATA_READY == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(self.read_register(DATA)?))
+    }
+}
\ No newline at end of file
@@ -11,23 +11,36 @@ extern crate alloc;
 use core::arch::asm;
 use core::panic::PanicInfo;
 use core::slice;
+use core::time::Duration;
+
+use alloc::format;
+use alloc::vec;
 
 use aurora::prelude::*;
-use aurora::process::{self, Command};
+use aurora::backtrace::Backtrace;
+use aurora::debug_print::klog;
+use aurora::io::Pipe;
+use aurora::process::{self, Command, ProcessError};
+use aurora::log::{Log, LogAsync, LogAdmin, LogAdminAsync};
+use aurora::registry::{Registry, RegistryAsync};
 use aurora::thread;
+use aurora::this_context;
+use aurora::collections::MessageVec;
+use aurora::time::{self, Instant};
 use aser::from_bytes;
-use initrd::InitrdData;
-use sys::{InitInfo, MmioAllocator, Rsdp};
-use fs_server::{Fs, FsAsync};
+use arpc::ClientRpcEndpoint;
+use initrd_archive::InitrdData;
+use aurora_core::thread::TlsTemplate;
+use sys::{InitInfo, MmioAllocator, IntAllocator, IoPortAccess, Rsdp, DebugCap, StackInfo, Channel, CapFlags, SysErr, KResult};
+use fs_server::{Fs, FsAsync, InitialFiles};
 use hwaccess_server::{HwAccess, HwAccessAsync};
-
-mod initrd;
+use aurora_test::TEST_BINARY_PREFIX;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    dprintln!("{}", info);
+    dprintln_unbuffered!("{}\n{}", info, Backtrace::capture());
 
-    process::exit();
+    process::exit_with_code(process::PANIC_EXIT_CODE);
 }
 
 #[naked]
@@ -35,10 +48,7 @@ fn panic(info: &PanicInfo) -> ! {
 pub extern "C" fn _aurora_startup() {
     unsafe {
         asm!(
-            "pop rdi", // process data pointer
-            "pop rsi", // process data size
-            "pop rdx", // startup data pointer
-            "pop rcx", // startup data size
+            "mov rdi, rsp", // stack info pointer
             "call _rust_startup",
             options(noreturn)
         )
@@ -46,78 +56,370 @@ pub extern "C" fn _aurora_startup() {
 }
 
 #[no_mangle]
-pub extern "C" fn _rust_startup(
-    process_data: *mut u8,
-    process_data_size: usize,
-    init_data: *mut u8,
-    init_data_size: usize,
-) -> ! {
+pub extern "C" fn _rust_startup(stack_info: &StackInfo) -> ! {
     let process_data = unsafe {
-        slice::from_raw_parts(process_data, process_data_size)
+        slice::from_raw_parts(stack_info.process_data_address as *const u8, stack_info.process_data_size)
     };
 
     let (process_init_data, memory_entries) = aurora_core::process_data_from_slice(process_data)
-        .expect("invalid process data array passed into program");
+        .unwrap_or_else(|error| panic!("invalid process data passed into program: {error}"));
+
+    // safety: the tls image is part of the executable, which is never unmapped
+    let tls_template = unsafe {
+        TlsTemplate::from_raw_parts(
+            stack_info.tls_image_address,
+            stack_info.tls_image_size,
+            stack_info.tls_block_size,
+            stack_info.tls_block_align,
+        )
+    }.expect("invalid tls segment passed into program");
 
-    aurora_core::init_allocation(process_init_data, memory_entries)
+    aurora_core::init_allocation(process_init_data, memory_entries, tls_template)
         .expect("failed to initialize aurora lib allocaror");
 
+    // the kernel passes init info where other processes get their namespace
     let init_data = unsafe {
-        slice::from_raw_parts(init_data, init_data_size)
+        slice::from_raw_parts(stack_info.namespace_data_address as *const u8, stack_info.namespace_data_size)
+    };
+
+    let env_data = unsafe {
+        slice::from_raw_parts(stack_info.env_data_address as *const u8, stack_info.env_data_size)
     };
 
+    aurora::env::init_env_vars(env_data)
+        .expect("failed to initialize environment variables");
+
     let init_info: InitInfo = from_bytes(init_data)
         .expect("failed to deserialize init data");
 
     dprintln!("early-init started");
 
-    // safety: we trust the kernel to give us a pointer to a valid initrd
-    let initrd_info = unsafe {
-        initrd::parse_initrd(init_info.initrd_address)
+    // safety: the kernel maps the initrd at this address and does not unmap it
+    let initrd = unsafe {
+        slice::from_raw_parts(init_info.initrd_address as *const u8, init_info.initrd_size)
     };
+    let initrd_info = InitrdData::parse(initrd);
+
+    let debug_cap = &init_info.debug_cap;
+
+    let registry = start_registry_server(&initrd_info, debug_cap);
+    let (log, log_admin) = start_log_server(&initrd_info, debug_cap, &registry);
+    let hwaccess = start_hwaccess_server(&initrd_info, debug_cap, &registry, &log, init_info.mmio_allocator, init_info.int_allocator, init_info.io_port_access, init_info.rsdp);
+    let fs = start_fs_server(&initrd_info, debug_cap, &registry, &log, &hwaccess);
+    start_console_server(&initrd_info, debug_cap, &registry, &log, &log_admin, &hwaccess);
 
-    let hwaccess = start_hwaccess_server(&initrd_info, init_info.mmio_allocator, init_info.rsdp);
-    let fs = start_fs_server(&initrd_info, &hwaccess);
+    // the registry only holds weak endpoints, so the clients are moved back out of the task to keep the services running,
+    // they are never dropped since this thread exits without returning from main
+    let (_log, hwaccess, fs, registry) = asynca::block_in_place(async move {
+        register_service(&registry, "log", log.endpoint()).await;
+        register_service(&registry, "hwaccess", hwaccess.endpoint()).await;
+        register_service(&registry, "fs", fs.endpoint()).await;
 
-    asynca::block_in_place(async move {
         //let result = fs.add(1, 2).await;
         //dprintln!("result: {result}");
 
         //let pci_devices = hwaccess.get_pci_devices().await;
         //dprintln!("devices: {pci_devices:x?}");
+
+        (log, hwaccess, fs, registry)
     });
 
+    run_tls_test(&initrd_info, debug_cap);
+    run_shutdown_test(&initrd_info, debug_cap);
+
+    if init_info.run_userland_tests {
+        run_test_runner(&initrd_info, debug_cap, &registry, &fs);
+    }
+
+    // there is nothing left to run once the tests are done, so the last step is turning off the machine
+    dprintln!("shutting down...");
+    asynca::block_in_place(hwaccess.shutdown());
+    dprintln!("failed to shut down, halting early-init");
+
     // can't use regular process exit here because that will terminate root thread group,
     // and kill every thread and process on the system
     thread::exit_thread_only();
 }
 
-fn start_hwaccess_server(initrd: &InitrdData, mmio: MmioAllocator, rsdp: Rsdp) -> HwAccess {
+/// Called when starting a server fails, prints the kernel log before panicking since it often says why
+fn spawn_failed(debug_cap: &DebugCap, name: &str, error: ProcessError) -> ! {
+    match klog(debug_cap) {
+        Ok(klog) => dprintln!("kernel log:\n{klog}"),
+        Err(klog_error) => dprintln!("failed to read kernel log: {klog_error}"),
+    }
+
+    panic!("failed to start {name}: {error}");
+}
+
+/// Runs the tls test program and waits for it, a failure is printed but does not stop the system from starting
+fn run_tls_test(initrd: &InitrdData<'static>, debug_cap: &DebugCap) {
+    let tls_test_elf = initrd.file("tls-test")
+        .expect("no tls test found in initrd");
+
+    let child = Command::from_bytes(tls_test_elf.into())
+        .name("tls-test".to_owned())
+        .spawn()
+        .unwrap_or_else(|error| spawn_failed(debug_cap, "tls test", error));
+
+    match child.wait() {
+        Ok(status) if status.success() => (),
+        Ok(status) => dprintln!("tls test failed: {status}"),
+        Err(error) => dprintln!("failed to wait for tls test: {error}"),
+    }
+}
+
+/// Sent by the shutdown test once it is listening for shutdown requests, must match the value in shutdown-test
+const SHUTDOWN_TEST_READY_MESSAGE: u8 = 1;
+/// Sent by the shutdown test from its exit handler, must match the value in shutdown-test
+const SHUTDOWN_TEST_EXIT_HANDLER_MESSAGE: u8 = 2;
+/// How long to wait for each step of the shutdown test
+const SHUTDOWN_TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runs the shutdown test program, asks it to shut down, and checks that its exit handler ran before it exited
+///
+/// A failure is printed but does not stop the system from starting
+fn run_shutdown_test(initrd: &InitrdData<'static>, debug_cap: &DebugCap) {
+    let shutdown_test_elf = initrd.file("shutdown-test")
+        .expect("no shutdown test found in initrd");
+
+    let channel = Channel::new(CapFlags::all(), &this_context().allocator)
+        .expect("failed to create shutdown test channel");
+
+    let child = Command::from_bytes(shutdown_test_elf.into())
+        .name("shutdown-test".to_owned())
+        .named_arg("parent_channel".to_owned(), &channel)
+        .shutdown_channel(true)
+        .spawn()
+        .unwrap_or_else(|error| spawn_failed(debug_cap, "shutdown test", error));
+
+    if let Err(error) = shut_down_test_child(&channel, &child) {
+        dprintln!("shutdown test failed: {error}");
+        return;
+    }
+
+    match child.wait() {
+        Ok(status) if status.success() => (),
+        Ok(status) => dprintln!("shutdown test failed: {status}"),
+        Err(error) => dprintln!("failed to wait for shutdown test: {error}"),
+    }
+}
+
+fn shut_down_test_child(channel: &Channel, child: &process::Child) -> Result<(), String> {
+    let message = recv_shutdown_test_message(channel)
+        .map_err(|error| format!("failed to recieve ready message: {error}"))?;
+    if message != SHUTDOWN_TEST_READY_MESSAGE {
+        return Err(format!("expected ready message, got {message}"));
+    }
+
+    // the ready message is sent from another task, so the listener may not be waiting for the request yet
+    let deadline = Instant::now() + SHUTDOWN_TEST_TIMEOUT;
+    loop {
+        match child.request_shutdown() {
+            Ok(()) => break,
+            Err(SysErr::OkUnreach) if Instant::now() < deadline => time::sleep(Duration::from_millis(10)),
+            Err(error) => return Err(format!("failed to request shutdown: {error}")),
+        }
+    }
+
+    let message = recv_shutdown_test_message(channel)
+        .map_err(|error| format!("failed to recieve exit handler message: {error}"))?;
+    if message != SHUTDOWN_TEST_EXIT_HANDLER_MESSAGE {
+        return Err(format!("expected exit handler message, got {message}"));
+    }
+
+    Ok(())
+}
+
+fn recv_shutdown_test_message(channel: &Channel) -> KResult<u8> {
+    let buffer = MessageVec::from_slice(&[0u8]);
+    let deadline = Instant::now() + SHUTDOWN_TEST_TIMEOUT;
+
+    // panic safety: the buffer is not empty, so it has a message buffer
+    channel.sync_recv(&buffer.message_buffer().unwrap(), Some(deadline.as_boot_time().as_nanos() as u64))?;
+
+    Ok(buffer.as_slice()[0])
+}
+
+/// Runs the userland test runner on every test binary in the initrd, and waits for it to finish
+///
+/// The test runner reports the result to the kernel itself, so a failure here is only printed
+fn run_test_runner(initrd: &InitrdData<'static>, debug_cap: &DebugCap, registry: &Registry, fs: &Fs) {
+    let test_runner_elf = initrd.file("test-runner")
+        .expect("no test runner found in initrd");
+
+    let test_binaries = InitialFiles::new(
+        initrd.entries()
+            .filter(|entry| entry.path().starts_with(TEST_BINARY_PREFIX))
+            .map(|entry| (entry.path().to_string(), entry.data())),
+    ).expect("failed to copy test binaries for test runner");
+
+    dprintln!("starting test runner...");
+    let child = Command::from_bytes(test_runner_elf.into())
+        .name("test-runner".to_owned())
+        .named_arg("debug_cap".to_owned(), debug_cap)
+        .named_arg("test_binaries".to_owned(), &test_binaries)
+        .named_arg("fs_server".to_owned(), fs)
+        // test binaries are given the same registry, so they can use services such as hwaccess
+        .registry(clone_registry(registry))
+        .spawn()
+        .unwrap_or_else(|error| spawn_failed(debug_cap, "test runner", error));
+
+    match child.wait() {
+        Ok(status) if status.success() => (),
+        Ok(status) => dprintln!("test runner failed: {status}"),
+        Err(error) => dprintln!("failed to wait for test runner: {error}"),
+    }
+}
+
+/// Makes another registry client to give to a spawned process
+fn clone_registry(registry: &Registry) -> Registry {
+    registry.endpoint().try_clone()
+        .expect("failed to clone registry endpoint")
+        .into()
+}
+
+async fn register_service(registry: &Registry, name: &str, endpoint: &ClientRpcEndpoint) {
+    let endpoint = endpoint.try_clone()
+        .expect("failed to clone service endpoint");
+
+    registry.register(name.to_owned(), endpoint).await
+        .expect("failed to register service");
+}
+
+fn start_registry_server(initrd: &InitrdData<'static>, debug_cap: &DebugCap) -> Registry {
+    let (registry_client_endpoint, registry_server_endpoint) = arpc::make_endpoints()
+        .expect("failed to make registry server rpc endpoints");
+
+    let registry_server_elf = initrd.file("registry-server")
+        .expect("no registry server found in initrd");
+
+    dprintln!("starting registry server...");
+    Command::from_bytes(registry_server_elf.into())
+        .name("registry-server".to_owned())
+        .named_arg("server_endpoint".to_owned(), &registry_server_endpoint)
+        .spawn()
+        .unwrap_or_else(|error| spawn_failed(debug_cap, "registry server", error));
+
+    Registry::from(registry_client_endpoint)
+}
+
+/// Creates a log endpoint for a process started by early-init
+fn process_log(log: &Log, process_name: &str) -> Log {
+    asynca::block_in_place(log.new_process_log(process_name.to_owned()))
+        .expect("failed to create log endpoint")
+}
+
+/// Starts the log server, and returns early-init's log endpoint and the admin endpoint
+fn start_log_server(initrd: &InitrdData<'static>, debug_cap: &DebugCap, registry: &Registry) -> (Log, LogAdmin) {
+    let (log_client_endpoint, log_server_endpoint) = arpc::make_endpoints()
+        .expect("failed to make log server rpc endpoints");
+    let (admin_client_endpoint, admin_server_endpoint) = arpc::make_endpoints()
+        .expect("failed to make log admin rpc endpoints");
+
+    let log_server_elf = initrd.file("log-server")
+        .expect("no log server found in initrd");
+
+    dprintln!("starting log server...");
+    Command::from_bytes(log_server_elf.into())
+        .name("log-server".to_owned())
+        .named_arg("server_endpoint".to_owned(), &log_server_endpoint)
+        .named_arg("admin_endpoint".to_owned(), &admin_server_endpoint)
+        .named_arg("client_name".to_owned(), &"early-init")
+        .registry(clone_registry(registry))
+        .spawn()
+        .unwrap_or_else(|error| spawn_failed(debug_cap, "log server", error));
+
+    (Log::from(log_client_endpoint), LogAdmin::from(admin_client_endpoint))
+}
+
+fn start_hwaccess_server(initrd: &InitrdData<'static>, debug_cap: &DebugCap, registry: &Registry, log: &Log, mmio: MmioAllocator, int_allocator: IntAllocator, io_port_access: IoPortAccess, rsdp: Rsdp) -> HwAccess {
     let (hwaccess_client_endpoint, hwaccess_server_endpoint) = arpc::make_endpoints()
         .expect("failed to make hwaccess server rpc endpoints");
 
+    let hwaccess_server_elf = initrd.file("hwaccess-server")
+        .expect("no hwaccess server found in initrd");
+
     dprintln!("starting hwaccess server...");
-    let hwaccess_server = Command::from_bytes(initrd.hwaccess_server.into())
+    let hwaccess_server = Command::from_bytes(hwaccess_server_elf.into())
+        .name("hwaccess-server".to_owned())
         .named_arg("server_endpoint".to_owned(), &hwaccess_server_endpoint)
         .named_arg("mmio_allocator".to_owned(), &mmio)
+        .named_arg("int_allocator".to_owned(), &int_allocator)
+        .named_arg("io_port_access".to_owned(), &io_port_access)
         .named_arg("rsdp".to_owned(), &rsdp)
+        .registry(clone_registry(registry))
+        .log(process_log(log, "hwaccess-server"))
         .spawn()
-        .expect("failed to start hwaccess server");
+        .unwrap_or_else(|error| spawn_failed(debug_cap, "hwaccess server", error));
 
     HwAccess::from(hwaccess_client_endpoint)
 }
 
-fn start_fs_server(initrd: &InitrdData, hwaccess: &HwAccess) -> Fs {
+fn start_fs_server(initrd: &InitrdData<'static>, debug_cap: &DebugCap, registry: &Registry, log: &Log, hwaccess: &HwAccess) -> Fs {
     // this is rpc channel used to control fs server
     let (fs_client_endpoint, fs_server_endpoint) = arpc::make_endpoints()
         .expect("failed to make fs server rpc endpoints");
 
+    let initial_files = InitialFiles::new(
+        initrd.entries().map(|entry| (entry.path().to_string(), entry.data())),
+    ).expect("failed to copy initrd files for fs server");
+
+    let fs_server_elf = initrd.file("fs-server")
+        .expect("no fs server found in initrd");
+
     dprintln!("starting fs server...");
-    let fs_server = Command::from_bytes(initrd.fs_server.into())
+    let fs_server = Command::from_bytes(fs_server_elf.into())
+        .name("fs-server".to_owned())
         .named_arg("server_endpoint".to_owned(), &fs_server_endpoint)
         .named_arg("hwaccess_server".to_owned(), hwaccess)
+        .named_arg("initial_files".to_owned(), &initial_files)
+        .registry(clone_registry(registry))
+        .log(process_log(log, "fs-server"))
         .spawn()
-        .expect("failed to start fs server");
+        .unwrap_or_else(|error| spawn_failed(debug_cap, "fs server", error));
 
     Fs::from(fs_client_endpoint)
+}
+
+/// Starts the console server with an echo program attached to its input, and hands the console off to it from the kernel
+///
+/// The console shows every line printed by the log server, as well as the output of the echo program.
+fn start_console_server(initrd: &InitrdData<'static>, debug_cap: &DebugCap, registry: &Registry, log: &Log, log_admin: &LogAdmin, hwaccess: &HwAccess) {
+    let console_server_elf = initrd.file("console-server")
+        .expect("no console server found in initrd");
+    let console_echo_elf = initrd.file("console-echo")
+        .expect("no console echo found in initrd");
+
+    let (log_output_writer, log_output_reader) = Pipe::new()
+        .expect("failed to create console log output pipe");
+    let (echo_output_writer, echo_output_reader) = Pipe::new()
+        .expect("failed to create console echo output pipe");
+    let (input_writer, input_reader) = Pipe::new()
+        .expect("failed to create console input pipe");
+
+    asynca::block_in_place(log_admin.add_output(log_output_writer));
+
+    dprintln!("starting console server...");
+    Command::from_bytes(console_server_elf.into())
+        .name("console-server".to_owned())
+        .named_arg("hwaccess_server".to_owned(), hwaccess)
+        .named_arg("output_streams".to_owned(), &vec![log_output_reader, echo_output_reader])
+        .named_arg("input".to_owned(), &input_writer)
+        .registry(clone_registry(registry))
+        .log(process_log(log, "console-server"))
+        .spawn()
+        .unwrap_or_else(|error| spawn_failed(debug_cap, "console server", error));
+
+    Command::from_bytes(console_echo_elf.into())
+        .name("console-echo".to_owned())
+        .named_arg("console_input".to_owned(), &input_reader)
+        .named_arg("console_output".to_owned(), &echo_output_writer)
+        .log(process_log(log, "console-echo"))
+        .spawn()
+        .unwrap_or_else(|error| spawn_failed(debug_cap, "console echo", error));
+
+    // the kernel keeps writing to its log, and takes the console back if it panics
+    if let Err(error) = debug_cap.console_handoff() {
+        dprintln!("failed to hand off the kernel console: {error}");
+    }
 }
\ No newline at end of file
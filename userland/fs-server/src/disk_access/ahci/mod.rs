@@ -1,24 +1,40 @@
-use aurora::{prelude::*, addr_space, allocator::addr_space::{MapPhysMemArgs, RegionPadding, MemoryMappingOptions, MemoryCacheSetting}};
-use volatile::map_field;
-use hwaccess_server::{HwAccess, HwAccessAsync};
-use hwaccess_server::pci::{PciDeviceInfo, config_space::PciConfigSpaceHeader};
+use aurora::{prelude::*, addr_space, log::debug, allocator::addr_space::{MapPhysMemArgs, RegionPadding, MemoryMappingOptions, MemoryCacheSetting}};
+use hwaccess_server::{HwAccess, HwAccessAsync, PciDeviceAsync};
+use hwaccess_server::pci::{PciDeviceInfo, config_space::ConfigWidth};
 
-use crate::error::FsError;
-use super::{DiskAccess, DiskCompletion};
+use crate::error::DiskError;
+use super::{BlockDevice, BlockFuture};
 
-pub struct AhciBackend {
+/// Bar which holds the ahci controller's registers, called the abar
+const ABAR: u8 = 5;
+/// Offset of the abar in the config space
+const ABAR_OFFSET: u16 = 0x24;
 
+pub struct AhciBackend {
+    /// Size of a logical sector in bytes, from the drive's identify data
+    block_size: usize,
+    /// Number of logical sectors, from the drive's identify data
+    capacity: u64,
 }
 
 impl AhciBackend {
-    pub async fn new(hwaccess: &HwAccess, device_info: PciDeviceInfo) -> Result<Self, FsError> {
-        dprintln!("ahci device detected");
+    pub async fn new(hwaccess: &HwAccess, device_info: PciDeviceInfo) -> Result<Self, DiskError> {
+        debug!("ahci device detected");
 
-        let phys_mem = hwaccess.get_pci_mem(device_info.device_address).await
-            .ok_or(FsError::DeviceMapError)?;
+        let device = hwaccess.claim_device(device_info.device_address).await
+            .ok_or(DiskError::DeviceMapError)?;
+
+        // TODO: make sure the controller is in ahci mode (osdev wiki says it can also be in ide mode)
 
-        let map_result = addr_space().map_phys_mem(MapPhysMemArgs {
-            phys_mem,
+        let abar_address = device.read_config(ABAR_OFFSET, ConfigWidth::U32).await
+            .ok_or(DiskError::InvalidDevice)?;
+        debug!("{:x?}", abar_address);
+
+        let abar_mem = device.get_bar_mem(ABAR).await
+            .ok_or(DiskError::DeviceMapError)?;
+
+        let _abar_mapping = addr_space().map_phys_mem(MapPhysMemArgs {
+            phys_mem: abar_mem,
             options: MemoryMappingOptions {
                 read: true,
                 write: true,
@@ -29,28 +45,26 @@ impl AhciBackend {
             padding: RegionPadding::default(),
         })?;
 
-        let config_space = unsafe {
-            PciConfigSpaceHeader::from_addr(map_result.address)
-        };
-
-        // panic safety: this will not fail because ahci device always has type 0 header
-        let config_data = config_space.data().unwrap();
-
-        // TODO: make sure the controller is in ahci mode (osdev wiki says it can also be in ide mode)
+        // TODO: initialize the ahci controller
+        Err(DiskError::InvalidDevice)
+    }
+}
 
-        let ahci_mem_phys_addr = map_field!(config_data.bar5).read();
-        dprintln!("{:x?}", ahci_mem_phys_addr);
+impl BlockDevice for AhciBackend {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
 
-        todo!()
+    fn capacity(&self) -> u64 {
+        self.capacity
     }
-}
 
-impl DiskAccess for AhciBackend {
-    unsafe fn read_sectors(&self, sector_num: usize, sector_count: usize, dest_addr: usize) -> DiskCompletion {
-        todo!()
+    // TODO: issue read and write dma commands once the controller is initialized
+    fn read_blocks<'a>(&'a self, _lba: u64, _count: usize, _buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async { Err(DiskError::InvalidDevice) })
     }
 
-    unsafe fn write_sectors(&self, sector_num: usize, sector_count: usize, src_addr: usize) -> DiskCompletion {
-        todo!()
+    fn write_blocks<'a>(&'a self, _lba: u64, _count: usize, _buf: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(async { Err(DiskError::InvalidDevice) })
     }
 }
\ No newline at end of file
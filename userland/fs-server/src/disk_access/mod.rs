@@ -1,49 +1,83 @@
 mod ahci;
+mod virtio;
+
+use core::future::Future;
+use core::pin::Pin;
 
 use aurora::prelude::*;
+use aurora::log::warn;
 use hwaccess_server::{HwAccess, HwAccessAsync};
-use hwaccess_server::pci::{CLASS_MASS_STORAGE, SUBCLASS_SERIAL_ATA, PROG_IF_AHCI};
+use hwaccess_server::pci::{PciDeviceInfo, CLASS_MASS_STORAGE, SUBCLASS_SERIAL_ATA, PROG_IF_AHCI};
 
-use crate::error::FsError;
+use crate::error::DiskError;
 
-trait DiskAccess {
-    unsafe fn read_sectors(&self, sector_num: usize, sector_count: usize, dest_addr: usize) -> DiskCompletion;
-    unsafe fn write_sectors(&self, sector_num: usize, sector_count: usize, src_addr: usize) -> DiskCompletion;
-}
+/// Future returned by [`BlockDevice`] operations
+pub type BlockFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, DiskError>> + 'a>>;
+
+/// A disk which is read and written in fixed size blocks
+pub trait BlockDevice {
+    /// Size of a block in bytes
+    fn block_size(&self) -> usize;
 
-/// Signals when a disk read or write has completed
-pub struct DiskCompletion {
+    /// Number of blocks on the device
+    fn capacity(&self) -> u64;
 
+    /// Reads `count` blocks starting at block `lba` into the start of `buf`
+    /// 
+    /// Fails if `buf` is smaller than `count` blocks, or the blocks go past the end of the device
+    fn read_blocks<'a>(&'a self, lba: u64, count: usize, buf: &'a mut [u8]) -> BlockFuture<'a, ()>;
+
+    /// Writes `count` blocks from the start of `buf` to the device starting at block `lba`
+    /// 
+    /// Fails if `buf` is smaller than `count` blocks, or the blocks go past the end of the device
+    fn write_blocks<'a>(&'a self, lba: u64, count: usize, buf: &'a [u8]) -> BlockFuture<'a, ()>;
 }
 
 /// Beckend to a disk which allows reading and writing to different sectors
 pub struct FsBackend {
-    disk_access: Box<dyn DiskAccess>,
+    block_device: Box<dyn BlockDevice>,
 }
 
 impl FsBackend {
-    fn new<T: DiskAccess + 'static>(disk_access: T) -> Self {
+    fn new<T: BlockDevice + 'static>(block_device: T) -> Self {
         FsBackend {
-            disk_access: Box::new(disk_access),
+            block_device: Box::new(block_device),
         }
     }
+
+    pub fn block_device(&self) -> &dyn BlockDevice {
+        &*self.block_device
+    }
+}
+
+async fn get_backend(hwaccess_server: &HwAccess, device: PciDeviceInfo) -> Option<Result<FsBackend, DiskError>> {
+    let device_type = device.device_type;
+
+    if device_type.class == CLASS_MASS_STORAGE
+        && device_type.subclass == SUBCLASS_SERIAL_ATA
+        && device_type.prog_if == PROG_IF_AHCI {
+        Some(ahci::AhciBackend::new(hwaccess_server, device).await.map(FsBackend::new))
+    } else if virtio::is_virtio_block(device.device_id) {
+        Some(virtio::VirtioBlock::new(hwaccess_server, device).await.map(FsBackend::new))
+    } else {
+        None
+    }
 }
 
 /// Queries the hwaccess server for all disks and constructs an FsBackend for each one
-pub async fn get_backends(hwaccess_server: HwAccess) -> Result<Vec<FsBackend>, FsError> {
+/// 
+/// Disks which fail to initialize are skipped
+pub async fn get_backends(hwaccess_server: HwAccess) -> Vec<FsBackend> {
     let mut backends = Vec::new();
     let pci_devices = hwaccess_server.get_pci_devices().await;
 
     for device in pci_devices.iter() {
-        let device_type = device.device_type;
-        if device_type.class == CLASS_MASS_STORAGE {
-            if device_type.subclass == SUBCLASS_SERIAL_ATA && device_type.prog_if == PROG_IF_AHCI {
-                backends.push(
-                    FsBackend::new(ahci::AhciBackend::new(&hwaccess_server, *device).await?),
-                );
-            }
+        match get_backend(&hwaccess_server, *device).await {
+            Some(Ok(backend)) => backends.push(backend),
+            Some(Err(error)) => warn!("failed to initialize disk at {:?}: {error}", device.device_address),
+            None => (),
         }
     }
 
-    Ok(backends)
+    backends
 }
\ No newline at end of file
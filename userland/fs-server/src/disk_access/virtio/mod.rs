@@ -0,0 +1,413 @@
+mod pci;
+mod virtqueue;
+
+use core::cell::Cell;
+use core::future::poll_fn;
+use core::mem::size_of;
+use core::ptr;
+use core::task::Poll;
+
+use aurora::prelude::*;
+use aurora::this_context;
+use aurora::allocator::addr_space::{MappedMemory, map_slice, MemoryMappingOptions};
+use bit_utils::{Size, PAGE_SIZE};
+use hwaccess_server::{HwAccess, HwAccessAsync};
+use hwaccess_server::pci::{PciDeviceInfo, PciDeviceId};
+use sys::{Memory, MemoryNewFlags};
+
+use crate::error::DiskError;
+use super::{BlockDevice, BlockFuture};
+use pci::{VirtioPciTransport, QueueNotifier, STATUS_ACKNOWLEDGE, STATUS_DRIVER, STATUS_DRIVER_OK};
+use virtqueue::{Virtqueue, Descriptor, DESC_F_WRITE, MAX_QUEUE_SIZE};
+
+pub const VENDOR_ID_VIRTIO: u16 = 0x1af4;
+/// Device id of block devices which only support the modern interface
+const DEVICE_ID_BLOCK_MODERN: u16 = 0x1042;
+/// Device id of block devices which support both the legacy and modern interface
+const DEVICE_ID_BLOCK_TRANSITIONAL: u16 = 0x1001;
+
+/// Set by devices which support the modern interface, the driver must accept it to use the modern interface
+const FEATURE_VERSION_1: u64 = 1 << 32;
+/// Set by read only block devices
+const FEATURE_BLOCK_READ_ONLY: u64 = 1 << 5;
+
+/// Virtio block devices always address in 512 byte sectors, regardless of their physical block size
+const SECTOR_SIZE: usize = 512;
+
+/// Offset of the capacity field in the block device config region
+const CONFIG_CAPACITY_OFFSET: usize = 0;
+
+const REQUEST_TYPE_IN: u32 = 0;
+const REQUEST_TYPE_OUT: u32 = 1;
+
+const REQUEST_STATUS_OK: u8 = 0;
+/// Written to the status byte before a request is submitted, so it is never mistaken for a result
+const REQUEST_STATUS_UNSET: u8 = 0xff;
+
+/// Index of the only virtqueue block devices have
+const REQUEST_QUEUE_INDEX: u16 = 0;
+
+/// Number of pages in the bounce buffer which block data is copied through
+const BOUNCE_BUFFER_PAGES: usize = 16;
+
+// layout of pages in the dma memory
+const QUEUE_PAGE: usize = 0;
+const REQUEST_PAGE: usize = 1;
+const BOUNCE_BUFFER_START_PAGE: usize = 2;
+const DMA_MEMORY_PAGES: usize = BOUNCE_BUFFER_START_PAGE + BOUNCE_BUFFER_PAGES;
+
+/// Offset of the request status byte in the request page, it comes right after the header
+const REQUEST_STATUS_OFFSET: usize = size_of::<RequestHeader>();
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct RequestHeader {
+    request_type: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// Returns true if the device is a virtio block device which supports the modern interface
+pub fn is_virtio_block(device_id: PciDeviceId) -> bool {
+    device_id.vendor_id == VENDOR_ID_VIRTIO
+        && (device_id.device_id == DEVICE_ID_BLOCK_MODERN || device_id.device_id == DEVICE_ID_BLOCK_TRANSITIONAL)
+}
+
+/// Driver for virtio block devices using the modern pci transport
+/// 
+/// Requests are completed by polling the used ring, since virtio pci devices signal completions with msix,
+/// which hwaccess-server can't configure yet. Only 1 request is in flight at a time,
+/// and data is copied through a bounce buffer because pages of user buffers are not physically contiguous.
+pub struct VirtioBlock {
+    transport: VirtioPciTransport,
+    queue: Virtqueue,
+    notifier: QueueNotifier,
+    /// Memory the device accesses, holding the virtqueue, request header, and bounce buffer
+    dma_memory: MappedMemory<[u8]>,
+    /// Physical address of each page in `dma_memory`
+    dma_phys_pages: Vec<usize>,
+    /// Capacity in sectors
+    capacity: u64,
+    read_only: bool,
+    /// Most sectors that fit in one request, limited by the bounce buffer size and the queue size
+    max_request_sectors: usize,
+    /// Set while a request is using the virtqueue and dma memory
+    request_active: Cell<bool>,
+    /// Set from when a request is submitted until the device finishes it
+    request_in_flight: Cell<bool>,
+    /// Set once the device needs a reset, after which no more requests are made
+    failed: Cell<bool>,
+}
+
+impl VirtioBlock {
+    pub async fn new(hwaccess: &HwAccess, device_info: PciDeviceInfo) -> Result<Self, DiskError> {
+        let device = hwaccess.claim_device(device_info.device_address).await
+            .ok_or(DiskError::DeviceMapError)?;
+
+        let transport = VirtioPciTransport::new(device).await?;
+
+        Self::initialize(transport)
+    }
+
+    /// Performs the virtio device initialization sequence, and marks the device as failed if anything goes wrong
+    fn initialize(transport: VirtioPciTransport) -> Result<Self, DiskError> {
+        transport.reset()?;
+
+        let result = Self::initialize_inner(&transport);
+        match result {
+            Ok((queue, notifier, dma_memory, dma_phys_pages, read_only)) => {
+                transport.add_status(STATUS_DRIVER_OK);
+
+                let capacity = transport.device_config().read::<u32>(CONFIG_CAPACITY_OFFSET) as u64
+                    | (transport.device_config().read::<u32>(CONFIG_CAPACITY_OFFSET + 4) as u64) << 32;
+
+                // every request needs a descriptor for the header and status, and 1 for each page of data
+                let max_request_pages = (queue.size() as usize - 2).min(BOUNCE_BUFFER_PAGES);
+
+                Ok(VirtioBlock {
+                    transport,
+                    queue,
+                    notifier,
+                    dma_memory,
+                    dma_phys_pages,
+                    capacity,
+                    read_only,
+                    max_request_sectors: max_request_pages * PAGE_SIZE / SECTOR_SIZE,
+                    request_active: Cell::new(false),
+                    request_in_flight: Cell::new(false),
+                    failed: Cell::new(false),
+                })
+            },
+            Err(error) => {
+                transport.fail();
+                Err(error)
+            },
+        }
+    }
+
+    fn initialize_inner(
+        transport: &VirtioPciTransport,
+    ) -> Result<(Virtqueue, QueueNotifier, MappedMemory<[u8]>, Vec<usize>, bool), DiskError> {
+        transport.add_status(STATUS_ACKNOWLEDGE);
+        transport.add_status(STATUS_DRIVER);
+
+        let device_features = transport.device_features();
+        if device_features & FEATURE_VERSION_1 == 0 {
+            return Err(DiskError::InvalidDevice);
+        }
+
+        let driver_features = device_features & (FEATURE_VERSION_1 | FEATURE_BLOCK_READ_ONLY);
+        transport.negotiate_features(driver_features)?;
+
+        // queue must fit the header, status, and at least 1 data descriptor
+        let max_queue_size = transport.max_queue_size(REQUEST_QUEUE_INDEX);
+        if max_queue_size < 4 {
+            return Err(DiskError::InvalidDevice);
+        }
+        // the virtio spec requires queue sizes to be powers of 2, but don't trust the device
+        let queue_size = 1 << max_queue_size.min(MAX_QUEUE_SIZE).ilog2();
+
+        let dma_memory = Memory::new(
+            &this_context().allocator,
+            Size::from_pages(DMA_MEMORY_PAGES),
+            MemoryNewFlags::ZEROED,
+        )?;
+
+        let dma_phys_pages = (0..DMA_MEMORY_PAGES)
+            .map(|page_index| dma_memory.phys_addr(page_index))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let dma_mapping = map_slice::<u8, _>(&dma_memory, 0, DMA_MEMORY_PAGES * PAGE_SIZE, MemoryMappingOptions {
+            read: true,
+            write: true,
+            ..Default::default()
+        })?;
+
+        // safety: the queue page is only used by this queue, and stays mapped for as long as the driver exists
+        let queue = unsafe {
+            Virtqueue::new(
+                dma_mapping.address() + QUEUE_PAGE * PAGE_SIZE,
+                dma_phys_pages[QUEUE_PAGE],
+                queue_size,
+            )
+        };
+
+        let notifier = transport.enable_queue(REQUEST_QUEUE_INDEX, &queue)?;
+
+        Ok((queue, notifier, dma_mapping, dma_phys_pages, driver_features & FEATURE_BLOCK_READ_ONLY != 0))
+    }
+
+    fn request_page_address(&self) -> usize {
+        self.dma_memory.address() + REQUEST_PAGE * PAGE_SIZE
+    }
+
+    fn bounce_buffer_address(&self) -> usize {
+        self.dma_memory.address() + BOUNCE_BUFFER_START_PAGE * PAGE_SIZE
+    }
+
+    /// Polls for completion of the request in flight, and marks the device as failed if it needs a reset
+    fn poll_completion(&self) -> Poll<Result<(), DiskError>> {
+        if self.queue.poll_used().is_some() {
+            self.request_in_flight.set(false);
+            Poll::Ready(Ok(()))
+        } else if self.failed.get() || self.transport.needs_reset() {
+            self.failed.set(true);
+            Poll::Ready(Err(DiskError::DeviceFailed))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Waits until no other request is using the virtqueue, then claims it
+    async fn lock_requests(&self) -> Result<RequestLock<'_>, DiskError> {
+        poll_fn(|cx| {
+            if self.request_active.get() {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            // the future of the last request was dropped before the device finished it,
+            // so the device might still be using the dma memory
+            if self.request_in_flight.get() {
+                if let Poll::Ready(result) = self.poll_completion() {
+                    result?;
+                } else {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+            }
+
+            if self.failed.get() {
+                return Poll::Ready(Err(DiskError::DeviceFailed));
+            }
+
+            self.request_active.set(true);
+            Poll::Ready(Ok(RequestLock(&self.request_active)))
+        }).await
+    }
+
+    /// Checks that `count` sectors starting at `lba` fit in the device and in a buffer of `buf_len` bytes
+    fn check_request(&self, lba: u64, count: usize, buf_len: usize) -> Result<(), DiskError> {
+        if count.checked_mul(SECTOR_SIZE).map_or(true, |size| size > buf_len) {
+            return Err(DiskError::InvalidBuffer);
+        }
+
+        if lba.checked_add(count as u64).map_or(true, |end| end > self.capacity) {
+            return Err(DiskError::OutOfBounds);
+        }
+
+        Ok(())
+    }
+
+    /// Submits a request for `count` sectors starting at `sector`, using the bounce buffer for data, and waits for it to complete
+    /// 
+    /// Must only be called while holding the request lock
+    async fn do_request(&self, request_type: u32, sector: u64, count: usize) -> Result<(), DiskError> {
+        assert!(count > 0 && count <= self.max_request_sectors);
+
+        let header = RequestHeader {
+            request_type,
+            reserved: 0,
+            sector,
+        };
+
+        // safety: the request page is not being used by the device since no request is in flight
+        unsafe {
+            ptr::write_volatile(self.request_page_address() as *mut RequestHeader, header);
+            ptr::write_volatile((self.request_page_address() + REQUEST_STATUS_OFFSET) as *mut u8, REQUEST_STATUS_UNSET);
+        }
+
+        let data_flags = if request_type == REQUEST_TYPE_IN {
+            DESC_F_WRITE
+        } else {
+            0
+        };
+
+        let mut chain = Vec::with_capacity(BOUNCE_BUFFER_PAGES + 2);
+        chain.push(Descriptor {
+            address: self.dma_phys_pages[REQUEST_PAGE] as u64,
+            len: size_of::<RequestHeader>() as u32,
+            ..Default::default()
+        });
+
+        // each page of the bounce buffer needs its own descriptor since they are not physically contiguous
+        let mut remaining = count * SECTOR_SIZE;
+        for phys_page in &self.dma_phys_pages[BOUNCE_BUFFER_START_PAGE..] {
+            if remaining == 0 {
+                break;
+            }
+
+            let len = remaining.min(PAGE_SIZE);
+            chain.push(Descriptor {
+                address: *phys_page as u64,
+                len: len as u32,
+                flags: data_flags,
+                ..Default::default()
+            });
+            remaining -= len;
+        }
+
+        chain.push(Descriptor {
+            address: (self.dma_phys_pages[REQUEST_PAGE] + REQUEST_STATUS_OFFSET) as u64,
+            len: 1,
+            flags: DESC_F_WRITE,
+            ..Default::default()
+        });
+
+        self.request_in_flight.set(true);
+        self.queue.submit(&chain);
+        self.notifier.notify();
+
+        poll_fn(|cx| {
+            let poll = self.poll_completion();
+            if poll.is_pending() {
+                cx.waker().wake_by_ref();
+            }
+            poll
+        }).await?;
+
+        // safety: the device is done with the request page
+        let status = unsafe {
+            ptr::read_volatile((self.request_page_address() + REQUEST_STATUS_OFFSET) as *const u8)
+        };
+
+        if status == REQUEST_STATUS_OK {
+            Ok(())
+        } else {
+            Err(DiskError::IoError)
+        }
+    }
+
+    async fn read(&self, lba: u64, count: usize, buf: &mut [u8]) -> Result<(), DiskError> {
+        self.check_request(lba, count, buf.len())?;
+        let _lock = self.lock_requests().await?;
+
+        let mut sector = lba;
+        for chunk in buf[..count * SECTOR_SIZE].chunks_mut(self.max_request_sectors * SECTOR_SIZE) {
+            let chunk_sectors = chunk.len() / SECTOR_SIZE;
+
+            self.do_request(REQUEST_TYPE_IN, sector, chunk_sectors).await?;
+
+            // safety: chunks are never bigger than the bounce buffer, and the device is done with it
+            unsafe {
+                ptr::copy_nonoverlapping(self.bounce_buffer_address() as *const u8, chunk.as_mut_ptr(), chunk.len());
+            }
+
+            sector += chunk_sectors as u64;
+        }
+
+        Ok(())
+    }
+
+    async fn write(&self, lba: u64, count: usize, buf: &[u8]) -> Result<(), DiskError> {
+        if self.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+
+        self.check_request(lba, count, buf.len())?;
+        let _lock = self.lock_requests().await?;
+
+        let mut sector = lba;
+        for chunk in buf[..count * SECTOR_SIZE].chunks(self.max_request_sectors * SECTOR_SIZE) {
+            let chunk_sectors = chunk.len() / SECTOR_SIZE;
+
+            // safety: chunks are never bigger than the bounce buffer, and the device is not using it
+            unsafe {
+                ptr::copy_nonoverlapping(chunk.as_ptr(), self.bounce_buffer_address() as *mut u8, chunk.len());
+            }
+
+            self.do_request(REQUEST_TYPE_OUT, sector, chunk_sectors).await?;
+
+            sector += chunk_sectors as u64;
+        }
+
+        Ok(())
+    }
+}
+
+impl BlockDevice for VirtioBlock {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_blocks<'a>(&'a self, lba: u64, count: usize, buf: &'a mut [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.read(lba, count, buf))
+    }
+
+    fn write_blocks<'a>(&'a self, lba: u64, count: usize, buf: &'a [u8]) -> BlockFuture<'a, ()> {
+        Box::pin(self.write(lba, count, buf))
+    }
+}
+
+/// Releases the virtqueue for other requests when dropped
+struct RequestLock<'a>(&'a Cell<bool>);
+
+impl Drop for RequestLock<'_> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,342 @@
+use core::mem::{size_of, align_of};
+use core::ptr;
+
+use aurora::prelude::*;
+use aurora::allocator::addr_space::{MappedMemory, map_slice, MemoryMappingOptions, MemoryCacheSetting};
+use hwaccess_server::{PciDevice, PciDeviceAsync};
+use hwaccess_server::pci::config_space::ConfigWidth;
+
+use crate::error::DiskError;
+use super::virtqueue::Virtqueue;
+
+const COMMAND_OFFSET: u16 = 0x4;
+const STATUS_OFFSET: u16 = 0x6;
+const CAPABILITIES_POINTER_OFFSET: u16 = 0x34;
+
+const STATUS_HAS_CAPABILITIES: u32 = 1 << 4;
+/// Setting this bit in the command register allows the device to perform dma
+const COMMAND_BUS_MASTER: u32 = 1 << 2;
+
+/// Capability id of vendor specific capabilities, virtio uses these to say where its registers are
+const CAPABILITY_ID_VENDOR: u32 = 0x9;
+/// Guards against malformed capability lists which loop forever
+const MAX_CAPABILITY_COUNT: usize = 48;
+
+// offsets of fields in a virtio pci capability
+const CAP_CFG_TYPE_OFFSET: u16 = 3;
+const CAP_BAR_OFFSET: u16 = 4;
+const CAP_REGION_OFFSET_OFFSET: u16 = 8;
+const CAP_REGION_LENGTH_OFFSET: u16 = 12;
+/// Only present in the notify capability
+const CAP_NOTIFY_MULTIPLIER_OFFSET: u16 = 16;
+
+// types of virtio pci capabilities
+const CFG_TYPE_COMMON: u32 = 1;
+const CFG_TYPE_NOTIFY: u32 = 2;
+const CFG_TYPE_DEVICE: u32 = 4;
+
+// offsets of registers in the common configuration region
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x0;
+const COMMON_DEVICE_FEATURE: usize = 0x4;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x8;
+const COMMON_DRIVER_FEATURE: usize = 0xc;
+const COMMON_DEVICE_STATUS: usize = 0x14;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_ENABLE: usize = 0x1c;
+const COMMON_QUEUE_NOTIFY_OFF: usize = 0x1e;
+const COMMON_QUEUE_DESC: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+// bits of the device status register
+pub const STATUS_ACKNOWLEDGE: u8 = 1;
+pub const STATUS_DRIVER: u8 = 1 << 1;
+pub const STATUS_DRIVER_OK: u8 = 1 << 2;
+pub const STATUS_FEATURES_OK: u8 = 1 << 3;
+pub const STATUS_DEVICE_NEEDS_RESET: u8 = 1 << 6;
+pub const STATUS_FAILED: u8 = 1 << 7;
+
+/// Number of times the device status is read while waiting for a reset to complete
+const RESET_POLL_COUNT: usize = 1000000;
+
+/// A region of a mapped bar containing device registers
+#[derive(Debug, Clone, Copy)]
+pub struct MmioRegion {
+    address: usize,
+    size: usize,
+}
+
+impl MmioRegion {
+    fn register_address<T>(&self, offset: usize) -> usize {
+        assert!(offset + size_of::<T>() <= self.size, "virtio register out of bounds");
+        assert!(offset % align_of::<T>() == 0, "unaligned virtio register");
+
+        self.address + offset
+    }
+
+    /// Reads the register at `offset`, `T` should be an integer type
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        // safety: the region is mapped for as long as the transport exists
+        unsafe {
+            ptr::read_volatile(self.register_address::<T>(offset) as *const T)
+        }
+    }
+
+    /// Writes the register at `offset`, `T` should be an integer type
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+        // safety: the region is mapped for as long as the transport exists
+        unsafe {
+            ptr::write_volatile(self.register_address::<T>(offset) as *mut T, value)
+        }
+    }
+
+    /// Writes a 64 bit register as 2 32 bit halves, low half first
+    fn write_u64(&self, offset: usize, value: u64) {
+        self.write(offset, value as u32);
+        self.write(offset + 4, (value >> 32) as u32);
+    }
+}
+
+/// Location of a register region described by a virtio pci capability
+#[derive(Debug, Clone, Copy)]
+struct CapabilityRegion {
+    bar: u8,
+    offset: u32,
+    length: u32,
+}
+
+async fn read_config(device: &PciDevice, offset: u16, width: ConfigWidth) -> Result<u32, DiskError> {
+    device.read_config(offset, width).await.ok_or(DiskError::InvalidDevice)
+}
+
+async fn read_capability_region(device: &PciDevice, capability_offset: u16) -> Result<CapabilityRegion, DiskError> {
+    Ok(CapabilityRegion {
+        bar: read_config(device, capability_offset + CAP_BAR_OFFSET, ConfigWidth::U8).await? as u8,
+        offset: read_config(device, capability_offset + CAP_REGION_OFFSET_OFFSET, ConfigWidth::U32).await?,
+        length: read_config(device, capability_offset + CAP_REGION_LENGTH_OFFSET, ConfigWidth::U32).await?,
+    })
+}
+
+/// Register regions found by walking the device's capability list
+#[derive(Default)]
+struct VirtioCapabilities {
+    common: Option<CapabilityRegion>,
+    notify: Option<(CapabilityRegion, u32)>,
+    device: Option<CapabilityRegion>,
+}
+
+async fn find_capabilities(device: &PciDevice) -> Result<VirtioCapabilities, DiskError> {
+    let mut capabilities = VirtioCapabilities::default();
+
+    if read_config(device, STATUS_OFFSET, ConfigWidth::U16).await? & STATUS_HAS_CAPABILITIES == 0 {
+        return Ok(capabilities);
+    }
+
+    // lowest 2 bits of capability pointers are reserved
+    let mut offset = read_config(device, CAPABILITIES_POINTER_OFFSET, ConfigWidth::U8).await? as u16 & 0xfc;
+
+    for _ in 0..MAX_CAPABILITY_COUNT {
+        if offset == 0 {
+            break;
+        }
+
+        if read_config(device, offset, ConfigWidth::U8).await? == CAPABILITY_ID_VENDOR {
+            // the first capability of each type is the one which should be used
+            match read_config(device, offset + CAP_CFG_TYPE_OFFSET, ConfigWidth::U8).await? {
+                CFG_TYPE_COMMON if capabilities.common.is_none() => {
+                    capabilities.common = Some(read_capability_region(device, offset).await?);
+                },
+                CFG_TYPE_NOTIFY if capabilities.notify.is_none() => {
+                    let multiplier = read_config(device, offset + CAP_NOTIFY_MULTIPLIER_OFFSET, ConfigWidth::U32).await?;
+                    capabilities.notify = Some((read_capability_region(device, offset).await?, multiplier));
+                },
+                CFG_TYPE_DEVICE if capabilities.device.is_none() => {
+                    capabilities.device = Some(read_capability_region(device, offset).await?);
+                },
+                _ => (),
+            }
+        }
+
+        offset = read_config(device, offset + 1, ConfigWidth::U8).await? as u16 & 0xfc;
+    }
+
+    Ok(capabilities)
+}
+
+/// Writes to this to tell the device a virtqueue has new available descriptors
+#[derive(Debug, Clone, Copy)]
+pub struct QueueNotifier {
+    notify_region: MmioRegion,
+    offset: usize,
+    queue_index: u16,
+}
+
+impl QueueNotifier {
+    pub fn notify(&self) {
+        self.notify_region.write::<u16>(self.offset, self.queue_index);
+    }
+}
+
+/// The modern virtio pci transport, which accesses device registers through memory bars
+pub struct VirtioPciTransport {
+    /// Keeps the device claimed for as long as the transport exists
+    _device: PciDevice,
+    _bar_mappings: Vec<MappedMemory<[u8]>>,
+    common: MmioRegion,
+    notify: MmioRegion,
+    notify_multiplier: u32,
+    device_config: MmioRegion,
+}
+
+impl VirtioPciTransport {
+    /// Maps the device's virtio registers, and enables bus mastering so the device can access virtqueues
+    pub async fn new(device: PciDevice) -> Result<Self, DiskError> {
+        let capabilities = find_capabilities(&device).await?;
+
+        // devices without these capabilities only support the legacy interface
+        let (Some(common), Some((notify, notify_multiplier)), Some(device_config)) =
+            (capabilities.common, capabilities.notify, capabilities.device) else {
+            return Err(DiskError::InvalidDevice);
+        };
+
+        let mut bar_mappings: Vec<(u8, MappedMemory<[u8]>)> = Vec::new();
+        let mut regions = [MmioRegion { address: 0, size: 0 }; 3];
+
+        for (region, capability) in regions.iter_mut().zip([common, notify, device_config]) {
+            let mapping_index = match bar_mappings.iter().position(|(bar, _)| *bar == capability.bar) {
+                Some(index) => index,
+                None => {
+                    let mut phys_mem = device.get_bar_mem(capability.bar).await
+                        .ok_or(DiskError::DeviceMapError)?;
+                    let bar_size = phys_mem.size()?.bytes();
+
+                    let mapping = map_slice::<u8, _>(&phys_mem, 0, bar_size, MemoryMappingOptions {
+                        read: true,
+                        write: true,
+                        cacheing: MemoryCacheSetting::Uncached,
+                        ..Default::default()
+                    })?;
+
+                    bar_mappings.push((capability.bar, mapping));
+                    bar_mappings.len() - 1
+                },
+            };
+
+            let bar_mapping = &bar_mappings[mapping_index].1;
+            let region_end = capability.offset as usize + capability.length as usize;
+            if region_end > bar_mapping.len() {
+                return Err(DiskError::InvalidDevice);
+            }
+
+            *region = MmioRegion {
+                address: bar_mapping.address() + capability.offset as usize,
+                size: capability.length as usize,
+            };
+        }
+
+        let command = read_config(&device, COMMAND_OFFSET, ConfigWidth::U16).await?;
+        if !device.write_config(COMMAND_OFFSET, ConfigWidth::U16, command | COMMAND_BUS_MASTER).await {
+            return Err(DiskError::InvalidDevice);
+        }
+
+        Ok(VirtioPciTransport {
+            _device: device,
+            _bar_mappings: bar_mappings.into_iter().map(|(_, mapping)| mapping).collect(),
+            common: regions[0],
+            notify: regions[1],
+            notify_multiplier,
+            device_config: regions[2],
+        })
+    }
+
+    /// Region containing the registers specific to the type of virtio device
+    pub fn device_config(&self) -> MmioRegion {
+        self.device_config
+    }
+
+    pub fn status(&self) -> u8 {
+        self.common.read(COMMON_DEVICE_STATUS)
+    }
+
+    /// Sets the given bits in the device status register
+    pub fn add_status(&self, status: u8) {
+        self.common.write(COMMON_DEVICE_STATUS, self.status() | status);
+    }
+
+    /// Returns true if the device has hit an error it can only recover from by being reset
+    pub fn needs_reset(&self) -> bool {
+        self.status() & STATUS_DEVICE_NEEDS_RESET != 0
+    }
+
+    /// Tells the device the driver has given up on it
+    pub fn fail(&self) {
+        self.add_status(STATUS_FAILED);
+    }
+
+    /// Resets the device and waits for the reset to complete
+    pub fn reset(&self) -> Result<(), DiskError> {
+        self.common.write::<u8>(COMMON_DEVICE_STATUS, 0);
+
+        for _ in 0..RESET_POLL_COUNT {
+            if self.status() == 0 {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+
+        Err(DiskError::DeviceFailed)
+    }
+
+    pub fn device_features(&self) -> u64 {
+        self.common.write::<u32>(COMMON_DEVICE_FEATURE_SELECT, 0);
+        let low = self.common.read::<u32>(COMMON_DEVICE_FEATURE);
+        self.common.write::<u32>(COMMON_DEVICE_FEATURE_SELECT, 1);
+        let high = self.common.read::<u32>(COMMON_DEVICE_FEATURE);
+
+        ((high as u64) << 32) | low as u64
+    }
+
+    /// Writes the features the driver will use, and checks that the device accepted them
+    pub fn negotiate_features(&self, features: u64) -> Result<(), DiskError> {
+        self.common.write::<u32>(COMMON_DRIVER_FEATURE_SELECT, 0);
+        self.common.write::<u32>(COMMON_DRIVER_FEATURE, features as u32);
+        self.common.write::<u32>(COMMON_DRIVER_FEATURE_SELECT, 1);
+        self.common.write::<u32>(COMMON_DRIVER_FEATURE, (features >> 32) as u32);
+
+        self.add_status(STATUS_FEATURES_OK);
+        if self.status() & STATUS_FEATURES_OK == 0 {
+            return Err(DiskError::InvalidDevice);
+        }
+
+        Ok(())
+    }
+
+    /// Largest size the device supports for virtqueue `queue_index`, or 0 if the queue does not exist
+    pub fn max_queue_size(&self, queue_index: u16) -> u16 {
+        self.common.write(COMMON_QUEUE_SELECT, queue_index);
+        self.common.read(COMMON_QUEUE_SIZE)
+    }
+
+    /// Gives the device the location of `queue`, and enables it as virtqueue `queue_index`
+    pub fn enable_queue(&self, queue_index: u16, queue: &Virtqueue) -> Result<QueueNotifier, DiskError> {
+        self.common.write(COMMON_QUEUE_SELECT, queue_index);
+        self.common.write(COMMON_QUEUE_SIZE, queue.size());
+        self.common.write_u64(COMMON_QUEUE_DESC, queue.descriptor_phys_address() as u64);
+        self.common.write_u64(COMMON_QUEUE_DRIVER, queue.avail_phys_address() as u64);
+        self.common.write_u64(COMMON_QUEUE_DEVICE, queue.used_phys_address() as u64);
+
+        let notify_offset = self.common.read::<u16>(COMMON_QUEUE_NOTIFY_OFF) as usize * self.notify_multiplier as usize;
+        if notify_offset + size_of::<u16>() > self.notify.size {
+            return Err(DiskError::InvalidDevice);
+        }
+
+        self.common.write::<u16>(COMMON_QUEUE_ENABLE, 1);
+
+        Ok(QueueNotifier {
+            notify_region: self.notify,
+            offset: notify_offset,
+            queue_index,
+        })
+    }
+}
\ No newline at end of file
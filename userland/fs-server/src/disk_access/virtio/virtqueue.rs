@@ -0,0 +1,183 @@
+use core::cell::Cell;
+use core::mem::size_of;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+use bit_utils::{align_up, PAGE_SIZE};
+
+/// Set on descriptors which continue in the descriptor referenced by `next`
+pub const DESC_F_NEXT: u16 = 1;
+/// Set on descriptors which the device writes to instead of reads from
+pub const DESC_F_WRITE: u16 = 2;
+
+/// Largest queue size used, this keeps every part of the queue in 1 page
+pub const MAX_QUEUE_SIZE: u16 = 64;
+
+/// An entry in the descriptor table, which points to a buffer the device reads or writes
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Descriptor {
+    pub address: u64,
+    pub len: u32,
+    pub flags: u16,
+    pub next: u16,
+}
+
+/// An entry in the used ring, written by the device when it is done with a descriptor chain
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UsedElement {
+    /// Index of the first descriptor in the chain
+    pub id: u32,
+    /// Number of bytes the device wrote into the chain
+    pub len: u32,
+}
+
+// offsets of fields in the available and used rings
+const RING_INDEX_OFFSET: usize = 2;
+const RING_ENTRIES_OFFSET: usize = 4;
+
+/// A split virtqueue, with the descriptor table, available ring, and used ring all in 1 page
+/// 
+/// Only 1 descriptor chain is in flight at a time, so every chain starts at descriptor 0
+pub struct Virtqueue {
+    size: u16,
+    virt_address: usize,
+    phys_address: usize,
+    avail_offset: usize,
+    used_offset: usize,
+    /// Index the next descriptor chain will be put at in the available ring
+    next_avail_index: Cell<u16>,
+    /// Index of the next entry in the used ring which has not been seen yet
+    next_used_index: Cell<u16>,
+}
+
+impl Virtqueue {
+    /// Creates a virtqueue in the page at `virt_address`, which is at physical address `phys_address`
+    /// 
+    /// `size` must be a power of 2 no bigger than [`MAX_QUEUE_SIZE`]
+    /// 
+    /// # Safety
+    /// 
+    /// The page must be mapped and stay mapped at both addresses for the lifetime of the virtqueue,
+    /// and it must not be used for anything else
+    pub unsafe fn new(virt_address: usize, phys_address: usize, size: u16) -> Self {
+        // ring indexes wrap at u16::MAX, so the size must divide evenly into that
+        assert!(size.is_power_of_two() && size <= MAX_QUEUE_SIZE);
+
+        let avail_offset = size as usize * size_of::<Descriptor>();
+        // available ring has a flags field, index field, ring, and used event field
+        let avail_size = RING_ENTRIES_OFFSET + size as usize * size_of::<u16>() + size_of::<u16>();
+        let used_offset = align_up(avail_offset + avail_size, 4);
+        let used_size = RING_ENTRIES_OFFSET + size as usize * size_of::<UsedElement>() + size_of::<u16>();
+        assert!(used_offset + used_size <= PAGE_SIZE);
+
+        unsafe {
+            ptr::write_bytes(virt_address as *mut u8, 0, PAGE_SIZE);
+        }
+
+        Virtqueue {
+            size,
+            virt_address,
+            phys_address,
+            avail_offset,
+            used_offset,
+            next_avail_index: Cell::new(0),
+            next_used_index: Cell::new(0),
+        }
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Physical address of the descriptor table
+    pub fn descriptor_phys_address(&self) -> usize {
+        self.phys_address
+    }
+
+    /// Physical address of the available ring, called the driver area by the virtio spec
+    pub fn avail_phys_address(&self) -> usize {
+        self.phys_address + self.avail_offset
+    }
+
+    /// Physical address of the used ring, called the device area by the virtio spec
+    pub fn used_phys_address(&self) -> usize {
+        self.phys_address + self.used_offset
+    }
+
+    /// Writes `chain` to the descriptor table and makes it available to the device
+    /// 
+    /// The `next` fields and [`DESC_F_NEXT`] flags are filled in to link the chain together.
+    /// The device must be notified after this for it to start processing the chain.
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if `chain` is empty or longer than the queue
+    pub fn submit(&self, chain: &[Descriptor]) {
+        assert!(!chain.is_empty() && chain.len() <= self.size as usize);
+
+        let descriptors = self.virt_address as *mut Descriptor;
+        for (i, descriptor) in chain.iter().enumerate() {
+            let mut descriptor = *descriptor;
+            if i + 1 < chain.len() {
+                descriptor.flags |= DESC_F_NEXT;
+                descriptor.next = i as u16 + 1;
+            } else {
+                descriptor.flags &= !DESC_F_NEXT;
+                descriptor.next = 0;
+            }
+
+            // safety: chain length was checked to fit in the descriptor table
+            unsafe {
+                ptr::write_volatile(descriptors.add(i), descriptor);
+            }
+        }
+
+        let avail_index = self.next_avail_index.get();
+        let avail = self.virt_address + self.avail_offset;
+        let ring_entry = avail + RING_ENTRIES_OFFSET + (avail_index % self.size) as usize * size_of::<u16>();
+
+        unsafe {
+            // chain always starts at descriptor 0
+            ptr::write_volatile(ring_entry as *mut u16, 0);
+        }
+
+        // device must see the descriptors before the new index
+        fence(Ordering::Release);
+
+        let avail_index = avail_index.wrapping_add(1);
+        unsafe {
+            ptr::write_volatile((avail + RING_INDEX_OFFSET) as *mut u16, avail_index);
+        }
+        self.next_avail_index.set(avail_index);
+
+        // index must be visible before the device is notified
+        fence(Ordering::SeqCst);
+    }
+
+    /// Returns the next descriptor chain the device has finished with, or None if the device has not finished any
+    pub fn poll_used(&self) -> Option<UsedElement> {
+        let used = self.virt_address + self.used_offset;
+        let used_index = unsafe {
+            ptr::read_volatile((used + RING_INDEX_OFFSET) as *const u16)
+        };
+
+        let next_used_index = self.next_used_index.get();
+        if used_index == next_used_index {
+            return None;
+        }
+
+        // used element must not be read before the index
+        fence(Ordering::Acquire);
+
+        let element_address = used + RING_ENTRIES_OFFSET + (next_used_index % self.size) as usize * size_of::<UsedElement>();
+        let element = unsafe {
+            ptr::read_volatile(element_address as *const UsedElement)
+        };
+
+        self.next_used_index.set(next_used_index.wrapping_add(1));
+
+        Some(element)
+    }
+}
\ No newline at end of file
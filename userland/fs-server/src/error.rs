@@ -1,14 +1,29 @@
 use aurora::allocator::addr_space::AddrSpaceError;
+use sys::SysErr;
 use thiserror_no_std::Error;
 
 use arpc::RpcError;
 
 #[derive(Debug, Error)]
-pub enum FsError {
+pub enum DiskError {
     #[error("An rpc error occured: {0}")]
     RpcError(#[from] RpcError),
     #[error("An address space error occured: {0}")]
     AddrSpaceError(#[from] AddrSpaceError),
+    #[error("A system error occured: {0}")]
+    SysErr(#[from] SysErr),
     #[error("Could not access memory mapped io for storage device")]
     DeviceMapError,
+    #[error("Storage device is not supported or is configured incorrectly")]
+    InvalidDevice,
+    #[error("Storage device has failed and needs to be reset")]
+    DeviceFailed,
+    #[error("Storage device could not complete the request")]
+    IoError,
+    #[error("Storage device is read only")]
+    ReadOnly,
+    #[error("Buffer is too small for the requested number of blocks")]
+    InvalidBuffer,
+    #[error("Requested blocks are past the end of the storage device")]
+    OutOfBounds,
 }
\ No newline at end of file
@@ -3,7 +3,86 @@
 #![feature(associated_type_defaults)]
 #![feature(decl_macro)]
 
-#[arpc::service(service_id = 11, name = "Fs")]
-pub trait FsServer {
-    fn add(&self, a: usize, b: usize) -> usize;
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde::{Serialize, Deserialize};
+use bit_utils::Size;
+use aurora::this_context;
+use aurora::allocator::addr_space::{AddrSpaceError, MemoryMappingOptions, map_slice};
+use sys::{Memory, MemoryNewFlags};
+
+// the fs api is defined in aurora so aurora::fs can use the client without a dependency cycle
+pub use aurora::fs::{
+    FsServer,
+    Fs,
+    FsAsync,
+    FsError,
+    OpenFlags,
+    FileHandle,
+    FileInfo,
+    FileKind,
+    DiskInfo,
+    BOOT_SIGNATURE,
+    BOOT_SIGNATURE_OFFSET,
+    normalize_path,
+};
+
+/// Files given to the fs server when it starts, which it puts in its ramfs
+#[derive(Serialize, Deserialize)]
+pub struct InitialFiles {
+    /// Holds the data of every file back to back
+    pub memory: Memory,
+    pub files: Vec<InitialFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InitialFile {
+    pub path: String,
+    /// Offset of the file's data in the memory
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl InitialFiles {
+    /// Copies `files` into a new memory capability
+    pub fn new<'a>(files: impl IntoIterator<Item = (String, &'a [u8])>) -> Result<Self, AddrSpaceError> {
+        let files: Vec<_> = files.into_iter().collect();
+        let total_size: usize = files.iter().map(|(_, data)| data.len()).sum();
+
+        // memory can't be zero sized, so always allocate at least 1 byte
+        let memory = Memory::new(
+            &this_context().allocator,
+            Size::from_bytes(total_size.max(1)),
+            MemoryNewFlags::empty(),
+        )?;
+
+        let mut mapping = map_slice::<u8, _>(&memory, 0, total_size.max(1), MemoryMappingOptions {
+            read: true,
+            write: true,
+            ..Default::default()
+        })?;
+
+        let mut initial_files = Vec::with_capacity(files.len());
+        let mut offset = 0;
+
+        for (path, data) in files {
+            mapping[offset..offset + data.len()].copy_from_slice(data);
+
+            initial_files.push(InitialFile {
+                path,
+                offset,
+                len: data.len(),
+            });
+
+            offset += data.len();
+        }
+
+        Ok(InitialFiles {
+            memory,
+            files: initial_files,
+        })
+    }
 }
\ No newline at end of file
@@ -4,36 +4,100 @@ extern crate std;
 
 mod disk_access;
 mod error;
+mod ramfs;
+mod server;
 
-use aurora::env;
+use aurora::env::{self, ArgError};
+use aurora::log::{info, warn, error};
+use aurora::allocator::addr_space::{MemoryMappingOptions, map_slice};
 use arpc::{ServerRpcEndpoint, run_rpc_service};
 use hwaccess_server::HwAccess;
 use std::prelude::*;
 
-use fs_server::FsServer;
+use fs_server::{InitialFiles, DiskInfo, BOOT_SIGNATURE_OFFSET, normalize_path};
+use disk_access::FsBackend;
+use ramfs::Ramfs;
+use server::FsServerImpl;
 
-struct FsServerImpl;
+/// Directory in the ramfs which the initial files from early-init are put in
+const INITIAL_FILES_DIRECTORY: &str = "/initrd/";
 
-#[arpc::service_impl]
-impl FsServer for FsServerImpl {
-    fn add(&self, a: usize, b: usize) -> usize {
-        a + b
+/// Copies the initial files into the ramfs
+fn load_initial_files(ramfs: &mut Ramfs, initial_files: InitialFiles) {
+    let data_size = initial_files.files.iter()
+        .map(|file| file.offset.saturating_add(file.len))
+        .max()
+        .unwrap_or(0);
+
+    if data_size == 0 {
+        return;
+    }
+
+    let data = map_slice::<u8, _>(&initial_files.memory, 0, data_size, MemoryMappingOptions {
+        read: true,
+        ..Default::default()
+    }).expect("failed to map initial files");
+
+    for file in initial_files.files.iter() {
+        let mut path = String::from(INITIAL_FILES_DIRECTORY);
+        path.push_str(&file.path);
+
+        let result = normalize_path(&path)
+            .and_then(|path| ramfs.insert_file(&path, data[file.offset..file.offset + file.len].to_vec()));
+
+        if let Err(error) = result {
+            warn!("failed to add initial file {}: {error}", file.path);
+        }
+    }
+}
+
+/// Reads the first block of `backend` to find its boot signature
+async fn disk_info(backend: &FsBackend) -> DiskInfo {
+    let block_device = backend.block_device();
+    let mut block = Vec::new();
+    block.resize(block_device.block_size(), 0);
+
+    let boot_signature = match block_device.read_blocks(0, 1, &mut block).await {
+        Ok(()) => block.get(BOOT_SIGNATURE_OFFSET..BOOT_SIGNATURE_OFFSET + 2)
+            // panic safety: the slice is 2 bytes long
+            .map(|signature| signature.try_into().unwrap()),
+        Err(error) => {
+            error!("failed to read first block of disk: {error}");
+            None
+        },
+    };
+
+    DiskInfo {
+        block_size: block_device.block_size() as u64,
+        capacity: block_device.capacity(),
+        boot_signature,
     }
 }
 
 fn main() {
-    dprintln!("hello fs");
+    info!("hello fs");
 
     let args = env::args();
-    let rpc_endpoint: ServerRpcEndpoint = args.named_arg("server_endpoint")
-        .expect("provided fs server rpc endpoint is invalid");
+    let rpc_endpoint: ServerRpcEndpoint = args.require("server_endpoint");
 
-    let hwaccess: HwAccess = args.named_arg("hwaccess_server")
-        .expect("no hwaccess_server endpoint provided");
+    let hwaccess: HwAccess = args.require("hwaccess_server");
 
-    asynca::block_in_place(async move {
+    let mut ramfs = Ramfs::new();
+    match args.named_arg("initial_files") {
+        Ok(initial_files) => load_initial_files(&mut ramfs, initial_files),
+        Err(ArgError::Missing { .. }) => (),
+        Err(error) => error!("failed to load initial files: {error}"),
+    }
+
+    let disks = asynca::block_in_place(async move {
         let backends = disk_access::get_backends(hwaccess).await;
+
+        let mut disks = Vec::new();
+        for backend in backends.iter() {
+            disks.push(disk_info(backend).await);
+        }
+        disks
     });
 
-    //asynca::block_in_place(run_rpc_service(rpc_endpoint, FsServerImpl));
+    asynca::block_in_place(run_rpc_service(rpc_endpoint, FsServerImpl::new(ramfs, disks)));
 }
@@ -0,0 +1,138 @@
+use std::prelude::*;
+use aurora::collections::HashMap;
+use fs_server::{FsError, FileInfo, FileKind};
+
+pub enum Node {
+    File(Vec<u8>),
+    Directory(HashMap<String, Node>),
+}
+
+impl Node {
+    pub fn info(&self) -> FileInfo {
+        match self {
+            Node::File(data) => FileInfo {
+                kind: FileKind::File,
+                size: data.len() as u64,
+            },
+            Node::Directory(_) => FileInfo {
+                kind: FileKind::Directory,
+                size: 0,
+            },
+        }
+    }
+}
+
+/// Splits a normalized path into its components
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|component| !component.is_empty())
+}
+
+/// Splits a normalized path into its parent directory and file name, returns None for the root
+fn split_parent(path: &str) -> Option<(&str, &str)> {
+    let (parent, name) = path.rsplit_once('/')?;
+
+    if name.is_empty() {
+        None
+    } else {
+        Some((parent, name))
+    }
+}
+
+/// A filesystem which is stored entirely in memory
+/// 
+/// All paths passed to the ramfs must already be normalized
+pub struct Ramfs {
+    root: Node,
+}
+
+impl Ramfs {
+    pub fn new() -> Self {
+        Ramfs {
+            root: Node::Directory(HashMap::default()),
+        }
+    }
+
+    pub fn lookup(&self, path: &str) -> Result<&Node, FsError> {
+        let mut node = &self.root;
+
+        for component in components(path) {
+            let Node::Directory(entries) = node else {
+                return Err(FsError::NotDirectory);
+            };
+
+            node = entries.get(component).ok_or(FsError::NotFound)?;
+        }
+
+        Ok(node)
+    }
+
+    pub fn lookup_mut(&mut self, path: &str) -> Result<&mut Node, FsError> {
+        let mut node = &mut self.root;
+
+        for component in components(path) {
+            let Node::Directory(entries) = node else {
+                return Err(FsError::NotDirectory);
+            };
+
+            node = entries.get_mut(component).ok_or(FsError::NotFound)?;
+        }
+
+        Ok(node)
+    }
+
+    /// Looks up the file at `path`, fails if it is a directory
+    pub fn file_mut(&mut self, path: &str) -> Result<&mut Vec<u8>, FsError> {
+        match self.lookup_mut(path)? {
+            Node::File(data) => Ok(data),
+            Node::Directory(_) => Err(FsError::IsDirectory),
+        }
+    }
+
+    /// Creates an empty file at `path`, the parent directory must already exist
+    /// 
+    /// Does nothing if the file already exists
+    pub fn create_file(&mut self, path: &str) -> Result<(), FsError> {
+        let (parent, name) = split_parent(path).ok_or(FsError::IsDirectory)?;
+
+        let Node::Directory(entries) = self.lookup_mut(parent)? else {
+            return Err(FsError::NotDirectory);
+        };
+
+        match entries.entry(name.to_owned()).or_insert_with(|| Node::File(Vec::new())) {
+            Node::File(_) => Ok(()),
+            Node::Directory(_) => Err(FsError::IsDirectory),
+        }
+    }
+
+    /// Creates the directory at `path` and any missing parent directories
+    pub fn create_dir_all(&mut self, path: &str) -> Result<(), FsError> {
+        let mut node = &mut self.root;
+
+        for component in components(path) {
+            let Node::Directory(entries) = node else {
+                return Err(FsError::NotDirectory);
+            };
+
+            node = entries.entry(component.to_owned())
+                .or_insert_with(|| Node::Directory(HashMap::default()));
+        }
+
+        match node {
+            Node::Directory(_) => Ok(()),
+            Node::File(_) => Err(FsError::NotDirectory),
+        }
+    }
+
+    /// Creates a file at `path` containing `data`, creating parent directories as needed
+    /// 
+    /// If the file already exists it is overwritten
+    pub fn insert_file(&mut self, path: &str, data: Vec<u8>) -> Result<(), FsError> {
+        let (parent, _) = split_parent(path).ok_or(FsError::IsDirectory)?;
+
+        self.create_dir_all(parent)?;
+        self.create_file(path)?;
+        *self.file_mut(path)? = data;
+
+        Ok(())
+    }
+}
\ No newline at end of file
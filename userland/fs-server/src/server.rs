@@ -0,0 +1,132 @@
+use std::prelude::*;
+use aurora::collections::HashMap;
+use aurora::sync::Mutex;
+use fs_server::{FsServer, FsError, OpenFlags, FileHandle, FileInfo, DiskInfo, normalize_path};
+
+use crate::ramfs::{Ramfs, Node};
+
+/// Largest size a file can be written to, files are kept in memory so this stops one write from using all of it
+const MAX_FILE_SIZE: usize = 64 * 1024 * 1024;
+
+struct OpenFile {
+    /// Normalized path of the file
+    path: String,
+    flags: OpenFlags,
+}
+
+struct FsState {
+    ramfs: Ramfs,
+    open_files: HashMap<FileHandle, OpenFile>,
+    next_handle: u64,
+}
+
+impl FsState {
+    fn open_file(&self, handle: FileHandle) -> Result<&OpenFile, FsError> {
+        self.open_files.get(&handle).ok_or(FsError::InvalidHandle)
+    }
+}
+
+pub struct FsServerImpl {
+    state: Mutex<FsState>,
+    disks: Vec<DiskInfo>,
+}
+
+impl FsServerImpl {
+    pub fn new(ramfs: Ramfs, disks: Vec<DiskInfo>) -> Self {
+        FsServerImpl {
+            state: Mutex::new(FsState {
+                ramfs,
+                open_files: HashMap::default(),
+                next_handle: 0,
+            }),
+            disks,
+        }
+    }
+}
+
+#[arpc::service_impl]
+impl FsServer for FsServerImpl {
+    fn open(&self, path: String, flags: OpenFlags) -> Result<FileHandle, FsError> {
+        if !(flags.read || flags.write) || ((flags.create || flags.truncate) && !flags.write) {
+            return Err(FsError::InvalidFlags);
+        }
+
+        let path = normalize_path(&path)?;
+        let mut state = self.state.lock();
+
+        if flags.create {
+            state.ramfs.create_file(&path)?;
+        }
+
+        let file = state.ramfs.file_mut(&path)?;
+        if flags.truncate {
+            file.clear();
+        }
+
+        let handle = FileHandle(state.next_handle);
+        state.next_handle += 1;
+        state.open_files.insert(handle, OpenFile {
+            path,
+            flags,
+        });
+
+        Ok(handle)
+    }
+
+    fn read(&self, handle: FileHandle, offset: u64, len: u32) -> Result<Vec<u8>, FsError> {
+        let state = self.state.lock();
+
+        let open_file = state.open_file(handle)?;
+        if !open_file.flags.read {
+            return Err(FsError::AccessDenied);
+        }
+
+        let Node::File(data) = state.ramfs.lookup(&open_file.path)? else {
+            return Err(FsError::IsDirectory);
+        };
+
+        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(data.len());
+        let end = start.saturating_add(len as usize).min(data.len());
+
+        Ok(data[start..end].to_vec())
+    }
+
+    fn write(&self, handle: FileHandle, offset: u64, data: Vec<u8>) -> Result<u32, FsError> {
+        let written = u32::try_from(data.len()).map_err(|_| FsError::FileTooLarge)?;
+        let start = usize::try_from(offset).map_err(|_| FsError::FileTooLarge)?;
+        let end = start.checked_add(data.len())
+            .filter(|end| *end <= MAX_FILE_SIZE)
+            .ok_or(FsError::FileTooLarge)?;
+
+        let mut state = self.state.lock();
+
+        let open_file = state.open_file(handle)?;
+        if !open_file.flags.write {
+            return Err(FsError::AccessDenied);
+        }
+
+        let path = open_file.path.clone();
+        let file = state.ramfs.file_mut(&path)?;
+
+        if file.len() < end {
+            file.resize(end, 0);
+        }
+        file[start..end].copy_from_slice(&data);
+
+        Ok(written)
+    }
+
+    fn stat(&self, path: String) -> Result<FileInfo, FsError> {
+        let path = normalize_path(&path)?;
+
+        Ok(self.state.lock().ramfs.lookup(&path)?.info())
+    }
+
+    fn close(&self, handle: FileHandle) {
+        self.state.lock().open_files.remove(&handle);
+    }
+
+    fn disks(&self) -> Vec<DiskInfo> {
+        self.disks.clone()
+    }
+}
\ No newline at end of file
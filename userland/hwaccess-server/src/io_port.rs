@@ -0,0 +1,36 @@
+use core::ops::Range;
+
+use aurora::prelude::*;
+use aurora::sync::Mutex;
+
+/// Tracks which ranges of io ports have been claimed by drivers, so 2 drivers can't use the same ports
+pub struct IoPortClaims {
+    claimed: Mutex<Vec<Range<u32>>>,
+}
+
+impl IoPortClaims {
+    pub fn new() -> Self {
+        IoPortClaims {
+            claimed: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Claims every port in `ports`, returns false if any of them are already claimed
+    pub fn try_claim(&self, ports: Range<u32>) -> bool {
+        let mut claimed = self.claimed.lock();
+
+        let overlaps = claimed.iter()
+            .any(|range| range.start < ports.end && ports.start < range.end);
+        if overlaps {
+            return false;
+        }
+
+        claimed.push(ports);
+        true
+    }
+
+    /// Releases a claim previously made with [`try_claim`](Self::try_claim)
+    pub fn release_claim(&self, ports: Range<u32>) {
+        self.claimed.lock().retain(|range| *range != ports);
+    }
+}
\ No newline at end of file
@@ -4,14 +4,19 @@
 #![feature(trait_alias)]
 #![feature(decl_macro)]
 
+extern crate alloc;
+
 mod acpi_handler;
 mod error;
+mod io_port;
 pub mod pci;
 mod pmem_access;
+mod power;
 mod server;
 
 use pmem_access::PmemAccess;
-use sys::PhysMem;
+use alloc::sync::Arc;
+use sys::{PhysMem, Interrupt, IntAllocator, IoPortAccess, IoPort};
 use aurora::prelude::*;
 use aurora::service::AppService;
 use arpc::ServerRpcEndpoint;
@@ -19,7 +24,8 @@ use aurora::sync::Once;
 use sys::{MmioAllocator, Rsdp};
 use arpc::run_rpc_service;
 
-use pci::{Pci, PciDeviceAddress, PciDeviceInfo};
+use pci::{Pci, PciDeviceAddress, PciDeviceInfo, config_space::ConfigWidth};
+use power::PowerControl;
 use server::HwAccessServerImpl;
 
 type AcpiTables = acpi::AcpiTables<acpi_handler::AcpiHandlerImpl>;
@@ -30,7 +36,73 @@ type AcpiTables = acpi::AcpiTables<acpi_handler::AcpiHandlerImpl>;
 pub trait HwAccessServer: AppService {
     fn get_pci_devices(&self) -> Vec<PciDeviceInfo>;
 
-    fn get_pci_mem(&self, device: PciDeviceAddress) -> Option<PhysMem>;
+    /// Claims the given device, and returns a client which can only access that device
+    /// 
+    /// The device stays claimed until the returned client is dropped
+    /// 
+    /// Returns None if the device does not exist or is already claimed
+    fn claim_device(&self, device: PciDeviceAddress) -> Option<PciDevice>;
+
+    /// Claims the `count` io ports starting at `base`, and returns a client which can get an io port capability for them
+    /// 
+    /// The ports stay claimed until the returned client is dropped
+    /// 
+    /// Returns None if `count` is 0, any of the ports are already claimed, or any of the ports are used by the kernel
+    fn claim_io_ports(&self, base: u16, count: u16) -> Option<IoPortRange>;
+
+    /// Powers off the machine
+    /// 
+    /// Uses the acpi S5 sleep state, and falls back to emulator specific ports if that does not work.
+    /// Only returns if the machine could not be powered off.
+    fn shutdown(&self);
+
+    /// Resets the machine
+    /// 
+    /// Uses the acpi reset register, and falls back to resetting with the keyboard controller if that does not work.
+    /// Only returns if the machine could not be reset.
+    fn reboot(&self);
+}
+
+/// Access to a single pci device that has been claimed with [`HwAccessServer::claim_device`]
+#[arpc::service(service_id = 12, name = "PciDevice", AppService = aurora::service)]
+pub trait PciDeviceServer: AppService {
+    fn device_info(&self) -> PciDeviceInfo;
+
+    /// Gets the physical memory of memory bar `bar`, and enables memory space decoding for the device
+    /// 
+    /// Returns None if the bar is not a memory bar, is not implemented, or is not page aligned
+    fn get_bar_mem(&self, bar: u8) -> Option<PhysMem>;
+
+    /// Reads the config space register at `offset`
+    /// 
+    /// Returns None if the offset is unaligned or out of bounds
+    fn read_config(&self, offset: u16, width: ConfigWidth) -> Option<u32>;
+
+    /// Writes the config space register at `offset`
+    /// 
+    /// Returns false if the offset is unaligned or out of bounds,
+    /// or the register is not allowed to be written (such as the bars)
+    fn write_config(&self, offset: u16, width: ConfigWidth, value: u32) -> bool;
+
+    /// Gets an interrupt capability which is triggered by the device
+    /// 
+    /// The device is configured to use msi with a single vector, so repeated calls
+    /// return capabilities for the same interrupt
+    /// 
+    /// Returns None if the device does not support msi
+    fn allocate_interrupt(&self) -> Option<Interrupt>;
+}
+
+/// A range of io ports that has been claimed with [`HwAccessServer::claim_io_ports`]
+#[arpc::service(service_id = 15, name = "IoPortRange", AppService = aurora::service)]
+pub trait IoPortRangeServer: AppService {
+    /// Returns the first port in the range and the number of ports
+    fn range(&self) -> (u16, u16);
+
+    /// Gets an io port capability which can access every port in the range
+    /// 
+    /// Returns None if the capability could not be copied
+    fn get_io_port(&self) -> Option<IoPort>;
 }
 
 static PMEM_ACCESS: Once<PmemAccess> = Once::new();
@@ -39,7 +111,7 @@ pub fn pmem_access() -> &'static PmemAccess {
     PMEM_ACCESS.get().unwrap()
 }
 
-pub fn run(mmio_allocator: MmioAllocator, rsdp: Rsdp, server_endpoint: ServerRpcEndpoint) {
+pub fn run(mmio_allocator: MmioAllocator, int_allocator: IntAllocator, io_port_access: IoPortAccess, rsdp: Rsdp, server_endpoint: ServerRpcEndpoint) {
     PMEM_ACCESS.call_once(|| mmio_allocator.into());
 
     let acpi_tables = unsafe {
@@ -47,7 +119,9 @@ pub fn run(mmio_allocator: MmioAllocator, rsdp: Rsdp, server_endpoint: ServerRpc
     };
 
     let pci = Pci::new(&acpi_tables);
-    let server = HwAccessServerImpl::new(pci);
+    let io_port_access = Arc::new(io_port_access);
+    let power = PowerControl::new(io_port_access.clone(), &acpi_tables);
+    let server = HwAccessServerImpl::new(pci, int_allocator, io_port_access, power);
 
     asynca::block_in_place(run_rpc_service(server_endpoint, server));
 }
\ No newline at end of file
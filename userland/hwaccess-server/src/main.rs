@@ -5,19 +5,20 @@ extern crate std;
 
 use arpc::ServerRpcEndpoint;
 use aurora::env;
-use sys::{MmioAllocator, Rsdp};
+use sys::{MmioAllocator, IntAllocator, IoPortAccess, Rsdp};
 
 fn main() {
     let args = env::args();
 
-    let server_endpoint: ServerRpcEndpoint = args.named_arg("server_endpoint")
-        .expect("provided hwaccess server endpoint is invalid");
+    let server_endpoint: ServerRpcEndpoint = args.require("server_endpoint");
 
-    let mmio_allocator: MmioAllocator = args.named_arg("mmio_allocator")
-        .expect("no mmio allocator provided to hwaccess server");
+    let mmio_allocator: MmioAllocator = args.require("mmio_allocator");
 
-    let rsdp: Rsdp = args.named_arg("rsdp")
-        .expect("no rsdp provided to hwacces-server");
+    let int_allocator: IntAllocator = args.require("int_allocator");
 
-    hwaccess_server::run(mmio_allocator, rsdp, server_endpoint);
+    let io_port_access: IoPortAccess = args.require("io_port_access");
+
+    let rsdp: Rsdp = args.require("rsdp");
+
+    hwaccess_server::run(mmio_allocator, int_allocator, io_port_access, rsdp, server_endpoint);
 }
\ No newline at end of file
@@ -0,0 +1,97 @@
+use bit_utils::PAGE_SIZE;
+
+use super::config_space::{PciConfigSpaceHeader, ConfigWidth, COMMAND_OFFSET};
+
+/// Number of bars in a type 0 config space header
+pub const BAR_COUNT: u8 = 6;
+/// Offset of bar 0 in the config space, the other bars follow it
+const BAR0_OFFSET: u16 = 0x10;
+
+/// Setting this bit in the command register makes the device respond to memory space accesses
+const COMMAND_MEMORY_SPACE: u32 = 1 << 1;
+
+/// Set in io space bars, clear in memory space bars
+const BAR_IO_SPACE: u32 = 1;
+const BAR_TYPE_MASK: u32 = 0b11 << 1;
+const BAR_TYPE_64_BIT: u32 = 0b10 << 1;
+/// Lower bits of memory bars which do not form part of the address
+const BAR_MEMORY_FLAGS_MASK: u32 = 0xf;
+
+/// Physical memory region decoded by a memory bar
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryBar {
+    pub address: usize,
+    pub size: usize,
+}
+
+fn bar_offset(bar: u8) -> u16 {
+    BAR0_OFFSET + 4 * bar as u16
+}
+
+/// Writes all ones to the bar register at `offset` to find which address bits are writable, then restores the old value
+fn probe_bar_register(config_space: &PciConfigSpaceHeader, offset: u16) -> Option<u32> {
+    let old_value = config_space.read_register(offset, ConfigWidth::U32)?;
+
+    config_space.write_register(offset, ConfigWidth::U32, u32::MAX);
+    let mask = config_space.read_register(offset, ConfigWidth::U32)?;
+    config_space.write_register(offset, ConfigWidth::U32, old_value);
+
+    Some(mask)
+}
+
+/// Reads the address and size of memory bar `bar`, and enables memory space decoding for the device
+/// 
+/// Returns None if the bar does not exist, is an io space bar, or is not implemented by the device.
+/// Memory space decoding is disabled while the bar is sized, so this must not race with other accesses to the device.
+pub fn read_memory_bar(config_space: &PciConfigSpaceHeader, bar: u8) -> Option<MemoryBar> {
+    // only type 0 headers have 6 bars
+    config_space.data()?;
+
+    if bar >= BAR_COUNT {
+        return None;
+    }
+
+    let offset = bar_offset(bar);
+    let low = config_space.read_register(offset, ConfigWidth::U32)?;
+    if low & BAR_IO_SPACE != 0 {
+        return None;
+    }
+
+    let is_64_bit = low & BAR_TYPE_MASK == BAR_TYPE_64_BIT;
+    if is_64_bit && bar + 1 >= BAR_COUNT {
+        return None;
+    }
+
+    let command = config_space.read_register(COMMAND_OFFSET, ConfigWidth::U16)?;
+    config_space.write_register(COMMAND_OFFSET, ConfigWidth::U16, command & !COMMAND_MEMORY_SPACE);
+
+    let low_mask = probe_bar_register(config_space, offset);
+    let high_mask = if is_64_bit {
+        probe_bar_register(config_space, offset + 4)
+    } else {
+        // upper bits of 32 bit bars are implicitly all ones
+        Some(u32::MAX)
+    };
+
+    config_space.write_register(COMMAND_OFFSET, ConfigWidth::U16, command | COMMAND_MEMORY_SPACE);
+
+    let low_mask = low_mask? & !BAR_MEMORY_FLAGS_MASK;
+    let high_mask = high_mask?;
+    if low_mask == 0 && (!is_64_bit || high_mask == 0) {
+        // no address bits are writable, so the bar is not implemented
+        return None;
+    }
+    let mask = ((high_mask as u64) << 32) | low_mask as u64;
+
+    let address = if is_64_bit {
+        let high = config_space.read_register(offset + 4, ConfigWidth::U32)?;
+        ((high as u64) << 32) | (low & !BAR_MEMORY_FLAGS_MASK) as u64
+    } else {
+        (low & !BAR_MEMORY_FLAGS_MASK) as u64
+    };
+
+    Some(MemoryBar {
+        address: address as usize,
+        size: ((!mask).wrapping_add(1) as usize).max(PAGE_SIZE),
+    })
+}
\ No newline at end of file
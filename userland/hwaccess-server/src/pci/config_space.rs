@@ -1,13 +1,55 @@
-use core::ptr::NonNull;
+use core::ptr::{self, NonNull};
 
+use serde::{Serialize, Deserialize};
 use volatile::{VolatilePtr, map_field};
 
 pub const CONFIG_SPACE_SIZE: usize = 4096;
 
+/// The raw memory of one pci config space
+pub type RawConfigSpace = [u8; CONFIG_SPACE_SIZE];
+
 pub const VENDOR_ID_INVALID: u16 = 0xffff;
 
 pub const STATUS_HAS_CAPABILITIES: u16 = 1 << 4;
 
+/// Offset of the command register in the config space
+pub const COMMAND_OFFSET: u16 = 0x4;
+/// Offset of the first byte after the status register
+pub const STATUS_END_OFFSET: u16 = 0x8;
+/// Offset of the first byte after the standard header, everything after this is capabilities or device specific registers
+pub const STANDARD_HEADER_END_OFFSET: u16 = 0x40;
+
+/// Width of a config space register access
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfigWidth {
+    U8,
+    U16,
+    U32,
+}
+
+impl ConfigWidth {
+    /// Size of the access in bytes
+    pub fn size(&self) -> u16 {
+        match self {
+            Self::U8 => 1,
+            Self::U16 => 2,
+            Self::U32 => 4,
+        }
+    }
+}
+
+/// Returns true if other processes are allowed to write to the config space register at `offset`
+/// 
+/// Only the command and status registers, and registers after the standard header are writable.
+/// Notably this means the bars can't be written, since hwaccess-server is in charge of bar assignment.
+pub fn is_config_write_allowed(offset: u16, width: ConfigWidth) -> bool {
+    let Some(end_offset) = offset.checked_add(width.size()) else {
+        return false;
+    };
+
+    (offset >= COMMAND_OFFSET && end_offset <= STATUS_END_OFFSET) || offset >= STANDARD_HEADER_END_OFFSET
+}
+
 // FIXME: get this to be packed without causing compile error in map_field macro
 #[repr(C)]
 struct PciConfigSpaceHeaderRaw {
@@ -42,6 +84,54 @@ impl PciConfigSpaceHeader {
         self.0.as_raw_ptr().as_ptr() as usize
     }
 
+    /// Returns the address of the register at `offset`, or None if the offset is unaligned or out of bounds
+    fn register_address(&self, offset: u16, width: ConfigWidth) -> Option<usize> {
+        let offset = offset as usize;
+        let size = width.size() as usize;
+
+        if offset % size != 0 || offset + size > CONFIG_SPACE_SIZE {
+            None
+        } else {
+            Some(self.virtual_address() + offset)
+        }
+    }
+
+    /// Reads the register at `offset`, returns None if the offset is unaligned or out of bounds
+    pub fn read_register(&self, offset: u16, width: ConfigWidth) -> Option<u32> {
+        let address = self.register_address(offset, width)?;
+
+        // safety: register_address checks the register is inside the config space
+        let value = unsafe {
+            match width {
+                ConfigWidth::U8 => ptr::read_volatile(address as *const u8) as u32,
+                ConfigWidth::U16 => ptr::read_volatile(address as *const u16) as u32,
+                ConfigWidth::U32 => ptr::read_volatile(address as *const u32),
+            }
+        };
+
+        Some(value)
+    }
+
+    /// Writes the register at `offset`, returns false if the offset is unaligned or out of bounds
+    /// 
+    /// Upper bits of `value` which don't fit in `width` are ignored
+    pub fn write_register(&self, offset: u16, width: ConfigWidth, value: u32) -> bool {
+        let Some(address) = self.register_address(offset, width) else {
+            return false;
+        };
+
+        // safety: register_address checks the register is inside the config space
+        unsafe {
+            match width {
+                ConfigWidth::U8 => ptr::write_volatile(address as *mut u8, value as u8),
+                ConfigWidth::U16 => ptr::write_volatile(address as *mut u16, value as u16),
+                ConfigWidth::U32 => ptr::write_volatile(address as *mut u32, value),
+            }
+        }
+
+        true
+    }
+
     pub fn vendor_id(&self) -> u16 {
         let ptr = self.0;
         map_field!(ptr.vendor_id).read()
@@ -98,6 +188,19 @@ impl PciConfigSpaceHeader {
         })
     }
 
+    /// Finds the first capability with the given capability id
+    pub fn find_capability(&self, capability_id: u8) -> Option<PciCapability> {
+        let mut capability = self.capabilities()?;
+
+        loop {
+            if capability.capability_id() == capability_id {
+                return Some(capability);
+            }
+
+            capability = capability.next_capability()?;
+        }
+    }
+
     pub fn data(&self) -> Option<VolatilePtr<PciConfigSpaceData>> {
         let ptr = self.0;
         // bit 7 indicates if multiple function device, ignore that bit
@@ -154,7 +257,7 @@ impl<'a> PciCapability<'a> {
             return None;
         }
 
-        sys::dprintln!("next cap: {next_capability}");
+        aurora::log::debug!("next cap: {next_capability}");
 
         let capability_address = self.config_space_header.virtual_address() + next_capability as usize;
 
@@ -170,6 +273,12 @@ impl<'a> PciCapability<'a> {
         })
     }
 
+    /// Offset of this capability from the start of the config space
+    pub fn offset(&self) -> u16 {
+        let address = self.capability_header.as_raw_ptr().as_ptr() as usize;
+        (address - self.config_space_header.virtual_address()) as u16
+    }
+
     pub fn capability_id(&self) -> u8 {
         let ptr = self.capability_header;
         map_field!(ptr.capability_id).read()
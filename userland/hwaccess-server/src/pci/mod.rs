@@ -1,14 +1,19 @@
+mod bar;
 pub mod config_space;
+mod msi;
+
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use serde::{Serialize, Deserialize};
 use acpi::mcfg::Mcfg;
-use bit_utils::Size;
-use aurora::{this_context, addr_space, allocator::addr_space::{MapPhysMemArgs, RegionPadding}};
+use bit_utils::{Size, PAGE_SIZE, align_up};
+use aurora::{this_context, allocator::addr_space::{MappedMemory, map_slice}};
 use aurora::prelude::*;
-use sys::{PhysMem, MemoryMappingOptions, MemoryCacheSetting};
+use aurora::sync::Mutex;
+use sys::{PhysMem, MemoryMappingOptions, MemoryCacheSetting, IntAllocator, Interrupt, CapFlags, CspaceTarget, cap_clone};
 
 use crate::{AcpiTables, pmem_access};
-use config_space::{PciConfigSpaceHeader, CONFIG_SPACE_SIZE, VENDOR_ID_INVALID};
+use config_space::{PciConfigSpaceHeader, RawConfigSpace, ConfigWidth, CONFIG_SPACE_SIZE, VENDOR_ID_INVALID, is_config_write_allowed};
 
 pub const DEVICE_PER_BUS: usize = 32;
 pub const FUNCTION_PER_DEVICE: usize = 8;
@@ -53,12 +58,15 @@ pub struct PciDevice {
     device_address: PciDeviceAddress,
     device_id: PciDeviceId,
     device_type: PciDeviceType,
-    mmio_phys_addr: usize,
     config_space: PciConfigSpaceHeader,
+    /// Interrupt the device's msi capability has been programmed to deliver to, if one has been allocated
+    interrupt: Mutex<Option<Interrupt>>,
+    /// Set while a process has claimed this device
+    claimed: AtomicBool,
 }
 
 impl PciDevice {
-    unsafe fn new(device_address: PciDeviceAddress, config_space: PciConfigSpaceHeader, mmio_phys_addr: usize) -> Option<Self> {
+    unsafe fn new(device_address: PciDeviceAddress, config_space: PciConfigSpaceHeader) -> Option<Self> {
         let vendor_id = config_space.vendor_id();
         if vendor_id == VENDOR_ID_INVALID {
             None
@@ -78,8 +86,9 @@ impl PciDevice {
                 device_address,
                 device_id,
                 device_type,
-                mmio_phys_addr,
                 config_space,
+                interrupt: Mutex::new(None),
+                claimed: AtomicBool::new(false),
             })
         }
     }
@@ -104,15 +113,85 @@ impl PciDevice {
         }
     }
 
-    pub fn get_phys_mem(&self) -> PhysMem {
+    /// Marks the device as claimed, returns false if it was already claimed
+    pub fn try_claim(&self) -> bool {
+        self.claimed.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).is_ok()
+    }
+
+    /// Releases a claim previously made with [`try_claim`](Self::try_claim)
+    pub fn release_claim(&self) {
+        self.claimed.store(false, Ordering::Release);
+    }
+
+    /// Reads the config space register at `offset`, returns None if the offset is unaligned or out of bounds
+    pub fn read_config(&self, offset: u16, width: ConfigWidth) -> Option<u32> {
+        self.config_space.read_register(offset, width)
+    }
+
+    /// Writes the config space register at `offset`
+    /// 
+    /// Returns false if the offset is unaligned or out of bounds, or if the register is not allowed to be written,
+    /// see [`is_config_write_allowed`] for which registers can be written
+    pub fn write_config(&self, offset: u16, width: ConfigWidth, value: u32) -> bool {
+        if !is_config_write_allowed(offset, width) {
+            return false;
+        }
+
+        self.config_space.write_register(offset, width, value)
+    }
+
+    /// Gets an interrupt which is triggered when the device signals an interrupt
+    /// 
+    /// The device is configured to use msi with a single vector, so every call returns a clone of the same interrupt.
+    /// Returns None if the device does not support msi, or allocating the interrupt failed.
+    // TODO: fall back to legacy INTx interrupts routed through the io apic for devices without msi
+    pub fn allocate_interrupt(&self, int_allocator: &IntAllocator) -> Option<Interrupt> {
+        let mut interrupt = self.interrupt.lock();
+
+        if interrupt.is_none() {
+            let msi_offset = self.config_space.find_capability(msi::CAPABILITY_ID_MSI)?.offset();
+
+            let (new_interrupt, interrupt_id) = int_allocator.create_interrupt(&this_context().allocator).ok()?;
+            msi::configure_msi(&self.config_space, msi_offset, interrupt_id);
+
+            *interrupt = Some(new_interrupt);
+        }
+
+        // panic safety: interrupt was set above if it did not exist
+        cap_clone(
+            CspaceTarget::Current,
+            CspaceTarget::Current,
+            interrupt.as_ref().unwrap(),
+            CapFlags::READ | CapFlags::PROD,
+        ).ok()
+    }
+
+    /// Gets the physical memory decoded by memory bar `bar`, and enables memory space decoding for the device
+    /// 
+    /// Returns None if the bar is not a memory bar, is not implemented, or is not page aligned
+    pub fn get_bar_mem(&self, bar: u8) -> Option<PhysMem> {
+        let memory_bar = bar::read_memory_bar(&self.config_space, bar)?;
+
+        // physical memory capabilities can only reference whole pages
+        // TODO: support small bars which share a page with other bars
+        if memory_bar.address % PAGE_SIZE != 0 {
+            return None;
+        }
+
         pmem_access().allocator
-            .alloc(&this_context().allocator, self.mmio_phys_addr, Size::from_bytes(CONFIG_SPACE_SIZE))
-            .expect("could not get phys mem for pci device")
+            .alloc(
+                &this_context().allocator,
+                memory_bar.address,
+                Size::from_bytes(align_up(memory_bar.size, PAGE_SIZE)),
+            )
+            .ok()
     }
 }
 
 pub struct Pci {
     devices: Vec<PciDevice>,
+    /// Mappings of the config spaces of every pci bus, these must be kept alive because the devices reference them
+    _config_space_mappings: Vec<MappedMemory<[RawConfigSpace]>>,
 }
 
 impl Pci {
@@ -121,6 +200,7 @@ impl Pci {
             .expect("could not find mcfg table");
 
         let mut devices = Vec::new();
+        let mut config_space_mappings = Vec::new();
     
         for entry in mcfg.entries() {
             // map entry in memory
@@ -132,16 +212,11 @@ impl Pci {
                 .alloc(&this_context().allocator, entry.base_address as usize, entry_size)
                 .expect("could not get physmem for pci config spaces");
     
-            let map_result = addr_space().map_phys_mem(MapPhysMemArgs {
-                phys_mem,
-                options: MemoryMappingOptions {
-                    read: true,
-                    write: true,
-                    cacheing: MemoryCacheSetting::Uncached,
-                    ..Default::default()
-                },
-                address: None,
-                padding: RegionPadding::default(),
+            let config_spaces = map_slice::<RawConfigSpace, _>(&phys_mem, 0, entry_count, MemoryMappingOptions {
+                read: true,
+                write: true,
+                cacheing: MemoryCacheSetting::Uncached,
+                ..Default::default()
             }).expect("could not map physical memory for acpi config space");
     
             // TODO: figure out if bus_number_end is inclusive or exclusive
@@ -151,7 +226,7 @@ impl Pci {
                 for device_id in 0..DEVICE_PER_BUS {
                     for function in 0..FUNCTION_PER_DEVICE {
                         let index = bus_index as usize * (DEVICE_PER_BUS * FUNCTION_PER_DEVICE) + device_id * FUNCTION_PER_DEVICE + function;
-                        let config_space_address = map_result.address + CONFIG_SPACE_SIZE * index;
+                        let config_space_address = config_spaces.address() + CONFIG_SPACE_SIZE * index;
     
                         let config_space = unsafe {
                             PciConfigSpaceHeader::from_addr(config_space_address)
@@ -164,9 +239,8 @@ impl Pci {
                             function_id: function as u8,
                         };
 
-                        let mmio_phys_addr = entry.base_address as usize + CONFIG_SPACE_SIZE * index;
                         let device = unsafe {
-                            PciDevice::new(device_address, config_space, mmio_phys_addr)
+                            PciDevice::new(device_address, config_space)
                         };
     
                         if let Some(device) = device {
@@ -175,10 +249,13 @@ impl Pci {
                     }
                 }
             }
+
+            config_space_mappings.push(config_spaces);
         }
 
         Pci {
-            devices
+            devices,
+            _config_space_mappings: config_space_mappings,
         }
     }
 
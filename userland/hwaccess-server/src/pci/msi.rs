@@ -0,0 +1,54 @@
+use sys::InterruptId;
+
+use super::config_space::{PciConfigSpaceHeader, ConfigWidth, COMMAND_OFFSET};
+
+/// Capability id of the message signaled interrupts capability
+pub const CAPABILITY_ID_MSI: u8 = 0x5;
+
+/// Setting this bit in the command register disables legacy INTx interrupts
+const COMMAND_INTERRUPT_DISABLE: u32 = 1 << 10;
+
+// offsets of msi registers from the start of the msi capability
+const MSI_CONTROL_OFFSET: u16 = 0x2;
+const MSI_ADDRESS_LOW_OFFSET: u16 = 0x4;
+const MSI_ADDRESS_HIGH_OFFSET: u16 = 0x8;
+const MSI_DATA_32_BIT_OFFSET: u16 = 0x8;
+const MSI_DATA_64_BIT_OFFSET: u16 = 0xc;
+
+const MSI_CONTROL_ENABLE: u32 = 1;
+/// Bits 4-6 of the control register say how many vectors the device is allowed to use
+const MSI_CONTROL_MULTIPLE_MESSAGE_ENABLE_MASK: u32 = 0b111 << 4;
+const MSI_CONTROL_64_BIT: u32 = 1 << 7;
+
+/// Base of the address range which local apics recieve msi writes on
+const MSI_ADDRESS_BASE: u32 = 0xfee00000;
+
+/// Programs the msi capability at `capability_offset` to deliver interrupts to `interrupt_id`, and enables msi
+/// 
+/// Only a single vector is used, even if the device supports multiple messages
+pub fn configure_msi(config_space: &PciConfigSpaceHeader, capability_offset: u16, interrupt_id: InterruptId) {
+    let control_offset = capability_offset + MSI_CONTROL_OFFSET;
+    let control = config_space.read_register(control_offset, ConfigWidth::U16)
+        .expect("invalid msi capability offset");
+
+    // fixed delivery mode, physical destination mode, edge triggered
+    let address = MSI_ADDRESS_BASE | ((interrupt_id.apic_id as u32 & 0xff) << 12);
+    let data = interrupt_id.interrupt_num as u32 & 0xff;
+
+    config_space.write_register(capability_offset + MSI_ADDRESS_LOW_OFFSET, ConfigWidth::U32, address);
+
+    if control & MSI_CONTROL_64_BIT != 0 {
+        config_space.write_register(capability_offset + MSI_ADDRESS_HIGH_OFFSET, ConfigWidth::U32, 0);
+        config_space.write_register(capability_offset + MSI_DATA_64_BIT_OFFSET, ConfigWidth::U16, data);
+    } else {
+        config_space.write_register(capability_offset + MSI_DATA_32_BIT_OFFSET, ConfigWidth::U16, data);
+    }
+
+    let control = (control & !MSI_CONTROL_MULTIPLE_MESSAGE_ENABLE_MASK) | MSI_CONTROL_ENABLE;
+    config_space.write_register(control_offset, ConfigWidth::U16, control);
+
+    // legacy interrupts would be redundant now
+    let command = config_space.read_register(COMMAND_OFFSET, ConfigWidth::U16)
+        .expect("command register should always be readable");
+    config_space.write_register(COMMAND_OFFSET, ConfigWidth::U16, command | COMMAND_INTERRUPT_DISABLE);
+}
\ No newline at end of file
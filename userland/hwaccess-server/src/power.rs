@@ -0,0 +1,269 @@
+//! Shutting down and rebooting the machine
+//!
+//! There is no aml interpreter, so the sleep type of the S5 (soft off) state is found by looking for
+//! the `_S5_` package in the dsdt, which only works when the package contains constants.
+//! When acpi can't be used, shutdown falls back to emulator specific ports,
+//! and reboot falls back to resetting the cpu with the keyboard controller.
+
+use alloc::sync::Arc;
+use core::slice;
+use core::time::Duration;
+
+use acpi::address::{AddressSpace, GenericAddress};
+use acpi::fadt::Fadt;
+use aurora::{addr_space, this_context};
+use aurora::log::warn;
+use aurora::time::sleep;
+use bit_utils::Size;
+use sys::{IoPortAccess, IoPort, KResult};
+
+use crate::{AcpiTables, pmem_access};
+
+/// Sleep enable bit of the pm1 control registers, setting it enters the sleep state in the sleep type bits
+const SLP_EN: u16 = 1 << 13;
+const SLP_TYP_SHIFT: u16 = 10;
+const SLP_TYP_MASK: u16 = 0b111 << SLP_TYP_SHIFT;
+
+/// Aml opcodes used in the declaration of the `_S5_` package
+const NAME_OP: u8 = 0x08;
+const ROOT_CHAR: u8 = b'\\';
+const PACKAGE_OP: u8 = 0x12;
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const BYTE_PREFIX: u8 = 0x0a;
+
+const KEYBOARD_CONTROLLER_PORT: u16 = 0x64;
+/// Set in the keyboard controller status while it has not read the last command yet
+const KEYBOARD_CONTROLLER_INPUT_FULL: u8 = 1 << 1;
+/// Keyboard controller command which pulses the cpu reset line
+const KEYBOARD_CONTROLLER_RESET: u8 = 0xfe;
+/// Number of times to check if the keyboard controller is ready before sending the reset command anyway
+const KEYBOARD_CONTROLLER_POLL_COUNT: usize = 0x10000;
+
+/// Pm1a control ports of emulators, and the value which powers them off
+const EMULATOR_POWER_OFF_PORTS: [(u16, u16); 2] = [
+    // qemu
+    (0x604, 0x2000),
+    // bochs and old versions of qemu
+    (0xb004, 0x2000),
+];
+/// Port of qemu's `isa-debug-exit` device, this must match the port run.sh configures
+const QEMU_EXIT_PORT: u16 = 0xf4;
+/// Makes qemu exit with status 33, which run.sh treats as a successful run
+const QEMU_EXIT_SUCCESS: u32 = 0x10;
+
+/// How long to wait for the machine to turn off or reset before trying the next method
+///
+/// The emulator or chipset may take a moment to act on the write.
+const POWER_CHANGE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Pm1 control registers and the values to write to them to enter the S5 sleep state
+#[derive(Debug, Clone, Copy)]
+struct SoftOffRegisters {
+    pm1a_control: u16,
+    pm1b_control: Option<u16>,
+    slp_typa: u16,
+    slp_typb: u16,
+}
+
+/// Turns off and resets the machine
+///
+/// Io ports are only created for the registers when they are used, so they are not claimed,
+/// and drivers can still claim the keyboard controller.
+pub struct PowerControl {
+    io_port_access: Arc<IoPortAccess>,
+    /// None if the pm1 control registers are not io ports, or the S5 sleep type could not be found
+    soft_off: Option<SoftOffRegisters>,
+    /// Io port of the fadt reset register, and the value to write to it
+    reset_register: Option<(u16, u8)>,
+}
+
+impl PowerControl {
+    pub fn new(io_port_access: Arc<IoPortAccess>, acpi_tables: &AcpiTables) -> Self {
+        let Ok(fadt) = acpi_tables.find_table::<Fadt>() else {
+            warn!("could not find fadt, only emulator specific shutdown and reboot methods will be used");
+
+            return PowerControl {
+                io_port_access,
+                soft_off: None,
+                reset_register: None,
+            };
+        };
+
+        let soft_off = read_soft_off_registers(&fadt, acpi_tables);
+        if soft_off.is_none() {
+            warn!("could not find acpi soft off registers, shutdown will only use emulator specific methods");
+        }
+
+        let reset_register = fadt.reset_register()
+            .ok()
+            .and_then(io_port_address)
+            .map(|port| (port, fadt.reset_value));
+
+        PowerControl {
+            io_port_access,
+            soft_off,
+            reset_register,
+        }
+    }
+
+    /// Turns off the machine, only returns if every shutdown method failed
+    pub fn shutdown(&self) {
+        if let Some(soft_off) = self.soft_off {
+            match self.enter_soft_off(soft_off) {
+                Ok(()) => sleep(POWER_CHANGE_TIMEOUT),
+                Err(error) => warn!("failed to write acpi pm1 control registers: {error}"),
+            }
+        }
+
+        for (port, value) in EMULATOR_POWER_OFF_PORTS {
+            if self.io_port(port, 2).and_then(|io_port| io_port.write16(port, value)).is_ok() {
+                sleep(POWER_CHANGE_TIMEOUT);
+            }
+        }
+
+        if self.io_port(QEMU_EXIT_PORT, 4).and_then(|io_port| io_port.write32(QEMU_EXIT_PORT, QEMU_EXIT_SUCCESS)).is_ok() {
+            sleep(POWER_CHANGE_TIMEOUT);
+        }
+    }
+
+    /// Resets the machine, only returns if every reset method failed
+    pub fn reboot(&self) {
+        if let Some((port, value)) = self.reset_register {
+            match self.io_port(port, 1).and_then(|io_port| io_port.write8(port, value)) {
+                Ok(()) => sleep(POWER_CHANGE_TIMEOUT),
+                Err(error) => warn!("failed to write acpi reset register: {error}"),
+            }
+        }
+
+        match self.keyboard_controller_reset() {
+            Ok(()) => sleep(POWER_CHANGE_TIMEOUT),
+            Err(error) => warn!("failed to reset with keyboard controller: {error}"),
+        }
+    }
+
+    /// Creates an io port capability for the `count` ports starting at `base`
+    fn io_port(&self, base: u16, count: u16) -> KResult<IoPort> {
+        self.io_port_access.new_port(&this_context().allocator, base, count)
+    }
+
+    fn enter_soft_off(&self, soft_off: SoftOffRegisters) -> KResult<()> {
+        let write_sleep_type = |port: u16, sleep_type: u16| -> KResult<()> {
+            let io_port = self.io_port(port, 2)?;
+            let value = io_port.read16(port)? & !SLP_TYP_MASK;
+            let sleep_type = (sleep_type << SLP_TYP_SHIFT) & SLP_TYP_MASK;
+
+            io_port.write16(port, value | sleep_type | SLP_EN)
+        };
+
+        // the pm1b register is written first, since the machine may turn off as soon as pm1a is written
+        if let Some(pm1b_control) = soft_off.pm1b_control {
+            write_sleep_type(pm1b_control, soft_off.slp_typb)?;
+        }
+
+        write_sleep_type(soft_off.pm1a_control, soft_off.slp_typa)
+    }
+
+    fn keyboard_controller_reset(&self) -> KResult<()> {
+        let io_port = self.io_port(KEYBOARD_CONTROLLER_PORT, 1)?;
+
+        for _ in 0..KEYBOARD_CONTROLLER_POLL_COUNT {
+            if io_port.read8(KEYBOARD_CONTROLLER_PORT)? & KEYBOARD_CONTROLLER_INPUT_FULL == 0 {
+                break;
+            }
+        }
+
+        io_port.write8(KEYBOARD_CONTROLLER_PORT, KEYBOARD_CONTROLLER_RESET)
+    }
+}
+
+/// Returns the io port `address` refers to, or None if it is not in io space
+fn io_port_address(address: GenericAddress) -> Option<u16> {
+    if matches!(address.address_space, AddressSpace::SystemIo) && address.address != 0 {
+        u16::try_from(address.address).ok()
+    } else {
+        None
+    }
+}
+
+fn read_soft_off_registers(fadt: &Fadt, acpi_tables: &AcpiTables) -> Option<SoftOffRegisters> {
+    let pm1a_control = io_port_address(fadt.pm1a_control_block().ok()?)?;
+    let pm1b_control = match fadt.pm1b_control_block().ok()? {
+        Some(address) => Some(io_port_address(address)?),
+        None => None,
+    };
+
+    let dsdt = acpi_tables.dsdt().ok()?;
+    let dsdt_size = dsdt.length as usize;
+    let dsdt_mapping = pmem_access()
+        .map_address_raw(dsdt.address, Size::from_bytes(dsdt_size))
+        .ok()?;
+
+    // safety: the whole dsdt was just mapped, and it is unmapped after the slice is last used
+    let aml = unsafe {
+        slice::from_raw_parts((dsdt_mapping.base_virt_address + dsdt_mapping.data_offset) as *const u8, dsdt_size)
+    };
+    let sleep_types = find_s5_sleep_types(aml);
+
+    unsafe {
+        addr_space().unmap_memory(dsdt_mapping.base_virt_address)
+            .expect("could not unmap dsdt");
+    }
+
+    let (slp_typa, slp_typb) = sleep_types?;
+
+    Some(SoftOffRegisters {
+        pm1a_control,
+        pm1b_control,
+        slp_typa,
+        slp_typb,
+    })
+}
+
+/// Finds the `Name(_S5, Package() { SLP_TYPa, SLP_TYPb, ... })` declaration in `aml`, and returns the sleep types in it
+///
+/// Returns None if the package is not declared, or the sleep types are not constants
+fn find_s5_sleep_types(aml: &[u8]) -> Option<(u16, u16)> {
+    let mut rest = aml;
+    let package = loop {
+        let index = rest.windows(4).position(|window| window == b"_S5_")?;
+        let before = &rest[..index];
+        rest = &rest[(index + 4)..];
+
+        // the name is also used in places other than its declaration
+        if before.ends_with(&[NAME_OP]) || before.ends_with(&[NAME_OP, ROOT_CHAR]) {
+            break rest;
+        }
+    };
+
+    let (&opcode, package) = package.split_first()?;
+    if opcode != PACKAGE_OP {
+        return None;
+    }
+
+    // the top 2 bits of the first byte of the package length are the number of bytes which follow it
+    let package_length_size = 1 + (*package.first()? >> 6) as usize;
+    // skip the package length and the element count
+    let mut elements = package.get((package_length_size + 1)..)?;
+
+    let mut next_element = || -> Option<u16> {
+        let (&opcode, rest) = elements.split_first()?;
+        let (value, rest) = match opcode {
+            ZERO_OP => (0, rest),
+            ONE_OP => (1, rest),
+            BYTE_PREFIX => {
+                let (&value, rest) = rest.split_first()?;
+                (value, rest)
+            },
+            _ => return None,
+        };
+
+        elements = rest;
+        Some(value as u16)
+    };
+
+    let slp_typa = next_element()?;
+    let slp_typb = next_element()?;
+
+    Some((slp_typa, slp_typb))
+}
\ No newline at end of file
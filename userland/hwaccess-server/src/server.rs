@@ -1,18 +1,32 @@
+use alloc::sync::Arc;
+use core::ops::Range;
+
 use aurora::prelude::*;
+use aurora::this_context;
 use aurora::service::{AppService, Service, NamedPermission};
-use sys::{PhysMem, Key};
+use sys::{PhysMem, Key, IntAllocator, Interrupt, IoPortAccess, IoPort, CspaceTarget, CapFlags, cap_clone};
 
-use crate::HwAccessServer;
-use crate::pci::{PciDeviceAddress, PciDeviceInfo, Pci};
+use crate::{HwAccessServer, PciDeviceServer, PciDevice, IoPortRangeServer, IoPortRange};
+use crate::io_port::IoPortClaims;
+use crate::pci::{PciDeviceAddress, PciDeviceInfo, Pci, config_space::ConfigWidth};
+use crate::power::PowerControl;
 
 pub struct HwAccessServerImpl {
-    pci_devices: Pci,
+    pci_devices: Arc<Pci>,
+    int_allocator: Arc<IntAllocator>,
+    io_port_access: Arc<IoPortAccess>,
+    io_port_claims: Arc<IoPortClaims>,
+    power: PowerControl,
 }
 
 impl HwAccessServerImpl {
-    pub fn new(pci_devices: Pci) -> Self {
+    pub fn new(pci_devices: Pci, int_allocator: IntAllocator, io_port_access: Arc<IoPortAccess>, power: PowerControl) -> Self {
         HwAccessServerImpl {
-            pci_devices,
+            pci_devices: Arc::new(pci_devices),
+            int_allocator: Arc::new(int_allocator),
+            io_port_access,
+            io_port_claims: Arc::new(IoPortClaims::new()),
+            power,
         }
     }
 }
@@ -22,8 +36,9 @@ impl AppService for HwAccessServerImpl {
         Vec::new()
     }
 
-    fn new_session_permissions(&self, perms: Vec<Key>) -> Service {
-        todo!()
+    fn new_session_permissions(&self, _perms: Vec<Key>) -> Option<Service> {
+        // there are no permissions yet, so there is nothing to restrict a new session to
+        None
     }
 }
 
@@ -39,7 +54,136 @@ impl HwAccessServer for HwAccessServerImpl {
         out
     }
 
-    fn get_pci_mem(&self, device: PciDeviceAddress) -> Option<PhysMem> {
-        Some(self.pci_devices.get_device(device)?.get_phys_mem())
+    fn claim_device(&self, device: PciDeviceAddress) -> Option<PciDevice> {
+        if !self.pci_devices.get_device(device)?.try_claim() {
+            return None;
+        }
+
+        let device_server = PciDeviceServerImpl {
+            device_address: device,
+            pci_devices: self.pci_devices.clone(),
+            int_allocator: self.int_allocator.clone(),
+        };
+
+        // the rpc service task exits once the client is dropped, which drops the device server and releases the claim
+        // if launching the service fails the device server is dropped right away, so the claim is also released
+        arpc::launch_service(device_server).ok()
+    }
+
+    fn claim_io_ports(&self, base: u16, count: u16) -> Option<IoPortRange> {
+        // creating the capability first checks that the kernel allows the ports to be used
+        let io_port = self.io_port_access.new_port(&this_context().allocator, base, count).ok()?;
+
+        let ports = (base as u32)..(base as u32 + count as u32);
+        if !self.io_port_claims.try_claim(ports.clone()) {
+            return None;
+        }
+
+        let range_server = IoPortRangeServerImpl {
+            ports,
+            io_port,
+            io_port_claims: self.io_port_claims.clone(),
+        };
+
+        // like with pci devices, the claim is released when the range server is dropped
+        arpc::launch_service(range_server).ok()
+    }
+
+    fn shutdown(&self) {
+        self.power.shutdown();
+    }
+
+    fn reboot(&self) {
+        self.power.reboot();
+    }
+}
+
+/// Serves a single pci device to the process which claimed it
+pub struct PciDeviceServerImpl {
+    device_address: PciDeviceAddress,
+    pci_devices: Arc<Pci>,
+    int_allocator: Arc<IntAllocator>,
+}
+
+impl PciDeviceServerImpl {
+    fn device(&self) -> &crate::pci::PciDevice {
+        // panic safety: this server is only created for devices which exist
+        self.pci_devices.get_device(self.device_address).unwrap()
+    }
+}
+
+impl Drop for PciDeviceServerImpl {
+    fn drop(&mut self) {
+        self.device().release_claim();
+    }
+}
+
+impl AppService for PciDeviceServerImpl {
+    fn get_permissions(&self) -> Vec<NamedPermission> {
+        Vec::new()
+    }
+
+    fn new_session_permissions(&self, _perms: Vec<Key>) -> Option<Service> {
+        // another session would let a second process use the device, which claiming is meant to prevent
+        None
+    }
+}
+
+#[arpc::service_impl]
+impl PciDeviceServer for PciDeviceServerImpl {
+    fn device_info(&self) -> PciDeviceInfo {
+        self.device().device_info()
+    }
+
+    fn get_bar_mem(&self, bar: u8) -> Option<PhysMem> {
+        self.device().get_bar_mem(bar)
+    }
+
+    fn read_config(&self, offset: u16, width: ConfigWidth) -> Option<u32> {
+        self.device().read_config(offset, width)
+    }
+
+    fn write_config(&self, offset: u16, width: ConfigWidth, value: u32) -> bool {
+        self.device().write_config(offset, width, value)
+    }
+
+    fn allocate_interrupt(&self) -> Option<Interrupt> {
+        self.device().allocate_interrupt(&self.int_allocator)
+    }
+}
+
+/// Serves a claimed range of io ports to the process which claimed it
+pub struct IoPortRangeServerImpl {
+    ports: Range<u32>,
+    io_port: IoPort,
+    io_port_claims: Arc<IoPortClaims>,
+}
+
+impl Drop for IoPortRangeServerImpl {
+    fn drop(&mut self) {
+        self.io_port_claims.release_claim(self.ports.clone());
+    }
+}
+
+impl AppService for IoPortRangeServerImpl {
+    fn get_permissions(&self) -> Vec<NamedPermission> {
+        Vec::new()
+    }
+
+    fn new_session_permissions(&self, _perms: Vec<Key>) -> Option<Service> {
+        // another session would let a second process use the claimed io ports
+        None
+    }
+}
+
+#[arpc::service_impl]
+impl IoPortRangeServer for IoPortRangeServerImpl {
+    fn range(&self) -> (u16, u16) {
+        // panic safety: the range was made from a u16 base and count
+        (self.ports.start as u16, (self.ports.end - self.ports.start) as u16)
+    }
+
+    fn get_io_port(&self) -> Option<IoPort> {
+        cap_clone(CspaceTarget::Current, CspaceTarget::Current, &self.io_port, CapFlags::all()).ok()
     }
 }
\ No newline at end of file
@@ -0,0 +1,450 @@
+//! Parser for the initrd, which is a ustar archive
+//!
+//! This is shared by the kernel, which finds early-init in the initrd, and early-init, which loads everything else.
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt::{self, Display};
+use core::str;
+
+const BLOCK_SIZE: usize = 512;
+
+const NAME_FIELD: (usize, usize) = (0, 100);
+const SIZE_FIELD: (usize, usize) = (124, 12);
+const CHECKSUM_FIELD: (usize, usize) = (148, 8);
+const TYPEFLAG_OFFSET: usize = 156;
+const MAGIC_FIELD: (usize, usize) = (257, 5);
+const PREFIX_FIELD: (usize, usize) = (345, 155);
+
+const USTAR_MAGIC: &[u8] = b"ustar";
+
+/// Type flags used for regular files, old archivers use a nul byte instead of '0'
+const REGULAR_FILE_TYPES: [u8; 2] = [b'0', 0];
+
+fn field(block: &[u8], (offset, len): (usize, usize)) -> &[u8] {
+    &block[offset..offset + len]
+}
+
+/// Returns the part of `bytes` before the first nul byte
+fn until_nul(bytes: &[u8]) -> &[u8] {
+    match bytes.iter().position(|byte| *byte == 0) {
+        Some(index) => &bytes[..index],
+        None => bytes,
+    }
+}
+
+/// Parses an octal number field, which may be padded with leading spaces and terminated with spaces or nul bytes
+fn parse_octal(bytes: &[u8]) -> Option<u64> {
+    let start = bytes.iter().position(|byte| *byte != b' ')?;
+    let bytes = &bytes[start..];
+
+    let digits_len = bytes.iter().position(|byte| *byte == b' ' || *byte == 0)
+        .unwrap_or(bytes.len());
+    let (digits, terminator) = bytes.split_at(digits_len);
+
+    if digits.is_empty() || terminator.iter().any(|byte| *byte != b' ' && *byte != 0) {
+        return None;
+    }
+
+    digits.iter().try_fold(0u64, |value, digit| {
+        if !(b'0'..=b'7').contains(digit) {
+            return None;
+        }
+
+        value.checked_mul(8)?.checked_add((digit - b'0') as u64)
+    })
+}
+
+/// Sum of every byte in the header, with the checksum field counted as spaces
+fn header_checksum(block: &[u8]) -> u64 {
+    let (checksum_offset, checksum_len) = CHECKSUM_FIELD;
+
+    block.iter().enumerate().map(|(i, byte)| {
+        if (checksum_offset..checksum_offset + checksum_len).contains(&i) {
+            b' ' as u64
+        } else {
+            *byte as u64
+        }
+    }).sum()
+}
+
+/// Full path of an entry in the initrd, made of the optional ustar prefix and the name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryPath<'a> {
+    prefix: &'a str,
+    name: &'a str,
+}
+
+impl EntryPath<'_> {
+    /// Checks if this path is equal to `path`
+    pub fn matches(&self, path: &str) -> bool {
+        if self.prefix.is_empty() {
+            return self.name == path;
+        }
+
+        path.strip_prefix(self.prefix)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .is_some_and(|rest| rest == self.name)
+    }
+}
+
+impl Display for EntryPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefix.is_empty() {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{}/{}", self.prefix, self.name)
+        }
+    }
+}
+
+/// A regular file in the initrd
+#[derive(Debug, Clone, Copy)]
+pub struct InitrdEntry<'a> {
+    path: EntryPath<'a>,
+    data: &'a [u8],
+}
+
+impl<'a> InitrdEntry<'a> {
+    pub fn path(&self) -> EntryPath<'a> {
+        self.path
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// Reason an archive entry could not be parsed
+#[derive(Debug)]
+struct EntryError<'a> {
+    /// Offset of the entry's header in the archive
+    offset: usize,
+    /// Name of the entry, if the header was intact enough to read it
+    name: Option<&'a str>,
+    reason: &'static str,
+}
+
+impl Display for EntryError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "corrupt initrd entry `{}` at offset {:#x}: {}",
+            self.name.unwrap_or("<unknown>"),
+            self.offset,
+            self.reason,
+        )
+    }
+}
+
+struct RawEntry<'a> {
+    entry: InitrdEntry<'a>,
+    is_regular_file: bool,
+    /// Offset of the header following this entry
+    next_offset: usize,
+}
+
+/// Parses the entry whose header is at `offset`
+///
+/// Returns None once the end of the archive is reached
+fn parse_entry(archive: &[u8], offset: usize) -> Result<Option<RawEntry<'_>>, EntryError<'_>> {
+    if offset >= archive.len() {
+        return Ok(None);
+    }
+
+    let error = |name, reason| EntryError {
+        offset,
+        name,
+        reason,
+    };
+
+    let Some(block) = archive.get(offset..offset + BLOCK_SIZE) else {
+        return Err(error(None, "header is truncated"));
+    };
+
+    // the end of the archive is marked with zeroed blocks
+    if block.iter().all(|byte| *byte == 0) {
+        return Ok(None);
+    }
+
+    // the name is read first so every other error can report it
+    let name = str::from_utf8(until_nul(field(block, NAME_FIELD))).ok();
+
+    if field(block, MAGIC_FIELD) != USTAR_MAGIC {
+        return Err(error(name, "missing ustar magic"));
+    }
+
+    let Some(checksum) = parse_octal(field(block, CHECKSUM_FIELD)) else {
+        return Err(error(name, "invalid checksum field"));
+    };
+
+    if checksum != header_checksum(block) {
+        return Err(error(name, "header checksum does not match"));
+    }
+
+    let Some(name) = name else {
+        return Err(error(None, "name is not valid utf-8"));
+    };
+
+    let Ok(prefix) = str::from_utf8(until_nul(field(block, PREFIX_FIELD))) else {
+        return Err(error(Some(name), "name prefix is not valid utf-8"));
+    };
+
+    let Some(size) = parse_octal(field(block, SIZE_FIELD)) else {
+        return Err(error(Some(name), "invalid size field"));
+    };
+
+    let data_start = offset + BLOCK_SIZE;
+    let data = usize::try_from(size).ok()
+        .and_then(|size| archive.get(data_start..data_start.checked_add(size)?));
+    let Some(data) = data else {
+        return Err(error(Some(name), "file data extends past the end of the archive"));
+    };
+
+    // data is padded to a multiple of the block size
+    let next_offset = data_start + data.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+
+    // archives created with `tar -C dir .` have a leading `./` on every path
+    let path = if prefix.is_empty() {
+        EntryPath {
+            prefix,
+            name: name.strip_prefix("./").unwrap_or(name),
+        }
+    } else {
+        EntryPath {
+            prefix: prefix.strip_prefix("./").unwrap_or(prefix),
+            name,
+        }
+    };
+
+    Ok(Some(RawEntry {
+        entry: InitrdEntry {
+            path,
+            data,
+        },
+        is_regular_file: REGULAR_FILE_TYPES.contains(&block[TYPEFLAG_OFFSET]),
+        next_offset,
+    }))
+}
+
+/// A validated initrd archive
+#[derive(Debug, Clone, Copy)]
+pub struct InitrdData<'a> {
+    archive: &'a [u8],
+}
+
+impl<'a> InitrdData<'a> {
+    /// Validates every entry in the initrd archive
+    ///
+    /// # Panics
+    ///
+    /// Panics if any entry is corrupt or extends past the end of `archive`, naming the offending entry
+    pub fn parse(archive: &'a [u8]) -> Self {
+        let mut offset = 0;
+
+        loop {
+            match parse_entry(archive, offset) {
+                Ok(Some(raw_entry)) => offset = raw_entry.next_offset,
+                Ok(None) => break,
+                Err(error) => panic!("{}", error),
+            }
+        }
+
+        InitrdData {
+            archive,
+        }
+    }
+
+    /// Gets the contents of the file at `path`, or None if it is not in the initrd
+    pub fn file(&self, path: &str) -> Option<&'a [u8]> {
+        self.entries()
+            .find(|entry| entry.path().matches(path))
+            .map(|entry| entry.data())
+    }
+
+    /// Iterates over every regular file in the initrd, in the order they are stored
+    pub fn entries(&self) -> Entries<'a> {
+        Entries {
+            archive: self.archive,
+            offset: 0,
+        }
+    }
+}
+
+pub struct Entries<'a> {
+    archive: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = InitrdEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // the archive was already validated, so errors are not possible here
+            let raw_entry = parse_entry(self.archive, self.offset).ok()??;
+            self.offset = raw_entry.next_offset;
+
+            if raw_entry.is_regular_file {
+                return Some(raw_entry.entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an in memory ustar archive
+    struct TarBuilder {
+        data: Vec<u8>,
+    }
+
+    impl TarBuilder {
+        fn new() -> Self {
+            TarBuilder {
+                data: Vec::new(),
+            }
+        }
+
+        fn header(&mut self, prefix: &str, name: &str, typeflag: u8, size: usize) -> usize {
+            let mut block = [0u8; BLOCK_SIZE];
+
+            block[..name.len()].copy_from_slice(name.as_bytes());
+            block[100..108].copy_from_slice(b"0000644\0");
+            block[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+            block[TYPEFLAG_OFFSET] = typeflag;
+            block[257..263].copy_from_slice(b"ustar\0");
+            block[263..265].copy_from_slice(b"00");
+            block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+            let checksum = header_checksum(&block);
+            block[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+            let offset = self.data.len();
+            self.data.extend_from_slice(&block);
+            offset
+        }
+
+        fn file_with_prefix(mut self, prefix: &str, name: &str, contents: &[u8]) -> Self {
+            self.header(prefix, name, b'0', contents.len());
+            self.data.extend_from_slice(contents);
+            self.data.resize(self.data.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+            self
+        }
+
+        fn file(self, name: &str, contents: &[u8]) -> Self {
+            self.file_with_prefix("", name, contents)
+        }
+
+        fn directory(mut self, name: &str) -> Self {
+            self.header("", name, b'5', 0);
+            self
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            self.data.resize(self.data.len() + 2 * BLOCK_SIZE, 0);
+            self.data
+        }
+    }
+
+    fn boot_archive() -> Vec<u8> {
+        TarBuilder::new()
+            .directory("./")
+            .file("./early-init", b"early init elf")
+            .file("./fs-server", &[0xab; 700])
+            .file("./part-list", b"")
+            .file_with_prefix("drivers", "virtio-blk", b"driver")
+            .finish()
+    }
+
+    #[test]
+    fn finds_files_by_name() {
+        let archive = boot_archive();
+        let initrd = InitrdData::parse(&archive);
+
+        assert_eq!(initrd.file("early-init"), Some(&b"early init elf"[..]));
+        assert_eq!(initrd.file("fs-server"), Some(&[0xab; 700][..]));
+        assert_eq!(initrd.file("part-list"), Some(&b""[..]));
+        assert_eq!(initrd.file("drivers/virtio-blk"), Some(&b"driver"[..]));
+        assert_eq!(initrd.file("hwaccess-server"), None);
+        assert_eq!(initrd.file("virtio-blk"), None);
+    }
+
+    #[test]
+    fn iterates_regular_files_in_order() {
+        let archive = boot_archive();
+        let initrd = InitrdData::parse(&archive);
+
+        let paths: Vec<String> = initrd.entries()
+            .map(|entry| entry.path().to_string())
+            .collect();
+
+        assert_eq!(paths, ["early-init", "fs-server", "part-list", "drivers/virtio-blk"]);
+    }
+
+    #[test]
+    fn empty_archive_has_no_entries() {
+        let archive = TarBuilder::new().finish();
+        let initrd = InitrdData::parse(&archive);
+
+        assert_eq!(initrd.entries().count(), 0);
+        assert_eq!(InitrdData::parse(&[]).entries().count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "corrupt initrd entry `fs-server` at offset 0x400: header checksum does not match")]
+    fn bad_checksum_panics() {
+        let mut archive = TarBuilder::new()
+            .file("early-init", b"a")
+            .file("fs-server", b"b")
+            .finish();
+
+        // corrupt the mode field of the second header
+        archive[0x400 + 100] = b'7';
+
+        InitrdData::parse(&archive);
+    }
+
+    #[test]
+    #[should_panic(expected = "corrupt initrd entry `fs-server` at offset 0x0: file data extends past the end of the archive")]
+    fn truncated_data_panics() {
+        let archive = TarBuilder::new()
+            .file("fs-server", &[1; 1000])
+            .finish();
+
+        InitrdData::parse(&archive[..BLOCK_SIZE + 600]);
+    }
+
+    #[test]
+    #[should_panic(expected = "corrupt initrd entry `<unknown>` at offset 0x400: header is truncated")]
+    fn truncated_header_panics() {
+        let archive = TarBuilder::new()
+            .file("early-init", b"a")
+            .file("fs-server", b"b")
+            .finish();
+
+        InitrdData::parse(&archive[..0x400 + 100]);
+    }
+
+    #[test]
+    #[should_panic(expected = "corrupt initrd entry `early-init` at offset 0x0: missing ustar magic")]
+    fn missing_magic_panics() {
+        let mut archive = TarBuilder::new()
+            .file("early-init", b"a")
+            .finish();
+
+        archive[257..262].copy_from_slice(b"xxxxx");
+
+        InitrdData::parse(&archive);
+    }
+
+    #[test]
+    fn parses_octal_fields() {
+        assert_eq!(parse_octal(b"00000001750\0"), Some(0o1750));
+        assert_eq!(parse_octal(b"  1750 \0"), Some(0o1750));
+        assert_eq!(parse_octal(b"\0\0\0"), None);
+        assert_eq!(parse_octal(b"0018\0"), None);
+        assert_eq!(parse_octal(b"17 5\0"), None);
+    }
+}
\ No newline at end of file
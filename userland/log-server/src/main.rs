@@ -0,0 +1,27 @@
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+mod server;
+
+use arpc::{ServerRpcEndpoint, run_rpc_service};
+use aurora::env;
+use std::prelude::*;
+
+use server::LogServerImpl;
+
+fn main() {
+    let args = env::args();
+
+    let server_endpoint: ServerRpcEndpoint = args.require("server_endpoint");
+    let admin_endpoint: ServerRpcEndpoint = args.require("admin_endpoint");
+
+    // name of the process which holds the first log endpoint
+    let client_name: String = args.require("client_name");
+
+    let log_server = LogServerImpl::new(client_name);
+    asynca::spawn(run_rpc_service(admin_endpoint, log_server.admin()));
+
+    asynca::block_in_place(run_rpc_service(server_endpoint, log_server));
+}
\ No newline at end of file
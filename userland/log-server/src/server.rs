@@ -0,0 +1,97 @@
+use alloc::format;
+use alloc::sync::Arc;
+use aurora::collections::HashMap;
+use aurora::io::{PipeWriter, Write};
+use aurora::log::{LogServer, LogAdminServer, LogLevel, Log};
+use aurora::sync::Mutex;
+use std::prelude::*;
+
+/// Level used for targets which have not had a level set
+const DEFAULT_LEVEL: LogLevel = LogLevel::Debug;
+
+#[derive(Default)]
+struct LevelFilter {
+    target_levels: HashMap<String, LogLevel>,
+}
+
+impl LevelFilter {
+    fn enabled(&self, target: &str, level: LogLevel) -> bool {
+        let min_level = self.target_levels.get(target)
+            .copied()
+            .unwrap_or(DEFAULT_LEVEL);
+
+        level >= min_level
+    }
+}
+
+/// Serves the log endpoint of one process
+pub struct LogServerImpl {
+    process_name: String,
+    /// Shared between every process's log endpoint
+    filter: Arc<Mutex<LevelFilter>>,
+    /// Pipes which printed lines are also written to, shared between every process's log endpoint
+    outputs: Arc<Mutex<Vec<PipeWriter>>>,
+}
+
+impl LogServerImpl {
+    pub fn new(process_name: String) -> Self {
+        LogServerImpl {
+            process_name,
+            filter: Arc::new(Mutex::new(LevelFilter::default())),
+            outputs: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Makes the admin service, which changes the settings shared with this endpoint
+    pub fn admin(&self) -> LogAdminServerImpl {
+        LogAdminServerImpl {
+            filter: self.filter.clone(),
+            outputs: self.outputs.clone(),
+        }
+    }
+}
+
+#[arpc::service_impl]
+impl LogServer for LogServerImpl {
+    fn log(&self, level: LogLevel, target: String, message: String) {
+        if !self.filter.lock().enabled(&target, level) {
+            return;
+        }
+
+        let mut outputs = self.outputs.lock();
+        for line in message.lines() {
+            let line = format!("[{}] [{level:<5}] {target}: {line}\n", self.process_name);
+            dprint!("{line}");
+
+            outputs.retain_mut(|output| output.write_all(line.as_bytes()).is_ok());
+        }
+    }
+
+    fn new_process_log(&self, process_name: String) -> Option<Log> {
+        let process_log = LogServerImpl {
+            process_name,
+            filter: self.filter.clone(),
+            outputs: self.outputs.clone(),
+        };
+
+        // the service task exits once the process drops its log endpoint
+        arpc::launch_service(process_log).ok()
+    }
+}
+
+/// Serves the admin endpoint, whose settings apply to every process's log endpoint
+pub struct LogAdminServerImpl {
+    filter: Arc<Mutex<LevelFilter>>,
+    outputs: Arc<Mutex<Vec<PipeWriter>>>,
+}
+
+#[arpc::service_impl]
+impl LogAdminServer for LogAdminServerImpl {
+    fn set_level(&self, target: String, level: LogLevel) {
+        self.filter.lock().target_levels.insert(target, level);
+    }
+
+    fn add_output(&self, output: PipeWriter) {
+        self.outputs.lock().push(output);
+    }
+}
\ No newline at end of file
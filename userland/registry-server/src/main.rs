@@ -0,0 +1,17 @@
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+mod server;
+
+use arpc::{ServerRpcEndpoint, run_rpc_service};
+use aurora::env;
+
+use server::RegistryServerImpl;
+
+fn main() {
+    let server_endpoint: ServerRpcEndpoint = env::args().require("server_endpoint");
+
+    asynca::block_in_place(run_rpc_service(server_endpoint, RegistryServerImpl::new()));
+}
\ No newline at end of file
@@ -0,0 +1,100 @@
+use alloc::sync::Arc;
+use core::future::{Future, poll_fn};
+use core::pin::pin;
+use core::task::Poll;
+
+use arpc::{ClientRpcEndpoint, WeakClientRpcEndpoint};
+use arpc::sys::SysErr;
+use aurora::collections::{HashMap, HashMapExt};
+use aurora::log::{warn, error};
+use aurora::registry::{RegistryServer, RegistryError};
+use aurora::sync::Mutex;
+use std::prelude::*;
+
+/// Services are held weakly, so the registry does not keep a service running after its other clients are gone
+type Services = Arc<Mutex<HashMap<String, Arc<WeakClientRpcEndpoint>>>>;
+
+pub struct RegistryServerImpl {
+    services: Services,
+}
+
+impl RegistryServerImpl {
+    pub fn new() -> Self {
+        RegistryServerImpl {
+            services: Arc::new(Mutex::new(HashMap::default())),
+        }
+    }
+}
+
+/// Removes the service registered as `name` once its server endpoint is dropped
+async fn remove_when_dropped(services: Services, name: String, endpoint: Arc<WeakClientRpcEndpoint>) {
+    let mut dropped = pin!(endpoint.server_dropped());
+
+    // the drop watch is registered by the first poll, and only sees drops which happen after that
+    let first_poll = poll_fn(|cx| Poll::Ready(dropped.as_mut().poll(cx))).await;
+    let result = match first_poll {
+        Poll::Ready(result) => result,
+        Poll::Pending => {
+            // the service may have stopped before the watch was registered,
+            // the upgraded endpoint is dropped right away so it does not keep the service running
+            let stopped = matches!(endpoint.upgrade(), Err(SysErr::InvlWeak));
+
+            if stopped {
+                Ok(0)
+            } else {
+                dropped.await
+            }
+        },
+    };
+
+    if let Err(error) = result {
+        error!("could not watch service {name} for drops: {error}");
+        return;
+    }
+
+    // lookup removes services which have stopped, so the name may already belong to another service
+    let mut services = services.lock();
+    if services.get(&name).is_some_and(|current| Arc::ptr_eq(current, &endpoint)) {
+        services.remove(&name);
+    }
+}
+
+#[arpc::service_impl]
+impl RegistryServer for RegistryServerImpl {
+    fn register(&self, name: String, endpoint: ClientRpcEndpoint) -> Result<(), RegistryError> {
+        if name.is_empty() {
+            return Err(RegistryError::EmptyName);
+        }
+
+        let mut services = self.services.lock();
+        if services.contains_key(&name) {
+            return Err(RegistryError::NameTaken);
+        }
+
+        let endpoint = Arc::new(endpoint.downgrade().or(Err(RegistryError::OutOfMemory))?);
+        services.insert_fallible(name.clone(), endpoint.clone())
+            .or(Err(RegistryError::OutOfMemory))?;
+
+        asynca::spawn(remove_when_dropped(self.services.clone(), name, endpoint));
+
+        Ok(())
+    }
+
+    fn lookup(&self, name: String) -> Option<ClientRpcEndpoint> {
+        let mut services = self.services.lock();
+        let endpoint = services.get(&name)?;
+
+        match endpoint.upgrade() {
+            Ok(endpoint) => Some(endpoint),
+            Err(SysErr::InvlWeak) => {
+                // the service stopped, but its drop has not been handled yet
+                services.remove(&name);
+                None
+            },
+            Err(error) => {
+                warn!("failed to clone endpoint for service {name}: {error}");
+                None
+            },
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,47 @@
+//! Checks that a shutdown request from the parent runs the exit handlers before the process exits
+//!
+//! The parent passes a channel as the `parent_channel` named argument.
+//! [`READY_MESSAGE`] is sent on it once this process is listening for shutdown requests,
+//! and [`EXIT_HANDLER_MESSAGE`] is sent from the exit handler.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use core::time::Duration;
+
+use aurora::collections::MessageVec;
+use aurora::env;
+use aurora::process;
+use aurora::time::Instant;
+use std::prelude::*;
+use sys::{Channel, ChannelSendFlags};
+
+const READY_MESSAGE: u8 = 1;
+const EXIT_HANDLER_MESSAGE: u8 = 2;
+
+/// How long to wait for the parent to recieve a message
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn send_to_parent(parent_channel: &Channel, message: u8) {
+    let message = MessageVec::from_slice(&[message]);
+    let deadline = Instant::now() + SEND_TIMEOUT;
+
+    parent_channel.sync_send(&message.message_buffer().unwrap(), Some(deadline.as_boot_time().as_nanos() as u64), ChannelSendFlags::empty())
+        .expect("failed to send message to parent");
+}
+
+fn main() {
+    let parent_channel: Channel = env::args().require("parent_channel");
+
+    process::spawn_shutdown_listener();
+
+    asynca::block_in_place(async move {
+        send_to_parent(&parent_channel, READY_MESSAGE);
+
+        process::on_exit(move || send_to_parent(&parent_channel, EXIT_HANDLER_MESSAGE));
+    });
+
+    panic!("shutdown test was not shut down by its parent");
+}
\ No newline at end of file
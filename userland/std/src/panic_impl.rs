@@ -1,7 +1,16 @@
+use core::fmt::Write;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aurora::backtrace::Backtrace;
+use aurora::log::{LogLevel, send_to_log_server};
+use aurora::thread;
 
 use crate::prelude::*;
 
+/// Set once any thread starts panicking, so a panic while reporting a panic doesn't recurse
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
 #[lang = "eh_personality"]
 #[no_mangle]
 extern "C" fn rust_eh_personality() {}
@@ -9,12 +18,28 @@ extern "C" fn rust_eh_personality() {}
 #[lang = "panic_impl"]
 #[no_mangle]
 fn rust_begin_panic(info: &PanicInfo) -> ! {
-	dprintln!("{}", info);
+	if PANICKING.swap(true, Ordering::Relaxed) {
+		dprintln_unbuffered!("panicked while another panic was being reported: {}", info);
+		aurora::process::exit_with_code(aurora::process::PANIC_EXIT_CODE);
+	}
+
+	let backtrace = Backtrace::capture();
+
+	let thread = thread::current();
+	let thread_name = thread.name().unwrap_or("<unnamed>");
+
+	// print before sending to the log server, since that allocates and the panic could be from the heap
+	dprintln_unbuffered!("thread '{}' {}\n{}", thread_name, info, backtrace);
+
+	// the serial console may have scrolled away by the time anyone looks, so also keep the report in the log
+	let mut report = String::new();
+	let _ = write!(report, "thread '{}' {}\n{}", thread_name, info, backtrace);
+	send_to_log_server(LogLevel::Error, "panic", report);
 
-	aurora::process::exit();
+	aurora::process::exit_with_code(aurora::process::PANIC_EXIT_CODE);
 }
 
 /*#[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     loop { core::hint::spin_loop(); }
-}*/
+}*/
\ No newline at end of file
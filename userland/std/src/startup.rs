@@ -1,5 +1,8 @@
 use core::{slice, arch::asm};
 
+use aurora_core::StackInfo;
+use aurora_core::thread::TlsTemplate;
+
 extern "C" {
     fn main();
 }
@@ -10,10 +13,7 @@ extern "C" {
 pub extern "C" fn _aurora_startup() {
     unsafe {
         asm!(
-            "pop rdi", // process data pointer
-            "pop rsi", // process data size
-            "pop rdx", // startup data pointer
-            "pop rcx", // startup data size
+            "mov rdi, rsp", // stack info pointer
             "call _rust_startup",
             options(noreturn)
         )
@@ -21,35 +21,49 @@ pub extern "C" fn _aurora_startup() {
 }
 
 #[no_mangle]
-pub extern "C" fn _rust_startup(
-    process_data: *mut u8,
-    process_data_size: usize,
-    namespace_data: *mut u8,
-    namespace_data_size: usize,
-) -> ! {
+pub extern "C" fn _rust_startup(stack_info: &StackInfo) -> ! {
     let process_data = unsafe {
-        slice::from_raw_parts(process_data, process_data_size)
+        slice::from_raw_parts(stack_info.process_data_address as *const u8, stack_info.process_data_size)
     };
 
     let (process_init_data, memory_entries) = aurora_core::process_data_from_slice(process_data)
-        .expect("invalid process data array passed into program");
+        .unwrap_or_else(|error| panic!("invalid process data passed into program: {error}"));
+
+    // safety: the tls image is part of the executable, which is never unmapped
+    let tls_template = unsafe {
+        TlsTemplate::from_raw_parts(
+            stack_info.tls_image_address,
+            stack_info.tls_image_size,
+            stack_info.tls_block_size,
+            stack_info.tls_block_align,
+        )
+    }.expect("invalid tls segment passed into program");
 
-    aurora_core::init_allocation(process_init_data, memory_entries)
+    aurora_core::init_allocation(process_init_data, memory_entries, tls_template)
         .expect("failed to initialize aurora lib allocaror");
 
     let namespace_data = unsafe {
-        slice::from_raw_parts(namespace_data, namespace_data_size)
+        slice::from_raw_parts(stack_info.namespace_data_address as *const u8, stack_info.namespace_data_size)
     };
 
     aurora::env::init_namespace(namespace_data)
         .expect("failed to initialize aurora library");
 
+    let env_data = unsafe {
+        slice::from_raw_parts(stack_info.env_data_address as *const u8, stack_info.env_data_size)
+    };
+
+    aurora::env::init_env_vars(env_data)
+        .expect("failed to initialize environment variables");
+
     unsafe {
         // main is function generated by rust compiler which calls the start lang item
         // it is used only to resolve actual rust main method, it does not perform any other startup actions
         main();
     }
 
+    aurora::io::flush_stdio();
+
     aurora::thread::exit();
 }
 
@@ -7,6 +7,7 @@ use bit_utils::get_bits;
 use serde::{Serialize, Deserialize, de::{Visitor, Error, EnumAccess, VariantAccess}};
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct CapFlags: usize {
         const READ = 1;
         const PROD = 1 << 1;
@@ -37,6 +38,9 @@ pub enum CapType {
     PhysMem = 17,
     IntAllocator = 18,
     Interrupt = 19,
+    DebugCap = 20,
+    IoPortAccess = 21,
+    IoPort = 22,
 }
 
 impl CapType {
@@ -61,6 +65,9 @@ impl CapType {
             17 => Self::PhysMem,
             18 => Self::IntAllocator,
             19 => Self::Interrupt,
+            20 => Self::DebugCap,
+            21 => Self::IoPortAccess,
+            22 => Self::IoPort,
             _ => return None,
         })
     }
@@ -75,6 +82,9 @@ impl CapType {
 pub struct CapId(usize);
 
 impl CapId {
+    /// The largest base id which can be stored in a CapId
+    pub const MAX_BASE_ID: usize = usize::MAX >> 10;
+
     pub fn try_from(n: usize) -> Option<Self> {
         // fail if invalid type of cap object
         let bits = get_bits(n, 5..10);
@@ -85,7 +95,8 @@ impl CapId {
 
     /// Creates a valid CapId from the given `cap_type`, `flags`, `is_weak`, and `base_id`
     /// 
-    /// `base_id` should be a unique integer in order for this id to be unique
+    /// `base_id` should be a unique integer in order for this id to be unique,
+    /// and it must not be larger than [`CapId::MAX_BASE_ID`]
     pub fn new(cap_type: CapType, flags: CapFlags, is_weak: bool, base_id: usize) -> Self {
         CapId(flags.bits() | ((is_weak as usize) << 4) | (cap_type.as_usize() << 5) | (base_id << 10))
     }
@@ -113,6 +124,14 @@ impl CapId {
         get_bits(self.0, 4..5) == 1
     }
 
+    /// Gets the unique integer this id was created from
+    /// 
+    /// Within 1 capability space, capabilities created later have a larger base id,
+    /// so base ids are never reused and a stale id will never refer to a newer capability
+    pub fn base_id(&self) -> usize {
+        self.0 >> 10
+    }
+
     /// # Panics
     /// 
     /// Panics if this capability is null
@@ -185,7 +204,6 @@ impl<'de> Visitor<'de> for CapIdVisitor {
     type Value = CapId;
 
     fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
-        crate::dprintln!("expecting");
         formatter.write_str("a valid 64 bit capability id")
     }
 
@@ -209,4 +227,94 @@ impl<'de> Visitor<'de> for CapIdVisitor {
 
         CapId::try_from(id).ok_or(D::Error::custom("invalid capid"))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap_id_fields_are_packed() {
+        let cap_id = CapId::new(CapType::Channel, CapFlags::READ | CapFlags::WRITE, true, 3);
+
+        assert_eq!(usize::from(cap_id), 0b101 | (1 << 4) | (8 << 5) | (3 << 10));
+        assert_eq!(cap_id.cap_type(), CapType::Channel);
+        assert_eq!(cap_id.flags(), CapFlags::READ | CapFlags::WRITE);
+        assert!(cap_id.is_weak());
+        assert_eq!(cap_id.base_id(), 3);
+    }
+
+    #[test]
+    fn invalid_cap_type_is_rejected() {
+        assert_eq!(CapId::try_from(0), None);
+        assert_eq!(CapId::try_from(23 << 5), None);
+        assert_eq!(CapId::try_from(31 << 5), None);
+
+        let cap_id = CapId::new(CapType::IoPort, CapFlags::all(), false, CapId::MAX_BASE_ID);
+        assert_eq!(CapId::try_from(cap_id.into()), Some(cap_id));
+    }
+
+    #[test]
+    fn null_cap_id_keeps_flags() {
+        assert!(CapId::null().is_null());
+
+        let cap_id = CapId::null_flags(CapFlags::PROD, true);
+        assert!(!cap_id.is_null());
+        assert_eq!(cap_id.flags(), CapFlags::PROD);
+        assert!(cap_id.is_weak());
+        assert_eq!(cap_id.base_id(), 0);
+    }
+
+    #[test]
+    fn cap_type_numbers_round_trip() {
+        for n in 1..=22 {
+            assert_eq!(CapType::from(n).unwrap().as_usize(), n);
+        }
+
+        assert_eq!(CapType::from(0), None);
+        assert_eq!(CapType::from(23), None);
+    }
+
+    #[cfg(feature = "std")]
+    mod properties {
+        use proptest::prelude::*;
+
+        use super::*;
+
+        fn cap_type() -> impl Strategy<Value = CapType> {
+            (1usize..=22).prop_map(|n| CapType::from(n).unwrap())
+        }
+
+        fn cap_flags() -> impl Strategy<Value = CapFlags> {
+            (0usize..16).prop_map(CapFlags::from_bits_truncate)
+        }
+
+        proptest! {
+            #[test]
+            fn unpacking_inverts_packing(
+                cap_type in cap_type(),
+                flags in cap_flags(),
+                is_weak: bool,
+                base_id in 0..=CapId::MAX_BASE_ID,
+            ) {
+                let cap_id = CapId::new(cap_type, flags, is_weak, base_id);
+
+                prop_assert_eq!(cap_id.cap_type(), cap_type);
+                prop_assert_eq!(cap_id.flags(), flags);
+                prop_assert_eq!(cap_id.is_weak(), is_weak);
+                prop_assert_eq!(cap_id.base_id(), base_id);
+                prop_assert_eq!(CapId::try_from(cap_id.into()), Some(cap_id));
+            }
+
+            #[test]
+            fn packing_inverts_unpacking(n: usize) {
+                if let Some(cap_id) = CapId::try_from(n) {
+                    let repacked = CapId::new(cap_id.cap_type(), cap_id.flags(), cap_id.is_weak(), cap_id.base_id());
+                    prop_assert_eq!(usize::from(repacked), n);
+                } else {
+                    prop_assert!(CapType::from((n >> 5) & 0b11111).is_none());
+                }
+            }
+        }
+    }
+}
@@ -8,32 +8,65 @@ use bit_utils::Size;
 
 use crate::{CapId, Reply};
 
-/// The event number of message recieved, kernel needs to know this
-pub const MESSAGE_RECIEVED_NUM: usize = EventNums::MessageRecieved as usize;
+/// Version of the layout of event records written into event pools
+/// 
+/// Every record starts with a header word containing its tag and this version, followed by the event id and an event count.
+/// The version is also passed when creating an event pool, and the kernel refuses to create event pools for a different layout.
+pub const EVENT_LAYOUT_VERSION: u32 = 2;
+
+/// Header of message recieved records, the kernel needs to know this
+pub const MESSAGE_RECIEVED_HEADER: usize = EventNums::MessageRecieved.header();
+
+/// Passed in place of the sender's identity key id when a message was sent without an identity
+pub const NO_SENDER_KEY_ID: usize = usize::MAX;
+
+/// Converts a sender key id word written by the kernel to an optional key id
+pub fn sender_key_id_from_usize(key_id: usize) -> Option<u64> {
+    (key_id != NO_SENDER_KEY_ID).then_some(key_id as u64)
+}
+
+/// Header of events dropped records, the kernel needs to know this
+pub const EVENTS_DROPPED_HEADER: usize = EventNums::EventsDropped.header();
 
 macro_rules! create_event_types {
     ($( $events:ident ),*,) => {
-        #[repr(usize)]
-        #[derive(FromRepr)]
+        #[repr(u32)]
+        #[derive(Clone, Copy, FromRepr)]
         enum EventNums {
             $(
                 $events,
             )*
-            // this one is special because it is variably sized
+            // these are special because they are not sent as an `Event`
+            EventsDropped,
             MessageRecieved,
         }
 
         impl EventNums {
+            /// The header word at the start of records with this tag
+            const fn header(self) -> usize {
+                self as usize | (EVENT_LAYOUT_VERSION as usize) << 32
+            }
+
             fn event_size(&self) -> usize {
                 match self {
                     $(
-                        Self::$events => size_of::<$events>() + size_of::<EventId>() + size_of::<Self>(),
+                        Self::$events => size_of::<$events>() + EVENT_RECORD_PREFIX_SIZE,
                     )*
+                    Self::EventsDropped => EVENT_RECORD_PREFIX_SIZE,
                     Self::MessageRecieved => panic!("message recieved is unsized"),
                 }
             }
         }
 
+        impl EventData {
+            /// Returns true if several of these events with the same event id can be delivered as one record with a count
+            /// 
+            /// This is the case for events which only signal that something happened, like interrupt triggers and drop checks.
+            pub fn is_coalescable(&self) -> bool {
+                matches!(self, Self::InterruptTrigger(_) | Self::CapDrop(_))
+            }
+        }
+
         #[derive(Debug, Clone, Copy)]
         pub enum EventData {
             $(
@@ -53,7 +86,9 @@ macro_rules! create_event_types {
                     $(
                         EventData::$events(event) => EventRaw {
                             tag: EventNums::$events,
+                            version: EVENT_LAYOUT_VERSION,
                             event_id: self.event_id,
+                            count: 1,
                             inner: EventRawInner {
                                 $events: event,
                             },
@@ -114,20 +149,34 @@ macro_rules! create_event_types {
         pub struct MessageRecievedEvent<'a> {
             pub event_id: EventId,
             pub reply: Option<Reply>,
+            /// Id of the sender's identity key, if the message was sent with [`SEND_IDENTITY`](crate::ChannelSendFlags::SEND_IDENTITY)
+            ///
+            /// This is filled in by the kernel, so it can't be forged by the sender.
+            pub sender_key_id: Option<u64>,
             pub message_data: &'a [u8],
         }
 
         #[derive(Debug)]
         pub enum EventParseResult<'a> {
             MessageRecieved(MessageRecievedEvent<'a>),
-            Event(Event),
+            Event {
+                event: Event,
+                /// Number of times the event occured, this is only more than 1 for [coalescable](EventData::is_coalescable) events
+                count: usize,
+            },
+            /// `count` events with `event_id` were dropped because the event pool was full
+            EventsDropped {
+                event_id: EventId,
+                count: usize,
+            },
         }
 
         impl EventParseResult<'_> {
             pub fn event_id(&self) -> EventId {
                 match self {
                     Self::MessageRecieved(message_event) => message_event.event_id,
-                    Self::Event(event) => event.event_id(),
+                    Self::Event { event, .. } => event.event_id(),
+                    Self::EventsDropped { event_id, .. } => *event_id,
                 }
             }
         }
@@ -139,7 +188,13 @@ macro_rules! create_event_types {
                 self.assert_aligned();
 
                 let event_type = EventNums::from_repr(self.take()?)?;
+                let version: u32 = self.take()?;
+                if version != EVENT_LAYOUT_VERSION {
+                    return None;
+                }
+
                 let event_id = EventId(self.take()?);
+                let count = self.take()?;
 
                 match event_type {
                     $(
@@ -150,15 +205,18 @@ macro_rules! create_event_types {
                                 event_id,
                             };
 
-                            Some(EventParseResult::Event(event))
+                            Some(EventParseResult::Event { event, count })
                         },
                     )*
+                    EventNums::EventsDropped => Some(EventParseResult::EventsDropped { event_id, count }),
                     EventNums::MessageRecieved => {
                         let reply_id = self.take()?;
                         let reply = CapId::try_from(reply_id)
                             .map(Reply::from_cap_id)
                             .flatten();
 
+                        let sender_key_id = sender_key_id_from_usize(self.take()?);
+
                         let message_size = self.take()?;
 
                         let message_data = self.take_bytes(message_size)?;
@@ -168,6 +226,7 @@ macro_rules! create_event_types {
                         Some(EventParseResult::MessageRecieved(MessageRecievedEvent {
                             event_id,
                             reply,
+                            sender_key_id,
                             message_data,
                         }))
                     },
@@ -178,10 +237,18 @@ macro_rules! create_event_types {
         #[repr(C)]
         pub struct EventRaw {
             tag: EventNums,
+            version: u32,
             event_id: EventId,
+            count: usize,
             inner: EventRawInner,
         }
 
+        /// Size of the header, event id, and count at the start of every record
+        const EVENT_RECORD_PREFIX_SIZE: usize = core::mem::offset_of!(EventRaw, inner);
+
+        /// Offset of the count from the start of a record, the kernel increments this when it coalesces events
+        pub const EVENT_COUNT_OFFSET: usize = core::mem::offset_of!(EventRaw, count);
+
         impl EventRaw {
             pub fn as_bytes(&self) -> &[u8] {
                 let ptr = self as *const Self as *const u8;
@@ -225,6 +292,9 @@ create_event_types! {
     ThreadExit,
     CapDrop,
     InterruptTrigger,
+    PageFault,
+    ReplyWritten,
+    ChannelSpace,
 }
 
 pub trait EventSyncReturn {
@@ -254,19 +324,89 @@ impl EventSyncReturn for MessageSent {
     }
 }
 
+/// The fault which killed a thread
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+pub enum FaultKind {
+    /// The thread faulted on a guard region
+    StackOverflow,
+    /// The thread faulted on an address that could not be resolved, and there was no fault handler
+    PageFault,
+}
+
+/// The reason a thread or thread group exited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// The thread group exited with the given exit code
+    ///
+    /// Threads which exit or are destroyed without exiting their thread group report an exit code of 0
+    Code(i32),
+    /// The thread group was destroyed, or a parent thread group exited
+    Killed,
+    /// The thread died from a fault
+    Fault(FaultKind),
+}
+
+impl ExitReason {
+    const CODE_TAG: usize = 0;
+    const KILLED_TAG: usize = 1;
+    const FAULT_TAG: usize = 2;
+
+    /// Encodes this reason in one usize, which is how it is sent in a [`ThreadExit`] event
+    ///
+    /// The upper 32 bits are the variant, and the lower 32 bits are the exit code or fault kind
+    pub fn as_raw(&self) -> usize {
+        let (tag, value) = match self {
+            Self::Code(code) => (Self::CODE_TAG, *code as u32 as usize),
+            Self::Killed => (Self::KILLED_TAG, 0),
+            Self::Fault(kind) => (Self::FAULT_TAG, *kind as usize),
+        };
+
+        (tag << 32) | value
+    }
+
+    pub fn from_raw(raw: usize) -> Option<Self> {
+        let value = raw & 0xffffffff;
+
+        match raw >> 32 {
+            Self::CODE_TAG => Some(Self::Code(value as u32 as i32)),
+            Self::KILLED_TAG => Some(Self::Killed),
+            Self::FAULT_TAG => Some(Self::Fault(FaultKind::from_repr(value)?)),
+            _ => None,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
-pub struct ThreadExit;
+pub struct ThreadExit {
+    /// An [`ExitReason`] describing why the thread exited, encoded with [`ExitReason::as_raw`]
+    pub reason: usize,
+}
+
+impl ThreadExit {
+    pub fn new(reason: ExitReason) -> Self {
+        ThreadExit {
+            reason: reason.as_raw(),
+        }
+    }
+
+    pub fn reason(&self) -> Option<ExitReason> {
+        ExitReason::from_raw(self.reason)
+    }
+}
 
 impl EventSyncReturn for ThreadExit {
-    type SyncReturn = ();
+    type SyncReturn = usize;
 
     fn as_sync_return(&self) -> Self::SyncReturn {
-        ()
+        self.reason
     }
 
-    fn from_sync_return(_: Self::SyncReturn) -> Self {
-        ThreadExit
+    fn from_sync_return(data: Self::SyncReturn) -> Self {
+        ThreadExit {
+            reason: data,
+        }
     }
 }
 
@@ -304,4 +444,56 @@ impl EventSyncReturn for InterruptTrigger {
     fn from_sync_return(_: Self::SyncReturn) -> Self {
         InterruptTrigger
     }
+}
+
+/// The type of memory access which caused a page fault
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+pub enum FaultAccess {
+    Read,
+    Write,
+    Execute,
+}
+
+/// Sent to the fault handler of an address space when a thread faults on an address the kernel could not resolve
+/// 
+/// The faulting thread is suspended, and will retry the faulting access if it is resumed
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct PageFault {
+    /// Address that was accessed
+    pub address: usize,
+    /// A [`FaultAccess`] describing the access
+    pub access: usize,
+    /// Capability id of the faulting thread, inserted into the capability space which set the fault handler
+    pub thread_id: usize,
+}
+
+impl PageFault {
+    pub fn access(&self) -> Option<FaultAccess> {
+        FaultAccess::from_repr(self.access)
+    }
+
+    pub fn thread_id(&self) -> Option<CapId> {
+        CapId::try_from(self.thread_id)
+    }
+}
+
+/// Sent to the caller of an async call made with a response buffer when the reply was copied directly into that buffer
+/// 
+/// If the reply did not fit in the response buffer, it is delivered as a normal message event instead
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct ReplyWritten {
+    /// Number of bytes written to the start of the response buffer
+    pub response_size: Size,
+}
+
+/// Sent to a listener registered with [`Channel::async_wait_space`](crate::Channel::async_wait_space)
+/// once the channel's async sender queue has space for more messages
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct ChannelSpace {
+    /// Number of async senders which were still queued when the event was sent
+    pub queued_senders: usize,
 }
\ No newline at end of file
@@ -69,6 +69,13 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct FutexWaitFlags: u32 {
+        const TIMEOUT = 1;
+    }
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy)]
     pub struct HandleEventAsyncFlags: u32 {
@@ -92,6 +99,13 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct ThreadParkFlags: u32 {
+        const PARK_TIMEOUT = 1;
+    }
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy)]
     pub struct ThreadDestroyFlags: u32 {
@@ -151,6 +165,22 @@ impl Default for MemoryMappingFlags {
     }
 }
 
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct AddressSpaceSetFaultHandlerFlags: u32 {
+        /// Removes the current fault handler instead of setting a new one
+        const CLEAR = 1;
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy)]
+    pub struct AddressSpaceListMappingsFlags: u32 {
+        /// Looks up the ids of capabilities to the mapped objects in the given capability space
+        const CAP_IDS = 1;
+    }
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy)]
     pub struct MemoryNewFlags: u32 {
@@ -161,6 +191,13 @@ bitflags! {
     }
 }
 
+/// By default memory is lazily allocated and zeroed, so new memory never contains stale data
+impl Default for MemoryNewFlags {
+    fn default() -> Self {
+        Self::LAZY_ALLOC | Self::ZEROED
+    }
+}
+
 bitflags! {
     /// The first three bits of flags are the same as MemoryMappingFlags, additonal options are here
     #[derive(Debug, Clone, Copy)]
@@ -195,6 +232,15 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Used by memory_query_dirty syscall
+    #[derive(Debug, Clone, Copy)]
+    pub struct MemoryQueryDirtyFlags: u32 {
+        /// Clears the dirty bits of the queried pages
+        const RESET = 1;
+    }
+}
+
 bitflags! {
     /// Used by event_pool_await syscall
     #[derive(Debug, Clone, Copy)]
@@ -211,9 +257,48 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Used by every syscall which sends a message on a channel, these don't overlap with the other channel flags
+    #[derive(Debug, Clone, Copy)]
+    pub struct ChannelSendFlags: u32 {
+        /// The kernel attaches the id of the process's identity key to the message
+        ///
+        /// The identity is registered with [`Key::register_identity`](crate::Key::register_identity),
+        /// if the process has no identity the message is sent without one.
+        const SEND_IDENTITY = 1 << 8;
+    }
+}
+
 bitflags! {
     #[derive(Debug, Clone, Copy)]
     pub struct ChannelAsyncRecvFlags: u32 {
         const AUTO_REQUE = 1;
     }
+}
+
+bitflags! {
+    /// Used by `channel_async_call`
+    #[derive(Debug, Clone, Copy)]
+    pub struct ChannelAsyncCallFlags: u32 {
+        /// The reply is copied into a response buffer passed with the call if it fits
+        const RESPONSE_BUFFER = 1;
+    }
+}
+
+bitflags! {
+    /// Used by debug_set_strace syscall
+    #[derive(Debug, Clone, Copy)]
+    pub struct DebugSetStraceFlags: u32 {
+        /// Syscalls in the syscall mask are traced
+        const ENABLED = 1;
+    }
+}
+
+bitflags! {
+    /// Used by `interrupt_new`
+    #[derive(Debug, Clone, Copy)]
+    pub struct InterruptNewFlags: u32 {
+        /// The interrupt is masked every time it is delivered, and must be unmasked once it is handled
+        const AUTO_MASK = 1;
+    }
 }
\ No newline at end of file
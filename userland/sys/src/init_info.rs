@@ -1,7 +1,7 @@
 use bytemuck::{Pod, Zeroable, bytes_of};
 use serde::{Serialize, Deserialize};
 
-use crate::{MmioAllocator, IntAllocator};
+use crate::{MmioAllocator, IntAllocator, DebugCap, IoPortAccess};
 
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy, Pod, Zeroable, Serialize, Deserialize)]
@@ -31,8 +31,16 @@ impl Rsdp {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InitInfo {
     pub initrd_address: usize,
+    /// Size of the initrd in bytes
+    pub initrd_size: usize,
     pub mmio_allocator: MmioAllocator,
     pub int_allocator: IntAllocator,
+    /// Allows using privileged debug syscalls such as reading the kernel log
+    pub debug_cap: DebugCap,
+    /// Allows accessing io ports which the kernel does not use, given to hwaccess-server
+    pub io_port_access: IoPortAccess,
     /// Copy of acpi root system descriptor pointer
     pub rsdp: Rsdp,
+    /// Set when `utest` is passed on the kernel command line, early-init then runs the userland test runner
+    pub run_userland_tests: bool,
 }
\ No newline at end of file
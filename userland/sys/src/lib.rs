@@ -1,6 +1,11 @@
 //! The sys crate is a low level interface to the aurora kernel syscalls
 #![no_std]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
 pub mod syscall_nums;
 
 mod cap;
@@ -3,10 +3,11 @@
 //! Thes definitions need to be heare because the kernel
 //! needs to know them to start the first userspace process
 
-use core::mem::size_of;
-
-use bytemuck::{Pod, Zeroable, PodCastError, try_from_bytes, try_cast_slice};
+use bytemuck::{Pod, Zeroable};
 
+/// Placed at the top of a new process's stack, the entry point is called with a pointer to it
+/// 
+/// The size is a multiple of 16 so the stack is still aligned for a call after it.
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 pub struct StackInfo {
@@ -14,18 +15,68 @@ pub struct StackInfo {
     pub process_data_size: usize,
     pub namespace_data_address: usize,
     pub namespace_data_size: usize,
+    /// Serialized map of environment variables, the size is 0 if the process has none
+    pub env_data_address: usize,
+    pub env_data_size: usize,
+    /// Initialization image for each thread's tls block, from the `PT_TLS` segment of the executable
+    pub tls_image_address: usize,
+    pub tls_image_size: usize,
+    /// Size of each thread's tls block, the bytes after the image are zeroed
+    /// 
+    /// This is 0 if the executable has no thread locals.
+    pub tls_block_size: usize,
+    pub tls_block_align: usize,
 }
 
+/// Value of [`ProcessInitData::magic`], used to detect when the startup data is not process data at all
+pub const PROCESS_INIT_DATA_MAGIC: usize = usize::from_le_bytes(*b"auroraPD");
+
+/// Value of [`ProcessInitData::version`], bump this whenever the layout of the process data changes
+pub const PROCESS_INIT_DATA_VERSION: usize = 2;
+
+/// Maximum length in bytes of [`ProcessInitData::process_name`], longer names are truncated
+pub const PROCESS_NAME_MAX_LEN: usize = 32;
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 pub struct ProcessInitData {
+    /// Must be [`PROCESS_INIT_DATA_MAGIC`]
+    pub magic: usize,
+    /// Must be [`PROCESS_INIT_DATA_VERSION`]
+    pub version: usize,
     pub thread_group_id: usize,
     pub address_space_id: usize,
     pub capability_space_id: usize,
     pub allocator_id: usize,
     pub main_thread_id: usize,
     pub stack_region_start_address: usize,
-    pub aslr_seed: [u8; 32]
+    pub aslr_seed: [u8; 32],
+    /// Number of bytes of `process_name` which are used
+    pub process_name_len: usize,
+    /// Name of the process, printed before each line of debug output
+    pub process_name: [u8; PROCESS_NAME_MAX_LEN],
+}
+
+impl ProcessInitData {
+    /// Stores `name` in the init data, truncating it to [`PROCESS_NAME_MAX_LEN`] bytes
+    pub fn set_process_name(&mut self, name: &str) {
+        let mut len = name.len().min(PROCESS_NAME_MAX_LEN);
+        // don't split a character when truncating
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        self.process_name = [0; PROCESS_NAME_MAX_LEN];
+        self.process_name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        self.process_name_len = len;
+    }
+
+    /// Returns the name of the process, or `None` if the name is not valid utf-8
+    pub fn process_name(&self) -> Option<&str> {
+        let len = self.process_name_len.min(PROCESS_NAME_MAX_LEN);
+
+        core::str::from_utf8(&self.process_name[..len]).ok()
+    }
 }
 
 #[repr(C, packed)]
@@ -43,12 +94,6 @@ pub struct ProcessMemoryEntry {
     pub padding_start: usize,
     /// End padding in bytes
     pub padding_end: usize,
-}
-
-/// Converts the raw block of memory passed into a program on startup into the process init data
-pub fn process_data_from_slice(data: &[u8]) -> Result<(ProcessInitData, &[ProcessMemoryEntry]), PodCastError> {
-    let process_init_data = *try_from_bytes(&data[..size_of::<ProcessInitData>()])?;
-    let memory_entries = try_cast_slice(&data[size_of::<ProcessInitData>()..])?;
-
-    Ok((process_init_data, memory_entries))
+    /// Nonzero if the start padding is reserved as a guard region
+    pub padding_start_guard: usize,
 }
\ No newline at end of file
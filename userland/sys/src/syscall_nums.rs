@@ -1,9 +1,17 @@
 //! Numbers used by all aurora kernel syscalls
 
 pub const PRINT_DEBUG: u32 = 0;
+pub const PRINT_DEBUG_BUFFER: u32 = 67;
+pub const DEBUG_SET_STRACE: u32 = 53;
+pub const DEBUG_READ_KLOG: u32 = 54;
+pub const DEBUG_EXIT_EMULATOR: u32 = 68;
+pub const DEBUG_CONSOLE_HANDOFF: u32 = 76;
 
 pub const THREAD_GROUP_NEW: u32 = 1;
 pub const THREAD_GROUP_EXIT: u32 = 2;
+pub const THREAD_GROUP_STATS: u32 = 55;
+pub const THREAD_GROUP_FAULT_INFO: u32 = 78;
+pub const THREAD_GROUP_SET_FAULT_CAPTURE: u32 = 79;
 pub const THREAD_NEW: u32 = 3;
 pub const THREAD_YIELD: u32 = 4;
 pub const THREAD_DESTROY: u32 = 5;
@@ -12,18 +20,31 @@ pub const THREAD_RESUME: u32 = 7;
 pub const THREAD_SET_PROPERTY: u32 = 8;
 pub const THREAD_HANDLE_THREAD_EXIT_SYNC: u32 = 9;
 pub const THREAD_HANDLE_THREAD_EXIT_ASYNC: u32 = 10;
+pub const THREAD_RUNTIME: u32 = 56;
+pub const THREAD_PARK: u32 = 80;
+pub const THREAD_UNPARK: u32 = 81;
 
 pub const CAP_CLONE: u32 = 11;
 pub const CAP_DESTROY: u32 = 12;
 
 pub const ADDRESS_SPACE_NEW: u32 = 13;
 pub const ADDRESS_SPACE_UNMAP: u32 = 14;
+pub const ADDRESS_SPACE_SET_FAULT_HANDLER: u32 = 22;
+pub const ADDRESS_SPACE_MAP_GUARD: u32 = 23;
+pub const ADDRESS_SPACE_UNMAP_RANGE: u32 = 65;
+pub const ADDRESS_SPACE_LIST_MAPPINGS: u32 = 66;
 
 pub const MEMORY_MAP: u32 = 15;
 pub const MEMORY_UPDATE_MAPPING: u32 = 16;
 pub const MEMORY_NEW: u32 = 17;
 pub const MEMORY_GET_SIZE: u32 = 18;
 pub const MEMORY_RESIZE: u32 = 19;
+pub const MEMORY_COPY: u32 = 20;
+pub const MEMORY_NEW_COW: u32 = 21;
+pub const MEMORY_PHYS_ADDR: u32 = 52;
+pub const MEMORY_SEAL: u32 = 71;
+pub const MEMORY_IS_SEALED: u32 = 72;
+pub const MEMORY_QUERY_DIRTY: u32 = 77;
 
 pub const EVENT_POOL_NEW: u32 = 24;
 pub const EVENT_POOL_MAP: u32 = 25;
@@ -33,6 +54,7 @@ pub const CHANNEL_NEW: u32 = 27;
 pub const CHANNEL_TRY_SEND: u32 = 28;
 pub const CHANNEL_SYNC_SEND: u32 = 29;
 pub const CHANNEL_ASYNC_SEND: u32 = 30;
+pub const CHANNEL_ASYNC_WAIT_SPACE: u32 = 82;
 pub const CHANNEL_TRY_RECV: u32 = 31;
 pub const CHANNEL_SYNC_RECV: u32 = 32;
 pub const CHANNEL_ASYNC_RECV: u32 = 33;
@@ -42,6 +64,7 @@ pub const REPLY_REPLY: u32 = 36;
 
 pub const KEY_NEW: u32 = 38;
 pub const KEY_ID: u32 = 39;
+pub const KEY_REGISTER_IDENTITY: u32 = 70;
 
 pub const DROP_CHECK_NEW: u32 = 40;
 pub const DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_SYNC: u32 = 41;
@@ -50,17 +73,74 @@ pub const DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_ASYNC: u32 = 42;
 pub const MMIO_ALLOCATOR_ALLOC: u32 = 43;
 pub const PHYS_MEM_MAP: u32 = 44;
 pub const PHYS_MEM_GET_SIZE: u32 = 45;
+pub const MMIO_ALLOCATOR_REQUEST_REGION: u32 = 61;
 
 pub const INTERRUPT_NEW: u32 = 46;
 pub const INTERRUPT_ID: u32 = 47;
 pub const INTERRUPT_HANDLE_INTERRUPT_TRIGGER_SYNC: u32 = 48;
 pub const INTERRUPT_HANDLE_INTERRUPT_TRIGGER_ASYNC: u32 = 49;
+pub const INTERRUPT_MASK: u32 = 59;
+pub const INTERRUPT_ACK: u32 = 60;
+
+pub const IO_PORT_IN: u32 = 73;
+pub const IO_PORT_OUT: u32 = 74;
+pub const IO_PORT_NEW: u32 = 75;
+
+pub const CAPABILITY_SPACE_LIST: u32 = 50;
+
+pub const ALLOCATOR_NEW_CHILD: u32 = 62;
+pub const ALLOCATOR_SET_LIMIT: u32 = 63;
+pub const ALLOCATOR_USAGE: u32 = 64;
+
+pub const SYSTEM_ENTROPY: u32 = 51;
+pub const SYSTEM_MEM_INFO: u32 = 69;
+
+pub const FUTEX_WAIT: u32 = 57;
+pub const FUTEX_WAKE: u32 = 58;
+
+pub const TIME_GET: u32 = 37;
+
+/// Syscalls which use channels, for tracing only the messages a process sends and recieves
+pub const CHANNEL_SYSCALLS: &[u32] = &[
+    CHANNEL_NEW,
+    CHANNEL_TRY_SEND,
+    CHANNEL_SYNC_SEND,
+    CHANNEL_ASYNC_SEND,
+    CHANNEL_ASYNC_WAIT_SPACE,
+    CHANNEL_TRY_RECV,
+    CHANNEL_SYNC_RECV,
+    CHANNEL_ASYNC_RECV,
+    CHANNEL_SYNC_CALL,
+    CHANNEL_ASYNC_CALL,
+    REPLY_REPLY,
+];
+
+/// Returns the strace syscall mask which traces only `syscalls`
+pub const fn syscall_mask(syscalls: &[u32]) -> u128 {
+    let mut mask = 0;
+
+    let mut i = 0;
+    while i < syscalls.len() {
+        mask |= 1 << syscalls[i];
+        i += 1;
+    }
+
+    mask
+}
 
 pub fn syscall_name(syscall_num: u32) -> &'static str {
     match syscall_num {
         PRINT_DEBUG => "print_debug",
+        PRINT_DEBUG_BUFFER => "print_debug_buffer",
+        DEBUG_SET_STRACE => "debug_set_strace",
+        DEBUG_READ_KLOG => "debug_read_klog",
+        DEBUG_EXIT_EMULATOR => "debug_exit_emulator",
+        DEBUG_CONSOLE_HANDOFF => "debug_console_handoff",
         THREAD_GROUP_NEW => "thread_group_new",
         THREAD_GROUP_EXIT => "thread_group_exit",
+        THREAD_GROUP_STATS => "thread_group_stats",
+        THREAD_GROUP_FAULT_INFO => "thread_group_fault_info",
+        THREAD_GROUP_SET_FAULT_CAPTURE => "thread_group_set_fault_capture",
         THREAD_NEW => "thread_new",
         THREAD_YIELD => "thread_yield",
         THREAD_DESTROY => "thread_destroy",
@@ -69,15 +149,28 @@ pub fn syscall_name(syscall_num: u32) -> &'static str {
         THREAD_SET_PROPERTY => "thread_set_property",
         THREAD_HANDLE_THREAD_EXIT_SYNC => "thread_handel_thread_exit_sync",
         THREAD_HANDLE_THREAD_EXIT_ASYNC => "thread_handel_thread_exit_async",
+        THREAD_RUNTIME => "thread_runtime",
+        THREAD_PARK => "thread_park",
+        THREAD_UNPARK => "thread_unpark",
         CAP_CLONE => "cap_clone",
         CAP_DESTROY => "cap_destroy",
         ADDRESS_SPACE_NEW => "address_space_new",
         ADDRESS_SPACE_UNMAP => "address_space_unmap",
+        ADDRESS_SPACE_SET_FAULT_HANDLER => "address_space_set_fault_handler",
+        ADDRESS_SPACE_MAP_GUARD => "address_space_map_guard",
+        ADDRESS_SPACE_UNMAP_RANGE => "address_space_unmap_range",
+        ADDRESS_SPACE_LIST_MAPPINGS => "address_space_list_mappings",
         MEMORY_MAP => "memory_map",
         MEMORY_UPDATE_MAPPING => "memory_update_mapping",
         MEMORY_NEW => "memory_new",
         MEMORY_GET_SIZE => "memory_get_size",
         MEMORY_RESIZE => "memory_resize",
+        MEMORY_COPY => "memory_copy",
+        MEMORY_NEW_COW => "memory_new_cow",
+        MEMORY_PHYS_ADDR => "memory_phys_addr",
+        MEMORY_SEAL => "memory_seal",
+        MEMORY_IS_SEALED => "memory_is_sealed",
+        MEMORY_QUERY_DIRTY => "memory_query_dirty",
         EVENT_POOL_NEW => "event_pool_new",
         EVENT_POOL_MAP => "event_pool_map",
         EVENT_POOL_AWAIT => "event_pool_await",
@@ -85,6 +178,7 @@ pub fn syscall_name(syscall_num: u32) -> &'static str {
         CHANNEL_TRY_SEND => "channel_try_send",
         CHANNEL_SYNC_SEND => "channel_sync_send",
         CHANNEL_ASYNC_SEND => "channel_async_send",
+        CHANNEL_ASYNC_WAIT_SPACE => "channel_async_wait_space",
         CHANNEL_TRY_RECV => "channel_try_recv",
         CHANNEL_SYNC_RECV => "channel_sync_recv",
         CHANNEL_ASYNC_RECV => "channel_async_recv",
@@ -93,16 +187,32 @@ pub fn syscall_name(syscall_num: u32) -> &'static str {
         REPLY_REPLY => "reply_reply",
         KEY_NEW => "key_new",
         KEY_ID => "key_id",
+        KEY_REGISTER_IDENTITY => "key_register_identity",
         DROP_CHECK_NEW => "drop_check_new",
         DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_SYNC => "drop_check_reciever_handle_cap_drop_sync",
         DROP_CHECK_RECIEVER_HANDLE_CAP_DROP_ASYNC => "drop_check_reciever_handle_cap_drop_async",
         MMIO_ALLOCATOR_ALLOC => "mmio_allocator_alloc",
         PHYS_MEM_MAP => "phys_mem_map",
         PHYS_MEM_GET_SIZE => "phys_mem_get_size",
+        MMIO_ALLOCATOR_REQUEST_REGION => "mmio_allocator_request_region",
         INTERRUPT_NEW => "interrupt_new",
         INTERRUPT_ID => "interrupt_id",
         INTERRUPT_HANDLE_INTERRUPT_TRIGGER_SYNC => "interrupt_handle_interrupt_trigger_sync",
         INTERRUPT_HANDLE_INTERRUPT_TRIGGER_ASYNC => "interrupt_handle_interrupt_trigger_async",
+        INTERRUPT_MASK => "interrupt_mask",
+        INTERRUPT_ACK => "interrupt_ack",
+        IO_PORT_IN => "io_port_in",
+        IO_PORT_OUT => "io_port_out",
+        IO_PORT_NEW => "io_port_new",
+        CAPABILITY_SPACE_LIST => "capability_space_list",
+        ALLOCATOR_NEW_CHILD => "allocator_new_child",
+        ALLOCATOR_SET_LIMIT => "allocator_set_limit",
+        ALLOCATOR_USAGE => "allocator_usage",
+        SYSTEM_ENTROPY => "system_entropy",
+        SYSTEM_MEM_INFO => "system_mem_info",
+        FUTEX_WAIT => "futex_wait",
+        FUTEX_WAKE => "futex_wake",
+        TIME_GET => "time_get",
         _ => "invalid syscall",
     }
 }
\ No newline at end of file
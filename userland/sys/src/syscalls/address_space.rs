@@ -1,5 +1,9 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
 use serde::{Serialize, Deserialize};
 use bit_utils::Size;
+use bytemuck::{Pod, Zeroable};
 
 use crate::{
     CapId,
@@ -9,12 +13,16 @@ use crate::{
     MemoryMapFlags,
     MemoryUpdateMappingFlags,
     CspaceTarget,
+    EventId,
+    AddressSpaceSetFaultHandlerFlags,
+    AddressSpaceListMappingsFlags,
     syscall,
     sysret_0,
-    sysret_1, MemoryCacheSetting,
+    sysret_1,
+    sysret_3, MemoryCacheSetting,
 };
 use crate::syscall_nums::*;
-use super::{Capability, Allocator, Memory, EventPool, PhysMem, cap_destroy, WEAK_AUTO_DESTROY, INVALID_CAPID_MESSAGE};
+use super::{Capability, Allocator, Memory, EventPool, PhysMem, CapabilitySpace, cap_destroy, WEAK_AUTO_DESTROY, INVALID_CAPID_MESSAGE};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AddressSpace(CapId);
@@ -74,6 +82,58 @@ impl From<MemoryMappingOptions> for MemoryMappingFlags {
     }
 }
 
+/// What kind of object is mapped by a [`MappingInfo`]
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MappingKind {
+    Memory = 0,
+    EventPool = 1,
+    PhysMem = 2,
+    /// A guard region, nothing is actually mapped here
+    Guard = 3,
+}
+
+impl MappingKind {
+    pub fn from_usize(n: usize) -> Option<Self> {
+        match n {
+            0 => Some(Self::Memory),
+            1 => Some(Self::EventPool),
+            2 => Some(Self::PhysMem),
+            3 => Some(Self::Guard),
+            _ => None,
+        }
+    }
+}
+
+/// Describes 1 mapping in an address space, written by [`AddressSpace::list_mappings`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
+pub struct MappingInfo {
+    pub address: usize,
+    /// Size of the mapping in bytes
+    pub size: usize,
+    /// Raw [`MappingKind`]
+    pub kind: usize,
+    /// Raw [`MemoryMappingFlags`] the mapping was mapped with
+    pub flags: usize,
+    /// Raw id of a capability to the mapped object, or the null capability id if it is unknown
+    pub cap_id: usize,
+}
+
+impl MappingInfo {
+    pub fn kind(&self) -> Option<MappingKind> {
+        MappingKind::from_usize(self.kind)
+    }
+
+    pub fn flags(&self) -> MemoryMappingFlags {
+        MemoryMappingFlags::from_bits_truncate(self.flags as u32)
+    }
+
+    pub fn cap_id(&self) -> Option<CapId> {
+        CapId::try_from(self.cap_id).filter(|cap_id| !cap_id.is_null())
+    }
+}
+
 impl AddressSpace {
     pub fn from_cap_id(cap_id: CapId) -> Option<Self> {
         if cap_id.cap_type() == CapType::AddressSpace {
@@ -151,6 +211,146 @@ impl AddressSpace {
             ))
         }
     }
+
+    /// Unmaps everything in the `size` bytes starting at `address`
+    /// 
+    /// Memory mappings only partially inside of the range are shrunk, or split in 2 if the range is in the middle of them.
+    /// Other mappings can't be split, so they must be entirely inside of the range.
+    pub fn unmap_range(&self, address: usize, size: Size) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                ADDRESS_SPACE_UNMAP_RANGE,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                address,
+                size.pages_rounded()
+            ))
+        }
+    }
+
+    /// Writes info about the mappings in this address space into `buffer`
+    /// 
+    /// Mappings are written in address order, starting with the first mapping at an address of at least `cursor`.
+    /// Pass 0 as the cursor to start from the beginning, and the returned cursor to continue after the last written mapping.
+    /// Listing is finished once fewer than `buffer.len()` mappings are written.
+    /// 
+    /// If `cspace` is given, the ids of capabilities in it which reference the mapped objects are also written.
+    /// 
+    /// # Returns
+    /// 
+    /// The number of mappings written, and the cursor to continue listing from
+    pub fn list_mappings(&self, cursor: usize, buffer: &mut [MappingInfo], cspace: Option<&CapabilitySpace>) -> KResult<(usize, usize)> {
+        let flags = if cspace.is_some() {
+            AddressSpaceListMappingsFlags::CAP_IDS
+        } else {
+            AddressSpaceListMappingsFlags::empty()
+        };
+
+        let (count, next_cursor, _) = self.list_mappings_inner(flags, cursor, buffer, cspace)?;
+        Ok((count, next_cursor))
+    }
+
+    /// Returns the number of pages used by the page tables of this address space
+    /// 
+    /// Page tables shared with the kernel are not counted, so an empty address space uses 1 page
+    pub fn page_table_pages(&self) -> KResult<usize> {
+        let (_, _, page_table_pages) = self.list_mappings_inner(
+            AddressSpaceListMappingsFlags::empty(),
+            0,
+            &mut [],
+            None,
+        )?;
+
+        Ok(page_table_pages)
+    }
+
+    fn list_mappings_inner(
+        &self,
+        flags: AddressSpaceListMappingsFlags,
+        cursor: usize,
+        buffer: &mut [MappingInfo],
+        cspace: Option<&CapabilitySpace>,
+    ) -> KResult<(usize, usize, usize)> {
+        unsafe {
+            sysret_3!(syscall!(
+                ADDRESS_SPACE_LIST_MAPPINGS,
+                flags.bits() | WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                cspace.map_or(0, CapabilitySpace::as_usize),
+                buffer.as_mut_ptr() as usize,
+                buffer.len(),
+                cursor
+            ))
+        }
+    }
+
+    /// Gets info about all mappings in this address space
+    /// 
+    /// The address space is listed in multiple syscalls, so mappings
+    /// changed while this is running may or may not be returned
+    #[cfg(feature = "alloc")]
+    pub fn mappings(&self, cspace: Option<&CapabilitySpace>) -> KResult<Vec<MappingInfo>> {
+        let mut out = Vec::new();
+        let mut buffer = [MappingInfo::default(); 32];
+        let mut cursor = 0;
+
+        loop {
+            let (count, next_cursor) = self.list_mappings(cursor, &mut buffer, cspace)?;
+            out.extend_from_slice(&buffer[..count]);
+
+            if count < buffer.len() {
+                return Ok(out);
+            }
+
+            cursor = next_cursor;
+        }
+    }
+
+    /// Reserves `size` bytes starting at `address` as a guard region
+    /// 
+    /// Nothing can be mapped in the guard region until it is unmapped with [`unmap`](Self::unmap),
+    /// and a thread which faults in the guard region exits with [`FaultKind::StackOverflow`](crate::FaultKind::StackOverflow)
+    pub fn map_guard(&self, address: usize, size: Size) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                ADDRESS_SPACE_MAP_GUARD,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                address,
+                size.pages_rounded()
+            ))
+        }
+    }
+
+    /// Sets the fault handler for this address space, replacing any previous fault handler
+    /// 
+    /// When a thread faults on an address which the kernel can't resolve, the thread is suspended
+    /// and a [`PageFault`](crate::PageFault) event with `event_id` is sent to `event_pool`.
+    /// The faulting thread can then be resumed to retry the access, or destroyed.
+    pub fn set_fault_handler(&self, event_pool: &EventPool, event_id: EventId) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                ADDRESS_SPACE_SET_FAULT_HANDLER,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                event_pool.as_usize(),
+                event_id.as_u64() as usize
+            ))
+        }
+    }
+
+    /// Removes the fault handler for this address space
+    pub fn clear_fault_handler(&self) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                ADDRESS_SPACE_SET_FAULT_HANDLER,
+                AddressSpaceSetFaultHandlerFlags::CLEAR.bits() | WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                0usize,
+                0usize
+            ))
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -1,12 +1,27 @@
-
 use serde::{Serialize, Deserialize};
 
 use crate::{
     CapId,
     CapType,
+    CapFlags,
+    KResult,
     CspaceTarget,
+    syscall,
+    sysret_0,
+    sysret_1,
+    sysret_2,
 };
-use super::{Capability, cap_destroy};
+use crate::syscall_nums::*;
+use super::{Capability, cap_destroy, WEAK_AUTO_DESTROY, INVALID_CAPID_MESSAGE};
+
+/// Memory usage of an [`Allocator`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocatorUsage {
+    /// Number of bytes allocated from the allocator, including memory given to child allocators
+    pub used: usize,
+    /// Maximum number of bytes which can be allocated from the allocator
+    pub limit: usize,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Allocator(CapId);
@@ -31,6 +46,53 @@ impl Allocator {
             None
         }
     }
+
+    /// Creates a new allocator which allocates its memory from this allocator
+    /// 
+    /// At most `limit` bytes can be allocated from the new allocator, and allocations
+    /// past that fail with [`SysErr::QuotaExceeded`](crate::SysErr::QuotaExceeded)
+    pub fn new_child(&self, flags: CapFlags, limit: usize) -> KResult<Self> {
+        unsafe {
+            sysret_1!(syscall!(
+                ALLOCATOR_NEW_CHILD,
+                flags.bits() as u32 | WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                limit
+            )).map(|num| Allocator(CapId::try_from(num).expect(INVALID_CAPID_MESSAGE)))
+        }
+    }
+
+    /// Sets the maximum number of bytes which can be allocated from this allocator
+    /// 
+    /// Fails with [`SysErr::InvlArgs`](crate::SysErr::InvlArgs) if more than `limit` bytes are already in use
+    pub fn set_limit(&self, limit: usize) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                ALLOCATOR_SET_LIMIT,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                limit
+            ))
+        }
+    }
+
+    /// Returns how much memory is in use by this allocator
+    pub fn usage(&self) -> KResult<AllocatorUsage> {
+        let (used, limit) = unsafe {
+            sysret_2!(syscall!(
+                ALLOCATOR_USAGE,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                0usize,
+                0usize
+            ))?
+        };
+
+        Ok(AllocatorUsage {
+            used,
+            limit,
+        })
+    }
 }
 
 impl Drop for Allocator {
@@ -1,11 +1,18 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
 use serde::{Serialize, Deserialize};
 
 use crate::{
     CapId,
     CapType,
+    KResult,
     CspaceTarget,
+    syscall,
+    sysret_2,
 };
-use super::{Capability, cap_destroy};
+use crate::syscall_nums::*;
+use super::{Capability, cap_destroy, WEAK_AUTO_DESTROY};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CapabilitySpace(CapId);
@@ -30,6 +37,50 @@ impl CapabilitySpace {
             None
         }
     }
+
+    /// Writes the ids of capabilities in this capability space into `buffer`
+    ///
+    /// Ids are written in order of increasing base id, starting from the first capability with a base id of at least `cursor`.
+    /// Pass 0 as the cursor to start from the beginning, and the returned cursor to continue after the last written id.
+    /// Listing is finished once fewer than `buffer.len()` ids are written.
+    ///
+    /// # Returns
+    ///
+    /// The number of ids written, and the cursor to continue listing from
+    pub fn list_caps(&self, cursor: usize, buffer: &mut [CapId]) -> KResult<(usize, usize)> {
+        unsafe {
+            sysret_2!(syscall!(
+                CAPABILITY_SPACE_LIST,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                buffer.as_mut_ptr() as usize,
+                buffer.len(),
+                cursor
+            ))
+        }
+    }
+
+    /// Gets the ids of all capabilities in this capability space
+    ///
+    /// The capability space is listed in multiple syscalls, so capabilities
+    /// inserted or removed while this is running may or may not be returned
+    #[cfg(feature = "alloc")]
+    pub fn iter_caps(&self) -> KResult<Vec<CapId>> {
+        let mut out = Vec::new();
+        let mut buffer = [CapId::null(); 64];
+        let mut cursor = 0;
+
+        loop {
+            let (count, next_cursor) = self.list_caps(cursor, &mut buffer)?;
+            out.extend_from_slice(&buffer[..count]);
+
+            if count < buffer.len() {
+                return Ok(out);
+            }
+
+            cursor = next_cursor;
+        }
+    }
 }
 
 impl Drop for CapabilitySpace {
@@ -7,16 +7,22 @@ use crate::{
     CapFlags,
     KResult,
     ChannelSyncFlags,
+    ChannelSendFlags,
     CspaceTarget,
     EventId,
+    sender_key_id_from_usize,
     syscall,
     sysret_0,
     sysret_1,
-    sysret_2,
+    sysret_3,
     ChannelAsyncRecvFlags,
+    ChannelAsyncCallFlags,
 };
 use crate::syscall_nums::*;
-use super::{Capability, Allocator, MessageBuffer, EventPool, Reply, cap_destroy, WEAK_AUTO_DESTROY, INVALID_CAPID_MESSAGE};
+use super::{Capability, Allocator, Memory, MessageBuffer, EventPool, Reply, cap_destroy, WEAK_AUTO_DESTROY, INVALID_CAPID_MESSAGE};
+
+/// Number of async senders a channel can queue while no reciever is waiting, unless another limit is given when it is created
+pub const DEFAULT_CHANNEL_SENDER_LIMIT: usize = 64;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Channel(CapId);
@@ -43,22 +49,30 @@ impl Channel {
     }
 
     pub fn new(flags: CapFlags, allocator: &Allocator) -> KResult<Self> {
+        Self::new_with_sender_limit(flags, allocator, DEFAULT_CHANNEL_SENDER_LIMIT)
+    }
+
+    /// Creates a channel which queues at most `sender_limit` async senders while no reciever is waiting
+    /// 
+    /// Async sends past the limit fail with [`SysErr::ChannelFull`](crate::SysErr::ChannelFull). `sender_limit` must not be 0.
+    pub fn new_with_sender_limit(flags: CapFlags, allocator: &Allocator, sender_limit: usize) -> KResult<Self> {
         unsafe {
             sysret_1!(syscall!(
                 CHANNEL_NEW,
                 flags.bits() as u32 | WEAK_AUTO_DESTROY,
-                allocator.as_usize()
+                allocator.as_usize(),
+                sender_limit
             )).map(|num| Channel(CapId::try_from(num).expect(INVALID_CAPID_MESSAGE)))
         }
     }
 
-    pub fn try_send(&self, buffer: &MessageBuffer) -> KResult<Size> {
+    pub fn try_send(&self, buffer: &MessageBuffer, send_flags: ChannelSendFlags) -> KResult<Size> {
         assert!(buffer.is_readable());
 
         unsafe {
             sysret_1!(syscall!(
                 CHANNEL_TRY_SEND,
-                WEAK_AUTO_DESTROY,
+                send_flags.bits() | WEAK_AUTO_DESTROY,
                 self.as_usize(),
                 usize::from(buffer.memory_id),
                 buffer.offset.bytes(),
@@ -67,7 +81,7 @@ impl Channel {
         }
     }
 
-    pub fn sync_send(&self, buffer: &MessageBuffer, timeout: Option<u64>) -> KResult<Size> {
+    pub fn sync_send(&self, buffer: &MessageBuffer, timeout: Option<u64>, send_flags: ChannelSendFlags) -> KResult<Size> {
         assert!(buffer.is_readable());
 
         let flags = match timeout {
@@ -78,7 +92,7 @@ impl Channel {
         unsafe {
             sysret_1!(syscall!(
                 CHANNEL_SYNC_SEND,
-                flags.bits() | WEAK_AUTO_DESTROY,
+                flags.bits() | send_flags.bits() | WEAK_AUTO_DESTROY,
                 self.as_usize(),
                 usize::from(buffer.memory_id),
                 buffer.offset.bytes(),
@@ -88,13 +102,13 @@ impl Channel {
         }
     }
 
-    pub fn async_send(&self, buffer: &MessageBuffer, event_pool: &EventPool, event_id: EventId) -> KResult<()> {
+    pub fn async_send(&self, buffer: &MessageBuffer, event_pool: &EventPool, event_id: EventId, send_flags: ChannelSendFlags) -> KResult<()> {
         assert!(buffer.is_readable());
 
         unsafe {
             sysret_0!(syscall!(
                 CHANNEL_ASYNC_SEND,
-                WEAK_AUTO_DESTROY,
+                send_flags.bits() | WEAK_AUTO_DESTROY,
                 self.as_usize(),
                 usize::from(buffer.memory_id),
                 buffer.offset.bytes(),
@@ -104,20 +118,38 @@ impl Channel {
             ))
         }
     }
+
+    /// Sends a [`ChannelSpace`](crate::ChannelSpace) event to `event_pool` once an async send would not fail with
+    /// [`SysErr::ChannelFull`](crate::SysErr::ChannelFull)
+    /// 
+    /// The event is sent right away if the sender queue isn't full, otherwise once it has drained to half its limit.
+    pub fn async_wait_space(&self, event_pool: &EventPool, event_id: EventId) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                CHANNEL_ASYNC_WAIT_SPACE,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                event_pool.as_usize(),
+                event_id.as_u64() as usize
+            ))
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct RecieveResult {
     pub recieve_size: Size,
     pub reply: Option<Reply>,
+    /// Id of the sender's identity key, if it was sent with [`SEND_IDENTITY`](ChannelSendFlags::SEND_IDENTITY)
+    pub sender_key_id: Option<u64>,
 }
 
 impl Channel {
     pub fn try_recv(&self, buffer: &MessageBuffer) -> KResult<RecieveResult> {
         assert!(buffer.is_writable());
 
-        let (recieve_size, reply_id) = unsafe {
-            sysret_2!(syscall!(
+        let (recieve_size, reply_id, sender_key_id) = unsafe {
+            sysret_3!(syscall!(
                 CHANNEL_TRY_RECV,
                 WEAK_AUTO_DESTROY,
                 self.as_usize(),
@@ -130,6 +162,7 @@ impl Channel {
         Ok(RecieveResult {
             recieve_size: Size::from_bytes(recieve_size),
             reply: Reply::from_usize(reply_id),
+            sender_key_id: sender_key_id_from_usize(sender_key_id),
         })
     }
 
@@ -141,8 +174,8 @@ impl Channel {
             None => ChannelSyncFlags::empty(),
         };
 
-        let (recieve_size, reply_id) = unsafe {
-            sysret_2!(syscall!(
+        let (recieve_size, reply_id, sender_key_id) = unsafe {
+            sysret_3!(syscall!(
                 CHANNEL_SYNC_RECV,
                 flags.bits() | WEAK_AUTO_DESTROY,
                 self.as_usize(),
@@ -156,6 +189,7 @@ impl Channel {
         Ok(RecieveResult {
             recieve_size: Size::from_bytes(recieve_size),
             reply: Reply::from_usize(reply_id),
+            sender_key_id: sender_key_id_from_usize(sender_key_id),
         })
     }
 
@@ -179,7 +213,13 @@ impl Channel {
 }
 
 impl Channel {
-    pub fn sync_call(&self, send_buffer: &MessageBuffer, recv_buffer: &MessageBuffer, timeout: Option<u64>) -> KResult<Size> {
+    pub fn sync_call(
+        &self,
+        send_buffer: &MessageBuffer,
+        recv_buffer: &MessageBuffer,
+        timeout: Option<u64>,
+        send_flags: ChannelSendFlags,
+    ) -> KResult<Size> {
         assert!(send_buffer.is_readable());
         assert!(recv_buffer.is_writable());
 
@@ -191,7 +231,7 @@ impl Channel {
         unsafe {
             sysret_1!(syscall!(
                 CHANNEL_SYNC_CALL,
-                flags.bits() | WEAK_AUTO_DESTROY,
+                flags.bits() | send_flags.bits() | WEAK_AUTO_DESTROY,
                 self.as_usize(),
                 usize::from(send_buffer.memory_id),
                 send_buffer.offset.bytes(),
@@ -204,13 +244,13 @@ impl Channel {
         }
     }
 
-    pub fn async_call(&self, send_buffer: &MessageBuffer, event_pool: &EventPool, event_id: EventId) -> KResult<()> {
+    pub fn async_call(&self, send_buffer: &MessageBuffer, event_pool: &EventPool, event_id: EventId, send_flags: ChannelSendFlags) -> KResult<()> {
         assert!(send_buffer.is_readable());
 
         unsafe {
             sysret_0!(syscall!(
                 CHANNEL_ASYNC_CALL,
-                WEAK_AUTO_DESTROY,
+                send_flags.bits() | WEAK_AUTO_DESTROY,
                 self.as_usize(),
                 usize::from(send_buffer.memory_id),
                 send_buffer.offset.bytes(),
@@ -220,6 +260,38 @@ impl Channel {
             ))
         }
     }
+
+    /// Like [`async_call`](Self::async_call), but the reply is copied into the first `response_size` bytes of `response_memory`
+    /// 
+    /// If the reply fits, a [`ReplyWritten`](crate::ReplyWritten) event carrying its size is sent to `event_pool`,
+    /// otherwise the reply is sent as a normal message event.
+    pub fn async_call_with_response(
+        &self,
+        send_buffer: &MessageBuffer,
+        response_memory: &Memory,
+        response_size: Size,
+        event_pool: &EventPool,
+        event_id: EventId,
+        send_flags: ChannelSendFlags,
+    ) -> KResult<()> {
+        assert!(send_buffer.is_readable());
+        assert!(response_memory.cap_id().flags().contains(CapFlags::WRITE));
+
+        unsafe {
+            sysret_0!(syscall!(
+                CHANNEL_ASYNC_CALL,
+                ChannelAsyncCallFlags::RESPONSE_BUFFER.bits() | send_flags.bits() | WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                usize::from(send_buffer.memory_id),
+                send_buffer.offset.bytes(),
+                send_buffer.size.bytes(),
+                event_pool.as_usize(),
+                event_id.as_u64() as usize,
+                response_memory.as_usize(),
+                response_size.bytes()
+            ))
+        }
+    }
 }
 
 impl Drop for Channel {
@@ -1,69 +1,221 @@
 use core::cmp::min;
 use core::fmt::{self, Write};
+#[cfg(debug_assertions)]
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use spin::Mutex;
+use core::cell::RefCell;
 
-use crate::{syscall_nums::*, syscall};
+use spin::{Mutex, Once};
 
-/// Prints up to 64 bytes from the input array to the kernel debug log
-fn print_debug_inner(data: &[u8]) {
-    let num_chars = min(64, data.len());
+use crate::{syscall_nums::*, syscall, sysret_1, PROCESS_NAME_MAX_LEN};
 
-    let get_char = |n| *data.get(n).unwrap_or(&0) as usize;
+/// Number of syscalls issued by this process, only tracked in debug builds
+#[cfg(debug_assertions)]
+static SYSCALL_COUNT: AtomicUsize = AtomicUsize::new(0);
 
-    let get_arg = |arg: usize| {
-        let base = arg * 8;
+/// Called by the `syscall` macro every time a syscall is issued
+#[doc(hidden)]
+#[inline(always)]
+pub fn count_syscall() {
+    #[cfg(debug_assertions)]
+    SYSCALL_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the number of syscalls issued by this process so far
+/// 
+/// This is only available in debug builds, and is meant for checking how many syscalls some code makes
+#[cfg(debug_assertions)]
+pub fn syscall_count() -> usize {
+    SYSCALL_COUNT.load(Ordering::Relaxed)
+}
+
+/// Maximum number of bytes printed by one [`print_debug_buffer`] call
+pub const PRINT_DEBUG_BUFFER_MAX_LEN: usize = 256;
 
-        get_char(base)
-            | get_char(base + 1) << 8
-            | get_char(base + 2) << 16
-            | get_char(base + 3) << 24
-            | get_char(base + 4) << 32
-            | get_char(base + 5) << 40
-            | get_char(base + 6) << 48
-            | get_char(base + 7) << 56
-    };
+/// Prints up to [`PRINT_DEBUG_BUFFER_MAX_LEN`] bytes from `data` to the kernel debug log
+/// 
+/// The bytes are never mixed with output from other threads or the kernel.
+/// Returns the number of bytes printed.
+pub fn print_debug_buffer(data: &[u8]) -> usize {
+    let num_bytes = min(PRINT_DEBUG_BUFFER_MAX_LEN, data.len());
 
     unsafe {
-        syscall!(
-            PRINT_DEBUG,
-            num_chars,
-            get_arg(0),
-            get_arg(1),
-            get_arg(2),
-            get_arg(3),
-            get_arg(4),
-            get_arg(5),
-            get_arg(6),
-            get_arg(7)
-        );
+        sysret_1!(syscall!(
+            PRINT_DEBUG_BUFFER,
+            0,
+            data.as_ptr() as usize,
+            num_bytes
+        )).unwrap_or(0)
     }
 }
 
 /// Prints `data` to the kernel debug log
 pub fn debug_print(data: &[u8]) {
-    for chunk in data.chunks(64) {
-        print_debug_inner(chunk);
+    for chunk in data.chunks(PRINT_DEBUG_BUFFER_MAX_LEN) {
+        print_debug_buffer(chunk);
+    }
+}
+
+/// Printed before each line of debug output, set by [`set_debug_print_name`]
+struct DebugPrefix {
+    data: [u8; PROCESS_NAME_MAX_LEN + 3],
+    len: usize,
+}
+
+static DEBUG_PREFIX: Once<DebugPrefix> = Once::new();
+
+/// Sets the process name printed before each line of debug output, this can only be set once
+pub fn set_debug_print_name(name: &str) {
+    DEBUG_PREFIX.call_once(|| {
+        let name = &name.as_bytes()[..min(name.len(), PROCESS_NAME_MAX_LEN)];
+
+        let mut prefix = DebugPrefix {
+            data: [0; PROCESS_NAME_MAX_LEN + 3],
+            len: name.len() + 3,
+        };
+        prefix.data[0] = b'[';
+        prefix.data[1..=name.len()].copy_from_slice(name);
+        prefix.data[name.len() + 1..prefix.len].copy_from_slice(b"] ");
+
+        prefix
+    });
+}
+
+fn debug_prefix() -> &'static [u8] {
+    match DEBUG_PREFIX.get() {
+        Some(prefix) => &prefix.data[..prefix.len],
+        None => &[],
+    }
+}
+
+/// Collects debug output until a whole line is written, so the line can be printed with one syscall
+/// 
+/// Lines longer than the buffer are printed in pieces.
+#[derive(Debug)]
+pub struct DebugLineBuffer {
+    data: [u8; PRINT_DEBUG_BUFFER_MAX_LEN],
+    len: usize,
+}
+
+impl DebugLineBuffer {
+    pub const fn new() -> Self {
+        DebugLineBuffer {
+            data: [0; PRINT_DEBUG_BUFFER_MAX_LEN],
+            len: 0,
+        }
+    }
+
+    /// Adds `bytes` to the buffer, calling `print_line` with the contents of the buffer each time a line is finished or the buffer fills up
+    /// 
+    /// `prefix` is put at the start of every piece passed to `print_line`.
+    fn write_with(&mut self, prefix: &[u8], mut bytes: &[u8], print_line: &mut impl FnMut(&[u8])) {
+        // always leave space for some of the line, or a long prefix would stop anything from being printed
+        let prefix = &prefix[..min(prefix.len(), self.data.len() / 2)];
+
+        while !bytes.is_empty() {
+            if self.len == 0 {
+                self.data[..prefix.len()].copy_from_slice(prefix);
+                self.len = prefix.len();
+            }
+
+            let line_len = match bytes.iter().position(|byte| *byte == b'\n') {
+                Some(newline_index) => newline_index + 1,
+                None => bytes.len(),
+            };
+            let copy_len = min(line_len, self.data.len() - self.len);
+
+            self.data[self.len..self.len + copy_len].copy_from_slice(&bytes[..copy_len]);
+            self.len += copy_len;
+            bytes = &bytes[copy_len..];
+
+            if self.data[self.len - 1] == b'\n' || self.len == self.data.len() {
+                print_line(&self.data[..self.len]);
+                self.len = 0;
+            }
+        }
+    }
+
+    /// Prints any partial line that is in the buffer
+    pub fn flush(&mut self) {
+        if self.len != 0 {
+            debug_print(&self.data[..self.len]);
+            self.len = 0;
+        }
     }
 }
 
-/// A writer which writes output to the debug_print syscall
-struct DebugWriter;
+impl Default for DebugLineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-impl Write for DebugWriter {
+impl Write for DebugLineBuffer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        debug_print(s.as_bytes());
+        self.write_with(debug_prefix(), s.as_bytes(), &mut debug_print);
         Ok(())
     }
 }
 
-static DEBUG_WRITER: Mutex<DebugWriter> = Mutex::new(DebugWriter);
+/// Returns the line buffer of the current thread, see [`set_thread_line_buffer_getter`]
+static THREAD_LINE_BUFFER_GETTER: Once<fn() -> Option<&'static RefCell<DebugLineBuffer>>> = Once::new();
+
+/// Line buffer used before the thread line buffer getter is set
+static FALLBACK_LINE_BUFFER: Mutex<DebugLineBuffer> = Mutex::new(DebugLineBuffer::new());
+
+/// Makes debug output be buffered in the line buffer returned by `getter` for each thread
+/// 
+/// Before this is called, output from every thread goes through one shared buffer.
+/// 
+/// # Safety
+/// 
+/// `getter` must return a line buffer belonging to the calling thread, which stays valid until the thread exits.
+/// It must be callable from every thread which prints debug output after this is called.
+pub unsafe fn set_thread_line_buffer_getter(getter: fn() -> Option<&'static RefCell<DebugLineBuffer>>) {
+    THREAD_LINE_BUFFER_GETTER.call_once(|| getter);
+
+    // print anything written before the getter was set so it isn't lost
+    FALLBACK_LINE_BUFFER.lock().flush();
+}
+
+/// Calls `f` with the line buffer of the current thread
+/// 
+/// If the buffer is already in use, because something is printed while formatting debug output,
+/// `f` gets a temporary buffer which is flushed right after.
+fn with_line_buffer(f: impl FnOnce(&mut DebugLineBuffer)) {
+    if let Some(getter) = THREAD_LINE_BUFFER_GETTER.get() {
+        if let Some(Ok(mut buffer)) = getter().map(RefCell::try_borrow_mut) {
+            f(&mut buffer);
+            return;
+        }
+    } else if let Some(mut buffer) = FALLBACK_LINE_BUFFER.try_lock() {
+        f(&mut buffer);
+        return;
+    }
+
+    let mut buffer = DebugLineBuffer::new();
+    f(&mut buffer);
+    buffer.flush();
+}
 
 #[doc(hidden)]
 pub fn _dprint(args: fmt::Arguments) {
-    DEBUG_WRITER.lock().write_fmt(args).unwrap();
+    with_line_buffer(|buffer| {
+        let _ = buffer.write_fmt(args);
+    });
 }
 
+#[doc(hidden)]
+pub fn _dprint_unbuffered(args: fmt::Arguments) {
+    // whatever the thread already wrote should come first
+    with_line_buffer(DebugLineBuffer::flush);
+
+    let mut buffer = DebugLineBuffer::new();
+    let _ = buffer.write_fmt(args);
+    buffer.flush();
+}
+
+/// Prints to the kernel debug log, output is buffered until a whole line is written
 #[macro_export]
 macro_rules! dprint {
     ($($arg:tt)*) => ($crate::_dprint(format_args!($($arg)*)));
@@ -73,4 +225,88 @@ macro_rules! dprint {
 macro_rules! dprintln {
     () => ($crate::dprint!("\n"));
     ($($arg:tt)*) => ($crate::dprint!("{}\n", format_args!($($arg)*)));
-}
\ No newline at end of file
+}
+
+/// Same as [`dprint`], but prints immediately instead of waiting for the end of the line
+/// 
+/// This is meant for panic handlers, which may never get to finish the line.
+#[macro_export]
+macro_rules! dprint_unbuffered {
+    ($($arg:tt)*) => ($crate::_dprint_unbuffered(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! dprintln_unbuffered {
+    () => ($crate::dprint_unbuffered!("\n"));
+    ($($arg:tt)*) => ($crate::dprint_unbuffered!("{}\n", format_args!($($arg)*)));
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::sync::Mutex;
+    use std::string::ToString;
+    use std::vec::Vec;
+    use std::{format, thread};
+
+    use super::*;
+
+    #[test]
+    fn concurrent_lines_are_not_interleaved() {
+        const LINE_COUNT: usize = 200;
+
+        let printed = Mutex::new(Vec::new());
+
+        thread::scope(|scope| {
+            for thread_id in 0..2 {
+                let printed = &printed;
+
+                scope.spawn(move || {
+                    let prefix = format!("[thread-{thread_id}] ");
+                    let mut buffer = DebugLineBuffer::new();
+                    let mut print_line = |line: &[u8]| printed.lock().unwrap().push(line.to_vec());
+
+                    for i in 0..LINE_COUNT {
+                        // write_fmt writes each line in several pieces
+                        for piece in ["line ", &i.to_string(), " from ", &thread_id.to_string(), "\n"] {
+                            buffer.write_with(prefix.as_bytes(), piece.as_bytes(), &mut print_line);
+                        }
+                    }
+                });
+            }
+        });
+
+        let printed = printed.into_inner().unwrap();
+        assert_eq!(printed.len(), 2 * LINE_COUNT);
+
+        for line in printed {
+            let line = core::str::from_utf8(&line).unwrap();
+            let (prefix, text) = line.split_once("] ").unwrap();
+            let thread_id = prefix.strip_prefix("[thread-").unwrap();
+
+            assert!(text.starts_with("line "), "interleaved line: {line:?}");
+            assert!(text.ends_with(&format!(" from {thread_id}\n")), "interleaved line: {line:?}");
+        }
+    }
+
+    #[test]
+    fn long_lines_are_split() {
+        let mut buffer = DebugLineBuffer::new();
+        let mut printed = Vec::new();
+        let mut print_line = |line: &[u8]| printed.push(line.to_vec());
+
+        buffer.write_with(b"[test] ", &[b'a'; PRINT_DEBUG_BUFFER_MAX_LEN * 2], &mut print_line);
+        buffer.write_with(b"[test] ", b"\n", &mut print_line);
+
+        assert_eq!(printed.len(), 3);
+        for piece in printed.iter() {
+            assert!(piece.starts_with(b"[test] "));
+            assert!(piece.len() <= PRINT_DEBUG_BUFFER_MAX_LEN);
+        }
+
+        let text_len: usize = printed.iter().map(|piece| piece.len() - b"[test] ".len()).sum();
+        assert_eq!(text_len, PRINT_DEBUG_BUFFER_MAX_LEN * 2 + 1);
+        assert!(printed[2].ends_with(b"a\n"));
+    }
+}
@@ -0,0 +1,154 @@
+use bytemuck::{Pod, Zeroable};
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    CapId,
+    CapType,
+    KResult,
+    CspaceTarget,
+    syscall,
+    sysret_0,
+    sysret_2,
+};
+use crate::syscall_nums::*;
+use super::{Capability, Memory, cap_destroy, WEAK_AUTO_DESTROY};
+
+/// Memory usage of the whole system, returned by [`DebugCap::system_mem_info`]
+/// 
+/// The counters are read without stopping other cpus, so they are a snapshot which may be slightly inconsistent.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
+pub struct MemInfo {
+    /// Number of physical pages the kernel can allocate
+    pub total_pages: usize,
+    /// Number of physical pages which are not allocated
+    pub free_pages: usize,
+    /// Number of bytes allocated from the kernel heap
+    pub heap_bytes: usize,
+    /// Number of memory capability objects which exist, copies of a capability to the same memory are counted once
+    pub memory_count: usize,
+    /// Total size in pages of all memory capability objects, including pages which have not been allocated yet
+    pub memory_pages: usize,
+    /// Number of physical memory zones, each zone has a [`ZoneMemInfo`]
+    pub zone_count: usize,
+}
+
+impl MemInfo {
+    /// Number of physical pages which are allocated
+    pub fn used_pages(&self) -> usize {
+        self.total_pages.saturating_sub(self.free_pages)
+    }
+}
+
+/// Memory usage of 1 physical memory zone, each zone is allocated from seperately
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
+pub struct ZoneMemInfo {
+    /// Physical address the zone starts at
+    pub start_address: usize,
+    pub total_pages: usize,
+    pub free_pages: usize,
+    /// Size in pages of the biggest allocation which could currently be made from this zone
+    pub largest_free_pages: usize,
+}
+
+/// Allows using privileged debugging syscalls, this is given to early-init
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DebugCap(CapId);
+
+impl Capability for DebugCap {
+    const TYPE: CapType = CapType::DebugCap;
+
+    fn cloned_new_id(&self, cap_id: CapId) -> Option<Self> {
+        Self::from_cap_id(cap_id)
+    }
+
+    fn cap_id(&self) -> CapId {
+        self.0
+    }
+}
+
+impl DebugCap {
+    pub fn from_cap_id(cap_id: CapId) -> Option<Self> {
+        if cap_id.cap_type() == CapType::DebugCap {
+            Some(DebugCap(cap_id))
+        } else {
+            None
+        }
+    }
+
+    /// Copies whole kernel log records with a sequence number of at least `since_seq` into `memory`,
+    /// starting at `offset` and writing at most `size` bytes
+    /// 
+    /// Returns the number of bytes written and the sequence number of the first record which was not copied.
+    /// Fails with [`SysErr::OutOfCapacity`](crate::SysErr::OutOfCapacity) if the next record is bigger than `size`.
+    pub fn read_klog(&self, memory: &Memory, offset: usize, size: usize, since_seq: u64) -> KResult<(usize, u64)> {
+        let (bytes_written, next_seq) = unsafe {
+            sysret_2!(syscall!(
+                DEBUG_READ_KLOG,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                memory.as_usize(),
+                offset,
+                size,
+                since_seq as usize
+            ))?
+        };
+
+        Ok((bytes_written, next_seq as u64))
+    }
+
+    /// Gets the memory usage of the whole system, and writes the usage of each physical memory zone to `zones`
+    /// 
+    /// Only the first `zones.len()` zones are written, [`MemInfo::zone_count`] is how many zones there are.
+    pub fn system_mem_info(&self, zones: &mut [ZoneMemInfo]) -> KResult<MemInfo> {
+        let mut mem_info = MemInfo::default();
+
+        unsafe {
+            sysret_0!(syscall!(
+                SYSTEM_MEM_INFO,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                &mut mem_info as *mut MemInfo as usize,
+                zones.as_mut_ptr() as usize,
+                zones.len()
+            ))?;
+        }
+
+        Ok(mem_info)
+    }
+
+    /// Exits the emulator the system is running in, with a status which reports if an automated test run succeeded
+    ///
+    /// Returns normally if the system is not running in an emulator which can be exited this way.
+    pub fn exit_emulator(&self, success: bool) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                DEBUG_EXIT_EMULATOR,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                success as usize
+            ))
+        }
+    }
+
+    /// Stops the kernel from printing its messages to the screen and the debug console, so a console server can own the console
+    ///
+    /// Kernel messages can still be read with [`read_klog`](Self::read_klog),
+    /// and the kernel takes the console back if it panics. This requires the write permission.
+    pub fn console_handoff(&self) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                DEBUG_CONSOLE_HANDOFF,
+                WEAK_AUTO_DESTROY,
+                self.as_usize()
+            ))
+        }
+    }
+}
+
+impl Drop for DebugCap {
+    fn drop(&mut self) {
+        let _ = cap_destroy(CspaceTarget::Current, self.0);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,26 @@
+use crate::{KResult, syscall, sysret_3};
+use crate::syscall_nums::*;
+
+/// Number of bytes of entropy returned by one call to [`system_entropy`]
+pub const SYSTEM_ENTROPY_SIZE: usize = 3 * core::mem::size_of::<usize>();
+
+/// Gets random data from the kernel, intended for seeding random number generators
+pub fn system_entropy() -> KResult<[u8; SYSTEM_ENTROPY_SIZE]> {
+    let (a, b, c) = unsafe {
+        sysret_3!(syscall!(
+            SYSTEM_ENTROPY,
+            0,
+            0usize,
+            0usize,
+            0usize,
+            0usize
+        ))?
+    };
+
+    let mut out = [0; SYSTEM_ENTROPY_SIZE];
+    for (chunk, word) in out.chunks_exact_mut(core::mem::size_of::<usize>()).zip([a, b, c]) {
+        chunk.copy_from_slice(&word.to_ne_bytes());
+    }
+
+    Ok(out)
+}
\ No newline at end of file
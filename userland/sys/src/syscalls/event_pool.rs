@@ -8,8 +8,9 @@ use crate::{
     CspaceTarget,
     syscall,
     sysret_1,
-    sysret_2,
+    sysret_3,
     EventPoolAwaitFlags,
+    EVENT_LAYOUT_VERSION,
 };
 use crate::syscall_nums::*;
 use super::{Capability, Allocator, cap_destroy, WEAK_AUTO_DESTROY, INVALID_CAPID_MESSAGE};
@@ -40,10 +41,14 @@ impl Capability for EventPool {
 }
 
 /// Returned by [`await_event`], represents a range of event data that can be processed
+/// 
+/// This contains every event written to the event pool since the previous call to [`await_event`]
 #[derive(Debug, Clone, Copy)]
 pub struct EventRange {
     pub data: *const u8,
     pub len: usize,
+    /// Number of records in the range, coalesced events only take up 1 record
+    pub event_count: usize,
 }
 
 impl EventRange {
@@ -61,13 +66,16 @@ impl EventRange {
 }
 
 impl EventPool {
+    /// Creates an event pool, fails with [`SysErr::InvlArgs`](crate::SysErr::InvlArgs) if the kernel writes events
+    /// in a different layout than [`EventParser`](crate::EventParser) parses
     pub fn new(allocator: &Allocator, max_size: Size) -> KResult<Self> {
         let cap_id = unsafe {
             sysret_1!(syscall!(
                 EVENT_POOL_NEW,
                 WEAK_AUTO_DESTROY,
                 allocator.as_usize(),
-                max_size.pages_rounded()
+                max_size.pages_rounded(),
+                EVENT_LAYOUT_VERSION as usize
             ))?
         };
 
@@ -82,18 +90,21 @@ impl EventPool {
     }
 
     /// Waits for an event to occur, and returns a pointer to the event data slice
+    /// 
+    /// All events which are ready are returned at once, so only 1 syscall is needed for a burst of events
     pub fn await_event(&self, timeout: Option<u64>) -> KResult<EventRange> {
         let flags = match timeout {
             Some(_) => EventPoolAwaitFlags::TIMEOUT,
             _ => EventPoolAwaitFlags::empty(),
         };
 
-        let (addr, size) = unsafe {
-            sysret_2!(syscall!(
+        let (addr, size, event_count) = unsafe {
+            sysret_3!(syscall!(
                 EVENT_POOL_AWAIT,
                 flags.bits() | WEAK_AUTO_DESTROY,
                 self.as_usize(),
                 timeout.unwrap_or_default(),
+                0usize,
                 0usize
             ))?
         };
@@ -101,6 +112,7 @@ impl EventPool {
         Ok(EventRange {
             data: addr as *const u8,
             len: size,
+            event_count,
         })
     }
 }
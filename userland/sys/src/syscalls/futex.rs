@@ -0,0 +1,42 @@
+use core::sync::atomic::AtomicU32;
+
+use crate::{KResult, FutexWaitFlags, syscall, sysret_1};
+use crate::syscall_nums::*;
+
+/// Blocks the current thread until [`futex_wake`] is called on `futex`, as long as `futex` still contains `expected_value`
+/// 
+/// The value is checked atomically with respect to [`futex_wake`], so a wake after `futex` is changed is never missed.
+/// If `timeout` is set, [`SysErr::OkTimeout`](crate::SysErr::OkTimeout) is returned once the monotonic clock ([`ClockId::Monotonic`](crate::ClockId::Monotonic)) reaches `timeout` nanoseconds.
+/// 
+/// Returns false without waiting if `futex` did not contain `expected_value`, wakeups can be spurious so the caller should check `futex` again
+pub fn futex_wait(futex: &AtomicU32, expected_value: u32, timeout: Option<u64>) -> KResult<bool> {
+    let flags = if timeout.is_some() {
+        FutexWaitFlags::TIMEOUT
+    } else {
+        FutexWaitFlags::empty()
+    };
+
+    let waited = unsafe {
+        sysret_1!(syscall!(
+            FUTEX_WAIT,
+            flags.bits(),
+            futex.as_ptr() as usize,
+            expected_value as usize,
+            timeout.unwrap_or_default() as usize
+        ))?
+    };
+
+    Ok(waited != 0)
+}
+
+/// Wakes up to `count` threads waiting on `futex` with [`futex_wait`], returns how many threads were woken
+pub fn futex_wake(futex: &AtomicU32, count: usize) -> KResult<usize> {
+    unsafe {
+        sysret_1!(syscall!(
+            FUTEX_WAKE,
+            0,
+            futex.as_ptr() as usize,
+            count
+        ))
+    }
+}
\ No newline at end of file
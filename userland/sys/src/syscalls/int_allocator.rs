@@ -5,6 +5,7 @@ use crate::{
     CapType,
     KResult,
     CspaceTarget,
+    InterruptNewFlags,
     syscall,
     sysret_3,
 };
@@ -36,10 +37,14 @@ impl IntAllocator {
     }
 
     pub fn create_interrupt(&self, allocator: &Allocator) -> KResult<(Interrupt, InterruptId)> {
-        let (interrupt_cap_id, cpu_num, interrupt_num) = unsafe {
+        self.create_interrupt_with_flags(allocator, InterruptNewFlags::empty())
+    }
+
+    pub fn create_interrupt_with_flags(&self, allocator: &Allocator, flags: InterruptNewFlags) -> KResult<(Interrupt, InterruptId)> {
+        let (interrupt_cap_id, _, _) = unsafe {
             sysret_3!(syscall!(
                 INTERRUPT_NEW,
-                WEAK_AUTO_DESTROY,
+                flags.bits() | WEAK_AUTO_DESTROY,
                 self.as_usize(),
                 allocator.as_usize(),
                 0usize,
@@ -49,10 +54,8 @@ impl IntAllocator {
 
         let interrupt_cap_id = CapId::try_from(interrupt_cap_id).expect(INVALID_CAPID_MESSAGE);
         let interrupt = Interrupt::from_cap_id(interrupt_cap_id).expect(INVALID_CAPID_MESSAGE);
-        let interrupt_id = InterruptId {
-            cpu_num,
-            interrupt_num,
-        };
+        // interrupt_new does not return the apic id, so the full id is queried seperately
+        let interrupt_id = interrupt.id()?;
 
         Ok((interrupt, interrupt_id))
     }
@@ -7,7 +7,8 @@ use crate::{
     CspaceTarget,
     InterruptTrigger,
     syscall,
-    sysret_2,
+    sysret_0,
+    sysret_3,
 };
 use crate::syscall_nums::*;
 use super::{Capability, cap_destroy, WEAK_AUTO_DESTROY};
@@ -15,7 +16,10 @@ use super::{Capability, cap_destroy, WEAK_AUTO_DESTROY};
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct InterruptId {
     pub cpu_num: usize,
+    /// The interrupt vector on the cpu
     pub interrupt_num: usize,
+    /// Local apic id of the cpu the interrupt is delivered to
+    pub apic_id: usize,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,12 +47,13 @@ impl Interrupt {
     }
 
     pub fn id(&self) -> KResult<InterruptId> {
-        let (cpu_num, interrupt_num) = unsafe {
-            sysret_2!(syscall!(
+        let (cpu_num, interrupt_num, apic_id) = unsafe {
+            sysret_3!(syscall!(
                 INTERRUPT_ID,
                 WEAK_AUTO_DESTROY,
                 self.as_usize(),
                 0usize,
+                0usize,
                 0usize
             ))?
         };
@@ -56,9 +61,47 @@ impl Interrupt {
         Ok(InterruptId {
             cpu_num,
             interrupt_num,
+            apic_id,
         })
     }
 
+    /// Stops the interrupt from being delivered until it is unmasked
+    /// 
+    /// If the interrupt triggers while masked, 1 trigger is delivered when it is unmasked.
+    /// This requires the write permission.
+    pub fn set_masked(&self, masked: bool) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                INTERRUPT_MASK,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                masked as usize
+            ))
+        }
+    }
+
+    pub fn mask(&self) -> KResult<()> {
+        self.set_masked(true)
+    }
+
+    pub fn unmask(&self) -> KResult<()> {
+        self.set_masked(false)
+    }
+
+    /// Acknowledges that the last interrupt has been handled, this requires the write permission
+    /// 
+    /// All interrupts are currently edge triggered and the kernel acknowledges them on delivery,
+    /// so this does nothing, but drivers should still call it once they are done handling an interrupt.
+    pub fn ack(&self) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                INTERRUPT_ACK,
+                WEAK_AUTO_DESTROY,
+                self.as_usize()
+            ))
+        }
+    }
+
     crate::generate_event_handlers!(
         InterruptTrigger,
         interrupt_trigger,
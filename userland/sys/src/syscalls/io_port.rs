@@ -0,0 +1,154 @@
+use serde::{Serialize, Deserialize};
+
+use crate::{
+    CapId,
+    CapType,
+    KResult,
+    CspaceTarget,
+    syscall,
+    sysret_0,
+    sysret_1,
+};
+use crate::syscall_nums::*;
+use super::{Capability, Allocator, cap_destroy, WEAK_AUTO_DESTROY, INVALID_CAPID_MESSAGE};
+
+/// Allows creating [`IoPort`] capabilities for any ports except the ones the kernel uses
+///
+/// Only 1 exists, the kernel gives it to early-init, which passes it on to hwaccess-server.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IoPortAccess(CapId);
+
+impl Capability for IoPortAccess {
+    const TYPE: CapType = CapType::IoPortAccess;
+
+    fn cloned_new_id(&self, cap_id: CapId) -> Option<Self> {
+        Self::from_cap_id(cap_id)
+    }
+
+    fn cap_id(&self) -> CapId {
+        self.0
+    }
+}
+
+impl IoPortAccess {
+    pub fn from_cap_id(cap_id: CapId) -> Option<Self> {
+        if cap_id.cap_type() == CapType::IoPortAccess {
+            Some(IoPortAccess(cap_id))
+        } else {
+            None
+        }
+    }
+
+    /// Creates an io port capability for the `count` ports starting at `base`
+    /// 
+    /// This requires the prod permission, and fails with [`SysErr::InvlPerm`](crate::SysErr::InvlPerm)
+    /// if any of the ports are used by the kernel.
+    pub fn new_port(&self, allocator: &Allocator, base: u16, count: u16) -> KResult<IoPort> {
+        let cap_id = unsafe {
+            sysret_1!(syscall!(
+                IO_PORT_NEW,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                allocator.as_usize(),
+                base as usize,
+                count as usize
+            ))?
+        };
+
+        let cap_id = CapId::try_from(cap_id).expect(INVALID_CAPID_MESSAGE);
+        Ok(IoPort::from_cap_id(cap_id).expect(INVALID_CAPID_MESSAGE))
+    }
+}
+
+impl Drop for IoPortAccess {
+    fn drop(&mut self) {
+        let _ = cap_destroy(CspaceTarget::Current, self.0);
+    }
+}
+
+/// Allows reading and writing a range of io ports, made with [`IoPortAccess::new_port`]
+///
+/// Ports are absolute port numbers, not offsets into the range.
+/// Reads need the read permission and writes need the write permission.
+/// Accessing a port outside of the range fails with [`SysErr::InvlPerm`](crate::SysErr::InvlPerm).
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IoPort(CapId);
+
+impl Capability for IoPort {
+    const TYPE: CapType = CapType::IoPort;
+
+    fn cloned_new_id(&self, cap_id: CapId) -> Option<Self> {
+        Self::from_cap_id(cap_id)
+    }
+
+    fn cap_id(&self) -> CapId {
+        self.0
+    }
+}
+
+impl IoPort {
+    pub fn from_cap_id(cap_id: CapId) -> Option<Self> {
+        if cap_id.cap_type() == CapType::IoPort {
+            Some(IoPort(cap_id))
+        } else {
+            None
+        }
+    }
+
+    /// Reads `width` bytes from `port`, `width` must be 1, 2, or 4
+    pub fn io_read(&self, port: u16, width: usize) -> KResult<usize> {
+        unsafe {
+            sysret_1!(syscall!(
+                IO_PORT_IN,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                port as usize,
+                width
+            ))
+        }
+    }
+
+    /// Writes the low `width` bytes of `value` to `port`, `width` must be 1, 2, or 4
+    pub fn io_write(&self, port: u16, width: usize, value: usize) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                IO_PORT_OUT,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                port as usize,
+                width,
+                value
+            ))
+        }
+    }
+
+    pub fn read8(&self, port: u16) -> KResult<u8> {
+        Ok(self.io_read(port, 1)? as u8)
+    }
+
+    pub fn read16(&self, port: u16) -> KResult<u16> {
+        Ok(self.io_read(port, 2)? as u16)
+    }
+
+    pub fn read32(&self, port: u16) -> KResult<u32> {
+        Ok(self.io_read(port, 4)? as u32)
+    }
+
+    pub fn write8(&self, port: u16, value: u8) -> KResult<()> {
+        self.io_write(port, 1, value as usize)
+    }
+
+    pub fn write16(&self, port: u16, value: u16) -> KResult<()> {
+        self.io_write(port, 2, value as usize)
+    }
+
+    pub fn write32(&self, port: u16, value: u32) -> KResult<()> {
+        self.io_write(port, 4, value as usize)
+    }
+}
+
+impl Drop for IoPort {
+    fn drop(&mut self) {
+        let _ = cap_destroy(CspaceTarget::Current, self.0);
+    }
+}
\ No newline at end of file
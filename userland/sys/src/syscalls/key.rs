@@ -7,6 +7,7 @@ use crate::{
     KResult,
     CspaceTarget,
     syscall,
+    sysret_0,
     sysret_1,
 };
 use crate::syscall_nums::*;
@@ -55,6 +56,24 @@ impl Key {
             ))
         }
     }
+
+    /// Registers this key as the identity of the current process
+    ///
+    /// Messages sent with [`SEND_IDENTITY`](crate::ChannelSendFlags::SEND_IDENTITY) carry this key's id,
+    /// which the reciever can trust since it is attached by the kernel.
+    ///
+    /// # Returns
+    ///
+    /// [`SysErr::InvlOp`](crate::SysErr::InvlOp) if the process already registered an identity
+    pub fn register_identity(&self) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                KEY_REGISTER_IDENTITY,
+                WEAK_AUTO_DESTROY,
+                self.as_usize()
+            ))
+        }
+    }
 }
 
 impl Drop for Key {
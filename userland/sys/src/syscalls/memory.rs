@@ -1,3 +1,9 @@
+#[cfg(feature = "alloc")]
+use alloc::vec;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::ops::Range;
+
 use serde::{Serialize, Deserialize};
 use bit_utils::Size;
 
@@ -7,10 +13,12 @@ use crate::{
     KResult,
     CspaceTarget,
     syscall,
+    sysret_0,
     sysret_1,
     sysret_2,
     MemoryNewFlags,
     MemoryResizeFlags,
+    MemoryQueryDirtyFlags,
 };
 use crate::syscall_nums::*;
 use super::{Capability, Allocator, cap_destroy, WEAK_AUTO_DESTROY, INVALID_CAPID_MESSAGE};
@@ -46,6 +54,10 @@ impl Memory {
         }
     }
 
+    /// Creates new memory of at least `size` bytes from `allocator`
+    /// 
+    /// `flags` control if memory is allocated lazily and if it is zeroed,
+    /// use `MemoryNewFlags::default()` for lazily allocated zeroed memory
     pub fn new(allocator: &Allocator, size: Size, flags: MemoryNewFlags) -> KResult<Self> {
         unsafe {
             sysret_2!(syscall!(
@@ -104,6 +116,168 @@ impl Memory {
 
         Ok(new_size)
     }
+
+    /// Copies `size` bytes from `src` starting at `src_offset` into this memory starting at `offset`
+    /// 
+    /// Neither memory needs to be mapped. If `src` references the same memory as `self`,
+    /// the source and destination ranges must not overlap, otherwise `InvlArgs` is returned.
+    pub fn copy_from(&self, offset: Size, src: &Memory, src_offset: Size, size: Size) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                MEMORY_COPY,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                offset.bytes(),
+                src.as_usize(),
+                src_offset.bytes(),
+                size.bytes()
+            ))
+        }
+    }
+
+    /// Creates a copy on write clone of this memory, allocating the new memory from `allocator`
+    /// 
+    /// Pages are shared until either memory writes to them, existing mappings of this memory become read only until written.
+    pub fn clone_cow(&self, allocator: &Allocator) -> KResult<Memory> {
+        unsafe {
+            sysret_2!(syscall!(
+                MEMORY_NEW_COW,
+                WEAK_AUTO_DESTROY,
+                allocator.as_usize(),
+                self.as_usize(),
+                // FIXME: hack to make syscall macro return right amount of values
+                0 as usize
+            )).map(|(cap_id, size)| Memory {
+                id: CapId::try_from(cap_id).expect(INVALID_CAPID_MESSAGE),
+                size: Some(Size::from_pages(size)),
+            })
+        }
+    }
+
+    /// Gets the physical address of the page at `page_index`, for giving to devices which perform dma
+    /// 
+    /// Lazily allocated and copy on write pages are allocated first. The physical address stays valid until
+    /// the memory is resized to not include the page, or a copy on write clone of the memory is made.
+    pub fn phys_addr(&self, page_index: usize) -> KResult<usize> {
+        unsafe {
+            sysret_1!(syscall!(
+                MEMORY_PHYS_ADDR,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                page_index
+            ))
+        }
+    }
+
+    /// Permanently makes this memory read only, this needs the write and prod permissions
+    /// 
+    /// Writable mappings of the memory become read only, and afterwards it can't be mapped writable, resized,
+    /// or written to in any other way, which fails with [`SysErr::InvlPerm`](crate::SysErr::InvlPerm).
+    /// Copy on write clones made before sealing are not sealed.
+    pub fn seal(&self) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                MEMORY_SEAL,
+                WEAK_AUTO_DESTROY,
+                self.as_usize()
+            ))
+        }
+    }
+
+    /// Checks if this memory has been sealed with [`seal`](Self::seal)
+    pub fn is_sealed(&self) -> KResult<bool> {
+        unsafe {
+            sysret_1!(syscall!(
+                MEMORY_IS_SEALED,
+                WEAK_AUTO_DESTROY,
+                self.as_usize()
+            )).map(|sealed| sealed != 0)
+        }
+    }
+
+    /// Finds which pages in `pages` were written through a mapping since their dirty bits were last reset
+    /// 
+    /// Bit `i` of `bitmap` is set if page `pages.start + i` is dirty in any mapping of this memory.
+    /// Pages which are not mapped are clean, and writes made by the kernel, like channel messages
+    /// and [`copy_from`](Self::copy_from), are not tracked. If `reset` is true the dirty bits are cleared,
+    /// which needs the write permission.
+    /// 
+    /// # Returns
+    /// 
+    /// The number of dirty pages
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if `bitmap` has less than `pages.len()` bits
+    pub fn query_dirty(&self, pages: Range<usize>, reset: bool, bitmap: &mut [u8]) -> KResult<usize> {
+        assert!(bitmap.len() * 8 >= pages.len(), "dirty page bitmap is too small");
+
+        let flags = if reset {
+            MemoryQueryDirtyFlags::RESET
+        } else {
+            MemoryQueryDirtyFlags::empty()
+        };
+
+        unsafe {
+            sysret_1!(syscall!(
+                MEMORY_QUERY_DIRTY,
+                flags.bits() | WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                pages.start,
+                pages.len(),
+                bitmap.as_mut_ptr() as usize
+            ))
+        }
+    }
+
+    /// Same as [`query_dirty`](Self::query_dirty), but returns the dirty pages as a [`DirtyPages`] bitmap
+    #[cfg(feature = "alloc")]
+    pub fn dirty_pages(&self, pages: Range<usize>, reset: bool) -> KResult<DirtyPages> {
+        let mut bitmap = vec![0; pages.len().div_ceil(8)];
+        let dirty_count = self.query_dirty(pages.clone(), reset, &mut bitmap)?;
+
+        Ok(DirtyPages {
+            pages,
+            bitmap,
+            dirty_count,
+        })
+    }
+}
+
+/// Which pages of a range of a [`Memory`] are dirty, returned by [`Memory::dirty_pages`]
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct DirtyPages {
+    pages: Range<usize>,
+    bitmap: Vec<u8>,
+    dirty_count: usize,
+}
+
+#[cfg(feature = "alloc")]
+impl DirtyPages {
+    /// The range of page indexes which was queried
+    pub fn pages(&self) -> Range<usize> {
+        self.pages.clone()
+    }
+
+    /// Returns true if the page at `page_index` in the memory is dirty, pages outside of the queried range are never dirty
+    pub fn is_dirty(&self, page_index: usize) -> bool {
+        if !self.pages.contains(&page_index) {
+            return false;
+        }
+
+        let bit = page_index - self.pages.start;
+        self.bitmap[bit / 8] & (1 << (bit % 8)) != 0
+    }
+
+    pub fn dirty_count(&self) -> usize {
+        self.dirty_count
+    }
+
+    /// Iterates over the indexes of the dirty pages in the memory, in increasing order
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.pages().filter(|page_index| self.is_dirty(*page_index))
+    }
 }
 
 impl Drop for Memory {
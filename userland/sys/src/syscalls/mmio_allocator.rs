@@ -51,6 +51,25 @@ impl MmioAllocator {
         let cap_id = CapId::try_from(cap_id).expect(INVALID_CAPID_MESSAGE);
         Ok(PhysMem::from_capid_size(cap_id, Some(size)).expect(INVALID_CAPID_MESSAGE))
     }
+
+    /// Exclusively claims the physical region, unlike [`MmioAllocator::alloc`] this fails if the region was already claimed
+    /// 
+    /// The region can be claimed again once all copies of the returned [`PhysMem`] capability are destroyed and it is unmapped
+    pub fn request_region(&self, allocator: &Allocator, phys_addr: usize, size: Size) -> KResult<PhysMem> {
+        let cap_id = unsafe {
+            sysret_1!(syscall!(
+                MMIO_ALLOCATOR_REQUEST_REGION,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                allocator.as_usize(),
+                phys_addr,
+                size.pages_rounded()
+            ))?
+        };
+
+        let cap_id = CapId::try_from(cap_id).expect(INVALID_CAPID_MESSAGE);
+        Ok(PhysMem::from_capid_size(cap_id, Some(size)).expect(INVALID_CAPID_MESSAGE))
+    }
 }
 
 impl Drop for MmioAllocator {
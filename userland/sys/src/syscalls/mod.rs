@@ -12,14 +12,22 @@ mod channel;
 pub use channel::*;
 pub mod debug;
 pub use debug::*;
+mod debug_cap;
+pub use debug_cap::*;
 mod drop_check;
 pub use drop_check::*;
+mod entropy;
+pub use entropy::*;
 mod event_pool;
 pub use event_pool::*;
+mod futex;
+pub use futex::*;
 mod interrupt;
 pub use interrupt::*;
 mod int_allocator;
 pub use int_allocator::*;
+mod io_port;
+pub use io_port::*;
 mod key;
 pub use key::*;
 mod memory;
@@ -34,6 +42,10 @@ mod thread;
 pub use thread::*;
 mod thread_group;
 pub use thread_group::*;
+mod time;
+pub use time::*;
+mod weak;
+pub use weak::*;
 
 // need to use rcx because rbx is reserved by llvm
 // FIXME: ugly
@@ -42,6 +54,7 @@ macro_rules! syscall {
     ($num:expr) => {syscall!($num, 0)};
 
 	($num:expr, $opt:expr) => {{
+        $crate::count_syscall();
         core::arch::asm!("syscall",
             inout("rax") (($opt as usize) << 32) | ($num as usize) => _,
             out("rcx") _,
@@ -53,6 +66,7 @@ macro_rules! syscall {
 	($num:expr, $opt:expr, $a1:expr) => {{
 		let o1: usize;
         let o2: usize;
+        $crate::count_syscall();
         core::arch::asm!("push rbx",
             "mov rbx, rcx",
             "syscall",
@@ -70,6 +84,7 @@ macro_rules! syscall {
 	($num:expr, $opt:expr, $a1:expr, $a2:expr) => {{
 		let o1: usize;
 		let o2: usize;
+        $crate::count_syscall();
         core::arch::asm!("push rbx",
             "mov rbx, rcx",
             "syscall",
@@ -88,6 +103,7 @@ macro_rules! syscall {
 		let o1: usize;
 		let o2: usize;
 		let o3: usize;
+        $crate::count_syscall();
         core::arch::asm!("push rbx",
             "mov rbx, rcx",
             "syscall",
@@ -108,6 +124,7 @@ macro_rules! syscall {
 		let o2: usize;
 		let o3: usize;
 		let o4: usize;
+        $crate::count_syscall();
         core::arch::asm!("push rbx",
             "mov rbx, rcx",
             "syscall",
@@ -130,6 +147,7 @@ macro_rules! syscall {
 		let o3: usize;
 		let o4: usize;
 		let o5: usize;
+        $crate::count_syscall();
         core::arch::asm!("push rbx",
             "mov rbx, rcx",
             "syscall",
@@ -154,6 +172,7 @@ macro_rules! syscall {
 		let o4: usize;
 		let o5: usize;
 		let o6: usize;
+        $crate::count_syscall();
         core::arch::asm!("push rbx",
             "mov rbx, rcx",
             "syscall",
@@ -180,6 +199,7 @@ macro_rules! syscall {
 		let o5: usize;
 		let o6: usize;
 		let o7: usize;
+        $crate::count_syscall();
         core::arch::asm!("push rbx",
             "mov rbx, rcx",
             "syscall",
@@ -208,6 +228,7 @@ macro_rules! syscall {
 		let o6: usize;
 		let o7: usize;
 		let o8: usize;
+        $crate::count_syscall();
         core::arch::asm!("push rbx",
             "mov rbx, rcx",
             "syscall",
@@ -297,6 +318,40 @@ macro_rules! sysret_3 {
     };
 }
 
+#[macro_export]
+macro_rules! sysret_4 {
+    ($data:expr) => {
+        {
+            let result = $data;
+            let syserr = $crate::SysErr::new(result.0)
+                .expect("invalid syserr code recieved from kernel");
+
+            if syserr == $crate::SysErr::Ok {
+                Ok((result.1, result.2, result.3, result.4))
+            } else {
+                Err(syserr)
+            }
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! sysret_5 {
+    ($data:expr) => {
+        {
+            let result = $data;
+            let syserr = $crate::SysErr::new(result.0)
+                .expect("invalid syserr code recieved from kernel");
+
+            if syserr == $crate::SysErr::Ok {
+                Ok((result.1, result.2, result.3, result.4, result.5))
+            } else {
+                Err(syserr)
+            }
+        }
+    };
+}
+
 const INVALID_CAPID_MESSAGE: &'static str = "invalid capid recieved from kernel";
 pub const WEAK_AUTO_DESTROY: u32 = 1 << 31;
 
@@ -463,7 +518,10 @@ pub fn cap_clone_inner(
     }
 }
 
-fn cap_destroy(
+/// Destroys the capability with id `capability_id` in `cspace`
+///
+/// Capability structs destroy their capability when dropped, so this is only needed for ids which no struct owns.
+pub fn cap_destroy(
     cspace: CspaceTarget,
     capability_id: CapId,
 ) -> KResult<()> {
@@ -1,3 +1,5 @@
+use core::time::Duration;
+
 use serde::{Serialize, Deserialize};
 use strum::FromRepr;
 
@@ -10,6 +12,7 @@ use crate::{
     CapabilitySpace,
     ThreadNewFlags,
     ThreadSuspendFlags,
+    ThreadParkFlags,
     ThreadDestroyFlags,
     CspaceTarget,
     syscall,
@@ -173,23 +176,118 @@ impl Thread {
         }
     }
 
-    crate::generate_event_handlers!(ThreadExit, thread_exit, THREAD_HANDLE_THREAD_EXIT_SYNC, THREAD_HANDLE_THREAD_EXIT_ASYNC, 0);
+    /// Blocks the current thread until [`Thread::unpark`] is called on it
+    ///
+    /// If the thread was unparked since it last parked, this consumes the wake token and returns immediately,
+    /// so an unpark which happens just before parking is not lost.
+    /// If `timeout` is set, [`SysErr::OkTimeout`](crate::SysErr::OkTimeout) is returned once the monotonic clock reaches `timeout` nanoseconds.
+    /// Wakeups can be spurious, so the caller should check the condition it is waiting for again.
+    pub fn park(timeout: Option<u64>) -> KResult<()> {
+        let flags = if timeout.is_some() {
+            ThreadParkFlags::PARK_TIMEOUT
+        } else {
+            ThreadParkFlags::empty()
+        };
+
+        unsafe {
+            sysret_0!(syscall!(
+                THREAD_PARK,
+                flags.bits(),
+                timeout.unwrap_or_default() as usize
+            ))
+        }
+    }
+
+    /// Wakes this thread if it is parked, otherwise its next [`Thread::park`] returns immediately, this requires the prod permission
+    pub fn unpark(&self) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                THREAD_UNPARK,
+                WEAK_AUTO_DESTROY,
+                self.as_usize()
+            ))
+        }
+    }
+
+    crate::generate_event_handlers!(ThreadExit, thread_exit, THREAD_HANDLE_THREAD_EXIT_SYNC, THREAD_HANDLE_THREAD_EXIT_ASYNC, 1);
 }
 
 #[repr(usize)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
 pub enum ThreadProperty {
+    /// Pointer to thread local data of the current thread
     ThreadLocalPointer,
+    /// [`ThreadPriority`] of the thread passed to the syscall
+    Priority,
+    /// Mask of cpus the thread passed to the syscall may run on, bit n allows cpu n
+    CpuMask,
+}
+
+/// Threads with higher priority are always run before ready threads with lower priority,
+/// except that lower priority threads which have waited too long are run once so they don't starve
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, FromRepr)]
+pub enum ThreadPriority {
+    Low,
+    #[default]
+    Normal,
+    /// Setting a priority above [`ThreadPriority::Normal`] requires the [`CapFlags::PROD`](crate::CapFlags::PROD) permission on the thread
+    High,
+    Highest,
 }
 
 impl Thread {
+    /// Sets a property of the current thread
     pub fn set_property(property: ThreadProperty, data: usize) -> KResult<()> {
         unsafe {
             sysret_0!(syscall!(
                 THREAD_SET_PROPERTY,
                 0,
                 property as usize,
-                data
+                data,
+                0usize
+            ))
+        }
+    }
+
+    /// Gets the total time this thread has spent running
+    pub fn runtime(&self) -> KResult<Duration> {
+        let runtime = unsafe {
+            sysret_1!(syscall!(
+                THREAD_RUNTIME,
+                WEAK_AUTO_DESTROY,
+                self.as_usize()
+            ))?
+        };
+
+        Ok(Duration::from_nanos(runtime as u64))
+    }
+
+    /// Sets the scheduling priority of this thread, this requires the write permission
+    pub fn set_priority(&self, priority: ThreadPriority) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                THREAD_SET_PROPERTY,
+                WEAK_AUTO_DESTROY,
+                ThreadProperty::Priority as usize,
+                priority as usize,
+                self.as_usize()
+            ))
+        }
+    }
+
+    /// Sets which cpus this thread may run on, bit n allows cpu n, this requires the write permission
+    ///
+    /// Fails with [`SysErr::InvlArgs`](crate::SysErr::InvlArgs) if `mask` is 0 or has bits set for cpus which don't exist.
+    /// If this thread is currently running on a cpu which is no longer allowed, it moves the next time it is switched away from.
+    pub fn set_affinity(&self, mask: u64) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                THREAD_SET_PROPERTY,
+                WEAK_AUTO_DESTROY,
+                ThreadProperty::CpuMask as usize,
+                mask as usize,
+                self.as_usize()
             ))
         }
     }
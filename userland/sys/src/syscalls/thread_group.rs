@@ -1,3 +1,6 @@
+use core::time::Duration;
+
+use bytemuck::{Pod, Zeroable};
 use serde::{Serialize, Deserialize};
 
 use crate::{
@@ -5,12 +8,65 @@ use crate::{
     CapType,
     KResult,
     CspaceTarget,
+    DebugSetStraceFlags,
+    FaultKind,
     syscall,
     sysret_0,
     sysret_1,
+    sysret_5,
 };
 use crate::syscall_nums::*;
-use super::{Capability, Allocator, cap_destroy, WEAK_AUTO_DESTROY, INVALID_CAPID_MESSAGE};
+use super::{Capability, Allocator, Memory, cap_destroy, WEAK_AUTO_DESTROY, INVALID_CAPID_MESSAGE};
+
+/// Cpu usage and thread counts of a thread group, returned by [`ThreadGroup::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ThreadGroupStats {
+    /// Total time threads in the group have spent running, time slices which have not ended yet are not included
+    pub runtime: Duration,
+    /// Number of times a thread in the group was switched away from
+    pub context_switches: u64,
+    pub running_threads: usize,
+    pub ready_threads: usize,
+    pub suspended_threads: usize,
+}
+
+/// State of the first thread in a thread group to fault, returned by [`ThreadGroup::fault_info`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
+pub struct FaultRecord {
+    pub rax: usize,
+    pub rbx: usize,
+    pub rcx: usize,
+    pub rdx: usize,
+    pub rbp: usize,
+    pub rsp: usize,
+    pub rdi: usize,
+    pub rsi: usize,
+    pub r8: usize,
+    pub r9: usize,
+    pub r10: usize,
+    pub r11: usize,
+    pub r12: usize,
+    pub r13: usize,
+    pub r14: usize,
+    pub r15: usize,
+    pub rflags: usize,
+    pub rip: usize,
+    /// Address whose access faulted
+    pub fault_address: usize,
+    /// Error code the cpu pushed for the fault
+    pub error_code: usize,
+    /// Raw [`FaultKind`]
+    pub kind: usize,
+    /// Number of bytes of the stack starting at `rsp` which were copied to the fault capture memory
+    pub stack_size: usize,
+}
+
+impl FaultRecord {
+    pub fn kind(&self) -> Option<FaultKind> {
+        FaultKind::from_repr(self.kind)
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ThreadGroup(CapId);
@@ -49,12 +105,101 @@ impl ThreadGroup {
         Ok(ThreadGroup(CapId::try_from(child_cap_id).expect(INVALID_CAPID_MESSAGE)))
     }
 
-    pub fn exit(&self) -> KResult<()> {
+    /// Gets the cpu time used by this thread group, and how many threads directly in this group are in each state
+    /// 
+    /// Threads in child thread groups are not counted
+    pub fn stats(&self) -> KResult<ThreadGroupStats> {
+        let (runtime, context_switches, running_threads, ready_threads, suspended_threads) = unsafe {
+            sysret_5!(syscall!(
+                THREAD_GROUP_STATS,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                0usize,
+                0usize,
+                0usize,
+                0usize,
+                0usize
+            ))?
+        };
+
+        Ok(ThreadGroupStats {
+            runtime: Duration::from_nanos(runtime as u64),
+            context_switches: context_switches as u64,
+            running_threads,
+            ready_threads,
+            suspended_threads,
+        })
+    }
+
+    /// Kills every thread in this thread group and its child thread groups
+    ///
+    /// Exit event listeners of threads directly in this group recieve [`ExitReason::Code`](crate::ExitReason::Code) with `exit_code`,
+    /// threads in child groups report [`ExitReason::Killed`](crate::ExitReason::Killed)
+    pub fn exit(&self, exit_code: i32) -> KResult<()> {
         unsafe {
             sysret_0!(syscall!(
                 THREAD_GROUP_EXIT,
                 WEAK_AUTO_DESTROY,
-                self.as_usize()
+                self.as_usize(),
+                exit_code as u32 as usize
+            ))
+        }
+    }
+
+    /// Gets the registers of the first thread in this thread group which was killed by a fault
+    /// 
+    /// Returns None if no thread in this group has faulted. This still works after the thread group has died.
+    pub fn fault_info(&self) -> KResult<Option<FaultRecord>> {
+        let mut record = FaultRecord::default();
+
+        let has_record = unsafe {
+            sysret_1!(syscall!(
+                THREAD_GROUP_FAULT_INFO,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                &mut record as *mut FaultRecord as usize
+            ))?
+        };
+
+        Ok((has_record != 0).then_some(record))
+    }
+
+    /// Makes the kernel copy the stack of a thread which faults in this group into `memory`
+    /// 
+    /// The stack is copied starting at the faulting thread's `rsp`, up to the size of `memory` or the end of the stack,
+    /// and [`FaultRecord::stack_size`] says how much was copied. `memory` is allocated when this is called,
+    /// and the kernel keeps a reference to it until the thread group is destroyed.
+    pub fn set_fault_capture(&self, memory: &Memory) -> KResult<()> {
+        unsafe {
+            sysret_0!(syscall!(
+                THREAD_GROUP_SET_FAULT_CAPTURE,
+                WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                memory.as_usize()
+            ))
+        }
+    }
+
+    /// Sets which syscalls made by threads in this thread group are printed to the kernel debug log
+    ///
+    /// Bit n of `syscall_mask` is set if syscall number n should be traced,
+    /// `process_name` is printed with each traced syscall and is truncated to 32 bytes
+    pub fn set_strace(&self, enabled: bool, syscall_mask: u128, process_name: &str) -> KResult<()> {
+        let flags = if enabled {
+            DebugSetStraceFlags::ENABLED
+        } else {
+            DebugSetStraceFlags::empty()
+        };
+
+        unsafe {
+            sysret_0!(syscall!(
+                DEBUG_SET_STRACE,
+                flags.bits() | WEAK_AUTO_DESTROY,
+                self.as_usize(),
+                syscall_mask as u64 as usize,
+                (syscall_mask >> 64) as u64 as usize,
+                process_name.as_ptr() as usize,
+                process_name.len()
             ))
         }
     }
@@ -0,0 +1,33 @@
+use core::time::Duration;
+
+use strum::FromRepr;
+
+use crate::{KResult, syscall, sysret_2};
+use crate::syscall_nums::*;
+
+/// Clocks which can be read with [`time_get`]
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+pub enum ClockId {
+    /// Time since boot, this never goes backwards
+    /// 
+    /// This is the clock used for timeouts passed to the kernel
+    Monotonic,
+    /// Time since the unix epoch, this is unavailable if the kernel could not read the real time clock
+    Realtime,
+}
+
+/// Gets the current time of the given clock
+pub fn time_get(clock_id: ClockId) -> KResult<Duration> {
+    let (seconds, nanoseconds) = unsafe {
+        sysret_2!(syscall!(
+            TIME_GET,
+            0,
+            clock_id as usize,
+            0usize,
+            0usize
+        ))?
+    };
+
+    Ok(Duration::new(seconds as u64, nanoseconds as u32))
+}
\ No newline at end of file
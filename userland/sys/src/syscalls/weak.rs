@@ -0,0 +1,82 @@
+use core::ops::Deref;
+
+use crate::{CapFlags, KResult, SysErr, CspaceTarget};
+use super::{Capability, cap_clone_weak, cap_clone_strong};
+
+/// A weak reference to a capability, which does not keep the object it refers to alive
+///
+/// This is meant for things like caches of service endpoints, which should not stop a service from being dropped.
+/// The capability can only be used by upgrading it with [`upgrade`](Self::upgrade), which needs the
+/// [`UPGRADE`](CapFlags::UPGRADE) permission.
+#[derive(Debug)]
+pub struct Weak<T: Capability>(T);
+
+impl<T: Capability> Weak<T> {
+    /// Makes a weak reference to the object `cap` refers to, with the same permissions as `cap`
+    ///
+    /// If `cap` does not have the upgrade permission, the weak reference can never be upgraded.
+    pub fn new(cap: &T) -> KResult<Self> {
+        let weak_cap = cap_clone_weak(CspaceTarget::Current, CspaceTarget::Current, cap, CapFlags::all())?;
+
+        Ok(Weak(weak_cap))
+    }
+
+    /// Wraps a capability which is already weak, returns None if `cap` is strong
+    pub fn from_weak(cap: T) -> Option<Self> {
+        if cap.cap_id().is_weak() {
+            Some(Weak(cap))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the weak capability, which can only be used to clone it or make strong capabilities
+    pub fn inner(&self) -> &T {
+        &self.0
+    }
+
+    /// Makes a temporary strong capability, which is destroyed when the returned guard is dropped
+    ///
+    /// # Returns
+    ///
+    /// [`SysErr::InvlWeak`] if the object has been dropped, or [`SysErr::InvlPerm`] if the capability does not have the upgrade permission
+    pub fn upgrade(&self) -> KResult<StrongGuard<T>> {
+        match cap_clone_strong(CspaceTarget::Current, CspaceTarget::Current, &self.0, CapFlags::all()) {
+            Ok(strong_cap) => Ok(StrongGuard(strong_cap)),
+            // the kernel removes the weak capability the first time it finds the object dropped
+            Err(SysErr::InvlId) => Err(SysErr::InvlWeak),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Checks if the object this refers to still exists
+    ///
+    /// There is no syscall to query a capability, so this upgrades the capability and then drops the strong capability.
+    /// Prefer just calling [`upgrade`](Self::upgrade) when the capability is going to be used.
+    pub fn is_alive(&self) -> KResult<bool> {
+        match self.upgrade() {
+            Ok(_) => Ok(true),
+            Err(SysErr::InvlWeak) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// A strong capability made by [`Weak::upgrade`], which is destroyed when this is dropped
+#[derive(Debug)]
+pub struct StrongGuard<T: Capability>(T);
+
+impl<T: Capability> StrongGuard<T> {
+    /// Keeps the strong capability instead of destroying it when the guard is dropped
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: Capability> Deref for StrongGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
\ No newline at end of file
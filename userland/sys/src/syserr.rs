@@ -29,12 +29,20 @@ pub enum SysErr {
     InvlSyscall = 17,
     InvlBuffer = 18,
     Unknown = 19,
+    /// An event could not be written because the event pool reached its maximum size
+    EventPoolFull = 20,
+    /// An allocation would put an allocator over its memory limit
+    QuotaExceeded = 21,
+    /// A thread was waiting on a channel or reply which was destroyed before it could be completed
+    PeerGone = 22,
+    /// A channel's queue of senders waiting for a reciever reached its limit
+    ChannelFull = 23,
 }
 
 impl SysErr {
     /// Creates a SysErr from the given number, returns none if `n` is an invalid syserr code
     pub fn new(n: usize) -> Option<Self> {
-        if n > Self::Unknown as usize {
+        if n > Self::ChannelFull as usize {
             None
         } else {
             unsafe { Some(core::mem::transmute(n)) }
@@ -67,6 +75,10 @@ impl SysErr {
             Self::InvlSyscall => "invalid syscall number",
             Self::InvlBuffer => "invalid buffer for reading or writing syscall arguments or return values",
             Self::Unknown => "unknown error",
+            Self::EventPoolFull => "event pool is full",
+            Self::QuotaExceeded => "allocator memory limit exceeded",
+            Self::PeerGone => "the other end of the channel went away",
+            Self::ChannelFull => "too many senders are waiting on the channel",
         }
     }
 }
@@ -75,4 +87,7 @@ impl fmt::Display for SysErr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str(self.as_str())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SysErr {}
\ No newline at end of file
@@ -0,0 +1,1141 @@
+//! Tests making rpc calls to a service running in the same process, and recording and replaying a service
+//!
+//! The identity test also calls the service from children, which are copies of this binary read from the fs server.
+//! A child is started with a client for the identity service in its handle table.
+//! The service mux test starts a child with the `duplicate_mux` named arg, which should panic.
+//! The capability wrapper tests send channels to a service wrapped in [`CapShare`] and [`CapMove`].
+//! The caller exit test starts a child with the `abandon_call` named arg, which makes a call and exits without waiting for the response.
+//! The loopback test serves the counter service over endpoints which are connected in this process instead of by a kernel channel.
+//! The arena test makes calls to the counter service with arguments serialized into one [`MessageArena`].
+//! The mixed service has methods which are skipped, pinned to an id, or run remotely despite having a default body.
+//! The version tests serve one version of an interface and call it with a client for another version.
+//! The aborted handler and shutdown tests use a service implemented by hand, whose calls can be held in a task until a gate is opened.
+
+#![no_std]
+
+#![feature(associated_type_defaults)]
+#![feature(trait_alias)]
+#![feature(decl_macro)]
+
+extern crate alloc;
+extern crate std;
+
+use core::cell::{Cell, RefCell};
+use core::future::{Future, poll_fn};
+use core::pin::pin;
+use core::task::Poll;
+
+use alloc::format;
+use alloc::rc::Rc;
+
+use arpc::{
+    CallContext,
+    CapMove,
+    CapShare,
+    ClientRpcEndpoint,
+    ReplyGuard,
+    RpcCall,
+    RpcCallMethod,
+    RpcError,
+    RpcService,
+    ServiceIds,
+    ServiceMux,
+};
+use arpc::replay::{self, ReplayError};
+use asynca::JoinHandle;
+use asynca::channel::{Receiver, Sender};
+use aurora::{env, fs, this_context};
+use aurora::collections::MessageArena;
+use aurora::process::{self, Child, Command};
+use aurora_test::{TestResult, test_assert, test_assert_eq};
+use bit_utils::Size;
+use serde::Serialize;
+use std::prelude::*;
+use sys::{
+    CapFlags,
+    CapId,
+    Capability,
+    CapabilityWeakness,
+    Channel,
+    CspaceTarget,
+    Key,
+    SysErr,
+    cap_clone,
+    cap_clone_inner,
+    cap_destroy,
+};
+
+/// Where the fs server puts this binary from the initrd
+const BINARY_PATH: &str = "/initrd/test-arpc";
+
+/// Slot of the handle table which holds the identity client of a child
+const IDENTITY_CLIENT_HANDLE: usize = 3;
+
+/// Slot of the handle table which holds the caller exit client of a child
+const ABANDONED_CALL_HANDLE: usize = 4;
+
+/// Number of clones of the echo client which make calls at the same time
+const CONCURRENT_CLIENT_COUNT: usize = 4;
+
+/// Total number of calls made by the clones, they are spread evenly between them
+const CONCURRENT_CALL_COUNT: usize = 100;
+
+/// Number of oneway notifications sent by the oneway test
+const NOTIFICATION_COUNT: usize = 100;
+
+/// Number of calls made with arguments serialized into the same message arena
+const ARENA_CALL_COUNT: u64 = 1000;
+
+#[arpc::service(service_id = 13, name = "Echo")]
+pub trait EchoServer {
+    /// Returns `message` unchanged
+    fn echo(&self, message: String) -> String;
+
+    /// Returns how many times [`echo`](EchoServer::echo) has been called
+    fn echo_count(&self) -> u64;
+
+    /// Returns the name of the service
+    ///
+    /// [`CounterServer`] has a method with the same name, which checks their generated items do not collide.
+    fn status(&self) -> String;
+}
+
+#[derive(Default)]
+struct EchoServerImpl {
+    echo_count: Cell<u64>,
+}
+
+#[arpc::service_impl]
+impl EchoServer for EchoServerImpl {
+    fn echo(&self, message: String) -> String {
+        self.echo_count.set(self.echo_count.get() + 1);
+        message
+    }
+
+    fn echo_count(&self) -> u64 {
+        self.echo_count.get()
+    }
+
+    fn status(&self) -> String {
+        "echo".to_owned()
+    }
+}
+
+/// Echoes messages backwards, used to check that replaying a recording against a changed service finds the change
+#[derive(Default)]
+struct ReversedEchoServerImpl {
+    echo_count: Cell<u64>,
+}
+
+#[arpc::service_impl]
+impl EchoServer for ReversedEchoServerImpl {
+    fn echo(&self, message: String) -> String {
+        self.echo_count.set(self.echo_count.get() + 1);
+        message.chars().rev().collect()
+    }
+
+    fn echo_count(&self) -> u64 {
+        self.echo_count.get()
+    }
+
+    fn status(&self) -> String {
+        "reversed echo".to_owned()
+    }
+}
+
+#[arpc::service(service_id = 14, name = "Identity")]
+pub trait IdentityServer {
+    /// Returns the identity key id of the caller
+    #[arpc(with_context)]
+    fn caller_identity(&self, context: CallContext) -> Option<u64>;
+}
+
+#[arpc::service(service_id = 16, name = "Counter")]
+pub trait CounterServer {
+    /// Adds `amount` to the counter and returns the new total
+    fn add(&self, amount: u64) -> u64;
+
+    /// Returns the name of the service
+    fn status(&self) -> String;
+}
+
+#[derive(Default)]
+struct CounterServerImpl {
+    total: Cell<u64>,
+}
+
+#[arpc::service_impl]
+impl CounterServer for CounterServerImpl {
+    fn add(&self, amount: u64) -> u64 {
+        self.total.set(self.total.get() + amount);
+        self.total.get()
+    }
+
+    fn status(&self) -> String {
+        "counter".to_owned()
+    }
+}
+
+#[arpc::service(service_id = 17, name = "CapReceiver")]
+pub trait CapReceiverServer {
+    /// Returns the permissions the shared channel was recieved with
+    fn shared_flags(&self, channel: CapShare<Channel>) -> usize;
+
+    /// Keeps the moved channel, and returns the permissions it was recieved with
+    fn take(&self, channel: CapMove<Channel>) -> usize;
+}
+
+#[derive(Default)]
+struct CapReceiverServerImpl {
+    taken: RefCell<Vec<Channel>>,
+}
+
+#[arpc::service_impl]
+impl CapReceiverServer for CapReceiverServerImpl {
+    fn shared_flags(&self, channel: CapShare<Channel>) -> usize {
+        channel.inner().cap_id().flags().bits()
+    }
+
+    fn take(&self, channel: CapMove<Channel>) -> usize {
+        let channel = channel.into_inner();
+        let flags = channel.cap_id().flags().bits();
+        self.taken.borrow_mut().push(channel);
+
+        flags
+    }
+}
+
+#[arpc::service(service_id = 18, name = "CallerExit")]
+pub trait CallerExitServer {
+    /// Waits for the child which called this to exit before returning, so the response can't be delivered
+    fn wait_for_caller_exit(&self);
+
+    /// Does nothing, used to check the service still responds
+    fn ping(&self);
+}
+
+struct CallerExitServerImpl {
+    /// The child which calls [`wait_for_caller_exit`](CallerExitServer::wait_for_caller_exit), set once it is spawned
+    child: Rc<RefCell<Option<Child>>>,
+    /// Sent to once the child's call has been handled
+    handled: Sender<()>,
+}
+
+#[arpc::service_impl]
+impl CallerExitServer for CallerExitServerImpl {
+    fn wait_for_caller_exit(&self) {
+        if let Some(child) = self.child.borrow_mut().take() {
+            // this blocks the service, but the child doesn't need it to exit
+            let _ = child.wait();
+        }
+
+        let _ = self.handled.try_send(());
+    }
+
+    fn ping(&self) {}
+}
+
+#[arpc::service(service_id = 19, name = "Notifier")]
+pub trait NotifierServer {
+    /// Counts a notification, the client does not wait for it to be handled
+    #[arpc(oneway)]
+    fn notify(&self);
+
+    /// Returns how many notifications have been handled
+    fn notification_count(&self) -> u64;
+}
+
+#[derive(Default)]
+struct NotifierServerImpl {
+    notification_count: Cell<u64>,
+}
+
+#[arpc::service_impl]
+impl NotifierServer for NotifierServerImpl {
+    fn notify(&self) {
+        self.notification_count.set(self.notification_count.get() + 1);
+    }
+
+    fn notification_count(&self) -> u64 {
+        self.notification_count.get()
+    }
+}
+
+#[arpc::service(service_id = 21, name = "CounterV1", version = 1)]
+pub trait CounterV1Server {
+    /// Adds `amount` to the counter and returns the new total
+    fn add(&self, amount: u64) -> u64;
+}
+
+/// A later version of [`CounterV1Server`], which has the same service id and adds a method
+#[arpc::service(service_id = 21, name = "CounterV2", version = 2)]
+pub trait CounterV2Server {
+    /// Adds `amount` to the counter and returns the new total
+    fn add(&self, amount: u64) -> u64;
+
+    /// Sets the counter back to 0
+    fn reset(&self);
+}
+
+#[derive(Default)]
+struct CounterV1ServerImpl {
+    total: Cell<u64>,
+}
+
+#[arpc::service_impl]
+impl CounterV1Server for CounterV1ServerImpl {
+    fn add(&self, amount: u64) -> u64 {
+        self.total.set(self.total.get() + amount);
+        self.total.get()
+    }
+}
+
+#[derive(Default)]
+struct CounterV2ServerImpl {
+    total: Cell<u64>,
+}
+
+#[arpc::service_impl]
+impl CounterV2Server for CounterV2ServerImpl {
+    fn add(&self, amount: u64) -> u64 {
+        self.total.set(self.total.get() + amount);
+        self.total.get()
+    }
+
+    fn reset(&self) {
+        self.total.set(0);
+    }
+}
+
+/// Mixes methods which are called over rpc with methods which only run on the server
+///
+/// `set` is pinned to id 1, so `describe` and `increment` are given ids 2 and 3.
+#[arpc::service(service_id = 22, name = "Mixed")]
+pub trait MixedServer {
+    fn get(&self) -> u64;
+
+    #[arpc(id = 1)]
+    fn set(&self, value: u64);
+
+    /// Has a default body, so it only runs on the server and has no method id
+    fn doubled(&self) -> u64 {
+        self.get() * 2
+    }
+
+    /// Has a default body, but is still called over rpc
+    #[arpc(remote)]
+    fn describe(&self) -> String {
+        format!("value is {}", self.get())
+    }
+
+    #[arpc(skip)]
+    fn reset(&self);
+
+    /// Adds `amount` to the value and returns the new value
+    fn increment(&self, amount: u64) -> u64;
+}
+
+#[derive(Default)]
+struct MixedServerImpl {
+    value: Cell<u64>,
+}
+
+#[arpc::service_impl]
+impl MixedServer for MixedServerImpl {
+    fn get(&self) -> u64 {
+        self.value.get()
+    }
+
+    fn set(&self, value: u64) {
+        self.value.set(value);
+    }
+
+    fn reset(&self) {
+        self.value.set(0);
+    }
+
+    fn increment(&self, amount: u64) -> u64 {
+        self.value.set(self.value.get() + amount);
+        self.value.get()
+    }
+}
+
+/// Service id of [`GatedServiceImpl`]
+const GATED_SERVICE_ID: u64 = 20;
+
+/// Method of the gated service which responds once the gate is opened
+const WAIT_FOR_GATE_METHOD_ID: u32 = 0;
+
+/// Method of the gated service which responds straight away
+const PING_METHOD_ID: u32 = 1;
+
+/// Handles calls to wait for the gate in a task, which responds once something is sent to the gate
+///
+/// This is implemented by hand instead of with [`arpc::service`], since generated services don't give out their handler tasks.
+struct GatedServiceImpl {
+    /// Taken by the first call to wait for the gate, later calls respond straight away
+    gate: RefCell<Option<Receiver<()>>>,
+    /// Handler task of the most recent call to wait for the gate
+    handler_task: Rc<RefCell<Option<JoinHandle<()>>>>,
+    /// Sent to once a handler task has started waiting
+    started: Sender<()>,
+}
+
+impl RpcService for GatedServiceImpl {
+    type Client = ClientRpcEndpoint;
+
+    fn service_ids(&self) -> ServiceIds {
+        let mut service_ids = ServiceIds::default();
+        service_ids.insert(GATED_SERVICE_ID);
+        service_ids
+    }
+
+    fn call_method(&self, call_data: &RpcCallMethod, _data: &[u8], reply: ReplyGuard, _context: CallContext) {
+        if call_data.method_id != WAIT_FOR_GATE_METHOD_ID {
+            reply.reply(());
+            return;
+        }
+
+        let gate = self.gate.borrow_mut().take();
+        let started = self.started.clone();
+
+        let handler_task = asynca::spawn(async move {
+            let _ = started.try_send(());
+            if let Some(mut gate) = gate {
+                gate.recv().await;
+            }
+
+            reply.reply(());
+        });
+
+        *self.handler_task.borrow_mut() = Some(handler_task);
+    }
+}
+
+/// Calls `method_id` on the gated service
+async fn call_gated(endpoint: &ClientRpcEndpoint, method_id: u32) -> Result<(), RpcError> {
+    endpoint.call(RpcCall {
+        service_id: GATED_SERVICE_ID,
+        method_id,
+        args: (),
+    }).await
+}
+
+struct IdentityServerImpl {
+    /// Every identity the service sees is also sent here, so the test knows when a child has made its calls
+    seen_identities: Sender<Option<u64>>,
+}
+
+#[arpc::service_impl]
+impl IdentityServer for IdentityServerImpl {
+    fn caller_identity(&self, context: CallContext) -> Option<u64> {
+        let _ = self.seen_identities.try_send(context.sender_key_id);
+        context.sender_key_id
+    }
+}
+
+/// Runs in a child, calls the identity service twice and exits with 0 if it saw the same identity both times
+fn identity_child(identity: Identity) -> ! {
+    let register_identity = env::args().require::<bool>("register_identity");
+
+    // the identity stays registered after the key is dropped
+    let _key = register_identity.then(|| {
+        let key = Key::new(CapFlags::READ, &this_context().allocator).expect("failed to create identity key");
+        key.register_identity().expect("failed to register identity");
+        key
+    });
+
+    let identity = identity.with_identity();
+    let (first, second) = asynca::block_in_place(async move {
+        (identity.caller_identity().await, identity.caller_identity().await)
+    });
+
+    let success = first == second && first.is_some() == register_identity;
+    process::exit_with_code(if success { 0 } else { 1 });
+}
+
+fn caller_identity_is_attached() -> TestResult {
+    let elf_data = asynca::block_in_place(fs::read(BINARY_PATH))
+        .map_err(|error| format!("failed to read {BINARY_PATH}: {error}"))?;
+
+    let results = asynca::block_in_place(async move {
+        let (sender, mut seen_identities) = asynca::channel::mpsc(8);
+        let identity = arpc::launch_service(IdentityServerImpl { seen_identities: sender })
+            .map_err(|error| format!("failed to launch identity service: {error}"))?;
+
+        let mut results = Vec::new();
+        // 2 children with their own identity, and 1 with none
+        for register_identity in [true, true, false] {
+            let endpoint = identity.endpoint().try_clone()
+                .map_err(|error| format!("failed to clone identity client: {error}"))?;
+
+            let child = Command::from_bytes(elf_data.clone())
+                .name("test-arpc-identity-child".to_owned())
+                .named_arg("register_identity".to_owned(), &register_identity)
+                .handle(IDENTITY_CLIENT_HANDLE, &Identity::from(endpoint))
+                .spawn()
+                .map_err(|error| format!("failed to spawn child: {error}"))?;
+
+            // the service runs on this thread, so the child's calls are handled before waiting for it to exit
+            let first = seen_identities.recv().await.ok_or("identity service stopped")?;
+            let second = seen_identities.recv().await.ok_or("identity service stopped")?;
+
+            let status = child.wait()
+                .map_err(|error| format!("failed to wait for child: {error}"))?;
+
+            results.push((first, second, status.code()));
+        }
+
+        Ok::<_, String>(results)
+    })?;
+
+    for (first, second, exit_code) in results.iter() {
+        test_assert_eq!(*exit_code, Some(0), "child saw a different identity than the service");
+        test_assert_eq!(first, second, "identity changed between calls");
+    }
+
+    test_assert!(results[0].0.is_some() && results[1].0.is_some());
+    test_assert!(results[0].0 != results[1].0, "children with different identity keys have the same identity");
+    test_assert_eq!(results[2].0, None);
+
+    Ok(())
+}
+
+/// Runs in a child, adding the same service to a mux twice should panic
+fn duplicate_mux_child() -> ! {
+    let _mux = ServiceMux::new()
+        .add(EchoServerImpl::default())
+        .add(EchoServerImpl::default());
+
+    process::exit_with_code(0);
+}
+
+fn services_share_endpoint_with_mux() -> TestResult {
+    let (reply, totals, statuses, unknown_service_result) = asynca::block_in_place(async move {
+        let mux = ServiceMux::new()
+            .add(EchoServerImpl::default())
+            .add(CounterServerImpl::default());
+        let endpoint = arpc::launch_service(mux)
+            .map_err(|error| format!("failed to launch service mux: {error}"))?;
+
+        let echo = endpoint.try_client::<Echo>()
+            .map_err(|error| format!("failed to make echo client: {error}"))?;
+        let counter = endpoint.try_client::<Counter>()
+            .map_err(|error| format!("failed to make counter client: {error}"))?;
+
+        let reply = echo.echo("hello aurora".to_owned()).await;
+        let totals = (counter.add(2).await, counter.add(3).await);
+        let statuses = (echo.status().await, counter.status().await);
+
+        // no service in the mux has id 0
+        let unknown_service_result = endpoint.call::<_, ()>(RpcCall {
+            service_id: 0,
+            method_id: 0,
+            args: (),
+        }).await;
+
+        Ok::<_, String>((reply, totals, statuses, unknown_service_result))
+    })?;
+
+    test_assert_eq!(reply, "hello aurora");
+    test_assert_eq!(totals, (2, 5));
+    test_assert_eq!(statuses, ("echo".to_owned(), "counter".to_owned()));
+    test_assert!(
+        matches!(unknown_service_result, Err(RpcError::InvalidServiceId)),
+        "call to unknown service gave {unknown_service_result:?}",
+    );
+
+    Ok(())
+}
+
+fn duplicate_service_in_mux_panics() -> TestResult {
+    let elf_data = asynca::block_in_place(fs::read(BINARY_PATH))
+        .map_err(|error| format!("failed to read {BINARY_PATH}: {error}"))?;
+
+    let status = Command::from_bytes(elf_data)
+        .name("test-arpc-duplicate-mux-child".to_owned())
+        .named_arg("duplicate_mux".to_owned(), &true)
+        .spawn()
+        .map_err(|error| format!("failed to spawn child: {error}"))?
+        .wait()
+        .map_err(|error| format!("failed to wait for child: {error}"))?;
+
+    test_assert_eq!(status.code(), Some(process::PANIC_EXIT_CODE), "adding the same service twice did not panic");
+
+    Ok(())
+}
+
+/// Runs in a child, sends a call to the caller exit service and exits without waiting for the response
+fn abandoned_call_child(caller_exit: CallerExit) -> ! {
+    asynca::block_in_place(async move {
+        let mut call = pin!(caller_exit.wait_for_caller_exit());
+        // the call is sent the first time it is polled
+        let _ = poll_fn(|cx| Poll::Ready(call.as_mut().poll(cx))).await;
+    });
+
+    process::exit_with_code(0);
+}
+
+fn oneway_methods_are_sent_without_reply() -> TestResult {
+    let (notification_count, metrics) = asynca::block_in_place(async move {
+        let (notifier, handle) = arpc::launch_service_with_handle(NotifierServerImpl::default())
+            .map_err(|error| format!("failed to launch notifier service: {error}"))?;
+
+        for i in 0..NOTIFICATION_COUNT {
+            notifier.notify().await
+                .map_err(|error| format!("failed to send notification {i}: {error}"))?;
+        }
+
+        // messages are handled in order, so every notification has been counted by the time this is handled
+        let notification_count = notifier.notification_count().await;
+
+        Ok::<_, String>((notification_count, handle.metrics()))
+    })?;
+
+    test_assert_eq!(notification_count, NOTIFICATION_COUNT as u64);
+    // reading the count is the only message which had a reply capability
+    test_assert_eq!(metrics.sent_messages, NOTIFICATION_COUNT);
+    test_assert_eq!(metrics.calls, 1);
+
+    Ok(())
+}
+
+fn counter_over_loopback() -> TestResult {
+    let (totals, cap_result, stopped) = asynca::block_in_place(async move {
+        let (client_endpoint, server_endpoint) = arpc::testing::loopback_endpoints();
+        let (service_future, handle) = arpc::run_rpc_service_with_handle(server_endpoint, CounterServerImpl::default());
+        let service_task = asynca::spawn(service_future);
+
+        let counter = Counter::from(client_endpoint);
+        let totals = (counter.add(2).await, counter.add(3).await);
+
+        // capabilities can't be sent without the kernel, so this fails before anything is sent
+        let cap_result = counter.endpoint().call::<_, u64>(RpcCall {
+            service_id: Counter::SERVICE_INFO.service_id,
+            method_id: 0,
+            args: new_channel()?,
+        }).await;
+
+        // like a service on a kernel channel, it stops once every client is dropped
+        drop(counter);
+        let _ = service_task.await;
+
+        Ok::<_, String>((totals, cap_result, handle.is_stopped()))
+    })?;
+
+    test_assert_eq!(totals, (2, 5));
+    test_assert!(
+        matches!(cap_result, Err(RpcError::LoopbackCapability)),
+        "call with a capability over loopback gave {cap_result:?}",
+    );
+    test_assert!(stopped, "loopback service did not stop after its client was dropped");
+
+    Ok(())
+}
+
+/// Arguments of methods which take 1 u64 like [`CounterServer::add`], serialized the same way as their generated argument structs
+#[derive(Serialize)]
+struct U64Args(u64);
+
+fn calls_reuse_arena_space() -> TestResult {
+    let (wrong_totals, first_call_mark, high_water_mark) = asynca::block_in_place(async move {
+        let counter = arpc::launch_service(CounterServerImpl::default())
+            .map_err(|error| format!("failed to launch counter service: {error}"))?;
+        let mut arena = MessageArena::new(Size::from_pages(1))
+            .map_err(|error| format!("failed to make message arena: {error}"))?;
+
+        let mut wrong_totals = 0;
+        let mut first_call_mark = None;
+        for call_index in 1..=ARENA_CALL_COUNT {
+            let total = counter.endpoint().call_in::<_, u64>(RpcCall {
+                service_id: Counter::SERVICE_INFO.service_id,
+                method_id: 0,
+                args: U64Args(1),
+            }, &arena).await
+                .map_err(|error| format!("call {call_index} failed: {error}"))?;
+
+            if total != call_index {
+                wrong_totals += 1;
+            }
+
+            first_call_mark.get_or_insert(arena.high_water_mark());
+            arena.reset();
+        }
+
+        Ok::<_, String>((wrong_totals, first_call_mark, arena.high_water_mark()))
+    })?;
+
+    test_assert_eq!(wrong_totals, 0);
+    // every call reused the space of the first one, instead of making a new allocation
+    test_assert_eq!(Some(high_water_mark), first_call_mark);
+
+    Ok(())
+}
+
+fn responding_to_exited_caller() -> TestResult {
+    let elf_data = asynca::block_in_place(fs::read(BINARY_PATH))
+        .map_err(|error| format!("failed to read {BINARY_PATH}: {error}"))?;
+
+    let (metrics, stopped) = asynca::block_in_place(async move {
+        let (sender, mut handled) = asynca::channel::mpsc(1);
+        let child_slot = Rc::new(RefCell::new(None));
+        let (caller_exit, handle) = arpc::launch_service_with_handle(CallerExitServerImpl {
+            child: child_slot.clone(),
+            handled: sender,
+        }).map_err(|error| format!("failed to launch caller exit service: {error}"))?;
+
+        let endpoint = caller_exit.endpoint().try_clone()
+            .map_err(|error| format!("failed to clone caller exit client: {error}"))?;
+
+        let child = Command::from_bytes(elf_data)
+            .name("test-arpc-abandoned-call-child".to_owned())
+            .named_arg("abandon_call".to_owned(), &true)
+            .handle(ABANDONED_CALL_HANDLE, &CallerExit::from(endpoint))
+            .spawn()
+            .map_err(|error| format!("failed to spawn child: {error}"))?;
+        *child_slot.borrow_mut() = Some(child);
+
+        handled.recv().await.ok_or("caller exit service stopped")?;
+
+        // a method returning nothing still has a response, which the service must be able to send
+        caller_exit.ping().await;
+
+        Ok::<_, String>((handle.metrics(), handle.is_stopped()))
+    })?;
+
+    test_assert!(!stopped, "service stopped after its caller exited");
+    // the response only fails once the kernel has freed the child's event pool, which may not have happened yet
+    test_assert!(metrics.respond_failures <= 1, "{} responses failed", metrics.respond_failures);
+
+    Ok(())
+}
+
+fn aborted_handler_responds_with_error() -> TestResult {
+    let (result, stopped) = asynca::block_in_place(async move {
+        // the gate is never opened, so the handler waits until it is aborted
+        let (_gate_sender, gate) = asynca::channel::mpsc(1);
+        let handler_task = Rc::new(RefCell::new(None));
+        let (sender, mut started) = asynca::channel::mpsc(1);
+        let (endpoint, handle) = arpc::launch_service_with_handle(GatedServiceImpl {
+            gate: RefCell::new(Some(gate)),
+            handler_task: handler_task.clone(),
+            started: sender,
+        }).map_err(|error| format!("failed to launch gated service: {error}"))?;
+
+        // the service stops once every client is dropped, so the calling task gets its own clone
+        let caller = endpoint.try_clone()
+            .map_err(|error| format!("failed to clone gated client: {error}"))?;
+        let call = asynca::spawn(async move { call_gated(&caller, WAIT_FOR_GATE_METHOD_ID).await });
+
+        // the handler is aborted while it is waiting, which drops the reply guard it holds
+        started.recv().await.ok_or("gated service stopped")?;
+        handler_task.borrow_mut().take()
+            .ok_or("gated service did not spawn a handler task")?
+            .abort();
+
+        let result = call.await.map_err(|_| "calling task was aborted".to_owned())?;
+
+        Ok::<_, String>((result, handle.is_stopped()))
+    })?;
+
+    test_assert!(matches!(result, Err(RpcError::ServiceError)), "call to aborted handler gave {result:?}");
+    test_assert!(!stopped, "service stopped after a handler was aborted");
+
+    Ok(())
+}
+
+fn shutdown_lets_running_calls_finish() -> TestResult {
+    let (slow_result, rejected_result, stopped_before_gate, stopped) = asynca::block_in_place(async move {
+        let (gate_sender, gate) = asynca::channel::mpsc(1);
+        let (sender, mut started) = asynca::channel::mpsc(1);
+        let (endpoint, handle) = arpc::launch_service_with_handle(GatedServiceImpl {
+            gate: RefCell::new(Some(gate)),
+            handler_task: Rc::default(),
+            started: sender,
+        }).map_err(|error| format!("failed to launch gated service: {error}"))?;
+
+        let caller = endpoint.try_clone()
+            .map_err(|error| format!("failed to clone gated client: {error}"))?;
+        let slow_call = asynca::spawn(async move { call_gated(&caller, WAIT_FOR_GATE_METHOD_ID).await });
+        started.recv().await.ok_or("gated service stopped")?;
+
+        // the service stops taking calls straight away, but keeps running until the slow call is handled
+        let shutdown = handle.shutdown();
+        let rejected_result = call_gated(&endpoint, PING_METHOD_ID).await;
+        let stopped_before_gate = handle.is_stopped();
+
+        gate_sender.send(()).await.map_err(|_| "gate was dropped".to_owned())?;
+        let slow_result = slow_call.await.map_err(|_| "calling task was aborted".to_owned())?;
+        shutdown.await;
+
+        Ok::<_, String>((slow_result, rejected_result, stopped_before_gate, handle.is_stopped()))
+    })?;
+
+    test_assert!(
+        matches!(rejected_result, Err(RpcError::ServiceShuttingDown)),
+        "call during shutdown gave {rejected_result:?}",
+    );
+    test_assert!(!stopped_before_gate, "service stopped before its running call finished");
+    test_assert!(slow_result.is_ok(), "running call gave {slow_result:?} after shutdown");
+    test_assert!(stopped, "service did not stop once its running call finished");
+
+    Ok(())
+}
+
+fn newer_client_detects_older_service() -> TestResult {
+    let (info, compatible, total, reset_result) = asynca::block_in_place(async move {
+        let old_counter = arpc::launch_service(CounterV1ServerImpl::default())
+            .map_err(|error| format!("failed to launch counter v1 service: {error}"))?;
+        let counter = old_counter.endpoint().try_client::<CounterV2>()
+            .map_err(|error| format!("failed to make counter v2 client: {error}"))?;
+
+        let info = counter.service_info().await;
+        let compatible = counter.check_compatible().await;
+        // methods both versions have still work
+        let total = counter.add(2).await;
+
+        // calling reset through the client would panic, so the error is read from the endpoint
+        let reset_result = counter.endpoint().call::<_, ()>(RpcCall {
+            service_id: CounterV2::SERVICE_INFO.service_id,
+            method_id: 1,
+            args: (),
+        }).await;
+
+        Ok::<_, String>((info, compatible, total, reset_result))
+    })?;
+
+    test_assert_eq!(info.ok(), Some(CounterV1::SERVICE_INFO));
+    test_assert_eq!(compatible.ok(), Some(false));
+    test_assert_eq!(total, 2);
+    test_assert!(
+        matches!(reset_result, Err(RpcError::InvalidMethodId { version: 1, method_count: 1 })),
+        "call of method the service does not have gave {reset_result:?}",
+    );
+
+    Ok(())
+}
+
+fn older_client_works_with_newer_service() -> TestResult {
+    let (compatible, total) = asynca::block_in_place(async move {
+        let new_counter = arpc::launch_service(CounterV2ServerImpl::default())
+            .map_err(|error| format!("failed to launch counter v2 service: {error}"))?;
+        let counter = new_counter.endpoint().try_client::<CounterV1>()
+            .map_err(|error| format!("failed to make counter v1 client: {error}"))?;
+
+        Ok::<_, String>((counter.check_compatible().await, counter.add(3).await))
+    })?;
+
+    test_assert_eq!(compatible.ok(), Some(true));
+    test_assert_eq!(total, 3);
+
+    Ok(())
+}
+
+fn skipped_methods_run_locally() -> TestResult {
+    // methods which are not called over rpc are still on the server
+    let server = MixedServerImpl::default();
+    server.set(4);
+    test_assert_eq!(server.doubled(), 8);
+    server.reset();
+    test_assert_eq!(server.get(), 0);
+
+    test_assert_eq!(Mixed::SERVICE_INFO.method_count, 4, "skipped methods were given method ids");
+
+    let (description, total) = asynca::block_in_place(async move {
+        let mixed = arpc::launch_service(MixedServerImpl::default())
+            .map_err(|error| format!("failed to launch mixed service: {error}"))?;
+
+        mixed.set(5).await;
+        Ok::<_, String>((mixed.describe().await, mixed.increment(2).await))
+    })?;
+
+    test_assert_eq!(description, "value is 5");
+    test_assert_eq!(total, 7);
+
+    Ok(())
+}
+
+fn methods_get_pinned_and_sequential_ids() -> TestResult {
+    let (set_result, increment_result, unknown_result) = asynca::block_in_place(async move {
+        let mixed = arpc::launch_service(MixedServerImpl::default())
+            .map_err(|error| format!("failed to launch mixed service: {error}"))?;
+        let service_id = Mixed::SERVICE_INFO.service_id;
+
+        let set_result = mixed.endpoint().call::<_, ()>(RpcCall {
+            service_id,
+            method_id: 1,
+            args: U64Args(10),
+        }).await;
+        let increment_result = mixed.endpoint().call::<_, u64>(RpcCall {
+            service_id,
+            method_id: 3,
+            args: U64Args(1),
+        }).await;
+        let unknown_result = mixed.endpoint().call::<_, u64>(RpcCall {
+            service_id,
+            method_id: 4,
+            args: U64Args(1),
+        }).await;
+
+        Ok::<_, String>((set_result, increment_result, unknown_result))
+    })?;
+
+    test_assert!(set_result.is_ok(), "call of pinned method id gave {set_result:?}");
+    test_assert_eq!(increment_result.ok(), Some(11));
+    test_assert!(
+        matches!(unknown_result, Err(RpcError::InvalidMethodId { version: 0, method_count: 4 })),
+        "call of method id past the last method gave {unknown_result:?}",
+    );
+
+    Ok(())
+}
+
+fn new_channel() -> Result<Channel, String> {
+    Channel::new(CapFlags::all(), &this_context().allocator)
+        .map_err(|error| format!("failed to create channel: {error}"))
+}
+
+/// Returns true if `cap_id` still refers to a capability in this process
+fn cap_exists(cap_id: CapId) -> bool {
+    match cap_clone_inner(CspaceTarget::Current, CspaceTarget::Current, cap_id, CapFlags::all(), CapabilityWeakness::Current, false) {
+        Ok(clone_id) => {
+            let _ = cap_destroy(CspaceTarget::Current, clone_id);
+            true
+        },
+        Err(_) => false,
+    }
+}
+
+fn cap_count() -> Result<usize, String> {
+    this_context().capability_space.iter_caps()
+        .map(|caps| caps.len())
+        .map_err(|error| format!("failed to list capabilities: {error}"))
+}
+
+fn cap_wrappers_transfer_over_channel() -> TestResult {
+    let channel = new_channel()?;
+    let moved = new_channel()?;
+    let moved_id = moved.cap_id();
+
+    let (shared_flags, taken_flags) = asynca::block_in_place(async {
+        let receiver = arpc::launch_service(CapReceiverServerImpl::default())
+            .map_err(|error| format!("failed to launch capability reciever service: {error}"))?;
+
+        let shared = CapShare::new(&channel, CapFlags::READ)
+            .map_err(|error| format!("failed to share channel: {error}"))?;
+        let shared_flags = receiver.shared_flags(shared).await;
+        let taken_flags = receiver.take(CapMove::new(moved)).await;
+
+        Ok::<_, String>((shared_flags, taken_flags))
+    })?;
+
+    test_assert_eq!(shared_flags, CapFlags::READ.bits());
+    // the caller keeps its own copy with every permission
+    test_assert_eq!(channel.cap_id().flags(), CapFlags::all());
+    test_assert!(cap_clone(CspaceTarget::Current, CspaceTarget::Current, &channel, CapFlags::all()).is_ok());
+
+    test_assert_eq!(taken_flags, CapFlags::all().bits());
+    test_assert!(!cap_exists(moved_id), "moved channel still exists after it was sent");
+
+    Ok(())
+}
+
+fn failed_transfer_keeps_moved_caps() -> TestResult {
+    let moved = new_channel()?;
+    let moved_id = moved.cap_id();
+    let destroyed_id = new_channel()?.cap_id();
+
+    // the channel after the moved one can't be cloned, so the whole transfer fails
+    let message = (CapMove::new(moved), Channel::from_cap_id(destroyed_id).unwrap());
+    let (mut data, cap_policies) = arpc::aser::to_bytes_with_policies::<_, Vec<u8>>(&message)
+        .map_err(|error| format!("failed to serialize message: {error}"))?;
+
+    let caps_before = cap_count()?;
+    let result = arpc::aser::clone_caps_to_cspace_with_policies(CspaceTarget::Current, &mut data, &cap_policies);
+
+    test_assert!(matches!(result, Err(arpc::aser::AserCloneCapsError::SysErr(SysErr::InvlId))), "transfer gave {result:?}");
+    // the clone of the moved channel was destroyed, and the moved channel was not
+    test_assert_eq!(cap_count()?, caps_before);
+    test_assert!(cap_exists(moved_id), "moved channel was destroyed by a failed transfer");
+
+    let (moved, _) = message;
+    let message = (moved,);
+    let (mut data, cap_policies) = arpc::aser::to_bytes_with_policies::<_, Vec<u8>>(&message)
+        .map_err(|error| format!("failed to serialize message: {error}"))?;
+
+    arpc::aser::clone_caps_to_cspace_with_policies(CspaceTarget::Current, &mut data, &cap_policies)
+        .map_err(|error| format!("failed to transfer capabilities: {error}"))?;
+    let (transferred,) = arpc::aser::from_bytes::<(Channel,)>(&data)
+        .map_err(|error| format!("failed to deserialize message: {error}"))?;
+
+    test_assert!(!cap_exists(moved_id), "moved channel still exists after a successful transfer");
+    test_assert!(cap_exists(transferred.cap_id()));
+
+    Ok(())
+}
+
+fn echo_round_trip() -> TestResult {
+    let (reply, echo_count) = asynca::block_in_place(async move {
+        // the service stops once this client is dropped at the end of the block
+        let echo = arpc::launch_service(EchoServerImpl::default())
+            .map_err(|error| format!("failed to launch echo service: {error}"))?;
+
+        let reply = echo.echo("hello aurora".to_owned()).await;
+        let echo_count = echo.echo_count().await;
+
+        Ok::<_, String>((reply, echo_count))
+    })?;
+
+    test_assert_eq!(reply, "hello aurora");
+    test_assert_eq!(echo_count, 1);
+
+    Ok(())
+}
+
+fn echo_large_message() -> TestResult {
+    // bigger than a page, so the message does not fit in a single event
+    let message: String = (0..0x2000).map(|i| char::from(b'a' + (i % 26) as u8)).collect();
+
+    let expected = message.clone();
+    let reply = asynca::block_in_place(async move {
+        let echo = arpc::launch_service(EchoServerImpl::default())
+            .map_err(|error| format!("failed to launch echo service: {error}"))?;
+
+        Ok::<_, String>(echo.echo(message).await)
+    })?;
+
+    test_assert_eq!(reply.len(), expected.len());
+    test_assert_eq!(reply, expected, "echoed message was changed");
+
+    Ok(())
+}
+
+fn concurrent_calls_from_clones() -> TestResult {
+    let (mismatches, echo_count) = asynca::block_in_place(async move {
+        let echo = arpc::launch_service(EchoServerImpl::default())
+            .map_err(|error| format!("failed to launch echo service: {error}"))?;
+        let counter = echo.try_clone()
+            .map_err(|error| format!("failed to clone echo client: {error}"))?;
+
+        let mut tasks = Vec::new();
+        for client_index in 0..CONCURRENT_CLIENT_COUNT {
+            let client = echo.try_clone()
+                .map_err(|error| format!("failed to clone echo client: {error}"))?;
+
+            // each task waits for its own calls one at a time, but the tasks run together so their calls are interleaved
+            tasks.push(asynca::spawn(async move {
+                let mut mismatches = Vec::new();
+
+                for call_index in (client_index..CONCURRENT_CALL_COUNT).step_by(CONCURRENT_CLIENT_COUNT) {
+                    let message = format!("call {call_index} from client {client_index}");
+                    let reply = client.echo(message.clone()).await;
+
+                    if reply != message {
+                        mismatches.push(format!("sent `{message}` but got `{reply}`"));
+                    }
+                }
+
+                mismatches
+            }));
+        }
+
+        // the service keeps running until every clone is dropped, not just the client it was launched with
+        drop(echo);
+
+        let mut mismatches = Vec::new();
+        for task in tasks {
+            mismatches.extend(task.await.map_err(|_| "echo client task was aborted".to_owned())?);
+        }
+
+        let echo_count = counter.echo_count().await;
+
+        Ok::<_, String>((mismatches, echo_count))
+    })?;
+
+    test_assert!(mismatches.is_empty(), "responses did not match requests: {}", mismatches.join(", "));
+    test_assert_eq!(echo_count, CONCURRENT_CALL_COUNT as u64);
+
+    Ok(())
+}
+
+fn replay_echo_session() -> TestResult {
+    let recording = Rc::new(RefCell::new(Vec::new()));
+
+    let (reply, echo_count) = asynca::block_in_place({
+        let recording = recording.clone();
+
+        async move {
+            let (client_endpoint, server_endpoint) = arpc::make_endpoints()
+                .map_err(|error| format!("failed to make endpoints: {error}"))?;
+            asynca::spawn(arpc::run_rpc_service(server_endpoint.with_recorder(recording), EchoServerImpl::default()));
+
+            let echo = Echo::from(client_endpoint);
+            let reply = echo.echo("hello aurora".to_owned()).await;
+            let echo_count = echo.echo_count().await;
+
+            Ok::<_, String>((reply, echo_count))
+        }
+    })?;
+
+    test_assert_eq!(reply, "hello aurora");
+    test_assert_eq!(echo_count, 1);
+
+    let recording = recording.borrow().clone();
+    let (same_result, reversed_result) = asynca::block_in_place(async move {
+        let same_result = replay::drive_service(&EchoServerImpl::default(), &recording).await;
+        let reversed_result = replay::drive_service(&ReversedEchoServerImpl::default(), &recording).await;
+
+        (same_result, reversed_result)
+    });
+
+    test_assert_eq!(same_result, Ok(2), "replaying against the recorded service diverged");
+
+    let Err(ReplayError::Divergence { call_index, method_id, .. }) = reversed_result else {
+        return Err(format!("replaying against the reversed echo service gave {reversed_result:?}"));
+    };
+    // echo_count still matches, so the echo call is the only one which diverges
+    test_assert_eq!(call_index, 0);
+    test_assert_eq!(method_id, Some(0));
+
+    Ok(())
+}
+
+aurora_test::tests! {
+    echo_round_trip,
+    echo_large_message,
+    concurrent_calls_from_clones,
+    replay_echo_session,
+    services_share_endpoint_with_mux,
+    cap_wrappers_transfer_over_channel,
+    failed_transfer_keeps_moved_caps,
+    caller_identity_is_attached,
+    duplicate_service_in_mux_panics,
+    responding_to_exited_caller,
+    oneway_methods_are_sent_without_reply,
+    counter_over_loopback,
+    calls_reuse_arena_space,
+    aborted_handler_responds_with_error,
+    shutdown_lets_running_calls_finish,
+    newer_client_detects_older_service,
+    older_client_works_with_newer_service,
+    skipped_methods_run_locally,
+    methods_get_pinned_and_sequential_ids,
+}
+
+fn main() {
+    if env::args().named_arg::<bool>("duplicate_mux").is_ok() {
+        duplicate_mux_child();
+    }
+
+    if env::args().named_arg::<bool>("abandon_call").is_ok() {
+        let caller_exit = env::handle::<CallerExit>(ABANDONED_CALL_HANDLE)
+            .expect("abandoned call child has no caller exit client");
+        abandoned_call_child(caller_exit);
+    }
+
+    if let Ok(identity) = env::handle::<Identity>(IDENTITY_CLIENT_HANDLE) {
+        identity_child(identity);
+    }
+
+    aurora_test::run_tests(TESTS);
+}
\ No newline at end of file
@@ -0,0 +1,475 @@
+//! Tests the async executor's event id allocation, by registering and dropping many channel receives,
+//! that replies to concurrent channel calls are given to the call they are for,
+//! joining, aborting, and detaching tasks, and the executor's metrics, including that queued events need few event pool waits
+//!
+//! When started with the `panic_in_task` named argument, a task panics instead, which should end the whole process.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use alloc::format;
+use alloc::rc::Rc;
+use core::cell::Cell;
+use core::future::{Future, poll_fn};
+use core::pin::Pin;
+use core::task::Poll;
+use core::time::Duration;
+
+use asynca::{EXECUTOR, Aborted, TaskName};
+use asynca::async_sys::AsyncChannel;
+use aurora::collections::MessageVec;
+use aurora::{env, fs, this_context, thread};
+use aurora::process::{Command, PANIC_EXIT_CODE};
+use aurora::time::{Instant, sleep};
+use aurora_test::{TestResult, test_assert, test_assert_eq};
+use std::prelude::*;
+use sys::{CapFlags, Channel, ChannelSendFlags, CspaceTarget, SysErr, cap_clone};
+
+/// Number of channels which are recieved on at once
+const CHANNEL_COUNT: usize = 32;
+/// Total number of oneshot receives registered during the soak test
+const RECEIVE_COUNT: usize = 1_000_000;
+/// A receive is dropped before its message is sent once every this many rounds
+const DROP_INTERVAL: usize = 64;
+
+/// Total number of calls made on 1 channel by the interleaved call test
+const INTERLEAVED_CALL_COUNT: usize = 1000;
+/// Number of tasks making calls at once, each task waits for its own calls one at a time
+const CALLING_TASK_COUNT: usize = 16;
+/// Longest time the server waits before replying to a call, in microseconds
+const MAX_REPLY_DELAY_MICROS: u64 = 200;
+
+/// Number of messages which are queued in the event pool before the batching test waits for any of them
+const BATCHED_MESSAGE_COUNT: usize = 10_000;
+
+/// Number of tasks spawned by the metrics test, each is polled twice
+const METRICS_TASK_COUNT: usize = 50;
+/// How long the named task in the metrics test spends in 1 poll, much longer than any other poll in the test
+const SLOW_POLL_TIME: Duration = Duration::from_millis(20);
+const SLOW_TASK_NAME: &str = "slow-task";
+
+/// Where the fs server puts this binary from the initrd
+const BINARY_PATH: &str = "/initrd/test-async";
+
+/// Polls `future` once, registering the current task's waker if it is not ready
+async fn poll_once<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+    poll_fn(|cx| Poll::Ready(Pin::new(&mut *future).poll(cx))).await
+}
+
+/// Message sent to channel `index` in `round`, so a receive which gets another channel's message is noticed
+fn message_data(round: usize, index: usize) -> [u8; 16] {
+    let mut data = [0; 16];
+    data[..8].copy_from_slice(&(round as u64).to_le_bytes());
+    data[8..].copy_from_slice(&(index as u64).to_le_bytes());
+    data
+}
+
+fn send(channel: &AsyncChannel, message: &mut MessageVec<u8>, round: usize, index: usize) -> TestResult {
+    message.clear();
+    message.extend_from_slice(&message_data(round, index));
+
+    channel.try_send(&message.message_buffer().unwrap(), ChannelSendFlags::empty())
+        .map_err(|error| format!("failed to send to channel {index} in round {round}: {error}"))?;
+
+    Ok(())
+}
+
+async fn receive_soak() -> TestResult {
+    let channels = (0..CHANNEL_COUNT)
+        .map(|_| Channel::new(CapFlags::all(), &this_context().allocator).map(AsyncChannel::from))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| format!("failed to create channel: {error}"))?;
+    let mut messages = (0..CHANNEL_COUNT)
+        .map(|_| MessageVec::with_capacity(16))
+        .collect::<Vec<_>>();
+
+    let round_count = RECEIVE_COUNT / CHANNEL_COUNT;
+    let mut dropped_count = 0;
+
+    for round in 0..round_count {
+        // the last round is never a drop round, so the stale events have all arrived by the end
+        let dropped_channel = (round % DROP_INTERVAL == DROP_INTERVAL - 1 && round + 1 < round_count)
+            .then_some(round % CHANNEL_COUNT);
+
+        // the kernel still delivers the message to the dropped receive's event id, which the executor should ignore
+        if let Some(index) = dropped_channel {
+            let mut recv = channels[index].recv();
+            test_assert!(poll_once(&mut recv).await.is_pending());
+            drop(recv);
+
+            send(&channels[index], &mut messages[index], round, index)?;
+            dropped_count += 1;
+        }
+
+        let mut receives = channels.iter()
+            .enumerate()
+            .filter(|(index, _)| Some(*index) != dropped_channel)
+            .map(|(index, channel)| (index, channel.recv()))
+            .collect::<Vec<_>>();
+
+        for (index, recv) in receives.iter_mut() {
+            test_assert!(poll_once(recv).await.is_pending(), "channel {index} recieved before anything was sent");
+        }
+
+        for (index, _) in receives.iter() {
+            send(&channels[*index], &mut messages[*index], round, *index)?;
+        }
+
+        for (index, recv) in receives {
+            let event = recv.await
+                .map_err(|error| format!("failed to recieve on channel {index} in round {round}: {error}"))?;
+
+            // safety: the data is read before the executor waits for more events
+            let data = unsafe { event.as_slice() };
+            test_assert_eq!(data, &message_data(round, index)[..]);
+        }
+    }
+
+    let metrics = EXECUTOR.with(|executor| executor.metrics());
+    test_assert_eq!(metrics.stale_events, dropped_count);
+    test_assert!(
+        metrics.event_id_slots <= CHANNEL_COUNT + 1,
+        "{} event id slots used for {CHANNEL_COUNT} concurrent receives",
+        metrics.event_id_slots,
+    );
+
+    Ok(())
+}
+
+fn oneshot_receive_ids_are_reused() -> TestResult {
+    asynca::block_in_place(receive_soak())
+}
+
+async fn receive_batched() -> TestResult {
+    let channel = Channel::new(CapFlags::all(), &this_context().allocator)
+        .map(AsyncChannel::from)
+        .map_err(|error| format!("failed to create channel: {error}"))?;
+    let mut message = MessageVec::with_capacity(16);
+
+    let mut receives = (0..BATCHED_MESSAGE_COUNT)
+        .map(|_| channel.recv())
+        .collect::<Vec<_>>();
+    for recv in receives.iter_mut() {
+        test_assert!(poll_once(recv).await.is_pending());
+    }
+
+    // every message is written to the event pool before the executor waits on it
+    for index in 0..BATCHED_MESSAGE_COUNT {
+        send(&channel, &mut message, 0, index)?;
+    }
+
+    let metrics_before = EXECUTOR.with(|executor| executor.metrics());
+
+    for (index, recv) in receives.into_iter().enumerate() {
+        let event = recv.await
+            .map_err(|error| format!("failed to recieve message {index}: {error}"))?;
+
+        // safety: the data is read before the executor waits for more events
+        let data = unsafe { event.as_slice() };
+        test_assert_eq!(data, &message_data(0, index)[..]);
+    }
+
+    let metrics = EXECUTOR.with(|executor| executor.metrics());
+    let event_waits = metrics.event_waits - metrics_before.event_waits;
+    test_assert_eq!(metrics.events_received - metrics_before.events_received, BATCHED_MESSAGE_COUNT as u64);
+    test_assert!(
+        event_waits < BATCHED_MESSAGE_COUNT as u64 / 100,
+        "{event_waits} event pool waits to recieve {BATCHED_MESSAGE_COUNT} queued messages",
+    );
+
+    Ok(())
+}
+
+fn queued_events_are_received_in_batches() -> TestResult {
+    asynca::block_in_place(receive_batched())
+}
+
+/// Xorshift generator used to pick reply orders and delays, it is seeded with a constant so failures can be reproduced
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Echoes back the sequence number in each of `call_count` calls on `channel`
+///
+/// The calls which have arrived are replied to in a random order after random delays,
+/// so replies come back in a different order than the calls were made.
+fn reply_out_of_order(channel: Channel, call_count: usize) -> Result<(), String> {
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let buffer = MessageVec::from_slice(&[0u8; 8]);
+    let mut pending = Vec::new();
+    let mut replied_count = 0;
+
+    while replied_count < call_count {
+        // wait for 1 call, then take every other call which has already arrived
+        let mut recieve_result = channel.sync_recv(&buffer.message_buffer().unwrap(), None);
+
+        loop {
+            let reply = match recieve_result {
+                Ok(recieve_result) => recieve_result.reply
+                    .ok_or_else(|| "recieved message which was not a call".to_owned())?,
+                Err(SysErr::OkUnreach) => break,
+                Err(error) => return Err(format!("failed to recieve call: {error}")),
+            };
+
+            // panic safety: the buffer is 8 bytes long
+            let sequence = u64::from_le_bytes(buffer.as_slice().try_into().unwrap());
+            pending.push((sequence, reply));
+
+            recieve_result = channel.try_recv(&buffer.message_buffer().unwrap());
+        }
+
+        while !pending.is_empty() {
+            let (sequence, reply) = pending.swap_remove(rng.next_u64() as usize % pending.len());
+            sleep(Duration::from_micros(rng.next_u64() % MAX_REPLY_DELAY_MICROS));
+
+            let message = MessageVec::from_slice(&sequence.to_le_bytes());
+            reply.reply(&message.message_buffer().unwrap())
+                .map_err(|error| format!("failed to reply to call {sequence}: {error}"))?;
+
+            replied_count += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes the calls with sequence numbers `first_sequence`, `first_sequence + CALLING_TASK_COUNT`, and so on,
+/// and returns a description of every reply which was for a different call
+async fn make_calls(channel: Rc<AsyncChannel>, first_sequence: usize) -> Result<Vec<String>, String> {
+    let mut mismatches = Vec::new();
+
+    for sequence in (first_sequence..INTERLEAVED_CALL_COUNT).step_by(CALLING_TASK_COUNT) {
+        let message = MessageVec::from_slice(&(sequence as u64).to_le_bytes());
+        let event = channel.call(message.message_buffer().unwrap(), ChannelSendFlags::empty()).await
+            .map_err(|error| format!("call {sequence} failed: {error}"))?;
+
+        // safety: the data is read before the executor waits for more events
+        let data = unsafe { event.as_slice() };
+        if data != (sequence as u64).to_le_bytes() {
+            mismatches.push(format!("call {sequence} got reply {data:?}"));
+        }
+    }
+
+    Ok(mismatches)
+}
+
+fn interleaved_calls_get_their_own_replies() -> TestResult {
+    let channel = Channel::new(CapFlags::all(), &this_context().allocator)
+        .map_err(|error| format!("failed to create channel: {error}"))?;
+    let server_channel = cap_clone(CspaceTarget::Current, CspaceTarget::Current, &channel, CapFlags::all())
+        .map_err(|error| format!("failed to clone channel: {error}"))?;
+
+    let server = thread::spawn(move || reply_out_of_order(server_channel, INTERLEAVED_CALL_COUNT));
+
+    let mismatches = asynca::block_in_place(async move {
+        let channel = Rc::new(AsyncChannel::from(channel));
+
+        let tasks = (0..CALLING_TASK_COUNT)
+            .map(|first_sequence| asynca::spawn(make_calls(channel.clone(), first_sequence)))
+            .collect::<Vec<_>>();
+
+        let mut mismatches = Vec::new();
+        for task in tasks {
+            mismatches.extend(task.await.map_err(|_| "calling task was aborted".to_owned())??);
+        }
+
+        Ok::<_, String>(mismatches)
+    })?;
+
+    server.join()?;
+
+    test_assert!(mismatches.is_empty(), "replies did not match calls: {}", mismatches.join(", "));
+
+    Ok(())
+}
+
+/// Lets the executor run other tasks before this one continues
+async fn yield_now() {
+    let mut yielded = false;
+
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }).await
+}
+
+/// Sets a flag when it is dropped, used to check a task's future was dropped
+struct DropFlag(Rc<Cell<bool>>);
+
+impl Drop for DropFlag {
+    fn drop(&mut self) {
+        self.0.set(true);
+    }
+}
+
+fn join_handle_returns_output() -> TestResult {
+    asynca::block_in_place(async {
+        let mut handle = asynca::spawn(async {
+            yield_now().await;
+            40 + 2
+        });
+        test_assert!(!handle.is_finished());
+
+        test_assert_eq!((&mut handle).await, Ok(42));
+        test_assert!(handle.is_finished());
+
+        Ok(())
+    })
+}
+
+fn aborted_task_drops_future() -> TestResult {
+    asynca::block_in_place(async {
+        let channel = Channel::new(CapFlags::all(), &this_context().allocator)
+            .map(AsyncChannel::from)
+            .map_err(|error| format!("failed to create channel: {error}"))?;
+
+        let dropped = Rc::new(Cell::new(false));
+        let drop_flag = DropFlag(dropped.clone());
+
+        let mut handle = asynca::spawn(async move {
+            let _drop_flag = drop_flag;
+            // nothing is ever sent on the channel, so this waits forever
+            let _ = channel.recv().await;
+        });
+
+        yield_now().await;
+        test_assert!(!handle.is_finished());
+        test_assert!(!dropped.get());
+
+        handle.abort();
+        test_assert_eq!((&mut handle).await, Err(Aborted));
+        test_assert!(dropped.get(), "aborted task's future was not dropped");
+        test_assert!(handle.is_finished());
+
+        Ok(())
+    })
+}
+
+fn detached_task_keeps_running() -> TestResult {
+    let finished = Rc::new(Cell::new(false));
+    let task_finished = finished.clone();
+
+    asynca::block_in_place(async move {
+        asynca::spawn(async move {
+            yield_now().await;
+            task_finished.set(true);
+        }).detach();
+    });
+
+    // the executor runs until every task is done, including detached ones
+    test_assert!(finished.get(), "detached task did not finish");
+
+    Ok(())
+}
+
+fn executor_metrics_count_tasks_and_polls() -> TestResult {
+    // executors are per thread, so a new thread starts with fresh metrics
+    let (alive_while_running, metrics) = thread::spawn(|| {
+        let alive_while_running = asynca::block_in_place(async {
+            let tasks = (0..METRICS_TASK_COUNT)
+                .map(|_| asynca::spawn(yield_now()))
+                .collect::<Vec<_>>();
+            let alive_while_running = EXECUTOR.with(|executor| executor.metrics().alive_tasks);
+
+            for task in tasks {
+                let _ = task.await;
+            }
+
+            let _ = asynca::spawn_named(SLOW_TASK_NAME, async {
+                let start = Instant::now();
+                while start.elapsed() < SLOW_POLL_TIME {}
+            }).await;
+
+            alive_while_running
+        });
+
+        (alive_while_running, EXECUTOR.with(|executor| executor.metrics()))
+    }).join();
+
+    // the block_in_place task is also counted
+    test_assert_eq!(alive_while_running, METRICS_TASK_COUNT + 1);
+    test_assert_eq!(metrics.alive_tasks, 0);
+    test_assert_eq!(metrics.spawned_tasks, METRICS_TASK_COUNT as u64 + 2);
+    test_assert!(
+        metrics.polls >= 2 * METRICS_TASK_COUNT as u64 + 3,
+        "only {} polls were counted",
+        metrics.polls,
+    );
+
+    // every task could always make progress, so the executor never had to wait for events
+    test_assert_eq!(metrics.event_waits, 0);
+
+    test_assert_eq!(metrics.longest_poll_task, Some(TaskName::new(SLOW_TASK_NAME)));
+    test_assert!(metrics.longest_poll_ticks > 0);
+
+    Ok(())
+}
+
+fn dump_tasks_from_running_task() -> TestResult {
+    asynca::block_in_place(async {
+        let first = asynca::spawn_named("queued-task-1", yield_now());
+        let second = asynca::spawn_named("queued-task-2", yield_now());
+
+        // the calling task is being polled while its state is printed
+        EXECUTOR.with(|executor| executor.dump_tasks());
+
+        let _ = first.await;
+        let _ = second.await;
+    });
+
+    Ok(())
+}
+
+fn panicking_task_ends_process() -> TestResult {
+    let elf_data = asynca::block_in_place(fs::read(BINARY_PATH))
+        .map_err(|error| format!("failed to read {BINARY_PATH}: {error}"))?;
+
+    let child = Command::from_bytes(elf_data)
+        .name("test-async-child".to_owned())
+        .named_arg("panic_in_task".to_owned(), &true)
+        .spawn()
+        .map_err(|error| format!("failed to spawn child: {error}"))?;
+
+    let status = child.wait()
+        .map_err(|error| format!("failed to wait for child: {error}"))?;
+    test_assert_eq!(status.code(), Some(PANIC_EXIT_CODE));
+
+    Ok(())
+}
+
+aurora_test::tests! {
+    oneshot_receive_ids_are_reused,
+    queued_events_are_received_in_batches,
+    interleaved_calls_get_their_own_replies,
+    join_handle_returns_output,
+    aborted_task_drops_future,
+    detached_task_keeps_running,
+    executor_metrics_count_tasks_and_polls,
+    dump_tasks_from_running_task,
+    panicking_task_ends_process,
+}
+
+fn main() {
+    if env::args().named_arg::<bool>("panic_in_task").is_ok() {
+        // panics are not caught, so the panic exits the process instead of being returned through the join handle
+        let _ = asynca::block_in_place(asynca::spawn(async {
+            panic!("task panicked");
+        }));
+    }
+
+    aurora_test::run_tests(TESTS);
+}
\ No newline at end of file
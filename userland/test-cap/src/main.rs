@@ -0,0 +1,83 @@
+//! Tests weak capabilities, and upgrading them with [`Weak::upgrade`]
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use alloc::format;
+
+use aurora::this_context;
+use aurora_test::{TestResult, test_assert, test_assert_eq};
+use std::prelude::*;
+use sys::{Capability, Channel, CapFlags, CspaceTarget, SysErr, Weak, cap_clone};
+
+fn new_channel() -> Result<Channel, String> {
+    Channel::new(CapFlags::all(), &this_context().allocator)
+        .map_err(|error| format!("failed to create channel: {error}"))
+}
+
+fn downgrade(channel: &Channel) -> Result<Weak<Channel>, String> {
+    Weak::new(channel)
+        .map_err(|error| format!("failed to make weak channel: {error}"))
+}
+
+fn is_alive(weak: &Weak<Channel>) -> Result<bool, String> {
+    weak.is_alive()
+        .map_err(|error| format!("failed to check if channel is alive: {error}"))
+}
+
+fn upgrade_fails_after_strong_dropped() -> TestResult {
+    let channel = new_channel()?;
+    let weak = downgrade(&channel)?;
+    test_assert!(weak.inner().cap_id().is_weak());
+    test_assert!(is_alive(&weak)?);
+
+    drop(channel);
+
+    test_assert_eq!(weak.upgrade().err(), Some(SysErr::InvlWeak));
+    // the kernel has removed the weak capability by now, which should look the same
+    test_assert_eq!(weak.upgrade().err(), Some(SysErr::InvlWeak));
+    test_assert!(!is_alive(&weak)?);
+
+    Ok(())
+}
+
+fn upgrade_needs_upgrade_permission() -> TestResult {
+    let channel = new_channel()?;
+    let no_upgrade = cap_clone(CspaceTarget::Current, CspaceTarget::Current, &channel, CapFlags::READ | CapFlags::PROD)
+        .map_err(|error| format!("failed to clone channel: {error}"))?;
+    let weak = downgrade(&no_upgrade)?;
+
+    test_assert_eq!(weak.upgrade().err(), Some(SysErr::InvlPerm));
+
+    Ok(())
+}
+
+fn guard_keeps_object_alive() -> TestResult {
+    let channel = new_channel()?;
+    let weak = downgrade(&channel)?;
+
+    let guard = weak.upgrade()
+        .map_err(|error| format!("failed to upgrade channel: {error}"))?;
+    test_assert!(!guard.cap_id().is_weak());
+    test_assert_eq!(guard.cap_id().flags(), channel.cap_id().flags());
+
+    drop(channel);
+    test_assert!(is_alive(&weak)?, "channel dropped while a strong guard exists");
+
+    drop(guard);
+    test_assert!(!is_alive(&weak)?, "channel still alive after the strong guard was dropped");
+
+    Ok(())
+}
+
+aurora_test::tests! {
+    upgrade_fails_after_strong_dropped,
+    upgrade_needs_upgrade_permission,
+    guard_keeps_object_alive,
+}
+
+fn main() {
+    aurora_test::run_tests(TESTS);
+}
\ No newline at end of file
@@ -0,0 +1,253 @@
+//! Tests the path based functions and [`File`] in `aurora::fs` against the ramfs of the fs server
+//!
+//! The ramfs can't create directories, so every test uses its own file in the root directory.
+//! The disk test checks the first block of the boot disk which the fs server read when it started.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::vec;
+
+use asynca::sync::Mutex;
+use aurora::fs::{self, File, FsAsync, FsError, FileKind, OpenFlags, SeekFrom};
+use aurora::io::{AsyncRead, BufReader};
+use aurora_test::{TestResult, test_assert, test_assert_eq};
+use std::prelude::*;
+use sys::SysErr;
+
+/// Bigger than the most the fs client reads or writes in 1 message, so whole file reads and writes take several
+const LARGE_FILE_SIZE: usize = 0x10000 * 2 + 123;
+
+/// Writing here would make a file bigger than the fs server allows
+const FAR_PAST_MAX_FILE_SIZE: u64 = 1 << 32;
+
+/// Number of records each task writes in the shared file test
+const SHARED_FILE_RECORDS: usize = 50;
+/// Each record is written in 2 halves, which would be split up by the other task without the lock
+const RECORD_HALF_SIZE: usize = 4;
+
+fn read_write_flags() -> OpenFlags {
+    OpenFlags {
+        read: true,
+        write: true,
+        create: true,
+        truncate: true,
+    }
+}
+
+/// Returns a function which describes an fs error for a test failure
+fn describe(action: &'static str) -> impl FnOnce(FsError) -> String {
+    move |error| format!("failed to {action}: {error}")
+}
+
+fn write_then_read_round_trips() -> TestResult {
+    asynca::block_in_place(async {
+        const PATH: &str = "/test-fs-round-trip";
+
+        let data = (0..LARGE_FILE_SIZE).map(|i| i as u8).collect::<Vec<_>>();
+        fs::write(PATH, &data).await.map_err(describe("write file"))?;
+
+        let info = fs::metadata(PATH).await.map_err(describe("get metadata"))?;
+        test_assert_eq!(info.kind, FileKind::File);
+        test_assert_eq!(info.size, LARGE_FILE_SIZE as u64);
+
+        let read_data = fs::read(PATH).await.map_err(describe("read file"))?;
+        test_assert!(read_data == data, "read {} bytes which don't match what was written", read_data.len());
+
+        // writing again replaces the contents instead of overwriting the start
+        fs::write(PATH, b"short").await.map_err(describe("rewrite file"))?;
+        test_assert_eq!(fs::read(PATH).await.map_err(describe("read rewritten file"))?, b"short");
+
+        Ok(())
+    })
+}
+
+fn missing_file_is_not_found() -> TestResult {
+    asynca::block_in_place(async {
+        const PATH: &str = "/test-fs-missing";
+
+        test_assert_eq!(fs::read(PATH).await, Err(FsError::NotFound));
+        test_assert_eq!(fs::metadata(PATH).await, Err(FsError::NotFound));
+        test_assert!(File::open(PATH, OpenFlags { read: true, ..Default::default() }).await.is_err());
+
+        Ok(())
+    })
+}
+
+fn read_is_short_at_end_of_file() -> TestResult {
+    asynca::block_in_place(async {
+        let mut file = File::open("/test-fs-short-read", read_write_flags()).await.map_err(describe("open file"))?;
+        file.write_all(b"0123456789").await.map_err(describe("write file"))?;
+        file.seek(SeekFrom::Start(4)).await.map_err(describe("seek"))?;
+
+        let mut buffer = [0; 64];
+        let read_size = file.read(&mut buffer).await.map_err(describe("read"))?;
+        test_assert_eq!(&buffer[..read_size], b"456789");
+
+        // every read at the end of the file is empty
+        for _ in 0..2 {
+            test_assert_eq!(file.read(&mut buffer).await, Ok(0));
+        }
+
+        file.seek(SeekFrom::Start(8)).await.map_err(describe("seek"))?;
+        test_assert_eq!(file.async_read_exact(&mut buffer[..4]).await, Err(SysErr::PeerGone));
+
+        file.close().await;
+        Ok(())
+    })
+}
+
+fn seek_moves_position() -> TestResult {
+    asynca::block_in_place(async {
+        let mut file = File::open("/test-fs-seek", read_write_flags()).await.map_err(describe("open file"))?;
+        file.write_all(b"abcdef").await.map_err(describe("write file"))?;
+
+        test_assert_eq!(file.seek(SeekFrom::End(-2)).await, Ok(4));
+        let mut buffer = [0; 2];
+        file.async_read_exact(&mut buffer).await.map_err(|error| format!("failed to read: {error}"))?;
+        test_assert_eq!(&buffer, b"ef");
+
+        test_assert_eq!(file.seek(SeekFrom::Current(-5)).await, Ok(1));
+        test_assert_eq!(file.seek(SeekFrom::Current(-2)).await, Err(FsError::InvalidSeek));
+        // a failed seek leaves the position alone
+        test_assert_eq!(file.position(), 1);
+
+        // writing past the end fills the gap with zeros
+        test_assert_eq!(file.seek(SeekFrom::End(2)).await, Ok(8));
+        file.write_all(b"gh").await.map_err(describe("write past end"))?;
+        file.close().await;
+
+        test_assert_eq!(fs::read("/test-fs-seek").await.map_err(describe("read file"))?, b"abcdef\0\0gh");
+
+        Ok(())
+    })
+}
+
+fn buf_reader_reads_lines() -> TestResult {
+    asynca::block_in_place(async {
+        const PATH: &str = "/test-fs-lines";
+
+        fs::write(PATH, b"first line\nsecond\r\n\nlast without newline").await.map_err(describe("write file"))?;
+
+        let file = File::open(PATH, OpenFlags { read: true, ..Default::default() }).await.map_err(describe("open file"))?;
+        // smaller than the lines, so lines are put together from several reads
+        let mut lines = BufReader::with_capacity(4, file).lines();
+
+        for expected in ["first line", "second", "", "last without newline"] {
+            let line = lines.next_line().await.map_err(|error| format!("failed to read line: {error}"))?;
+            test_assert_eq!(line.as_deref(), Some(expected));
+        }
+        test_assert_eq!(lines.next_line().await, Ok(None));
+
+        Ok(())
+    })
+}
+
+fn buf_reader_keeps_newline() -> TestResult {
+    asynca::block_in_place(async {
+        const PATH: &str = "/test-fs-read-line";
+
+        fs::write(PATH, b"one\ntwo").await.map_err(describe("write file"))?;
+
+        let file = File::open(PATH, OpenFlags { read: true, ..Default::default() }).await.map_err(describe("open file"))?;
+        let mut reader = BufReader::new(file);
+
+        let mut line = String::new();
+        test_assert_eq!(reader.read_line(&mut line).await, Ok(4));
+        test_assert_eq!(reader.read_line(&mut line).await, Ok(3));
+        test_assert_eq!(reader.read_line(&mut line).await, Ok(0));
+        test_assert_eq!(line, "one\ntwo");
+
+        Ok(())
+    })
+}
+
+fn shared_file_with_lock() -> TestResult {
+    const PATH: &str = "/test-fs-shared";
+
+    let file = asynca::block_in_place(File::open(PATH, read_write_flags())).map_err(describe("open file"))?;
+    let file = Rc::new(Mutex::new(file));
+
+    // every write waits for the fs server, so the other task runs while a record is half written
+    let tasks = [b'a', b'b'].map(|tag| {
+        let file = file.clone();
+
+        asynca::spawn(async move {
+            for _ in 0..SHARED_FILE_RECORDS {
+                let mut file = file.lock().await;
+                file.write_all(&[tag; RECORD_HALF_SIZE]).await?;
+                file.write_all(&[tag; RECORD_HALF_SIZE]).await?;
+            }
+
+            Ok::<(), FsError>(())
+        })
+    });
+
+    for task in tasks {
+        asynca::block_in_place(task)
+            .map_err(|_| "writing task was aborted".to_owned())?
+            .map_err(describe("write record"))?;
+    }
+
+    let data = asynca::block_in_place(fs::read(PATH)).map_err(describe("read file"))?;
+    test_assert_eq!(data.len(), 2 * SHARED_FILE_RECORDS * 2 * RECORD_HALF_SIZE);
+
+    let mut record_counts = [0; 2];
+    for record in data.chunks(2 * RECORD_HALF_SIZE) {
+        test_assert!(record.iter().all(|byte| *byte == record[0]), "record {:?} was torn", record);
+        record_counts[(record[0] - b'a') as usize] += 1;
+    }
+    test_assert_eq!(record_counts, [SHARED_FILE_RECORDS; 2]);
+
+    Ok(())
+}
+
+fn write_past_max_file_size_fails() -> TestResult {
+    asynca::block_in_place(async {
+        const PATH: &str = "/test-fs-too-large";
+
+        let mut file = File::open(PATH, read_write_flags()).await.map_err(describe("open file"))?;
+        file.seek(SeekFrom::Start(FAR_PAST_MAX_FILE_SIZE)).await.map_err(describe("seek"))?;
+
+        test_assert_eq!(file.write_all(b"data").await, Err(FsError::FileTooLarge));
+
+        // the file must not have been grown by the rejected write
+        let info = file.metadata().await.map_err(describe("get metadata"))?;
+        test_assert_eq!(info.size, 0);
+
+        Ok(())
+    })
+}
+
+fn boot_disk_has_boot_signature() -> TestResult {
+    asynca::block_in_place(async {
+        let disks = fs::client().await.disks().await;
+
+        // the disk image made by gen-img.sh is the first disk, and has grub installed in its master boot record
+        let boot_disk = disks.first().ok_or_else(|| "fs server found no disks".to_owned())?;
+        test_assert_eq!(boot_disk.boot_signature, Some(fs::BOOT_SIGNATURE));
+        test_assert!(boot_disk.capacity > 0, "boot disk has no blocks");
+
+        Ok(())
+    })
+}
+
+aurora_test::tests! {
+    write_then_read_round_trips,
+    missing_file_is_not_found,
+    read_is_short_at_end_of_file,
+    seek_moves_position,
+    buf_reader_reads_lines,
+    buf_reader_keeps_newline,
+    shared_file_with_lock,
+    write_past_max_file_size_fails,
+    boot_disk_has_boot_signature,
+}
+
+fn main() {
+    aurora_test::run_tests(TESTS);
+}
\ No newline at end of file
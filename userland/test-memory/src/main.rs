@@ -0,0 +1,84 @@
+//! Tests the userland allocator against real heap zones mapped by the kernel
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use core::alloc::Layout;
+
+use aurora::allocator::LinkedListAllocator;
+use aurora_test::{TestResult, test_assert};
+use std::prelude::*;
+
+/// Bigger than the initial heap zone size, so the allocation can't come from memory the zone header was written to
+const FRESH_ZONE_ALLOC_SIZE: usize = 16 * 4096;
+
+/// 1 MiB is allocated in total, which would take 32 of the old fixed size heap zones
+const GROWTH_ALLOC_SIZE: usize = 1024;
+const GROWTH_ALLOC_COUNT: usize = 1024;
+
+fn fresh_heap_zone_reads_as_zero() -> TestResult {
+    // a new allocator has no heap zones, so this maps new memory
+    let allocator = LinkedListAllocator::new();
+    let layout = Layout::from_size_align(FRESH_ZONE_ALLOC_SIZE, 8).unwrap();
+
+    let (allocation, _) = allocator.alloc_with_message_buffer(layout)
+        .ok_or_else(|| "failed to allocate from fresh heap zone".to_owned())?;
+
+    // reading faults in the lazily allocated pages
+    let data = unsafe { allocation.as_ref() };
+    let nonzero = data.iter().position(|byte| *byte != 0);
+
+    unsafe {
+        allocator.dealloc(allocation.cast(), layout);
+    }
+
+    test_assert!(nonzero.is_none(), "fresh heap zone has nonzero byte at offset {nonzero:?}");
+
+    Ok(())
+}
+
+/// Allocates [`GROWTH_ALLOC_COUNT`] blocks of [`GROWTH_ALLOC_SIZE`] bytes from a new allocator,
+/// and checks the heap zone was grown instead of a new zone being made for every few allocations
+#[cfg(debug_assertions)]
+fn heap_growth_uses_few_syscalls() -> TestResult {
+    let allocator = LinkedListAllocator::new();
+    let layout = Layout::from_size_align(GROWTH_ALLOC_SIZE, 8).unwrap();
+    let mut allocations = Vec::with_capacity(GROWTH_ALLOC_COUNT);
+
+    let start_count = sys::syscall_count();
+    for _ in 0..GROWTH_ALLOC_COUNT {
+        let (allocation, _) = allocator.alloc_with_message_buffer(layout)
+            .ok_or_else(|| "failed to allocate".to_owned())?;
+        allocations.push(allocation);
+    }
+    let syscalls = sys::syscall_count() - start_count;
+
+    for allocation in allocations {
+        unsafe {
+            allocator.dealloc(allocation.cast(), layout);
+        }
+    }
+
+    // making a new heap zone takes at least 2 syscalls, and the old allocator made one for every 8 pages
+    let zone_count = GROWTH_ALLOC_COUNT * GROWTH_ALLOC_SIZE / (8 * 4096);
+    test_assert!(syscalls < zone_count, "allocating took {syscalls} syscalls");
+
+    Ok(())
+}
+
+/// Syscalls are only counted in debug builds
+#[cfg(not(debug_assertions))]
+fn heap_growth_uses_few_syscalls() -> TestResult {
+    Ok(())
+}
+
+aurora_test::tests! {
+    fresh_heap_zone_reads_as_zero,
+    heap_growth_uses_few_syscalls,
+}
+
+fn main() {
+    aurora_test::run_tests(TESTS);
+}
\ No newline at end of file
@@ -0,0 +1,219 @@
+//! Tests pci config space register access against a mock config space in normal memory,
+//! and claiming devices and io ports from the hwaccess server in the registry
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use core::ptr;
+
+use alloc::boxed::Box;
+
+use aurora::env;
+use aurora::registry::RegistryAsync;
+use aurora::service::AppServiceAsync;
+use aurora_test::{TestResult, test_assert, test_assert_eq};
+use hwaccess_server::{HwAccess, HwAccessAsync, IoPortRangeAsync, PciDeviceAsync};
+use hwaccess_server::pci::config_space::{
+    PciConfigSpaceHeader,
+    RawConfigSpace,
+    ConfigWidth,
+    CONFIG_SPACE_SIZE,
+    COMMAND_OFFSET,
+    STANDARD_HEADER_END_OFFSET,
+    is_config_write_allowed,
+};
+use std::prelude::*;
+
+/// Ports of the second serial port, which no server claims
+const COM2_BASE: u16 = 0x2f8;
+const COM2_PORT_COUNT: u16 = 8;
+
+/// Offset of bar 0, the bars take up the next 24 bytes
+const BAR0_OFFSET: u16 = 0x10;
+const BARS_END_OFFSET: u16 = 0x28;
+
+#[repr(C, align(4096))]
+struct ConfigSpaceMemory(RawConfigSpace);
+
+/// A config space in normal memory, every byte starts out as the low byte of its offset
+struct MockConfigSpace {
+    header: PciConfigSpaceHeader,
+    memory: *mut ConfigSpaceMemory,
+}
+
+impl MockConfigSpace {
+    fn new() -> Self {
+        let mut memory = Box::new(ConfigSpaceMemory([0; CONFIG_SPACE_SIZE]));
+        for (offset, byte) in memory.0.iter_mut().enumerate() {
+            *byte = offset as u8;
+        }
+
+        let memory = Box::into_raw(memory);
+
+        MockConfigSpace {
+            // safety: memory is a config space sized allocation which is freed only after the header is dropped
+            header: unsafe { PciConfigSpaceHeader::from_addr(memory as usize) },
+            memory,
+        }
+    }
+
+    fn byte(&self, offset: usize) -> u8 {
+        assert!(offset < CONFIG_SPACE_SIZE);
+
+        // safety: offset was checked to be inside the memory, which is read volatile like the header does
+        unsafe { ptr::read_volatile((self.memory as *const u8).add(offset)) }
+    }
+}
+
+impl Drop for MockConfigSpace {
+    fn drop(&mut self) {
+        // safety: memory was made with Box::into_raw, and the header is not used after this
+        drop(unsafe { Box::from_raw(self.memory) });
+    }
+}
+
+fn reads_use_access_width() -> TestResult {
+    let config_space = MockConfigSpace::new();
+
+    test_assert_eq!(config_space.header.read_register(0x45, ConfigWidth::U8), Some(0x45));
+    test_assert_eq!(config_space.header.read_register(0x46, ConfigWidth::U16), Some(0x4746));
+    test_assert_eq!(config_space.header.read_register(0x48, ConfigWidth::U32), Some(0x4b4a4948));
+
+    Ok(())
+}
+
+fn writes_only_touch_access_width() -> TestResult {
+    let config_space = MockConfigSpace::new();
+
+    test_assert!(config_space.header.write_register(0x41, ConfigWidth::U8, 0xaabbccdd));
+    test_assert!(config_space.header.write_register(0x46, ConfigWidth::U16, 0xaabbccdd));
+    test_assert!(config_space.header.write_register(0x48, ConfigWidth::U32, 0xaabbccdd));
+
+    let expected: [u8; 0x10] = [
+        0x40, 0xdd, 0x42, 0x43, 0x44, 0x45, 0xdd, 0xcc,
+        0xdd, 0xcc, 0xbb, 0xaa, 0x4c, 0x4d, 0x4e, 0x4f,
+    ];
+    for (i, expected_byte) in expected.iter().enumerate() {
+        test_assert_eq!(config_space.byte(0x40 + i), *expected_byte, "byte at offset {:#x}", 0x40 + i);
+    }
+
+    test_assert_eq!(config_space.header.read_register(0x48, ConfigWidth::U32), Some(0xaabbccdd));
+
+    Ok(())
+}
+
+fn unaligned_offsets_are_rejected() -> TestResult {
+    let config_space = MockConfigSpace::new();
+
+    for (offset, width) in [(0x41, ConfigWidth::U16), (0x42, ConfigWidth::U32), (0x43, ConfigWidth::U32)] {
+        test_assert_eq!(config_space.header.read_register(offset, width), None, "read of {width:?} at {offset:#x}");
+        test_assert!(
+            !config_space.header.write_register(offset, width, 0),
+            "write of {width:?} at {offset:#x} was not rejected",
+        );
+    }
+
+    // rejected writes must not change anything
+    for offset in 0x40..0x48 {
+        test_assert_eq!(config_space.byte(offset), offset as u8, "byte at offset {offset:#x}");
+    }
+
+    Ok(())
+}
+
+fn out_of_bounds_offsets_are_rejected() -> TestResult {
+    let config_space = MockConfigSpace::new();
+    let end = CONFIG_SPACE_SIZE as u16;
+
+    test_assert_eq!(config_space.header.read_register(end - 1, ConfigWidth::U8), Some(0xff));
+    test_assert_eq!(config_space.header.read_register(end - 4, ConfigWidth::U32), Some(0xfffefdfc));
+
+    test_assert_eq!(config_space.header.read_register(end, ConfigWidth::U8), None);
+    test_assert_eq!(config_space.header.read_register(end, ConfigWidth::U32), None);
+    test_assert!(!config_space.header.write_register(end, ConfigWidth::U16, 0));
+
+    Ok(())
+}
+
+fn only_command_status_and_capabilities_are_writable() -> TestResult {
+    test_assert!(is_config_write_allowed(COMMAND_OFFSET, ConfigWidth::U16));
+    test_assert!(is_config_write_allowed(COMMAND_OFFSET + 2, ConfigWidth::U16));
+    test_assert!(is_config_write_allowed(COMMAND_OFFSET, ConfigWidth::U32));
+    test_assert!(is_config_write_allowed(STANDARD_HEADER_END_OFFSET, ConfigWidth::U32));
+    test_assert!(is_config_write_allowed(0xffc, ConfigWidth::U32));
+
+    // vendor and device id
+    test_assert!(!is_config_write_allowed(0, ConfigWidth::U32));
+    // overlaps the status register and the revision id
+    test_assert!(!is_config_write_allowed(COMMAND_OFFSET + 2, ConfigWidth::U32));
+
+    for offset in BAR0_OFFSET..BARS_END_OFFSET {
+        test_assert!(!is_config_write_allowed(offset, ConfigWidth::U8), "bar byte at {offset:#x} is writable");
+    }
+
+    test_assert!(!is_config_write_allowed(u16::MAX, ConfigWidth::U32));
+
+    Ok(())
+}
+
+fn second_device_claim_fails() -> TestResult {
+    asynca::block_in_place(async {
+        let hwaccess: HwAccess = env::registry().lookup("hwaccess".to_owned()).await
+            .ok_or_else(|| "no hwaccess service in registry".to_owned())?
+            .into();
+
+        // other servers have already claimed some devices, so use the first one which is still free
+        let mut claimed = None;
+        for device_info in hwaccess.get_pci_devices().await {
+            if let Some(device) = hwaccess.claim_device(device_info.device_address).await {
+                claimed = Some((device_info, device));
+                break;
+            }
+        }
+        let (device_info, device) = claimed.ok_or_else(|| "no unclaimed pci device to test with".to_owned())?;
+
+        test_assert_eq!(device.device_info().await, device_info);
+        test_assert!(
+            hwaccess.claim_device(device_info.device_address).await.is_none(),
+            "device {:?} was claimed twice",
+            device_info.device_address,
+        );
+
+        Ok(())
+    })
+}
+
+fn io_port_range_has_no_new_sessions() -> TestResult {
+    asynca::block_in_place(async {
+        let hwaccess: HwAccess = env::registry().lookup("hwaccess".to_owned()).await
+            .ok_or_else(|| "no hwaccess service in registry".to_owned())?
+            .into();
+
+        let port_range = hwaccess.claim_io_ports(COM2_BASE, COM2_PORT_COUNT).await
+            .ok_or_else(|| "could not claim com2 io ports".to_owned())?;
+
+        test_assert_eq!(port_range.range().await, (COM2_BASE, COM2_PORT_COUNT));
+        test_assert!(
+            port_range.new_session_permissions(Vec::new()).await.is_none(),
+            "io port range made a new session",
+        );
+
+        Ok(())
+    })
+}
+
+aurora_test::tests! {
+    reads_use_access_width,
+    writes_only_touch_access_width,
+    unaligned_offsets_are_rejected,
+    out_of_bounds_offsets_are_rejected,
+    only_command_status_and_capabilities_are_writable,
+    second_device_claim_fails,
+    io_port_range_has_no_new_sessions,
+}
+
+fn main() {
+    aurora_test::run_tests(TESTS);
+}
\ No newline at end of file
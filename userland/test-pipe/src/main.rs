@@ -0,0 +1,210 @@
+//! Tests sending data through pipes, within this process and to a child process
+//!
+//! The child is a copy of this binary read from the fs server, started with the reader end of a pipe as the
+//! `pipe_reader` named argument. It reads slowly, so the parent has to wait for buffer space,
+//! and exits with 0 if the data it read has the length and checksum it was given.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use core::time::Duration;
+
+use alloc::format;
+use alloc::vec;
+
+use aurora::env::{self, ArgError};
+use aurora::fs;
+use aurora::io::{Pipe, PipeReader, PipeWriter, Read, Write, PIPE_BUFFER_SIZE};
+use aurora::process::{self, Child, Command};
+use aurora::time;
+use aurora_test::{TestResult, test_assert, test_assert_eq};
+use std::prelude::*;
+use sys::SysErr;
+
+/// Where the fs server puts this binary from the initrd
+const BINARY_PATH: &str = "/initrd/test-pipe";
+
+/// Number of bytes sent to the child
+const TRANSFER_SIZE: usize = 10 * 1024 * 1024;
+
+/// Size of the chunks the parent writes, larger than the pipe buffer so writes have to wait
+const WRITE_CHUNK_SIZE: usize = PIPE_BUFFER_SIZE + 1000;
+
+/// Size of the reads the child does, not a divisor of the pipe buffer size so reads wrap around the end of it
+const READ_CHUNK_SIZE: usize = 3000;
+
+/// The child sleeps after this many reads
+const READS_PER_SLEEP: usize = 64;
+
+const READ_SLEEP_TIME: Duration = Duration::from_millis(1);
+
+/// Generates the data sent to the child
+struct DataGenerator(u64);
+
+impl DataGenerator {
+    fn new() -> Self {
+        DataGenerator(0x2545f4914f6cdd1d)
+    }
+
+    /// Fills `buffer` with the next bytes of the data, using xorshift
+    fn fill(&mut self, buffer: &mut [u8]) {
+        for byte in buffer {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            *byte = self.0 as u8;
+        }
+    }
+}
+
+/// FNV-1a hash of all the bytes passed to [`update`](Self::update)
+struct Checksum(u64);
+
+impl Checksum {
+    fn new() -> Self {
+        Checksum(0xcbf29ce484222325)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// Calls `f` with each chunk of the data sent to the child
+fn for_each_chunk(mut f: impl FnMut(&[u8]) -> TestResult) -> TestResult {
+    let mut generator = DataGenerator::new();
+    let mut buffer = vec![0; WRITE_CHUNK_SIZE];
+    let mut remaining = TRANSFER_SIZE;
+
+    while remaining > 0 {
+        let chunk = &mut buffer[..remaining.min(WRITE_CHUNK_SIZE)];
+        generator.fill(chunk);
+        f(chunk)?;
+        remaining -= chunk.len();
+    }
+
+    Ok(())
+}
+
+fn new_pipe() -> Result<(PipeWriter, PipeReader), String> {
+    Pipe::new().map_err(|error| format!("failed to create pipe: {error}"))
+}
+
+fn spawn_child(reader: &PipeReader, checksum: u64) -> Result<Child, String> {
+    let elf_data = asynca::block_in_place(fs::read(BINARY_PATH))
+        .map_err(|error| format!("failed to read {BINARY_PATH}: {error}"))?;
+
+    let mut command = Command::from_bytes(elf_data);
+    command.name("test-pipe-child".to_owned())
+        .named_arg("pipe_reader".to_owned(), reader)
+        .named_arg("length".to_owned(), &TRANSFER_SIZE)
+        .named_arg("checksum".to_owned(), &checksum);
+
+    command.spawn()
+        .map_err(|error| format!("failed to spawn child: {error}"))
+}
+
+fn reader_sees_end_after_writer_dropped() -> TestResult {
+    let (mut writer, mut reader) = new_pipe()?;
+
+    writer.write_all(b"hello pipe")
+        .map_err(|error| format!("failed to write: {error}"))?;
+    drop(writer);
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)
+        .map_err(|error| format!("failed to read: {error}"))?;
+    test_assert_eq!(data, b"hello pipe");
+
+    Ok(())
+}
+
+fn write_fails_after_reader_dropped() -> TestResult {
+    let (mut writer, reader) = new_pipe()?;
+    drop(reader);
+
+    // more than fits in the buffer, so the write waits until the reader is seen to be dropped
+    let result = writer.write_all(&vec![0; PIPE_BUFFER_SIZE + 1]);
+    test_assert_eq!(result, Err(SysErr::PeerGone));
+
+    Ok(())
+}
+
+fn large_transfer_to_slow_child() -> TestResult {
+    let mut checksum = Checksum::new();
+    for_each_chunk(|chunk| {
+        checksum.update(chunk);
+        Ok(())
+    })?;
+
+    let (mut writer, reader) = new_pipe()?;
+    let child = spawn_child(&reader, checksum.0)?;
+    drop(reader);
+
+    for_each_chunk(|chunk| {
+        writer.write_all(chunk)
+            .map_err(|error| format!("failed to write to child: {error}"))
+    })?;
+    drop(writer);
+
+    let status = child.wait()
+        .map_err(|error| format!("failed to wait for child: {error}"))?;
+    test_assert!(status.success(), "child exited with {status}, the data it read was wrong");
+
+    Ok(())
+}
+
+aurora_test::tests! {
+    reader_sees_end_after_writer_dropped,
+    write_fails_after_reader_dropped,
+    large_transfer_to_slow_child,
+}
+
+/// Reads until the end of the pipe, and exits with 0 if the data has the expected length and checksum
+fn run_child(mut reader: PipeReader) -> ! {
+    let args = env::args();
+    let expected_length: usize = args.require("length");
+    let expected_checksum: u64 = args.require("checksum");
+
+    let mut buffer = [0; READ_CHUNK_SIZE];
+    let mut checksum = Checksum::new();
+    let mut length = 0;
+
+    for read_count in 1.. {
+        let read_size = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read_size) => read_size,
+            Err(error) => {
+                dprintln!("failed to read from pipe after {length} bytes: {error}");
+                process::exit_with_code(2);
+            },
+        };
+
+        checksum.update(&buffer[..read_size]);
+        length += read_size;
+
+        if read_count % READS_PER_SLEEP == 0 {
+            time::sleep(READ_SLEEP_TIME);
+        }
+    }
+
+    if length != expected_length || checksum.0 != expected_checksum {
+        dprintln!("read {length} bytes with checksum {:#x}, expected {expected_length} bytes with checksum {expected_checksum:#x}", checksum.0);
+        process::exit_with_code(1);
+    }
+
+    process::exit_with_code(0);
+}
+
+fn main() {
+    match env::args().named_arg::<PipeReader>("pipe_reader") {
+        Ok(reader) => run_child(reader),
+        Err(ArgError::Missing { .. }) => aurora_test::run_tests(TESTS),
+        Err(error) => panic!("invalid pipe reader: {error}"),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,222 @@
+//! Tests spawning processes and reading how they exited
+//!
+//! The children are copies of this binary read from the fs server. A child started with the `exit_code` named argument
+//! exits with that code right away, one started with the `hang` named argument never exits,
+//! one started with the `fault` named argument reads from a null pointer,
+//! one started with the `recurse` named argument recurses until its stack overflows,
+//! and one started with the `raise_limit` named argument tries to raise the memory limit of its own allocator.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use core::hint::black_box;
+use core::time::Duration;
+
+use alloc::format;
+
+use aurora::env;
+use aurora::fs;
+use aurora::process::{self, Child, Command};
+use aurora::this_context;
+use aurora::time::{self, Instant};
+use aurora_test::{TestResult, test_assert, test_assert_eq};
+use std::prelude::*;
+use bit_utils::Size;
+use sys::{ExitReason, FaultKind, SysErr};
+
+/// Where the fs server puts this binary from the initrd
+const BINARY_PATH: &str = "/initrd/test-process";
+
+/// Exit code passed to [`Child::kill`]
+const KILL_EXIT_CODE: i32 = 77;
+
+/// Stored on the stack of a faulting child, so the test can find it in the captured stack
+const FAULT_STACK_MARKER: u64 = 0xfa17_5eed_fa17_5eed;
+
+/// Exit code of a `raise_limit` child whose allocator rejected the new limit
+const LIMIT_REJECTED_EXIT_CODE: i32 = 0;
+
+/// Exit code of a `raise_limit` child which was able to raise its own limit
+const LIMIT_RAISED_EXIT_CODE: i32 = 1;
+
+fn spawn_child(configure: impl FnOnce(&mut Command)) -> Result<Child, String> {
+    let elf_data = asynca::block_in_place(fs::read(BINARY_PATH))
+        .map_err(|error| format!("failed to read {BINARY_PATH}: {error}"))?;
+
+    let mut command = Command::from_bytes(elf_data);
+    command.name("test-process-child".to_owned());
+    configure(&mut command);
+
+    command.spawn()
+        .map_err(|error| format!("failed to spawn child: {error}"))
+}
+
+fn exit_code_is_reported() -> TestResult {
+    for exit_code in [0, 1, 42] {
+        let child = spawn_child(|command| {
+            command.named_arg("exit_code".to_owned(), &exit_code);
+        })?;
+
+        let status = child.wait()
+            .map_err(|error| format!("failed to wait for child: {error}"))?;
+
+        test_assert_eq!(status.code(), Some(exit_code));
+        test_assert_eq!(status.success(), exit_code == 0);
+    }
+
+    Ok(())
+}
+
+fn wait_times_out_and_kill_ends_child() -> TestResult {
+    let child = spawn_child(|command| {
+        command.named_arg("hang".to_owned(), &true);
+    })?;
+
+    let result = child.wait_until(Instant::now() + Duration::from_millis(50));
+    test_assert!(matches!(result, Err(SysErr::OkTimeout)), "waiting for hanging child returned {result:?}");
+
+    child.kill(KILL_EXIT_CODE)
+        .map_err(|error| format!("failed to kill child: {error}"))?;
+
+    let status = child.wait()
+        .map_err(|error| format!("failed to wait for child: {error}"))?;
+    test_assert_eq!(status.code(), Some(KILL_EXIT_CODE));
+
+    Ok(())
+}
+
+fn fault_snapshot_has_registers_and_stack() -> TestResult {
+    let child = spawn_child(|command| {
+        command.named_arg("fault".to_owned(), &true);
+        command.fault_capture(Size::from_pages(4));
+    })?;
+
+    let status = child.wait()
+        .map_err(|error| format!("failed to wait for child: {error}"))?;
+    test_assert_eq!(status.reason(), ExitReason::Fault(FaultKind::PageFault));
+
+    let snapshot = child.fault_snapshot()
+        .map_err(|error| format!("failed to get fault snapshot: {error}"))?
+        .ok_or("faulted child has no fault snapshot")?;
+
+    test_assert_eq!(snapshot.record.kind(), Some(FaultKind::PageFault));
+    test_assert_eq!(snapshot.record.fault_address, 0);
+    test_assert!(snapshot.record.rip != 0);
+    test_assert!(!snapshot.stack.is_empty(), "no stack was captured");
+
+    let has_marker = snapshot.stack.chunks_exact(8)
+        .any(|word| word == FAULT_STACK_MARKER.to_le_bytes());
+    test_assert!(has_marker, "stack marker is not in the {} captured bytes", snapshot.stack.len());
+
+    Ok(())
+}
+
+fn fault_is_recorded_without_stack_capture() -> TestResult {
+    let child = spawn_child(|command| {
+        command.named_arg("fault".to_owned(), &true);
+    })?;
+
+    child.wait()
+        .map_err(|error| format!("failed to wait for child: {error}"))?;
+
+    let snapshot = child.fault_snapshot()
+        .map_err(|error| format!("failed to get fault snapshot: {error}"))?
+        .ok_or("faulted child has no fault snapshot")?;
+
+    test_assert_eq!(snapshot.record.fault_address, 0);
+    test_assert!(snapshot.stack.is_empty());
+
+    Ok(())
+}
+
+fn stack_overflow_is_reported() -> TestResult {
+    let child = spawn_child(|command| {
+        command.named_arg("recurse".to_owned(), &true);
+    })?;
+
+    let status = child.wait()
+        .map_err(|error| format!("failed to wait for child: {error}"))?;
+
+    // the guard region below the main stack turns the overflow into its own exit reason, instead of a page fault
+    test_assert_eq!(status.reason(), ExitReason::Fault(FaultKind::StackOverflow));
+
+    Ok(())
+}
+
+fn child_cannot_raise_memory_limit() -> TestResult {
+    let child = spawn_child(|command| {
+        command.named_arg("raise_limit".to_owned(), &true);
+        command.memory_limit(Size::from_pages(16384));
+    })?;
+
+    let status = child.wait()
+        .map_err(|error| format!("failed to wait for child: {error}"))?;
+
+    test_assert_eq!(status.code(), Some(LIMIT_REJECTED_EXIT_CODE));
+
+    Ok(())
+}
+
+aurora_test::tests! {
+    exit_code_is_reported,
+    wait_times_out_and_kill_ends_child,
+    fault_snapshot_has_registers_and_stack,
+    fault_is_recorded_without_stack_capture,
+    stack_overflow_is_reported,
+    child_cannot_raise_memory_limit,
+}
+
+/// Reads from a null pointer with [`FAULT_STACK_MARKER`] on the stack
+fn fault() -> ! {
+    let marker = black_box([FAULT_STACK_MARKER; 4]);
+    // the address goes through black_box so the read isn't optimized out as undefined behavior
+    let null = black_box(0usize) as *const u64;
+    let value = unsafe { core::ptr::read_volatile(null) };
+
+    // the marker is used after the read, so it is still on the stack when the fault happens
+    black_box((marker, value));
+    unreachable!("read from null pointer did not fault");
+}
+
+/// Recurses until the stack runs into its guard region
+#[allow(unconditional_recursion)]
+fn recurse(depth: u64) -> u64 {
+    // the frame is kept alive across the call, so every level uses more stack
+    let frame = black_box([depth; 64]);
+    recurse(depth + 1) + frame[0]
+}
+
+fn main() {
+    let args = env::args();
+
+    if let Ok(exit_code) = args.named_arg::<i32>("exit_code") {
+        process::exit_with_code(exit_code);
+    }
+
+    if args.named_arg::<bool>("fault").is_ok() {
+        fault();
+    }
+
+    if args.named_arg::<bool>("recurse").is_ok() {
+        recurse(0);
+    }
+
+    if args.named_arg::<bool>("raise_limit").is_ok() {
+        let exit_code = match this_context().allocator.set_limit(usize::MAX) {
+            Err(SysErr::InvlPerm) => LIMIT_REJECTED_EXIT_CODE,
+            // any other error means the limit itself was checked, which only happens with write permissions
+            _ => LIMIT_RAISED_EXIT_CODE,
+        };
+        process::exit_with_code(exit_code);
+    }
+
+    if args.named_arg::<bool>("hang").is_ok() {
+        loop {
+            time::sleep(Duration::from_secs(1));
+        }
+    }
+
+    aurora_test::run_tests(TESTS);
+}
\ No newline at end of file
@@ -0,0 +1,217 @@
+//! Runs every userland test binary and reports the results
+//!
+//! early-init starts this when `utest` is passed on the kernel command line, and passes it the initrd files
+//! starting with [`TEST_BINARY_PREFIX`] in the `test_binaries` named argument.
+//! Each binary is run in turn with a channel to send its [`TestReport`]s on, and is killed if it runs for longer than
+//! [`TEST_BINARY_TIMEOUT`]. Once every binary has run a summary is printed,
+//! and the kernel is told if every test passed so the emulator can exit with a matching status.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use core::cmp::min;
+use core::time::Duration;
+
+use alloc::format;
+
+use aurora::collections::MessageVec;
+use aurora::allocator::addr_space::{MemoryMappingOptions, map_slice};
+use aurora::env;
+use aurora::fs::Fs;
+use aurora::process::{Child, Command};
+use aurora::this_context;
+use aurora::time::Instant;
+use aurora_test::{TestReport, TEST_BINARY_PREFIX, TEST_CHANNEL_ARG, REPORT_BUFFER_SIZE};
+use fs_server::InitialFiles;
+use std::prelude::*;
+use sys::{Channel, CapFlags, DebugCap, SysErr};
+
+/// How long a test binary can run for before it is killed
+const TEST_BINARY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often to check if a test binary exited while waiting for its next report
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for a test binary to exit after it has sent its last report or been killed
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Exit code given to test binaries which are killed for running too long
+const TIMED_OUT_EXIT_CODE: i32 = 124;
+
+/// Results of running one test binary
+#[derive(Debug, Default)]
+struct BinaryResults {
+    passed: usize,
+    /// Names of the tests which failed
+    failed: Vec<String>,
+    /// Set if the binary itself failed, for example by panicking or timing out
+    error: Option<String>,
+}
+
+impl BinaryResults {
+    fn success(&self) -> bool {
+        self.failed.is_empty() && self.error.is_none()
+    }
+}
+
+fn deadline_nanos(deadline: Instant) -> u64 {
+    deadline.as_boot_time().as_nanos() as u64
+}
+
+/// Recieves reports from a running test binary until it sends [`TestReport::Finished`], exits, or times out
+fn collect_reports(name: &str, channel: &Channel, child: &Child, results: &mut BinaryResults) {
+    let deadline = Instant::now() + TEST_BINARY_TIMEOUT;
+    let buffer = MessageVec::from_slice(&[0u8; REPORT_BUFFER_SIZE]);
+
+    loop {
+        let poll_deadline = min(Instant::now() + EXIT_POLL_INTERVAL, deadline);
+
+        // panic safety: the buffer is not empty, so it has a message buffer
+        let recieve_size = match channel.sync_recv(&buffer.message_buffer().unwrap(), Some(deadline_nanos(poll_deadline))) {
+            Ok(recieve_result) => recieve_result.recieve_size.bytes(),
+            Err(SysErr::OkTimeout) => {
+                // test binaries wait for each report to be recieved, so once the binary has exited there are no more reports
+                if child.wait_until(Instant::now()).is_ok() {
+                    results.error = Some("exited before all tests finished".to_owned());
+                    return;
+                }
+
+                if Instant::now() >= deadline {
+                    if let Err(error) = child.kill(TIMED_OUT_EXIT_CODE) {
+                        dprintln!("failed to kill {name}: {error}");
+                    }
+
+                    results.error = Some(format!("timed out after {} seconds", TEST_BINARY_TIMEOUT.as_secs()));
+                    return;
+                }
+
+                continue;
+            },
+            Err(error) => {
+                results.error = Some(format!("failed to recieve test report: {error}"));
+                return;
+            },
+        };
+
+        match aser::from_bytes(&buffer.as_slice()[..recieve_size]) {
+            Ok(TestReport::Outcome(outcome)) => match outcome.failure {
+                None => results.passed += 1,
+                Some(failure) => {
+                    dprintln!("{name}: {} failed: {failure}", outcome.name);
+                    results.failed.push(outcome.name);
+                },
+            },
+            Ok(TestReport::Finished) => return,
+            Err(error) => {
+                results.error = Some(format!("sent invalid test report: {error}"));
+                return;
+            },
+        }
+    }
+}
+
+fn run_test_binary(name: &str, elf_data: Vec<u8>, fs: Option<&Fs>) -> BinaryResults {
+    let mut results = BinaryResults::default();
+
+    let channel = match Channel::new(CapFlags::all(), &this_context().allocator) {
+        Ok(channel) => channel,
+        Err(error) => {
+            results.error = Some(format!("failed to create test channel: {error}"));
+            return results;
+        },
+    };
+
+    let mut command = Command::from_bytes(elf_data);
+    command.name(name.to_owned())
+        .named_arg(TEST_CHANNEL_ARG.to_owned(), &channel);
+    if let Some(fs) = fs {
+        command.named_arg("fs_server".to_owned(), fs);
+    }
+
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(error) => {
+            results.error = Some(format!("failed to spawn: {error}"));
+            return results;
+        },
+    };
+
+    collect_reports(name, &channel, &child, &mut results);
+
+    match child.wait_until(Instant::now() + EXIT_TIMEOUT) {
+        // a binary with failed tests exits with an error, which is already reported by the failed tests
+        Ok(status) if status.success() || !results.failed.is_empty() => (),
+        Ok(status) => {
+            results.error.get_or_insert_with(|| format!("exited with {status}"));
+        },
+        Err(error) => {
+            results.error.get_or_insert_with(|| format!("failed to wait for exit: {error}"));
+        },
+    }
+
+    results
+}
+
+fn main() {
+    let args = env::args();
+    let debug_cap: DebugCap = args.require("debug_cap");
+    let test_binaries: InitialFiles = args.require("test_binaries");
+    let fs: Option<Fs> = args.named_arg("fs_server").ok();
+
+    let data_size = test_binaries.files.iter()
+        .map(|file| file.offset.saturating_add(file.len))
+        .max()
+        .unwrap_or(0);
+
+    let mut binaries_failed = 0;
+    let mut tests_passed = 0;
+    let mut tests_failed = 0;
+
+    if data_size > 0 {
+        let data = map_slice::<u8, _>(&test_binaries.memory, 0, data_size, MemoryMappingOptions {
+            read: true,
+            ..Default::default()
+        }).expect("failed to map test binaries");
+
+        for file in test_binaries.files.iter() {
+            if !file.path.starts_with(TEST_BINARY_PREFIX) {
+                continue;
+            }
+
+            dprintln!("running {} ...", file.path);
+            let elf_data = data[file.offset..file.offset + file.len].to_vec();
+            let results = run_test_binary(&file.path, elf_data, fs.as_ref());
+
+            tests_passed += results.passed;
+            tests_failed += results.failed.len();
+
+            if results.success() {
+                dprintln!("{} ... ok ({} passed)", file.path, results.passed);
+            } else {
+                binaries_failed += 1;
+                dprintln!(
+                    "{} ... FAILED ({} passed, {} failed){}",
+                    file.path,
+                    results.passed,
+                    results.failed.len(),
+                    results.error.map(|error| format!(": {error}")).unwrap_or_default(),
+                );
+            }
+        }
+    }
+
+    let success = binaries_failed == 0;
+    dprintln!(
+        "UTEST RESULT: {} passed={} failed={} failed_binaries={}",
+        if success { "ok" } else { "FAILED" },
+        tests_passed,
+        tests_failed,
+        binaries_failed,
+    );
+
+    if let Err(error) = debug_cap.exit_emulator(success) {
+        dprintln!("failed to report test result to kernel: {error}");
+    }
+}
\ No newline at end of file
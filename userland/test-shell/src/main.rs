@@ -0,0 +1,154 @@
+//! Tests the ash shell by running a scripted session through its standard input and checking what it prints
+//!
+//! The programs the script runs are copies of this binary read from the fs server, which are given positional
+//! arguments saying what to do. The test runner passes no positional arguments, so that is when the tests are run.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use core::cmp::min;
+use core::time::Duration;
+
+use alloc::format;
+
+use aurora::collections::MessageVec;
+use aurora::env;
+use aurora::fs::{self, fs_client};
+use aurora::io::{ChannelWriter, Write, OUTPUT_BUFFER_SIZE};
+use aurora::process::{self, Child, Command};
+use aurora::thread;
+use aurora::this_context;
+use aurora::time::Instant;
+use aurora_test::{TestResult, test_assert, test_assert_eq};
+use std::prelude::*;
+use sys::{Channel, CapFlags, SysErr};
+
+/// Where the fs server puts the shell from the initrd
+const SHELL_PATH: &str = "/initrd/ash";
+
+/// How long the whole session can take
+const SESSION_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How often to check if the shell exited while waiting for output
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Runs programs with absolute and relative paths, hits each kind of error, and exits with a code of 3
+const SCRIPT: &str = "\
+/initrd/test-shell echo hello world
+cd /initrd
+test-shell echo relative path
+does-not-exist
+test-shell panic
+test-shell exit 7
+
+test-shell echo in background &
+cd /nope
+exit 3
+";
+
+fn new_channel() -> Result<Channel, String> {
+    Channel::new(CapFlags::all(), &this_context().allocator)
+        .map_err(|error| format!("failed to create channel: {error}"))
+}
+
+fn spawn_shell(stdin: &Channel, output: &Channel) -> Result<Child, String> {
+    let elf_data = asynca::block_in_place(fs::read(SHELL_PATH))
+        .map_err(|error| format!("failed to read {SHELL_PATH}: {error}"))?;
+    let fs = fs_client().ok_or("test was not given an fs server")?;
+
+    Command::from_bytes(elf_data)
+        .name("ash".to_owned())
+        .named_arg("fs_server".to_owned(), fs)
+        .stdin(stdin)
+        .stdout(output)
+        .stderr(output)
+        .spawn()
+        .map_err(|error| format!("failed to spawn shell: {error}"))
+}
+
+/// Recieves output sent on `channel` until `shell` exits
+fn read_session_output(channel: &Channel, shell: &Child) -> Result<String, String> {
+    let deadline = Instant::now() + SESSION_TIMEOUT;
+    let buffer = MessageVec::from_slice(&[0u8; OUTPUT_BUFFER_SIZE]);
+    let mut output = Vec::new();
+
+    loop {
+        let poll_deadline = min(Instant::now() + EXIT_POLL_INTERVAL, deadline);
+
+        // panic safety: the buffer is not empty, so it has a message buffer
+        match channel.sync_recv(&buffer.message_buffer().unwrap(), Some(poll_deadline.as_boot_time().as_nanos() as u64)) {
+            Ok(recieve_result) => output.extend_from_slice(&buffer.as_slice()[..recieve_result.recieve_size.bytes()]),
+            Err(SysErr::OkTimeout) => {
+                // output is sent synchronously, so once the shell has exited everything it printed has been recieved
+                if shell.wait_until(Instant::now()).is_ok() {
+                    break;
+                }
+
+                if Instant::now() >= deadline {
+                    let _ = shell.kill(1);
+                    return Err(format!("shell did not exit, output so far:\n{}", String::from_utf8_lossy(&output)));
+                }
+            },
+            Err(error) => return Err(format!("failed to recieve output after {} bytes: {error}", output.len())),
+        }
+    }
+
+    String::from_utf8(output)
+        .map_err(|error| format!("output is not utf-8: {error}"))
+}
+
+fn scripted_session() -> TestResult {
+    let stdin = new_channel()?;
+    let output = new_channel()?;
+    let shell = spawn_shell(&stdin, &output)?;
+
+    // sending waits for the shell to recieve the script, and the shell waits for its prompt to be recieved before that,
+    // so the script is sent from another thread while this one reads the output
+    // the whole script is sent in 1 message, and the shell splits it into lines
+    let send_script = thread::spawn(move || ChannelWriter::new(stdin).write_all(SCRIPT.as_bytes()));
+
+    let output = read_session_output(&output, &shell)?;
+    send_script.join()
+        .map_err(|error| format!("failed to send script: {error}"))?;
+    let expect_output = |text: &str| -> TestResult {
+        test_assert!(output.contains(text), "expected `{text}` in shell output:\n{output}");
+        Ok(())
+    };
+
+    expect_output("/$ hello world\n[/initrd/test-shell: exit code: 0]\n")?;
+    expect_output("/initrd$ relative path\n")?;
+    expect_output("ash: does-not-exist: No file or directory exists at the given path\n")?;
+    expect_output("ash: test-shell panicked\n[test-shell: exit code: 101]\n")?;
+    expect_output("[test-shell: exit code: 7]\n")?;
+    expect_output("started test-shell in the background\n")?;
+    expect_output("cd: /nope: No file or directory exists at the given path\n")?;
+
+    let status = shell.wait()
+        .map_err(|error| format!("failed to wait for shell: {error}"))?;
+    test_assert_eq!(status.code(), Some(3));
+
+    Ok(())
+}
+
+aurora_test::tests! {
+    scripted_session,
+}
+
+fn main() {
+    let args = env::args();
+
+    match args.positional::<&str>(0) {
+        Ok("echo") => {
+            let words: Vec<&str> = (1..args.positional_count())
+                .map(|index| args.require_positional(index))
+                .collect();
+
+            println!("{}", words.join(" "));
+        },
+        Ok("panic") => panic!("test-shell was asked to panic"),
+        Ok("exit") => process::exit_with_code(args.require_positional::<&str>(1).parse().unwrap()),
+        _ => aurora_test::run_tests(TESTS),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,132 @@
+//! Tests giving a child process channels as its standard output and error, and reading back what it prints
+//!
+//! The children are copies of this binary read from the fs server, started with the `print` named argument
+//! set to what they should print.
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use core::time::Duration;
+
+use alloc::format;
+
+use aurora::collections::MessageVec;
+use aurora::env;
+use aurora::fs;
+use aurora::io::OUTPUT_BUFFER_SIZE;
+use aurora::process::{Child, Command};
+use aurora::this_context;
+use aurora::time::Instant;
+use aurora_test::{TestResult, test_assert, test_assert_eq};
+use std::prelude::*;
+use sys::{Channel, CapFlags};
+
+/// Where the fs server puts this binary from the initrd
+const BINARY_PATH: &str = "/initrd/test-stdio";
+
+/// How long to wait for a child to print everything it was asked to
+const OUTPUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Output printed by a child started with `print` set to `stdout`, the last line is only sent when the child exits
+const STDOUT_TEXT: &str = "hello from child\nline 2\nno newline";
+
+const STDERR_TEXT: &str = "to stderr\n";
+
+fn spawn_child(print: &str, configure: impl FnOnce(&mut Command)) -> Result<Child, String> {
+    let elf_data = asynca::block_in_place(fs::read(BINARY_PATH))
+        .map_err(|error| format!("failed to read {BINARY_PATH}: {error}"))?;
+
+    let mut command = Command::from_bytes(elf_data);
+    command.name("test-stdio-child".to_owned())
+        .named_arg("print".to_owned(), &print);
+    configure(&mut command);
+
+    command.spawn()
+        .map_err(|error| format!("failed to spawn child: {error}"))
+}
+
+fn new_channel() -> Result<Channel, String> {
+    Channel::new(CapFlags::all(), &this_context().allocator)
+        .map_err(|error| format!("failed to create channel: {error}"))
+}
+
+/// Recieves output sent on `channel` until `expected_len` bytes have been read
+fn read_output(channel: &Channel, expected_len: usize) -> Result<String, String> {
+    let deadline = Instant::now() + OUTPUT_TIMEOUT;
+    let buffer = MessageVec::from_slice(&[0u8; OUTPUT_BUFFER_SIZE]);
+    let mut output = Vec::new();
+
+    while output.len() < expected_len {
+        // panic safety: the buffer is not empty, so it has a message buffer
+        let recieve_result = channel.sync_recv(&buffer.message_buffer().unwrap(), Some(deadline.as_boot_time().as_nanos() as u64))
+            .map_err(|error| format!("failed to recieve output after {} bytes: {error}", output.len()))?;
+
+        output.extend_from_slice(&buffer.as_slice()[..recieve_result.recieve_size.bytes()]);
+    }
+
+    String::from_utf8(output)
+        .map_err(|error| format!("output is not utf-8: {error}"))
+}
+
+fn stdout_is_sent_to_parent() -> TestResult {
+    let stdout = new_channel()?;
+    let child = spawn_child("stdout", |command| {
+        command.stdout(&stdout);
+    })?;
+
+    let output = read_output(&stdout, STDOUT_TEXT.len())?;
+    test_assert_eq!(output, STDOUT_TEXT);
+
+    let status = child.wait()
+        .map_err(|error| format!("failed to wait for child: {error}"))?;
+    test_assert!(status.success(), "child exited with {status}");
+
+    Ok(())
+}
+
+fn stderr_is_separate_from_stdout() -> TestResult {
+    let stdout = new_channel()?;
+    let stderr = new_channel()?;
+    let child = spawn_child("both", |command| {
+        command.stdout(&stdout)
+            .stderr(&stderr);
+    })?;
+
+    // the child prints to stderr first, and waits for each line to be recieved
+    let error_output = read_output(&stderr, STDERR_TEXT.len())?;
+    test_assert_eq!(error_output, STDERR_TEXT);
+
+    let output = read_output(&stdout, STDOUT_TEXT.len())?;
+    test_assert_eq!(output, STDOUT_TEXT);
+
+    let status = child.wait()
+        .map_err(|error| format!("failed to wait for child: {error}"))?;
+    test_assert!(status.success(), "child exited with {status}");
+
+    Ok(())
+}
+
+aurora_test::tests! {
+    stdout_is_sent_to_parent,
+    stderr_is_separate_from_stdout,
+}
+
+fn print_stdout_text() {
+    println!("hello from child");
+    println!("line {}", 2);
+    // sent when stdout is flushed after main returns
+    print!("no newline");
+}
+
+fn main() {
+    match env::args().named_arg::<&str>("print") {
+        Ok("stdout") => print_stdout_text(),
+        Ok("both") => {
+            eprint!("{STDERR_TEXT}");
+            print_stdout_text();
+        },
+        _ => aurora_test::run_tests(TESTS),
+    }
+}
\ No newline at end of file
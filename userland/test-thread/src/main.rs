@@ -0,0 +1,204 @@
+//! Tests parking and unparking threads, in particular that an unpark which happens before the park is not lost,
+//! and the futex based Mutex and Condvar
+
+#![no_std]
+
+extern crate alloc;
+extern crate std;
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::time::Duration;
+
+use alloc::sync::Arc;
+
+use aurora::sync::{Mutex, Condvar};
+use aurora::thread;
+use aurora::time::Instant;
+use aurora_test::{TestResult, test_assert, test_assert_eq};
+use std::prelude::*;
+use sys::SysErr;
+
+/// Timeout of parks which are expected to return right away, long enough that a lost unpark can't go unnoticed
+const LONG_PARK_TIMEOUT: Duration = Duration::from_secs(5);
+/// A park which returns faster than this did not wait for its timeout
+const IMMEDIATE_RETURN: Duration = Duration::from_secs(1);
+/// Timeout of parks which are expected to time out
+const SHORT_PARK_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Parks for at most [`LONG_PARK_TIMEOUT`] and returns how long the park took
+fn timed_park() -> Duration {
+    let start = Instant::now();
+    thread::park_timeout(LONG_PARK_TIMEOUT);
+    start.elapsed()
+}
+
+fn unpark_before_park_returns_immediately() -> TestResult {
+    thread::current().unpark();
+
+    let park_time = timed_park();
+    test_assert!(park_time < IMMEDIATE_RETURN, "park after unpark took {park_time:?}");
+
+    Ok(())
+}
+
+fn wake_tokens_do_not_accumulate() -> TestResult {
+    let current = thread::current();
+    current.unpark();
+    current.unpark();
+
+    let park_time = timed_park();
+    test_assert!(park_time < IMMEDIATE_RETURN, "park after unpark took {park_time:?}");
+
+    // the first park consumed the only token
+    let start = Instant::now();
+    thread::park_timeout(SHORT_PARK_TIMEOUT);
+    let park_time = start.elapsed();
+    test_assert!(park_time >= SHORT_PARK_TIMEOUT, "second park returned after {park_time:?}");
+
+    Ok(())
+}
+
+fn park_timeout_is_reported() -> TestResult {
+    let deadline = Instant::now() + SHORT_PARK_TIMEOUT;
+    let result = sys::Thread::park(Some(deadline.as_boot_time().as_nanos() as u64));
+
+    test_assert_eq!(result, Err(SysErr::OkTimeout));
+    test_assert!(Instant::now() >= deadline);
+
+    Ok(())
+}
+
+fn unpark_before_other_thread_parks() -> TestResult {
+    let unparked = Arc::new(AtomicBool::new(false));
+    let child_unparked = unparked.clone();
+
+    let child = thread::spawn(move || {
+        // the child doesn't park until after it has been unparked
+        while !child_unparked.load(Ordering::Acquire) {
+            thread::yield_now();
+        }
+
+        timed_park()
+    });
+
+    child.thread().unpark();
+    unparked.store(true, Ordering::Release);
+
+    let park_time = child.join();
+    test_assert!(park_time < IMMEDIATE_RETURN, "child park after unpark took {park_time:?}");
+
+    Ok(())
+}
+
+fn unpark_wakes_parked_thread() -> TestResult {
+    const ROUNDS: usize = 1000;
+
+    let done = Arc::new(AtomicBool::new(false));
+    let child_done = done.clone();
+    let main_thread = thread::current();
+
+    // the child and the main thread take turns, so each unpark races with the other thread parking
+    let child = thread::spawn(move || {
+        let mut wakeups = 0;
+        loop {
+            thread::park();
+            if child_done.load(Ordering::Acquire) {
+                return wakeups;
+            }
+
+            wakeups += 1;
+            main_thread.unpark();
+        }
+    });
+
+    // a lost wakeup would leave both threads parked until the main thread's timeout
+    let mut slowest_round = Duration::ZERO;
+    for _ in 0..ROUNDS {
+        child.thread().unpark();
+        slowest_round = slowest_round.max(timed_park());
+    }
+
+    done.store(true, Ordering::Release);
+    child.thread().unpark();
+
+    test_assert_eq!(child.join(), ROUNDS);
+    test_assert!(slowest_round < IMMEDIATE_RETURN, "slowest round took {slowest_round:?}");
+
+    Ok(())
+}
+
+fn futex_wait_with_wrong_value_returns_immediately() -> TestResult {
+    let futex = AtomicU32::new(1);
+    let deadline = Instant::now() + LONG_PARK_TIMEOUT;
+
+    let start = Instant::now();
+    let result = sys::futex_wait(&futex, 0, Some(deadline.as_boot_time().as_nanos() as u64));
+    let wait_time = start.elapsed();
+
+    test_assert_eq!(result, Ok(false));
+    test_assert!(wait_time < IMMEDIATE_RETURN, "futex wait with wrong value took {wait_time:?}");
+
+    Ok(())
+}
+
+fn futex_wait_timeout_is_reported() -> TestResult {
+    let futex = AtomicU32::new(1);
+    let deadline = Instant::now() + SHORT_PARK_TIMEOUT;
+
+    let result = sys::futex_wait(&futex, 1, Some(deadline.as_boot_time().as_nanos() as u64));
+
+    test_assert_eq!(result, Err(SysErr::OkTimeout));
+    test_assert!(Instant::now() >= deadline);
+
+    Ok(())
+}
+
+fn mutex_condvar_ping_pong() -> TestResult {
+    const ROUNDS: usize = 1000;
+
+    // counts the turns taken so far, the main thread takes even turns and the child takes odd turns
+    let state = Arc::new((Mutex::new(0usize), Condvar::new()));
+    let child_state = state.clone();
+
+    let child = thread::spawn(move || {
+        let (turns, condvar) = &*child_state;
+
+        for i in 0..ROUNDS {
+            let mut turn = condvar.wait_while(turns.lock(), |turn| *turn != 2 * i + 1);
+            *turn += 1;
+            condvar.notify_one();
+        }
+    });
+
+    let (turns, condvar) = &*state;
+    let start = Instant::now();
+    for i in 0..ROUNDS {
+        let mut turn = condvar.wait_while(turns.lock(), |turn| *turn != 2 * i);
+        *turn += 1;
+        condvar.notify_one();
+    }
+
+    child.join();
+    let elapsed = start.elapsed();
+
+    test_assert_eq!(*turns.lock(), 2 * ROUNDS);
+    // a lost notify would leave both threads waiting forever, so mostly this checks the test finished at all
+    test_assert!(elapsed < LONG_PARK_TIMEOUT, "ping pong took {elapsed:?}");
+
+    Ok(())
+}
+
+aurora_test::tests! {
+    unpark_before_park_returns_immediately,
+    wake_tokens_do_not_accumulate,
+    park_timeout_is_reported,
+    unpark_before_other_thread_parks,
+    unpark_wakes_parked_thread,
+    futex_wait_with_wrong_value_returns_immediately,
+    futex_wait_timeout_is_reported,
+    mutex_condvar_ping_pong,
+}
+
+fn main() {
+    aurora_test::run_tests(TESTS);
+}
\ No newline at end of file
@@ -0,0 +1,46 @@
+//! Checks that `#[thread_local]` statics are set up from the tls image in every thread
+//! 
+//! Exits with a code of 0 if everything is correct, and panics otherwise.
+
+#![no_std]
+#![feature(thread_local)]
+
+extern crate alloc;
+extern crate std;
+
+use core::cell::Cell;
+
+use aurora::thread;
+use std::prelude::*;
+
+const INITIAL_VALUE: u64 = 0x1234_5678_9abc_def0;
+
+/// Nonzero, so this is placed in the tls image (`.tdata`)
+#[thread_local]
+static INITIALIZED: Cell<u64> = Cell::new(INITIAL_VALUE);
+
+/// Zero, so this is placed in the part of the tls block after the image (`.tbss`)
+#[thread_local]
+static ZEROED: Cell<u64> = Cell::new(0);
+
+fn check_initial_values(thread_name: &str) {
+    assert_eq!(INITIALIZED.get(), INITIAL_VALUE, "{thread_name} thread did not see initialized thread local value");
+    assert_eq!(ZEROED.get(), 0, "{thread_name} thread did not see zeroed thread local value");
+}
+
+fn main() {
+    check_initial_values("main");
+    INITIALIZED.set(1);
+    ZEROED.set(2);
+
+    thread::spawn(|| {
+        check_initial_values("spawned");
+        INITIALIZED.set(3);
+        ZEROED.set(4);
+    }).join();
+
+    assert_eq!(INITIALIZED.get(), 1, "write in spawned thread changed main thread's thread local");
+    assert_eq!(ZEROED.get(), 2, "write in spawned thread changed main thread's thread local");
+
+    dprintln!("tls test passed");
+}
\ No newline at end of file